@@ -69,17 +69,28 @@ pub(crate) fn icontray_server(conn_to_main: xous::CID) {
                     crate::VaultOp::IncrementalLine.to_usize().unwrap()
                 ).expect("couldn't forward input");
             }
+            Some(Opcode::SourcedInput) => {
+                // the main loop takes a plain input line; the source makes no difference to the icons
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let sourced = buffer.to_original::<SourcedInput, _>().unwrap();
+                Buffer::into_buf(sourced.input).expect("couldn't allocate input")
+                    .lend(conn_to_main, crate::VaultOp::IncrementalLine.to_u32().unwrap())
+                    .expect("couldn't forward input");
+            }
             Some(Opcode::Picked) => {
                 // this is ignored
             }
+            Some(Opcode::FeedbackBatch) => {
+                // this is ignored
+            }
             Some(Opcode::Prediction) => {
                 // we don't check the API token here, because our "predictions" are just the four menu slots
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
                 // every key press, the four slots get queried
-                prediction.string.clear();
+                prediction.set("");
                 if prediction.index < ICONS.len() as u32 {
-                    prediction.string.append(ICONS[prediction.index as usize]).ok();
+                    prediction.set(ICONS[prediction.index as usize]);
                     prediction.valid = true;
                 } else {
                     prediction.valid = false;
@@ -87,12 +98,51 @@ pub(crate) fn icontray_server(conn_to_main: xous::CID) {
                 // pack our data back into the buffer to return
                 buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
             }
+            Some(Opcode::InlineCompletion) => {
+                // menu slots don't complete anything
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut completion = buffer.to_original::<InlineCompletion, _>().unwrap();
+                completion.suffix.clear();
+                buffer.replace(completion).expect("couldn't return InlineCompletion");
+            }
             Some(Opcode::Unpick) => {
                 // ignore
             }
             Some(Opcode::GetPredictionTriggers) => {
                 xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
             }
+            Some(Opcode::WarmUp) => {
+                // the icons are static, nothing to load
+            }
+            Some(Opcode::SetTokenizer) => {
+                // the icons are fixed, nothing is learned
+            }
+            Some(Opcode::GetReadyState) => {
+                xous::return_scalar(msg.sender, ReadyState::Ready as usize).expect("couldn't return GetReadyState");
+            }
+            Some(Opcode::GetDictionaryInfo) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                buffer.replace(DictionaryInfo::new("und", ICONS.len() as u32, 0)).expect("couldn't return GetDictionaryInfo");
+            }
+            Some(Opcode::SetInputDebounce) | Some(Opcode::InputDebounceElapsed) => {
+                // the icons don't depend on the input, which is forwarded as it arrives
+            }
+            Some(Opcode::SetEchoFallback) => {
+                // the icons are always there, so there is never a need to fall back
+            }
+            Some(Opcode::SetPhrases) => {
+                // the icons are fixed, they never complete a phrase
+            }
+            Some(Opcode::SetSpellCorrect) => {
+                // nor do they correct what's typed
+            }
+            Some(Opcode::SelfCheck) => {
+                // the icons are compiled in, so there's nothing that can go bad
+                xous::return_scalar(msg.sender, SelfCheck::ALL as usize).expect("couldn't return SelfCheck");
+            }
+            Some(Opcode::NewField) => {
+                // the icons don't depend on the input, so there's nothing to start over
+            }
             Some(Opcode::Quit) => {
                 if api_token.is_some() {
                     log::error!("received quit, goodbye!"); break;
@@ -282,6 +282,20 @@ pub(crate) enum Opcode {
 
     /// gets more details on the latest interrupt
     IntFetchVector = 49,
+
+    /// hosted mode: request for the backlight level to be forwarded, for the emulator to show
+    #[cfg(not(target_os = "xous"))]
+    RegisterBacklightListener = 50, //BacklightListener
+}
+
+/// Where the backlight level is forwarded to in hosted mode, each time it's set
+#[cfg(not(target_os = "xous"))]
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct BacklightListener {
+    /// the server to forward it to
+    pub sid: [u32; 4],
+    /// the opcode of the scalar message it's forwarded in, with the main and secondary levels
+    pub op: u32,
 }
 
 /// These enums indicate what kind of callback type we're sending.
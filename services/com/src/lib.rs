@@ -335,6 +335,17 @@ impl Com {
         ).map(|_| ())
     }
 
+    /// Hosted mode only: has the backlight level forwarded to the server `sid`, each time it's
+    /// set, as an `op` scalar message of the main and secondary levels, 0-31. There's no backlight
+    /// to set on a host; this is for the emulator to show.
+    #[cfg(not(target_os = "xous"))]
+    pub fn register_backlight_listener(&self, sid: xous::SID, op: u32) -> Result<(), xous::Error> {
+        let sid = sid.to_u32();
+        let listener = api::BacklightListener { sid: [sid.0, sid.1, sid.2, sid.3], op };
+        let buf = Buffer::into_buf(listener).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::RegisterBacklightListener.to_u32().unwrap()).map(|_| ())
+    }
+
     pub fn is_charging(&self) -> Result<bool, xous::Error> {
         if let xous::Result::Scalar1(state) =
             send_message(self.conn,
@@ -389,6 +389,9 @@ fn main() -> ! {
 
     let mut bl_main = 0;
     let mut bl_sec = 0;
+    // where the backlight level is forwarded to in hosted mode, and the opcode it goes with
+    #[cfg(not(target_os = "xous"))]
+    let mut backlight_listener: Option<(xous::CID, usize)> = None;
 
     let mut flash_id: Option<[u32;4]> = None; // only one process can acquire this, and its ID is stored here.
     const FLASH_LEN: u32 = 0x10_0000;
@@ -615,6 +618,16 @@ fn main() -> ! {
                     }
                 }
             ),
+            #[cfg(not(target_os = "xous"))]
+            Some(Opcode::RegisterBacklightListener) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let listener = buffer.to_original::<api::BacklightListener, _>().unwrap();
+                let [sid0, sid1, sid2, sid3] = listener.sid;
+                match xous::connect(xous::SID::from_u32(sid0, sid1, sid2, sid3)) {
+                    Ok(cid) => backlight_listener = Some((cid, listener.op as usize)),
+                    Err(e) => error!("couldn't connect to backlight listener: {:?}", e),
+                }
+            }
             Some(Opcode::IsCharging) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 com.txrx(ComState::POWER_CHARGER_STATE.verb);
                 let result = com.wait_txrx(ComState::LINK_READ.verb, Some(STD_TIMEOUT));
@@ -675,6 +688,10 @@ fn main() -> ! {
             Some(Opcode::SetBackLight) => msg_scalar_unpack!(msg, main, secondary, _, _, {
                 #[cfg(not(target_os = "xous"))]
                 log::info!("HOSTED: set backlight to {},{}", main, secondary);
+                #[cfg(not(target_os = "xous"))]
+                if let Some((cid, op)) = backlight_listener {
+                    xous::try_send_message(cid, xous::Message::new_scalar(op, main, secondary, 0, 0)).ok();
+                }
                 bl_main = main;
                 bl_sec = secondary;
                 com.txrx(ComState::BL_START.verb | (main as u16) & 0x1f | (((secondary as u16) & 0x1f) << 5));
@@ -114,6 +114,7 @@ fn wrapped_main() -> ! {
         log::info!("USAGE:");
         log::info!("   `Home` key to bring up menu; arrow keys to go up/down; `Home` to select again");
         log::info!("   `F1`-`F4` to pick predictions; `F5` and `F6` generate test unicode characters");
+        log::info!("   `Shift`+`F1` in the GUI window shows which keys stand for the Precursor's keys");
         log::info!("   Otherwise type in the GUI window; `help` for the current command list");
         log::info!("   ^C in the console window (this window) to quit");
         log::info!("********************************************************************************");
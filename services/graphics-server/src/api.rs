@@ -122,9 +122,73 @@ pub(crate) enum Opcode {
     /// draw the boot logo (for continuity as apps initialize)
     DrawBootLogo,
 
+    /// turns inverted video on or off
+    SetInverted,
+
+    /// copies the frame buffer into a lent `FramebufferDump`, for tests in hosted mode
+    DumpFramebuffer,
+
+    /// redraws skipped because the window was already up to date, for performance tests in
+    /// hosted mode
+    SkippedFrames,
+
+    /// frames put up in the window with something new in them, and when the latest was, in
+    /// milliseconds since the display started, for lining draw calls up with frames in hosted
+    /// mode
+    PresentedFrames,
+
+    /// registers a server to be sent clicks and drags in the emulator window, as a lent
+    /// `PointerRegistration`, in hosted mode
+    RegisterPointerListener,
+
+    /// sets the backlight level the emulator dims the display to, as the larger of a scalar's
+    /// first two arguments, 0-31; the COM server forwards the hardware backlight level here in
+    /// hosted mode
+    SetBrightness,
+
+    /// opens another display surface in a window of its own, alongside the main display, and
+    /// returns its index, in hosted mode; a display that can't returns the surface in use
+    OpenSurface,
+
+    /// sends drawing, flushes and frame buffer reads to the surface of the index in a scalar's
+    /// first argument, 0 being the main display, in hosted mode
+    SelectSurface,
+
+    /// services the emulator windows' input between redraws, so fast typing isn't dropped;
+    /// sent by the server's own input timer in hosted mode
+    PollInput,
+
+    /// blocks until a frame's been put up on the display since the last flush, returning its
+    /// number and when it was put up, in milliseconds since the display started; a flush with
+    /// nothing new in it puts up no frame, and is answered with the one already up
+    WaitForVsync,
+
+    /// sent by the backend as each frame's put up, with its number and when as the first two
+    /// arguments
+    FrameComplete,
+
     Quit,
 }
 
+/// A server to send pointer events to, and the opcode to send them on. Each event is a scalar
+/// message of its `PointerAction`, the x and y of the pixel it happened at, and the index of the
+/// surface it happened on.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PointerRegistration {
+    pub server_name: xous_ipc::String<64>,
+    pub listener_op_id: usize,
+}
+
+/// What the pointer did, in hosted mode: the emulator window's clicks and drags, made out as
+/// touches on the display
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum PointerAction {
+    Press,
+    /// dragged to somewhere new, while pressed
+    Move,
+    Release,
+}
+
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
 pub enum ClipObjectType {
     Line(Line),
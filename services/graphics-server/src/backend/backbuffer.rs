@@ -0,0 +1,117 @@
+//! The back buffer frames are blitted into for the hosted display, handed over to be drawn whole,
+//! so a frame blitted from another thread while the last one's being converted can't tear it.
+//! Blits of a few lines only leave those lines to be taken.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Holds the lines blitted until the display takes them. Shared between the display and
+/// whichever threads blit to it.
+#[derive(Debug)]
+pub struct BackBuffer {
+    /// words to a line of the frame
+    line_words: usize,
+    /// the latest of each line blitted, and which of them are still to be taken
+    back: Mutex<(Vec<u32>, Vec<bool>)>,
+    /// goes up once each blit is complete in the back buffer
+    generation: AtomicU64,
+    /// blits that went over lines before they were taken, when blits outpace redraws
+    dropped: AtomicU64,
+}
+impl BackBuffer {
+    /// A back buffer for frames of `lines` lines of `line_words` words
+    pub fn new(line_words: usize, lines: usize) -> BackBuffer {
+        BackBuffer {
+            line_words,
+            back: Mutex::new((vec![0; line_words * lines], vec![false; lines])),
+            generation: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+    /// Puts `frame` in the back buffer, in place of anything there that's still to be taken
+    pub fn blit(&self, frame: &[u32]) {
+        self.blit_lines(frame, 0);
+    }
+    /// Puts the whole lines in `lines` in the back buffer from `start_line` down, in place of
+    /// those lines if they're still to be taken. Lines past the end of the frame are left out.
+    pub fn blit_lines(&self, lines: &[u32], start_line: usize) {
+        let mut back = self.back.lock().unwrap();
+        let (frame, pending) = &mut *back;
+        let mut overwrote = false;
+        for ((dest, pending), src) in frame
+            .chunks_mut(self.line_words)
+            .zip(pending.iter_mut())
+            .skip(start_line)
+            .zip(lines.chunks_exact(self.line_words))
+        {
+            dest.copy_from_slice(src);
+            overwrote |= *pending;
+            *pending = true;
+        }
+        if overwrote {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+    /// Hands each run of lines blitted since generation `seen` to `take`, with the line it
+    /// starts at, and brings `seen` up to date. With nothing new, it doesn't wait on the lock.
+    pub fn take(&self, seen: &mut u64, mut take: impl FnMut(usize, &[u32])) {
+        if self.generation.load(Ordering::Acquire) == *seen {
+            return;
+        }
+        let mut back = self.back.lock().unwrap();
+        *seen = self.generation.load(Ordering::Acquire);
+        let (frame, pending) = &mut *back;
+        let mut line = 0;
+        while line < pending.len() {
+            let run = pending[line..].iter().take_while(|&&p| p).count();
+            if run > 0 {
+                take(line, &frame[line * self.line_words..(line + run) * self.line_words]);
+                pending[line..line + run].iter_mut().for_each(|p| *p = false);
+            }
+            line += run.max(1);
+        }
+    }
+    /// Blits that went over lines before the display took them
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_back_buffer() {
+        let back = BackBuffer::new(2, 4);
+        let mut seen = 0;
+        let mut taken = Vec::new();
+        back.take(&mut seen, |line, lines| taken.push((line, lines.to_vec())));
+        assert!(taken.is_empty());
+        // only the latest of the frames blitted since the last take is taken; the rest are dropped
+        back.blit(&[1; 8]);
+        back.blit(&[2; 8]);
+        back.blit(&[3; 8]);
+        back.take(&mut seen, |line, lines| taken.push((line, lines.to_vec())));
+        back.take(&mut seen, |line, lines| taken.push((line, lines.to_vec())));
+        assert_eq!(taken, vec![(0, vec![3; 8])]);
+        assert_eq!((seen, back.dropped()), (3, 2));
+
+        // regions are taken a run of lines at a time; what runs off the end, or isn't a whole
+        // line, is left out
+        taken.clear();
+        back.blit_lines(&[4, 4], 0);
+        back.blit_lines(&[5, 5, 6, 6, 7], 3);
+        back.take(&mut seen, |line, lines| taken.push((line, lines.to_vec())));
+        assert_eq!(taken, vec![(0, vec![4, 4]), (3, vec![5, 5])]);
+        assert_eq!(back.dropped(), 2);
+        // lines blitted over before they're taken drop the blit before
+        taken.clear();
+        back.blit_lines(&[8, 8, 8, 8], 1);
+        back.blit_lines(&[9, 9], 2);
+        back.take(&mut seen, |line, lines| taken.push((line, lines.to_vec())));
+        assert_eq!(taken, vec![(1, vec![8, 8, 9, 9])]);
+        assert_eq!(back.dropped(), 3);
+    }
+}
@@ -1,5 +1,8 @@
-use crate::api::Point;
+use crate::api::Rectangle;
 use crate::api::{LINES, WIDTH};
+use super::damage::Damage;
+use super::{Capabilities, DisplayBackend, DisplayError, DisplayInfo, FrameComplete};
+use std::time::Instant;
 use susres::{RegManager, RegOrField, SuspendResume};
 use utralib::generated::*;
 use xous::MemoryRange;
@@ -8,7 +11,11 @@ pub const FB_WIDTH_WORDS: usize = 11;
 pub const FB_WIDTH_PIXELS: usize = WIDTH as usize;
 pub const FB_LINES: usize = LINES as usize;
 pub const FB_SIZE: usize = FB_WIDTH_WORDS * FB_LINES; // 44 bytes by 536 lines
+pub const DISPLAY_INFO: DisplayInfo =
+    DisplayInfo { width: FB_WIDTH_PIXELS, height: FB_LINES, words_per_line: FB_WIDTH_WORDS, fb_size_words: FB_SIZE };
 const CONFIG_CLOCK_FREQUENCY: u32 = 100_000_000;
+/// pacing of `wait_for_vsync()`, matching the hosted window's refresh rate
+const FRAME_PERIOD_MS: u64 = 1000 / 60;
 
 pub struct XousDisplay {
     fb: MemoryRange,
@@ -16,6 +23,12 @@ pub struct XousDisplay {
     srfb: [u32; FB_SIZE],
     csr: utralib::CSR<u32>,
     susres: RegManager<{ utra::memlcd::MEMLCD_NUMREGS }>,
+    /// the areas `mark_dirty()` has been given since the last redraw
+    damage: Damage,
+    /// told of each frame the panel takes, and how many it has, since `started`
+    frame_listener: Option<(xous::CID, usize)>,
+    frames_complete: u64,
+    started: Instant,
 }
 
 impl XousDisplay {
@@ -62,6 +75,10 @@ impl XousDisplay {
             csr: CSR::new(control.as_mut_ptr() as *mut u32),
             susres: RegManager::new(control.as_mut_ptr() as *mut u32),
             srfb: [0u32; FB_SIZE],
+            damage: Damage::default(),
+            frame_listener: None,
+            frames_complete: 0,
+            started: Instant::now(),
         };
 
         display.set_clock(CONFIG_CLOCK_FREQUENCY);
@@ -100,26 +117,183 @@ impl XousDisplay {
         )
     }
 
-    pub fn stash(&mut self) {
-        let fb = unsafe{core::slice::from_raw_parts_mut(self.fb.as_mut_ptr() as *mut u32, FB_SIZE)};
-        for lines in 0..FB_LINES {
-            // set the dirty bits prior to stashing the frame buffer
-            fb[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] |= 0x1_0000;
+    fn poll_frame_complete(&mut self) {
+        if self.csr.rf(utra::memlcd::EV_PENDING_DONE) == 0 {
+            return;
         }
-        let srfb_ptr = self.srfb.as_mut_ptr() as *mut u32;
-        for (index, &src) in fb.iter().enumerate() {
-            unsafe{srfb_ptr.add(index).write_volatile(src)};
+        self.csr.wfo(utra::memlcd::EV_PENDING_DONE, 1);
+        self.frames_complete += 1;
+        if let Some((cid, opcode)) = self.frame_listener {
+            let done = FrameComplete { frame: self.frames_complete, at_ms: self.started.elapsed().as_millis() as u64 };
+            // not blocking: the listener could be waiting on this server
+            let message = xous::Message::new_scalar(opcode, done.frame as usize, done.at_ms as usize, 0, 0);
+            if let Err(e) = xous::try_send_message(cid, message) {
+                log::warn!("couldn't send frame {} complete: {:?}", done.frame, e);
+            }
         }
     }
-    pub fn pop(&mut self) {
-        let fb: &mut [u32] = self.fb.as_slice_mut();
-        // skip copying the status bar, so that the status info is not overwritten by the pop.
-        // this is "fixed" at 32 pixels high (2 * Cjk glyph height hint) per line 79 in gam/src/main.rs
-        fb[FB_WIDTH_WORDS * 32..FB_SIZE].copy_from_slice(&self.srfb[FB_WIDTH_WORDS * 32..FB_SIZE]);
-        self.redraw();
+
+    /// Beneath this line are pure-HAL layer, and should not be user-visible
+
+    ///
+    fn set_clock(&mut self, clk_mhz: u32) {
+        self.csr
+            .wfo(utra::memlcd::PRESCALER_PRESCALER, (clk_mhz / 2_000_000) - 1);
+    }
+
+    fn update_all(&mut self) {
+        self.csr.wfo(utra::memlcd::COMMAND_UPDATEALL, 1);
+    }
+
+    fn update_dirty(&mut self) {
+        self.csr.wfo(utra::memlcd::COMMAND_UPDATEDIRTY, 1);
+    }
+
+    /// "synchronous clear" -- must be called on init, so that the state of the LCD
+    /// internal memory is consistent with the state of the frame buffer
+    /*
+    fn sync_clear(&mut self) {
+        let framebuffer = self.fb.as_mut_ptr() as *mut u32;
+        for words in 0..FB_SIZE {
+            if words % FB_WIDTH_WORDS != 10 {
+                unsafe { framebuffer.add(words).write_volatile(0xFFFF_FFFF) };
+            } else {
+                unsafe { framebuffer.add(words).write_volatile(0x0000_FFFF) };
+            }
+        }
+        self.update_all(); // because we force an all update here
+        while self.busy() {}
+    }
+    */
+
+    /// The hardware display has no notion of window focus; the keyboard is always attached.
+    pub fn is_focused(&self) -> bool {
+        true
+    }
+}
+
+impl DisplayBackend for XousDisplay {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { screenshot: false, window: false, power: true, brightness: false }
+    }
+
+    /// The size of the display and the layout of its frame buffer
+    fn info(&self) -> DisplayInfo {
+        DISPLAY_INFO
+    }
+
+    fn native_buffer(&mut self) -> &mut [u32; FB_SIZE] {
+        unsafe { &mut *(self.fb.as_mut_ptr() as *mut [u32; FB_SIZE]) }
+    }
+
+    fn blit_screen(&mut self, bmp: &[u32; FB_SIZE]) {
+        let framebuffer = self.fb.as_mut_ptr() as *mut u32;
+
+        for words in 0..FB_SIZE {
+            unsafe {
+                framebuffer.add(words).write_volatile(bmp[words]);
+            }
+        }
+        self.update_all();
+
+        while self.busy() {}
     }
 
-    pub fn suspend(&mut self) {
+    /// Blits just the `line_count` lines in `src` from `start_line` down, marking them dirty, so
+    /// the next redraw sends the panel only those. Lines that would run off the end of the
+    /// screen, or that `src` is too short to hold, are left out.
+    fn blit_region(&mut self, src: &[u32], start_line: usize, line_count: usize) {
+        let framebuffer = self.fb.as_mut_ptr() as *mut u32;
+        let line_count = line_count.min(FB_LINES.saturating_sub(start_line)).min(src.len() / FB_WIDTH_WORDS);
+
+        for words in 0..line_count * FB_WIDTH_WORDS {
+            let word = if words % FB_WIDTH_WORDS == FB_WIDTH_WORDS - 1 {
+                src[words] | 0x1_0000 // mark the line dirty
+            } else {
+                src[words]
+            };
+            unsafe {
+                framebuffer.add(start_line * FB_WIDTH_WORDS + words).write_volatile(word);
+            }
+        }
+    }
+
+    /// Marks `area` of the frame buffer for the next redraw to send: every line it touches, as at
+    /// most `MAX_UPDATE_BANDS` runs of lines
+    fn mark_dirty(&mut self, area: Rectangle) {
+        self.damage.add(&area);
+    }
+
+    /// Copies the frame buffer out to the panel; this can't fail, the `Result` is for the hosted
+    /// backend's sake
+    fn redraw(&mut self) -> Result<(), DisplayError> {
+        let mut busy_count = 0;
+        let mut dirty_count = 0;
+        while self.busy() {
+            xous::yield_slice();
+            busy_count += 1;
+        }
+        self.poll_frame_complete();
+        let fb: *mut [u32; FB_SIZE] = self.fb.as_mut_ptr() as *mut [u32; FB_SIZE];
+        let hwfb: *mut [u32; FB_SIZE] = self.hwfb.as_mut_ptr() as *mut [u32; FB_SIZE];
+        self.damage.mark_lines(unsafe { &mut *fb });
+        for words in 0..FB_SIZE {
+            unsafe {
+                (*hwfb)[words] = (*fb)[words];
+            }
+        }
+        self.update_dirty();
+        // clear all the dirty bits, under the theory that it's time-wise cheaper on average
+        // to visit every line and clear the dirty bits than it is to do an update_all()
+        for lines in 0..FB_LINES {
+            if unsafe { (*fb)[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] & 0xFFFF_0000 } != 0x0
+            {
+                dirty_count += 1;
+            }
+            unsafe {
+                (*fb)[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] &= 0x0000_FFFF;
+            }
+        }
+        log::trace!("redraw {}/{}", busy_count, dirty_count);
+        Ok(())
+    }
+
+    // note: this API is used by emulation, don't remove calls to it
+    fn update(&mut self) -> Result<(), DisplayError> {
+        self.poll_frame_complete();
+        Ok(())
+    }
+
+    /// Whether the LCD controller is still sending an update to the panel; update commands sent
+    /// meanwhile are ignored
+    fn busy(&self) -> bool {
+        self.csr.rf(utra::memlcd::BUSY_BUSY) == 1
+    }
+
+    /// The memory LCD has no refresh signal to sync to, so this just sleeps for a frame; see
+    /// the hosted backend for the pacing this stands in for.
+    fn wait_for_vsync(&mut self) {
+        std::thread::sleep(std::time::Duration::from_millis(FRAME_PERIOD_MS));
+    }
+
+    /// Has each frame the panel finishes taking sent to `cid` as a scalar message on `opcode`,
+    /// its number and when it finished, in milliseconds since the display started, as its first
+    /// two arguments. The controller's done event isn't wired to an interrupt, so it's picked up
+    /// from its pending bit by the next `update()` or `redraw()`.
+    fn on_frame_complete(&mut self, cid: xous::CID, opcode: usize) {
+        self.csr.wfo(utra::memlcd::EV_PENDING_DONE, 1);
+        self.frame_listener = Some((cid, opcode));
+    }
+    /// Waits out the frame going up, then picks up the controller's done event for it, as
+    /// nothing else would until the display's next updated
+    fn report_frame_complete(&mut self) -> Result<(), DisplayError> {
+        while self.busy() {
+            xous::yield_slice();
+        }
+        self.update()
+    }
+
+    fn suspend(&mut self) {
         while self.busy() {
             // just wait until any pending FB operations are done
         }
@@ -171,109 +345,43 @@ impl XousDisplay {
             // busy wait, blocking suspend until this has happened
         }
     }
-    pub fn resume(&mut self) {
+
+    fn resume(&mut self) {
         self.susres.resume();
         let fb: &mut [u32] = self.fb.as_slice_mut();
         fb[..FB_SIZE].copy_from_slice(&self.srfb);
 
-        self.redraw();
+        // the panel can't fail to take a frame
+        self.redraw().ok();
     }
 
-    pub fn screen_size(&self) -> Point {
-        Point::new(FB_WIDTH_PIXELS as i16, FB_LINES as i16)
-    }
-
-    pub fn redraw(&mut self) {
-        let mut busy_count = 0;
-        let mut dirty_count = 0;
-        while self.busy() {
-            xous::yield_slice();
-            busy_count += 1;
-        }
-        let fb: *mut [u32; FB_SIZE] = self.fb.as_mut_ptr() as *mut [u32; FB_SIZE];
-        let hwfb: *mut [u32; FB_SIZE] = self.hwfb.as_mut_ptr() as *mut [u32; FB_SIZE];
-        for words in 0..FB_SIZE {
-            unsafe {
-                (*hwfb)[words] = (*fb)[words];
-            }
-        }
-        self.update_dirty();
-        // clear all the dirty bits, under the theory that it's time-wise cheaper on average
-        // to visit every line and clear the dirty bits than it is to do an update_all()
+    fn stash(&mut self) {
+        let fb = unsafe{core::slice::from_raw_parts_mut(self.fb.as_mut_ptr() as *mut u32, FB_SIZE)};
         for lines in 0..FB_LINES {
-            if unsafe { (*fb)[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] & 0xFFFF_0000 } != 0x0
-            {
-                dirty_count += 1;
-            }
-            unsafe {
-                (*fb)[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] &= 0x0000_FFFF;
-            }
+            // set the dirty bits prior to stashing the frame buffer
+            fb[lines * FB_WIDTH_WORDS + (FB_WIDTH_WORDS - 1)] |= 0x1_0000;
         }
-        log::trace!("redraw {}/{}", busy_count, dirty_count);
-    }
-
-    // note: this API is used by emulation, don't remove calls to it
-    pub fn update(&mut self) {}
-
-    pub fn native_buffer(&mut self) -> &mut [u32; FB_SIZE] {
-        unsafe { &mut *(self.fb.as_mut_ptr() as *mut [u32; FB_SIZE]) }
-    }
-
-    pub fn blit_screen(&mut self, bmp: &[u32]) {
-        let framebuffer = self.fb.as_mut_ptr() as *mut u32;
-
-        for words in 0..FB_SIZE {
-            unsafe {
-                framebuffer.add(words).write_volatile(bmp[words]);
-            }
+        let srfb_ptr = self.srfb.as_mut_ptr() as *mut u32;
+        for (index, &src) in fb.iter().enumerate() {
+            unsafe{srfb_ptr.add(index).write_volatile(src)};
         }
-        self.update_all();
-
-        while self.busy() {}
-    }
-
-    pub fn as_slice(&self) -> &[u32] {
-        &self.fb.as_slice::<u32>()[..FB_SIZE]
-    }
-
-    /// Beneath this line are pure-HAL layer, and should not be user-visible
-
-    ///
-    fn set_clock(&mut self, clk_mhz: u32) {
-        self.csr
-            .wfo(utra::memlcd::PRESCALER_PRESCALER, (clk_mhz / 2_000_000) - 1);
-    }
-
-    fn update_all(&mut self) {
-        self.csr.wfo(utra::memlcd::COMMAND_UPDATEALL, 1);
     }
 
-    fn update_dirty(&mut self) {
-        self.csr.wfo(utra::memlcd::COMMAND_UPDATEDIRTY, 1);
-    }
-
-    /// "synchronous clear" -- must be called on init, so that the state of the LCD
-    /// internal memory is consistent with the state of the frame buffer
-    /*
-    fn sync_clear(&mut self) {
-        let framebuffer = self.fb.as_mut_ptr() as *mut u32;
-        for words in 0..FB_SIZE {
-            if words % FB_WIDTH_WORDS != 10 {
-                unsafe { framebuffer.add(words).write_volatile(0xFFFF_FFFF) };
-            } else {
-                unsafe { framebuffer.add(words).write_volatile(0x0000_FFFF) };
-            }
-        }
-        self.update_all(); // because we force an all update here
-        while self.busy() {}
+    fn pop(&mut self) -> Result<(), DisplayError> {
+        let fb: &mut [u32] = self.fb.as_slice_mut();
+        // skip copying the status bar, so that the status info is not overwritten by the pop.
+        // this is "fixed" at 32 pixels high (2 * Cjk glyph height hint) per line 79 in gam/src/main.rs
+        fb[FB_WIDTH_WORDS * 32..FB_SIZE].copy_from_slice(&self.srfb[FB_WIDTH_WORDS * 32..FB_SIZE]);
+        self.redraw()
     }
-    */
 
-    fn busy(&self) -> bool {
-        self.csr.rf(utra::memlcd::BUSY_BUSY) == 1
+    /// The memory LCD controller has no inverted video mode, so this only notes the request;
+    /// the hosted backend renders it.
+    fn set_inverted(&mut self, inverted: bool) {
+        log::warn!("inverted video ({}) isn't supported by the memory LCD controller; ignoring", inverted);
     }
 
-    pub fn set_devboot(&mut self, ena: bool) {
+    fn set_devboot(&mut self, ena: bool) {
         if ena {
             self.csr.wfo(utra::memlcd::DEVBOOT_DEVBOOT, 1);
         } else {
@@ -0,0 +1,163 @@
+//! Pasting the host's clipboard into the hosted emulator, typed a key at a time as if by hand,
+//! and copying to it
+
+use super::hostkeys::{HostKeyEvent, KeyAction};
+use minifb::Key;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Commands that print the host's clipboard, tried in turn
+#[cfg(target_os = "macos")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(windows)]
+const PASTE_COMMANDS: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "[Console]::Out.Write((Get-Clipboard -Raw))"]];
+#[cfg(all(unix, not(target_os = "macos")))]
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+/// Commands that put what they read in on the host's clipboard, tried in turn
+#[cfg(target_os = "macos")]
+const COPY_COMMANDS: &[&[&str]] = &[&["pbcopy"]];
+#[cfg(windows)]
+const COPY_COMMANDS: &[&[&str]] = &[&["clip"]];
+#[cfg(all(unix, not(target_os = "macos")))]
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard", "-i"],
+    &["xsel", "--clipboard", "--input"],
+];
+
+/// The text on the host's clipboard, read with whichever of the host's clipboard tools works
+pub fn read_host_clipboard() -> io::Result<String> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no clipboard tool");
+    for command in PASTE_COMMANDS.iter() {
+        match Command::new(command[0]).args(&command[1..]).output() {
+            Ok(output) if output.status.success() => return Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Ok(output) => {
+                error = io::Error::new(io::ErrorKind::Other, format!("{} failed: {}", command[0], output.status));
+            }
+            Err(e) => error = io::Error::new(e.kind(), format!("couldn't run {}: {}", command[0], e)),
+        }
+    }
+    Err(error)
+}
+
+/// Puts `text` on the host's clipboard, with whichever of the host's clipboard tools works
+pub fn write_host_clipboard(text: &str) -> io::Result<()> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no clipboard tool");
+    for command in COPY_COMMANDS.iter() {
+        let mut child = match Command::new(command[0]).args(&command[1..]).stdin(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error = io::Error::new(e.kind(), format!("couldn't run {}: {}", command[0], e));
+                continue;
+            }
+        };
+        // the tool's stdin closes as it's dropped, for it to take what it's been given
+        let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(text.as_bytes()));
+        match (written, child.wait()) {
+            (Ok(()), Ok(status)) if status.success() => return Ok(()),
+            (Err(e), _) | (_, Err(e)) => error = io::Error::new(e.kind(), format!("{} failed: {}", command[0], e)),
+            (Ok(()), Ok(status)) => {
+                error = io::Error::new(io::ErrorKind::Other, format!("{} failed: {}", command[0], status));
+            }
+        }
+    }
+    Err(error)
+}
+
+/// The characters typing `text` takes, with line breaks as Enter, and the number of characters
+/// left out because the keyboard has no way to type them
+pub fn paste_chars(text: &str) -> (Vec<char>, usize) {
+    let mut skipped = 0;
+    let chars = text
+        .replace("\r\n", "\n")
+        .chars()
+        .filter_map(|c| match c {
+            '\n' | '\r' => Some('\r'),
+            c if c.is_control() => {
+                skipped += 1;
+                None
+            }
+            c => Some(c),
+        })
+        .collect();
+    (chars, skipped)
+}
+
+/// A paste being typed in
+pub struct Paste {
+    chars: VecDeque<char>,
+    /// least time between keys
+    delay: Duration,
+    /// when the next key is due
+    next: Instant,
+}
+impl Paste {
+    /// Starts typing `chars` at `now`, a key every `delay` at most
+    pub fn new(chars: Vec<char>, delay: Duration, now: Instant) -> Paste {
+        Paste { chars: chars.into(), delay, next: now }
+    }
+    /// Adds `chars` to the end of what's still to type
+    pub fn extend(&mut self, chars: Vec<char>) {
+        self.chars.extend(chars);
+    }
+    /// The key events of the characters due by `now`. Keys don't bunch up to catch up when this
+    /// isn't called as often as they're due; with no delay, they all come at once.
+    pub fn due(&mut self, now: Instant) -> Vec<HostKeyEvent> {
+        let mut events = Vec::new();
+        while now >= self.next {
+            match self.chars.pop_front() {
+                // Enter is a key, not a character
+                Some('\r') => events.extend_from_slice(&[
+                    HostKeyEvent::Key(Key::Enter, KeyAction::Press),
+                    HostKeyEvent::Key(Key::Enter, KeyAction::Release),
+                ]),
+                Some(c) => events.push(HostKeyEvent::Char(c)),
+                None => break,
+            }
+            self.next = now + self.delay;
+        }
+        events
+    }
+    pub fn is_done(&self) -> bool {
+        self.chars.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paste() {
+        // line breaks, whichever way they're written, are Enter; tabs and the like can't be typed
+        let (chars, skipped) = paste_chars("Ké\r\ny\t福\n😊\u{7}");
+        assert_eq!(chars, vec!['K', 'é', '\r', 'y', '福', '\r', '😊']);
+        assert_eq!(skipped, 2);
+
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+        let mut paste = Paste::new(vec!['a', '\r', 'b'], Duration::from_millis(20), start);
+        assert_eq!(paste.due(ms(0)), vec![HostKeyEvent::Char('a')]);
+        assert!(paste.due(ms(19)).is_empty());
+        // late: the next key comes, but not the one after it as well
+        assert_eq!(paste.due(ms(50)), vec![
+            HostKeyEvent::Key(Key::Enter, KeyAction::Press),
+            HostKeyEvent::Key(Key::Enter, KeyAction::Release),
+        ]);
+        assert!(paste.due(ms(69)).is_empty());
+        paste.extend(vec!['c']);
+        assert_eq!(paste.due(ms(70)), vec![HostKeyEvent::Char('b')]);
+        assert!(!paste.is_done());
+
+        // no delay: all at once
+        let mut paste = Paste::new(vec!['x', 'y'], Duration::from_millis(0), start);
+        assert_eq!(paste.due(start), vec![HostKeyEvent::Char('x'), HostKeyEvent::Char('y')]);
+        assert!(paste.is_done());
+    }
+}
@@ -0,0 +1,132 @@
+//! Damage tracking for partial updates. The memory LCD's controller updates whole lines, never
+//! parts of them, so damage is snapped out to the lines it touches, and a frame's damage is kept
+//! as at most `MAX_UPDATE_BANDS` runs of lines. Damage that needs more than that has its closest
+//! runs merged, which sends the lines between them too: partial-update code that leans on that
+//! is slower on the device than it looks, so it's logged, or with strict damage, panicked on.
+
+use crate::api::Rectangle;
+use crate::backend::{FB_LINES, FB_SIZE, FB_WIDTH_WORDS};
+use std::ops::Range;
+
+/// The most runs of lines a frame's damage is sent as
+pub const MAX_UPDATE_BANDS: usize = 8;
+
+/// The lines damaged since the last frame went out
+#[derive(Debug, Default)]
+pub struct Damage {
+    /// runs of damaged lines, in order, none touching another
+    bands: Vec<Range<usize>>,
+    /// panic, rather than log, when bands have to be merged
+    strict: bool,
+    /// times bands have had to be merged
+    coalesced: u64,
+}
+
+impl Damage {
+    /// Panics, rather than logging, when damage needs more bands than the hardware takes, so
+    /// tests of partial-update code catch it
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// Adds `area` of the frame buffer to the damage, snapped out to the whole lines it
+    /// touches. Returns `true` if that took merging bands.
+    pub fn add(&mut self, area: &Rectangle) -> bool {
+        let clamp = |y: i16| (y.max(0) as usize).min(FB_LINES);
+        let lines = clamp(area.tl.y.min(area.br.y))..clamp(area.tl.y.max(area.br.y) + 1);
+        if lines.is_empty() {
+            return false;
+        }
+        let at = self.bands.iter().position(|band| band.start > lines.start).unwrap_or(self.bands.len());
+        self.bands.insert(at, lines);
+        // bands that overlap or touch are one band
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(self.bands.len());
+        for band in self.bands.drain(..) {
+            match merged.last_mut() {
+                Some(last) if band.start <= last.end => last.end = last.end.max(band.end),
+                _ => merged.push(band),
+            }
+        }
+        self.bands = merged;
+        if self.bands.len() <= MAX_UPDATE_BANDS {
+            return false;
+        }
+        self.coalesced += 1;
+        let message = format!(
+            "GFX: damage needs {} bands, but updates take at most {}; merging the closest",
+            self.bands.len(),
+            MAX_UPDATE_BANDS
+        );
+        assert!(!self.strict, "{}", message);
+        log::warn!("{}", message);
+        while self.bands.len() > MAX_UPDATE_BANDS {
+            let closest = (1..self.bands.len())
+                .min_by_key(|&i| self.bands[i].start - self.bands[i - 1].end)
+                .unwrap_or(1);
+            let band = self.bands.remove(closest);
+            self.bands[closest - 1].end = band.end;
+        }
+        true
+    }
+    /// The runs of lines damaged, in order
+    pub fn bands(&self) -> &[Range<usize>] {
+        &self.bands
+    }
+    /// Times damage needed more bands than updates take, and had them merged
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+    /// Marks the damaged lines of `fb` dirty, for the next update to send, and starts the next
+    /// frame's damage afresh
+    pub fn mark_lines(&mut self, fb: &mut [u32; FB_SIZE]) {
+        for line in self.bands.drain(..).flatten() {
+            fb[line * FB_WIDTH_WORDS + FB_WIDTH_WORDS - 1] |= 0x1_0000;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Point;
+
+    fn area(top: i16, bottom: i16) -> Rectangle {
+        Rectangle::new(Point::new(10, top), Point::new(20, bottom))
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut damage = Damage::default();
+        // snapped to whole lines, whichever way round the corners are, and clipped to the display
+        assert!(!damage.add(&Rectangle::new(Point::new(30, 12), Point::new(5, 10))));
+        assert!(!damage.add(&area(13, 14)));
+        assert!(!damage.add(&area(-5, 1)));
+        assert!(!damage.add(&area(FB_LINES as i16 - 2, 1000)));
+        assert!(!damage.add(&area(-10, -1)));
+        assert_eq!(damage.bands(), &[0..2, 10..15, FB_LINES - 2..FB_LINES]);
+
+        let mut fb = [0; FB_SIZE];
+        damage.mark_lines(&mut fb);
+        let dirty: Vec<usize> = (0..FB_LINES).filter(|line| fb[line * FB_WIDTH_WORDS + FB_WIDTH_WORDS - 1] != 0).collect();
+        assert_eq!(dirty, [0, 1, 10, 11, 12, 13, 14, FB_LINES - 2, FB_LINES - 1]);
+        assert!(damage.bands().is_empty());
+
+        // one band too many: the two closest are merged, taking in the lines between
+        for band in 0..MAX_UPDATE_BANDS as i16 {
+            assert!(!damage.add(&area(band * 50, band * 50)));
+        }
+        assert!(damage.add(&area(152, 152)));
+        assert_eq!(damage.bands().len(), MAX_UPDATE_BANDS);
+        assert_eq!(damage.bands()[3], 150..153);
+        assert_eq!(damage.coalesced(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "damage needs 9 bands")]
+    fn test_strict_damage() {
+        let mut damage = Damage::default();
+        damage.set_strict(true);
+        for band in 0..=MAX_UPDATE_BANDS as i16 {
+            damage.add(&area(band * 10, band * 10));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! What the graphics server asks of a display, whichever backend's behind it: the hosted one, in
+//! a window or headless, or the device's memory LCD. Features only some backends have are
+//! flagged in `Capabilities`, and their methods do nothing, or fail, on the others.
+
+use crate::api::{Point, PointerAction, Rectangle};
+use crate::backend::{DisplayError, DisplayInfo, FrameComplete, FB_SIZE};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The optional features of a backend
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `screenshot()` saves what's shown
+    pub screenshot: bool,
+    /// there's a window on the host, taking keys and the pointer
+    pub window: bool,
+    /// `suspend()` and `resume()` power the panel down and back up
+    pub power: bool,
+    /// `set_brightness()` dims what's shown as the backlight would
+    pub brightness: bool,
+}
+
+/// Something the pointer did, at a pixel of the display
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PointerEvent {
+    pub action: PointerAction,
+    pub at: Point,
+}
+
+/// A display the graphics server draws to
+pub trait DisplayBackend {
+    /// What this backend can do beyond the basics
+    fn capabilities(&self) -> Capabilities;
+    /// The size of the display and the layout of its frame buffer
+    fn info(&self) -> DisplayInfo;
+    fn screen_size(&self) -> Point {
+        let info = self.info();
+        Point::new(info.width as i16, info.height as i16)
+    }
+
+    /// The frame buffer, to draw into
+    fn native_buffer(&mut self) -> &mut [u32; FB_SIZE];
+    /// The frame buffer as drawn, with the dirty bits cleared
+    fn copy_framebuffer(&mut self) -> [u32; FB_SIZE] {
+        let words_per_line = self.info().words_per_line;
+        let mut fb = *self.native_buffer();
+        for line in fb.chunks_mut(words_per_line) {
+            line[words_per_line - 1] &= 0x0000_FFFF;
+        }
+        fb
+    }
+    /// Puts `bmp`, a whole frame, up on the display
+    fn blit_screen(&mut self, bmp: &[u32; FB_SIZE]);
+    /// Copies the `line_count` lines in `src` into the frame buffer from `start_line` down; they
+    /// go up on the display with the next `redraw`
+    fn blit_region(&mut self, src: &[u32], start_line: usize, line_count: usize);
+    /// Marks `area` of the frame buffer for the next redraw to send
+    fn mark_dirty(&mut self, area: Rectangle);
+
+    /// Sends the lines of the frame buffer that have changed to the display
+    fn redraw(&mut self) -> Result<(), DisplayError>;
+    /// Takes in what's happened to the display since it was last redrawn or updated
+    fn update(&mut self) -> Result<(), DisplayError>;
+    /// Whether the display's still taking the last frame sent it
+    fn busy(&self) -> bool;
+    /// Blocks until the display's next refresh
+    fn wait_for_vsync(&mut self);
+    /// Has each frame put up from now on sent to `cid` as a scalar message on `opcode`, its
+    /// number and when it was put up, in milliseconds since the display started, as its first
+    /// two arguments
+    fn on_frame_complete(&mut self, cid: xous::CID, opcode: usize);
+    /// Makes sure the frame going up, if any, is reported to the `on_frame_complete()` listener
+    /// once it's up, for a backend that only finds out as it's updated
+    fn report_frame_complete(&mut self) -> Result<(), DisplayError> {
+        Ok(())
+    }
+    /// The last frame put up, if it's been kept track of
+    fn last_frame(&self) -> Option<FrameComplete> {
+        None
+    }
+    /// Redraws that found nothing new to put up, where they're counted
+    fn skipped_frames(&self) -> u64 {
+        0
+    }
+
+    /// Gets the display ready for the system to sleep
+    fn suspend(&mut self) {}
+    /// Brings the display back, as it was before `suspend()`
+    fn resume(&mut self) {}
+    /// Keeps a copy of the frame buffer for `pop()` to put back
+    fn stash(&mut self);
+    /// Puts back the frame `stash()` kept, but for the status bar, and redraws
+    fn pop(&mut self) -> Result<(), DisplayError>;
+    fn set_inverted(&mut self, inverted: bool);
+    fn set_devboot(&mut self, ena: bool);
+    /// Dims the display as the backlight at `level`, 0-31, would, where
+    /// `Capabilities::brightness` says so
+    fn set_brightness(&mut self, _level: u8) {}
+
+    /// How often `poll_input()` is to be called, for a backend that takes input in itself
+    fn input_poll_interval(&self) -> Option<Duration> {
+        None
+    }
+    /// Takes in the keys and pointer events that have come in since the display was last
+    /// polled, redrawn or updated
+    fn poll_input(&mut self) -> Result<(), DisplayError> {
+        Ok(())
+    }
+    /// Takes the characters typed since the last call, oldest first, for the keyboard service
+    fn drain_keys(&mut self) -> Vec<char> {
+        Vec::new()
+    }
+    /// Takes the pointer events since the last call, oldest first
+    fn drain_pointer_events(&mut self) -> Vec<PointerEvent> {
+        Vec::new()
+    }
+
+    /// Which of the display's surfaces this is; the main display is 0
+    fn surface(&self) -> usize {
+        0
+    }
+    /// Opens another surface alongside this one, numbered `surface`, if the backend can
+    fn open_surface(&self, _surface: usize) -> Option<Box<dyn DisplayBackend>> {
+        None
+    }
+
+    /// Saves what's shown as an image in the display's capture directory, where
+    /// `Capabilities::screenshot` says so, returning the path it was saved at
+    fn screenshot(&self) -> std::io::Result<PathBuf> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "this display can't take screenshots"))
+    }
+    /// The snapshot the display started out showing, if any, for the boot logo to be left off
+    fn startup_snapshot(&self) -> Option<&Path> {
+        None
+    }
+}
+
+#[cfg(all(test, any(windows, unix)))]
+mod tests {
+    use super::*;
+    use crate::backend::{XousDisplay, DISPLAY_INFO, FB_WIDTH_WORDS};
+
+    #[test]
+    fn test_display_backend() {
+        let mut headless = XousDisplay::headless();
+        headless.set_capture_dir(&std::env::temp_dir().join(format!("xous-display-backend-{}", std::process::id())));
+        let display: &mut dyn DisplayBackend = &mut headless;
+        assert_eq!(display.capabilities(), Capabilities { screenshot: true, window: false, power: true, brightness: true });
+        assert_eq!(display.info(), DISPLAY_INFO);
+        assert_eq!(display.screen_size(), Point::new(DISPLAY_INFO.width as i16, DISPLAY_INFO.height as i16));
+        assert!(display.startup_snapshot().is_none());
+
+        // a frame blitted comes back with its dirty bits cleared, once it's been drawn over
+        let mut frame = [0xAAAA_AAAA; FB_SIZE];
+        display.blit_screen(&frame);
+        display.native_buffer()[0] = 0;
+        frame[0] = 0;
+        display.mark_dirty(Rectangle::new(Point::new(0, 0), Point::new(0, 0)));
+        assert!(display.redraw().is_ok() && display.update().is_ok());
+        assert!(!display.busy());
+        let fb = display.copy_framebuffer();
+        assert_eq!(fb[1..FB_WIDTH_WORDS - 1], frame[1..FB_WIDTH_WORDS - 1]);
+        assert_eq!((fb[0], fb[FB_WIDTH_WORDS - 1]), (0, 0x0000_AAAA));
+
+        // what's stashed comes back below the status bar, and only there
+        display.stash();
+        display.blit_screen(&[0xFFFF_FFFF; FB_SIZE]);
+        assert!(display.pop().is_ok());
+        let fb = display.copy_framebuffer();
+        assert_eq!((fb[0], fb[FB_WIDTH_WORDS * 32]), (0xFFFF_FFFF, 0xAAAA_AAAA));
+
+        // nothing's new since the frame `pop()` put up, so the redraw's skipped
+        assert_eq!(display.last_frame().map(|last| last.frame), Some(2));
+        assert!(display.redraw().is_ok());
+        assert_eq!((display.last_frame().map(|last| last.frame), display.skipped_frames()), (Some(2), 1));
+
+        // with no window, there's no input but a script's
+        assert!(display.input_poll_interval().is_some());
+        assert!(display.poll_input().is_ok());
+        assert!(display.drain_keys().is_empty() && display.drain_pointer_events().is_empty());
+
+        // it's the main display, and another surface opens alongside it headless too
+        let surface = display.open_surface(1).unwrap();
+        assert_eq!((display.surface(), surface.surface()), (0, 1));
+        assert!(!surface.capabilities().window);
+
+        let path = display.screenshot().unwrap();
+        assert!(path.exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,127 @@
+//! Frame rate statistics for the hosted display, and an overlay that shows them in a corner of
+//! the window
+
+use super::overlaytext::{draw_text_box, text_box_height, Corner};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// frames the rolling average is taken over
+const FPS_WINDOW: usize = 30;
+
+/// How the hosted display has been keeping up
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// frames rendered for the window
+    pub frames: u64,
+    /// frames put up in the window with something new in them; the counter frame numbers
+    /// elsewhere go by
+    pub presented: u64,
+    /// when the latest of them was put up, since the display started
+    pub last_presented: Option<Duration>,
+    /// frame rate going by the time since the frame before, in frames per second
+    pub fps: f32,
+    /// frame rate over the last `FPS_WINDOW` frames
+    pub avg_fps: f32,
+    /// time the latest frame took to convert from the emulated framebuffer
+    pub last_convert: Duration,
+    /// the frame rate limit in force, `None` if uncapped
+    pub fps_limit: Option<u32>,
+    /// the backlight level the display is dimmed to, out of 31
+    pub brightness: u8,
+}
+
+/// Keeps `FrameStats` up to date as frames go out
+#[derive(Debug, Default)]
+pub struct FrameClock {
+    /// when the latest frames went out, oldest first
+    times: VecDeque<Instant>,
+    stats: FrameStats,
+}
+impl FrameClock {
+    /// Counts a frame going out at `now`, which took `convert` to convert
+    pub fn frame(&mut self, now: Instant, convert: Duration) {
+        if self.times.len() == FPS_WINDOW {
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+        let rate = |frames: usize, since: Instant| {
+            let elapsed = now.saturating_duration_since(since).as_secs_f32();
+            if elapsed > 0.0 { frames as f32 / elapsed } else { 0.0 }
+        };
+        let n = self.times.len();
+        self.stats.frames += 1;
+        self.stats.fps = if n >= 2 { rate(1, self.times[n - 2]) } else { 0.0 };
+        self.stats.avg_fps = rate(n - 1, self.times[0]);
+        self.stats.last_convert = convert;
+    }
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
+
+/// lines of text in the overlay
+const OVERLAY_TEXT_LINES: usize = 6;
+/// lines of the display the overlay covers, from the top
+pub const OVERLAY_HEIGHT: usize = text_box_height(OVERLAY_TEXT_LINES);
+
+/// The overlay's lines of text
+fn overlay_text(stats: &FrameStats) -> [String; OVERLAY_TEXT_LINES] {
+    [
+        format!("FPS {:.1}", stats.fps),
+        format!("AVG {:.1}", stats.avg_fps),
+        format!("N {}", stats.frames),
+        format!("CONV {:.2}MS", stats.last_convert.as_secs_f32() * 1000.0),
+        match stats.fps_limit {
+            Some(fps) => format!("LIM {}", fps),
+            None => "LIM -".to_string(),
+        },
+        format!("BL {}", stats.brightness),
+    ]
+}
+
+/// Draws `stats` into the top right corner of `native`, a `width` pixels wide buffer, as `fg`
+/// text on a box of `bg`. The box covers the top `OVERLAY_HEIGHT` lines at most.
+pub fn draw_overlay(native: &mut [u32], width: usize, stats: &FrameStats, fg: u32, bg: u32) {
+    draw_text_box(native, width, &overlay_text(stats), Corner::TopRight, fg, bg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::overlaytext::{CELL_WIDTH, MARGIN};
+
+    #[test]
+    fn test_frame_stats() {
+        let start = Instant::now();
+        let mut clock = FrameClock::default();
+        clock.frame(start, Duration::from_micros(500));
+        assert_eq!(clock.stats().fps, 0.0);
+        // 20 frames a second, then one that came 100ms late
+        for i in 1..=10 {
+            clock.frame(start + Duration::from_millis(50 * i), Duration::from_micros(500));
+        }
+        assert!((clock.stats().fps - 20.0).abs() < 0.01);
+        clock.frame(start + Duration::from_millis(650), Duration::from_millis(2));
+        let stats = clock.stats();
+        assert_eq!(stats.frames, 12);
+        assert!((stats.fps - 6.67).abs() < 0.01);
+        assert!((stats.avg_fps - 11.0 / 0.65).abs() < 0.01);
+        assert_eq!(stats.last_convert, Duration::from_millis(2));
+        assert_eq!(overlay_text(&stats)[3], "CONV 2.00MS");
+        assert_eq!(overlay_text(&FrameStats { fps_limit: Some(15), ..stats })[4], "LIM 15");
+        assert_eq!(overlay_text(&FrameStats { brightness: 31, ..stats })[5], "BL 31");
+
+        const BG: u32 = 0x000000;
+        const FG: u32 = 0xFFFFFF;
+        let width = 100;
+        let mut native = vec![0x123456; width * 60];
+        draw_overlay(&mut native, width, &stats, FG, BG);
+        // "CONV 2.00MS" is the longest line: 11 cells, less the last gap, and the margins
+        let left = width - (11 * CELL_WIDTH - 1 + 2 * MARGIN);
+        assert_eq!((native[left - 1], native[left]), (0x123456, BG));
+        assert_eq!(native[OVERLAY_HEIGHT * width + left], 0x123456);
+        // the top left of the 'F' of "FPS"
+        assert_eq!(native[MARGIN * width + left + MARGIN], FG);
+        assert!(native[..OVERLAY_HEIGHT * width].iter().filter(|&&p| p == FG).count() > 100);
+    }
+}
@@ -0,0 +1,141 @@
+//! Gestures made out of the drags in the hosted emulator window, for scrollable UI: a drag that's
+//! long and quick enough is a swipe
+
+use crate::api::{Point, PointerAction};
+use super::PointerEvent;
+use std::time::{Duration, Instant};
+
+/// What makes a drag a swipe
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GestureConfig {
+    /// pixels a swipe has to cover, along the way it goes
+    pub min_distance: u16,
+    /// longest a swipe can take, from press to release
+    pub max_duration: Duration,
+}
+impl Default for GestureConfig {
+    fn default() -> GestureConfig {
+        GestureConfig { min_distance: 40, max_duration: Duration::from_millis(500) }
+    }
+}
+
+/// What a drag was taken for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    SwipeLeft,
+    SwipeRight,
+    SwipeUp,
+    SwipeDown,
+    /// a drag too short or too slow for a swipe
+    Drag,
+}
+
+/// A drag, from press to release, and what it was taken for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GestureEvent {
+    pub gesture: Gesture,
+    pub from: Point,
+    pub to: Point,
+    pub duration: Duration,
+    /// pixels per second along x and y, from start to end
+    pub velocity: (f32, f32),
+}
+
+/// Makes out what a drag from `from` to `to` over `duration` was. One that went nowhere is a tap,
+/// and no gesture.
+pub fn classify(from: Point, to: Point, duration: Duration, config: &GestureConfig) -> Option<GestureEvent> {
+    let (dx, dy) = (to.x as i32 - from.x as i32, to.y as i32 - from.y as i32);
+    if dx == 0 && dy == 0 {
+        return None;
+    }
+    let horizontal = dx.abs() >= dy.abs();
+    let distance = if horizontal { dx.abs() } else { dy.abs() };
+    let gesture = if distance < config.min_distance as i32 || duration > config.max_duration {
+        Gesture::Drag
+    } else if horizontal {
+        if dx < 0 { Gesture::SwipeLeft } else { Gesture::SwipeRight }
+    } else if dy < 0 {
+        Gesture::SwipeUp
+    } else {
+        Gesture::SwipeDown
+    };
+    // a drag can't go by quicker than the mouse is sampled, but keep clear of dividing by 0
+    let secs = duration.as_secs_f32().max(0.001);
+    Some(GestureEvent { gesture, from, to, duration, velocity: (dx as f32 / secs, dy as f32 / secs) })
+}
+
+/// Follows pointer events from press to release, to make out the gesture each drag was
+#[derive(Debug, Default)]
+pub struct GestureTracker {
+    /// where and when the pointer was pressed, while it is
+    pressed: Option<(Point, Instant)>,
+}
+impl GestureTracker {
+    /// Takes in `event`, which came at `now`, and returns the gesture it finished, if any
+    pub fn pointer(&mut self, event: PointerEvent, now: Instant, config: &GestureConfig) -> Option<GestureEvent> {
+        match event.action {
+            PointerAction::Press => {
+                self.pressed = Some((event.at, now));
+                None
+            }
+            PointerAction::Move => None,
+            PointerAction::Release => self
+                .pressed
+                .take()
+                .and_then(|(from, at)| classify(from, event.at, now.saturating_duration_since(at), config)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gestures() {
+        let config = GestureConfig::default();
+        let ms = Duration::from_millis;
+        let gesture = |from: (i16, i16), to: (i16, i16), duration| {
+            classify(Point::new(from.0, from.1), Point::new(to.0, to.1), duration, &config).map(|g| g.gesture)
+        };
+        // quick and far enough: swipes, going by the way the drag went most
+        assert_eq!(gesture((200, 100), (100, 130), ms(200)), Some(Gesture::SwipeLeft));
+        assert_eq!(gesture((100, 100), (140, 100), ms(500)), Some(Gesture::SwipeRight));
+        assert_eq!(gesture((100, 300), (90, 100), ms(100)), Some(Gesture::SwipeUp));
+        assert_eq!(gesture((100, 100), (130, 400), ms(100)), Some(Gesture::SwipeDown));
+        // too short, too slow, or neither way enough to be a swipe
+        assert_eq!(gesture((100, 100), (139, 100), ms(100)), Some(Gesture::Drag));
+        assert_eq!(gesture((100, 100), (300, 100), ms(501)), Some(Gesture::Drag));
+        assert_eq!(gesture((100, 100), (130, 130), ms(100)), Some(Gesture::Drag));
+        // a tap
+        assert_eq!(gesture((100, 100), (100, 100), ms(100)), None);
+
+        let swipe = classify(Point::new(300, 50), Point::new(100, 10), ms(250), &config).unwrap();
+        assert_eq!(swipe.velocity, (-800.0, -160.0));
+
+        // a trace of pointer events: the press and the release make the gesture, not the moves
+        let start = Instant::now();
+        let mut tracker = GestureTracker::default();
+        let trace = [
+            (0, PointerAction::Press, 300, 200),
+            (50, PointerAction::Move, 250, 205),
+            (100, PointerAction::Move, 150, 210),
+            (150, PointerAction::Release, 100, 210),
+            (400, PointerAction::Press, 10, 10),
+            (450, PointerAction::Release, 10, 10),
+        ];
+        let gestures: Vec<_> = trace
+            .iter()
+            .filter_map(|&(t, action, x, y)| {
+                tracker.pointer(PointerEvent { action, at: Point::new(x, y) }, start + ms(t), &config)
+            })
+            .collect();
+        assert_eq!(gestures, vec![GestureEvent {
+            gesture: Gesture::SwipeLeft,
+            from: Point::new(300, 200),
+            to: Point::new(100, 210),
+            duration: ms(150),
+            velocity: (-200.0 / 0.15, 10.0 / 0.15),
+        }]);
+    }
+}
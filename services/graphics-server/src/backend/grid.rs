@@ -0,0 +1,97 @@
+//! A grid of guide lines drawn over the hosted display, for lining UI up with the GAM's design
+//! grid. It's only ever drawn in the window: the frame buffer never has it in.
+
+/// colour the grid lines are tinted towards
+const GRID_COLOUR: u32 = 0x2080FF;
+/// colour of the margin guides, drawn solid
+const MARGIN_COLOUR: u32 = 0xFF40C0;
+
+/// The lines of a grid, in pixels of the display
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GridSpec {
+    /// space between the faint lines, or 0 for none
+    pub minor: usize,
+    /// space between the stronger lines, or 0 for none
+    pub major: usize,
+    /// guides this far in from each edge of the display, if any
+    pub margin: Option<usize>,
+}
+impl Default for GridSpec {
+    fn default() -> GridSpec {
+        GridSpec { minor: 8, major: 16, margin: None }
+    }
+}
+impl GridSpec {
+    /// The colour a guide at pixel (`x`, `y`) of a `size` display tints it towards, and by how
+    /// many quarters of the way, if there's one there
+    fn guide_at(&self, x: usize, y: usize, (width, height): (usize, usize)) -> Option<(u32, u32)> {
+        let on = |spacing: usize| spacing > 0 && (x % spacing == 0 || y % spacing == 0);
+        let on_margin = self.margin.map_or(false, |m| {
+            let (right, bottom) = (width.saturating_sub(m + 1), height.saturating_sub(m + 1));
+            ((x == m || x == right) && (m..=bottom).contains(&y)) || ((y == m || y == bottom) && (m..=right).contains(&x))
+        });
+        if on_margin {
+            Some((MARGIN_COLOUR, 4))
+        } else if on(self.major) {
+            Some((GRID_COLOUR, 2))
+        } else if on(self.minor) {
+            Some((GRID_COLOUR, 1))
+        } else {
+            None
+        }
+    }
+}
+
+/// `pixel` tinted `quarters` quarters of the way towards `colour`
+fn tint(pixel: u32, colour: u32, quarters: u32) -> u32 {
+    let (from, to) = (pixel.to_be_bytes(), colour.to_be_bytes());
+    (1..4).fold(0, |tinted, i| {
+        let (from, to) = (from[i] as i32, to[i] as i32);
+        (tinted << 8) | (from + (to - from) * quarters as i32 / 4) as u32
+    })
+}
+
+/// Draws the grid `spec` gives over `native`, a `size` buffer showing a `display` sized display.
+/// `to_display` gives the pixel of the display each pixel of `native` shows, so the grid lines
+/// up with the display however it's turned.
+pub fn draw_grid(native: &mut [u32], (width, height): (usize, usize), spec: &GridSpec, display: (usize, usize), to_display: impl Fn(usize, usize) -> (usize, usize)) {
+    for (shown_y, row) in native.chunks_mut(width).take(height).enumerate() {
+        for (shown_x, pixel) in row.iter_mut().enumerate() {
+            let (x, y) = to_display(shown_x, shown_y);
+            if let Some((colour, quarters)) = spec.guide_at(x, y, display) {
+                *pixel = tint(*pixel, colour, quarters);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        assert_eq!(tint(0x000000, 0xFF8040, 2), 0x7F4020);
+        assert_eq!(tint(0x123456, 0xFF8040, 0), 0x123456);
+        assert_eq!(tint(0x123456, 0xFF8040, 4), 0xFF8040);
+
+        // a 10x6 display, turned a quarter, with a line every 2 and 4 pixels and a margin of 1
+        let (width, height) = (6, 10);
+        let mut native = vec![0x000000; width * height];
+        let spec = GridSpec { minor: 2, major: 4, margin: Some(1) };
+        draw_grid(&mut native, (width, height), &spec, (10, 6), |x, y| (y, width - 1 - x));
+        let tinted = |quarters| tint(0x000000, GRID_COLOUR, quarters);
+        let at = |x: usize, y: usize| native[(width - 1 - y) + x * width];
+        assert_eq!((at(0, 0), at(4, 3), at(8, 5)), (tinted(2), tinted(2), tinted(2)));
+        assert_eq!((at(2, 3), at(3, 2), at(6, 5)), (tinted(1), tinted(1), tinted(1)));
+        assert_eq!((at(3, 3), at(5, 5)), (0x000000, 0x000000));
+        // the margin guides go round the display a pixel in, over the grid
+        assert_eq!((at(1, 1), at(8, 1), at(8, 4), at(1, 4), at(5, 1)), (MARGIN_COLOUR, MARGIN_COLOUR, MARGIN_COLOUR, MARGIN_COLOUR, MARGIN_COLOUR));
+        assert_eq!((at(9, 1), at(2, 5)), (0x000000, tinted(1)));
+
+        // with no lines at all, nothing's drawn
+        let mut native = vec![0x123456; 4];
+        draw_grid(&mut native, (2, 2), &GridSpec { minor: 0, major: 0, margin: None }, (2, 2), |x, y| (x, y));
+        assert_eq!(native, vec![0x123456; 4]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Keys typed into the hosted emulator window, and their translation to what the Precursor
+//! keyboard would have sent
+
+use minifb::Key;
+use std::time::{Duration, Instant};
+
+/// What happened to a key of the host keyboard
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    Press,
+    /// the host repeating a key that's held down
+    Repeat,
+    Release,
+}
+
+/// A key event from the emulator window, in the order the host delivered it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HostKeyEvent {
+    /// a key changed state
+    Key(Key, KeyAction),
+    /// a character typed, as the host's keyboard layout makes it out
+    Char(char),
+}
+
+/// The Precursor's keys that have no character of their own: the four function keys under the
+/// screen, the navigation pad's arrows and centre, which brings up the menu, and backspace and
+/// enter. They reach apps as the codes the device's keyboard sends for them, control characters
+/// and arrows, so apps can tell them from anything printable.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PrecursorKey {
+    F1,
+    F2,
+    F3,
+    F4,
+    Center,
+    Up,
+    Down,
+    Left,
+    Right,
+    Backspace,
+    Enter,
+}
+impl PrecursorKey {
+    const ALL: [PrecursorKey; 11] = [
+        PrecursorKey::F1, PrecursorKey::F2, PrecursorKey::F3, PrecursorKey::F4, PrecursorKey::Center,
+        PrecursorKey::Up, PrecursorKey::Down, PrecursorKey::Left, PrecursorKey::Right,
+        PrecursorKey::Backspace, PrecursorKey::Enter,
+    ];
+    /// what the device's keyboard sends for the key
+    pub fn code(self) -> char {
+        match self {
+            PrecursorKey::F1 => 0x11_u8.into(),
+            PrecursorKey::F2 => 0x12_u8.into(),
+            PrecursorKey::F3 => 0x13_u8.into(),
+            PrecursorKey::F4 => 0x14_u8.into(),
+            PrecursorKey::Center => '∴',
+            PrecursorKey::Up => '↑',
+            PrecursorKey::Down => '↓',
+            PrecursorKey::Left => '←',
+            PrecursorKey::Right => '→',
+            PrecursorKey::Backspace => 0x8_u8.into(),
+            PrecursorKey::Enter => 0xd_u8.into(),
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            PrecursorKey::F1 => "F1",
+            PrecursorKey::F2 => "F2",
+            PrecursorKey::F3 => "F3",
+            PrecursorKey::F4 => "F4",
+            PrecursorKey::Center => "CENTER",
+            PrecursorKey::Up => "UP",
+            PrecursorKey::Down => "DOWN",
+            PrecursorKey::Left => "LEFT",
+            PrecursorKey::Right => "RIGHT",
+            PrecursorKey::Backspace => "BACKSPACE",
+            PrecursorKey::Enter => "ENTER",
+        }
+    }
+    /// the navigation keys, and backspace, still type with shift held; the rest don't
+    fn types_shifted(self) -> bool {
+        !matches!(self, PrecursorKey::F1 | PrecursorKey::F2 | PrecursorKey::F3 | PrecursorKey::F4 | PrecursorKey::Enter)
+    }
+    /// the arrows and backspace repeat when held; the others have no repeat on the device
+    fn repeats(self) -> bool {
+        matches!(self, PrecursorKey::Up | PrecursorKey::Down | PrecursorKey::Left | PrecursorKey::Right | PrecursorKey::Backspace)
+    }
+}
+
+/// Of the characters, only the keys for the digits and space repeat when held on the device; the
+/// others type a character of their own when held, rather than repeating
+fn repeats_on_device(c: char) -> bool {
+    c.is_ascii_digit() || c == ' '
+}
+
+/// Host keys that can be mapped to Precursor keys, by the names `XOUS_KEYMAP` and the keymap
+/// overlay give them. F7 to F12 are left out: they're the emulator's hotkeys.
+const HOST_KEY_NAMES: [(Key, &str); 19] = [
+    (Key::F1, "F1"), (Key::F2, "F2"), (Key::F3, "F3"), (Key::F4, "F4"), (Key::F5, "F5"), (Key::F6, "F6"),
+    (Key::Home, "HOME"), (Key::End, "END"), (Key::Insert, "INSERT"), (Key::Delete, "DELETE"),
+    (Key::PageUp, "PAGEUP"), (Key::PageDown, "PAGEDOWN"), (Key::Backspace, "BACKSPACE"),
+    (Key::Enter, "ENTER"), (Key::Tab, "TAB"), (Key::Up, "UP"), (Key::Down, "DOWN"), (Key::Left, "LEFT"),
+    (Key::Right, "RIGHT"),
+];
+
+/// The host key an input script names, in any case: one `XOUS_KEYMAP` can map, or shift
+pub(super) fn host_key(name: &str) -> Option<Key> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "SHIFT" | "LEFTSHIFT" => Some(Key::LeftShift),
+        "RIGHTSHIFT" => Some(Key::RightShift),
+        name => HOST_KEY_NAMES.iter().find(|&&(_, n)| n == name).map(|&(key, _)| key),
+    }
+}
+
+/// Which host keys stand for which of the Precursor's keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostKeymap {
+    /// in the order the overlay lists them
+    keys: Vec<(Key, PrecursorKey)>,
+}
+impl Default for HostKeymap {
+    fn default() -> HostKeymap {
+        HostKeymap {
+            keys: vec![
+                (Key::F1, PrecursorKey::F1),
+                (Key::F2, PrecursorKey::F2),
+                (Key::F3, PrecursorKey::F3),
+                (Key::F4, PrecursorKey::F4),
+                (Key::Home, PrecursorKey::Center),
+                (Key::Up, PrecursorKey::Up),
+                (Key::Down, PrecursorKey::Down),
+                (Key::Left, PrecursorKey::Left),
+                (Key::Right, PrecursorKey::Right),
+                (Key::Backspace, PrecursorKey::Backspace),
+                (Key::Delete, PrecursorKey::Backspace),
+                (Key::Enter, PrecursorKey::Enter),
+            ],
+        }
+    }
+}
+impl HostKeymap {
+    pub fn get(&self, key: Key) -> Option<PrecursorKey> {
+        self.keys.iter().find(|&&(host, _)| host == key).map(|&(_, precursor)| precursor)
+    }
+    /// Has `key` stand for `precursor`, or for nothing with `None`
+    pub fn set(&mut self, key: Key, precursor: Option<PrecursorKey>) {
+        match (self.keys.iter_mut().find(|(host, _)| *host == key), precursor) {
+            (Some(entry), Some(precursor)) => entry.1 = precursor,
+            (None, Some(precursor)) => self.keys.push((key, precursor)),
+            (_, None) => self.keys.retain(|&(host, _)| host != key),
+        }
+    }
+    /// This keymap with the changes `spec` makes, as `XOUS_KEYMAP` gives them: `host=device`
+    /// pairs split by commas, like `F5=F1,Insert=center,Home=none`, with `none` for no key.
+    /// Returns `None` if any of the pairs can't be made out.
+    pub fn with_overrides(&self, spec: &str) -> Option<HostKeymap> {
+        let mut keymap = self.clone();
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let mut sides = pair.splitn(2, '=').map(|side| side.trim().to_ascii_uppercase());
+            let (host, precursor) = (sides.next()?, sides.next()?);
+            let key = HOST_KEY_NAMES.iter().find(|&&(_, name)| name == host)?.0;
+            let precursor = match precursor.as_str() {
+                "NONE" => None,
+                "HOME" | "MENU" => Some(PrecursorKey::Center),
+                name => Some(*PrecursorKey::ALL.iter().find(|key| key.name() == name)?),
+            };
+            keymap.set(key, precursor);
+        }
+        Some(keymap)
+    }
+    /// The mapping a line a key, for showing
+    pub fn describe(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|&(key, precursor)| {
+                let host = HOST_KEY_NAMES.iter().find(|&&(k, _)| k == key).map_or("?", |&(_, name)| name);
+                format!("{} > {}", host, precursor.name())
+            })
+            .collect()
+    }
+}
+
+/// How held keys repeat
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyRepeatConfig {
+    /// how long a key is held before it repeats
+    pub delay: Duration,
+    /// time between repeats
+    pub rate: Duration,
+}
+impl Default for KeyRepeatConfig {
+    /// the keyboard service's defaults on the device
+    fn default() -> KeyRepeatConfig {
+        KeyRepeatConfig { delay: Duration::from_millis(500), rate: Duration::from_millis(20) }
+    }
+}
+
+/// Repeats held keys the way the Precursor's keyboard does, in place of the host's auto-repeat:
+/// only the keys that repeat on the device do, after its delay and at its rate. As on the device,
+/// it's the latest key pressed that repeats.
+#[derive(Debug, Default)]
+pub struct KeyRepeater {
+    config: KeyRepeatConfig,
+    /// the host repeated a key, so the character that comes next is a repeat too
+    host_repeat: bool,
+    /// the latest key pressed, and when, for the character it types to repeat
+    pressed: Option<(Key, Instant)>,
+    /// the key held down that repeats, the event it repeats as, and when it's next due
+    held: Option<(Key, HostKeyEvent, Instant)>,
+}
+impl KeyRepeater {
+    pub fn new(config: KeyRepeatConfig) -> KeyRepeater {
+        KeyRepeater { config, ..Default::default() }
+    }
+    /// Takes in `event`, which came from the host at `now`, and returns it, unless it's one of
+    /// the host's repeats
+    pub fn event(&mut self, event: HostKeyEvent, now: Instant, keymap: &HostKeymap) -> Option<HostKeyEvent> {
+        match event {
+            HostKeyEvent::Key(_, KeyAction::Repeat) => {
+                self.host_repeat = true;
+                return None;
+            }
+            HostKeyEvent::Key(key, KeyAction::Press) => {
+                self.host_repeat = false;
+                self.pressed = Some((key, now));
+                if keymap.get(key).map_or(false, |precursor| precursor.repeats()) {
+                    self.held = Some((key, HostKeyEvent::Key(key, KeyAction::Repeat), now + self.config.delay));
+                } else if let Some((_, _, next)) = self.held.as_mut() {
+                    // any key going down puts off the repeat, as the device times it from the
+                    // latest key down
+                    *next = now + self.config.delay;
+                }
+            }
+            HostKeyEvent::Key(key, KeyAction::Release) => {
+                self.host_repeat = false;
+                if self.held.map_or(false, |(held, _, _)| held == key) {
+                    self.held = None;
+                }
+                if self.pressed.map_or(false, |(pressed, _)| pressed == key) {
+                    self.pressed = None;
+                }
+            }
+            HostKeyEvent::Char(_) if self.host_repeat => return None,
+            HostKeyEvent::Char(c) => {
+                if let Some((key, at)) = self.pressed.take() {
+                    if repeats_on_device(c) {
+                        self.held = Some((key, event, at + self.config.delay));
+                    }
+                }
+            }
+        }
+        Some(event)
+    }
+    /// The repeat due by `now`, if there is one. Repeats don't bunch up to catch up when this
+    /// isn't called as often as they're due.
+    pub fn due(&mut self, now: Instant) -> Option<HostKeyEvent> {
+        match self.held.as_mut() {
+            Some((_, event, next)) if now >= *next => {
+                *next = now + self.config.rate;
+                Some(*event)
+            }
+            _ => None,
+        }
+    }
+    /// Stops any repeat, for when the key's release might not come, like when the window loses
+    /// focus
+    pub fn stop(&mut self) {
+        self.held = None;
+        self.pressed = None;
+    }
+}
+
+/// Turns host key events into the characters the keyboard service takes, keeping track of shift
+#[derive(Debug, Default)]
+pub struct KeyTranslator {
+    left_shift: bool,
+    right_shift: bool,
+}
+impl KeyTranslator {
+    /// The character `event` types on the Precursor keyboard, if any. Characters come from the
+    /// host's layout; of the keys, only those `keymap` has standing for a Precursor key type
+    /// anything, along with two for testing rendering. A repeat types again.
+    pub fn translate(&mut self, event: HostKeyEvent, keymap: &HostKeymap) -> Option<char> {
+        match event {
+            HostKeyEvent::Key(Key::LeftShift, action) => {
+                self.left_shift = action != KeyAction::Release;
+                None
+            }
+            HostKeyEvent::Key(Key::RightShift, action) => {
+                self.right_shift = action != KeyAction::Release;
+                None
+            }
+            HostKeyEvent::Key(_, KeyAction::Release) => None,
+            HostKeyEvent::Key(key, _) => {
+                let shift = self.left_shift || self.right_shift;
+                match keymap.get(key) {
+                    Some(precursor) if shift && !precursor.types_shifted() => None,
+                    Some(precursor) => Some(precursor.code()),
+                    None => test_char(key, shift),
+                }
+            }
+            // either the keys make these, so they'd come twice, like Enter's '\r', or they're Ctrl
+            // combinations, like the paste hotkey's, that the Precursor has no way to type
+            HostKeyEvent::Char(c) if c.is_control() => None,
+            HostKeyEvent::Char(c) => Some(c),
+        }
+    }
+}
+
+/// Characters for testing rendering, typed by host keys that aren't mapped to Precursor keys
+fn test_char(key: Key, shift: bool) -> Option<char> {
+    match key {
+        Key::F5 if !shift => Some('😊'),
+        Key::F6 if !shift => Some('福'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_translation() {
+        let mut keys = KeyTranslator::default();
+        let keymap = HostKeymap::default();
+        let mut typed = |events: &[HostKeyEvent]| -> Vec<char> {
+            events.iter().filter_map(|&e| keys.translate(e, &keymap)).collect()
+        };
+        use HostKeyEvent::{Char, Key as K};
+        use KeyAction::*;
+        // arrows type on press and repeat, not release; letters come through as characters
+        assert_eq!(typed(&[K(Key::Up, Press), K(Key::Up, Repeat), K(Key::Up, Release)]), vec!['↑', '↑']);
+        assert_eq!(typed(&[K(Key::A, Press), Char('a'), K(Key::A, Release)]), vec!['a']);
+        // Enter and Backspace type once, although the host sends a character for them too
+        assert_eq!(typed(&[K(Key::Enter, Press), Char('\r'), K(Key::Backspace, Press), Char('\u{8}')]),
+            vec!['\r', '\u{8}']);
+        assert_eq!(typed(&[K(Key::F1, Press), K(Key::F5, Press), K(Key::Home, Press)]), vec!['\u{11}', '😊', '∴']);
+        assert_eq!(typed(&[K(Key::LeftCtrl, Press), K(Key::V, Press), Char('\u{16}')]), vec![]);
+
+        // held shift, from either side, leaves the arrows but takes the buttons off the F keys
+        assert_eq!(typed(&[K(Key::LeftShift, Press), K(Key::LeftShift, Repeat), K(Key::F1, Press),
+            K(Key::Left, Press), Char('A')]), vec!['←', 'A']);
+        assert_eq!(typed(&[K(Key::RightShift, Press), K(Key::LeftShift, Release), K(Key::Enter, Press)]), vec![]);
+        assert_eq!(typed(&[K(Key::RightShift, Release), K(Key::F2, Press)]), vec!['\u{12}']);
+        // keys with no Precursor equivalent type nothing
+        assert_eq!(typed(&[K(Key::PageUp, Press), K(Key::LeftCtrl, Press), K(Key::F9, Press)]), vec![]);
+    }
+
+    #[test]
+    fn test_key_repeat() {
+        let start = Instant::now();
+        let ms = |t: u64| start + Duration::from_millis(t);
+        let keymap = HostKeymap::default();
+        // the key events passed on, and when, given the host's events, over a run of updates
+        // every 10ms
+        let timeline = |repeater: &mut KeyRepeater, host: &[(u64, HostKeyEvent)], until: u64| {
+            let mut passed = Vec::new();
+            for t in (0..=until).step_by(10) {
+                for &(_, event) in host.iter().filter(|&&(at, _)| at == t) {
+                    passed.extend(repeater.event(event, ms(t), &keymap).map(|e| (t, e)));
+                }
+                passed.extend(repeater.due(ms(t)).map(|e| (t, e)));
+            }
+            passed
+        };
+        use HostKeyEvent::{Char, Key as K};
+        use KeyAction::*;
+
+        // Up held for 600ms: the host's own repeats go, and the device's come after 500ms, every
+        // 20ms
+        let host = [(0, K(Key::Up, Press)), (250, K(Key::Up, Repeat)), (280, K(Key::Up, Repeat)), (600, K(Key::Up, Release))];
+        let mut expected = vec![(0, K(Key::Up, Press))];
+        expected.extend([500, 520, 540, 560, 580].iter().map(|&t| (t, K(Key::Up, Repeat))));
+        expected.push((600, K(Key::Up, Release)));
+        assert_eq!(timeline(&mut KeyRepeater::default(), &host, 700), expected);
+
+        // a digit repeats its character; the host's repeats of it go
+        let host = [(0, K(Key::Key1, Press)), (0, Char('1')), (300, K(Key::Key1, Repeat)), (300, Char('1')),
+            (540, K(Key::Key1, Release))];
+        assert_eq!(timeline(&mut KeyRepeater::default(), &host, 600), vec![
+            (0, K(Key::Key1, Press)), (0, Char('1')), (500, Char('1')), (520, Char('1')), (540, K(Key::Key1, Release)),
+        ]);
+
+        // letters don't repeat on the device, and neither does Enter: just the presses, the
+        // releases and the one 'a' come through
+        let host = [(0, K(Key::A, Press)), (0, Char('a')), (300, K(Key::A, Repeat)), (300, Char('a')),
+            (600, K(Key::A, Release)), (600, K(Key::Enter, Press)), (1200, K(Key::Enter, Release))];
+        let passed = timeline(&mut KeyRepeater::default(), &host, 1300);
+        assert_eq!(passed.len(), 5);
+        assert_eq!(passed.iter().filter(|&&(_, e)| e == Char('a')).count(), 1);
+
+        // pressing another key puts the repeat off; a different delay and rate
+        let mut repeater = KeyRepeater::new(KeyRepeatConfig { delay: Duration::from_millis(300), rate: Duration::from_millis(50) });
+        let host = [(0, K(Key::Space, Press)), (0, Char(' ')), (200, K(Key::LeftShift, Press))];
+        let spaces: Vec<u64> = timeline(&mut repeater, &host, 600).iter().filter(|&&(_, e)| e == Char(' ')).map(|&(t, _)| t).collect();
+        assert_eq!(spaces, vec![0, 500, 550, 600]);
+        // losing focus stops it
+        repeater.stop();
+        assert_eq!(repeater.due(ms(1000)), None);
+    }
+
+    #[test]
+    fn test_keymap() {
+        let default = HostKeymap::default();
+        assert_eq!(default.get(Key::Home), Some(PrecursorKey::Center));
+        assert_eq!(default.get(Key::Delete), Some(PrecursorKey::Backspace));
+        assert_eq!(default.get(Key::F5), None);
+
+        let keymap = default.with_overrides(" F5=f1, insert=Menu,Home=none,F1=enter").unwrap();
+        assert_eq!(keymap.get(Key::F5), Some(PrecursorKey::F1));
+        assert_eq!(keymap.get(Key::Insert), Some(PrecursorKey::Center));
+        assert_eq!(keymap.get(Key::Home), None);
+        assert_eq!(keymap.get(Key::F1), Some(PrecursorKey::Enter));
+        // keys that don't exist, the hotkeys, and pairs missing a side don't go
+        assert_eq!(default.with_overrides("F5=F9"), None);
+        assert_eq!(default.with_overrides("F11=F1"), None);
+        assert_eq!(default.with_overrides("F5"), None);
+        assert_eq!(default.with_overrides(""), Some(default.clone()));
+
+        // F5 now types F1's code rather than its test character, and Home nothing at all
+        let mut keys = KeyTranslator::default();
+        let press = |key| HostKeyEvent::Key(key, KeyAction::Press);
+        assert_eq!(keys.translate(press(Key::F5), &keymap), Some('\u{11}'));
+        assert_eq!(keys.translate(press(Key::Home), &keymap), None);
+        assert_eq!(keys.translate(press(Key::F6), &keymap), Some('福'));
+
+        let lines = keymap.describe();
+        assert_eq!(lines[0], "F1 > ENTER");
+        assert!(!lines.contains(&"HOME > CENTER".to_string()));
+        assert_eq!(lines.last().map(|s| s.as_str()), Some("INSERT > CENTER"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Input played into the hosted display from a script, for end-to-end tests of the UI. The keys
+//! and pointer events in it go through the same queues as the window's, timed as the script says,
+//! so the system can't tell them from someone at the keyboard. A script's built up in code, or
+//! read from text, a step to a line:
+//!
+//! ```text
+//! # whole lines starting with # are comments, and blank lines are skipped
+//! type hello\n        characters, with \n for Enter
+//! key Home tap        press, release or tap a key, by the name XOUS_KEYMAP has for it, or shift
+//! tap 168 300         the pointer at a pixel of the display: press, move, release or tap
+//! swipe 300 200 40 200 150    a drag from one pixel to another, over so many milliseconds
+//! wait 250            milliseconds
+//! frames 3            redraws of the display
+//! ```
+
+use crate::api::{Point, PointerAction};
+use super::clipboard::paste_chars;
+use super::hostkeys::{host_key, HostKeyEvent, KeyAction};
+use super::PointerEvent;
+use minifb::Key;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Something a script plays in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScriptInput {
+    Key(HostKeyEvent),
+    Pointer(PointerEvent),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    Input(ScriptInput),
+    /// this long after the wait before it ended, or the script started, so the script's timing
+    /// doesn't drift with how often it's played
+    Wait(Duration),
+    /// this many more redraws
+    Frames(u64),
+}
+
+/// What the wait going is for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Until {
+    Time(Instant),
+    Frame(u64),
+}
+
+/// Timed input for the hosted display to play in, with `XousDisplay::play_script()`
+#[derive(Default)]
+pub struct InputScript {
+    steps: VecDeque<Step>,
+    /// the wait going, if any
+    waiting: Option<Until>,
+    /// when the last wait ended, once the script's started
+    clock: Option<Instant>,
+    /// called once the script's played through
+    on_done: Option<Box<dyn FnOnce()>>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript::default()
+    }
+    /// Reads a script from `text`, a step to a line. Returns the first line that can't be made
+    /// out, if any, as the error.
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut script = InputScript::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (command, args) = match line.find(char::is_whitespace) {
+                Some(at) => (&line[..at], line[at..].trim_start()),
+                None => (line, ""),
+            };
+            script = script.with_line(command, args).ok_or_else(|| format!("line {}: can't make out `{}`", number + 1, line))?;
+        }
+        Ok(script)
+    }
+    /// Reads the script in the file at `path`
+    pub fn load(path: &Path) -> std::io::Result<InputScript> {
+        InputScript::parse(&std::fs::read_to_string(path)?).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+    fn with_line(self, command: &str, args: &str) -> Option<InputScript> {
+        if command == "type" {
+            return Some(self.type_text(&args.replace("\\n", "\n")));
+        }
+        let words: Vec<&str> = args.split_whitespace().collect();
+        if command == "key" {
+            let key = host_key(words.first()?)?;
+            return match &words[1..] {
+                ["press"] => Some(self.key(key, KeyAction::Press)),
+                ["release"] => Some(self.key(key, KeyAction::Release)),
+                [] | ["tap"] => Some(self.tap_key(key)),
+                _ => None,
+            };
+        }
+        let numbers = |count: usize| -> Option<Vec<u64>> {
+            let numbers = words.iter().map(|word| word.parse().ok()).collect::<Option<Vec<u64>>>()?;
+            Some(numbers).filter(|numbers| numbers.len() == count)
+        };
+        let point = |x: u64, y: u64| Some(Point::new(i16::try_from(x).ok()?, i16::try_from(y).ok()?));
+        Some(match command {
+            "press" | "move" | "release" | "tap" => {
+                let n = numbers(2)?;
+                let at = point(n[0], n[1])?;
+                match command {
+                    "press" => self.press(at),
+                    "move" => self.move_to(at),
+                    "release" => self.release(at),
+                    _ => self.tap(at),
+                }
+            }
+            "swipe" => {
+                let n = numbers(5)?;
+                self.swipe(point(n[0], n[1])?, point(n[2], n[3])?, Duration::from_millis(n[4]))
+            }
+            "wait" => self.wait(Duration::from_millis(numbers(1)?[0])),
+            "frames" => self.frames(numbers(1)?[0]),
+            _ => return None,
+        })
+    }
+
+    fn input(mut self, input: ScriptInput) -> InputScript {
+        self.steps.push_back(Step::Input(input));
+        self
+    }
+    pub fn key(self, key: Key, action: KeyAction) -> InputScript {
+        self.input(ScriptInput::Key(HostKeyEvent::Key(key, action)))
+    }
+    /// Presses `key` and lets it go
+    pub fn tap_key(self, key: Key) -> InputScript {
+        self.key(key, KeyAction::Press).key(key, KeyAction::Release)
+    }
+    /// Types `text` all at once, as a paste would, line breaks as Enter. Characters the keyboard
+    /// has no way to type are left out.
+    pub fn type_text(self, text: &str) -> InputScript {
+        paste_chars(text).0.into_iter().fold(self, |script, c| match c {
+            '\r' => script.tap_key(Key::Enter),
+            c => script.input(ScriptInput::Key(HostKeyEvent::Char(c))),
+        })
+    }
+    fn pointer(self, action: PointerAction, at: Point) -> InputScript {
+        self.input(ScriptInput::Pointer(PointerEvent { action, at }))
+    }
+    pub fn press(self, at: Point) -> InputScript {
+        self.pointer(PointerAction::Press, at)
+    }
+    pub fn move_to(self, at: Point) -> InputScript {
+        self.pointer(PointerAction::Move, at)
+    }
+    pub fn release(self, at: Point) -> InputScript {
+        self.pointer(PointerAction::Release, at)
+    }
+    pub fn tap(self, at: Point) -> InputScript {
+        self.press(at).release(at)
+    }
+    /// Drags from `from` to `to`, taking `duration`: a swipe, if it's long and quick enough
+    pub fn swipe(self, from: Point, to: Point, duration: Duration) -> InputScript {
+        self.press(from).wait(duration).move_to(to).release(to)
+    }
+    /// Waits `duration` from the end of the wait before, or the start of the script
+    pub fn wait(mut self, duration: Duration) -> InputScript {
+        self.steps.push_back(Step::Wait(duration));
+        self
+    }
+    /// Waits for the display to be redrawn `frames` times
+    pub fn frames(mut self, frames: u64) -> InputScript {
+        self.steps.push_back(Step::Frames(frames));
+        self
+    }
+    /// Has `callback` called once the script's played through, waits and all
+    pub fn on_done(mut self, callback: impl FnOnce() + 'static) -> InputScript {
+        self.on_done = Some(Box::new(callback));
+        self
+    }
+
+    /// The input due by `now`, with the display on its `frame`th redraw. The script starts the
+    /// first time it's asked.
+    pub fn due(&mut self, now: Instant, frame: u64) -> Vec<ScriptInput> {
+        let mut inputs = Vec::new();
+        let mut clock = *self.clock.get_or_insert(now);
+        loop {
+            match self.waiting {
+                Some(Until::Time(at)) if now < at => break,
+                Some(Until::Frame(until)) if frame < until => break,
+                Some(Until::Time(at)) => clock = at,
+                Some(Until::Frame(_)) => clock = now,
+                None => {}
+            }
+            self.waiting = None;
+            match self.steps.pop_front() {
+                Some(Step::Input(input)) => inputs.push(input),
+                Some(Step::Wait(duration)) => self.waiting = Some(Until::Time(clock + duration)),
+                Some(Step::Frames(frames)) => self.waiting = Some(Until::Frame(frame + frames)),
+                None => break,
+            }
+        }
+        self.clock = Some(clock);
+        inputs
+    }
+    pub fn is_done(&self) -> bool {
+        self.steps.is_empty() && self.waiting.is_none()
+    }
+    /// Calls the callback `on_done()` gave, if any
+    pub fn finish(self) {
+        if let Some(callback) = self.on_done {
+            callback();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_input_script() {
+        let done = Rc::new(Cell::new(false));
+        let mut script = InputScript::parse(
+            "# a comment, and a blank line\n\ntype hi\\n\n  key Home press\nkey shift\nwait 100\ntap 10 20\nframes 2\nswipe 0 5 100 5 50\n",
+        )
+        .unwrap()
+        .on_done({
+            let done = done.clone();
+            move || done.set(true)
+        });
+        let key = |key, action| ScriptInput::Key(HostKeyEvent::Key(key, action));
+        let pointer = |action, x, y| ScriptInput::Pointer(PointerEvent { action, at: Point::new(x, y) });
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+
+        assert_eq!(script.due(start, 0), vec![
+            ScriptInput::Key(HostKeyEvent::Char('h')),
+            ScriptInput::Key(HostKeyEvent::Char('i')),
+            key(Key::Enter, KeyAction::Press),
+            key(Key::Enter, KeyAction::Release),
+            key(Key::Home, KeyAction::Press),
+            key(Key::LeftShift, KeyAction::Press),
+            key(Key::LeftShift, KeyAction::Release),
+        ]);
+        assert!(script.due(ms(99), 0).is_empty());
+        // late: the tap comes, and the frames are counted from the redraw it came on
+        assert_eq!(script.due(ms(150), 5), vec![pointer(PointerAction::Press, 10, 20), pointer(PointerAction::Release, 10, 20)]);
+        assert!(script.due(ms(200), 6).is_empty());
+        // the swipe's timed from the redraw the frames ended on
+        assert_eq!(script.due(ms(200), 7), vec![pointer(PointerAction::Press, 0, 5)]);
+        assert!(script.due(ms(249), 8).is_empty());
+        assert!(!script.is_done());
+        assert_eq!(script.due(ms(250), 8), vec![pointer(PointerAction::Move, 100, 5), pointer(PointerAction::Release, 100, 5)]);
+        assert!(script.is_done() && !done.get());
+        script.finish();
+        assert!(done.get());
+
+        // waits are timed from the one before, not from when the input after it came
+        let mut script = InputScript::new().wait(Duration::from_millis(10)).tap_key(Key::F1).wait(Duration::from_millis(10)).tap_key(Key::F2);
+        assert!(script.due(start, 0).is_empty());
+        assert_eq!(script.due(ms(15), 0).len(), 2);
+        assert_eq!(script.due(ms(20), 0).len(), 2);
+        assert!(script.is_done());
+
+        assert_eq!(InputScript::parse("wait 5\njump 1 2").err(), Some("line 2: can't make out `jump 1 2`".to_string()));
+        for line in ["key Q", "key Home hold", "tap 1", "tap 1 40000", "wait soon", "frames"].iter() {
+            assert!(InputScript::parse(line).is_err(), "{}", line);
+        }
+    }
+}
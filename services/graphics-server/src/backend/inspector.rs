@@ -0,0 +1,75 @@
+//! The pixel inspector: reads out the frame buffer under the mouse in the hosted emulator
+//! window, for tracking down off-by-one rendering without counting pixels in screenshots
+
+use crate::api::Point;
+use std::fmt;
+
+/// What's in the frame buffer at a pixel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelReadout {
+    pub at: Point,
+    /// the pixel's bit, set for a light pixel
+    pub bit: bool,
+    /// the word of the frame buffer that holds the pixel, counted from its start
+    pub word_offset: usize,
+    /// which bit of that word the pixel is
+    pub bit_index: usize,
+}
+impl PixelReadout {
+    /// Reads pixel `at` out of `fb`, whose lines are `words_per_line` words, where it's bit
+    /// `bit_index` of its word
+    pub fn read(fb: &[u32], words_per_line: usize, at: Point, bit_index: usize) -> PixelReadout {
+        let word_offset = at.y as usize * words_per_line + at.x as usize / 32;
+        PixelReadout { at, bit: fb[word_offset] & (1 << bit_index) != 0, word_offset, bit_index }
+    }
+}
+impl fmt::Display for PixelReadout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x={}, y={}, bit={}, word offset={}", self.at.x, self.at.y, self.bit as u8, self.word_offset)
+    }
+}
+
+/// Follows the mouse while the inspector's on
+#[derive(Debug, Default)]
+pub struct Inspector {
+    /// the pixel under the mouse, if it's over the display
+    pub hovered: Option<PixelReadout>,
+    /// the button was down as of the last sample, so that a click copies once
+    down: bool,
+}
+impl Inspector {
+    /// Takes in the pixel under the mouse, if any, with its button `down` or not, and returns
+    /// the pixel clicked on, if that's what this is
+    pub fn sample(&mut self, hovered: Option<PixelReadout>, down: bool) -> Option<PixelReadout> {
+        let clicked = if down && !self.down { hovered } else { None };
+        self.hovered = hovered;
+        self.down = down;
+        clicked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspector() {
+        let mut fb = vec![0u32; 11 * 4];
+        // the pixel at (40, 2), bit 8 of the second word of the third line
+        fb[2 * 11 + 1] = 1 << 8;
+        let lit = PixelReadout::read(&fb, 11, Point::new(40, 2), 8);
+        assert_eq!((lit.bit, lit.word_offset), (true, 23));
+        assert_eq!(lit.to_string(), "x=40, y=2, bit=1, word offset=23");
+        assert!(!PixelReadout::read(&fb, 11, Point::new(41, 2), 9).bit);
+
+        // a click copies once, where it went down, and only on the display
+        let mut inspector = Inspector::default();
+        assert_eq!(inspector.sample(Some(lit), false), None);
+        assert_eq!(inspector.hovered, Some(lit));
+        assert_eq!(inspector.sample(Some(lit), true), Some(lit));
+        assert_eq!(inspector.sample(Some(lit), true), None);
+        assert_eq!(inspector.sample(None, false), None);
+        assert_eq!(inspector.sample(None, true), None);
+        assert_eq!(inspector.hovered, None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Simulation of the memory LCD's update artifacts, for the hosted display: changed pixels
+//! leave a fading ghost of what was there before, and an update sweeps down the panel a line
+//! at a time rather than appearing all at once.
+
+/// How strong the simulated artifacts are
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LcdArtifactConfig {
+    /// share of a pixel's old colour that shows through the frame it changes in, 0.0 to 1.0
+    pub ghost_fraction: f32,
+    /// frames a ghost takes to fade out; 0 leaves no ghosts
+    pub ghost_frames: u8,
+    /// steps an update sweeps down the display in, spread over a frame time; 1 updates every
+    /// line at once
+    pub sweep_steps: u16,
+}
+impl Default for LcdArtifactConfig {
+    fn default() -> LcdArtifactConfig {
+        LcdArtifactConfig { ghost_fraction: 0.25, ghost_frames: 3, sweep_steps: 8 }
+    }
+}
+
+/// The frames shown on the simulated panel, and the ghosts they left
+pub struct LcdArtifacts {
+    config: LcdArtifactConfig,
+    width: usize,
+    /// the latest frame, as drawn
+    frame: Vec<u32>,
+    /// per pixel, the colour it changed from and the frames its ghost has left
+    ghosts: Vec<(u32, u8)>,
+    /// the panel as it looks, ghosts included; lines the sweep hasn't reached yet still show
+    /// the frame before
+    shown: Vec<u32>,
+}
+
+impl LcdArtifacts {
+    /// Starts out showing `frame`, a `width` pixels wide buffer, with no ghosts
+    pub fn new(config: LcdArtifactConfig, frame: &[u32], width: usize) -> LcdArtifacts {
+        LcdArtifacts {
+            config,
+            width,
+            frame: frame.to_vec(),
+            ghosts: vec![(0, 0); frame.len()],
+            shown: frame.to_vec(),
+        }
+    }
+    /// steps the sweep of each frame takes, at least 1
+    pub fn sweep_steps(&self) -> u16 {
+        self.config.sweep_steps.max(1)
+    }
+    /// Takes in the next frame. Pixels that differ from the last one start to ghost, and the
+    /// ghosts of the others fade by a frame.
+    pub fn start_frame(&mut self, frame: &[u32]) {
+        for ((old, &new), ghost) in self.frame.iter_mut().zip(frame).zip(self.ghosts.iter_mut()) {
+            if *old != new {
+                *ghost = (*old, self.config.ghost_frames);
+                *old = new;
+            } else {
+                ghost.1 = ghost.1.saturating_sub(1);
+            }
+        }
+    }
+    /// Sweeps the frame down the display to the end of step `step`, counting from 1 to
+    /// `sweep_steps()`, and returns the panel as it then looks
+    pub fn sweep_to(&mut self, step: u16) -> &[u32] {
+        let lines = self.frame.len() / self.width;
+        let swept = lines * step.min(self.sweep_steps()) as usize / self.sweep_steps() as usize;
+        let config = self.config;
+        let ghost_alpha = |frames_left: u8| {
+            (config.ghost_fraction.max(0.0).min(1.0) * 256.0 * frames_left as f32
+                / config.ghost_frames.max(1) as f32) as u32
+        };
+        for (i, shown) in self.shown[..swept * self.width].iter_mut().enumerate() {
+            let (old, frames_left) = self.ghosts[i];
+            *shown = if frames_left > 0 {
+                blend(self.frame[i], old, ghost_alpha(frames_left))
+            } else {
+                self.frame[i]
+            };
+        }
+        &self.shown
+    }
+}
+
+/// Mixes `alpha`/256 of `under` into `over`, channel by channel
+fn blend(over: u32, under: u32, alpha: u32) -> u32 {
+    (0..3).fold(0, |colour, channel| {
+        let shift = channel * 8;
+        let (a, b) = ((over >> shift) & 0xFF, (under >> shift) & 0xFF);
+        colour | (((a * (256 - alpha) + b * alpha) / 256) << shift)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcd_artifacts() {
+        const BLACK: u32 = 0x000000;
+        const WHITE: u32 = 0xFFFFFF;
+        let config = LcdArtifactConfig { ghost_fraction: 0.5, ghost_frames: 2, sweep_steps: 2 };
+        // 2 pixels wide, 4 lines
+        let mut lcd = LcdArtifacts::new(config, &[WHITE; 8], 2);
+        let mut frame = [WHITE; 8];
+        frame[0] = BLACK;
+        frame[6] = BLACK;
+        lcd.start_frame(&frame);
+        // halfway down, the change at the top is in with its ghost, the one at the bottom isn't
+        let shown = lcd.sweep_to(1).to_vec();
+        assert_eq!(shown[0], 0x7F7F7F);
+        assert_eq!((shown[1], shown[6]), (WHITE, WHITE));
+        assert_eq!(lcd.sweep_to(2)[6], 0x7F7F7F);
+
+        // the ghosts fade over the next frames
+        lcd.start_frame(&frame);
+        assert_eq!(lcd.sweep_to(2)[0], 0x3F3F3F);
+        lcd.start_frame(&frame);
+        assert_eq!(lcd.sweep_to(2), &frame[..]);
+
+        // no ghosts, and no sweep: the frame comes up exactly as drawn
+        let config = LcdArtifactConfig { ghost_fraction: 0.5, ghost_frames: 0, sweep_steps: 0 };
+        let mut lcd = LcdArtifacts::new(config, &[WHITE; 8], 2);
+        lcd.start_frame(&frame);
+        assert_eq!(lcd.sweep_steps(), 1);
+        assert_eq!(lcd.sweep_to(1), &frame[..]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Simulation of the memory LCD controller's timing, for the hosted display: an update takes
+//! time to clock out to the panel a line at a time, and an update command sent while one's still
+//! going is ignored, as the hardware ignores it. Flow control around the busy flag gets the same
+//! workout here as on the device.
+
+use crate::backend::{FB_SIZE, FB_WIDTH_WORDS};
+use std::time::{Duration, Instant};
+
+/// the dirty bits of the last word of a line
+const DIRTY_BITS: u32 = 0xFFFF_0000;
+
+/// How long the simulated transfers take
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LcdTimingConfig {
+    /// time to clock a line out to the panel
+    pub line_time: Duration,
+}
+impl Default for LcdTimingConfig {
+    /// about what the device's panel takes, a full frame in just under a tenth of a second
+    fn default() -> LcdTimingConfig {
+        LcdTimingConfig { line_time: Duration::from_micros(180) }
+    }
+}
+
+/// The simulated controller: its memory, the transfer going, if any, and the panel
+pub struct LcdTiming {
+    config: LcdTimingConfig,
+    /// when the transfer going finishes
+    busy_until: Option<Instant>,
+    /// the controller's memory, as the last redraw loaded it
+    hwfb: Vec<u32>,
+    /// the frame the panel holds, dirty bits cleared
+    panel: Vec<u32>,
+    /// update commands sent while busy, and so ignored
+    ignored: u64,
+}
+
+impl LcdTiming {
+    /// Starts out idle, the controller's memory and the panel both holding `frame`
+    pub fn new(config: LcdTimingConfig, frame: &[u32; FB_SIZE]) -> LcdTiming {
+        let mut panel = frame.to_vec();
+        clear_dirty(&mut panel);
+        LcdTiming { config, busy_until: None, hwfb: frame.to_vec(), panel, ignored: 0 }
+    }
+    /// Whether a transfer is still going at `now`
+    pub fn busy(&self, now: Instant) -> bool {
+        self.busy_until.map_or(false, |until| now < until)
+    }
+    /// How long after `now` the transfer going has left to run
+    pub fn busy_for(&self, now: Instant) -> Duration {
+        self.busy_until.map_or(Duration::ZERO, |until| until.saturating_duration_since(now))
+    }
+    /// Copies `fb` into the controller's memory, as a redraw does ahead of an update
+    pub fn load(&mut self, fb: &[u32; FB_SIZE]) {
+        self.hwfb.copy_from_slice(fb);
+    }
+    /// Sends an update command at `now`: the lines of the controller's memory that are flagged
+    /// dirty, or all of them, go out to the panel. Returns `false`, and does nothing, if a
+    /// transfer's still going.
+    pub fn update(&mut self, all: bool, now: Instant) -> bool {
+        if self.busy(now) {
+            self.ignored += 1;
+            log::trace!("GFX|hosted: LCD update sent while busy; ignored");
+            return false;
+        }
+        let mut lines = 0;
+        for (from, to) in self.hwfb.chunks(FB_WIDTH_WORDS).zip(self.panel.chunks_mut(FB_WIDTH_WORDS)) {
+            if all || from[FB_WIDTH_WORDS - 1] & DIRTY_BITS != 0 {
+                to.copy_from_slice(from);
+                to[FB_WIDTH_WORDS - 1] &= !DIRTY_BITS;
+                lines += 1;
+            }
+        }
+        self.busy_until = Some(now + self.config.line_time * lines);
+        true
+    }
+    /// The frame the panel holds
+    pub fn panel(&self) -> &[u32] {
+        &self.panel
+    }
+    /// Update commands ignored for being sent while busy
+    pub fn ignored(&self) -> u64 {
+        self.ignored
+    }
+}
+
+fn clear_dirty(fb: &mut [u32]) {
+    for line in fb.chunks_mut(FB_WIDTH_WORDS) {
+        line[FB_WIDTH_WORDS - 1] &= !DIRTY_BITS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcd_timing() {
+        let start = Instant::now();
+        let line_time = Duration::from_micros(100);
+        let mut timing = LcdTiming::new(LcdTimingConfig { line_time }, &[0xFFFF_FFFF; FB_SIZE]);
+        assert!(!timing.busy(start));
+        assert_eq!(timing.panel()[FB_WIDTH_WORDS - 1], !DIRTY_BITS);
+
+        // two lines dirty: they take two lines' time, and nothing else goes out
+        let mut fb = [0; FB_SIZE];
+        fb[3 * FB_WIDTH_WORDS - 1] = 0x1_0000;
+        fb[6 * FB_WIDTH_WORDS - 1] = 0x1_0000;
+        timing.load(&fb);
+        assert!(timing.update(false, start));
+        assert_eq!(timing.panel()[2 * FB_WIDTH_WORDS..3 * FB_WIDTH_WORDS], [0; FB_WIDTH_WORDS]);
+        assert_eq!(timing.panel()[0], 0xFFFF_FFFF);
+        assert!(timing.busy(start + line_time));
+        assert_eq!(timing.busy_for(start + line_time), line_time);
+        assert!(!timing.busy(start + 2 * line_time));
+
+        // an update while that's going is ignored, like the hardware ignores it
+        assert!(!timing.update(true, start + line_time));
+        assert_eq!((timing.ignored(), timing.panel()[0]), (1, 0xFFFF_FFFF));
+        assert!(timing.update(true, start + 2 * line_time));
+        assert_eq!(timing.panel(), &[0; FB_SIZE][..]);
+        assert_eq!(timing.busy_for(start + 2 * line_time), line_time * FB_SIZE as u32 / FB_WIDTH_WORDS as u32);
+    }
+}
@@ -0,0 +1,147 @@
+//! The magnifier of the hosted display: the pixels around the mouse, blown up in a corner of
+//! the window with a grid between them, to check glyphs and lines pixel by pixel
+
+/// how many times over each pixel's blown up
+pub const MAGNIFICATION: usize = 8;
+/// pixels shown either side of the one under the mouse
+pub const REACH: usize = 7;
+/// pixels shown across and down
+const SPAN: usize = 2 * REACH + 1;
+/// width and height of the magnifier: the pixels, with the grid between and around them
+pub const MAGNIFIER_SIZE: usize = SPAN * (MAGNIFICATION + 1) + 1;
+
+/// colours of set and clear bits, whatever the palette
+const SET_COLOUR: u32 = 0xFFFFFF;
+const CLEAR_COLOUR: u32 = 0x000000;
+const GRID_COLOUR: u32 = 0x808080;
+/// colour of the pixel under the mouse's grid square, to find it by
+const CENTRE_COLOUR: u32 = 0xFF0000;
+
+/// Where the magnifier was last drawn, and what it covers
+#[derive(Debug)]
+struct Drawn {
+    /// index in the native buffer of each line of the magnifier, from its left edge
+    lines: Vec<usize>,
+    /// the pixels under it, a line at a time
+    under: Vec<u32>,
+}
+
+/// The magnifier, while it's on
+#[derive(Debug, Default)]
+pub struct Magnifier {
+    /// the pixel of the display under the mouse, if it's over the display
+    pub at: Option<(usize, usize)>,
+    drawn: Option<Drawn>,
+}
+impl Magnifier {
+    /// Draws the pixels around `at`, where the mouse is in a `width` by `height` native buffer,
+    /// into the bottom corner of it across from the mouse. `bit` gives whether the bit shown at a
+    /// place in the native buffer is set, or `None` past the edge of the display. Nothing's drawn
+    /// while the mouse is off the display.
+    pub fn draw(&mut self, native: &mut [u32], (width, height): (usize, usize), bit: impl Fn(isize, isize) -> Option<bool>) {
+        self.erase(native);
+        let (x, y) = match self.at {
+            Some(at) => at,
+            None => return,
+        };
+        let (size_x, size_y) = (MAGNIFIER_SIZE.min(width), MAGNIFIER_SIZE.min(height));
+        let left = if x < width / 2 { width - size_x } else { 0 };
+        let top = height - size_y;
+        let lines: Vec<usize> = (top..top + size_y).map(|line| line * width + left).collect();
+        let mut under = Vec::with_capacity(size_x * size_y);
+        for (dy, &start) in lines.iter().enumerate() {
+            under.extend_from_slice(&native[start..start + size_x]);
+            for (dx, dest) in native[start..start + size_x].iter_mut().enumerate() {
+                let on_grid = dx % (MAGNIFICATION + 1) == 0 || dy % (MAGNIFICATION + 1) == 0;
+                let (cell_x, cell_y) = (dx / (MAGNIFICATION + 1), dy / (MAGNIFICATION + 1));
+                *dest = if on_grid {
+                    // the centre's grid square picks out the pixel under the mouse
+                    let by_centre = |d: usize, cell: usize| cell == REACH || (d % (MAGNIFICATION + 1) == 0 && cell == REACH + 1);
+                    if by_centre(dx, cell_x) && by_centre(dy, cell_y) { CENTRE_COLOUR } else { GRID_COLOUR }
+                } else {
+                    let at = |pos: usize, cell: usize| pos as isize + cell as isize - REACH as isize;
+                    match bit(at(x, cell_x), at(y, cell_y)) {
+                        Some(true) => SET_COLOUR,
+                        Some(false) => CLEAR_COLOUR,
+                        None => GRID_COLOUR,
+                    }
+                };
+            }
+        }
+        self.drawn = Some(Drawn { lines, under });
+    }
+    /// Puts back what the magnifier was drawn over in `native`, returning `false` if it wasn't
+    /// there to take off
+    pub fn erase(&mut self, native: &mut [u32]) -> bool {
+        match self.drawn.take() {
+            Some(drawn) => {
+                restore(native, &drawn);
+                true
+            }
+            None => false,
+        }
+    }
+    /// `native` without the magnifier, if it's drawn there
+    pub fn without(&self, native: &[u32]) -> Vec<u32> {
+        let mut native = native.to_vec();
+        if let Some(drawn) = self.drawn.as_ref() {
+            restore(&mut native, drawn);
+        }
+        native
+    }
+}
+
+fn restore(native: &mut [u32], drawn: &Drawn) {
+    let width = drawn.under.len() / drawn.lines.len().max(1);
+    for (&start, under) in drawn.lines.iter().zip(drawn.under.chunks(width)) {
+        native[start..start + width].copy_from_slice(under);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magnifier() {
+        let (width, height) = (200usize, 300usize);
+        let background: Vec<u32> = (0..(width * height) as u32).collect();
+        let mut native = background.clone();
+        let mut magnifier = Magnifier::default();
+        // a bit set every third pixel across; off the display to the left
+        let bit = |x: isize, _y: isize| if x < 0 { None } else { Some(x % 3 == 0) };
+        magnifier.draw(&mut native, (width, height), bit);
+        assert_eq!(native, background);
+
+        // the mouse on the left: the bottom right corner
+        magnifier.at = Some((3, 150));
+        magnifier.draw(&mut native, (width, height), bit);
+        let left = width - MAGNIFIER_SIZE;
+        let top = height - MAGNIFIER_SIZE;
+        let pixel = |native: &[u32], x: usize, y: usize| native[(top + y) * width + left + x];
+        assert_eq!(native[top * width + left - 1], background[top * width + left - 1]);
+        assert_eq!(native[(top - 1) * width + left], background[(top - 1) * width + left]);
+        // the pixel under the mouse, blown up in the middle, red around it, is set; the one to
+        // its right isn't; the mouse is 3 in, so the leftmost 4 pixels are off the display
+        let centre = REACH * (MAGNIFICATION + 1);
+        assert_eq!(pixel(&native, centre + 1, centre + 1), SET_COLOUR);
+        assert_eq!(pixel(&native, centre + MAGNIFICATION, centre + MAGNIFICATION), SET_COLOUR);
+        assert_eq!(pixel(&native, centre + MAGNIFICATION + 2, centre + 1), CLEAR_COLOUR);
+        assert_eq!(pixel(&native, centre, centre + 1), CENTRE_COLOUR);
+        assert_eq!(pixel(&native, centre + MAGNIFICATION + 1, centre + MAGNIFICATION), CENTRE_COLOUR);
+        assert_eq!(pixel(&native, centre + 1, centre + MAGNIFICATION + 1), CENTRE_COLOUR);
+        assert_eq!(pixel(&native, 0, 1), GRID_COLOUR);
+        assert_eq!(pixel(&native, 3 * (MAGNIFICATION + 1) + 1, 1), GRID_COLOUR);
+        assert_eq!(pixel(&native, 4 * (MAGNIFICATION + 1) + 1, 1), SET_COLOUR);
+        // and it's left out of what's saved
+        assert_eq!(magnifier.without(&native), background);
+
+        // the mouse on the right: the bottom left corner, and the right one's put back
+        magnifier.at = Some((150, 150));
+        magnifier.draw(&mut native, (width, height), bit);
+        assert_eq!(native[(height - 1) * width + width - 1], background[(height - 1) * width + width - 1]);
+        assert_eq!(native[(height - 1) * width], GRID_COLOUR);
+        magnifier.erase(&mut native);
+        assert_eq!(native, background);
+    }
+}
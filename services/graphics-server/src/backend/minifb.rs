@@ -1,7 +1,32 @@
 #![cfg_attr(not(target_os = "none"), allow(dead_code))]
 
-use crate::api::Point;
-use minifb::{Key, Window, WindowOptions};
+use crate::api::{Point, Rectangle};
+use super::backbuffer::BackBuffer;
+use super::clipboard::{paste_chars, read_host_clipboard, write_host_clipboard, Paste};
+use super::damage::Damage;
+use super::framestats::{draw_overlay, FrameClock, FrameStats, OVERLAY_HEIGHT};
+use super::gestures::{GestureConfig, GestureEvent, GestureTracker};
+use super::grid::{draw_grid, GridSpec};
+use super::hostkeys::{HostKeyEvent, HostKeymap, KeyAction, KeyRepeatConfig, KeyRepeater, KeyTranslator};
+use super::inputscript::{InputScript, ScriptInput};
+use super::inspector::{Inspector, PixelReadout};
+use super::lcdsim::{LcdArtifactConfig, LcdArtifacts};
+use super::lcdtiming::{LcdTiming, LcdTimingConfig};
+use super::magnifier::Magnifier;
+use super::overlaytext::{draw_text_box, text_box_height, Corner};
+use super::pointer::PointerTracker;
+use super::recording::Recording;
+use super::snapshot::Snapshot;
+use super::{Capabilities, DisplayBackend, DisplayError, DisplayInfo, FrameComplete, PointerEvent};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use crate::api::{LINES, WIDTH};
 
 const HEIGHT: i16 = LINES;
@@ -12,291 +37,3167 @@ pub const FB_WIDTH_WORDS: usize = WIDTH_WORDS;
 pub const FB_WIDTH_PIXELS: usize = WIDTH as usize;
 pub const FB_LINES: usize = HEIGHT as usize;
 pub const FB_SIZE: usize = WIDTH_WORDS * HEIGHT as usize; // 44 bytes by 536 lines
+pub const DISPLAY_INFO: DisplayInfo =
+    DisplayInfo { width: WIDTH as usize, height: HEIGHT as usize, words_per_line: WIDTH_WORDS, fb_size_words: FB_SIZE };
 
-const MAX_FPS: u64 = 60;
+/// how long the system gets to shut down once the window's been closed, before the process
+/// exits anyway
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// least time between the keys of a paste, unless `set_paste_delay()` says otherwise
+const PASTE_DELAY: Duration = Duration::from_millis(20);
+/// the least time between updates of the live status in the window title
+const TITLE_INTERVAL: Duration = Duration::from_secs(1);
+/// how often the window's input is polled between redraws, about 120 times a second
+pub const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(8);
+/// frames a recording started with Shift+F11 holds at most
+const RECORDING_MAX_FRAMES: usize = 3000;
+/// directory captures go in unless `XOUS_CAPTURE_DIR` says otherwise
+const CAPTURE_DIR: &str = "xous-captures";
+/// frame rate limit unless `XOUS_FPS` says otherwise
+const MAX_FPS: u32 = 60;
+/// the frame rate limits F7 and F8 step through, slowest first; `None` is uncapped
+const FPS_STEPS: [Option<u32>; 5] = [Some(5), Some(15), Some(30), Some(60), None];
+/// refresh period of the window under `fps_limit`, as used for `limit_update_rate()`. Uncapped,
+/// refreshes are still taken to come at `MAX_FPS`, for pacing.
+fn frame_period(fps_limit: Option<u32>) -> Duration {
+    Duration::from_micros(1000 * 1000 / fps_limit.unwrap_or(MAX_FPS) as u64)
+}
 const DARK_COLOUR: u32 = 0xB5B5AD;
 const LIGHT_COLOUR: u32 = 0x1B1B19;
+/// fills the window around the display under `Scaling::Integer`; a grey that's neither theme colour
+const BORDER_COLOUR: u32 = 0x5A5A56;
+/// the backlight's brightest level; it has 32, 0 being off
+pub const MAX_BRIGHTNESS: u8 = 31;
+
+/// Ordered-dither masks used to simulate a dimmed background behind a modal dialog.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DitherPattern {
+    /// one in four pixels is blanked
+    Dim25,
+    /// checkerboard, every other pixel is blanked
+    Dim50,
+}
+impl DitherPattern {
+    /// returns `true` if the pixel at (`x`, `y`) is blanked by this pattern
+    fn masks(&self, x: usize, y: usize) -> bool {
+        match self {
+            DitherPattern::Dim25 => (x % 2 == 0) && (y % 2 == 0),
+            DitherPattern::Dim50 => (x + y) % 2 == 0,
+        }
+    }
+}
+
+/// Which end of each framebuffer word holds the leftmost pixel. This depends on how the panel
+/// controller is wired.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitOrder {
+    /// bit 0 is the leftmost pixel
+    Lsb,
+    /// bit 31 is the leftmost pixel
+    Msb,
+}
+impl Default for BitOrder {
+    fn default() -> BitOrder {
+        BitOrder::Lsb
+    }
+}
+impl BitOrder {
+    /// returns the bit of a framebuffer word that holds pixel `x` of the word
+    fn bit(&self, x: usize) -> usize {
+        match self {
+            BitOrder::Lsb => x,
+            BitOrder::Msb => 31 - x,
+        }
+    }
+}
+
+/// How `redraw()` chooses between rendering the whole display and only the lines marked dirty.
+/// The memory LCD ghosts if it only ever gets partial updates, so the real panel has to be
+/// refreshed in full every so often.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RefreshPolicy {
+    /// every redraw renders the whole display
+    Full,
+    /// only the lines marked dirty are rendered
+    Partial,
+    /// only the lines marked dirty are rendered, except that every `n`th redraw renders the
+    /// whole display
+    FullEvery(u32),
+}
+impl Default for RefreshPolicy {
+    fn default() -> RefreshPolicy {
+        RefreshPolicy::Full
+    }
+}
+
+/// The colours the display is rendered in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// colour of dark pixels, the text on most screens
+    pub fg: u32,
+    /// colour of light pixels, the background on most screens
+    pub bg: u32,
+    /// swaps the two, as inverted video does
+    pub inverted: bool,
+}
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette { fg: LIGHT_COLOUR, bg: DARK_COLOUR, inverted: false }
+    }
+}
+impl Palette {
+    /// colour a light pixel, a set bit of the framebuffer, is rendered in
+    fn light(&self) -> u32 {
+        if self.inverted { self.fg } else { self.bg }
+    }
+    /// colour a dark pixel is rendered in
+    fn dark(&self) -> u32 {
+        if self.inverted { self.bg } else { self.fg }
+    }
+    /// the colour between dark and light, `light` quarters of the way to light
+    fn grey(&self, light: u32) -> u32 {
+        let (dark, light_colour) = (self.dark().to_be_bytes(), self.light().to_be_bytes());
+        (1..4).fold(0, |grey, i| {
+            let (from, to) = (dark[i] as i32, light_colour[i] as i32);
+            (grey << 8) | (from + (to - from) * light as i32 / 4) as u32
+        })
+    }
+    /// these colours as they look with the backlight at `level`, scaled toward black in
+    /// proportion
+    fn dimmed(&self, level: u8) -> Palette {
+        let dim = |colour: u32| {
+            colour.to_be_bytes()[1..]
+                .iter()
+                .fold(0, |dimmed, &channel| (dimmed << 8) | (channel as u32 * level as u32 / MAX_BRIGHTNESS as u32))
+        };
+        Palette { fg: dim(self.fg), bg: dim(self.bg), ..*self }
+    }
+}
+
+/// How the display is scaled to the window
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scaling {
+    /// stretched as far as the window allows, keeping the aspect ratio; pixels can come out
+    /// unevenly sized
+    AspectRatioStretch,
+    /// scaled by the largest whole number that fits, so every pixel is the same size, and
+    /// centred with a border around it
+    Integer,
+}
+impl Default for Scaling {
+    fn default() -> Scaling {
+        Scaling::AspectRatioStretch
+    }
+}
+
+/// Whether the hosted display opens a window, as `XOUS_DISPLAY` says
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisplayMode {
+    /// always in a window; not being able to open one is fatal
+    Window,
+    /// never in a window, as `XousDisplay::headless()`
+    Headless,
+    /// in a window if one can be opened, headless if not
+    Auto,
+}
+impl Default for DisplayMode {
+    fn default() -> DisplayMode {
+        DisplayMode::Auto
+    }
+}
+impl DisplayMode {
+    /// The mode as `XOUS_DISPLAY` gives it
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Window => "window",
+            DisplayMode::Headless => "headless",
+            DisplayMode::Auto => "auto",
+        }
+    }
+}
+
+/// How far the display is turned in the window, clockwise. Only what's shown turns: the
+/// framebuffer is laid out the same whichever way the display's turned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::Rotate0
+    }
+}
+impl Rotation {
+    /// how far it turns, clockwise
+    pub fn degrees(&self) -> u16 {
+        match self {
+            Rotation::Rotate0 => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+    /// width and height of the display, turned
+    pub fn shown_size(&self) -> (usize, usize) {
+        match self {
+            Rotation::Rotate0 | Rotation::Rotate180 => (WIDTH as usize, HEIGHT as usize),
+            Rotation::Rotate90 | Rotation::Rotate270 => (HEIGHT as usize, WIDTH as usize),
+        }
+    }
+    /// where pixel (`x`, `y`) of the display ends up once it's turned
+    fn turn(&self, x: usize, y: usize) -> (usize, usize) {
+        let (width, height) = (WIDTH as usize, HEIGHT as usize);
+        match self {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (height - 1 - y, x),
+            Rotation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Rotation::Rotate270 => (y, width - 1 - x),
+        }
+    }
+    /// the pixel of the display that ends up at (`x`, `y`) once it's turned
+    fn unturn(&self, x: usize, y: usize) -> (usize, usize) {
+        let (width, height) = (WIDTH as usize, HEIGHT as usize);
+        match self {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (y, height - 1 - x),
+            Rotation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Rotation::Rotate270 => (width - 1 - y, x),
+        }
+    }
+}
+
+/// Where the simulated panel is in being powered down and back up
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PanelPower {
+    /// on, and taking frames
+    On,
+    /// off, as it is over a suspend: the window's blanked, and nothing drawn gets to it
+    Off,
+    /// on again, but not set up again with `reinit()` yet, as the controller isn't after a resume
+    Uninitialized,
+}
+
+/// The redraws done under a `RefreshPolicy`, to tell which of them are full
+#[derive(Debug, Default, Copy, Clone)]
+struct RefreshSchedule {
+    policy: RefreshPolicy,
+    /// partial redraws since the last full one
+    partials: u32,
+}
+impl RefreshSchedule {
+    /// Counts a redraw, and returns `true` if it has to render the whole display
+    fn next_is_full(&mut self) -> bool {
+        let full = match self.policy {
+            RefreshPolicy::Full => true,
+            RefreshPolicy::Partial => false,
+            RefreshPolicy::FullEvery(n) => self.partials + 1 >= n,
+        };
+        self.partials = if full { 0 } else { self.partials + 1 };
+        full
+    }
+}
+
+/// The bits of the last word of each line that flag the line as dirty; they're past the right
+/// edge of the screen.
+const DIRTY_BITS: u32 = 0xFFFF_0000;
+/// The line the devboot defile is drawn over
+const DEVBOOT_LINE: usize = 7;
+
+/// Marks every line of `emulated` dirty, so the next render covers the whole display
+fn mark_all_dirty(emulated: &mut [u32]) {
+    for line in emulated.chunks_mut(WIDTH_WORDS) {
+        line[WIDTH_WORDS - 1] |= 0x1_0000;
+    }
+}
+
+/// Marks the lines of `emulated` under an overlay over the top `lines` lines of the display,
+/// turned by `rotation`, dirty. Turned a quarter, every line runs under it.
+fn mark_overlay_dirty(emulated: &mut [u32], lines: usize, rotation: Rotation) {
+    let under = match rotation {
+        Rotation::Rotate0 => 0..lines,
+        Rotation::Rotate180 => (HEIGHT as usize).saturating_sub(lines)..HEIGHT as usize,
+        Rotation::Rotate90 | Rotation::Rotate270 => 0..HEIGHT as usize,
+    };
+    for line in emulated.chunks_mut(WIDTH_WORDS).skip(under.start).take(under.len()) {
+        line[WIDTH_WORDS - 1] |= 0x1_0000;
+    }
+}
+
+/// Clears the dirty bits of every line of `emulated`, once it's been rendered
+fn clear_dirty(emulated: &mut [u32]) {
+    for line in emulated.chunks_mut(WIDTH_WORDS) {
+        line[WIDTH_WORDS - 1] &= !DIRTY_BITS;
+    }
+}
 
 pub struct XousDisplay {
     native_buffer: Vec<u32>, //[u32; WIDTH * HEIGHT],
+    /// frames blitted, from this thread or any other, on their way to the emulated buffer, and
+    /// the generation of the last one taken from it
+    back_buffer: Arc<BackBuffer>,
+    back_generation: u64,
     emulated_buffer: [u32; FB_SIZE],
     srfb: [u32; FB_SIZE],
-    window: Window,
+    /// `None` when headless
+    window: Option<Window>,
     devboot: bool,
+    dim_overlay: Option<DitherPattern>,
+    bit_order: BitOrder,
+    /// the native pixels for each byte of the framebuffer, in the palette last shown
+    pixel_lut: PixelLut,
+    /// the area rendered to, if not the whole screen
+    viewport: Option<Rectangle>,
+    /// how far the display's turned in the window; the native buffer holds it turned
+    rotation: Rotation,
+    /// focus state of the window as of the last `update()`, shared with the keyboard handler
+    focused: Rc<Cell<bool>>,
+    /// keys typed into the window, as the host sent them, shared with the keyboard handler
+    host_keys: Rc<RefCell<Vec<HostKeyEvent>>>,
+    /// held keys repeat as on the device, rather than as the host repeats them
+    key_repeat: KeyRepeater,
+    /// keys typed since the last `drain_key_events()` or `drain_keys()`
+    key_events: Vec<HostKeyEvent>,
+    /// turns them into characters for `drain_keys()`
+    key_translator: KeyTranslator,
+    /// which host keys stand for the Precursor's keys that have no character of their own
+    keymap: HostKeymap,
+    /// the keymap is shown over the top left corner of the display
+    keymap_overlay: bool,
+    /// Ctrl+V pastes the host's clipboard, unless `XOUS_PASTE=0` is set
+    clipboard_paste: bool,
+    paste_delay: Duration,
+    /// the paste being typed in, if any
+    paste: Option<Paste>,
+    pointer: PointerTracker,
+    /// clicks and drags on the display since the last `drain_pointer_events()`
+    pointer_events: Vec<PointerEvent>,
+    gestures: GestureTracker,
+    gesture_config: GestureConfig,
+    /// drags made out as gestures since the last `drain_gestures()`
+    gesture_events: Vec<GestureEvent>,
+    /// the input script playing, if any
+    script: Option<InputScript>,
+    /// the pixel inspector, while it's on; the mouse reads out pixels rather than touching them
+    inspector: Option<Inspector>,
+    /// the magnifier, while it's on, and whether screenshots keep it in
+    magnifier: Option<Magnifier>,
+    magnifier_in_screenshots: bool,
+    /// the grid drawn over the display, if any, and what Ctrl+F11 turns it on with
+    grid: Option<GridSpec>,
+    grid_spec: GridSpec,
+    /// the refresh most recently waited for with `wait_for_vsync()`
+    last_vsync: Instant,
+    refresh: RefreshSchedule,
+    palette: Palette,
+    /// backlight level, out of `MAX_BRIGHTNESS`, that the palette is dimmed to
+    brightness: u8,
+    /// the display rendered in 1bpp, as it is, while the native buffer shows it smoothed into
+    /// greys; empty when the greyscale preview is off
+    sharp_buffer: Vec<u32>,
+    scaling: Scaling,
+    /// the memory LCD's artifacts, when they're simulated
+    lcd_artifacts: Option<LcdArtifacts>,
+    /// what F10 turns the simulation on with
+    lcd_config: LcdArtifactConfig,
+    /// the memory LCD controller's transfer timing, when it's simulated
+    lcd_timing: Option<LcdTiming>,
+    /// whether the panel's powered, and set up to take frames
+    power: PanelPower,
+    /// the areas `mark_dirty()` has been given since the last redraw, as the lines they send
+    damage: Damage,
+    /// the native buffer has changed since it was last put up in the window
+    native_changed: bool,
+    skipped_frames: u64,
+    fps_limit: Option<u32>,
+    frame_clock: FrameClock,
+    /// frame statistics are drawn over the top right corner of the display
+    stats_overlay: bool,
+    recording: Option<Recording>,
+    /// closes the window, as if it had been closed, unless `XOUS_QUIT_KEY` says otherwise
+    quit_key: Option<Key>,
+    /// the snapshot `XOUS_SNAPSHOT` had the main display start out showing, if any
+    startup_snapshot: Option<PathBuf>,
+    /// where the hotkeys' screenshots, recordings and snapshots go
+    capture_dir: PathBuf,
+    /// which of the emulator's display surfaces this is; 0 is the main display, whose window
+    /// closing shuts the system down
+    surface: usize,
+    /// tells this emulator's window from others': `XOUS_INSTANCE`, or the process ID, and the
+    /// surface if it isn't the main display
+    instance: String,
+    /// what `set_title_note()` adds to the title
+    title_note: String,
+    /// the window's title as last set, and when its live status was last brought up to date,
+    /// with the frame count and rate as of then
+    title: String,
+    title_at: Instant,
+    title_frames: u64,
+    title_fps: f32,
+    /// told of each frame put up
+    frame_callback: Option<Box<dyn FnMut(FrameComplete)>>,
+    /// frames put up in the window with something new in them, and when the latest was
+    presented_frames: u64,
+    last_presented: Option<Instant>,
+    started: Instant,
+    /// size of the window as of the last `update()`, to spot resizes
+    window_size: (usize, usize),
+    /// the window's contents under `Scaling::Integer`, at `window_size`
+    scaled_buffer: Vec<u32>,
 }
 
-struct XousKeyboardHandler {
-    kbd: keyboard::Keyboard,
-    left_shift: bool,
-    right_shift: bool,
+/// Queues the keys typed into the window, for `drain_key_events()`
+struct KeyEventQueue {
+    events: Rc<RefCell<Vec<HostKeyEvent>>>,
+    /// keys held down, to tell a repeat from a press
+    down: HashSet<Key>,
+    /// keys are only queued while the window has focus
+    focused: Rc<Cell<bool>>,
 }
 
 impl XousDisplay {
+    /// Opens the emulator's main display, in a window or headless as `XOUS_DISPLAY` says:
+    /// `window`, `headless`, or `auto`, the default, for a window if one can be opened. The
+    /// older `XOUS_HEADLESS=1` still means headless.
     pub fn new() -> XousDisplay {
-        let mut window = Window::new(
-            "Precursor",
-            WIDTH as usize,
-            HEIGHT as usize,
-            WindowOptions {
-                scale_mode: minifb::ScaleMode::AspectRatioStretch,
-                resize: true,
-                ..WindowOptions::default()
+        XousDisplay::new_surface(0)
+    }
+    /// Opens another display surface, `surface`, alongside the main display, surface 0, in a
+    /// window of its own, half the size to start with, unless `XOUS_DISPLAY` says otherwise.
+    /// Surfaces are laid out as the main display is, and are driven from the same thread as it.
+    /// Closing a surface's window leaves it running headless; only closing the main display's
+    /// shuts the system down.
+    pub fn new_surface(surface: usize) -> XousDisplay {
+        XousDisplay::open_surface(surface, display_mode())
+    }
+    /// Opens display surface `surface` as `mode` says. Panics if `mode` is `Window` and no
+    /// window can be opened; `Auto` falls back to headless, with a warning.
+    pub fn open_surface(surface: usize, mode: DisplayMode) -> XousDisplay {
+        let display = XousDisplay::headless_surface(surface);
+        let display = match mode {
+            DisplayMode::Headless => display,
+            DisplayMode::Window | DisplayMode::Auto => match display.open_first_window() {
+                Ok(display) => display,
+                Err((display, e)) if mode == DisplayMode::Auto => {
+                    log::warn!("GFX|hosted: {}; running headless", e);
+                    display
+                }
+                Err((_, e)) => panic!("GFX|hosted: XOUS_DISPLAY=window, but {}", e),
             },
-        )
-        .unwrap_or_else(|e| {
-            panic!("{}", e);
-        });
+        };
+        if surface == 0 {
+            let shown = if display.window.is_some() { "in a window" } else { "headless" };
+            log::info!("GFX|hosted: display {} (XOUS_DISPLAY={}; window, headless or auto)", shown, mode.name());
+        }
+        display
+    }
+    /// Puts the display up in a new window, or hands it back with why it couldn't be
+    fn open_first_window(self) -> Result<XousDisplay, (XousDisplay, String)> {
+        let (width, height) = self.rotation.shown_size();
+        let mut window = match open_window(self.focused.clone(), self.host_keys.clone(), self.fps_limit, self.window_start_size()) {
+            Ok(window) => window,
+            Err(e) => return Err((self, format!("couldn't open a window ({:?})", e))),
+        };
+        if let Err(e) = window.update_with_buffer(&self.native_buffer, width, height) {
+            return Err((self, format!("couldn't show the window ({:?})", e)));
+        }
+        let window_size = window.get_size();
+        let mut display = XousDisplay { window: Some(window), window_size, ..self };
+        display.retitle();
+        Ok(display)
+    }
+    /// A display with no window, that renders into memory only, for tests and CI machines with
+    /// no windowing system. Frames can still be looked at with `copy_framebuffer()` and
+    /// `screenshot()`; there's no keyboard input, and no window for `update()` to find closed.
+    pub fn headless() -> XousDisplay {
+        XousDisplay::headless_surface(0)
+    }
+    /// A display surface, `surface`, with no window; see `new_surface()` and `headless()`
+    pub fn headless_surface(surface: usize) -> XousDisplay {
+        let instance = std::env::var("XOUS_INSTANCE").unwrap_or_else(|_| format!("pid {}", std::process::id()));
+        let fps_limit = match std::env::var("XOUS_FPS") {
+            Ok(fps) => parse_fps_limit(&fps).unwrap_or_else(|| {
+                log::warn!("GFX|hosted: XOUS_FPS={} isn't a frame rate; using {}", fps, MAX_FPS);
+                Some(MAX_FPS)
+            }),
+            Err(_) => Some(MAX_FPS),
+        };
 
-        // // Limit the maximum refresh rate
-        // window.limit_update_rate(Some(std::time::Duration::from_micros(
-        //     1000 * 1000 / MAX_FPS,
-        // )));
-
-        let native_buffer = vec![DARK_COLOUR; WIDTH as usize * HEIGHT as usize];
-        window
-            .update_with_buffer(&native_buffer, WIDTH as usize, HEIGHT as usize)
-            .unwrap();
-
-        let xns = xous_names::XousNames::new().unwrap();
-        let kbd =
-            keyboard::Keyboard::new(&xns).expect("GFX|hosted can't connect to KBD for emulation");
-        let keyboard_handler = Box::new(XousKeyboardHandler {
-            kbd: kbd,
-            left_shift: false,
-            right_shift: false,
-        });
-        window.set_input_callback(keyboard_handler);
+        let quit_key = match std::env::var("XOUS_QUIT_KEY") {
+            Ok(key) => parse_quit_key(&key).unwrap_or_else(|| {
+                log::warn!("GFX|hosted: XOUS_QUIT_KEY={} isn't a key that can quit; using Escape", key);
+                Some(Key::Escape)
+            }),
+            Err(_) => Some(Key::Escape),
+        };
+
+        let rotation = match std::env::var("XOUS_ROTATION") {
+            Ok(degrees) => parse_rotation(&degrees).unwrap_or_else(|| {
+                log::warn!("GFX|hosted: XOUS_ROTATION={} isn't 0, 90, 180 or 270; not turning the display", degrees);
+                Rotation::default()
+            }),
+            Err(_) => Rotation::default(),
+        };
+
+        let lcd_timing = match std::env::var("XOUS_LCD_LINE_US") {
+            Ok(us) => match us.trim().parse::<u64>() {
+                Ok(us) => Some(LcdTimingConfig { line_time: Duration::from_micros(us) }),
+                Err(_) => {
+                    log::warn!("GFX|hosted: XOUS_LCD_LINE_US={} isn't a number of microseconds; not simulating LCD timing", us);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
 
-        XousDisplay {
-            native_buffer,
-            window,
+        let keymap = match std::env::var("XOUS_KEYMAP") {
+            Ok(spec) => HostKeymap::default().with_overrides(&spec).unwrap_or_else(|| {
+                log::warn!("GFX|hosted: XOUS_KEYMAP={} isn't a keymap; using the default", spec);
+                HostKeymap::default()
+            }),
+            Err(_) => HostKeymap::default(),
+        };
+
+        let mut display = XousDisplay {
+            native_buffer: vec![DARK_COLOUR; WIDTH as usize * HEIGHT as usize],
+            back_buffer: Arc::new(BackBuffer::new(WIDTH_WORDS, HEIGHT as usize)),
+            back_generation: 0,
+            window: None,
             emulated_buffer: [0u32; FB_SIZE],
             srfb: [0u32; FB_SIZE],
             devboot: true,
+            dim_overlay: None,
+            bit_order: BitOrder::default(),
+            pixel_lut: PixelLut::new(Palette::default(), BitOrder::default()),
+            viewport: None,
+            rotation,
+            focused: Rc::new(Cell::new(true)),
+            host_keys: Rc::new(RefCell::new(Vec::new())),
+            key_repeat: KeyRepeater::default(),
+            key_events: Vec::new(),
+            key_translator: KeyTranslator::default(),
+            keymap,
+            keymap_overlay: false,
+            clipboard_paste: std::env::var("XOUS_PASTE").map_or(true, |v| v != "0"),
+            paste_delay: PASTE_DELAY,
+            paste: None,
+            pointer: PointerTracker::default(),
+            pointer_events: Vec::new(),
+            gestures: GestureTracker::default(),
+            gesture_config: GestureConfig::default(),
+            gesture_events: Vec::new(),
+            script: None,
+            inspector: None,
+            magnifier: None,
+            magnifier_in_screenshots: false,
+            grid: None,
+            grid_spec: GridSpec::default(),
+            last_vsync: Instant::now(),
+            refresh: RefreshSchedule::default(),
+            palette: Palette::default(),
+            brightness: MAX_BRIGHTNESS,
+            sharp_buffer: Vec::new(),
+            scaling: Scaling::default(),
+            lcd_artifacts: None,
+            lcd_config: LcdArtifactConfig::default(),
+            lcd_timing: None,
+            power: PanelPower::On,
+            damage: Damage::default(),
+            native_changed: false,
+            skipped_frames: 0,
+            fps_limit,
+            frame_clock: FrameClock::default(),
+            stats_overlay: false,
+            recording: None,
+            quit_key,
+            startup_snapshot: None,
+            capture_dir: std::env::var_os("XOUS_CAPTURE_DIR").map_or_else(|| PathBuf::from(CAPTURE_DIR), PathBuf::from),
+            surface,
+            instance: if surface == 0 { instance } else { format!("{}, surface {}", instance, surface) },
+            title_note: String::new(),
+            title: String::new(),
+            title_at: Instant::now(),
+            title_frames: 0,
+            title_fps: 0.0,
+            frame_callback: None,
+            presented_frames: 0,
+            last_presented: None,
+            started: Instant::now(),
+            window_size: rotation.shown_size(),
+            scaled_buffer: Vec::new(),
+        };
+        display.set_lcd_timing(lcd_timing);
+        if let (0, Some(path)) = (surface, std::env::var_os("XOUS_SNAPSHOT")) {
+            let path = PathBuf::from(path);
+            match display.load_snapshot(&path) {
+                Ok(()) => {
+                    log::info!("GFX|hosted: showing the snapshot {}", path.display());
+                    display.startup_snapshot = Some(path);
+                }
+                Err(e) => log::error!("GFX|hosted: couldn't load the snapshot {}: {:?}", path.display(), e),
+            }
+        }
+        if let (0, Some(path)) = (surface, std::env::var_os("XOUS_INPUT_SCRIPT")) {
+            let path = PathBuf::from(path);
+            match InputScript::load(&path) {
+                Ok(script) => {
+                    log::info!("GFX|hosted: playing the input script {}", path.display());
+                    let shown = path.display().to_string();
+                    display.play_script(script.on_done(move || log::info!("GFX|hosted: played the input script {}", shown)));
+                }
+                Err(e) => log::error!("GFX|hosted: couldn't load the input script {}: {:?}", path.display(), e),
+            }
         }
+        display
     }
-    pub fn set_devboot(&mut self, ena: bool) {
-        if ena {
-            self.devboot = true;
+    /// Applies a dither mask over the whole display when it is rendered, to preview a dimmed
+    /// background behind a modal. The logical framebuffer is not altered; `None` restores the
+    /// undimmed display.
+    pub fn set_dim_overlay(&mut self, pattern: Option<DitherPattern>) {
+        self.dim_overlay = pattern;
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    /// Selects how the bits of each framebuffer word map to pixels when the display is rendered,
+    /// to match a panel that shifts its pixels in MSB-first. Defaults to `BitOrder::Lsb`.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.bit_order = order;
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    /// Restricts rendering to `viewport`, whose corners are inclusive, to simulate a panel with a
+    /// smaller active area or a letterboxed app. Pixels outside it are painted with the
+    /// background colour, whatever the framebuffer holds there. `None` restores the full screen.
+    pub fn set_viewport(&mut self, viewport: Option<Rectangle>) {
+        self.viewport = viewport;
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    /// Turns the display `rotation` clockwise in the window, for landscape layouts. Only what's
+    /// shown turns, along with screenshots and the pointer, so the graphics server is none the
+    /// wiser. The window is opened again in the new shape, and a recording going is stopped and
+    /// saved, as the frames after would be a different shape. Not turned by default, unless
+    /// `XOUS_ROTATION` gives the degrees to turn it.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        if rotation == self.rotation {
+            return;
         }
-        // ignore attempts to turn off devboot
+        self.rotation = rotation;
+        if self.recording.is_some() {
+            self.log_stop_recording();
+        }
+        // turns the native buffer over right away, for the LCD simulation to start from
+        mark_all_dirty(&mut self.emulated_buffer);
+        self.emulated_to_native();
+        let width = rotation.shown_size().0;
+        self.lcd_artifacts = self.lcd_artifacts.take().map(|_| LcdArtifacts::new(self.lcd_config, &self.native_buffer, width));
+        if self.window.is_some() {
+            self.window = None;
+            if let Err(e) = self.reopen_window() {
+                log::error!("GFX|hosted: couldn't open the window turned: {:?}; running headless", e);
+            }
+        }
+        self.native_changed = true;
     }
-    pub fn suspend(&self) {}
-    pub fn resume(&self) {}
-
-    pub fn stash(&mut self) {
-        self.srfb.copy_from_slice(&self.emulated_buffer);
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+    /// Selects how often `redraw()` renders the whole display rather than just the lines that
+    /// were drawn to since the last one, to exercise the anti-ghosting refreshes the memory LCD
+    /// needs. In the emulator the result looks the same either way. Defaults to
+    /// `RefreshPolicy::Full`.
+    pub fn set_refresh_policy(&mut self, policy: RefreshPolicy) {
+        self.refresh = RefreshSchedule { policy, partials: 0 };
+    }
+    /// Sets the colours the display is rendered in, as 0xRRGGBB: `fg` for dark pixels and `bg`
+    /// for light ones. Inverted video, if on, stays on.
+    pub fn set_palette(&mut self, fg: u32, bg: u32) {
+        self.palette = Palette { fg, bg, ..self.palette };
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+    /// Previews how the display would look with grey levels, each pixel shown as the average
+    /// of the 2x2 pixels from it to the right and down, which also shows up single-pixel jaggies.
+    /// Off by default. It only changes what's shown: screenshots and recordings are of the
+    /// display as it is, in black and white, without the overlays; screenshots note that the
+    /// preview was on.
+    pub fn set_greyscale_preview(&mut self, on: bool) {
+        self.sharp_buffer = if on { vec![DARK_COLOUR; self.native_buffer.len()] } else { Vec::new() };
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    pub fn greyscale_preview(&self) -> bool {
+        !self.sharp_buffer.is_empty()
+    }
+    /// Toggles inverted video
+    pub fn invert(&mut self) {
+        self.set_inverted(!self.palette.inverted);
+    }
+    /// Simulates the ghosting and line-by-line updates of the memory LCD, so UI that smears on
+    /// the device smears here too, or turns the simulation off with `None`. Off by default,
+    /// which keeps what's shown deterministic; screenshots and dumps never include the artifacts.
+    pub fn set_lcd_artifacts(&mut self, config: Option<LcdArtifactConfig>) {
+        if let Some(config) = config {
+            self.lcd_config = config;
+        }
+        let width = self.rotation.shown_size().0;
+        self.lcd_artifacts = config.map(|c| LcdArtifacts::new(c, &self.native_buffer, width));
+    }
+    /// Simulates the time the memory LCD controller takes to send an update to the panel, a
+    /// line at a time, or turns the simulation off with `None`. While it's on, `busy()` reads as
+    /// the hardware's busy flag does: `redraw()` waits out a transfer that's going before it
+    /// sends the next, as the hardware backend's does, and the update `blit_screen()` sends is
+    /// ignored if it comes while the controller's busy, as the hardware ignores it. Off unless
+    /// `XOUS_LCD_LINE_US` gives the microseconds a line takes.
+    pub fn set_lcd_timing(&mut self, config: Option<LcdTimingConfig>) {
+        self.take_blit();
+        let fb = &self.emulated_buffer;
+        self.lcd_timing = config.map(|config| LcdTiming::new(config, fb));
+    }
+    /// Updates the simulated LCD controller ignored for coming while it was busy
+    pub fn ignored_lcd_updates(&self) -> u64 {
+        self.lcd_timing.as_ref().map_or(0, |timing| timing.ignored())
+    }
+    /// Shows the frame statistics over the top right corner of the display, or takes them off.
+    /// They're drawn into the native buffer, so screenshots include them.
+    pub fn set_stats_overlay(&mut self, on: bool) {
+        self.stats_overlay = on;
+        // brings back what was under the overlay, or redraws it
+        mark_overlay_dirty(&mut self.emulated_buffer, OVERLAY_HEIGHT, self.rotation);
+    }
+    /// How the display has been keeping up, for benchmarks
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats {
+            fps_limit: self.fps_limit,
+            brightness: self.brightness,
+            presented: self.presented_frames,
+            last_presented: self.last_presented.map(|at| at.saturating_duration_since(self.started)),
+            ..self.frame_clock.stats()
+        }
+    }
+    /// Records the frames put up from now on into an animated PNG in the capture directory,
+    /// which is written out by `stop_recording()`, returning the path it'll be at. Repeats of a
+    /// frame are left out, and frames past `max_frames` are dropped. Any recording already going
+    /// is stopped and saved first.
+    pub fn start_recording(&mut self, max_frames: usize) -> std::io::Result<PathBuf> {
+        if self.recording.is_some() {
+            self.log_stop_recording();
+        }
+        let palette = self.shown_palette();
+        let colours = [palette.dark(), palette.light()];
+        let (width, height) = self.rotation.shown_size();
+        let path = self.capture_path("recording", "png");
+        let mut recording = Recording::start(&path, max_frames, width as u32, height as u32, colours)?;
+        // the frame up now is the first
+        recording.frame(Instant::now(), self.presented_frames, &native_to_png_rows(self.sharp_frame(), width, recording.set_colour()));
+        self.recording = Some(recording);
+        Ok(path)
+    }
+    /// Stops recording and writes out the animation, returning the number of frames in it, or
+    /// 0 if there was no recording going
+    pub fn stop_recording(&mut self) -> std::io::Result<usize> {
+        match self.recording.take() {
+            Some(recording) => recording.finish(Instant::now()),
+            None => Ok(0),
+        }
+    }
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+    fn log_stop_recording(&mut self) {
+        match self.stop_recording() {
+            Ok(frames) => log::info!("GFX|hosted: recording of {} frames saved", frames),
+            Err(e) => log::error!("GFX|hosted: couldn't save the recording: {:?}", e),
+        }
+    }
+    /// Sets the key that closes the window, as clicking its close button does, or takes the
+    /// binding off with `None`, leaving the key to apps. Starts out at `XOUS_QUIT_KEY` if that's
+    /// set, to a key name or `none`, and Escape if not.
+    pub fn set_quit_key(&mut self, key: Option<Key>) {
+        self.quit_key = key;
+    }
+    /// Caps the rate the window is updated at, in frames per second; `None`, or `Some(0)`,
+    /// lifts the cap. Starts out at `XOUS_FPS` if that's set, to a number or `uncapped`, and
+    /// `MAX_FPS` if not.
+    pub fn set_fps_limit(&mut self, fps_limit: Option<u32>) {
+        self.fps_limit = fps_limit.filter(|&fps| fps > 0);
+        if let Some(window) = self.window.as_mut() {
+            window.limit_update_rate(self.fps_limit.map(|fps| frame_period(Some(fps))));
+        }
+        self.retitle();
+    }
+    /// Selects how the display is scaled to the window. `Scaling::Integer` keeps every pixel
+    /// the same size, for judging font rendering. Defaults to `Scaling::AspectRatioStretch`.
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        self.retitle();
+        // so the next redraw puts it up at the new scale
+        self.native_changed = true;
+    }
+    /// Returns `true` if the emulator window had focus as of the last `update()`.
+    ///
+    /// Key forwarding is gated on this: while the window is unfocused, key events delivered
+    /// by the host are dropped instead of being queued for the emulated keyboard, so
+    /// keystrokes meant for another application can't leak into the emulated system.
+    /// Forwarding resumes on the first `update()` after focus is regained.
+    pub fn is_focused(&self) -> bool {
+        self.focused.get()
+    }
+    /// Takes the keys typed into the window since the last call, oldest first, as the host sent
+    /// them; `drain_keys()` has them as the characters they type. Events come in as the window's
+    /// updated.
+    pub fn drain_key_events(&mut self) -> Vec<HostKeyEvent> {
+        std::mem::take(&mut self.key_events)
+    }
+    /// Sets how long a key is held before it repeats, and how often it repeats then. The host's
+    /// own repeats are dropped, and only the keys that repeat on the device do. Defaults to the
+    /// keyboard service's settings on the device, 500ms and 20ms.
+    pub fn set_key_repeat(&mut self, config: KeyRepeatConfig) {
+        self.key_repeat = KeyRepeater::new(config);
+    }
+    /// Sets which host keys stand for the Precursor's keys that have no character of their own,
+    /// like the function keys under the screen. Starts out at `HostKeymap::default()`, with the
+    /// changes `XOUS_KEYMAP` makes if that's set.
+    pub fn set_keymap(&mut self, keymap: HostKeymap) {
+        self.keymap = keymap;
+        if self.keymap_overlay {
+            // it can change size
+            mark_all_dirty(&mut self.emulated_buffer);
+        }
+    }
+    pub fn keymap(&self) -> &HostKeymap {
+        &self.keymap
+    }
+    /// Shows the keymap over the top left corner of the display, or takes it off. Like the
+    /// frame statistics, it's drawn into the native buffer, so screenshots include it.
+    pub fn set_keymap_overlay(&mut self, on: bool) {
+        self.keymap_overlay = on;
+        mark_overlay_dirty(&mut self.emulated_buffer, text_box_height(keymap_overlay_text(&self.keymap).len()), self.rotation);
+    }
+    /// Turns the pixel inspector on or off. While it's on, the window title reads out the pixel
+    /// under the mouse, as "x=.., y=.., bit=0/1, word offset=..", and a click copies that to the
+    /// host's clipboard rather than touching the display. Shift+F12 toggles it.
+    pub fn set_inspector(&mut self, on: bool) {
+        if on == self.inspector.is_some() {
+            return;
+        }
+        if on {
+            // a drag going is let go where it got to, as the inspector takes the mouse over
+            if let Some(event) = self.pointer.sample(None, false) {
+                self.pointer_events.push(event);
+                self.gesture_events.extend(self.gestures.pointer(event, Instant::now(), &self.gesture_config));
+            }
+        }
+        self.inspector = if on { Some(Inspector::default()) } else { None };
+        self.retitle();
+    }
+    /// The pixel under the mouse, while the inspector's on and the mouse is over the display
+    pub fn inspected(&self) -> Option<PixelReadout> {
+        self.inspector.as_ref().and_then(|inspector| inspector.hovered)
+    }
+    /// Turns the magnifier on or off. While it's on, the pixels around the mouse are shown blown
+    /// up 8x, with a grid between them, in the bottom corner of the window across from the mouse.
+    /// It shows the frame buffer's bits as they are, in black and white, whatever the palette or
+    /// backlight. Recordings never have it in, and screenshots only with
+    /// `set_magnifier_in_screenshots()`. Ctrl+F12 toggles it.
+    pub fn set_magnifier(&mut self, on: bool) {
+        if on == self.magnifier.is_some() {
+            return;
+        }
+        match self.magnifier.take() {
+            Some(mut magnifier) => {
+                magnifier.erase(&mut self.native_buffer);
+            }
+            None => self.magnifier = Some(Magnifier::default()),
+        }
+        self.native_changed = true;
+    }
+    /// Keeps the magnifier in screenshots, rather than leaving it out
+    pub fn set_magnifier_in_screenshots(&mut self, on: bool) {
+        self.magnifier_in_screenshots = on;
+    }
+    /// Draws a grid over the display, a line every `minor` pixels and a stronger one every
+    /// `major`, with guides `margin` pixels in from its edges, or takes it off with `None`. It
+    /// follows the display however it's turned. It's only drawn in the window, and screenshots
+    /// and recordings of it: the frame buffer, and so dumps and golden images, never have it in.
+    /// Ctrl+F11 toggles it.
+    pub fn set_grid(&mut self, grid: Option<GridSpec>) {
+        if let Some(spec) = grid {
+            self.grid_spec = spec;
+        }
+        self.grid = grid;
+        // brings back what was under it, or draws it afresh
+        mark_all_dirty(&mut self.emulated_buffer);
+        self.native_changed = true;
+    }
+    /// Adds `note` to the end of the window title, for context like the app in the foreground; an
+    /// empty note takes it off. Like the title's live status, it's brought up to date within a
+    /// second.
+    pub fn set_title_note(&mut self, note: &str) {
+        self.title_note = note.to_string();
+    }
+    /// Has Ctrl+V type the host's clipboard into the emulated system, or not, for tests that
+    /// need their input to be only what they give. On unless `XOUS_PASTE=0` is set.
+    pub fn set_clipboard_paste(&mut self, on: bool) {
+        self.clipboard_paste = on;
+        if !on {
+            self.paste = None;
+        }
+    }
+    /// Sets the least time between the keys of a paste, so the IME sees them one at a time, as
+    /// typed. Defaults to 20ms.
+    pub fn set_paste_delay(&mut self, delay: Duration) {
+        self.paste_delay = delay;
+    }
+    /// Sets what it takes for a drag to be a swipe
+    pub fn set_gesture_config(&mut self, config: GestureConfig) {
+        self.gesture_config = config;
+    }
+    /// Takes the drags made out as gestures since the last call, oldest first. Each comes as it's
+    /// released, after the pointer events it's made of.
+    pub fn drain_gestures(&mut self) -> Vec<GestureEvent> {
+        std::mem::take(&mut self.gesture_events)
     }
-    pub fn pop(&mut self) {
-        self.emulated_buffer[FB_WIDTH_WORDS*32..].copy_from_slice(&self.srfb[FB_WIDTH_WORDS*32..]);
-        self.redraw();
-        self.update();
+    /// Plays `script` in as `poll_input()` is called, its keys and pointer events going the way
+    /// the window's do, in place of any script still playing, whose callback is then never
+    /// called. Starts out playing the script at `XOUS_INPUT_SCRIPT`, if that's set, on the main
+    /// display.
+    pub fn play_script(&mut self, script: InputScript) {
+        self.script = Some(script);
+    }
+    pub fn is_playing_script(&self) -> bool {
+        self.script.is_some()
+    }
+    /// Has `callback` called with each frame put up from now on, as `on_frame_complete()` sends
+    /// them, or stops with `None`. A redraw that puts nothing new up calls it with the frame
+    /// that's already up.
+    pub fn set_frame_callback(&mut self, callback: Option<Box<dyn FnMut(FrameComplete)>>) {
+        self.frame_callback = callback;
+    }
+    /// Powers the simulated panel off or back on, as the device's is over a suspend. Off, the
+    /// window's blanked but for a banner saying so; blits are refused, and redraws fail with
+    /// `DisplayError::PoweredOff`. Powered back on, that goes on until `reinit()` sets the
+    /// controller up again, so a resume path that leaves that out shows up here too. Drawing into
+    /// the frame buffer carries on regardless, as it's only memory.
+    pub fn power(&mut self, on: bool) -> Result<(), DisplayError> {
+        match (on, self.power) {
+            (false, PanelPower::Off) | (true, PanelPower::On) | (true, PanelPower::Uninitialized) => Ok(()),
+            (true, PanelPower::Off) => {
+                self.power = PanelPower::Uninitialized;
+                Ok(())
+            }
+            (false, _) => {
+                self.power = PanelPower::Off;
+                let width = self.rotation.shown_size().0;
+                let dark = self.palette.dark();
+                self.native_buffer.iter_mut().for_each(|pixel| *pixel = dark);
+                draw_text_box(&mut self.native_buffer, width, &["SUSPENDED".to_string()], Corner::TopLeft, self.palette.light(), dark);
+                self.native_changed = true;
+                self.present()
+            }
+        }
+    }
+    /// Sets the simulated panel's controller up again once it's been powered back on, for frames
+    /// to get to it again, starting with the whole of the one in the frame buffer. Does nothing
+    /// while it's powered off.
+    pub fn reinit(&mut self) {
+        match self.power {
+            PanelPower::Off => log::error!("GFX|hosted: the panel can't be set up while it's powered off"),
+            PanelPower::Uninitialized => {
+                self.power = PanelPower::On;
+                mark_all_dirty(&mut self.emulated_buffer);
+            }
+            PanelPower::On => (),
+        }
+    }
+    /// Whether the simulated panel's powered and set up, to take frames
+    pub fn is_powered(&self) -> bool {
+        self.power == PanelPower::On
+    }
+    /// Logs a blit that comes while the panel can't take it, and returns `true`, for it to be
+    /// dropped
+    fn refuses_blits(&self) -> bool {
+        if self.power == PanelPower::On {
+            return false;
+        }
+        log::error!("GFX|hosted: blit refused, as the panel's {}", if self.power == PanelPower::Off { "powered off" } else { "not set up again" });
+        true
     }
 
-    pub fn screen_size(&self) -> Point {
-        Point::new(WIDTH as i16, HEIGHT as i16)
+    /// Makes damage that needs more runs of lines than the hardware takes panic, rather than be
+    /// logged and merged, so tests of partial-update code catch it
+    pub fn set_strict_damage(&mut self, strict: bool) {
+        self.damage.set_strict(strict);
+    }
+    /// A handle for other threads to blit whole frames with, as `blit_screen()` does. A frame
+    /// blitted while the last is being converted waits for the next redraw, rather than tearing.
+    pub fn back_buffer(&self) -> Arc<BackBuffer> {
+        self.back_buffer.clone()
+    }
+    /// Frames blitted that were blitted over before a redraw got to them
+    pub fn frames_dropped(&self) -> u64 {
+        self.back_buffer.dropped()
+    }
+    pub fn as_slice(&mut self) -> &[u32] {
+        self.take_blit();
+        &self.emulated_buffer
     }
 
-    pub fn blit_screen(&mut self, bmp: &[u32]) {
-        for (dest, src) in self.emulated_buffer.iter_mut().zip(bmp.iter()) {
-            *dest = *src;
+    /// Puts the native buffer up in the window, through the LCD simulation if it's on
+    fn present(&mut self) -> Result<(), DisplayError> {
+        // only a frame with something new in it counts as put up; putting the same one up again,
+        // for the LCD simulation or a new title, doesn't
+        let new_frame = std::mem::replace(&mut self.native_changed, false);
+        let width = self.rotation.shown_size().0;
+        if let Some(spec) = self.grid.as_ref() {
+            let rotation = self.rotation;
+            draw_grid(&mut self.native_buffer, rotation.shown_size(), spec, (WIDTH as usize, HEIGHT as usize), |x, y| rotation.unturn(x, y));
+            // the grid's over the whole display, so all of it's converted afresh next time
+            mark_all_dirty(&mut self.emulated_buffer);
         }
+        if self.stats_overlay {
+            let stats = self.frame_stats();
+            draw_overlay(&mut self.native_buffer, width, &stats, self.palette.light(), self.palette.dark());
+            // so the next conversion puts back what's under it, and it's drawn afresh
+            mark_overlay_dirty(&mut self.emulated_buffer, OVERLAY_HEIGHT, self.rotation);
+        }
+        if self.keymap_overlay {
+            let text = keymap_overlay_text(&self.keymap);
+            draw_text_box(&mut self.native_buffer, width, &text, Corner::TopLeft, self.palette.light(), self.palette.dark());
+            mark_overlay_dirty(&mut self.emulated_buffer, text_box_height(text.len()), self.rotation);
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            let frame = if self.sharp_buffer.is_empty() { &self.native_buffer } else { &self.sharp_buffer };
+            let number = self.presented_frames + new_frame as u64;
+            recording.frame(Instant::now(), number, &native_to_png_rows(frame, width, recording.set_colour()));
+        }
+        if let Some(magnifier) = self.magnifier.as_mut() {
+            // from the frame buffer, so the palette and backlight don't come into it
+            let (rotation, order, fb) = (self.rotation, self.bit_order, &self.emulated_buffer);
+            let (width, height) = rotation.shown_size();
+            magnifier.draw(&mut self.native_buffer, (width, height), |x, y| {
+                if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                    return None;
+                }
+                let (x, y) = rotation.unturn(x as usize, y as usize);
+                Some(fb[y * WIDTH_WORDS + x / 32] & (1 << order.bit(x % 32)) != 0)
+            });
+        }
+        if let Some(lcd) = self.lcd_artifacts.as_mut() {
+            lcd.start_frame(&self.native_buffer);
+        }
+        let shown = match self.show_frame() {
+            Err(minifb::Error::UpdateFailed(e)) => {
+                log::warn!("GFX|hosted: couldn't update the window ({}); opening it again", e);
+                self.reopen_window()?;
+                self.show_frame().map_err(|e| DisplayError::Window(format!("{:?}", e)))
+            }
+            result => result.map_err(|e| DisplayError::Window(format!("{:?}", e))),
+        };
+        if shown.is_ok() && new_frame {
+            let now = Instant::now();
+            self.presented_frames += 1;
+            self.last_presented = Some(now);
+            let at_ms = now.saturating_duration_since(self.started).as_millis() as u64;
+            let done = FrameComplete { frame: self.presented_frames, at_ms };
+            if let Some(callback) = self.frame_callback.as_mut() {
+                callback(done);
+            }
+        }
+        shown
     }
-    pub fn as_slice(&self) -> &[u32] {
-        &self.emulated_buffer
+    /// Tells the frame callback that a redraw found nothing new: the frame already up is the one
+    /// it drew, so it's reported again, with its number and the time it first went up. Whoever's
+    /// waiting for the flush behind the redraw isn't left waiting for a frame that won't come.
+    fn report_frame_again(&mut self) {
+        let frame = self.last_frame().unwrap_or_default();
+        if let Some(callback) = self.frame_callback.as_mut() {
+            callback(frame);
+        }
+    }
+    /// Size a window for this surface opens at: the display's, as it's turned, or half that for
+    /// a surface other than the main display
+    fn window_start_size(&self) -> (usize, usize) {
+        let (width, height) = self.rotation.shown_size();
+        if self.surface == 0 { (width, height) } else { (width / 2, height / 2) }
+    }
+    /// Opens the window again, the shape of the display as it's turned
+    fn reopen_window(&mut self) -> Result<(), DisplayError> {
+        let window = open_window(self.focused.clone(), self.host_keys.clone(), self.fps_limit, self.window_start_size())
+            .map_err(|e| DisplayError::Window(format!("{:?}", e)))?;
+        self.window_size = window.get_size();
+        self.window = Some(window);
+        self.title.clear();
+        self.retitle();
+        Ok(())
+    }
+    /// Shows the frame in the window, sweeping it down through the LCD simulation if that's on
+    fn show_frame(&mut self) -> minifb::Result<()> {
+        let window = match self.window.as_mut() {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let size = self.rotation.shown_size();
+        let lcd = match self.lcd_artifacts.as_mut() {
+            Some(lcd) => lcd,
+            None => return show(window, self.scaling, self.window_size, &mut self.scaled_buffer, &self.native_buffer, size),
+        };
+        let steps = lcd.sweep_steps();
+        for step in 1..=steps {
+            show(window, self.scaling, self.window_size, &mut self.scaled_buffer, lcd.sweep_to(step), size)?;
+            if step < steps {
+                std::thread::sleep(frame_period(self.fps_limit) / steps as u32);
+            }
+        }
+        Ok(())
     }
 
-    pub fn native_buffer(&mut self) -> &mut [u32; FB_SIZE] {
-        &mut self.emulated_buffer
+    /// Sets the directory screenshots, recordings and snapshots taken with the hotkeys go in. It's
+    /// created when the first capture's taken. Starts out at `XOUS_CAPTURE_DIR` if that's set,
+    /// and `CAPTURE_DIR` in the working directory if not.
+    pub fn set_capture_dir(&mut self, dir: &Path) {
+        self.capture_dir = dir.to_path_buf();
+    }
+    /// Where the next `kind` of capture goes: the capture directory, named for the emulator
+    /// instance, the frame up and the time. If the directory can't be created, that's logged,
+    /// and the capture goes in the working directory instead.
+    fn capture_path(&self, kind: &str, extension: &str) -> PathBuf {
+        let dir = match std::fs::create_dir_all(&self.capture_dir) {
+            Ok(()) => self.capture_dir.as_path(),
+            Err(e) => {
+                log::warn!("GFX|hosted: couldn't create {} ({:?}); capturing to the working directory", self.capture_dir.display(), e);
+                Path::new(".")
+            }
+        };
+        free_path(dir, &capture_name(kind, &self.instance, self.presented_frames, SystemTime::now()), extension)
+    }
+    /// Saves the display as a 1bpp PNG at `path`, at the emulated resolution, turned as it's
+    /// shown, and in the colours of the palette, inverted or not and dimmed to the backlight
+    /// level, however the window happens to be scaled. The backlight level is noted in the PNG
+    /// as well.
+    pub fn screenshot_to(&self, path: &Path) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        write_png(std::io::BufWriter::new(file), &self.screenshot_frame(), self.rotation.shown_size(), self.shown_palette(), self.brightness, self.greyscale_preview())
+    }
+    /// The frame a screenshot's of: the display as rendered, with the magnifier taken off unless
+    /// it's meant to stay
+    fn screenshot_frame(&self) -> Cow<'_, [u32]> {
+        match self.magnifier.as_ref() {
+            Some(magnifier) if !self.magnifier_in_screenshots && !self.greyscale_preview() => {
+                Cow::Owned(magnifier.without(&self.native_buffer))
+            }
+            _ => Cow::Borrowed(self.sharp_frame()),
+        }
     }
 
-    pub fn redraw(&mut self) {
+    /// Saves the frame buffer as drawn to `path`, with the palette and rotation it's shown in, for
+    /// `load_snapshot()` to bring back. Snapshots are plain PBMs, so they do as golden images too.
+    pub fn save_snapshot(&mut self, path: &Path) -> std::io::Result<()> {
+        let snapshot = Snapshot { fb: self.copy_framebuffer(), palette: self.palette, rotation: self.rotation };
+        std::fs::write(path, snapshot.to_pbm())
+    }
+    /// Puts the frame saved in the snapshot at `path` in the frame buffer, shown in the palette
+    /// and rotation it was saved with, to look at a frame again without what drew it. Snapshots
+    /// of another version are refused. Starting up with `XOUS_SNAPSHOT` set to a snapshot, the
+    /// main display opens showing it.
+    pub fn load_snapshot(&mut self, path: &Path) -> std::io::Result<()> {
+        let snapshot = Snapshot::from_pbm(&std::fs::read_to_string(path)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        // frames blitted before it go under it
+        self.take_blit();
+        self.emulated_buffer = snapshot.fb;
+        self.set_palette(snapshot.palette.fg, snapshot.palette.bg);
+        self.set_inverted(snapshot.palette.inverted);
+        self.set_rotation(snapshot.rotation);
+        mark_all_dirty(&mut self.emulated_buffer);
         self.emulated_to_native();
-        self.window
-            .update_with_buffer(&self.native_buffer, WIDTH as usize, HEIGHT as usize)
-            .unwrap();
+        Ok(())
     }
 
-    pub fn update(&mut self) {
-        self.emulated_to_native();
-        self.window.update();
-        if !self.window.is_open() || self.window.is_key_down(Key::Escape) {
-            std::process::exit(0);
+    /// Keeps the window handling input, without putting up a new frame
+    fn pump(&mut self) {
+        if let Some(window) = self.window.as_mut() {
+            window.update();
         }
     }
-
-    fn emulated_to_native(&mut self) {
-        const DEVBOOT_LINE: usize = 7;
-        let mut row = 0;
-        for (dest_row, src_row) in self
-            .native_buffer
-            .chunks_mut(WIDTH as _)
-            .zip(self.emulated_buffer.chunks(WIDTH_WORDS as _))
-        {
-            for (dest_cell, src_cell) in dest_row.chunks_mut(32).zip(src_row) {
-                for (bit, dest) in dest_cell.iter_mut().enumerate() {
-                    if self.devboot && ((bit >> 1) % 2) == 0 && (row == DEVBOOT_LINE) {
-                        // try to render the devboot defile somewhat accurately
-                        *dest = LIGHT_COLOUR
-                    } else {
-                        *dest = if src_cell & (1 << bit) != 0 {
-                            DARK_COLOUR
-                        } else {
-                            LIGHT_COLOUR
-                        };
-                    }
-                }
-            }
-            row += 1;
-        }
-    }
-}
-
-impl XousKeyboardHandler {
-    fn decode_key(&mut self, k: Key) -> char {
-        let shift = self.left_shift || self.right_shift;
-        let base: char = if shift == false {
-            match k {
-                // key maps are commented out so we can use the add_char routine for all the characters natively handled by mini_fb
-                // this allows us to apply the native keyboard map to all the typed characters, while still passing through the special
-                // keys needed to emulate the special buttons on the device.
-                /* Key::A => 'a',
-                Key::B => 'b',
-                Key::C => 'c',
-                Key::D => 'd',
-                Key::E => 'e',
-                Key::F => 'f',
-                Key::G => 'g',
-                Key::H => 'h',
-                Key::I => 'i',
-                Key::J => 'j',
-                Key::K => 'k',
-                Key::L => 'l',
-                Key::M => 'm',
-                Key::N => 'n',
-                Key::O => 'o',
-                Key::P => 'p',
-                Key::Q => 'q',
-                Key::R => 'r',
-                Key::S => 's',
-                Key::T => 't',
-                Key::U => 'u',
-                Key::V => 'v',
-                Key::W => 'w',
-                Key::X => 'x',
-                Key::Y => 'y',
-                Key::Z => 'z',
-                Key::Key0 => '0',
-                Key::Key1 => '1',
-                Key::Key2 => '2',
-                Key::Key3 => '3',
-                Key::Key4 => '4',
-                Key::Key5 => '5',
-                Key::Key6 => '6',
-                Key::Key7 => '7',
-                Key::Key8 => '8',
-                Key::Key9 => '9',*/
-                Key::Left => '←',
-                Key::Right => '→',
-                Key::Up => '↑',
-                Key::Down => '↓',
-                Key::Home => '∴',
-                Key::Backspace => '\u{0008}',
-                Key::Delete => '\u{0008}',
-                Key::Enter => 0xd_u8.into(),
-                //Key::Space => ' ',
-                //Key::Comma => ',',
-                //Key::Period => '.',
-                Key::F1 => 0x11_u8.into(),
-                Key::F2 => 0x12_u8.into(),
-                Key::F3 => 0x13_u8.into(),
-                Key::F4 => 0x14_u8.into(),
-                Key::F5 => '😊',
-                Key::F6 => '福',
-                _ => '\u{0000}',
+    fn key_pressed(&self, key: Key) -> bool {
+        self.window.as_ref().map_or(false, |window| window.is_key_pressed(key, KeyRepeat::No))
+    }
+    fn shift_down(&self) -> bool {
+        self.window.as_ref().map_or(false, |window| window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift))
+    }
+    fn ctrl_down(&self) -> bool {
+        self.window.as_ref().map_or(false, |window| window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl))
+    }
+    /// Starts typing the host's clipboard in, after anything still being pasted
+    fn paste_clipboard(&mut self) {
+        let text = match read_host_clipboard() {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("GFX|hosted: couldn't read the clipboard to paste: {:?}", e);
+                return;
             }
+        };
+        let (chars, skipped) = paste_chars(&text);
+        if skipped > 0 {
+            log::warn!("GFX|hosted: pasting {} characters, skipping {} that can't be typed", chars.len(), skipped);
         } else {
-            match k {
-                /* Key::A => 'A',
-                Key::B => 'B',
-                Key::C => 'C',
-                Key::D => 'D',
-                Key::E => 'E',
-                Key::F => 'F',
-                Key::G => 'G',
-                Key::H => 'H',
-                Key::I => 'I',
-                Key::J => 'J',
-                Key::K => 'K',
-                Key::L => 'L',
-                Key::M => 'M',
-                Key::N => 'N',
-                Key::O => 'O',
-                Key::P => 'P',
-                Key::Q => 'Q',
-                Key::R => 'R',
-                Key::S => 'S',
-                Key::T => 'T',
-                Key::U => 'U',
-                Key::V => 'V',
-                Key::W => 'W',
-                Key::X => 'X',
-                Key::Y => 'Y',
-                Key::Z => 'Z',
-                Key::Key0 => ')',
-                Key::Key1 => '!',
-                Key::Key2 => '@',
-                Key::Key3 => '#',
-                Key::Key4 => '$',
-                Key::Key5 => '%',
-                Key::Key6 => '^',
-                Key::Key7 => '&',
-                Key::Key8 => '*',
-                Key::Key9 => '(', */
-                Key::Left => '←',
-                Key::Right => '→',
-                Key::Up => '↑',
-                Key::Down => '↓',
-                Key::Home => '∴',
-                Key::Backspace => '\u{0008}',
-                Key::Delete => '\u{0008}',
-                //Key::Space => ' ',
-                //Key::Comma => '<',
-                //Key::Period => '>',
-                _ => '\u{0000}',
-            }
+            log::info!("GFX|hosted: pasting {} characters", chars.len());
+        }
+        match self.paste.as_mut() {
+            Some(paste) => paste.extend(chars),
+            None => self.paste = Some(Paste::new(chars, self.paste_delay, Instant::now())),
+        }
+    }
+    /// Reads out the pixel the mouse is at, if it's over the display, for the inspector, and
+    /// copies the readout to the host's clipboard if the pixel's been clicked
+    fn inspect(&mut self, at: Option<Point>, down: bool) {
+        let fb = self.copy_framebuffer();
+        let order = self.bit_order;
+        let hovered = at.map(|at| PixelReadout::read(&fb, WIDTH_WORDS, at, order.bit(at.x as usize % 32)));
+        let inspector = match self.inspector.as_mut() {
+            Some(inspector) => inspector,
+            None => return,
         };
-        base
+        let before = inspector.hovered;
+        if let Some(clicked) = inspector.sample(hovered, down) {
+            match write_host_clipboard(&clicked.to_string()) {
+                Ok(()) => log::info!("GFX|hosted: copied {}", clicked),
+                Err(e) => log::error!("GFX|hosted: couldn't copy {} to the clipboard: {:?}", clicked, e),
+            }
+        }
+        if hovered != before {
+            self.retitle();
+        }
     }
-}
-
-impl minifb::InputCallback for XousKeyboardHandler {
-    fn add_char(&mut self, uni_char: u32) {
-        let c = char::from_u32(uni_char).unwrap_or('\u{0000}');
-        if c != '\u{0008}'
-        && c != '\u{000d}'
-        && c != '\u{007f}' {
-            self.kbd.hostmode_inject_key(c);
+    /// Puts the window title up to date straight away, for a change made on purpose
+    fn retitle(&mut self) {
+        self.update_title(true);
+    }
+    /// Brings the live status in the window title up to date, at most once every
+    /// `TITLE_INTERVAL`, so window managers aren't kept busy; with `force`, the title goes up to
+    /// date now, with the frame rate as of the last time
+    fn update_title(&mut self, force: bool) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.title_at);
+        if elapsed >= TITLE_INTERVAL {
+            let frames = self.frame_clock.stats().frames;
+            self.title_fps = (frames - self.title_frames) as f32 / elapsed.as_secs_f32();
+            self.title_frames = frames;
+            self.title_at = now;
+        } else if !force {
+            return;
+        }
+        let readout = self.inspector.as_ref().map(|inspector| match inspector.hovered {
+            Some(readout) => readout.to_string(),
+            None => "off the display".to_string(),
+        });
+        let title = window_title(&TitleStatus {
+            instance: &self.instance,
+            scaling: self.scaling,
+            window: self.window_size,
+            display: self.rotation.shown_size(),
+            fps: self.title_fps,
+            fps_limit: self.fps_limit,
+            frames: self.presented_frames,
+            recording: self.recording.is_some(),
+            lcd_artifacts: self.lcd_artifacts.is_some(),
+            inspector: readout.as_deref(),
+            note: &self.title_note,
+        });
+        if title != self.title {
+            if let Some(window) = self.window.as_mut() {
+                window.set_title(&title);
+            }
+            self.title = title;
         }
     }
 
-    fn set_key_state(&mut self, key: minifb::Key, state: bool) {
-        if key == Key::LeftShift {
-            self.left_shift = state;
+    /// The display as rendered, before the greyscale preview smooths it, if it's on
+    fn sharp_frame(&self) -> &[u32] {
+        if self.greyscale_preview() { &self.sharp_buffer } else { &self.native_buffer }
+    }
+    /// The palette as it looks at the backlight level
+    fn shown_palette(&self) -> Palette {
+        self.palette.dimmed(self.brightness)
+    }
+
+    /// Copies the lines blitted since the last time, if any, into the emulated buffer, marking
+    /// the lines they change dirty
+    fn take_blit(&mut self) {
+        let emulated = &mut self.emulated_buffer;
+        self.back_buffer.take(&mut self.back_generation, |line, lines| {
+            blit(&mut emulated[line * WIDTH_WORDS..], lines);
+        });
+    }
+
+    fn emulated_to_native(&mut self) {
+        if self.power != PanelPower::On {
+            // what's in the window stays blanked until the panel's set up again
             return;
         }
-        if key == Key::RightShift {
-            self.right_shift = state;
+        self.take_blit();
+        // the magnifier's taken off, to be drawn again where the mouse is now
+        if let Some(magnifier) = self.magnifier.as_mut() {
+            if magnifier.erase(&mut self.native_buffer) {
+                self.native_changed = true;
+            }
+        }
+        let palette = self.shown_palette();
+        // the table's only made again when the palette or the bit order changes
+        if !self.pixel_lut.is_for(palette, self.bit_order) {
+            self.pixel_lut = PixelLut::new(palette, self.bit_order);
+        }
+        // the greyscale preview renders as usual, then smooths that into the native buffer, so
+        // the usual path doesn't pay for it
+        let sharp = if self.greyscale_preview() { &mut self.sharp_buffer } else { &mut self.native_buffer };
+        let changed = emulated_to_native(sharp, &self.emulated_buffer, self.devboot, self.dim_overlay, self.viewport, &self.pixel_lut, self.rotation) > 0;
+        // the grid's drawn over the smoothed frame, so that's made afresh under it, and the grid
+        // drawn again, even when the sharp one hasn't changed
+        if self.greyscale_preview() && (changed || self.grid.is_some()) {
+            box_filter(&mut self.native_buffer, &self.sharp_buffer, self.rotation.shown_size().0, palette);
+            self.native_changed = true;
+        } else if changed {
+            self.native_changed = true;
+        }
+        clear_dirty(&mut self.emulated_buffer);
+        if self.brightness == 0 {
+            draw_outline(&mut self.native_buffer, self.rotation.shown_size().0, BORDER_COLOUR);
+        }
+    }
+}
+
+impl DisplayBackend for XousDisplay {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { screenshot: true, window: self.window.is_some(), power: true, brightness: true }
+    }
+    /// The size of the display and the layout of its frame buffer
+    fn info(&self) -> DisplayInfo {
+        DISPLAY_INFO
+    }
+    fn native_buffer(&mut self) -> &mut [u32; FB_SIZE] {
+        // drawing goes over the frame blitted before it
+        self.take_blit();
+        &mut self.emulated_buffer
+    }
+    /// Returns the frame buffer as drawn, with the dirty bits cleared, so that what it holds
+    /// doesn't depend on when the window last refreshed
+    fn copy_framebuffer(&mut self) -> [u32; FB_SIZE] {
+        self.take_blit();
+        let mut fb = self.emulated_buffer;
+        clear_dirty(&mut fb);
+        fb
+    }
+    /// Blits `bmp`, a whole frame, into the back buffer. It's drawn, whole, by the next redraw,
+    /// or sooner if the framebuffer's drawn to or read first.
+    fn blit_screen(&mut self, bmp: &[u32; FB_SIZE]) {
+        if self.refuses_blits() {
             return;
         }
-        if !state {
+        self.back_buffer.blit(bmp);
+        // the hardware's blit sends an update of the whole panel, without waiting for the last
+        // to finish, then waits for that
+        if let Some(timing) = self.lcd_timing.as_mut() {
+            timing.update(true, Instant::now());
+            std::thread::sleep(timing.busy_for(Instant::now()));
+        }
+    }
+    /// Blits just the `line_count` lines in `src` from `start_line` down, so that only those
+    /// lines are copied, and marked dirty where they change. Lines that would run off the end of
+    /// the screen, or that `src` is too short to hold, are left out.
+    fn blit_region(&mut self, src: &[u32], start_line: usize, line_count: usize) {
+        if self.refuses_blits() {
             return;
         }
-
-        log::debug!("GFX|hosted: sending key {:?}", key);
-        let c = self.decode_key(key);
-        if c != '\u{0000}' {
-            self.kbd.hostmode_inject_key(c);
+        self.back_buffer.blit_lines(&src[..(line_count * WIDTH_WORDS).min(src.len())], start_line);
+    }
+    /// Marks `area` of the frame buffer for the next redraw to send to the panel. The controller
+    /// updates whole lines, so that's every line `area` touches, and a frame's damage goes out as
+    /// at most `MAX_UPDATE_BANDS` runs of lines; past that, the closest are merged, and that's
+    /// logged, as it sends lines that didn't change.
+    fn mark_dirty(&mut self, area: Rectangle) {
+        self.damage.add(&area);
+    }
+    /// Puts what's been drawn up in the window. An update the window fails is retried once,
+    /// in a new window; if that fails too, the error is returned, and the next redraw tries
+    /// again.
+    fn redraw(&mut self) -> Result<(), DisplayError> {
+        if self.power != PanelPower::On {
+            return Err(DisplayError::PoweredOff);
+        }
+        self.take_blit();
+        self.damage.mark_lines(&mut self.emulated_buffer);
+        if let Some(timing) = self.lcd_timing.as_mut() {
+            // as the hardware's redraw does: waits out the transfer going, then loads the frame
+            // into the controller and sends its dirty lines
+            std::thread::sleep(timing.busy_for(Instant::now()));
+            timing.load(&self.emulated_buffer);
+            timing.update(false, Instant::now());
         }
+        if self.refresh.next_is_full() {
+            mark_all_dirty(&mut self.emulated_buffer);
+        }
+        let started = Instant::now();
+        self.emulated_to_native();
+        let convert = started.elapsed();
+        let changed = self.native_changed;
+        if !changed && self.lcd_artifacts.is_none() {
+            // nothing to put up; just keep the window handling input
+            self.skipped_frames += 1;
+            self.pump();
+            self.report_frame_again();
+            return Ok(());
+        }
+        self.frame_clock.frame(Instant::now(), convert);
+        let shown = self.present();
+        if shown.is_ok() && !changed {
+            self.report_frame_again();
+        }
+        shown
+    }
+    /// Takes in what's happened to the window: resizes, focus and the emulator's hotkeys.
+    /// Returns `DisplayError::CloseRequested`, once, when the main display's window has been
+    /// closed or the quit key pressed, for the server to shut the system down on. The window
+    /// goes, and the display carries on headless; if the process is still around
+    /// `SHUTDOWN_TIMEOUT` later, it exits. Any other surface's window just goes.
+    fn update(&mut self) -> Result<(), DisplayError> {
+        self.emulated_to_native();
+        let window_size = match self.window.as_ref() {
+            Some(window) => window.get_size(),
+            // headless: there's no window to resize, take keys from or close
+            None => return Ok(()),
+        };
+        if window_size != self.window_size {
+            self.window_size = window_size;
+            self.retitle();
+            if self.scaling == Scaling::Integer {
+                // a new scale, and a scaled buffer the new size of the window
+                self.present()?;
+            }
+        }
+        self.poll_input()
+    }
+    /// Whether the simulated LCD controller is still sending an update to the panel; never,
+    /// unless its timing's simulated
+    fn busy(&self) -> bool {
+        self.lcd_timing.as_ref().map_or(false, |timing| timing.busy(Instant::now()))
+    }
+    /// Blocks until the next refresh of the window, so an animation loop can draw one frame
+    /// per refresh instead of pacing itself with ad-hoc sleeps.
+    ///
+    /// This is best-effort: minifb doesn't report the host's vertical blank, so refreshes are
+    /// taken to happen once every `limit_update_rate` period, on a fixed grid. A caller that
+    /// overruns a frame waits for the next refresh on the grid rather than trying to catch up.
+    fn wait_for_vsync(&mut self) {
+        let now = Instant::now();
+        let vsync = next_vsync(self.last_vsync, now, frame_period(self.fps_limit));
+        std::thread::sleep(vsync - now);
+        self.last_vsync = vsync;
+    }
+    /// Has each frame put up from now on sent to `cid` as a scalar message on `opcode`, its
+    /// number and when it was put up, in milliseconds since the display started, as its first
+    /// two arguments. Frames are put up once they've gone out to the window, or with no window,
+    /// once they've been rendered; a redraw that finds nothing changed puts none up, and sends
+    /// the frame already up again instead.
+    fn on_frame_complete(&mut self, cid: xous::CID, opcode: usize) {
+        self.set_frame_callback(Some(Box::new(move |done: FrameComplete| {
+            // not blocking: the listener could be waiting on this server
+            let message = xous::Message::new_scalar(opcode, done.frame as usize, done.at_ms as usize, 0, 0);
+            if let Err(e) = xous::try_send_message(cid, message) {
+                log::warn!("GFX|hosted: couldn't send frame {} complete: {:?}", done.frame, e);
+            }
+        })));
+    }
+    /// The last frame put up with something new in it, and when
+    fn last_frame(&self) -> Option<FrameComplete> {
+        let at = self.last_presented?;
+        Some(FrameComplete { frame: self.presented_frames, at_ms: at.saturating_duration_since(self.started).as_millis() as u64 })
+    }
+    /// Redraws that found the window already up to date, and skipped pushing the frame to it
+    fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+    /// Powers the panel off, as the device's is over a suspend
+    fn suspend(&mut self) {
+        if let Err(e) = self.power(false) {
+            log::warn!("GFX|hosted: couldn't blank the window for the suspend: {:?}", e);
+        }
+    }
+    /// Powers the panel back on and sets it up again, as the device's resume does, and puts the
+    /// frame back up
+    fn resume(&mut self) {
+        // powering on can't fail; only blanking shows anything
+        self.power(true).ok();
+        self.reinit();
+        if let Err(e) = self.redraw() {
+            log::warn!("GFX|hosted: couldn't put the frame back up after the resume: {:?}", e);
+        }
+    }
+
+    fn stash(&mut self) {
+        self.take_blit();
+        self.srfb.copy_from_slice(&self.emulated_buffer);
+    }
+    fn pop(&mut self) -> Result<(), DisplayError> {
+        // the status bar's left as it is; only lines the stashed frame changes are redrawn
+        self.back_buffer.blit_lines(&self.srfb[FB_WIDTH_WORDS * 32..], 32);
+        self.redraw()?;
+        self.update()
+    }
+    /// Turns inverted video on or off, swapping the colours of dark and light pixels. The
+    /// framebuffer itself is left as it is.
+    fn set_inverted(&mut self, inverted: bool) {
+        self.palette.inverted = inverted;
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+    fn set_devboot(&mut self, ena: bool) {
+        if ena {
+            self.devboot = true;
+            mark_all_dirty(&mut self.emulated_buffer);
+        }
+        // ignore attempts to turn off devboot
+    }
+    /// Dims the display as the backlight at `level`, out of `MAX_BRIGHTNESS`, would. At 0 the
+    /// backlight's off, and the display is black but for a thin outline, so the window can still
+    /// be found.
+    fn set_brightness(&mut self, level: u8) {
+        self.brightness = level.min(MAX_BRIGHTNESS);
+        mark_all_dirty(&mut self.emulated_buffer);
+    }
+
+    fn input_poll_interval(&self) -> Option<Duration> {
+        Some(INPUT_POLL_INTERVAL)
+    }
+    /// Services the window's event queue, queueing the keys and pointer events in it and acting
+    /// on the hotkeys, without rendering a frame. `update()` does this too, but only as often as
+    /// the display's redrawn; calling this every `INPUT_POLL_INTERVAL` in between keeps fast
+    /// typing from outrunning it. Like everything else to do with the window, it has to be
+    /// called on the thread that opened it.
+    fn poll_input(&mut self) -> Result<(), DisplayError> {
+        self.pump();
+        self.focused.set(self.window.as_mut().map_or(true, |window| window.is_active()));
+        let now = Instant::now();
+        if let Some(script) = self.script.as_mut() {
+            // frames go by as the display's redrawn, whether or not there's anything new to put up
+            for input in script.due(now, self.presented_frames + self.skipped_frames) {
+                match input {
+                    ScriptInput::Key(event) => self.host_keys.borrow_mut().push(event),
+                    ScriptInput::Pointer(event) => {
+                        self.pointer_events.push(event);
+                        self.gesture_events.extend(self.gestures.pointer(event, now, &self.gesture_config));
+                    }
+                }
+            }
+            if script.is_done() {
+                if let Some(script) = self.script.take() {
+                    script.finish();
+                }
+            }
+        }
+        for event in self.host_keys.borrow_mut().drain(..) {
+            self.key_events.extend(self.key_repeat.event(event, now, &self.keymap));
+        }
+        if !self.focused.get() {
+            // the held key's release may never come
+            self.key_repeat.stop();
+        }
+        self.key_events.extend(self.key_repeat.due(now));
+        // the pixel under the mouse comes out the same for the inspector as for the pointer
+        let mouse = self.window.as_ref().map(|window| {
+            let at = window
+                .get_mouse_pos(MouseMode::Discard)
+                .and_then(|pos| window_to_display(pos, self.window_size, self.scaling, self.rotation));
+            (at, window.get_mouse_down(MouseButton::Left))
+        });
+        let rotation = self.rotation;
+        if let (Some((at, _)), Some(magnifier)) = (mouse, self.magnifier.as_mut()) {
+            let at = at.map(|at| rotation.turn(at.x as usize, at.y as usize));
+            if at != magnifier.at {
+                magnifier.at = at;
+                self.present()?;
+            }
+        }
+        if let Some((at, down)) = mouse {
+            if self.inspector.is_some() {
+                self.inspect(at, down);
+            } else if let Some(event) = self.pointer.sample(at, down) {
+                self.pointer_events.push(event);
+                self.gesture_events.extend(self.gestures.pointer(event, Instant::now(), &self.gesture_config));
+            }
+        }
+        if self.key_pressed(Key::F1) && self.shift_down() {
+            self.set_keymap_overlay(!self.keymap_overlay);
+            self.emulated_to_native();
+            self.present()?;
+        }
+        for &(key, faster) in [(Key::F7, false), (Key::F8, true)].iter() {
+            if self.key_pressed(key) {
+                self.set_fps_limit(step_fps_limit(self.fps_limit, faster));
+            }
+        }
+        if self.key_pressed(Key::F9) && self.shift_down() {
+            self.set_greyscale_preview(!self.greyscale_preview());
+            log::info!("GFX|hosted: greyscale preview {}", if self.greyscale_preview() { "on" } else { "off" });
+            self.emulated_to_native();
+            self.present()?;
+        } else if self.key_pressed(Key::F9) {
+            self.invert();
+            self.emulated_to_native();
+            self.present()?;
+        }
+        if self.key_pressed(Key::F10) && self.shift_down() {
+            let path = self.capture_path("snapshot", "pbm");
+            match self.save_snapshot(&path) {
+                Ok(()) => log::info!("GFX|hosted: snapshot saved to {}", path.display()),
+                Err(e) => log::error!("GFX|hosted: couldn't save snapshot to {}: {:?}", path.display(), e),
+            }
+        } else if self.key_pressed(Key::F10) {
+            let config = if self.lcd_artifacts.is_some() { None } else { Some(self.lcd_config) };
+            self.set_lcd_artifacts(config);
+            log::info!("GFX|hosted: LCD artifact simulation {}", if config.is_some() { "on" } else { "off" });
+        }
+        if self.key_pressed(Key::F11) && self.ctrl_down() {
+            self.set_grid(if self.grid.is_some() { None } else { Some(self.grid_spec) });
+            log::info!("GFX|hosted: grid {}", if self.grid.is_some() { "on" } else { "off" });
+            self.emulated_to_native();
+            self.present()?;
+        } else if self.key_pressed(Key::F11) && self.shift_down() {
+            if self.recording.is_some() {
+                self.log_stop_recording();
+            } else {
+                match self.start_recording(RECORDING_MAX_FRAMES) {
+                    Ok(path) => log::info!("GFX|hosted: recording to {}", path.display()),
+                    Err(e) => log::error!("GFX|hosted: couldn't start recording: {:?}", e),
+                }
+            }
+        } else if self.key_pressed(Key::F11) {
+            self.set_stats_overlay(!self.stats_overlay);
+            self.emulated_to_native();
+            self.present()?;
+        }
+        if self.clipboard_paste && self.ctrl_down() && self.key_pressed(Key::V) {
+            self.paste_clipboard();
+        }
+        if let Some(paste) = self.paste.as_mut() {
+            self.key_events.extend(paste.due(Instant::now()));
+            if paste.is_done() {
+                self.paste = None;
+            }
+        }
+        self.update_title(false);
+        if self.key_pressed(Key::F12) && self.ctrl_down() {
+            self.set_magnifier(self.magnifier.is_none());
+            log::info!("GFX|hosted: magnifier {}", if self.magnifier.is_some() { "on" } else { "off" });
+        } else if self.key_pressed(Key::F12) && self.shift_down() {
+            self.set_inspector(self.inspector.is_none());
+            log::info!("GFX|hosted: pixel inspector {}", if self.inspector.is_some() { "on" } else { "off" });
+        } else if self.key_pressed(Key::F12) {
+            match self.screenshot() {
+                Ok(path) => log::info!("GFX|hosted: screenshot saved to {}", path.display()),
+                Err(e) => log::error!("GFX|hosted: couldn't save a screenshot: {:?}", e),
+            }
+        }
+        let quit_key = self.quit_key;
+        if self.window.as_ref().map_or(false, |window| !window.is_open() || quit_key.map_or(false, |key| window.is_key_down(key))) {
+            if self.recording.is_some() {
+                self.log_stop_recording();
+            }
+            self.window = None;
+            if self.surface != 0 {
+                log::info!("GFX|hosted: surface {} closed; carrying on without it", self.surface);
+                return Ok(());
+            }
+            std::thread::spawn(|| {
+                std::thread::sleep(SHUTDOWN_TIMEOUT);
+                log::warn!("GFX|hosted: the system didn't shut down within {:?} of the window closing; exiting", SHUTDOWN_TIMEOUT);
+                std::process::exit(0);
+            });
+            return Err(DisplayError::CloseRequested);
+        }
+        Ok(())
+    }
+    /// Takes the keys typed into the window since the last call as the characters they type on
+    /// the Precursor, oldest first, as `drain_key_events()` and `KeyTranslator` make them out
+    fn drain_keys(&mut self) -> Vec<char> {
+        let keymap = &self.keymap;
+        let translator = &mut self.key_translator;
+        self.key_events.drain(..).filter_map(|event| translator.translate(event, keymap)).collect()
+    }
+    /// Takes the clicks and drags on the display since the last call, oldest first, for the
+    /// server to pass on. The mouse is sampled as the window's updated, so a click quicker than
+    /// an update can be missed. Nothing takes the gestures they make over IPC yet, so those are
+    /// logged, to check the thresholds by, and dropped.
+    fn drain_pointer_events(&mut self) -> Vec<PointerEvent> {
+        for gesture in self.drain_gestures() {
+            log::debug!("{:?}", gesture);
+        }
+        std::mem::take(&mut self.pointer_events)
+    }
+
+    /// Which of the emulator's display surfaces this is; the main display is 0
+    fn surface(&self) -> usize {
+        self.surface
+    }
+    /// Opens surface `surface` as `new_surface()` does, or headless if this display is
+    fn open_surface(&self, surface: usize) -> Option<Box<dyn DisplayBackend>> {
+        Some(Box::new(if self.window.is_some() {
+            XousDisplay::new_surface(surface)
+        } else {
+            XousDisplay::headless_surface(surface)
+        }))
+    }
+    /// Saves the display as a PNG in the capture directory, as `screenshot_to()` does, returning
+    /// the path it was saved at
+    fn screenshot(&self) -> std::io::Result<PathBuf> {
+        let path = self.capture_path("screenshot", "png");
+        self.screenshot_to(&path)?;
+        Ok(path)
+    }
+    /// The snapshot `XOUS_SNAPSHOT` had the display start out showing, if any, for the boot logo
+    /// to be left off
+    fn startup_snapshot(&self) -> Option<&Path> {
+        self.startup_snapshot.as_deref()
+    }
+}
+
+/// Opens the emulator window, `size` to start with, queueing the keys typed into it on
+/// `host_keys` while `focused`
+fn open_window(focused: Rc<Cell<bool>>, host_keys: Rc<RefCell<Vec<HostKeyEvent>>>, fps_limit: Option<u32>, (width, height): (usize, usize)) -> minifb::Result<Window> {
+    let mut window = Window::new(
+        "Precursor",
+        width,
+        height,
+        WindowOptions {
+            scale_mode: minifb::ScaleMode::AspectRatioStretch,
+            resize: true,
+            ..WindowOptions::default()
+        },
+    )?;
+    window.limit_update_rate(fps_limit.map(|fps| frame_period(Some(fps))));
+    window.set_input_callback(Box::new(KeyEventQueue { events: host_keys, down: HashSet::new(), focused }));
+    Ok(window)
+}
+
+/// Puts `frame`, a native buffer's worth of pixels `frame_size` in size, up in `window`, scaled as
+/// `scaling` says
+fn show(window: &mut Window, scaling: Scaling, window_size: (usize, usize), scaled_buffer: &mut Vec<u32>, frame: &[u32], frame_size: (usize, usize)) -> minifb::Result<()> {
+    match scaling {
+        Scaling::AspectRatioStretch => window.update_with_buffer(frame, frame_size.0, frame_size.1),
+        Scaling::Integer => {
+            let (width, height) = window_size;
+            if width == 0 || height == 0 {
+                // minimized
+                window.update();
+                return Ok(());
+            }
+            scale_native(frame, frame_size, scaled_buffer, window_size, integer_scale(window_size, frame_size));
+            window.update_with_buffer(scaled_buffer, width, height)
+        }
+    }
+}
+
+/// The largest whole-number scale at which a `display`-sized display fits in a `window`-sized
+/// area. A window too small for the display at all still gets 1x, and shows the middle of it.
+fn integer_scale((width, height): (usize, usize), display: (usize, usize)) -> usize {
+    (width / display.0).min(height / display.1).max(1)
+}
+
+/// The pixel of the display at `(x, y)` in a `window`-sized window, as `scaling` lays the
+/// display out in it, turned by `rotation`; `None` in the border around the display
+fn window_to_display((x, y): (f32, f32), (width, height): (usize, usize), scaling: Scaling, rotation: Rotation) -> Option<Point> {
+    if width == 0 || height == 0 {
+        // minimized
+        return None;
+    }
+    let (display_width, display_height) = rotation.shown_size();
+    let (width, height) = (width as i32, height as i32);
+    let aspect = display_width as f32 / display_height as f32;
+    let (shown_width, shown_height) = match scaling {
+        // as minifb stretches it: to the full width or height of the window, whichever fits
+        Scaling::AspectRatioStretch if aspect > width as f32 / height as f32 => (width, (width as f32 / aspect) as i32),
+        Scaling::AspectRatioStretch => ((height as f32 * aspect) as i32, height),
+        Scaling::Integer => {
+            let scale = integer_scale((width as usize, height as usize), (display_width, display_height)) as i32;
+            (display_width as i32 * scale, display_height as i32 * scale)
+        }
+    };
+    // centred, in both cases; a window too small for the display at 1x shows the middle of it
+    let x = x - ((width - shown_width) / 2) as f32;
+    let y = y - ((height - shown_height) / 2) as f32;
+    if x < 0.0 || y < 0.0 || x >= shown_width as f32 || y >= shown_height as f32 {
+        return None;
+    }
+    let (x, y) = rotation.unturn(
+        ((x * display_width as f32 / shown_width as f32) as usize).min(display_width - 1),
+        ((y * display_height as f32 / shown_height as f32) as usize).min(display_height - 1),
+    );
+    Some(Point::new(x as i16, y as i16))
+}
+
+/// Renders `native`, `native_size` in size, at `scale` into `scaled`, a `window`-sized buffer,
+/// centred, with the rest of it in the border colour
+fn scale_native(native: &[u32], (native_width, native_height): (usize, usize), scaled: &mut Vec<u32>, (width, height): (usize, usize), scale: usize) {
+    scaled.clear();
+    scaled.resize(width * height, BORDER_COLOUR);
+    let (shown_width, shown_height) = ((native_width * scale) as isize, (native_height * scale) as isize);
+    let left = (width as isize - shown_width) / 2;
+    let top = (height as isize - shown_height) / 2;
+    for (y, dest_row) in scaled.chunks_mut(width).enumerate() {
+        let src_y = y as isize - top;
+        if src_y < 0 || src_y >= shown_height {
+            continue;
+        }
+        let src_row = &native[(src_y as usize / scale) * native_width..][..native_width];
+        for (x, dest) in dest_row.iter_mut().enumerate() {
+            let src_x = x as isize - left;
+            if src_x >= 0 && src_x < shown_width {
+                *dest = src_row[src_x as usize / scale];
+            }
+        }
+    }
+}
+
+/// What the window title says about the emulator
+struct TitleStatus<'a> {
+    instance: &'a str,
+    scaling: Scaling,
+    /// size of the window, and of the display, turned as it's shown
+    window: (usize, usize),
+    display: (usize, usize),
+    /// frame rate over the last while
+    fps: f32,
+    fps_limit: Option<u32>,
+    /// frames put up so far
+    frames: u64,
+    recording: bool,
+    lcd_artifacts: bool,
+    /// the pixel inspector's readout, while it's on
+    inspector: Option<&'a str>,
+    note: &'a str,
+}
+
+/// Window title telling emulators apart, and giving their status at a glance: the scale the
+/// display is shown at, the frame rate against the limit, the frames put up, and the modes
+/// that change what's shown or saved, so bug reports and measurements can say
+fn window_title(status: &TitleStatus) -> String {
+    let (width, height) = status.window;
+    let (display_width, display_height) = status.display;
+    let scale = match status.scaling {
+        Scaling::Integer => format!("{}x", integer_scale((width, height), status.display)),
+        Scaling::AspectRatioStretch => {
+            format!("{:.2}x", (width as f32 / display_width as f32).min(height as f32 / display_height as f32))
+        }
+    };
+    let fps = match status.fps_limit {
+        Some(limit) => format!("{:.0}/{} fps", status.fps, limit),
+        None => format!("{:.0} fps uncapped", status.fps),
+    };
+    let mut title = format!("Precursor [{}] ({}, {}, frame {}", status.instance, scale, fps, status.frames);
+    if status.recording {
+        title.push_str(", REC");
+    }
+    if status.lcd_artifacts {
+        title.push_str(", LCD sim");
+    }
+    title.push(')');
+    if let Some(readout) = status.inspector {
+        title.push_str(" | ");
+        title.push_str(readout);
+    }
+    if !status.note.is_empty() {
+        title.push_str(" - ");
+        title.push_str(status.note);
+    }
+    title
+}
+
+/// The keymap overlay's lines of text
+fn keymap_overlay_text(keymap: &HostKeymap) -> Vec<String> {
+    let mut text = vec!["HOST > PRECURSOR".to_string()];
+    text.extend(keymap.describe());
+    text
+}
+
+/// Reads a frame rate limit as `XOUS_FPS` gives it: a number of frames per second, or
+/// `uncapped` or 0 for none. Returns `None` if it's neither.
+fn parse_fps_limit(fps: &str) -> Option<Option<u32>> {
+    match fps.trim() {
+        "uncapped" | "0" => Some(None),
+        fps => fps.parse::<u32>().ok().map(Some),
+    }
+}
+
+/// Reads the mode `XOUS_DISPLAY` gives: `window`, `headless` or `auto`. Returns `None` if it's
+/// none of them.
+fn parse_display_mode(mode: &str) -> Option<DisplayMode> {
+    match mode.trim().to_ascii_lowercase().as_str() {
+        "window" => Some(DisplayMode::Window),
+        "headless" => Some(DisplayMode::Headless),
+        "auto" => Some(DisplayMode::Auto),
+        _ => None,
+    }
+}
+
+/// The display mode `XOUS_DISPLAY` asks for, or failing that, headless if `XOUS_HEADLESS=1` is
+/// set, as it used to be asked for
+fn display_mode() -> DisplayMode {
+    match std::env::var("XOUS_DISPLAY") {
+        Ok(mode) => parse_display_mode(&mode).unwrap_or_else(|| {
+            log::warn!("GFX|hosted: XOUS_DISPLAY={} isn't window, headless or auto; using auto", mode);
+            DisplayMode::Auto
+        }),
+        Err(_) if std::env::var("XOUS_HEADLESS").map_or(false, |v| v == "1") => DisplayMode::Headless,
+        Err(_) => DisplayMode::default(),
+    }
+}
+
+/// Reads the degrees `XOUS_ROTATION` gives to turn the display clockwise. Returns `None` unless
+/// it's a quarter turn or so many.
+pub(super) fn parse_rotation(degrees: &str) -> Option<Rotation> {
+    match degrees.trim() {
+        "0" => Some(Rotation::Rotate0),
+        "90" => Some(Rotation::Rotate90),
+        "180" => Some(Rotation::Rotate180),
+        "270" => Some(Rotation::Rotate270),
+        _ => None,
+    }
+}
+
+/// Reads the key `XOUS_QUIT_KEY` names, or `none` for no key. Only keys the emulated keyboard
+/// and the hotkeys leave alone can quit. Returns `None` if it's none of them.
+fn parse_quit_key(name: &str) -> Option<Option<Key>> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "none" => Some(None),
+        "escape" | "esc" => Some(Some(Key::Escape)),
+        "pause" => Some(Some(Key::Pause)),
+        "scrolllock" => Some(Some(Key::ScrollLock)),
+        "insert" => Some(Some(Key::Insert)),
+        "end" => Some(Some(Key::End)),
+        _ => None,
+    }
+}
+
+/// The next of `FPS_STEPS` from `fps_limit`, faster or slower, staying put at either end. A
+/// limit between the steps goes to the nearest step in that direction.
+fn step_fps_limit(fps_limit: Option<u32>, faster: bool) -> Option<u32> {
+    let rate = |limit: Option<u32>| limit.unwrap_or(u32::MAX);
+    let current = rate(fps_limit);
+    let step = if faster {
+        FPS_STEPS.iter().find(|&&s| rate(s) > current)
+    } else {
+        FPS_STEPS.iter().rev().find(|&&s| rate(s) < current)
+    };
+    step.copied().unwrap_or(fps_limit)
+}
+
+/// Name, less its extension, of a `kind` of capture, of frame `frame` of the display of emulator
+/// `instance`, taken at `now`
+fn capture_name(kind: &str, instance: &str, frame: u64, now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    // the instance is whatever XOUS_INSTANCE was set to: only what's safe in a file name is kept
+    let instance: Vec<&str> = instance.split(|c: char| !c.is_ascii_alphanumeric()).filter(|part| !part.is_empty()).collect();
+    format!("{}-{}-frame{}-{}", kind, instance.join("-"), frame, since_epoch.as_secs())
+}
+/// `name` with `extension` in `dir`, or if there's something there already, the first of
+/// `name-2`, `name-3` and on that's free, so captures taken in the same second don't overwrite
+/// each other
+fn free_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", name, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", name, n, extension));
+        n += 1;
+    }
+    path
+}
+
+/// Packs the rows of the native buffer, `width` pixels wide, into 1bpp, leftmost pixel in the top
+/// bit, as PNG has them. Pixels in the colour `light` are 1, and anything else is taken as dark.
+fn native_to_png_rows(native: &[u32], width: usize, light: u32) -> Vec<u8> {
+    native
+        .chunks(width)
+        .flat_map(|row| row.chunks(8))
+        .map(|pixels| {
+            pixels.iter().enumerate().fold(0u8, |byte, (i, &p)| {
+                if p == light { byte | (0x80 >> i) } else { byte }
+            })
+        })
+        .collect()
+}
+
+/// Encodes the native buffer, `size` in size and rendered in `palette`, as a 1bpp indexed PNG
+/// with the same colours, noting the backlight `brightness` it was shown at and whether it was
+/// shown through the `greyscale` preview in text chunks
+fn write_png<W: Write>(w: W, native: &[u32], (width, height): (usize, usize), palette: Palette, brightness: u8, greyscale: bool) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::One);
+    let colours: Vec<u8> = [palette.dark(), palette.light()].iter()
+        .flat_map(|c| c.to_be_bytes()[1..].to_vec())
+        .collect();
+    encoder.set_palette(colours);
+    let to_io = |e: png::EncodingError| std::io::Error::new(std::io::ErrorKind::Other, e);
+    encoder.add_text_chunk("Brightness".to_string(), format!("{}/{}", brightness, MAX_BRIGHTNESS)).map_err(to_io)?;
+    encoder.add_text_chunk("Greyscale preview".to_string(), if greyscale { "on" } else { "off" }.to_string()).map_err(to_io)?;
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    writer.write_image_data(&native_to_png_rows(native, width, palette.light())).map_err(to_io)
+}
+
+/// Smooths `sharp`, a `width` pixels wide buffer in the light and dark colours of `palette`, into
+/// `native`, each pixel the average of the 2x2 pixels from it to the right and down. Past the
+/// right and bottom edges, the edge pixels count again.
+fn box_filter(native: &mut [u32], sharp: &[u32], width: usize, palette: Palette) {
+    let greys: Vec<u32> = (0..=4).map(|light| palette.grey(light)).collect();
+    let light = palette.light();
+    let height = sharp.len() / width;
+    for (y, row) in native.chunks_mut(width).enumerate() {
+        let (above, below) = (&sharp[y * width..][..width], &sharp[(y + 1).min(height - 1) * width..][..width]);
+        for (x, dest) in row.iter_mut().enumerate() {
+            let right = (x + 1).min(width - 1);
+            let lit = [above[x], above[right], below[x], below[right]].iter().filter(|&&p| p == light).count();
+            *dest = greys[lit];
+        }
+    }
+}
+
+/// Outlines the edge of `native`, a `width` pixels wide buffer, in `colour`
+fn draw_outline(native: &mut [u32], width: usize, colour: u32) {
+    let last_row = native.len() / width - 1;
+    for (y, row) in native.chunks_mut(width).enumerate() {
+        if y == 0 || y == last_row {
+            row.iter_mut().for_each(|p| *p = colour);
+        } else {
+            row[0] = colour;
+            row[width - 1] = colour;
+        }
+    }
+}
+
+/// Returns the first refresh after `now`, on the grid of refreshes every `period` from `last`.
+fn next_vsync(last: Instant, now: Instant, period: Duration) -> Instant {
+    let frames = now.saturating_duration_since(last).as_micros() / period.as_micros() + 1;
+    last + period * frames as u32
+}
+
+/// Expands the lines of the 1bpp emulated framebuffer that are marked dirty into the native
+/// 32bpp window buffer, turned by `rotation`; the other lines are left as they were. Pixels
+/// outside `viewport` are left as background. Returns the number of lines whose pixels came out
+/// different, which is often none: a full refresh marks every line dirty whether it was drawn to
+/// or not.
+fn emulated_to_native(native: &mut [u32], emulated: &[u32], devboot: bool, dim: Option<DitherPattern>, viewport: Option<Rectangle>, lut: &PixelLut, rotation: Rotation) -> usize {
+    let width = WIDTH as usize;
+    let mut changed_lines = 0;
+    for (row, src_row) in emulated.chunks(WIDTH_WORDS as _).enumerate() {
+        if src_row[WIDTH_WORDS - 1] & DIRTY_BITS == 0 {
+            continue;
+        }
+        // plain lines go a byte at a time; anything drawn over them, or turned, a pixel at a time
+        let plain = rotation == Rotation::Rotate0 && viewport.is_none() && dim.is_none() && !(devboot && row == DEVBOOT_LINE);
+        let changed = if plain {
+            lut.convert_row(&mut native[row * width..(row + 1) * width], src_row)
+        } else {
+            row_to_native(native, src_row, row, devboot, dim, viewport, lut, rotation)
+        };
+        if changed {
+            changed_lines += 1;
+        }
+    }
+    changed_lines
+}
+
+/// Renders line `row` of the framebuffer, `src_row`, into `native` a pixel at a time, with
+/// everything drawn over it, in the palette and bit order of `lut`, and returns whether that
+/// changed anything
+fn row_to_native(native: &mut [u32], src_row: &[u32], row: usize, devboot: bool, dim: Option<DitherPattern>, viewport: Option<Rectangle>, lut: &PixelLut, rotation: Rotation) -> bool {
+    let (palette, order) = (lut.palette, lut.order);
+    let shown_width = rotation.shown_size().0;
+    let mut changed = false;
+    for (word, src_cell) in src_row.iter().enumerate() {
+        // the dirty bits aren't pixels, whichever end of the word the pixels start from
+        let src_cell = if word == WIDTH_WORDS - 1 { src_cell & !DIRTY_BITS } else { *src_cell };
+        for bit in 0..(WIDTH as usize - word * 32).min(32) {
+            let (shown_x, shown_y) = rotation.turn(word * 32 + bit, row);
+            let dest = &mut native[shown_y * shown_width + shown_x];
+            let (x, y) = ((word * 32 + bit) as i16, row as i16);
+            let colour = if viewport.map_or(false, |v| x < v.tl.x || x > v.br.x || y < v.tl.y || y > v.br.y) {
+                palette.light()
+            } else if devboot && ((bit >> 1) % 2) == 0 && (row == DEVBOOT_LINE) {
+                // try to render the devboot defile somewhat accurately
+                palette.dark()
+            } else if dim.map_or(false, |d| d.masks(word * 32 + bit, row)) {
+                palette.dark()
+            } else if src_cell & (1 << order.bit(bit)) != 0 {
+                palette.light()
+            } else {
+                palette.dark()
+            };
+            changed |= *dest != colour;
+            *dest = colour;
+        }
+    }
+    changed
+}
+
+/// The native pixels each byte of a framebuffer word renders as, left first, in one palette and
+/// bit order, so that plain lines convert a byte at a time rather than a bit
+#[derive(Debug, Clone)]
+struct PixelLut {
+    palette: Palette,
+    order: BitOrder,
+    runs: Vec<[u32; 8]>,
+}
+impl PixelLut {
+    fn new(palette: Palette, order: BitOrder) -> PixelLut {
+        let runs = (0..256)
+            .map(|byte| {
+                let mut run = [palette.dark(); 8];
+                for (x, pixel) in run.iter_mut().enumerate() {
+                    // pixel x of a byte sits where pixel x of a word would, within its byte
+                    if byte & (1 << (order.bit(x) % 8)) != 0 {
+                        *pixel = palette.light();
+                    }
+                }
+                run
+            })
+            .collect();
+        PixelLut { palette, order, runs }
+    }
+    /// whether this is the table for `palette` and `order`
+    fn is_for(&self, palette: Palette, order: BitOrder) -> bool {
+        self.palette == palette && self.order == order
+    }
+    /// Renders the framebuffer line `src_row` into the native line `dest`, as is, and returns
+    /// whether that changed anything
+    fn convert_row(&self, dest: &mut [u32], src_row: &[u32]) -> bool {
+        let mut changed = false;
+        for (group, dest) in dest.chunks_mut(8).enumerate() {
+            let word = group / 4;
+            // the dirty bits aren't pixels, whichever end of the word the pixels start from
+            let src = if word == WIDTH_WORDS - 1 { src_row[word] & !DIRTY_BITS } else { src_row[word] };
+            let byte = (src >> (self.order.bit(group % 4 * 8) / 8 * 8)) & 0xFF;
+            let run = &self.runs[byte as usize][..dest.len()];
+            if dest != run {
+                dest.copy_from_slice(run);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Copies the frame `bmp` into `emulated`, marking dirty only the lines whose pixels it
+/// changes, and returns the number of them. Lines already marked dirty stay that way.
+fn blit(emulated: &mut [u32], bmp: &[u32]) -> usize {
+    let mut changed_lines = 0;
+    for (line, src) in emulated.chunks_mut(WIDTH_WORDS).zip(bmp.chunks(WIDTH_WORDS)) {
+        let dirty = line[WIDTH_WORDS - 1] & DIRTY_BITS;
+        let mut changed = false;
+        for (word, (dest, &src)) in line.iter_mut().zip(src).enumerate() {
+            let src = if word == WIDTH_WORDS - 1 { (src & !DIRTY_BITS) | dirty } else { src };
+            changed |= *dest != src;
+            *dest = src;
+        }
+        if changed {
+            line[WIDTH_WORDS - 1] |= 0x1_0000;
+            changed_lines += 1;
+        }
+    }
+    changed_lines
+}
+
+impl minifb::InputCallback for KeyEventQueue {
+    fn add_char(&mut self, uni_char: u32) {
+        match char::from_u32(uni_char) {
+            Some(c) if self.focused.get() => self.events.borrow_mut().push(HostKeyEvent::Char(c)),
+            _ => {}
+        }
+    }
+
+    fn set_key_state(&mut self, key: Key, state: bool) {
+        let action = if !state {
+            self.down.remove(&key);
+            KeyAction::Release
+        } else if self.down.insert(key) {
+            KeyAction::Press
+        } else {
+            KeyAction::Repeat
+        };
+        // releases are still queued while unfocused, so shift isn't left held down
+        if action == KeyAction::Release || self.focused.get() {
+            log::debug!("GFX|hosted: key {:?} {:?}", key, action);
+            self.events.borrow_mut().push(HostKeyEvent::Key(key, action));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dim_overlay() {
+        let emulated = [0xFFFF_FFFFu32; FB_SIZE]; // all pixels lit
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert!(native.iter().all(|&p| p == DARK_COLOUR));
+
+        emulated_to_native(&mut native, &emulated, false, Some(DitherPattern::Dim50), None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!(native[0], LIGHT_COLOUR);
+        assert_eq!(native[1], DARK_COLOUR);
+        assert_eq!(native[WIDTH as usize], DARK_COLOUR);
+        assert_eq!(native[WIDTH as usize + 1], LIGHT_COLOUR);
+        assert_eq!(native.iter().filter(|&&p| p == LIGHT_COLOUR).count(), native.len() / 2);
+
+        emulated_to_native(&mut native, &emulated, false, Some(DitherPattern::Dim25), None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!(native[0], LIGHT_COLOUR);
+        assert_eq!(native[1], DARK_COLOUR);
+        assert_eq!(native[WIDTH as usize], DARK_COLOUR);
+        assert_eq!(native[2 * WIDTH as usize + 2], LIGHT_COLOUR);
+        assert_eq!(native.iter().filter(|&&p| p == LIGHT_COLOUR).count(), native.len() / 4);
+
+        // the overlay never touches the logical framebuffer
+        assert!(emulated.iter().all(|&w| w == 0xFFFF_FFFF));
+    }
+
+    #[test]
+    fn test_bit_order() {
+        // first word of the first line: bits 0 and 1 set
+        let mut emulated = [0u32; FB_SIZE];
+        mark_all_dirty(&mut emulated);
+        emulated[0] = 0b11;
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let lit = |native: &[u32]| -> Vec<usize> {
+            native.iter().enumerate().filter(|(_, p)| **p == DARK_COLOUR).map(|(i, _)| i).collect()
+        };
+
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!(lit(&native), vec![0, 1]);
+
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Msb), Rotation::Rotate0);
+        assert_eq!(lit(&native), vec![30, 31]);
+
+        // the dither mask follows screen position, not bit position
+        emulated[0] = 1 << 31;
+        emulated_to_native(&mut native, &emulated, false, Some(DitherPattern::Dim50), None, &PixelLut::new(Palette::default(), BitOrder::Msb), Rotation::Rotate0);
+        assert_eq!(native[0], LIGHT_COLOUR);
+        emulated[0] = 1 << 30;
+        emulated_to_native(&mut native, &emulated, false, Some(DitherPattern::Dim50), None, &PixelLut::new(Palette::default(), BitOrder::Msb), Rotation::Rotate0);
+        assert_eq!(native[1], DARK_COLOUR);
+    }
+
+    #[test]
+    fn test_viewport() {
+        let mut emulated = [0u32; FB_SIZE]; // all pixels unlit
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let viewport = Rectangle::new_coords(10, 20, 41, 29);
+        let outside = |native: &[u32]| -> Vec<u32> {
+            native.iter().enumerate()
+                .filter(|(i, _)| {
+                    let (x, y) = ((i % WIDTH as usize) as i16, (i / WIDTH as usize) as i16);
+                    x < 10 || x > 41 || y < 20 || y > 29
+                })
+                .map(|(_, p)| *p)
+                .collect()
+        };
+
+        // the exterior is background, whatever the framebuffer holds, and with devboot and
+        // dimming on
+        for fill in [0u32, 0xFFFF_FFFF, 0xA5A5_5A5A].iter() {
+            emulated.iter_mut().for_each(|w| *w = *fill);
+            mark_all_dirty(&mut emulated);
+            emulated_to_native(&mut native, &emulated, true, Some(DitherPattern::Dim50), Some(viewport), &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+            assert!(outside(&native).iter().all(|&p| p == DARK_COLOUR));
+        }
+        // the interior is rendered as usual, up to and including the corners
+        emulated.iter_mut().for_each(|w| *w = 0);
+        mark_all_dirty(&mut emulated);
+        emulated_to_native(&mut native, &emulated, false, None, Some(viewport), &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!(native[20 * WIDTH as usize + 10], LIGHT_COLOUR);
+        assert_eq!(native[29 * WIDTH as usize + 41], LIGHT_COLOUR);
+        assert_eq!(native[29 * WIDTH as usize + 42], DARK_COLOUR);
+        assert_eq!(native.iter().filter(|&&p| p == LIGHT_COLOUR).count(), 32 * 10);
+
+        // and with no viewport, the whole screen is rendered again
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+        assert!(native.iter().all(|&p| p == LIGHT_COLOUR));
+    }
+
+    #[test]
+    fn test_refresh_policy() {
+        let mut emulated = [0u32; FB_SIZE];
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let mut refresh = RefreshSchedule { policy: RefreshPolicy::FullEvery(3), partials: 0 };
+        let line = |native: &[u32], y: usize| native[y * WIDTH as usize];
+        // draws to line 5 only, then redraws the way `XousDisplay::redraw()` does
+        let mut redraw = |emulated: &mut [u32; FB_SIZE], native: &mut [u32], fill: u32| -> bool {
+            emulated.iter_mut().for_each(|w| *w = fill);
+            clear_dirty(emulated);
+            emulated[5 * WIDTH_WORDS + WIDTH_WORDS - 1] |= 0x1_0000;
+            let full = refresh.next_is_full();
+            if full {
+                mark_all_dirty(emulated);
+            }
+            emulated_to_native(native, emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+            clear_dirty(emulated);
+            full
+        };
+
+        // every third redraw renders the whole display, whatever was drawn to
+        let fills = [0xFFFF_FFFFu32, 0, 0xFFFF_FFFF, 0, 0xFFFF_FFFF, 0];
+        let full: Vec<bool> = fills.iter().map(|&fill| redraw(&mut emulated, &mut native, fill)).collect();
+        assert_eq!(full, vec![false, false, true, false, false, true]);
+        // the damaged line is always rendered...
+        assert_eq!(line(&native, 5), LIGHT_COLOUR);
+        // ...but the rest of the display is only brought up to date by a full redraw
+        redraw(&mut emulated, &mut native, 0xFFFF_FFFF);
+        assert_eq!(line(&native, 5), DARK_COLOUR);
+        assert_eq!(line(&native, 100), LIGHT_COLOUR);
+        assert!(emulated.chunks(WIDTH_WORDS).all(|l| l[WIDTH_WORDS - 1] & DIRTY_BITS == 0));
+
+        let mut refresh = RefreshSchedule::default();
+        assert!((0..4).all(|_| refresh.next_is_full()));
+        let mut refresh = RefreshSchedule { policy: RefreshPolicy::Partial, partials: 0 };
+        assert!((0..4).all(|_| !refresh.next_is_full()));
+        let mut refresh = RefreshSchedule { policy: RefreshPolicy::FullEvery(1), partials: 0 };
+        assert!((0..4).all(|_| refresh.next_is_full()));
+    }
+
+    #[test]
+    fn test_skip_unchanged() {
+        let mut emulated = [0u32; FB_SIZE];
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let render = |emulated: &mut [u32; FB_SIZE], native: &mut [u32]| {
+            let changed = emulated_to_native(native, emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+            clear_dirty(emulated);
+            changed
+        };
+        mark_all_dirty(&mut emulated);
+        assert_eq!(render(&mut emulated, &mut native), HEIGHT as usize);
+        // a full refresh of an unchanged frame changes nothing on screen
+        mark_all_dirty(&mut emulated);
+        assert_eq!(render(&mut emulated, &mut native), 0);
+
+        // blitting the same frame again dirties nothing; a different line dirties just that line
+        let mut frame = emulated;
+        assert_eq!(blit(&mut emulated, &frame), 0);
+        frame[3 * WIDTH_WORDS + 2] = 0x10;
+        // the incoming dirty bits don't count as a difference
+        frame[7 * WIDTH_WORDS + WIDTH_WORDS - 1] |= 0x1_0000;
+        assert_eq!(blit(&mut emulated, &frame), 1);
+        assert_eq!(emulated[3 * WIDTH_WORDS + WIDTH_WORDS - 1] & DIRTY_BITS, 0x1_0000);
+        assert_eq!(emulated[7 * WIDTH_WORDS + WIDTH_WORDS - 1] & DIRTY_BITS, 0);
+        assert_eq!(render(&mut emulated, &mut native), 1);
+        assert_eq!(native[3 * WIDTH as usize + 68], DARK_COLOUR);
+    }
+
+    #[test]
+    fn test_screenshot() {
+        let mut emulated = [0u32; FB_SIZE];
+        mark_all_dirty(&mut emulated);
+        // a lit pixel at each end of the first line, and the start of the last
+        emulated[0] = 1;
+        emulated[WIDTH_WORDS - 1] |= 1 << 15;
+        emulated[(HEIGHT as usize - 1) * WIDTH_WORDS] = 1;
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), Rotation::Rotate0);
+
+        let mut png_bytes = Vec::new();
+        write_png(&mut png_bytes, &native, (WIDTH as usize, HEIGHT as usize), Palette::default(), MAX_BRIGHTNESS, false).unwrap();
+        let decoder = png::Decoder::new(&png_bytes[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        // the emulated resolution, in the two theme colours
+        assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+        assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Indexed, png::BitDepth::One));
+        assert_eq!(reader.info().palette.as_deref(), Some(&[0x1B, 0x1B, 0x19, 0xB5, 0xB5, 0xAD][..]));
+        // and the pixels exactly
+        let row_bytes = WIDTH as usize / 8;
+        assert_eq!(pixels.len(), row_bytes * HEIGHT as usize);
+        assert_eq!(pixels[0], 0x80);
+        assert_eq!(pixels[row_bytes - 1], 0x01);
+        assert_eq!(pixels[(HEIGHT as usize - 1) * row_bytes], 0x80);
+        assert_eq!(pixels.iter().map(|b| b.count_ones()).sum::<u32>(), 3);
+
+        assert_eq!(capture_name("screenshot", "pid 7, surface 1", 42, UNIX_EPOCH + Duration::from_millis(1_650_000_000_042)),
+            "screenshot-pid-7-surface-1-frame42-1650000000");
+    }
+
+    #[test]
+    fn test_power() {
+        let mut display = XousDisplay::headless();
+        let mut frame = [0x0F0F_0F0F; FB_SIZE];
+        display.blit_screen(&frame);
+        display.redraw().unwrap();
+        let shown = display.native_buffer.clone();
+
+        // suspended, the window's blanked, and blits and redraws are refused
+        display.suspend();
+        assert!(!display.is_powered() && display.native_buffer != shown);
+        display.blit_screen(&[0; FB_SIZE]);
+        assert!(matches!(display.redraw(), Err(DisplayError::PoweredOff)));
+        // powered on again, it still takes setting up
+        display.power(true).unwrap();
+        assert!(matches!(display.redraw(), Err(DisplayError::PoweredOff)));
+        display.reinit();
+        display.redraw().unwrap();
+        assert!(display.native_buffer == shown);
+        clear_dirty(&mut frame);
+        assert_eq!(display.copy_framebuffer()[..], frame[..]);
+
+        // and resuming does all of that, drawing done meanwhile coming back with the frame
+        display.suspend();
+        display.native_buffer()[0] = 0xFFFF_FFFF;
+        display.resume();
+        frame[0] = 0xFFFF_FFFF;
+        assert_eq!(display.copy_framebuffer()[..], frame[..]);
+        let mut expected = XousDisplay::headless();
+        expected.blit_screen(&frame);
+        expected.redraw().unwrap();
+        assert!(display.is_powered() && display.native_buffer == expected.native_buffer);
+    }
+
+    #[test]
+    fn test_capture_dir() {
+        let dir = std::env::temp_dir().join(format!("xous-captures-test-{}", std::process::id()));
+        let mut display = XousDisplay::headless();
+        display.set_capture_dir(&dir.join("nested"));
+        // the directory's made for the first, and a second doesn't overwrite it
+        let first = display.screenshot().unwrap();
+        let second = display.screenshot().unwrap();
+        assert_eq!(first.parent(), Some(dir.join("nested").as_path()));
+        assert!(first.exists() && second.exists() && first != second);
+        // taken the same second, the second's numbered
+        std::fs::write(dir.join("shot.png"), b"").unwrap();
+        assert_eq!(free_path(&dir, "shot", "png"), dir.join("shot-2.png"));
+        std::fs::write(dir.join("shot-2.png"), b"").unwrap();
+        assert_eq!(free_path(&dir, "shot", "png"), dir.join("shot-3.png"));
+
+        // a directory that can't be made falls back to the working directory
+        std::fs::write(dir.join("file"), b"").unwrap();
+        display.set_capture_dir(&dir.join("file").join("captures"));
+        assert_eq!(display.capture_path("screenshot", "png").parent(), Some(Path::new(".")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_palette() {
+        let mut emulated = [0u32; FB_SIZE];
+        mark_all_dirty(&mut emulated);
+        emulated[0] = 1; // one light pixel, on a dark screen
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let palette = Palette { fg: 0x000000, bg: 0xFFFFFF, inverted: false };
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(palette, BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!((native[0], native[1]), (0xFFFFFF, 0x000000));
+
+        // inverted, the dim overlay and the viewport border swap with the pixels
+        let inverted = Palette { inverted: true, ..palette };
+        mark_all_dirty(&mut emulated);
+        emulated_to_native(&mut native, &emulated, false, Some(DitherPattern::Dim50), Some(Rectangle::new_coords(0, 0, 10, 10)), &PixelLut::new(inverted, BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!((native[0], native[1], native[11]), (0xFFFFFF, 0xFFFFFF, 0x000000));
+        mark_all_dirty(&mut emulated);
+        emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(inverted, BitOrder::Lsb), Rotation::Rotate0);
+        assert_eq!((native[0], native[1]), (0x000000, 0xFFFFFF));
+
+        // and a screenshot comes out as it looked
+        let mut png_bytes = Vec::new();
+        write_png(&mut png_bytes, &native, (WIDTH as usize, HEIGHT as usize), inverted, MAX_BRIGHTNESS, true).unwrap();
+        let mut reader = png::Decoder::new(&png_bytes[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(reader.info().palette.as_deref(), Some(&[0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00][..]));
+        assert_eq!(pixels[0], 0x80);
+        let text: Vec<_> = reader.info().uncompressed_latin1_text.iter().map(|t| (t.keyword.as_str(), t.text.as_str())).collect();
+        assert_eq!(text, vec![("Brightness", "31/31"), ("Greyscale preview", "on")]);
+
+        // the backlight dims both colours toward black, to nothing when it's off
+        let palette = Palette { fg: 0x1F3E5D, bg: 0xF8F8F8, inverted: true };
+        assert_eq!(palette.dimmed(MAX_BRIGHTNESS), palette);
+        assert_eq!(palette.dimmed(10), Palette { fg: 0x0A141E, bg: 0x505050, inverted: true });
+        assert_eq!(palette.dimmed(0), Palette { fg: 0, bg: 0, inverted: true });
+        // which leaves just an outline
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        draw_outline(&mut native, WIDTH as usize, BORDER_COLOUR);
+        assert_eq!((native[0], native[WIDTH as usize - 1], native[native.len() - 1]), (BORDER_COLOUR, BORDER_COLOUR, BORDER_COLOUR));
+        assert_eq!((native[WIDTH as usize + 1], native[2 * WIDTH as usize - 2]), (0, 0));
+        assert_eq!(native.iter().filter(|&&p| p == BORDER_COLOUR).count(), 2 * (WIDTH + HEIGHT) as usize - 4);
+    }
+
+    #[test]
+    fn test_integer_scaling() {
+        let display = (WIDTH as usize, HEIGHT as usize);
+        assert_eq!(integer_scale((336, 536), display), 1);
+        assert_eq!(integer_scale((1100, 1200), display), 2); // the height limits it
+        assert_eq!(integer_scale((1008, 2000), display), 3);
+        assert_eq!(integer_scale((200, 200), display), 1);
+        // turned a quarter, the width does
+        assert_eq!(integer_scale((1100, 1200), Rotation::Rotate90.shown_size()), 2);
+        let status = TitleStatus {
+            instance: "pid 7",
+            scaling: Scaling::Integer,
+            window: (1100, 1200),
+            display,
+            fps: 14.6,
+            fps_limit: Some(60),
+            frames: 1234,
+            recording: false,
+            lcd_artifacts: false,
+            inspector: None,
+            note: "",
+        };
+        assert_eq!(window_title(&status), "Precursor [pid 7] (2x, 15/60 fps, frame 1234)");
+        assert_eq!(window_title(&TitleStatus {
+            instance: "left",
+            scaling: Scaling::AspectRatioStretch,
+            fps_limit: None,
+            recording: true,
+            lcd_artifacts: true,
+            note: "shellchat",
+            ..status
+        }), "Precursor [left] (2.24x, 15 fps uncapped, frame 1234, REC, LCD sim) - shellchat");
+        assert_eq!(window_title(&TitleStatus {
+            inspector: Some("x=40, y=2, bit=1, word offset=23"),
+            note: "shellchat",
+            ..status
+        }), "Precursor [pid 7] (2x, 15/60 fps, frame 1234) | x=40, y=2, bit=1, word offset=23 - shellchat");
+
+        let mut native = vec![LIGHT_COLOUR; WIDTH as usize * HEIGHT as usize];
+        native[0] = DARK_COLOUR;
+        native[WIDTH as usize * HEIGHT as usize - 1] = DARK_COLOUR;
+        // 2x, with a border of 1 on the left and right and 3 on the top and bottom
+        let window = (2 * WIDTH as usize + 2, 2 * HEIGHT as usize + 6);
+        let mut scaled = Vec::new();
+        scale_native(&native, display, &mut scaled, window, 2);
+        assert_eq!(scaled.len(), window.0 * window.1);
+        let at = |x: usize, y: usize| scaled[y * window.0 + x];
+        assert_eq!((at(0, 0), at(1, 2), at(0, 3)), (BORDER_COLOUR, BORDER_COLOUR, BORDER_COLOUR));
+        // the first pixel is a 2x2 block
+        assert_eq!((at(1, 3), at(2, 3), at(1, 4), at(2, 4)), (DARK_COLOUR, DARK_COLOUR, DARK_COLOUR, DARK_COLOUR));
+        assert_eq!((at(3, 3), at(1, 5)), (LIGHT_COLOUR, LIGHT_COLOUR));
+        assert_eq!((at(window.0 - 3, window.1 - 5), at(window.0 - 2, window.1 - 4)), (DARK_COLOUR, DARK_COLOUR));
+        assert_eq!(at(window.0 - 4, window.1 - 4), LIGHT_COLOUR);
+        assert_eq!((at(window.0 - 1, window.1 - 4), at(window.0 - 2, window.1 - 3)), (BORDER_COLOUR, BORDER_COLOUR));
+        assert_eq!(scaled.iter().filter(|&&p| p == DARK_COLOUR).count(), 8);
+
+        // too small a window shows the middle of the display
+        scale_native(&native, display, &mut scaled, (WIDTH as usize - 2, HEIGHT as usize - 2), 1);
+        assert_eq!(scaled[0], native[WIDTH as usize + 1]);
+        assert!(!scaled.contains(&BORDER_COLOUR));
+    }
+
+    #[test]
+    fn test_double_buffering() {
+        // frames of a single word repeated, that a torn frame would mix
+        const FRAMES: u32 = 1000;
+        let mut display = XousDisplay::headless();
+        let back_buffer = display.back_buffer();
+        let blitter = std::thread::spawn(move || {
+            for n in 1..=FRAMES {
+                back_buffer.blit(&[n; FB_SIZE]);
+            }
+        });
+        let light = display.shown_palette().light();
+        let mut last = 0;
+        let mut check = |display: &mut XousDisplay| {
+            display.redraw().unwrap();
+            // the frame converted is whole, and no older than the last one; the devboot line's
+            // drawn over, so it's left out
+            let n = display.native_buffer[..32].iter().rev().fold(0, |n, &p| (n << 1) | (p == light) as u32);
+            for (y, row) in display.native_buffer.chunks(WIDTH as usize).enumerate().filter(|&(y, _)| y != 7) {
+                assert!(row.iter().enumerate().all(|(x, &p)| (p == light) == (n & (1 << (x % 32)) != 0)), "line {} isn't frame {}", y, n);
+            }
+            assert!(n >= last, "frames out of order");
+            last = n;
+            // as is the frame read back, which may be newer than the one converted
+            let fb = display.copy_framebuffer();
+            assert!(fb[0] >= n && fb.iter().all(|&word| word == fb[0]), "torn frame");
+        };
+        while !blitter.is_finished() {
+            check(&mut display);
+        }
+        blitter.join().unwrap();
+        check(&mut display);
+        assert_eq!(last, FRAMES);
+        assert!(display.frames_dropped() < FRAMES as u64);
+    }
+
+    /// A framebuffer of noise from `seed`, every line dirty
+    fn random_frame(seed: u32) -> [u32; FB_SIZE] {
+        let mut state = seed | 1;
+        let mut frame = [0; FB_SIZE];
+        for word in frame.iter_mut() {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *word = state;
+        }
+        mark_all_dirty(&mut frame);
+        frame
+    }
+
+    /// Renders every line of `emulated` a pixel at a time, as lines were before the table
+    fn naive_to_native(native: &mut [u32], emulated: &[u32], devboot: bool, palette: Palette, order: BitOrder) {
+        let lut = PixelLut::new(palette, order);
+        for (row, src_row) in emulated.chunks(WIDTH_WORDS).enumerate() {
+            row_to_native(native, src_row, row, devboot, None, None, &lut, Rotation::Rotate0);
+        }
+    }
+
+    #[test]
+    fn test_pixel_lut() {
+        let palette = Palette { fg: 0x102030, bg: 0xC0D0E0, inverted: false };
+        for (seed, order, palette, devboot) in [
+            (1, BitOrder::Lsb, palette, false),
+            (2, BitOrder::Msb, palette, true),
+            (3, BitOrder::Lsb, Palette { inverted: true, ..palette }, true),
+            (4, BitOrder::Msb, Palette::default(), false),
+        ] {
+            let emulated = random_frame(seed);
+            let (mut naive, mut native) = (vec![0u32; WIDTH as usize * HEIGHT as usize], vec![0u32; WIDTH as usize * HEIGHT as usize]);
+            naive_to_native(&mut naive, &emulated, devboot, palette, order);
+            let lut = PixelLut::new(palette, order);
+            assert_eq!(emulated_to_native(&mut native, &emulated, devboot, None, None, &lut, Rotation::Rotate0), HEIGHT as usize);
+            assert!(naive == native, "table and naive renders differ for {:?}", order);
+            // the same frame again changes nothing
+            assert_eq!(emulated_to_native(&mut native, &emulated, devboot, None, None, &lut, Rotation::Rotate0), 0);
+        }
+
+        // the display makes the table again when the palette changes, and not otherwise
+        let mut display = XousDisplay::headless();
+        display.blit_screen(&random_frame(5));
+        display.redraw().unwrap();
+        assert!(display.pixel_lut.is_for(display.shown_palette(), BitOrder::Lsb));
+        display.set_inverted(true);
+        display.redraw().unwrap();
+        assert!(display.pixel_lut.is_for(display.shown_palette(), BitOrder::Lsb));
+        let mut naive = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        naive_to_native(&mut naive, &display.copy_framebuffer(), true, display.shown_palette(), BitOrder::Lsb);
+        assert!(naive[8 * WIDTH as usize..] == display.native_buffer[8 * WIDTH as usize..]);
+    }
+
+    /// Times rendering whole frames both ways; run with `cargo test bench_ -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_emulated_to_native() {
+        const FRAMES: usize = 200;
+        // two frames in turn, so that every pixel's written every time
+        let frames = [random_frame(6), random_frame(7)];
+        let mut native = vec![0u32; WIDTH as usize * HEIGHT as usize];
+        let palette = Palette::default();
+        let lut = PixelLut::new(palette, BitOrder::Lsb);
+        let mut time = |name: &str, render: &mut dyn FnMut(&mut [u32], &[u32])| {
+            // warm up, then take the mean
+            render(&mut native, &frames[1]);
+            let start = Instant::now();
+            for i in 0..FRAMES {
+                render(&mut native, &frames[i % 2]);
+            }
+            let per_frame = start.elapsed() / FRAMES as u32;
+            println!("{:<24} time: {:>10?}/frame", name, per_frame);
+            per_frame
+        };
+        let naive = time("emulated_to_native/naive", &mut |native, emulated| naive_to_native(native, emulated, false, palette, BitOrder::Lsb));
+        let table = time("emulated_to_native/lut", &mut |native, emulated| {
+            emulated_to_native(native, emulated, false, None, None, &lut, Rotation::Rotate0);
+        });
+        println!("speedup: {:.1}x", naive.as_secs_f64() / table.as_secs_f64());
+    }
+
+    #[test]
+    fn test_display_info() {
+        // the buffers are the sizes the info gives
+        let mut display = XousDisplay::headless();
+        let info = display.info();
+        assert_eq!(info, DisplayInfo { width: 336, height: 536, words_per_line: 11, fb_size_words: 11 * 536 });
+        assert_eq!(display.copy_framebuffer().len(), info.fb_size_words);
+        assert_eq!(display.native_buffer.len(), info.width * info.height);
+        assert_eq!((display.screen_size().x as usize, display.screen_size().y as usize), (info.width, info.height));
+    }
+
+    #[test]
+    fn test_surfaces() {
+        // surfaces side by side on one thread, each with its own frame and window
+        let mut main = XousDisplay::headless();
+        let mut status = XousDisplay::headless_surface(1);
+        assert_eq!((main.surface(), status.surface()), (0, 1));
+        assert!(status.instance.ends_with(", surface 1") && !main.instance.contains("surface"));
+        assert_eq!(main.window_start_size(), (336, 536));
+        assert_eq!(status.window_start_size(), (168, 268));
+
+        main.blit_screen(&[0; FB_SIZE]);
+        status.blit_screen(&[0xFFFF; FB_SIZE]);
+        main.redraw().unwrap();
+        status.redraw().unwrap();
+        assert_eq!((main.copy_framebuffer()[0], status.copy_framebuffer()[0]), (0, 0xFFFF));
+        assert_eq!((main.native_buffer[0], status.native_buffer[0]), (main.shown_palette().dark(), status.shown_palette().light()));
+        // and neither has a window to find closed
+        assert!(main.update().is_ok() && status.update().is_ok());
+    }
+
+    #[test]
+    fn test_pixel_inspector() {
+        let mut display = XousDisplay::headless();
+        let mut frame = [0; FB_SIZE];
+        frame[20 * WIDTH_WORDS + 1] = 1 << 8;
+        display.blit_screen(&frame);
+        display.inspect(Some(Point::new(40, 20)), false);
+        assert_eq!(display.inspected(), None);
+        display.set_inspector(true);
+        display.inspect(Some(Point::new(40, 20)), false);
+        assert_eq!(display.inspected().map(|readout| readout.to_string()), Some("x=40, y=20, bit=1, word offset=221".to_string()));
+        assert!(display.title.ends_with(" | x=40, y=20, bit=1, word offset=221"));
+        // the other way round, it's another pixel that's lit
+        display.set_bit_order(BitOrder::Msb);
+        display.inspect(Some(Point::new(40, 20)), false);
+        assert_eq!(display.inspected().map(|readout| (readout.bit, readout.bit_index)), Some((false, 23)));
+        display.inspect(Some(Point::new(55, 20)), false);
+        assert_eq!(display.inspected().map(|readout| readout.bit), Some(true));
+        display.inspect(None, false);
+        assert!(display.title.ends_with(" | off the display"));
+        display.set_inspector(false);
+        assert!(!display.title.contains(" | "));
+    }
+
+    #[test]
+    fn test_magnifier() {
+        use super::super::magnifier::{MAGNIFICATION, MAGNIFIER_SIZE, REACH};
+        let mut display = XousDisplay::headless();
+        let mut frame = [0; FB_SIZE];
+        // the pixels at (5, 10) and (6, 7) set; the devboot defile is drawn over line 7
+        frame[10 * WIDTH_WORDS] = 1 << 5;
+        frame[DEVBOOT_LINE * WIDTH_WORDS] = 1 << 6;
+        display.blit_screen(&frame);
+        display.set_palette(0x123456, 0x654321);
+        display.set_brightness(MAX_BRIGHTNESS / 2);
+        display.redraw().unwrap();
+        let plain = display.native_buffer.clone();
+
+        display.set_magnifier(true);
+        display.magnifier.as_mut().unwrap().at = Some((5, 10));
+        display.redraw().unwrap();
+        // in the bottom right corner, across from the mouse, the bits as they are in black and
+        // white, whatever the palette, backlight or devboot defile
+        let (left, top) = (WIDTH as usize - MAGNIFIER_SIZE, HEIGHT as usize - MAGNIFIER_SIZE);
+        let centre = REACH * (MAGNIFICATION + 1) + 1;
+        let magnified = |display: &XousDisplay, dx: isize, dy: isize| {
+            let at = |d: isize| (centre as isize + d * (MAGNIFICATION + 1) as isize) as usize;
+            display.native_buffer[(top + at(dy)) * WIDTH as usize + left + at(dx)]
+        };
+        assert_eq!((magnified(&display, 0, 0), magnified(&display, 1, 0)), (0xFFFFFF, 0x000000));
+        assert_eq!((magnified(&display, 1, -3), magnified(&display, 2, -3)), (0xFFFFFF, 0x000000));
+        assert_eq!(display.native_buffer[..top * WIDTH as usize], plain[..top * WIDTH as usize]);
+        // and left out of screenshots, unless it's meant to be in them
+        assert!(display.screenshot_frame()[..] == plain[..]);
+        display.set_magnifier_in_screenshots(true);
+        assert!(display.screenshot_frame()[..] == display.native_buffer[..]);
+
+        // it follows the mouse, and comes off when it's turned off
+        display.magnifier.as_mut().unwrap().at = Some((300, 10));
+        display.redraw().unwrap();
+        let bottom_line = (HEIGHT as usize - 1) * WIDTH as usize;
+        assert_eq!(display.native_buffer[bottom_line + WIDTH as usize - 1], plain[bottom_line + WIDTH as usize - 1]);
+        assert!(display.native_buffer[bottom_line] != plain[bottom_line]);
+        display.set_magnifier(false);
+        display.redraw().unwrap();
+        assert!(display.native_buffer == plain);
+    }
+
+    #[test]
+    fn test_grid() {
+        let mut display = XousDisplay::headless();
+        let frame = [0xFFFF_FFFF; FB_SIZE];
+        display.blit_screen(&frame);
+        display.redraw().unwrap();
+        let plain = display.native_buffer.clone();
+        let fb = display.copy_framebuffer();
+
+        display.set_grid(Some(GridSpec { minor: 8, major: 16, margin: Some(4) }));
+        display.redraw().unwrap();
+        let at = |display: &XousDisplay, x: usize, y: usize| display.native_buffer[y * WIDTH as usize + x];
+        let was = |x: usize, y: usize| plain[y * WIDTH as usize + x];
+        assert!(at(&display, 16, 3) != was(16, 3) && at(&display, 3, 8) != was(3, 8));
+        assert!(at(&display, 4, 5) != was(4, 5));
+        assert_eq!(at(&display, 3, 3), was(3, 3));
+        // drawn over afresh each time, rather than on top of the last one
+        let gridded = display.native_buffer.clone();
+        display.redraw().unwrap();
+        assert!(display.native_buffer == gridded);
+        display.set_greyscale_preview(true);
+        display.redraw().unwrap();
+        let smoothed = display.native_buffer.clone();
+        display.redraw().unwrap();
+        assert!(display.native_buffer == smoothed);
+        display.set_greyscale_preview(false);
+        // the frame buffer never has it in
+        assert!(display.copy_framebuffer()[..] == fb[..]);
+
+        display.set_grid(None);
+        display.redraw().unwrap();
+        assert!(display.native_buffer == plain);
+    }
+
+    #[test]
+    fn test_lcd_timing() {
+        let mut display = XousDisplay::headless();
+        assert!(!display.busy());
+        let line_time = Duration::from_micros(200);
+        display.set_lcd_timing(Some(LcdTimingConfig { line_time }));
+        // frames blitted and drawn over as fast as the server can take them: each redraw sends
+        // only once the last update's done, and the blit's update, sent while one's still going,
+        // is ignored, as on the device
+        let mut frame = [0xFFFF_FFFF; FB_SIZE];
+        for i in 0..10 {
+            frame[i * 40 * WIDTH_WORDS] = i as u32;
+            display.blit_screen(&frame);
+            display.redraw().unwrap();
+            let lines = [0x5555_5555 ^ i as u32; 20 * WIDTH_WORDS];
+            display.blit_region(&lines, 100 + i, 20);
+            display.redraw().unwrap();
+        }
+        assert!(display.busy());
+        assert_eq!(display.ignored_lcd_updates(), 9);
+        // but the last frame drawn is the one the panel's left holding
+        let fb = display.copy_framebuffer();
+        assert!(display.lcd_timing.as_ref().unwrap().panel() == &fb[..]);
+        std::thread::sleep(line_time * HEIGHT as u32);
+        assert!(!display.busy());
+
+        display.set_lcd_timing(None);
+        assert!(!display.busy());
+    }
+
+    #[test]
+    fn test_mark_dirty() {
+        let mut display = XousDisplay::headless();
+        display.redraw().unwrap();
+        display.set_refresh_policy(RefreshPolicy::Partial);
+        let plain = display.native_buffer.clone();
+        // drawn into without the lines marked dirty, then a corner of two of them marked
+        for &line in &[20, 21, 300] {
+            display.native_buffer()[line * WIDTH_WORDS] = 0xFFFF_FFFF;
+            display.native_buffer()[line * WIDTH_WORDS + 5] = 0xFFFF_FFFF;
+        }
+        display.set_strict_damage(true);
+        display.mark_dirty(Rectangle::new(Point::new(0, 20), Point::new(10, 21)));
+        display.redraw().unwrap();
+        // the whole of each line marked goes out, not just the corner
+        let pixel = |display: &XousDisplay, x: usize, y: usize| display.native_buffer[y * WIDTH as usize + x];
+        for &line in &[20, 21] {
+            assert!(pixel(&display, 0, line) != plain[line * WIDTH as usize]);
+            assert!(pixel(&display, 5 * 32, line) != plain[line * WIDTH as usize + 5 * 32]);
+        }
+        assert_eq!(pixel(&display, 0, 300), plain[300 * WIDTH as usize]);
+        // and the damage was the one frame's
+        display.redraw().unwrap();
+        assert_eq!(pixel(&display, 0, 300), plain[300 * WIDTH as usize]);
+    }
+
+    #[test]
+    fn test_frame_callback() {
+        let mut display = XousDisplay::headless();
+        display.set_fps_limit(Some(30));
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let sink = frames.clone();
+        display.set_frame_callback(Some(Box::new(move |done| sink.borrow_mut().push(done))));
+        // an animation drawing a frame per refresh
+        let mut frame = [0xFFFF_FFFF; FB_SIZE];
+        for i in 0..15 {
+            frame[100 * WIDTH_WORDS] = i;
+            display.blit_screen(&frame);
+            display.redraw().unwrap();
+            display.wait_for_vsync();
+        }
+        // a redraw with nothing new in it puts up no frame, and reports the one that's up again
+        display.redraw().unwrap();
+        let frames = frames.borrow();
+        assert_eq!(frames.iter().map(|done| done.frame).collect::<Vec<u64>>(), (1..=15).chain(Some(15)).collect::<Vec<u64>>());
+        assert_eq!(frames[15], frames[14]);
+        // 14 refreshes apart at 30 FPS, give or take a refresh for scheduling
+        let took = frames[14].at_ms - frames[0].at_ms;
+        assert!((14 * 1000 / 30 - 34..=14 * 1000 / 30 + 34).contains(&took), "{} ms", took);
+    }
+
+    #[test]
+    fn test_presented_frames() {
+        let mut display = XousDisplay::headless();
+        assert_eq!((display.frame_stats().presented, display.frame_stats().last_presented), (0, None));
+        let mut frame = [0xFFFF_FFFF; FB_SIZE];
+        display.blit_screen(&frame);
+        display.redraw().unwrap();
+        let first = display.frame_stats().last_presented.unwrap();
+        assert_eq!(display.frame_stats().presented, 1);
+
+        // nothing new: skipped, or put up again for the LCD simulation or the title, it's not
+        // another frame
+        display.redraw().unwrap();
+        display.set_lcd_artifacts(Some(LcdArtifactConfig { sweep_steps: 1, ..LcdArtifactConfig::default() }));
+        display.redraw().unwrap();
+        display.set_title_note("settings");
+        display.set_lcd_artifacts(None);
+        let stats = display.frame_stats();
+        assert_eq!((stats.presented, stats.last_presented), (1, Some(first)));
+        assert_eq!(display.skipped_frames(), 1);
+
+        std::thread::sleep(Duration::from_millis(5));
+        frame[0] = 0;
+        display.blit_screen(&frame);
+        display.redraw().unwrap();
+        let stats = display.frame_stats();
+        assert_eq!(stats.presented, 2);
+        assert!(stats.last_presented.unwrap() >= first + Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_input_burst() {
+        let mut display = XousDisplay::headless();
+        display.set_fps_limit(Some(15));
+        // typing far faster than the redraws: a burst of keys comes in between each of them, as
+        // the window's callback queues them, and the input's polled in between
+        let keys = [Key::A, Key::B, Key::C, Key::D];
+        let mut typed = Vec::new();
+        for round in 0..3 {
+            for (i, &key) in keys.iter().enumerate() {
+                let c = (b'a' + i as u8) as char;
+                let burst = [HostKeyEvent::Key(key, KeyAction::Press), HostKeyEvent::Char(c), HostKeyEvent::Key(key, KeyAction::Release)];
+                display.host_keys.borrow_mut().extend_from_slice(&burst);
+                typed.extend_from_slice(&burst);
+                if i % 2 == round % 2 {
+                    display.poll_input().unwrap();
+                }
+            }
+            display.redraw().unwrap();
+            display.update().unwrap();
+            display.poll_input().unwrap();
+            display.wait_for_vsync();
+        }
+        // every one of them, in order
+        assert_eq!(display.drain_key_events(), typed);
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let mut display = XousDisplay::headless();
+        let mut frame = [0x0F0F_0F0F; FB_SIZE];
+        clear_dirty(&mut frame);
+        display.blit_screen(&frame);
+        display.set_palette(0x102030, 0xE0D0C0);
+        display.set_inverted(true);
+        display.set_rotation(Rotation::Rotate90);
+        let path = std::env::temp_dir().join(format!("xous-snapshot-test-{}.pbm", std::process::id()));
+        display.save_snapshot(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        // it's a golden image as it is
+        assert_eq!(crate::backend::testing::compare_fb(&frame, &saved), None);
+
+        // loaded over another frame, it's shown as it was
+        let mut loaded = XousDisplay::headless();
+        loaded.blit_screen(&[0; FB_SIZE]);
+        loaded.load_snapshot(&path).unwrap();
+        assert_eq!(loaded.copy_framebuffer()[..], frame[..]);
+        assert_eq!((loaded.palette, loaded.rotation()), (display.palette, Rotation::Rotate90));
+        assert!(loaded.native_buffer == display.native_buffer);
+
+        // one of another version is refused, and the display left as it was
+        std::fs::write(&path, saved.replacen("version 1", "version 0", 1)).unwrap();
+        assert_eq!(loaded.load_snapshot(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(loaded.copy_framebuffer()[..], frame[..]);
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn test_blit_region() {
+        let mut display = XousDisplay::headless();
+        display.blit_screen(&[0; FB_SIZE]);
+        display.redraw().unwrap();
+        let last = FB_LINES - 1;
+        let dirty = |display: &mut XousDisplay| -> Vec<usize> {
+            let fb = display.as_slice();
+            (0..FB_LINES).filter(|line| fb[line * WIDTH_WORDS + WIDTH_WORDS - 1] & DIRTY_BITS != 0).collect()
+        };
+        // the first and last lines, alone; only the lines given are copied. The pixels are kept
+        // clear of the dirty bits, which don't read back.
+        let mut lines = [0x5555; 2 * WIDTH_WORDS];
+        display.blit_region(&lines, 0, 1);
+        display.blit_region(&lines, last, 1);
+        assert_eq!(dirty(&mut display), vec![0, last]);
+        let fb = display.copy_framebuffer();
+        assert!(fb[..WIDTH_WORDS].iter().chain(&fb[last * WIDTH_WORDS..]).all(|&word| word == 0x5555));
+        assert!(fb[WIDTH_WORDS..last * WIDTH_WORDS].iter().all(|&word| word == 0));
+        display.redraw().unwrap();
+        assert!(dirty(&mut display).is_empty());
+
+        // a region running off the end is cut short, as is one longer than the lines it's given
+        lines[WIDTH_WORDS..].iter_mut().for_each(|word| *word = 0xFFFF);
+        display.blit_region(&lines, last, 2);
+        display.blit_region(&lines[..WIDTH_WORDS], 1, 2);
+        assert_eq!(dirty(&mut display), vec![1]);
+        let fb = display.copy_framebuffer();
+        assert!(fb[last * WIDTH_WORDS..].iter().all(|&word| word == 0x5555));
+        assert!(fb[2 * WIDTH_WORDS..3 * WIDTH_WORDS].iter().all(|&word| word == 0));
+
+        // lines the same as they were aren't dirtied
+        display.redraw().unwrap();
+        display.blit_region(&lines, 0, 2);
+        assert_eq!(dirty(&mut display), vec![1]);
+    }
+
+    #[test]
+    fn test_greyscale_preview() {
+        let palette = Palette { fg: 0x000000, bg: 0xFFFFFF, inverted: false };
+        assert_eq!((0..=4).map(|light| palette.grey(light)).collect::<Vec<_>>(), vec![0x000000, 0x3F3F3F, 0x7F7F7F, 0xBFBFBF, 0xFFFFFF]);
+        const L: u32 = 0xFFFFFF;
+        const D: u32 = 0x000000;
+        #[rustfmt::skip]
+        let sharp = [
+            L, D, D, D,
+            D, D, D, D,
+            D, D, L, L,
+        ];
+        let mut native = [0x123456; 12];
+        box_filter(&mut native, &sharp, 4, palette);
+        // the bottom right corner counts itself four times over
+        #[rustfmt::skip]
+        assert_eq!(native, [
+            0x3F3F3F, 0x000000, 0x000000, 0x000000,
+            0x000000, 0x3F3F3F, 0x7F7F7F, 0x7F7F7F,
+            0x000000, 0x7F7F7F, 0xFFFFFF, 0xFFFFFF,
+        ]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        use Rotation::{Rotate0, Rotate180, Rotate270, Rotate90};
+        let (width, height) = (WIDTH as usize, HEIGHT as usize);
+        // an L in the top left corner of the display
+        let mut emulated = [0u32; FB_SIZE];
+        for &(x, y) in [(0, 0), (0, 1), (0, 2), (1, 2)].iter() {
+            emulated[y * WIDTH_WORDS] |= 1 << x;
+        }
+        let render = |rotation| {
+            let mut native = vec![0; width * height];
+            let mut emulated = emulated;
+            mark_all_dirty(&mut emulated);
+            emulated_to_native(&mut native, &emulated, false, None, None, &PixelLut::new(Palette::default(), BitOrder::Lsb), rotation);
+            native
+        };
+        // the light pixels, row by row, in a `shown_width` wide buffer
+        let light = |native: &[u32], shown_width: usize| -> Vec<(usize, usize)> {
+            native.iter().enumerate().filter(|(_, &p)| p == DARK_COLOUR).map(|(i, _)| (i % shown_width, i / shown_width)).collect()
+        };
+        let upright = render(Rotate0);
+        assert_eq!(light(&upright, width), vec![(0, 0), (0, 1), (0, 2), (1, 2)]);
+        // a quarter turn clockwise brings it to the top right, on its back, and three quarters to
+        // the bottom left
+        let turned = render(Rotate90);
+        assert_eq!(light(&turned, height), vec![(533, 0), (534, 0), (535, 0), (533, 1)]);
+        assert!((0..height).all(|y| (0..width).all(|x| turned[x * height + height - 1 - y] == upright[y * width + x])));
+        assert_eq!(light(&render(Rotate270), height), vec![(2, 334), (0, 335), (1, 335), (2, 335)]);
+        // half a turn is the same picture back to front
+        let mut reversed = upright.clone();
+        reversed.reverse();
+        assert_eq!(render(Rotate180), reversed);
+
+        for &rotation in [Rotate0, Rotate90, Rotate180, Rotate270].iter() {
+            for &(x, y) in [(0, 0), (335, 0), (12, 500), (335, 535)].iter() {
+                let (shown_x, shown_y) = rotation.turn(x, y);
+                assert!(shown_x < rotation.shown_size().0 && shown_y < rotation.shown_size().1);
+                assert_eq!(rotation.unturn(shown_x, shown_y), (x, y));
+            }
+        }
+        assert_eq!(parse_rotation(" 270"), Some(Rotate270));
+        assert_eq!(parse_rotation("45"), None);
+    }
+
+    #[test]
+    fn test_window_to_display() {
+        use Rotation::{Rotate0, Rotate180, Rotate270, Rotate90};
+        use Scaling::{AspectRatioStretch, Integer};
+        let pixel = |x, y| Some(Point::new(x, y));
+        // pixel for pixel in a window the display's size
+        assert_eq!(window_to_display((0.0, 0.0), (336, 536), AspectRatioStretch, Rotate0), pixel(0, 0));
+        assert_eq!(window_to_display((335.5, 535.5), (336, 536), Integer, Rotate0), pixel(335, 535));
+        assert_eq!(window_to_display((336.0, 10.0), (336, 536), AspectRatioStretch, Rotate0), None);
+        // resized to twice the size, then wider, with a margin of 100 either side, then taller
+        assert_eq!(window_to_display((21.0, 41.0), (672, 1072), AspectRatioStretch, Rotate0), pixel(10, 20));
+        assert_eq!(window_to_display((121.0, 41.0), (872, 1072), AspectRatioStretch, Rotate0), pixel(10, 20));
+        assert_eq!(window_to_display((99.0, 41.0), (872, 1072), AspectRatioStretch, Rotate0), None);
+        assert_eq!(window_to_display((772.5, 41.0), (872, 1072), AspectRatioStretch, Rotate0), None);
+        assert_eq!(window_to_display((21.0, 141.0), (672, 1272), AspectRatioStretch, Rotate0), pixel(10, 20));
+        assert_eq!(window_to_display((21.0, 99.0), (672, 1272), AspectRatioStretch, Rotate0), None);
+        // 2x, centred, with a margin of 84 either side and 3 top and bottom
+        assert_eq!(window_to_display((105.0, 44.0), (840, 1078), Integer, Rotate0), pixel(10, 20));
+        assert_eq!(window_to_display((83.5, 44.0), (840, 1078), Integer, Rotate0), None);
+        assert_eq!(window_to_display((105.0, 2.5), (840, 1078), Integer, Rotate0), None);
+        // too small a window for 1x shows the middle of the display
+        assert_eq!(window_to_display((0.0, 0.0), (334, 534), Integer, Rotate0), pixel(1, 1));
+        assert_eq!(window_to_display((0.0, 0.0), (0, 0), AspectRatioStretch, Rotate0), None);
+        // turned, the corners of the window are other corners of the display; a quarter turn
+        // makes it a landscape window
+        assert_eq!(window_to_display((0.0, 0.0), (536, 336), Integer, Rotate90), pixel(0, 535));
+        assert_eq!(window_to_display((535.5, 0.0), (536, 336), AspectRatioStretch, Rotate90), pixel(0, 0));
+        assert_eq!(window_to_display((0.0, 0.0), (336, 536), AspectRatioStretch, Rotate180), pixel(335, 535));
+        assert_eq!(window_to_display((21.0, 41.0), (1072, 672), Integer, Rotate270), pixel(335 - 20, 10));
+        assert_eq!(window_to_display((0.0, 0.0), (336, 536), Integer, Rotate90), None);
+    }
+
+    #[test]
+    fn test_fps_limit() {
+        assert_eq!(parse_fps_limit("30"), Some(Some(30)));
+        assert_eq!(parse_fps_limit("uncapped"), Some(None));
+        assert_eq!(parse_fps_limit("0"), Some(None));
+        assert_eq!(parse_fps_limit("fast"), None);
+        assert_eq!(frame_period(Some(5)), Duration::from_millis(200));
+        assert_eq!(frame_period(None), frame_period(Some(MAX_FPS)));
+
+        assert_eq!(step_fps_limit(Some(15), true), Some(30));
+        assert_eq!(step_fps_limit(Some(60), true), None);
+        assert_eq!(step_fps_limit(None, true), None);
+        assert_eq!(step_fps_limit(None, false), Some(60));
+        assert_eq!(step_fps_limit(Some(5), false), Some(5));
+        // a limit off the steps, from XOUS_FPS, steps to its neighbours
+        assert_eq!(step_fps_limit(Some(24), true), Some(30));
+        assert_eq!(step_fps_limit(Some(24), false), Some(15));
+        assert_eq!(step_fps_limit(Some(1000), false), Some(60));
+    }
+
+    #[test]
+    fn test_display_mode() {
+        assert_eq!(parse_display_mode(" Headless"), Some(DisplayMode::Headless));
+        assert_eq!(parse_display_mode("window"), Some(DisplayMode::Window));
+        assert_eq!(parse_display_mode("auto"), Some(DisplayMode::Auto));
+        assert_eq!(parse_display_mode("x11"), None);
+
+        // forced headless, the display never tries for a window, whatever the host has
+        std::env::set_var("XOUS_DISPLAY", "headless");
+        assert_eq!(display_mode(), DisplayMode::Headless);
+        let display = XousDisplay::new();
+        assert!(display.window.is_none() && !display.capabilities().window);
+        std::env::remove_var("XOUS_DISPLAY");
+    }
+
+    #[test]
+    fn test_quit_key() {
+        assert_eq!(parse_quit_key("Pause"), Some(Some(Key::Pause)));
+        assert_eq!(parse_quit_key("esc"), Some(Some(Key::Escape)));
+        assert_eq!(parse_quit_key(" none"), Some(None));
+        // F1 is a key of the emulated keyboard, and F12 takes screenshots
+        assert_eq!(parse_quit_key("f1"), None);
+        assert_eq!(parse_quit_key("F12"), None);
+    }
+
+    #[test]
+    fn test_next_vsync() {
+        let period = Duration::from_millis(16);
+        let last = Instant::now();
+        // a frame drawn quickly waits out the rest of the period
+        assert_eq!(next_vsync(last, last, period), last + period);
+        assert_eq!(next_vsync(last, last + Duration::from_millis(5), period), last + period);
+        // exactly on a refresh, the wait is for the one after it
+        assert_eq!(next_vsync(last, last + period, period), last + period * 2);
+        // an overrun skips to the next refresh on the grid, rather than drifting
+        assert_eq!(next_vsync(last, last + Duration::from_millis(40), period), last + period * 3);
     }
 }
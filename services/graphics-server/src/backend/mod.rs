@@ -1,3 +1,95 @@
+/// Why a frame couldn't be shown
+#[derive(Debug)]
+pub enum DisplayError {
+    /// the emulator window was closed: the system should shut down
+    CloseRequested,
+    /// the window couldn't be updated, even opened afresh
+    Window(String),
+    /// the panel's powered off, or hasn't been set up again since it was powered back on, and
+    /// can't take a frame
+    PoweredOff,
+}
+
+/// The size of the display and the layout of its frame buffer, which every backend shares.
+/// Buffer sizes should come from here, rather than being worked out again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// pixels across
+    pub width: usize,
+    /// lines of pixels
+    pub height: usize,
+    /// 32-bit words to a line of the frame buffer, the dirty bits at the end of it included
+    pub words_per_line: usize,
+    /// 32-bit words to the whole frame buffer
+    pub fb_size_words: usize,
+}
+
+/// A frame put up on the display, as the callback `on_frame_complete()` registers is told of it
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameComplete {
+    /// frames put up before it and it, since the display started
+    pub frame: u64,
+    /// when it was put up, in milliseconds since the display started
+    pub at_ms: u64,
+}
+
+#[cfg(any(windows, unix))]
+mod backbuffer;
+#[cfg(any(windows, unix))]
+pub use crate::backend::backbuffer::BackBuffer;
+#[cfg(any(windows, unix))]
+mod clipboard;
+#[cfg(any(windows, unix))]
+mod framestats;
+#[cfg(any(windows, unix))]
+pub use crate::backend::framestats::FrameStats;
+#[cfg(any(windows, unix))]
+mod gestures;
+#[cfg(any(windows, unix))]
+pub use crate::backend::gestures::{Gesture, GestureConfig, GestureEvent};
+#[cfg(any(windows, unix))]
+mod grid;
+#[cfg(any(windows, unix))]
+pub use crate::backend::grid::GridSpec;
+#[cfg(any(windows, unix))]
+mod hostkeys;
+#[cfg(any(windows, unix))]
+pub use crate::backend::hostkeys::{HostKeyEvent, HostKeymap, KeyAction, KeyRepeatConfig, KeyTranslator, PrecursorKey};
+#[cfg(any(windows, unix))]
+mod inputscript;
+#[cfg(any(windows, unix))]
+pub use crate::backend::inputscript::{InputScript, ScriptInput};
+#[cfg(any(windows, unix))]
+mod inspector;
+#[cfg(any(windows, unix))]
+pub use crate::backend::inspector::PixelReadout;
+#[cfg(any(windows, unix))]
+mod lcdsim;
+#[cfg(any(windows, unix))]
+pub use crate::backend::lcdsim::LcdArtifactConfig;
+#[cfg(any(windows, unix))]
+mod lcdtiming;
+#[cfg(any(windows, unix))]
+pub use crate::backend::lcdtiming::LcdTimingConfig;
+#[cfg(any(windows, unix))]
+mod magnifier;
+#[cfg(any(windows, unix))]
+mod overlaytext;
+#[cfg(any(windows, unix))]
+mod pointer;
+#[cfg(any(windows, unix))]
+mod recording;
+#[cfg(any(windows, unix))]
+mod snapshot;
+#[cfg(all(test, any(windows, unix)))]
+pub mod testing;
+// every backend's driven through the same trait, so the server needn't know which it has
+mod displaybackend;
+pub use crate::backend::displaybackend::{Capabilities, DisplayBackend, PointerEvent};
+// both backends track damage the same way, so partial updates behave here as on the device
+mod damage;
+pub use crate::backend::damage::MAX_UPDATE_BANDS;
+
 #[cfg(any(windows, unix))]
 mod minifb;
 #[cfg(any(windows, unix))]
@@ -7,3 +99,19 @@ pub use crate::backend::minifb::*;
 mod betrusted;
 #[cfg(any(feature="precursor", feature="renode"))]
 pub use crate::backend::betrusted::*;
+
+// whichever backend's built, it has to agree with the API, the drawing code and itself on the
+// frame buffer
+const _: () = assert!(
+    DISPLAY_INFO.width == crate::api::WIDTH as usize
+        && DISPLAY_INFO.height == crate::api::LINES as usize
+        && DISPLAY_INFO.width == crate::op::LCD_PX_PER_LINE
+        && DISPLAY_INFO.height == crate::op::LCD_LINES
+        && DISPLAY_INFO.words_per_line == crate::op::LCD_WORDS_PER_LINE
+        && DISPLAY_INFO.fb_size_words == crate::op::LCD_FRAME_BUF_SIZE
+        && DISPLAY_INFO.fb_size_words == DISPLAY_INFO.words_per_line * DISPLAY_INFO.height
+        && DISPLAY_INFO.fb_size_words == FB_SIZE
+        && DISPLAY_INFO.words_per_line == FB_WIDTH_WORDS
+        // the dirty bits take the top half of the last word of a line
+        && DISPLAY_INFO.width + 16 <= DISPLAY_INFO.words_per_line * 32
+);
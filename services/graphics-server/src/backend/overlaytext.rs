@@ -0,0 +1,123 @@
+//! Boxes of text drawn over the hosted display, for the emulator's own overlays, with a tiny font
+//! of their own so they work whatever state the text path is in
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// a glyph and the gap after it
+pub const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+pub const CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+/// space around the text in a box
+pub const MARGIN: usize = 2;
+
+/// lines of the display a box of `lines` lines of text covers
+pub const fn text_box_height(lines: usize) -> usize {
+    lines * CELL_HEIGHT + 2 * MARGIN - 1
+}
+
+/// The corner of the display a box of text goes in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+}
+
+/// The rows of a 5x7 glyph, top first, the leftmost pixel in bit 4. Letters are all capitals;
+/// characters that aren't here come out blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        '?' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Draws `text` into `corner` of `native`, a `width` pixels wide buffer, as `fg` text on a box of
+/// `bg`. The box covers the top `text_box_height()` lines for that much text.
+pub fn draw_text_box(native: &mut [u32], width: usize, text: &[String], corner: Corner, fg: u32, bg: u32) {
+    if text.is_empty() {
+        return;
+    }
+    let columns = text.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let box_width = (columns * CELL_WIDTH + 2 * MARGIN - 1).min(width);
+    let left = match corner {
+        Corner::TopLeft => 0,
+        Corner::TopRight => width - box_width,
+    };
+    for row in native.chunks_mut(width).take(text_box_height(text.len())) {
+        row[left..left + box_width].iter_mut().for_each(|p| *p = bg);
+    }
+    for (line, s) in text.iter().enumerate() {
+        for (column, c) in s.chars().enumerate() {
+            let (x0, y0) = (left + MARGIN + column * CELL_WIDTH, MARGIN + line * CELL_HEIGHT);
+            for (dy, bits) in glyph(c).iter().enumerate() {
+                for dx in (0..GLYPH_WIDTH).filter(|dx| bits & (0x10 >> dx) != 0) {
+                    if x0 + dx < left + box_width && (y0 + dy) * width < native.len() {
+                        native[(y0 + dy) * width + x0 + dx] = fg;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_box() {
+        // every letter has a glyph, whichever case it's in
+        assert!(('a'..='z').chain('A'..='Z').all(|c| glyph(c) != [0; GLYPH_HEIGHT]));
+
+        const BG: u32 = 0x000000;
+        const FG: u32 = 0xFFFFFF;
+        let width = 60;
+        let mut native = vec![0x123456; width * 30];
+        draw_text_box(&mut native, width, &["F1 > F1".to_string(), "UP".to_string()], Corner::TopLeft, FG, BG);
+        // 7 cells, less the last gap, and the margins
+        let box_width = 7 * CELL_WIDTH - 1 + 2 * MARGIN;
+        assert_eq!((native[box_width - 1], native[box_width]), (BG, 0x123456));
+        assert_eq!(native[text_box_height(2) * width], 0x123456);
+        // the top left of the 'F', and of the 'U' a line down
+        assert_eq!(native[MARGIN * width + MARGIN], FG);
+        assert_eq!(native[(MARGIN + CELL_HEIGHT) * width + MARGIN], FG);
+    }
+}
@@ -0,0 +1,70 @@
+//! Clicks and drags in the hosted emulator window, as pointer events on the display
+
+use crate::api::{Point, PointerAction};
+use super::PointerEvent;
+
+/// Turns the state of the mouse, as sampled each time the window's updated, into pointer
+/// events. Only the left button counts, and the pointer only moves while it's held down, the way
+/// a finger on a touch screen would.
+#[derive(Debug, Default)]
+pub struct PointerTracker {
+    /// where the button went down, or has been dragged to since, while it's held
+    pressed_at: Option<Point>,
+    /// the button went down off the display, and is ignored until it's let go
+    ignoring: bool,
+}
+impl PointerTracker {
+    /// The event made by the mouse being at `at`, `None` if it's off the display, with its
+    /// button `down` or not. A press off the display, in the border around it, does nothing, and
+    /// neither does dragging off it: the release comes where the pointer was last on it.
+    pub fn sample(&mut self, at: Option<Point>, down: bool) -> Option<PointerEvent> {
+        let (action, at) = match (self.pressed_at, down) {
+            (None, false) => {
+                self.ignoring = false;
+                return None;
+            }
+            (None, true) => match at {
+                Some(at) if !self.ignoring => (PointerAction::Press, at),
+                _ => {
+                    self.ignoring = true;
+                    return None;
+                }
+            },
+            (Some(last), true) => match at {
+                Some(at) if at != last => (PointerAction::Move, at),
+                _ => return None,
+            },
+            (Some(last), false) => {
+                self.pressed_at = None;
+                return Some(PointerEvent { action: PointerAction::Release, at: last });
+            }
+        };
+        self.pressed_at = Some(at);
+        Some(PointerEvent { action, at })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_tracker() {
+        let mut pointer = PointerTracker::default();
+        let event = |action, x, y| Some(PointerEvent { action, at: Point::new(x, y) });
+        // hovering does nothing; a drag presses, moves when the pointer does, and releases
+        assert_eq!(pointer.sample(Some(Point::new(5, 5)), false), None);
+        assert_eq!(pointer.sample(Some(Point::new(10, 20)), true), event(PointerAction::Press, 10, 20));
+        assert_eq!(pointer.sample(Some(Point::new(10, 20)), true), None);
+        assert_eq!(pointer.sample(Some(Point::new(11, 22)), true), event(PointerAction::Move, 11, 22));
+        // dragged off the display, and let go there: released where it left
+        assert_eq!(pointer.sample(None, true), None);
+        assert_eq!(pointer.sample(None, false), event(PointerAction::Release, 11, 22));
+
+        // a press in the border is ignored, even once it's dragged onto the display
+        assert_eq!(pointer.sample(None, true), None);
+        assert_eq!(pointer.sample(Some(Point::new(0, 0)), true), None);
+        assert_eq!(pointer.sample(Some(Point::new(0, 0)), false), None);
+        assert_eq!(pointer.sample(Some(Point::new(1, 1)), true), event(PointerAction::Press, 1, 1));
+    }
+}
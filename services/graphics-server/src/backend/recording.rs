@@ -0,0 +1,164 @@
+//! Recording of the hosted display as an animated PNG, for showing UI flows in issues and docs.
+//! Frames are spooled to a temporary file as they come in, so a long recording doesn't have to
+//! fit in memory, and encoded when the recording stops.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A recording in progress
+pub struct Recording {
+    /// where the animation goes when the recording stops
+    path: PathBuf,
+    max_frames: usize,
+    width: u32,
+    height: u32,
+    /// colours of clear and set bits in the frames
+    colours: [u32; 2],
+    spool: File,
+    spool_path: PathBuf,
+    /// how long each frame spooled was up, but for the latest
+    durations: Vec<Duration>,
+    /// each frame spooled's number, as the display counts the frames it puts up
+    numbers: Vec<u64>,
+    /// the latest frame, to spot repeats of it, and when it went up
+    last: Vec<u8>,
+    last_at: Instant,
+    /// when the recording stopped taking frames, if it has: it's full, or the spool couldn't
+    /// be written
+    stopped_at: Option<Instant>,
+}
+
+impl Recording {
+    /// Starts recording `width` x `height` frames into an animated PNG at `path`, keeping at most
+    /// `max_frames` of them. Frames are given 1bpp, with `colours` for clear and set bits.
+    pub fn start(path: &Path, max_frames: usize, width: u32, height: u32, colours: [u32; 2]) -> io::Result<Recording> {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let spool_path = std::env::temp_dir()
+            .join(format!("xous-recording-{}-{}.spool", std::process::id(), since_epoch.as_nanos()));
+        let spool = OpenOptions::new().read(true).write(true).create_new(true).open(&spool_path)?;
+        Ok(Recording {
+            path: path.to_path_buf(),
+            max_frames,
+            width,
+            height,
+            colours,
+            spool,
+            spool_path,
+            durations: Vec::new(),
+            numbers: Vec::new(),
+            last: Vec::new(),
+            last_at: Instant::now(),
+            stopped_at: None,
+        })
+    }
+    /// colour that set bits stand for; frames should be packed against it
+    pub fn set_colour(&self) -> u32 {
+        self.colours[1]
+    }
+    /// Takes in frame `number` that went up at `now`, packed 1bpp a row at a time, leftmost
+    /// pixel in the top bit. A repeat of the latest frame just keeps that one up for longer.
+    pub fn frame(&mut self, now: Instant, number: u64, rows: &[u8]) {
+        if self.stopped_at.is_some() || rows == &self.last[..] {
+            return;
+        }
+        if self.frames() == self.max_frames {
+            log::info!("GFX|hosted: recording is full at {} frames; stop it to save it", self.max_frames);
+            self.stopped_at = Some(now);
+            return;
+        }
+        if let Err(e) = self.spool.write_all(rows) {
+            // what's been spooled so far can still be saved
+            log::error!("GFX|hosted: couldn't spool a frame of the recording, so it stops here: {:?}", e);
+            self.stopped_at = Some(now);
+            return;
+        }
+        if !self.last.is_empty() {
+            self.durations.push(now.saturating_duration_since(self.last_at));
+        }
+        self.numbers.push(number);
+        self.last = rows.to_vec();
+        self.last_at = now;
+    }
+    /// frames spooled so far
+    pub fn frames(&self) -> usize {
+        if self.last.is_empty() { 0 } else { self.durations.len() + 1 }
+    }
+    /// Stops recording at `now` and writes out the animation, returning the number of frames in it
+    pub fn finish(mut self, now: Instant) -> io::Result<usize> {
+        let frames = self.frames();
+        if frames == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "no frames were recorded"));
+        }
+        let end = self.stopped_at.unwrap_or(now);
+        self.durations.push(end.saturating_duration_since(self.last_at));
+
+        let to_io = |e: png::EncodingError| io::Error::new(io::ErrorKind::Other, e);
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::One);
+        encoder.set_palette(self.colours.iter().flat_map(|c| c.to_be_bytes()[1..].to_vec()).collect::<Vec<u8>>());
+        encoder.set_animated(frames as u32, 0).map_err(to_io)?;
+        // to line the animation's frames up with the display's, and logs that go by them
+        let numbers: Vec<String> = self.numbers.iter().map(u64::to_string).collect();
+        encoder.add_text_chunk("Frame numbers".to_string(), numbers.join(" ")).map_err(to_io)?;
+        let mut writer = encoder.write_header().map_err(to_io)?;
+        self.spool.seek(SeekFrom::Start(0))?;
+        let mut rows = vec![0u8; self.last.len()];
+        for duration in self.durations.iter() {
+            self.spool.read_exact(&mut rows)?;
+            writer.set_frame_delay(duration.as_millis().min(u16::MAX as u128) as u16, 1000).map_err(to_io)?;
+            writer.write_image_data(&rows).map_err(to_io)?;
+        }
+        writer.finish().map_err(to_io)?;
+        Ok(frames)
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.spool_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording() {
+        let path = std::env::temp_dir().join(format!("xous-test-recording-{}.png", std::process::id()));
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+        // 16 x 2 pixels, 2 bytes a row
+        let mut recording = Recording::start(&path, 3, 16, 2, [0x000000, 0xFFFFFF]).unwrap();
+        recording.frame(ms(0), 7, &[0xFF, 0x00, 0x00, 0x00]);
+        recording.frame(ms(10), 8, &[0xFF, 0x00, 0x00, 0x00]);
+        recording.frame(ms(50), 9, &[0x00, 0x00, 0x00, 0x01]);
+        recording.frame(ms(60), 12, &[0xFF, 0xFF, 0x00, 0x00]);
+        // full: this one is left out, and the one before stays up until it came
+        recording.frame(ms(100), 13, &[0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(recording.frames(), 3);
+        assert_eq!(recording.finish(ms(1000)).unwrap(), 3);
+
+        let mut reader = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        assert_eq!(reader.info().animation_control.map(|a| a.num_frames), Some(3));
+        let numbers = reader.info().uncompressed_latin1_text.iter().find(|t| t.keyword == "Frame numbers");
+        assert_eq!(numbers.map(|t| t.text.as_str()), Some("7 9 12"));
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            reader.next_frame(&mut pixels).unwrap();
+            let delay = reader.info().frame_control.map(|f| (f.delay_num, f.delay_den));
+            frames.push((pixels.clone(), delay));
+        }
+        assert_eq!(frames, vec![
+            (vec![0xFF, 0x00, 0x00, 0x00], Some((50, 1000))),
+            (vec![0x00, 0x00, 0x00, 0x01], Some((10, 1000))),
+            (vec![0xFF, 0xFF, 0x00, 0x00], Some((40, 1000))),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
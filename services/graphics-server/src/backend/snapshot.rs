@@ -0,0 +1,108 @@
+//! Snapshots of the hosted display's frame buffer, saved to see a frame again without the app
+//! flow that drew it. A snapshot is a plain (P1) PBM of the frame, dark pixels 1, a line of the
+//! display to a line of the file, with the version of the layout and the palette and rotation
+//! the frame was shown in as comments at the top, so any PBM reader, golden image checks
+//! included, can take it as it is.
+
+use super::minifb::{parse_rotation, Palette, Rotation};
+use crate::backend::FB_SIZE;
+use crate::fbdump::FramebufferDump;
+
+/// Goes up whenever the layout of snapshots changes, so old ones are refused rather than misread
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// the comment a snapshot starts with, ahead of its version
+const SNAPSHOT_HEADER: &str = "xous frame buffer snapshot, version";
+
+/// A frame, and how it was shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// the frame buffer, dirty bits cleared
+    pub fb: [u32; FB_SIZE],
+    pub palette: Palette,
+    pub rotation: Rotation,
+}
+impl Snapshot {
+    pub fn to_pbm(&self) -> String {
+        fb_to_pbm(&self.fb, &[
+            format!("{} {}", SNAPSHOT_HEADER, SNAPSHOT_VERSION),
+            format!("palette fg={:06x} bg={:06x} inverted={}", self.palette.fg, self.palette.bg, self.palette.inverted),
+            format!("rotation {}", self.rotation.degrees()),
+        ])
+    }
+    /// Reads a snapshot back, refusing a PBM that isn't one, or is one of another version
+    pub fn from_pbm(pbm: &str) -> Result<Snapshot, String> {
+        let comments: Vec<&str> =
+            pbm.lines().filter_map(|line| line.split_once('#')).map(|(_, comment)| comment.trim()).collect();
+        let find = |key: &str| comments.iter().find_map(|comment| comment.strip_prefix(key)).map(str::trim);
+        match find(SNAPSHOT_HEADER).map(|version| version.parse::<u32>()) {
+            None => return Err("not a frame buffer snapshot".to_string()),
+            Some(Ok(SNAPSHOT_VERSION)) => {}
+            Some(Ok(version)) => {
+                return Err(format!("snapshot version {}; only version {} can be read", version, SNAPSHOT_VERSION))
+            }
+            Some(Err(_)) => return Err("snapshot version isn't a number".to_string()),
+        }
+        let palette = find("palette ").and_then(parse_palette).ok_or("no palette, or one that can't be read")?;
+        let rotation = find("rotation ").and_then(parse_rotation).ok_or("no rotation, or one that can't be read")?;
+        Ok(Snapshot { fb: FramebufferDump::from_pbm(pbm)?.words, palette, rotation })
+    }
+}
+
+/// Reads a palette as a snapshot gives it: `fg=RRGGBB bg=RRGGBB inverted=true|false`
+fn parse_palette(palette: &str) -> Option<Palette> {
+    let (mut fg, mut bg, mut inverted) = (None, None, None);
+    for field in palette.split_whitespace() {
+        match field.split_once('=')? {
+            ("fg", colour) => fg = u32::from_str_radix(colour, 16).ok(),
+            ("bg", colour) => bg = u32::from_str_radix(colour, 16).ok(),
+            ("inverted", on) => inverted = on.parse::<bool>().ok(),
+            _ => return None,
+        }
+    }
+    Some(Palette { fg: fg?, bg: bg?, inverted: inverted? })
+}
+
+/// `fb` as a plain PBM, each of `comments` on a comment line of its own after the magic number
+pub fn fb_to_pbm(fb: &[u32; FB_SIZE], comments: &[String]) -> String {
+    let pbm = FramebufferDump { words: *fb }.to_pbm();
+    let mut header = "P1\n".to_string();
+    for comment in comments {
+        header.push_str(&format!("# {}\n", comment));
+    }
+    pbm.replacen("P1\n", &header, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FB_WIDTH_WORDS;
+
+    #[test]
+    fn test_snapshot() {
+        let mut fb = [0x5555_5555; FB_SIZE];
+        for line in fb.chunks_mut(FB_WIDTH_WORDS) {
+            line[FB_WIDTH_WORDS - 1] &= 0xFFFF;
+        }
+        fb[3] = 0xFFFF_0000;
+        let snapshot = Snapshot {
+            fb,
+            palette: Palette { fg: 0x123456, bg: 0xABCDEF, inverted: true },
+            rotation: Rotation::Rotate270,
+        };
+        let pbm = snapshot.to_pbm();
+        assert!(pbm.starts_with(
+            "P1\n# xous frame buffer snapshot, version 1\n# palette fg=123456 bg=abcdef inverted=true\n# rotation 270\n336 536\n0101"
+        ));
+        assert_eq!(Snapshot::from_pbm(&pbm), Ok(snapshot));
+        // it reads as a PBM like any other
+        assert_eq!(FramebufferDump::from_pbm(&pbm).unwrap().words[..], fb[..]);
+
+        // a PBM with no header, or the header of another version, or no palette, isn't taken
+        let plain = fb_to_pbm(&fb, &[]);
+        assert_eq!(Snapshot::from_pbm(&plain), Err("not a frame buffer snapshot".to_string()));
+        let newer = pbm.replacen("version 1", "version 2", 1);
+        assert_eq!(Snapshot::from_pbm(&newer), Err("snapshot version 2; only version 1 can be read".to_string()));
+        let no_palette = pbm.replacen("# palette", "#", 1);
+        assert!(Snapshot::from_pbm(&no_palette).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Checking what's been drawn in the hosted display against golden images, for regression tests.
+//! Golden images are plain (P1) PBMs of the whole display, dark pixels 1, a line of the display
+//! to a line of the file, as `FramebufferDump::to_pbm()` writes them on the client side; a
+//! snapshot saved from the emulator is one as it is.
+
+use super::snapshot::fb_to_pbm;
+use crate::backend::{FB_LINES, FB_SIZE, FB_WIDTH_PIXELS};
+use crate::fbdump::{FbDiff, FramebufferDump};
+use crate::api::PixelColor;
+use std::path::{Path, PathBuf};
+
+/// Set to write golden images afresh from what's drawn, rather than checking against them, when
+/// what's drawn has changed on purpose
+pub const REGENERATE_GOLDEN_VAR: &str = "XOUS_REGENERATE_GOLDEN";
+
+/// the colours of the visual diff
+const MATCHED_LIGHT: [u8; 3] = [0xC0, 0xC0, 0xC0];
+const MATCHED_DARK: [u8; 3] = [0x60, 0x60, 0x60];
+const MISMATCHED: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// Compares `actual`, a frame buffer as `XousDisplay::copy_framebuffer()` gives it, with
+/// `golden_pbm` pixel for pixel. Returns `None` if every pixel matches. Panics if `golden_pbm`
+/// isn't a golden image the size of the display, as a test with a golden image that can't be
+/// read can't pass.
+pub fn compare_fb(actual: &[u32; FB_SIZE], golden_pbm: &str) -> Option<FbDiff> {
+    FramebufferDump { words: *actual }
+        .diff_pbm(golden_pbm)
+        .unwrap_or_else(|e| panic!("couldn't read the golden image: {}", e))
+}
+
+/// Writes how `actual` differs from `golden` to `path` as a PNG the size of the display: pixels
+/// that match in grey, lighter or darker as they are, and those that don't in red
+pub fn write_diff_png(actual: &FramebufferDump, golden: &FramebufferDump, path: &Path) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, FB_WIDTH_PIXELS as u32, FB_LINES as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let to_io = |e: png::EncodingError| std::io::Error::new(std::io::ErrorKind::Other, e);
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    let rgb: Vec<u8> = (0..FB_LINES as i16)
+        .flat_map(|y| (0..FB_WIDTH_PIXELS as i16).map(move |x| (x, y)))
+        .flat_map(|(x, y)| match (actual.pixel(x, y), golden.pixel(x, y)) {
+            (PixelColor::Dark, PixelColor::Dark) => MATCHED_DARK,
+            (PixelColor::Light, PixelColor::Light) => MATCHED_LIGHT,
+            _ => MISMATCHED,
+        })
+        .collect();
+    writer.write_image_data(&rgb).map_err(to_io)
+}
+
+/// Panics unless `actual` matches the golden image at `golden`. On a mismatch, a visual diff is
+/// written to the working directory, named after the golden image. With `REGENERATE_GOLDEN_VAR`
+/// set, the golden image is written from `actual` instead.
+pub fn assert_golden(actual: &[u32; FB_SIZE], golden: &Path) {
+    if std::env::var_os(REGENERATE_GOLDEN_VAR).is_some() {
+        std::fs::write(golden, fb_to_pbm(actual, &[]))
+            .unwrap_or_else(|e| panic!("couldn't write the golden image {}: {}", golden.display(), e));
+        log::info!("wrote the golden image {}", golden.display());
+        return;
+    }
+    let golden_pbm = std::fs::read_to_string(golden).unwrap_or_else(|e| {
+        panic!("couldn't read the golden image {}: {}; set {} to write it", golden.display(), e, REGENERATE_GOLDEN_VAR)
+    });
+    let diff = match compare_fb(actual, &golden_pbm) {
+        None => return,
+        Some(diff) => diff,
+    };
+    let stem = golden.file_stem().map_or("golden".into(), |s| s.to_string_lossy());
+    let diff_png = PathBuf::from(format!("{}-diff.png", stem));
+    // compare_fb has read the golden image already
+    let golden_fb = FramebufferDump::from_pbm(&golden_pbm).unwrap();
+    let saved = match write_diff_png(&FramebufferDump { words: *actual }, &golden_fb, &diff_png) {
+        Ok(()) => format!("diff saved to {}", diff_png.display()),
+        Err(e) => format!("couldn't save the diff: {}", e),
+    };
+    panic!(
+        "frame buffer differs from {} in {} pixels, within ({}, {})-({}, {}); {}. If that's meant, set {} to write the golden image afresh.",
+        golden.display(), diff.count, diff.tl.x, diff.tl.y, diff.br.x, diff.br.y, saved, REGENERATE_GOLDEN_VAR
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Point;
+    use crate::backend::FB_WIDTH_WORDS;
+
+    #[test]
+    fn test_compare_fb() {
+        // all light but for a dark pixel at each end of the first line
+        let mut fb = [0xFFFF_FFFF; FB_SIZE];
+        fb[0] &= !1;
+        fb[FB_WIDTH_WORDS - 1] &= !(1 << (FB_WIDTH_PIXELS % 32 - 1));
+        let pbm = fb_to_pbm(&fb, &[]);
+        assert!(pbm.starts_with("P1\n336 536\n1000"));
+        assert_eq!(compare_fb(&fb, &pbm), None);
+        // the dirty bits past the end of a line aren't pixels
+        fb[2 * FB_WIDTH_WORDS - 1] &= 0xFFFF;
+        // comments and whitespace between the pixels are fine
+        let spaced = pbm.replacen("P1\n", "P1 # golden\n", 1).replace("000", "0 0\t0");
+        assert_eq!(compare_fb(&fb, &spaced), None);
+
+        // two pixels further down drawn dark
+        fb[5 * FB_WIDTH_WORDS + 1] &= !(1 << 3);
+        fb[9 * FB_WIDTH_WORDS] &= !(1 << 7);
+        let diff = compare_fb(&fb, &pbm).unwrap();
+        assert_eq!((diff.count, diff.tl, diff.br), (2, Point::new(7, 5), Point::new(35, 9)));
+    }
+
+    #[test]
+    fn test_write_diff_png() {
+        let golden = FramebufferDump::default();
+        let mut actual = FramebufferDump::default();
+        actual.words[5 * FB_WIDTH_WORDS + 1] = 1 << 3; // (35, 5) light, where the golden image is dark
+        let path = std::env::temp_dir().join(format!("xous-diff-test-{}.png", std::process::id()));
+        write_diff_png(&actual, &golden, &path).unwrap();
+        let mut reader = png::Decoder::new(std::fs::File::open(&path).unwrap()).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgb).unwrap();
+        std::fs::remove_file(&path).ok();
+        let at = |x: usize, y: usize| &rgb[(y * FB_WIDTH_PIXELS + x) * 3..][..3];
+        assert_eq!(at(35, 5), MISMATCHED);
+        assert_eq!(at(0, 0), MATCHED_DARK);
+        assert_eq!(rgb.chunks(3).filter(|&p| p == MISMATCHED).count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "the screen is 336x536")]
+    fn test_compare_fb_wrong_size() {
+        compare_fb(&[0; FB_SIZE], "P1\n2 1\n01\n");
+    }
+}
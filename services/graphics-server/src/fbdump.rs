@@ -0,0 +1,156 @@
+//! Frame buffer dumps, for tests that check what was drawn in hosted mode
+
+use crate::api::{PixelColor, Point, LINES, WIDTH};
+
+/// words in a line of the frame buffer; the last one holds the line's dirty bit above its pixels
+pub const FB_DUMP_WORDS_PER_LINE: usize = 11;
+pub const FB_DUMP_WORDS: usize = FB_DUMP_WORDS_PER_LINE * LINES as usize;
+
+/// A copy of the frame buffer, as filled in by `Gfx::dump_framebuffer()`. Pixel `x` of line `y`
+/// is bit `x % 32` of word `y * FB_DUMP_WORDS_PER_LINE + x / 32`, set if it's light, as the
+/// drawing ops leave it; the dirty bits are cleared. Page aligned, so it can be lent as is.
+#[repr(C, align(4096))]
+pub struct FramebufferDump {
+    pub words: [u32; FB_DUMP_WORDS],
+}
+impl Default for FramebufferDump {
+    fn default() -> FramebufferDump {
+        FramebufferDump { words: [0; FB_DUMP_WORDS] }
+    }
+}
+
+/// How a dump differs from the image it was compared with
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FbDiff {
+    /// pixels that differ
+    pub count: usize,
+    /// corners of the box around them, inclusive
+    pub tl: Point,
+    pub br: Point,
+}
+
+impl FramebufferDump {
+    pub fn pixel(&self, x: i16, y: i16) -> PixelColor {
+        let word = self.words[y as usize * FB_DUMP_WORDS_PER_LINE + x as usize / 32];
+        if word & (1 << (x as usize % 32)) != 0 { PixelColor::Light } else { PixelColor::Dark }
+    }
+    /// The dump as a plain (P1) PBM, with dark pixels as 1. Each line of the screen goes on a
+    /// line of its own, rather than wrapping at 70 characters, so golden images diff line for line.
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", WIDTH, LINES);
+        for y in 0..LINES {
+            pbm.extend((0..WIDTH).map(|x| if self.pixel(x, y) == PixelColor::Dark { '1' } else { '0' }));
+            pbm.push('\n');
+        }
+        pbm
+    }
+    /// Reads a plain PBM the size of the screen, as `to_pbm()` writes it, back into a dump
+    pub fn from_pbm(pbm: &str) -> Result<FramebufferDump, String> {
+        let mut dump = FramebufferDump::default();
+        for (i, _) in parse_pbm(pbm)?.iter().enumerate().filter(|(_, &dark)| !dark) {
+            let (x, y) = (i % WIDTH as usize, i / WIDTH as usize);
+            dump.words[y * FB_DUMP_WORDS_PER_LINE + x / 32] |= 1 << (x % 32);
+        }
+        Ok(dump)
+    }
+    /// Compares the dump with a plain PBM the size of the screen. Returns `Ok(None)` if every
+    /// pixel matches, or an error describing why `expected_pbm` couldn't be read.
+    pub fn diff_pbm(&self, expected_pbm: &str) -> Result<Option<FbDiff>, String> {
+        let expected = parse_pbm(expected_pbm)?;
+        let mut diff: Option<FbDiff> = None;
+        for (i, &dark) in expected.iter().enumerate() {
+            let (x, y) = ((i % WIDTH as usize) as i16, (i / WIDTH as usize) as i16);
+            if (self.pixel(x, y) == PixelColor::Dark) == dark {
+                continue;
+            }
+            diff = Some(match diff {
+                None => FbDiff { count: 1, tl: Point::new(x, y), br: Point::new(x, y) },
+                Some(d) => FbDiff {
+                    count: d.count + 1,
+                    tl: Point::new(d.tl.x.min(x), d.tl.y.min(y)),
+                    br: Point::new(d.br.x.max(x), d.br.y.max(y)),
+                },
+            });
+        }
+        Ok(diff)
+    }
+    /// Panics unless the dump matches `expected_pbm` pixel for pixel. On a mismatch, the dump is
+    /// saved as `fb-mismatch.pbm` in the working directory, to look at or take as the new golden image.
+    pub fn assert_matches(&self, expected_pbm: &str) {
+        let diff = match self.diff_pbm(expected_pbm) {
+            Ok(None) => return,
+            Ok(Some(diff)) => diff,
+            Err(e) => panic!("couldn't read the expected frame buffer: {}", e),
+        };
+        let saved = match std::fs::write("fb-mismatch.pbm", self.to_pbm()) {
+            Ok(()) => "saved to fb-mismatch.pbm".to_string(),
+            Err(e) => format!("couldn't save it: {}", e),
+        };
+        panic!("frame buffer differs in {} pixels, within ({}, {})-({}, {}); {}",
+            diff.count, diff.tl.x, diff.tl.y, diff.br.x, diff.br.y, saved);
+    }
+}
+
+/// Reads a plain PBM the size of the screen into one `bool` per pixel, `true` if it's dark
+fn parse_pbm(pbm: &str) -> Result<Vec<bool>, String> {
+    let mut tokens = pbm
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split_whitespace());
+    if tokens.next() != Some("P1") {
+        return Err("not a plain (P1) PBM".to_string());
+    }
+    let mut dimension = || tokens.next().and_then(|t| t.parse::<i16>().ok());
+    match (dimension(), dimension()) {
+        (Some(w), Some(h)) if (w, h) == (WIDTH, LINES) => {}
+        (Some(w), Some(h)) => return Err(format!("{}x{} image, the screen is {}x{}", w, h, WIDTH, LINES)),
+        _ => return Err("missing width or height".to_string()),
+    }
+    let pixels = tokens
+        .flat_map(|t| t.chars())
+        .map(|c| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(format!("'{}' isn't a pixel", c)),
+        })
+        .collect::<Result<Vec<bool>, String>>()?;
+    if pixels.len() != WIDTH as usize * LINES as usize {
+        return Err(format!("{} pixels, expected {}", pixels.len(), WIDTH as usize * LINES as usize));
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_pbm() {
+        // all dark but for a light pixel at each end of the first line
+        let mut dump = FramebufferDump::default();
+        dump.words[0] = 1;
+        dump.words[FB_DUMP_WORDS_PER_LINE - 1] = 1 << 15;
+        let pbm = dump.to_pbm();
+        assert!(pbm.starts_with("P1\n336 536\n0111"));
+        assert_eq!(dump.diff_pbm(&pbm), Ok(None));
+        // comments and any whitespace between the pixels are fine
+        let spaced = pbm.replacen("P1\n", "P1 # golden\n", 1).replace("111", "1 1\t1");
+        assert_eq!(dump.diff_pbm(&spaced), Ok(None));
+
+        dump.words[2 * FB_DUMP_WORDS_PER_LINE + 1] = 0b11; // (32, 2) and (33, 2)
+        dump.words[5 * FB_DUMP_WORDS_PER_LINE] = 0x10; // (4, 5)
+        assert_eq!(dump.diff_pbm(&pbm),
+            Ok(Some(FbDiff { count: 3, tl: Point::new(4, 2), br: Point::new(33, 5) })));
+
+        assert!(dump.diff_pbm("P4\n336 536\n").is_err());
+        assert!(dump.diff_pbm("P1\n8 8\n").is_err());
+        assert!(dump.diff_pbm(&pbm[..pbm.len() - 2]).is_err());
+        assert!(dump.diff_pbm(&pbm.replacen("0", "2", 1)).is_err());
+
+        // and reads back as it was written
+        let read = FramebufferDump::from_pbm(&spaced).unwrap();
+        assert_eq!((read.words[0], read.words[FB_DUMP_WORDS_PER_LINE - 1]), (1, 1 << 15));
+        assert_eq!(read.diff_pbm(&pbm), Ok(None));
+        assert!(FramebufferDump::from_pbm("P1\n8 8\n").is_err());
+    }
+}
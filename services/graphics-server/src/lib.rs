@@ -13,6 +13,13 @@ pub mod op;
 pub mod fontmap;
 pub use fontmap::*;
 
+#[cfg(not(target_os = "xous"))]
+pub mod fbdump;
+#[cfg(not(target_os = "xous"))]
+pub use fbdump::{FbDiff, FramebufferDump};
+#[cfg(not(target_os = "xous"))]
+pub use api::PointerAction;
+
 use api::Opcode; // if you prefer to map the api into your local namespace
 use num_traits::ToPrimitive;
 use xous::{send_message, Message};
@@ -99,6 +106,22 @@ impl Gfx {
         )
         .map(|_| ())
     }
+    /// Blocks until a frame's been put up on the display since the last `flush()`, so an
+    /// animation can draw a frame per refresh rather than pace itself with sleeps; if one's up
+    /// already, returns at once. Returns the frame's number and when it was put up, in
+    /// milliseconds since the display started. A flush with nothing new in it puts up no frame,
+    /// and the wait after it gets the one already up, with its number unchanged.
+    pub fn wait_for_vsync(&self) -> Result<(u64, u64), xous::Error> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::WaitForVsync.to_usize().unwrap(), 0, 0, 0, 0),
+        )?;
+        if let xous::Result::Scalar2(frame, at_ms) = response {
+            Ok((frame as u64, at_ms as u64))
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
 
     pub fn draw_sleepscreen(&self) -> Result<(), xous::Error> {
         send_message(
@@ -253,6 +276,15 @@ impl Gfx {
         .map(|_| ())
     }
 
+    /// Turns inverted video on or off, swapping dark and light pixels on the whole display
+    pub fn set_inverted(&self, inverted: bool) -> Result<(), xous::Error> {
+        send_message(
+            self.conn,
+            Message::new_scalar(Opcode::SetInverted.to_usize().unwrap(), if inverted { 1 } else { 0 }, 0, 0, 0),
+        )
+        .map(|_| ())
+    }
+
     /// instead of implementing the read in the library, we had the raw opcode to the caller
     /// this allows the caller to re-use the bulk read data structure across multiple reads
     /// instead of it being re-allocated and re-init'd every single call
@@ -269,6 +301,107 @@ impl Gfx {
         .expect("couldn't reset bulk read");
     }
 
+    /// Copies out the frame buffer as it stands, for tests to check what was drawn
+    #[cfg(not(target_os = "xous"))]
+    pub fn dump_framebuffer(&self) -> Result<Box<FramebufferDump>, xous::Error> {
+        let mut dump = Box::new(FramebufferDump::default());
+        let buf = unsafe {
+            xous::MemoryRange::new(
+                &mut *dump as *mut FramebufferDump as usize,
+                core::mem::size_of::<FramebufferDump>(),
+            )?
+        };
+        send_message(
+            self.conn,
+            Message::new_lend_mut(Opcode::DumpFramebuffer.to_usize().unwrap(), buf, None, None),
+        )
+        .map(|_| dump)
+    }
+    /// The number of redraws the server skipped because nothing on screen had changed; an
+    /// idle UI should see this climb instead of the frame being pushed over and over
+    #[cfg(not(target_os = "xous"))]
+    pub fn skipped_frames(&self) -> Result<u64, xous::Error> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::SkippedFrames.to_usize().unwrap(), 0, 0, 0, 0),
+        )?;
+        if let xous::Result::Scalar1(skipped) = response {
+            Ok(skipped as u64)
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
+    /// The number of frames the server has put up in the emulator window with something new in
+    /// them, and when it put up the latest, in milliseconds since the display started, if it
+    /// has; the window title and recordings number frames the same way
+    #[cfg(not(target_os = "xous"))]
+    pub fn presented_frames(&self) -> Result<(u64, Option<u64>), xous::Error> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::PresentedFrames.to_usize().unwrap(), 0, 0, 0, 0),
+        )?;
+        match response {
+            xous::Result::Scalar2(0, _) => Ok((0, None)),
+            xous::Result::Scalar2(frames, at_ms) => Ok((frames as u64, Some(at_ms as u64))),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Has clicks and drags in the emulator window sent to the server registered as
+    /// `server_name`, as scalar messages on `action_opcode` carrying a `PointerAction`, the x
+    /// and y of the pixel, and the surface's index. Only the latest server registered gets them.
+    #[cfg(not(target_os = "xous"))]
+    pub fn register_pointer_listener(&self, server_name: &str, action_opcode: usize) -> Result<(), xous::Error> {
+        let registration = api::PointerRegistration {
+            server_name: xous_ipc::String::<64>::from_str(server_name),
+            listener_op_id: action_opcode,
+        };
+        let buf = Buffer::into_buf(registration).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::RegisterPointerListener.to_u32().unwrap()).map(|_| ())
+    }
+    /// Dims the emulator's display as the backlight at `level`, 0 (off) to 31, would. The
+    /// backlight level set through the COM server does the same.
+    #[cfg(not(target_os = "xous"))]
+    pub fn set_brightness(&self, level: u8) -> Result<(), xous::Error> {
+        send_message(
+            self.conn,
+            Message::new_scalar(Opcode::SetBrightness.to_usize().unwrap(), level as usize, 0, 0, 0),
+        )
+        .map(|_| ())
+    }
+    /// Opens another display surface in a window of its own, for prototypes with a second
+    /// display, and returns its index, for `select_surface()`. It's laid out as the main display
+    /// is; closing its window doesn't shut the system down.
+    #[cfg(not(target_os = "xous"))]
+    pub fn open_surface(&self) -> Result<usize, xous::Error> {
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::OpenSurface.to_usize().unwrap(), 0, 0, 0, 0),
+        )?;
+        if let xous::Result::Scalar1(surface) = response {
+            Ok(surface)
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
+    /// Sends drawing, flushes and frame buffer dumps from here on to surface `surface`, 0 being
+    /// the main display. An index no surface has is ignored.
+    #[cfg(not(target_os = "xous"))]
+    pub fn select_surface(&self, surface: usize) -> Result<(), xous::Error> {
+        send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::SelectSurface.to_usize().unwrap(), surface, 0, 0, 0),
+        )
+        .map(|_| ())
+    }
+    /// Panics unless the screen matches `expected_pbm`, a plain PBM golden image; see
+    /// `FramebufferDump::assert_matches()`
+    #[cfg(not(target_os = "xous"))]
+    pub fn assert_fb_matches(&self, expected_pbm: &str) {
+        self.dump_framebuffer()
+            .expect("couldn't dump the frame buffer")
+            .assert_matches(expected_pbm);
+    }
+
     pub fn selftest(&self, duration_ms: usize) {
         send_message(
             self.conn,
@@ -4,13 +4,14 @@
 mod api;
 
 mod backend;
-use backend::XousDisplay;
+use backend::{DisplayBackend, DisplayError, XousDisplay};
 
 mod op;
 
 mod logo;
 mod poweron;
 mod sleep_note;
+mod vsync;
 
 use api::*;
 
@@ -27,9 +28,15 @@ use xous_ipc::Buffer;
 mod fontmap;
 use api::BulkRead;
 
+// shared with the library, for the hosted display's snapshots and golden image checks
+#[cfg(any(windows, unix))]
+#[allow(dead_code)]
+mod fbdump;
+
 #[cfg(any(feature="precursor", feature="renode"))] // only install for hardware targets; hosted mode uses host's panic handler
 mod panic;
 
+use core::convert::TryInto;
 use core::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -39,10 +46,23 @@ use core::ops::Add;
 #[cfg(feature = "gfx-testing")]
 mod testing;
 
-fn draw_boot_logo(display: &mut XousDisplay) {
+fn draw_boot_logo(display: &mut dyn DisplayBackend) {
     display.blit_screen(&poweron::LOGO_MAP);
 }
 
+/// Deals with the outcome of showing a frame. Returns `true` if the display was closed, and the
+/// system should shut down; any other error is just logged, as the next frame may well make it.
+fn closed(result: Result<(), DisplayError>) -> bool {
+    match result {
+        Ok(()) => false,
+        Err(DisplayError::CloseRequested) => true,
+        Err(e) => {
+            log::error!("couldn't show a frame: {:?}", e);
+            false
+        }
+    }
+}
+
 #[cfg(any(feature="precursor", feature="renode"))]
 fn map_fonts() -> MemoryRange {
     log::trace!("mapping fonts");
@@ -112,7 +132,10 @@ fn wrapped_main() -> ! {
     log::info!("my PID is {}", xous::process::id());
 
     let mut display = XousDisplay::new();
-    draw_boot_logo(&mut display); // bring this up as soon as possible
+    // hosted, the display can start out showing a snapshot instead, to look at a frame again
+    if display.startup_snapshot().is_none() {
+        draw_boot_logo(&mut display); // bring this up as soon as possible
+    }
     let fontregion = map_fonts();
 
     // install the graphical panic handler. It won't catch really early panics, or panics in this crate,
@@ -126,6 +149,9 @@ fn wrapped_main() -> ! {
         };
         panic::panic_handler_thread(is_panic.clone(), hwfb, control);
     }
+    // from here on the display's only driven through what every backend does, so the server
+    // runs the same whichever it is
+    let mut display: Box<dyn DisplayBackend> = Box::new(display);
 
     let xns = xous_names::XousNames::new().unwrap();
     // these connections should be established:
@@ -142,7 +168,9 @@ fn wrapped_main() -> ! {
 
     let screen_clip = Rectangle::new(Point::new(0, 0), display.screen_size());
 
-    display.redraw();
+    // set once the display's been closed, to shut down after the message at hand
+    let mut close_requested = closed(display.redraw());
+    let mut shutdown_requested = false;
 
     // register a suspend/resume listener
     let sr_cid = xous::connect(sid).expect("couldn't create suspend callback connection");
@@ -153,6 +181,51 @@ fn wrapped_main() -> ! {
 
     let ticktimer = ticktimer_server::Ticktimer::new().unwrap();
 
+    // the backend tells the server of each frame it puts up, to let apps waiting for one go
+    let frame_cid = xous::connect(sid).expect("couldn't create frame complete connection");
+    display.on_frame_complete(frame_cid, Opcode::FrameComplete as usize);
+    let mut vsync = vsync::VsyncWaits::default();
+
+    // keys typed into the emulator window go to the keyboard service, as if typed on the device;
+    // the device's own keys go there directly
+    #[cfg(not(target_os = "xous"))]
+    let inject_key = {
+        let kbd = keyboard::Keyboard::new(&xns).expect("can't connect to KBD for emulation");
+        move |c| kbd.hostmode_inject_key(c)
+    };
+    #[cfg(target_os = "xous")]
+    let inject_key = |_: char| ();
+    // and clicks and drags in it to whoever's registered for them, with the opcode they gave
+    let mut pointer_listener: Option<(xous::CID, usize)> = None;
+    // the display surfaces other than the one drawn to, opened with OpenSurface; SelectSurface
+    // swaps the one asked for in as `display`
+    let mut other_surfaces: Vec<Box<dyn DisplayBackend>> = Vec::new();
+    // a display that takes input in, like the emulator's windows, is polled more often than it's
+    // redrawn, on this thread, as the windows have to be; a timer thread just asks for it
+    if let Some(interval) = display.input_poll_interval() {
+        let poll_cid = xous::connect(sid).expect("couldn't create input poll connection");
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            // not blocking: a poll that finds the server's queue full is dropped, and the next
+            // one gets the input all the same
+            xous::try_send_message(poll_cid, xous::Message::new_scalar(Opcode::PollInput as usize, 0, 0, 0, 0)).ok();
+        });
+    }
+    // and a display that dims with the backlight is told its level; in its own thread, so as
+    // not to hold up the server until the COM server's up. Only hosted builds have COM send it.
+    #[cfg(not(target_os = "xous"))]
+    {
+        if display.capabilities().brightness {
+            std::thread::spawn(move || {
+                let xns = xous_names::XousNames::new().unwrap();
+                let com = com::Com::new(&xns).expect("can't connect to COM");
+                if let Err(e) = com.register_backlight_listener(sid, Opcode::SetBrightness as u32) {
+                    log::error!("couldn't register for the backlight level: {:?}", e);
+                }
+            });
+        }
+    }
+
     #[cfg(feature = "gfx-testing")]
     testing::tests();
     loop {
@@ -392,8 +465,13 @@ fn wrapped_main() -> ! {
                 }
                 Some(Opcode::Flush) => {
                     log::trace!("***gfx flush*** redraw##");
-                    display.update();
-                    display.redraw();
+                    vsync.flushed();
+                    close_requested |= closed(display.update().and_then(|()| display.redraw()));
+                    // the other surfaces' windows are kept going too, with anything drawn to
+                    // them before they were swapped out
+                    for surface in other_surfaces.iter_mut() {
+                        close_requested |= closed(surface.update().and_then(|()| surface.redraw()));
+                    }
                 }
                 Some(Opcode::Clear) => {
                     let mut r = Rectangle::full_screen();
@@ -455,13 +533,15 @@ fn wrapped_main() -> ! {
                 }),
                 Some(Opcode::DrawSleepScreen) => msg_scalar_unpack!(msg, _, _, _, _, {
                     display.blit_screen(&logo::LOGO_MAP);
-                    display.update();
-                    display.redraw();
+                    close_requested |= closed(display.update().and_then(|()| display.redraw()));
                 }),
                 Some(Opcode::DrawBootLogo) => msg_scalar_unpack!(msg, _, _, _, _, {
                     display.blit_screen(&poweron::LOGO_MAP);
-                    display.update();
-                    display.redraw();
+                    close_requested |= closed(display.update().and_then(|()| display.redraw()));
+                }),
+                Some(Opcode::SetInverted) => msg_scalar_unpack!(msg, inverted, _, _, _, {
+                    display.set_inverted(inverted != 0);
+                    close_requested |= closed(display.redraw());
                 }),
                 Some(Opcode::Devboot) => msg_scalar_unpack!(msg, ena, _, _, _, {
                     if ena != 0 {
@@ -510,41 +590,110 @@ fn wrapped_main() -> ! {
                     bulkread.from_offset += readlen as u32;
                     buf.replace(bulkread).unwrap();
                 }
+                Some(Opcode::DumpFramebuffer) => {
+                    let mem = msg.body.memory_message_mut().unwrap();
+                    let dest = mem.buf.as_slice_mut::<u32>();
+                    let fb_size = display.info().fb_size_words;
+                    if dest.len() < fb_size {
+                        log::error!("DumpFramebuffer buffer is {} words, needs {}; ignoring!", dest.len(), fb_size);
+                        continue;
+                    }
+                    dest[..fb_size].copy_from_slice(&display.copy_framebuffer());
+                }
+                Some(Opcode::RegisterPointerListener) => {
+                    let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                    let registration = buffer.as_flat::<PointerRegistration, _>().unwrap();
+                    match xns.request_connection_blocking(registration.server_name.as_str()) {
+                        Ok(cid) => pointer_listener = Some((cid, registration.listener_op_id as usize)),
+                        Err(e) => {
+                            log::error!("couldn't connect to pointer listener: {:?}", e);
+                            pointer_listener = None;
+                        }
+                    }
+                }
+                Some(Opcode::PollInput) => {
+                    // the keys and pointer events taken in go out below, with everyone else's
+                    close_requested |= closed(display.poll_input());
+                    for surface in other_surfaces.iter_mut() {
+                        close_requested |= closed(surface.poll_input());
+                    }
+                }
+                Some(Opcode::SetBrightness) => msg_scalar_unpack!(msg, main, secondary, _, _, {
+                    let level = main.max(secondary).min(u8::MAX as usize) as u8;
+                    display.set_brightness(level);
+                    close_requested |= closed(display.redraw());
+                    for surface in other_surfaces.iter_mut() {
+                        surface.set_brightness(level);
+                        close_requested |= closed(surface.redraw());
+                    }
+                }),
+                Some(Opcode::OpenSurface) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                    match display.open_surface(other_surfaces.len() + 1) {
+                        Some(mut surface) => {
+                            close_requested |= closed(surface.redraw());
+                            log::info!("opened display surface {}", surface.surface());
+                            xous::return_scalar(msg.sender, surface.surface())
+                                .expect("couldn't return OpenSurface request");
+                            other_surfaces.push(surface);
+                        }
+                        None => {
+                            log::warn!("this display can't open another surface");
+                            xous::return_scalar(msg.sender, display.surface())
+                                .expect("couldn't return OpenSurface request");
+                        }
+                    }
+                }),
+                Some(Opcode::SelectSurface) => msg_blocking_scalar_unpack!(msg, index, _, _, _, {
+                    match other_surfaces.iter().position(|surface| surface.surface() == index) {
+                        Some(at) => std::mem::swap(&mut display, &mut other_surfaces[at]),
+                        None if index == display.surface() => (),
+                        None => log::warn!("there's no display surface {} to select", index),
+                    }
+                    xous::return_scalar(msg.sender, display.surface())
+                        .expect("couldn't return SelectSurface request");
+                }),
+                Some(Opcode::SkippedFrames) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                    xous::return_scalar(msg.sender, display.skipped_frames() as usize)
+                        .expect("couldn't return SkippedFrames request");
+                }),
+                Some(Opcode::PresentedFrames) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                    let last = display.last_frame().unwrap_or_default();
+                    xous::return_scalar2(msg.sender, last.frame as usize, last.at_ms as usize)
+                        .expect("couldn't return PresentedFrames request");
+                }),
                 Some(Opcode::TestPattern) => msg_blocking_scalar_unpack!(msg, duration, _, _, _, {
+                    let info = display.info();
                     let mut stashmem = xous::syscall::map_memory(
                         None,
                         None,
-                        ((backend::FB_SIZE * 4) + 4096) & !4095,
+                        ((info.fb_size_words * 4) + 4096) & !4095,
                         xous::MemoryFlags::R | xous::MemoryFlags::W,
                     ).expect("couldn't map stash frame buffer");
-                    let stash = &mut stashmem.as_slice_mut()[..backend::FB_SIZE];
-                    for (&src, dst) in display.as_slice().iter().zip(stash.iter_mut()) {
-                        *dst = src;
-                    }
-                    for lines in 0..backend::FB_LINES { // mark all lines dirty
-                        stash[lines * backend::FB_WIDTH_WORDS + (backend::FB_WIDTH_WORDS - 1)] |= 0x1_0000;
+                    let stash: &mut [u32; backend::FB_SIZE] = (&mut stashmem.as_slice_mut()[..info.fb_size_words]).try_into().unwrap();
+                    *stash = display.copy_framebuffer();
+                    for lines in 0..info.height { // mark all lines dirty
+                        stash[lines * info.words_per_line + (info.words_per_line - 1)] |= 0x1_0000;
                     }
 
                     let start_time = ticktimer.elapsed_ms();
                     let mut testmem = xous::syscall::map_memory(
                         None,
                         None,
-                        ((backend::FB_SIZE * 4) + 4096) & !4095,
+                        ((info.fb_size_words * 4) + 4096) & !4095,
                         xous::MemoryFlags::R | xous::MemoryFlags::W,
                     ).expect("couldn't map stash frame buffer");
-                    let testpat = &mut testmem.as_slice_mut()[..backend::FB_SIZE];
+                    let testpat: &mut [u32; backend::FB_SIZE] = (&mut testmem.as_slice_mut()[..info.fb_size_words]).try_into().unwrap();
                     const DWELL: usize = 1000;
-                    while ticktimer.elapsed_ms() - start_time < duration as u64 {
+                    while ticktimer.elapsed_ms() - start_time < duration as u64 && !close_requested {
                         // all black
                         for w in testpat.iter_mut() {
                             *w = 0;
                         }
-                        for lines in 0..backend::FB_LINES { // mark dirty bits
-                            testpat[lines * backend::FB_WIDTH_WORDS + (backend::FB_WIDTH_WORDS - 1)] |= 0x1_0000;
+                        for lines in 0..info.height { // mark dirty bits
+                            testpat[lines * info.words_per_line + (info.words_per_line - 1)] |= 0x1_0000;
                         }
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
                         // all white
@@ -553,60 +702,55 @@ fn wrapped_main() -> ! {
                         }
                         // dirty bits already set
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
                         // vertical bars
-                        for lines in 0..backend::FB_LINES {
-                            for words in 0..backend::FB_WIDTH_WORDS {
-                                testpat[lines * backend::FB_WIDTH_WORDS + words] = 0xaaaa_aaaa;
+                        for lines in 0..info.height {
+                            for words in 0..info.words_per_line {
+                                testpat[lines * info.words_per_line + words] = 0xaaaa_aaaa;
                             }
                         }
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
-                        for lines in 0..backend::FB_LINES {
-                            for words in 0..backend::FB_WIDTH_WORDS {
-                                testpat[lines * backend::FB_WIDTH_WORDS + words] = 0x5555_5555;
+                        for lines in 0..info.height {
+                            for words in 0..info.words_per_line {
+                                testpat[lines * info.words_per_line + words] = 0x5555_5555;
                             }
                         }
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
                         // horiz bars
-                        for lines in 0..backend::FB_LINES {
-                            for words in 0..backend::FB_WIDTH_WORDS {
+                        for lines in 0..info.height {
+                            for words in 0..info.words_per_line {
                                 if lines % 2 == 0 {
-                                    testpat[lines * backend::FB_WIDTH_WORDS + words] = 0x0;
+                                    testpat[lines * info.words_per_line + words] = 0x0;
                                 } else {
-                                    testpat[lines * backend::FB_WIDTH_WORDS + words] = 0xffff_ffff;
+                                    testpat[lines * info.words_per_line + words] = 0xffff_ffff;
                                 }
                             }
-                            testpat[lines * backend::FB_WIDTH_WORDS + (backend::FB_WIDTH_WORDS - 1)] |= 0x1_0000;
+                            testpat[lines * info.words_per_line + (info.words_per_line - 1)] |= 0x1_0000;
                         }
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
-                        for lines in 0..backend::FB_LINES {
-                            for words in 0..backend::FB_WIDTH_WORDS {
+                        for lines in 0..info.height {
+                            for words in 0..info.words_per_line {
                                 if lines % 2 == 1 {
-                                    testpat[lines * backend::FB_WIDTH_WORDS + words] = 0x0;
+                                    testpat[lines * info.words_per_line + words] = 0x0;
                                 } else {
-                                    testpat[lines * backend::FB_WIDTH_WORDS + words] = 0xffff_ffff;
+                                    testpat[lines * info.words_per_line + words] = 0xffff_ffff;
                                 }
                             }
-                            testpat[lines * backend::FB_WIDTH_WORDS + (backend::FB_WIDTH_WORDS - 1)] |= 0x1_0000;
+                            testpat[lines * info.words_per_line + (info.words_per_line - 1)] |= 0x1_0000;
                         }
                         display.blit_screen(testpat);
-                        display.update();
-                        display.redraw();
+                        close_requested |= closed(display.update().and_then(|()| display.redraw()));
                         ticktimer.sleep_ms(DWELL).unwrap();
 
                     }
@@ -622,12 +766,25 @@ fn wrapped_main() -> ! {
                     }
                 }
                 Some(Opcode::Pop) => {
-                    display.pop();
+                    close_requested |= closed(display.pop());
                     match msg.body { // ack the message if it's a blocking scalar
                         xous::Message::BlockingScalar(_) => xous::return_scalar(msg.sender, 1).unwrap(),
                         _ => ()
                     }
                 }
+                Some(Opcode::WaitForVsync) => {
+                    // answered once the frame last flushed is put up, which may have been already
+                    if let Some((frame, at_ms)) = vsync.wait(msg.sender) {
+                        xous::return_scalar2(msg.sender, frame, at_ms).expect("couldn't answer a vsync wait");
+                    } else {
+                        close_requested |= closed(display.report_frame_complete());
+                    }
+                }
+                Some(Opcode::FrameComplete) => msg_scalar_unpack!(msg, frame, at_ms, _, _, {
+                    for waiter in vsync.frame_complete(frame, at_ms) {
+                        xous::return_scalar2(waiter, frame, at_ms).expect("couldn't answer a vsync wait");
+                    }
+                }),
                 Some(Opcode::Quit) => break,
                 None => {
                     log::error!("received opcode scalar that is not handled");
@@ -637,6 +794,31 @@ fn wrapped_main() -> ! {
             // this is effectively an abort, because this is long enough for the WDT to fire and reboot the system
             ticktimer.sleep_ms(10_000).unwrap();
         }
+        // input from every surface's window counts, whichever's being drawn to
+        for surface in std::iter::once(&mut display).chain(other_surfaces.iter_mut()) {
+            for c in surface.drain_keys() {
+                inject_key(c);
+            }
+            for event in surface.drain_pointer_events() {
+                if let Some((cid, op)) = pointer_listener {
+                    // not blocking: the listener could be waiting on this server
+                    if let Err(e) = xous::try_send_message(cid, xous::Message::new_scalar(op,
+                        event.action as usize, event.at.x as usize, event.at.y as usize, surface.surface())) {
+                        log::warn!("couldn't send a pointer event, dropping it: {:?}", e);
+                    }
+                }
+            }
+        }
+        if close_requested && !shutdown_requested {
+            // shut down the whole system, so every server gets taken down and not just this one;
+            // meanwhile, carry on serving as headless
+            log::info!("display closed, shutting down");
+            shutdown_requested = true;
+            if let Err(e) = susres::Susres::new_without_hook(&xns).and_then(|s| s.immediate_poweroff()) {
+                log::error!("couldn't ask for a shutdown, quitting: {:?}", e);
+                break;
+            }
+        }
     }
     log::trace!("main loop exit, destroying servers");
     xns.unregister_server(sid).unwrap();
@@ -644,3 +826,95 @@ fn wrapped_main() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+#[cfg(all(test, not(target_os = "xous")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless() {
+        // boots and draws the way the server does, with no window to draw in
+        let mut display = XousDisplay::headless();
+        draw_boot_logo(&mut display);
+        display.redraw().unwrap();
+        let light = DrawStyle::new(PixelColor::Light, PixelColor::Light, 1);
+        let dark = DrawStyle::new(PixelColor::Dark, PixelColor::Dark, 1);
+        let screen = Rectangle::new(Point::new(0, 0), display.screen_size());
+        op::rectangle(display.native_buffer(), Rectangle::new_with_style(Point::new(0, 0), Point::new(335, 535), light), None);
+        op::rectangle(display.native_buffer(), Rectangle::new_with_style(Point::new(10, 20), Point::new(41, 29), dark), Some(screen));
+        display.redraw().unwrap();
+        // and there's no window to be closed
+        assert!(display.update().is_ok());
+
+        let fb = display.copy_framebuffer();
+        let info = display.info();
+        let is_dark = |x: usize, y: usize| fb[y * info.words_per_line + x / 32] & (1 << (x % 32)) == 0;
+        assert!(is_dark(10, 20) && is_dark(41, 29));
+        assert!(!is_dark(9, 20) && !is_dark(42, 29) && !is_dark(10, 30));
+        let dark_pixels = (0..info.height).flat_map(|y| (0..info.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| is_dark(x, y))
+            .count();
+        assert_eq!(dark_pixels, 32 * 10);
+
+        // nothing's changed since, so there's nothing to put up
+        display.redraw().unwrap();
+        assert_eq!(display.skipped_frames(), 1);
+        assert_eq!(display.frame_stats().frames, 2);
+    }
+
+    #[test]
+    fn test_golden_text() {
+        // text typeset and drawn the way DrawTextView does it, checked against a golden image; run
+        // with XOUS_REGENERATE_GOLDEN set to write it afresh when the text is meant to look different
+        let mut display = XousDisplay::headless();
+        let light = DrawStyle::new(PixelColor::Light, PixelColor::Light, 1);
+        let screen = Rectangle::new(Point::new(0, 0), display.screen_size());
+        op::rectangle(display.native_buffer(), Rectangle::new_with_style(Point::new(0, 0), Point::new(335, 535), light), None);
+        let mut typesetter = Typesetter::setup(
+            "Hello, Precursor!\nThe quick brown fox jumps over the lazy dog.",
+            &Pt::new(296, 200),
+            &GlyphStyle::Regular,
+            None,
+        );
+        let composition = typesetter.typeset(OverflowStrategy::Ellipsis);
+        composition.render(display.native_buffer(), Point::new(20, 40), false, screen);
+        display.redraw().unwrap();
+
+        let golden = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/hello.pbm");
+        backend::testing::assert_golden(&display.copy_framebuffer(), &golden);
+    }
+
+    #[test]
+    fn test_scripted_typing() {
+        // end to end: a script types, its keys go through the same queues and translation as the
+        // window's, the text's drawn each frame as it stands, and three frames on it's checked
+        // against a golden image
+        let mut display = XousDisplay::headless();
+        let done = std::rc::Rc::new(std::cell::Cell::new(false));
+        let script = backend::InputScript::parse("type hello\nframes 3\n").unwrap().on_done({
+            let done = done.clone();
+            move || done.set(true)
+        });
+        display.play_script(script);
+        let mut typed = String::new();
+        let light = DrawStyle::new(PixelColor::Light, PixelColor::Light, 1);
+        let screen = Rectangle::new(Point::new(0, 0), display.screen_size());
+        let mut frames = 0;
+        while !done.get() {
+            assert!(frames < 10, "the script didn't play through");
+            display.poll_input().unwrap();
+            typed.extend(display.drain_keys());
+            op::rectangle(display.native_buffer(), Rectangle::new_with_style(Point::new(0, 0), Point::new(335, 535), light), None);
+            let mut typesetter = Typesetter::setup(&typed, &Pt::new(296, 200), &GlyphStyle::Regular, None);
+            typesetter.typeset(OverflowStrategy::Ellipsis).render(display.native_buffer(), Point::new(20, 40), false, screen);
+            display.redraw().unwrap();
+            frames += 1;
+        }
+        // typed all at once, then three frames more
+        assert_eq!((typed.as_str(), frames), ("hello", 4));
+        assert!(!display.is_playing_script());
+
+        let golden = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/typed-hello.pbm");
+        backend::testing::assert_golden(&display.copy_framebuffer(), &golden);
+    }
+}
@@ -0,0 +1,84 @@
+//! Apps waiting with `WaitForVsync` for the frame they flushed to go up. A flush is sent
+//! without waiting for it to be done, so the frame it puts up can be reported before the wait
+//! for it comes in; the last frame is kept to answer such a wait with at once, rather than
+//! leaving it for a frame that may never come. A flush with nothing new in it puts no frame up,
+//! and has the one already up reported again, with the same number, so any frame reported since
+//! the flush is the one it's waited for.
+
+/// Who's waiting for a frame to go up, and the last that did
+#[derive(Debug, Default)]
+pub struct VsyncWaits {
+    waiters: Vec<xous::MessageSender>,
+    /// the number of the last frame put up, and when, in milliseconds since the display started
+    last_frame: Option<(usize, usize)>,
+    /// there's been a flush, and no frame reported since
+    flush_pending: bool,
+}
+impl VsyncWaits {
+    /// Notes a flush: waits from now on are for a frame reported after it
+    pub fn flushed(&mut self) {
+        self.flush_pending = true;
+    }
+    /// Takes a wait from `sender`. Returns the frame to answer it with at once, if one's been
+    /// reported since the last flush; otherwise `sender` is kept for `frame_complete()` to answer.
+    pub fn wait(&mut self, sender: xous::MessageSender) -> Option<(usize, usize)> {
+        match self.last_frame {
+            Some((frame, at_ms)) if !self.flush_pending => Some((frame, at_ms)),
+            _ => {
+                self.waiters.push(sender);
+                None
+            }
+        }
+    }
+    /// Notes frame `frame` going up at `at_ms`, or being reported again, returning the waits to
+    /// answer with it
+    pub fn frame_complete(&mut self, frame: usize, at_ms: usize) -> Vec<xous::MessageSender> {
+        self.last_frame = Some((frame, at_ms));
+        self.flush_pending = false;
+        std::mem::take(&mut self.waiters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_then_wait() {
+        let (a, b) = (xous::MessageSender::from_usize(1), xous::MessageSender::from_usize(2));
+        let mut vsync = VsyncWaits::default();
+        // nothing's gone up yet
+        vsync.flushed();
+        assert_eq!(vsync.wait(a), None);
+        assert_eq!(vsync.frame_complete(0, 16), vec![a]);
+
+        // the flushed frame goes up before the wait for it comes in: it's answered at once
+        vsync.flushed();
+        assert_eq!(vsync.frame_complete(1, 33), vec![]);
+        assert_eq!(vsync.wait(a), Some((1, 33)));
+        assert_eq!(vsync.wait(b), Some((1, 33)));
+
+        // the wait comes in first, and is answered when the frame goes up
+        vsync.flushed();
+        assert_eq!(vsync.wait(a), None);
+        assert_eq!(vsync.wait(b), None);
+        assert_eq!(vsync.frame_complete(2, 50), vec![a, b]);
+        assert_eq!(vsync.frame_complete(3, 66), vec![]);
+    }
+
+    #[test]
+    fn test_flush_with_nothing_new() {
+        let (a, b) = (xous::MessageSender::from_usize(1), xous::MessageSender::from_usize(2));
+        let mut vsync = VsyncWaits::default();
+        vsync.flushed();
+        vsync.frame_complete(1, 16);
+        // the frame already up is reported again for a flush that changes nothing, before the
+        // wait comes in or after
+        vsync.flushed();
+        assert_eq!(vsync.frame_complete(1, 16), vec![]);
+        assert_eq!(vsync.wait(a), Some((1, 16)));
+        vsync.flushed();
+        assert_eq!(vsync.wait(b), None);
+        assert_eq!(vsync.frame_complete(1, 16), vec![b]);
+    }
+}
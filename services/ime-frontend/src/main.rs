@@ -67,11 +67,17 @@ struct InputTracker {
     menu_mode: bool,
 
     /// render the predictions. Slightly awkward because this code comes from before we had libstd
-    pred_options: [Option<String>; MAX_PREDICTION_OPTIONS],
+    pred_options: [Option<PredOption>; MAX_PREDICTION_OPTIONS],
     #[cfg(feature = "tts")]
     tts: TtsFrontend,
 }
 
+/// A prediction as offered to the user: `display` is rendered, `commit` is inserted when picked.
+struct PredOption {
+    display: String,
+    commit: String,
+}
+
 impl InputTracker {
     pub fn new(xns: &xous_names::XousNames)-> InputTracker {
         InputTracker {
@@ -189,7 +195,7 @@ impl InputTracker {
         let debug1 = false;
         if debug1{info!("IMEF|insert_prediction index {}", index);}
         let pred_str = match &self.pred_options[index] {
-            Some(s) => s,
+            Some(p) => &p.commit,
             _ => return // if the index doesn't exist for some reason, do nothing without throwing an error
         };
         if debug1{info!("IMEF|insert_prediction string {}, last_trigger {:?}", pred_str, self.last_trigger_char);}
@@ -601,7 +607,10 @@ impl InputTracker {
                     for i in 0..self.pred_options.len() {
                         let p = if let Some(prediction) =
                         pred.get_prediction(i as u32, api_token).expect("couldn't query prediction engine") {
-                            Some(String::from(prediction.as_str().unwrap_or("UTF-8 Error")))
+                            Some(PredOption {
+                                display: String::from(prediction.display.as_str().unwrap_or("UTF-8 Error")),
+                                commit: String::from(prediction.commit.as_str().unwrap_or("UTF-8 Error")),
+                            })
                         } else {
                             None
                         };
@@ -638,7 +647,7 @@ impl InputTracker {
 
                 let mut i = 0;
                 for p in self.pred_options.iter() {
-                    if let Some(pred_str) = p {
+                    if let Some(pred) = p {
                         // the post-clip is necessary because the approx_width is rounded to some integer fraction
                         let p_clip = Rectangle::new(
                             Point::new(i * approx_width, 1),
@@ -658,7 +667,7 @@ impl InputTracker {
                         p_tv.clear_area = false;
                         p_tv.ellipsis = true;
                         p_tv.style = GlyphStyle::Regular;
-                        write!(p_tv.text, "{}", pred_str).expect("can't write the prediction string");
+                        write!(p_tv.text, "{}", pred.display).expect("can't write the prediction string");
                         log::trace!("posting string with length {}", p_tv.text.as_str().unwrap().len());
                         self.gam.post_textview(&mut p_tv).expect("couldn't post prediction text");
                         i += 1;
@@ -731,12 +740,20 @@ fn main() -> ! {
                     match xns.request_connection_with_token(s.as_str().unwrap()) {
                         Ok((pc, token)) => {
                             let pred = ime_plugin_api::PredictionPlugin {connection: Some(pc)};
+                            // start the model loading now, so it's ready by the time the first input arrives
+                            pred.warm_up().ok();
                             match pred.acquire(descriptor.predictor_token) {
                                 Ok(confirmation) => {
                                     api_token = Some(ApiToken {
                                         api_token: confirmation,
                                         gam_token: descriptor.token,
                                     });
+                                    match pred.self_check() {
+                                        Ok(check) if !check.is_healthy() => log::warn!("predictor {} is degraded, failed checks: {:?}",
+                                            s.as_str().unwrap(), check.failed_checks()),
+                                        Ok(_) => (),
+                                        Err(e) => log::warn!("predictor {} couldn't be checked: {:?}", s.as_str().unwrap(), e),
+                                    }
                                 },
                                 Err(e) => log::error!("Internal error: {:?}", e),
                             }
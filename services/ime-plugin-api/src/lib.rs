@@ -11,8 +11,227 @@ use xous_ipc::{Buffer, String};
 pub struct Prediction {
     pub index: u32,
     pub valid: bool,
+    /// the text that is committed to the input line when this prediction is picked
     pub string: String<1000>,
+    /// the text shown to the user for this prediction. Normally identical to `string`, but it can
+    /// carry extra context, e.g. "café (fr)" for a prediction that commits "café".
+    pub display: String<4096>,
     pub api_token: [u32; 4],
+    /// where the prediction came from, a `PredictionSource`
+    pub source: u8,
+    /// the digit key that picks this prediction, 1-9, or 0 for none. Filled in with
+    /// `default_shortcut()` when the prediction is requested; a plugin can change it, e.g. to
+    /// skip digits it reserves, so the candidate bar and the key handler agree on the mapping.
+    pub shortcut: u8,
+}
+impl Prediction {
+    /// sets both the commit and display forms to `s`, truncating each to fit
+    pub fn set(&mut self, s: &str) {
+        copy_truncated(&mut self.string, s);
+        copy_truncated(&mut self.display, s);
+    }
+    /// overrides the display form set by `set()`
+    pub fn set_display(&mut self, s: &str) {
+        copy_truncated(&mut self.display, s);
+    }
+    pub fn set_source(&mut self, source: PredictionSource) {
+        self.source = source.to_u8().unwrap();
+    }
+}
+
+/// Where a prediction came from
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum PredictionSource {
+    /// the plugin's model
+    Model = 0,
+    /// the token being typed, offered back as-is because the model had nothing; see
+    /// `Opcode::SetEchoFallback`
+    Echo = 1,
+    /// a multi-word phrase completing the words at the end of the input; see `Opcode::SetPhrases`
+    Phrase = 2,
+    /// a word one edit away from a token that nothing starts with; see `Opcode::SetSpellCorrect`
+    Correction = 3,
+}
+impl Default for PredictionSource {
+    fn default() -> PredictionSource {
+        PredictionSource::Model
+    }
+}
+
+/// Returns the typed token to offer as prediction `index` when the echo fallback is `enabled`:
+/// it's offered as the only prediction, and only when there are no real `candidates`.
+pub fn echo_fallback(enabled: bool, index: u32, candidates: usize, typed: &str) -> Option<&str> {
+    if enabled && index == 0 && candidates == 0 && !typed.is_empty() {
+        Some(typed)
+    } else {
+        None
+    }
+}
+/// The shortcut digit for prediction `index`: the digits 1-9 in order, and none past the ninth
+pub fn default_shortcut(index: u32) -> u8 {
+    if index < 9 {
+        index as u8 + 1
+    } else {
+        0
+    }
+}
+/// copies as many whole characters of `s` as will fit into `dst`
+fn copy_truncated<const N: usize>(dst: &mut String<N>, s: &str) {
+    dst.clear();
+    for ch in s.chars() {
+        if dst.push(ch).is_err() {
+            break;
+        }
+    }
+}
+
+/// Request/response record for `Opcode::InlineCompletion`
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct InlineCompletion {
+    /// the text that would complete the current input token; empty if there is no completion
+    pub suffix: String<1000>,
+    pub api_token: [u32; 4],
+}
+
+/// Describes the dictionary behind a plugin's predictions, as returned by `Opcode::GetDictionaryInfo`
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct DictionaryInfo {
+    /// language of the dictionary, e.g. "en-US"; truncated to fit
+    pub language: String<32>,
+    /// number of words in the base dictionary, not counting anything learned at run-time
+    pub word_count: u32,
+    /// version of the dictionary data, as defined by the plugin
+    pub version: u32,
+}
+impl DictionaryInfo {
+    pub fn new(language: &str, word_count: u32, version: u32) -> DictionaryInfo {
+        let mut info = DictionaryInfo { language: String::new(), word_count, version };
+        copy_truncated(&mut info.language, language);
+        info
+    }
+}
+
+/// Result of `Opcode::SelfCheck`: a bitfield of the plugin's internal consistency checks, with
+/// a bit set for each check that passed. A check that doesn't apply to a plugin, e.g. the
+/// dictionary check of a plugin that has no dictionary, is reported as passed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SelfCheck(pub u32);
+impl SelfCheck {
+    /// the dictionary or model behind the predictions is loaded
+    pub const DICTIONARY_LOADED: u32 = 1 << 0;
+    /// the pick history of the current client can be read, and is well formed
+    pub const HISTORY_ACCESSIBLE: u32 = 1 << 1;
+    /// the plugin's internal buffers, e.g. the current input, are within their bounds
+    pub const BUFFERS_SANE: u32 = 1 << 2;
+    /// every check, as reported by a healthy plugin
+    pub const ALL: u32 = SelfCheck::DICTIONARY_LOADED | SelfCheck::HISTORY_ACCESSIBLE | SelfCheck::BUFFERS_SANE;
+
+    pub fn new(dictionary_loaded: bool, history_accessible: bool, buffers_sane: bool) -> SelfCheck {
+        let mut bits = 0;
+        if dictionary_loaded {
+            bits |= SelfCheck::DICTIONARY_LOADED;
+        }
+        if history_accessible {
+            bits |= SelfCheck::HISTORY_ACCESSIBLE;
+        }
+        if buffers_sane {
+            bits |= SelfCheck::BUFFERS_SANE;
+        }
+        SelfCheck(bits)
+    }
+    pub fn is_healthy(&self) -> bool {
+        self.failures() == 0
+    }
+    /// the bits of the checks that failed
+    pub fn failures(&self) -> u32 {
+        !self.0 & SelfCheck::ALL
+    }
+    /// names of the checks that failed, for logging
+    pub fn failed_checks(&self) -> Vec<&'static str> {
+        [
+            (SelfCheck::DICTIONARY_LOADED, "dictionary"),
+            (SelfCheck::HISTORY_ACCESSIBLE, "history"),
+            (SelfCheck::BUFFERS_SANE, "buffers"),
+        ]
+        .iter()
+        .filter(|(bit, _)| self.failures() & bit != 0)
+        .map(|&(_, name)| name)
+        .collect()
+    }
+}
+
+/// Returns the part of `candidate` that would complete `typed`, or an empty string if
+/// `candidate` doesn't extend `typed`.
+pub fn completion_suffix<'a>(typed: &str, candidate: &'a str) -> &'a str {
+    if typed.is_empty() {
+        return "";
+    }
+    candidate.strip_prefix(typed).unwrap_or("")
+}
+
+/// How an `Input` was entered, which decides how strictly predictions have to match it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum InputSource {
+    /// typed key by key: a prediction must start with exactly what was typed
+    Tap = 0,
+    /// traced with a swipe gesture: the trace passes over letters that aren't in the word, so a
+    /// prediction only has to start with the same letter and have its letters, in order, somewhere
+    /// along the trace
+    Swipe = 1,
+    /// pasted in one go: the text isn't being typed, so it doesn't steer the ranking
+    Paste = 2,
+}
+impl Default for InputSource {
+    fn default() -> InputSource {
+        InputSource::Tap
+    }
+}
+impl InputSource {
+    /// Returns `true` if `candidate` is a match for the token `typed` entered from this source.
+    /// Matching is case-insensitive, and nothing matches an empty token.
+    pub fn matches(&self, typed: &str, candidate: &str) -> bool {
+        if typed.is_empty() {
+            return false;
+        }
+        let mut typed_chars = typed.chars().flat_map(char::to_lowercase);
+        let mut candidate_chars = candidate.chars().flat_map(char::to_lowercase);
+        match self {
+            InputSource::Tap => typed_chars.all(|t| candidate_chars.next() == Some(t)),
+            InputSource::Swipe => {
+                if typed_chars.next() != candidate_chars.next() {
+                    return false;
+                }
+                // a doubled letter is traced once
+                let mut previous = None;
+                candidate_chars.all(|c| {
+                    let repeat = previous == Some(c);
+                    previous = Some(c);
+                    repeat || typed_chars.any(|t| t == c)
+                })
+            }
+            InputSource::Paste => false,
+        }
+    }
+}
+
+/// Request record for `Opcode::SourcedInput`
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct SourcedInput {
+    pub input: String<4000>,
+    /// an `InputSource`
+    pub source: u8,
+}
+
+/// A prediction as returned by `PredictionApi::get_prediction()`
+#[derive(Debug, Copy, Clone)]
+pub struct Candidate {
+    /// what to show the user
+    pub display: String<4096>,
+    /// what to insert if the candidate is picked
+    pub commit: String<4000>,
+    pub source: PredictionSource,
+    /// the digit key that picks the candidate, or 0 for none; see `Prediction::shortcut`
+    pub shortcut: u8,
 }
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct AcquirePredictor {
@@ -49,6 +268,23 @@ impl From<PredictionTriggers> for usize {
         ret
     }
 }
+impl PredictionTriggers {
+    /// returns `true` if `c` marks the end of a predictive unit under this set of triggers
+    pub fn is_trigger(&self, c: char) -> bool {
+        (self.newline && (c == '\n' || c == '\r'))
+            || (self.punctuation && c.is_ascii_punctuation())
+            || (self.whitespace && c.is_ascii_whitespace())
+    }
+    /// splits `s` into the predictive units delimited by this set of triggers. Empty units
+    /// (e.g. from runs of consecutive trigger characters) are skipped.
+    pub fn tokenize<'a>(self, s: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        s.split(move |c: char| self.is_trigger(c)).filter(|t| !t.is_empty())
+    }
+    /// returns the partial unit at the end of `s`, i.e. everything after the last trigger
+    pub fn current_token<'a>(&self, s: &'a str) -> &'a str {
+        s.rsplit(|c: char| self.is_trigger(c)).next().unwrap_or("")
+    }
+}
 impl From<usize> for PredictionTriggers {
     fn from(code: usize) -> PredictionTriggers {
         PredictionTriggers {
@@ -59,14 +295,105 @@ impl From<usize> for PredictionTriggers {
     }
 }
 
+/// How a plugin splits text into tokens, both for finding the current token of an `Input` and
+/// for learning from `Picked` and `FeedbackBatch`. Set with `Opcode::SetTokenizer`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum TokenizerMode {
+    /// tokens are delimited by the plugin's `PredictionTriggers`
+    Whitespace = 0,
+    /// every character is a token, for scripts such as Chinese where a character is a word
+    Character = 1,
+    /// the longest dictionary word at each position is a token, for scripts such as Thai that
+    /// don't put spaces between words. A character that starts no dictionary word is a token
+    /// on its own.
+    Dictionary = 2,
+}
+impl Default for TokenizerMode {
+    fn default() -> TokenizerMode {
+        TokenizerMode::Whitespace
+    }
+}
+
+/// longest word looked up by `TokenizerMode::Dictionary`, in characters
+pub const MAX_DICTIONARY_WORD_CHARS: usize = 16;
+
+/// Splits text into tokens. Text is first split into units at the `triggers`, and each unit is
+/// then segmented according to the `mode`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Tokenizer {
+    pub mode: TokenizerMode,
+    pub triggers: PredictionTriggers,
+}
+impl Tokenizer {
+    /// Splits `s` into tokens. `is_word` is the dictionary for `TokenizerMode::Dictionary`, and
+    /// is not consulted in the other modes.
+    pub fn tokenize<'a, F: Fn(&str) -> bool>(&self, s: &'a str, is_word: F) -> Vec<&'a str> {
+        let mut tokens = Vec::new();
+        for unit in self.triggers.tokenize(s) {
+            self.segment(unit, &is_word, &mut tokens);
+        }
+        tokens
+    }
+    /// returns the partial token at the end of `s`
+    pub fn current_token<'a, F: Fn(&str) -> bool>(&self, s: &'a str, is_word: F) -> &'a str {
+        let mut tokens = Vec::new();
+        self.segment(self.triggers.current_token(s), &is_word, &mut tokens);
+        tokens.pop().unwrap_or("")
+    }
+    fn segment<'a, F: Fn(&str) -> bool>(&self, unit: &'a str, is_word: &F, tokens: &mut Vec<&'a str>) {
+        match self.mode {
+            TokenizerMode::Whitespace => {
+                if !unit.is_empty() {
+                    tokens.push(unit);
+                }
+            }
+            TokenizerMode::Character => {
+                tokens.extend(unit.char_indices().map(|(i, c)| &unit[i..i + c.len_utf8()]));
+            }
+            TokenizerMode::Dictionary => {
+                let mut rest = unit;
+                while let Some(first) = rest.chars().next() {
+                    // greedy longest match, falling back to a single character
+                    let len = rest
+                        .char_indices()
+                        .map(|(i, c)| i + c.len_utf8())
+                        .take(MAX_DICTIONARY_WORD_CHARS)
+                        .filter(|&end| is_word(&rest[..end]))
+                        .last()
+                        .unwrap_or(first.len_utf8());
+                    tokens.push(&rest[..len]);
+                    rest = &rest[len..];
+                }
+            }
+        }
+    }
+}
+
+/// State of a plugin's prediction model, as reported by `Opcode::GetReadyState`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum ReadyState {
+    /// the model has not been loaded; predictions may come back empty
+    Cold = 0,
+    /// a `WarmUp` has been received and the model is loading
+    WarmingUp = 1,
+    /// predictions are available
+    Ready = 2,
+}
+
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
 pub enum Opcode {
-    /// update with the latest input candidate. Replaces the previous input.
+    /// update with the latest input candidate. Replaces the previous input. The input is taken
+    /// to be typed, i.e. from `InputSource::Tap`; see `SourcedInput` for other sources.
     Input, //(String<4000>),
 
     /// feed back to the IME plugin as to what was picked, so predictions can be updated
     Picked, //(String<4000>),
 
+    /// feed back a block of text (e.g. a paste) in a single call. The text is split into tokens
+    /// using the plugin's `PredictionTriggers`, and every token is recorded as picked: tokens already
+    /// in the history have their frequency bumped, and new tokens are added.
+    FeedbackBatch, //(String<4096>),
+
     /// Undo the last Picked value. To be used when a user hits backspace after picking a prediction
     /// note that repeated calls to Unpick will have an implementation-defined behavior
     Unpick,
@@ -75,10 +402,66 @@ pub enum Opcode {
     /// if there is no prediction available, just return an empty string
     Prediction, //(Prediction),
 
+    /// return the suffix that would complete the current `Input` token to the top prediction, for
+    /// rendering inline after the cursor. The suffix is empty if there is no top prediction, or
+    /// if it doesn't start with what has been typed so far.
+    InlineCompletion, //(InlineCompletion),
+
     /// return the prediction triggers used by this IME. These are characters that can indicate that a
     /// whole predictive unit has been entered.
     GetPredictionTriggers,
 
+    /// start loading the prediction model in the background, so the first `Input` doesn't race
+    /// the model's lazy initialization. Non-blocking. Idempotent: a `WarmUp` sent while the model
+    /// is loading or already loaded has no effect.
+    WarmUp,
+
+    /// return the `ReadyState` of the prediction model
+    GetReadyState,
+
+    /// set the `TokenizerMode` used to split input and feedback into tokens. Defaults to
+    /// `TokenizerMode::Whitespace`.
+    SetTokenizer, //(TokenizerMode)
+
+    /// return a `DictionaryInfo` describing the plugin's base dictionary
+    GetDictionaryInfo,
+
+    /// set how long the input has to be quiet before a burst of `Input`s is acted on, in ms.
+    /// 0 acts on every `Input`. Queries always see the latest input. See `InputDebouncer`.
+    SetInputDebounce, //(u32)
+
+    /// sent by a plugin to itself when an input debounce period may have ended
+    InputDebounceElapsed,
+
+    /// as `Input`, but tagged with the `InputSource` it was entered from, so the plugin can match
+    /// it more loosely for e.g. a swipe. The source applies until the next input.
+    SourcedInput, //(SourcedInput),
+
+    /// when set, and there are no predictions, prediction 0 is the current `Input` token itself,
+    /// with a `PredictionSource::Echo` source, so there is always something to pick. Defaults to
+    /// off.
+    SetEchoFallback, //(bool)
+
+    /// when set, and the words at the end of the `Input` start a known phrase, prediction 0 is
+    /// the rest of the phrase, with a `PredictionSource::Phrase` source. Its `string` commits
+    /// the phrase from the current token on, and its `display` is the whole phrase. Defaults to
+    /// off.
+    SetPhrases, //(bool)
+
+    /// when set, and nothing in the dictionary matches the current `Input` token, the words one
+    /// edit away from it (a letter added, dropped, changed, or two swapped) are offered first,
+    /// most frequent first, with a `PredictionSource::Correction` source. Defaults to off.
+    SetSpellCorrect, //(bool)
+
+    /// run a quick internal consistency check, and return a `SelfCheck` bitfield of the checks
+    /// that passed
+    SelfCheck,
+
+    /// the edit focus has moved to another field: forget the current `Input` and the pick that
+    /// `Unpick` would undo, so a half-typed word from the previous field doesn't lead the new
+    /// field's predictions. What has been learned from picks is kept.
+    NewField,
+
     /// Access control & data cleanup
     Acquire,
     Release,
@@ -90,8 +473,36 @@ pub trait PredictionApi {
     fn get_prediction_triggers(&self) -> Result<PredictionTriggers, xous::Error>;
     fn unpick(&self) -> Result<(), xous::Error>;
     fn set_input(&self, s: String<4000>) -> Result<(), xous::Error>;
+    /// as `set_input()`, for input entered some other way than typing; see `Opcode::SourcedInput`
+    fn set_input_from(&self, s: String<4000>, source: InputSource) -> Result<(), xous::Error>;
     fn feedback_picked(&self, s: String<4000>) -> Result<(), xous::Error>;
-    fn get_prediction(&self, index: u32, api_token: [u32; 4]) -> Result<Option<String<4000>>, xous::Error>;
+    /// records every token in `s` as picked, in one round-trip to the plugin
+    fn feedback_batch(&self, s: String<4096>) -> Result<(), xous::Error>;
+    fn get_prediction(&self, index: u32, api_token: [u32; 4]) -> Result<Option<Candidate>, xous::Error>;
+    /// the suffix that completes the current input token to the top prediction; see `Opcode::InlineCompletion`
+    fn get_inline_completion(&self, api_token: [u32; 4]) -> Result<String<1000>, xous::Error>;
+    /// kicks off loading of the prediction model and returns immediately. Safe to call more than once.
+    fn warm_up(&self) -> Result<(), xous::Error>;
+    fn get_ready_state(&self) -> Result<ReadyState, xous::Error>;
+    /// kicks off loading of the prediction model and blocks until it is ready, or until `timeout_ms`
+    /// has elapsed, in which case `xous::Error::Timeout` is returned.
+    fn warm_up_wait(&self, timeout_ms: u64) -> Result<(), xous::Error>;
+    /// select how the plugin splits text into tokens; see `Opcode::SetTokenizer`
+    fn set_tokenizer(&self, mode: TokenizerMode) -> Result<(), xous::Error>;
+    /// language, size and version of the plugin's base dictionary
+    fn get_dictionary_info(&self) -> Result<DictionaryInfo, xous::Error>;
+    /// sets the quiet period before a burst of input is acted on; see `Opcode::SetInputDebounce`
+    fn set_input_debounce(&self, ms: u32) -> Result<(), xous::Error>;
+    /// offer the typed token back when there are no predictions; see `Opcode::SetEchoFallback`
+    fn set_echo_fallback(&self, enabled: bool) -> Result<(), xous::Error>;
+    /// offer multi-word phrases as single predictions; see `Opcode::SetPhrases`
+    fn set_phrases(&self, enabled: bool) -> Result<(), xous::Error>;
+    /// offer corrections for tokens that aren't in the dictionary; see `Opcode::SetSpellCorrect`
+    fn set_spell_correct(&self, enabled: bool) -> Result<(), xous::Error>;
+    /// checks that the plugin is healthy; see `Opcode::SelfCheck`
+    fn self_check(&self) -> Result<SelfCheck, xous::Error>;
+    /// starts over with empty input, keeping the learned history; see `Opcode::NewField`
+    fn new_field(&self) -> Result<(), xous::Error>;
     /// gets an exclusive lock on the predictor. Returns an error if the predictor is already locked.
     fn acquire(&self, api_token: Option<[u32; 4]>) -> Result<[u32; 4], xous::Error>;
     /// releases the lock. Also clears any sensitive data that may be in the predictor.
@@ -153,6 +564,19 @@ impl PredictionApi for PredictionPlugin {
         }
     }
 
+    fn set_input_from(&self, s: String<4000>, source: InputSource) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let sourced = SourcedInput { input: s, source: source.to_u8().unwrap() };
+                let buf = Buffer::into_buf(sourced).or(Err(xous::Error::InternalError))?;
+                buf.lend(cid, Opcode::SourcedInput.to_u32().unwrap())
+                    .expect("|API: set_input_from operation failure");
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
     fn feedback_picked(&self, s: String<4000>) -> Result<(), xous::Error> {
         match self.connection {
             Some(cid) => {
@@ -165,15 +589,30 @@ impl PredictionApi for PredictionPlugin {
         }
     }
 
+    fn feedback_batch(&self, s: String<4096>) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let buf = Buffer::into_buf(s).or(Err(xous::Error::InternalError))?;
+                buf.lend(cid, Opcode::FeedbackBatch.to_u32().unwrap())
+                    .expect("|API: feedback_batch operation failure");
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
     /// this function could disclose sensitive data, so it requires an API token to call
-    fn get_prediction(&self, index: u32, api_token: [u32; 4]) -> Result<Option<String<4000>>, xous::Error> {
+    fn get_prediction(&self, index: u32, api_token: [u32; 4]) -> Result<Option<Candidate>, xous::Error> {
         match self.connection {
             Some(cid) => {
                 let prediction = Prediction {
                     index,
                     string: String::<1000>::new(),
+                    display: String::<4096>::new(),
                     valid: false,
                     api_token,
+                    source: PredictionSource::Model.to_u8().unwrap(),
+                    shortcut: default_shortcut(index),
                 };
                 let mut buf = Buffer::into_buf(prediction).or(Err(xous::Error::InternalError))?;
                 buf.lend_mut(cid, Opcode::Prediction.to_u32().unwrap())
@@ -185,10 +624,11 @@ impl PredictionApi for PredictionPlugin {
                     Return::Prediction(pred) => {
                         log::trace!("|API: got {:?}", pred);
                         if pred.valid {
-                            let mut ret = String::<4000>::new();
+                            let mut commit = String::<4000>::new();
                             use core::fmt::Write as CoreWrite;
-                            write!(ret, "{}", pred.string).unwrap();
-                            Ok(Some(ret))
+                            write!(commit, "{}", pred.string).unwrap();
+                            let source = FromPrimitive::from_u8(pred.source).unwrap_or_default();
+                            Ok(Some(Candidate { display: pred.display, commit, source, shortcut: pred.shortcut }))
                         } else {
                             Ok(None)
                         }
@@ -203,6 +643,171 @@ impl PredictionApi for PredictionPlugin {
         }
     }
 
+    /// this function could disclose sensitive data, so it requires an API token to call
+    fn get_inline_completion(&self, api_token: [u32; 4]) -> Result<String<1000>, xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let completion = InlineCompletion {
+                    suffix: String::<1000>::new(),
+                    api_token,
+                };
+                let mut buf = Buffer::into_buf(completion).or(Err(xous::Error::InternalError))?;
+                buf.lend_mut(cid, Opcode::InlineCompletion.to_u32().unwrap())
+                    .or(Err(xous::Error::InternalError))?;
+                let ret = buf.to_original::<InlineCompletion, _>().or(Err(xous::Error::InternalError))?;
+                Ok(ret.suffix)
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn warm_up(&self) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::WarmUp.to_usize().unwrap(), 0, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn get_ready_state(&self) -> Result<ReadyState, xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let response = send_message(
+                    cid,
+                    Message::new_blocking_scalar(Opcode::GetReadyState.to_usize().unwrap(), 0, 0, 0, 0),
+                )?;
+                if let xous::Result::Scalar1(code) = response {
+                    FromPrimitive::from_usize(code).ok_or(xous::Error::InternalError)
+                } else {
+                    Err(xous::Error::InternalError)
+                }
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn warm_up_wait(&self, timeout_ms: u64) -> Result<(), xous::Error> {
+        self.warm_up()?;
+        wait_ready(
+            timeout_ms,
+            || self.get_ready_state(),
+            |ms| std::thread::sleep(std::time::Duration::from_millis(ms)),
+        )
+    }
+
+    fn set_tokenizer(&self, mode: TokenizerMode) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::SetTokenizer.to_usize().unwrap(), mode.to_usize().unwrap(), 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn get_dictionary_info(&self) -> Result<DictionaryInfo, xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let info = DictionaryInfo::new("", 0, 0);
+                let mut buf = Buffer::into_buf(info).or(Err(xous::Error::InternalError))?;
+                buf.lend_mut(cid, Opcode::GetDictionaryInfo.to_u32().unwrap())
+                    .or(Err(xous::Error::InternalError))?;
+                buf.to_original::<DictionaryInfo, _>().or(Err(xous::Error::InternalError))
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn set_input_debounce(&self, ms: u32) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::SetInputDebounce.to_usize().unwrap(), ms as usize, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn set_echo_fallback(&self, enabled: bool) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::SetEchoFallback.to_usize().unwrap(), enabled as usize, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn set_phrases(&self, enabled: bool) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::SetPhrases.to_usize().unwrap(), enabled as usize, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn set_spell_correct(&self, enabled: bool) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::SetSpellCorrect.to_usize().unwrap(), enabled as usize, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn self_check(&self) -> Result<SelfCheck, xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                let response = send_message(
+                    cid,
+                    Message::new_blocking_scalar(Opcode::SelfCheck.to_usize().unwrap(), 0, 0, 0, 0),
+                )?;
+                if let xous::Result::Scalar1(bits) = response {
+                    Ok(SelfCheck(bits as u32))
+                } else {
+                    Err(xous::Error::InternalError)
+                }
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
+    fn new_field(&self) -> Result<(), xous::Error> {
+        match self.connection {
+            Some(cid) => {
+                send_message(
+                    cid,
+                    Message::new_scalar(Opcode::NewField.to_usize().unwrap(), 0, 0, 0, 0),
+                )?;
+                Ok(())
+            }
+            _ => Err(xous::Error::UseBeforeInit),
+        }
+    }
+
     fn acquire(&self, api_token: Option<[u32; 4]>) -> Result<[u32; 4], xous::Error> {
         match self.connection {
             Some(cid) => {
@@ -241,6 +846,91 @@ impl PredictionApi for PredictionPlugin {
     }
 }
 
+/// interval between `GetReadyState` polls in `warm_up_wait()`
+const WARM_UP_POLL_MS: u64 = 20;
+
+/// Polls `ready_state` until it reports `ReadyState::Ready`, sleeping between polls with `sleep`.
+/// The transport and timer are broken out so the polling logic can be exercised in tests.
+fn wait_ready<R, S>(timeout_ms: u64, mut ready_state: R, mut sleep: S) -> Result<(), xous::Error>
+where R: FnMut() -> Result<ReadyState, xous::Error>,
+      S: FnMut(u64),
+{
+    let mut waited: u64 = 0;
+    loop {
+        if ready_state()? == ReadyState::Ready {
+            return Ok(());
+        }
+        if waited >= timeout_ms {
+            return Err(xous::Error::Timeout);
+        }
+        let interval = WARM_UP_POLL_MS.min(timeout_ms - waited);
+        sleep(interval);
+        waited += interval;
+    }
+}
+
+/// default quiet period of an `InputDebouncer`, in ms
+pub const DEFAULT_INPUT_DEBOUNCE_MS: u32 = 40;
+
+/// Coalesces bursts of `Input` so that a plugin re-ranks its predictions once the input has been
+/// quiet for a while, rather than once per keystroke.
+///
+/// Each `input()` that returns `false` leaves an update pending. The plugin arms a timer for
+/// `deadline()`, and when it fires calls `expired()`; if more input arrived in the meantime the
+/// timer is re-armed for the new deadline, so the last input of a burst is always acted on.
+/// Queries call `flush()` first, so they never see stale results.
+#[derive(Debug, Copy, Clone)]
+pub struct InputDebouncer {
+    debounce_ms: u32,
+    /// time of the latest input that hasn't been acted on
+    pending: Option<u64>,
+}
+impl Default for InputDebouncer {
+    fn default() -> InputDebouncer {
+        InputDebouncer::new(DEFAULT_INPUT_DEBOUNCE_MS)
+    }
+}
+impl InputDebouncer {
+    pub fn new(debounce_ms: u32) -> InputDebouncer {
+        InputDebouncer { debounce_ms, pending: None }
+    }
+    pub fn set_debounce_ms(&mut self, debounce_ms: u32) {
+        self.debounce_ms = debounce_ms;
+    }
+    pub fn debounce_ms(&self) -> u32 {
+        self.debounce_ms
+    }
+    /// Records an input at `now`. Returns `true` if it should be acted on right away, which is
+    /// only the case with debouncing turned off.
+    pub fn input(&mut self, now: u64) -> bool {
+        if self.debounce_ms == 0 {
+            self.pending = None;
+            true
+        } else {
+            self.pending = Some(now);
+            false
+        }
+    }
+    /// when the pending update is due, if there is one
+    pub fn deadline(&self) -> Option<u64> {
+        self.pending.map(|t| t + self.debounce_ms as u64)
+    }
+    /// Returns `true`, once, if the pending update is due as of `now`.
+    pub fn expired(&mut self, now: u64) -> bool {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Returns `true` if an update was pending, and clears it: the caller acts on it now.
+    pub fn flush(&mut self) -> bool {
+        self.pending.take().is_some()
+    }
+}
+
 //////////////////////////////////////////////////////
 //////////////////// FRONT END API
 //////////////////////////////////////////////////////
@@ -447,3 +1137,300 @@ fn callback_server(sid0: usize, sid1: usize, sid2: usize, sid3: usize) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// stands in for a plugin whose model takes a few polls to finish loading
+    struct SlowModel {
+        polls_to_ready: u32,
+        state: ReadyState,
+    }
+    impl SlowModel {
+        fn ready_state(&mut self) -> Result<ReadyState, xous::Error> {
+            if self.polls_to_ready == 0 {
+                self.state = ReadyState::Ready;
+            } else {
+                self.polls_to_ready -= 1;
+                self.state = ReadyState::WarmingUp;
+            }
+            Ok(self.state)
+        }
+        fn prediction(&self) -> Option<&str> {
+            if self.state == ReadyState::Ready { Some("hello") } else { None }
+        }
+    }
+
+    fn round_trip(p: &Prediction) -> Prediction {
+        use rkyv::{ser::{Serializer, serializers::BufferSerializer}, Deserialize};
+        #[repr(align(16))]
+        struct Aligned([u8; 8192]);
+        let mut storage = Aligned([0; 8192]);
+        let mut ser = BufferSerializer::new(&mut storage.0[..]);
+        let pos = ser.serialize_value(p).unwrap();
+        let bytes = ser.into_inner();
+        let archived = unsafe { rkyv::archived_value::<Prediction>(bytes, pos) };
+        archived.deserialize(&mut xous_ipc::XousDeserializer {}).unwrap()
+    }
+
+    fn info_round_trip(info: &DictionaryInfo) -> DictionaryInfo {
+        use rkyv::{ser::{Serializer, serializers::BufferSerializer}, Deserialize};
+        #[repr(align(16))]
+        struct Aligned([u8; 256]);
+        let mut storage = Aligned([0; 256]);
+        let mut ser = BufferSerializer::new(&mut storage.0[..]);
+        let pos = ser.serialize_value(info).unwrap();
+        let bytes = ser.into_inner();
+        let archived = unsafe { rkyv::archived_value::<DictionaryInfo>(bytes, pos) };
+        archived.deserialize(&mut xous_ipc::XousDeserializer {}).unwrap()
+    }
+
+    fn blank(index: u32) -> Prediction {
+        Prediction {
+            index,
+            valid: true,
+            string: String::new(),
+            display: String::new(),
+            api_token: [1, 2, 3, 4],
+            source: 0,
+            shortcut: default_shortcut(index),
+        }
+    }
+
+    #[test]
+    fn test_prediction_round_trip() {
+        // by default, the display form is the same as the commit form
+        let mut p = blank(2);
+        p.set("hello");
+        let r = round_trip(&p);
+        assert_eq!(r.index, 2);
+        assert!(r.valid);
+        assert_eq!(r.api_token, [1, 2, 3, 4]);
+        assert_eq!(r.string.as_str().unwrap(), "hello");
+        assert_eq!(r.display.as_str().unwrap(), "hello");
+
+        // the forms can differ, and both survive the trip
+        let mut p = blank(0);
+        p.set("café");
+        p.set_display("café (fr)");
+        let r = round_trip(&p);
+        assert_eq!(r.string.as_str().unwrap(), "café");
+        assert_eq!(r.display.as_str().unwrap(), "café (fr)");
+    }
+
+    #[test]
+    fn test_dictionary_info_round_trip() {
+        let r = info_round_trip(&DictionaryInfo::new("en-US", 84_000, 3));
+        assert_eq!(r.language.as_str().unwrap(), "en-US");
+        assert_eq!((r.word_count, r.version), (84_000, 3));
+
+        // an over-long language tag is cut at a character boundary
+        let long: std::string::String = core::iter::repeat("ü").take(40).collect();
+        let r = info_round_trip(&DictionaryInfo::new(&long, 0, 0));
+        assert_eq!(r.language.as_str().unwrap().chars().count(), 16);
+    }
+
+    #[test]
+    fn test_input_debounce() {
+        // a burst of keystrokes 10ms apart, with the timer firing at each deadline
+        let mut debounce = InputDebouncer::new(30);
+        let mut reranks = 0;
+        let mut timer: Option<u64> = None;
+        for now in 0..=400u64 {
+            if now < 200 && now % 10 == 0 {
+                assert!(!debounce.input(now));
+                timer = timer.or(debounce.deadline());
+            }
+            if timer == Some(now) {
+                timer = None;
+                if debounce.expired(now) {
+                    reranks += 1;
+                    // the trailing update comes a debounce period after the last keystroke
+                    assert_eq!(now, 190 + 30);
+                } else {
+                    timer = debounce.deadline();
+                }
+            }
+        }
+        assert_eq!(reranks, 1);
+        assert_eq!(debounce.deadline(), None);
+
+        // a query in the middle of a burst takes the pending update, and the timer then has
+        // nothing left to do
+        debounce.input(1000);
+        assert!(debounce.flush());
+        assert!(!debounce.flush());
+        assert!(!debounce.expired(2000));
+
+        // with debouncing off, every input is acted on at once
+        debounce.set_debounce_ms(0);
+        assert!(debounce.input(3000));
+        assert_eq!(debounce.deadline(), None);
+    }
+
+    #[test]
+    fn test_prediction_truncation() {
+        // the commit form is smaller than the display form, and only whole characters are kept
+        let long: std::string::String = core::iter::repeat("é").take(600).collect();
+        let mut p = blank(0);
+        p.set(&long);
+        assert_eq!(p.string.as_str().unwrap().chars().count(), 500);
+        assert_eq!(p.display.as_str().unwrap(), long);
+    }
+
+    #[test]
+    fn test_inline_completion() {
+        let triggers = PredictionTriggers { newline: false, punctuation: true, whitespace: true };
+        let token = triggers.current_token("I like caf");
+        assert_eq!(token, "caf");
+        // the top candidate extends what was typed
+        assert_eq!(completion_suffix(token, "café"), "é");
+        assert_eq!(completion_suffix(token, "caf"), "");
+        // the top candidate doesn't share the typed prefix
+        assert_eq!(completion_suffix(token, "coffee"), "");
+        assert_eq!(completion_suffix(token, "ca"), "");
+        // nothing typed yet after a trigger
+        assert_eq!(triggers.current_token("I like "), "");
+        assert_eq!(completion_suffix(triggers.current_token("I like "), "café"), "");
+    }
+
+    #[test]
+    fn test_dictionary_tokenizer() {
+        let dictionary = ["สวัสดี", "ครับ", "ผม", "ชื่อ", "北京", "北京大学", "大学"];
+        let is_word = |w: &str| dictionary.contains(&w);
+        let triggers = PredictionTriggers { newline: true, punctuation: true, whitespace: true };
+        let mut tokenizer = Tokenizer { mode: TokenizerMode::Dictionary, triggers };
+
+        assert_eq!(tokenizer.tokenize("สวัสดีครับผมชื่อ", is_word), vec!["สวัสดี", "ครับ", "ผม", "ชื่อ"]);
+        // longest match wins, and unknown characters stand alone
+        assert_eq!(tokenizer.tokenize("我在北京大学", is_word), vec!["我", "在", "北京大学"]);
+        // triggers still separate units
+        assert_eq!(tokenizer.tokenize("北京, 大学", is_word), vec!["北京", "大学"]);
+        assert_eq!(tokenizer.current_token("สวัสดีครับผม", is_word), "ผม");
+        assert_eq!(tokenizer.current_token("สวัสดี ", is_word), "");
+
+        tokenizer.mode = TokenizerMode::Character;
+        assert_eq!(tokenizer.tokenize("北京 大学", is_word), vec!["北", "京", "大", "学"]);
+        tokenizer.mode = TokenizerMode::Whitespace;
+        assert_eq!(tokenizer.tokenize("北京 大学", is_word), vec!["北京", "大学"]);
+        assert_eq!(tokenizer.current_token("北京 大", is_word), "大");
+    }
+
+    #[test]
+    fn test_warm_up_wait_ready() {
+        let mut model = SlowModel { polls_to_ready: 3, state: ReadyState::Cold };
+        let mut slept = 0;
+        assert!(model.prediction().is_none());
+        let result = wait_ready(1000, || model.ready_state(), |ms| slept += ms);
+        assert!(result.is_ok());
+        // predictions are there as soon as the wait returns, without another round-trip
+        assert_eq!(model.prediction(), Some("hello"));
+        assert_eq!(slept, 3 * WARM_UP_POLL_MS);
+    }
+
+    #[test]
+    fn test_warm_up_wait_timeout() {
+        let mut model = SlowModel { polls_to_ready: 100, state: ReadyState::Cold };
+        let mut slept = 0;
+        let result = wait_ready(50, || model.ready_state(), |ms| slept += ms);
+        assert!(matches!(result, Err(xous::Error::Timeout)));
+        assert_eq!(slept, 50);
+        assert!(model.prediction().is_none());
+    }
+
+    #[test]
+    fn test_input_source_matching() {
+        let candidates = ["hello", "help", "hollow", "world"];
+        let matching = |source: InputSource, typed: &str| -> Vec<&str> {
+            candidates.iter().copied().filter(|c| source.matches(typed, c)).collect()
+        };
+        // a clean prefix is matched exactly when tapped
+        assert_eq!(matching(InputSource::Tap, "hel"), vec!["hello", "help"]);
+        assert_eq!(matching(InputSource::Tap, "HEL"), vec!["hello", "help"]);
+        // a swipe trace passes over extra letters on the way between the word's letters; tapped,
+        // the same letters match nothing
+        assert!(matching(InputSource::Tap, "hgreklo").is_empty());
+        assert_eq!(matching(InputSource::Swipe, "hgreklo"), vec!["hello"]);
+        assert_eq!(matching(InputSource::Swipe, "hgrewsdfghjklpo"), vec!["hello", "help"]);
+        // but it has to start on the first letter of the word
+        assert!(matching(InputSource::Swipe, "jhgreklo").is_empty());
+        // pasted text doesn't steer the ranking, and nothing matches an empty token
+        assert!(matching(InputSource::Paste, "hello").is_empty());
+        assert!(matching(InputSource::Swipe, "").is_empty());
+        assert_eq!(InputSource::default(), InputSource::Tap);
+    }
+
+    #[test]
+    fn test_echo_fallback() {
+        // off by default, and then nothing is echoed
+        assert_eq!(echo_fallback(false, 0, 0, "xyzzy"), None);
+        // only when the model has nothing, and only as the first prediction
+        assert_eq!(echo_fallback(true, 0, 0, "xyzzy"), Some("xyzzy"));
+        assert_eq!(echo_fallback(true, 1, 0, "xyzzy"), None);
+        assert_eq!(echo_fallback(true, 0, 3, "xyzzy"), None);
+        // there's nothing to echo before anything is typed
+        assert_eq!(echo_fallback(true, 0, 0, ""), None);
+
+        // the source makes it across to the client
+        let mut p = blank(0);
+        assert_eq!(FromPrimitive::from_u8(round_trip(&p).source), Some(PredictionSource::Model));
+        p.set_source(PredictionSource::Echo);
+        assert_eq!(FromPrimitive::from_u8(round_trip(&p).source), Some(PredictionSource::Echo));
+    }
+    #[test]
+    fn test_shortcuts() {
+        // the digits 1-9 go to the first nine predictions, and the rest get none
+        let shortcuts: Vec<u8> = (0..12).map(|i| round_trip(&blank(i)).shortcut).collect();
+        assert_eq!(shortcuts, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0, 0]);
+        // a plugin's own mapping makes it across to the client
+        let mut p = blank(0);
+        p.shortcut = 2;
+        assert_eq!(round_trip(&p).shortcut, 2);
+        p.shortcut = 0;
+        assert_eq!(round_trip(&p).shortcut, 0);
+    }
+
+    /// stands in for a plugin whose state has been corrupted
+    struct BrokenPlugin {
+        dictionary: Vec<&'static str>,
+        /// `None` when the history can't be read
+        history: Option<Vec<&'static str>>,
+        input: std::string::String,
+        input_capacity: usize,
+    }
+    impl BrokenPlugin {
+        fn self_check(&self) -> SelfCheck {
+            SelfCheck::new(
+                !self.dictionary.is_empty(),
+                self.history.as_ref().map_or(false, |h| h.iter().all(|w| !w.is_empty())),
+                self.input.len() <= self.input_capacity,
+            )
+        }
+    }
+    #[test]
+    fn test_self_check() {
+        let mut plugin = BrokenPlugin {
+            dictionary: vec!["hello", "world"],
+            history: Some(vec!["hello"]),
+            input: "hel".into(),
+            input_capacity: 8,
+        };
+        assert_eq!(plugin.self_check(), SelfCheck(SelfCheck::ALL));
+        assert!(plugin.self_check().is_healthy());
+
+        // each fault shows up as its own bit
+        plugin.dictionary.clear();
+        assert_eq!(plugin.self_check().failures(), SelfCheck::DICTIONARY_LOADED);
+        plugin.history = None;
+        assert_eq!(plugin.self_check().failures(), SelfCheck::DICTIONARY_LOADED | SelfCheck::HISTORY_ACCESSIBLE);
+        plugin.history = Some(vec!["hello", ""]);
+        plugin.input = "a runaway input line".into();
+        let check = plugin.self_check();
+        assert_eq!(check.failures(), SelfCheck::ALL);
+        assert!(!check.is_healthy());
+        assert_eq!(check.failed_checks(), vec!["dictionary", "history", "buffers"]);
+        // bits the plugin doesn't define are ignored
+        assert!(SelfCheck(SelfCheck::ALL | 1 << 31).is_healthy());
+    }
+}
@@ -0,0 +1,222 @@
+use ime_plugin_api::InputSource;
+use xous_ipc::String;
+
+pub(crate) struct HistoryEntry {
+    pub(crate) word: String<64>,
+    /// number of times this entry has been picked
+    pub(crate) count: u32,
+}
+
+/// Pick history for a single predictor client.
+///
+/// Entries are kept in rank order, so index 0 is the most likely prediction. Rank is
+/// determined by how many times an entry was picked; ties go to the most recently picked entry.
+pub(crate) struct History {
+    entries: Vec<HistoryEntry>,
+    max: usize,
+    /// the last value picked, so it can be undone with `unpick()`
+    last_pick: Option<String<64>>,
+}
+
+impl History {
+    pub(crate) fn new(max: usize) -> History {
+        History {
+            entries: Vec::new(),
+            max,
+            last_pick: None,
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// returns the entry at `rank`, where 0 is the most likely prediction
+    pub(crate) fn get(&self, rank: usize) -> Option<&str> {
+        self.entries.get(rank).map(|e| e.word.to_str())
+    }
+    /// returns the rank of `s`, if it is in the history
+    pub(crate) fn rank_of(&self, s: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.word.to_str() == s)
+    }
+    /// Ranks in the order predictions are offered for the token `typed`, entered from `source`:
+    /// the entries that match it come first, and otherwise the order is that of `get()`.
+    pub(crate) fn ranked_for(&self, typed: &str, source: InputSource) -> Vec<usize> {
+        let (mut ranks, rest): (Vec<usize>, Vec<usize>) =
+            (0..self.entries.len()).partition(|&rank| source.matches(typed, self.entries[rank].word.to_str()));
+        ranks.extend(rest);
+        ranks
+    }
+    /// records `s` as picked. If `s` is already in the history its frequency is bumped,
+    /// otherwise it is added, evicting the lowest-ranked entry if the history is full.
+    pub(crate) fn pick(&mut self, s: &str) {
+        // the API allows for large picked feedback, but this implementation only keeps the first 64 bytes
+        let mut word: String<64> = String::new();
+        for ch in s.chars() {
+            if word.push(ch).is_err() {
+                break;
+            }
+        }
+        log::trace!("storing history value | {}", word);
+        let entry = if let Some(rank) = self.rank_of(word.to_str()) {
+            let mut e = self.entries.remove(rank);
+            e.count = e.count.saturating_add(1);
+            e
+        } else {
+            if self.entries.len() == self.max {
+                self.entries.pop();
+            }
+            HistoryEntry { word, count: 1 }
+        };
+        self.insert_ranked(entry);
+        self.last_pick = Some(word);
+        log::trace!("history has length {}", self.entries.len());
+    }
+    /// undoes the last pick. Repeated calls have no further effect.
+    pub(crate) fn unpick(&mut self) {
+        if let Some(word) = self.last_pick.take() {
+            if let Some(rank) = self.rank_of(word.to_str()) {
+                let mut e = self.entries.remove(rank);
+                if e.count > 1 {
+                    e.count -= 1;
+                    self.insert_ranked(e);
+                }
+            }
+        }
+    }
+    /// Starts a new field: the last pick can no longer be undone, as it was made in the
+    /// previous one. The entries and their counts are kept.
+    pub(crate) fn new_field(&mut self) {
+        self.last_pick = None;
+    }
+    /// `true` if the history is within its bounds, and its entries are non-empty and in rank order
+    pub(crate) fn is_well_formed(&self) -> bool {
+        self.entries.len() <= self.max
+            && self.entries.iter().all(|e| !e.word.to_str().is_empty() && e.count > 0)
+            && self.entries.windows(2).all(|w| w[0].count >= w[1].count)
+    }
+    /// inserts an entry ahead of every entry with an equal or lower count
+    fn insert_ranked(&mut self, entry: HistoryEntry) {
+        let pos = self.entries.iter().position(|e| e.count <= entry.count).unwrap_or(self.entries.len());
+        self.entries.insert(pos, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spell::corrections;
+    use ime_plugin_api::{echo_fallback, PredictionTriggers};
+
+    #[test]
+    fn test_batch_raises_rank() {
+        let triggers = PredictionTriggers {
+            newline: false,
+            punctuation: true,
+            whitespace: true,
+        };
+        let mut history = History::new(8);
+        for w in ["the", "cat", "sat", "on", "mat", "the", "dog", "ran"].iter() {
+            history.pick(w);
+        }
+        let before_cat = history.rank_of("cat").unwrap();
+        let before_mat = history.rank_of("mat").unwrap();
+
+        for token in triggers.tokenize("cat, mat") {
+            history.pick(token);
+        }
+        assert!(history.rank_of("cat").unwrap() < before_cat);
+        assert!(history.rank_of("mat").unwrap() < before_mat);
+        // frequency wins over recency: "the" was picked twice before the batch
+        assert_eq!(history.get(0), Some("mat"));
+        assert_eq!(history.get(1), Some("cat"));
+        assert_eq!(history.get(2), Some("the"));
+        assert_eq!(history.len(), 7);
+
+        // new words from a batch are added, evicting the lowest ranked entries
+        for token in triggers.tokenize("a new  phrase.") {
+            history.pick(token);
+        }
+        assert_eq!(history.len(), 8);
+        assert!(history.rank_of("phrase").is_some());
+        assert!(history.rank_of("sat").is_none());
+    }
+
+    #[test]
+    fn test_unpick() {
+        let mut history = History::new(4);
+        history.pick("one");
+        history.pick("two");
+        history.pick("two");
+        assert_eq!(history.get(0), Some("two"));
+        history.unpick();
+        // "two" drops back to a count of 1, but is still the most recent
+        assert_eq!(history.get(0), Some("two"));
+        history.unpick();
+        assert_eq!(history.len(), 2);
+        history.pick("three");
+        history.unpick();
+        assert_eq!(history.rank_of("three"), None);
+        assert!(history.is_well_formed());
+    }
+
+    #[test]
+    fn test_new_field() {
+        let mut history = History::new(4);
+        history.pick("hello");
+        history.pick("world");
+        history.pick("hello");
+        // "hel" was left half-typed in the previous field
+        assert_eq!(history.get(history.ranked_for("hel", InputSource::Tap)[0]), Some("hello"));
+        history.new_field();
+        // the fresh input has no token, so nothing is corrected or echoed, and the last pick
+        // from the previous field can't be undone from this one
+        assert!(corrections(true, &history, "", InputSource::Tap).is_empty());
+        assert_eq!(echo_fallback(true, 0, 0, ""), None);
+        history.unpick();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.get(0), Some("hello"));
+        assert!((0..history.len()).all(|rank| !InputSource::Tap.matches("", history.get(rank).unwrap())));
+        // what was learned still ranks when it's typed again
+        assert_eq!(history.get(history.ranked_for("hel", InputSource::Tap)[0]), Some("hello"));
+        assert_eq!(history.get(history.ranked_for("wor", InputSource::Tap)[0]), Some("world"));
+        assert!(history.is_well_formed());
+    }
+
+    #[test]
+    fn test_well_formed() {
+        let mut history = History::new(2);
+        assert!(history.is_well_formed());
+        history.pick("one");
+        history.pick("two");
+        history.pick("two");
+        assert!(history.is_well_formed());
+        // out of rank order
+        history.entries.swap(0, 1);
+        assert!(!history.is_well_formed());
+        history.entries.swap(0, 1);
+        // past its bound
+        history.entries.push(HistoryEntry { word: String::from_str("three"), count: 1 });
+        assert!(!history.is_well_formed());
+        history.entries.pop();
+        history.entries[1].word.clear();
+        assert!(!history.is_well_formed());
+    }
+
+    #[test]
+    fn test_ranked_for_source() {
+        let mut history = History::new(4);
+        for w in ["hello", "world", "help", "world"].iter() {
+            history.pick(w);
+        }
+        // "world" was picked twice, so it leads without any input
+        assert_eq!(history.ranked_for("", InputSource::Tap), vec![0, 1, 2]);
+        assert_eq!(history.get(0), Some("world"));
+        // a tapped prefix brings the entries it starts forward
+        let hel: Vec<&str> = history.ranked_for("hel", InputSource::Tap).iter().map(|&r| history.get(r).unwrap()).collect();
+        assert_eq!(hel, vec!["help", "hello", "world"]);
+        // a sloppy trace only finds "hello" when it's known to be a swipe
+        assert_eq!(history.ranked_for("hgreklo", InputSource::Tap), vec![0, 1, 2]);
+        let swiped = history.ranked_for("hgreklo", InputSource::Swipe);
+        assert_eq!(history.get(swiped[0]), Some("hello"));
+        assert_eq!(history.ranked_for("world", InputSource::Paste), vec![0, 1, 2]);
+    }
+}
@@ -6,9 +6,16 @@ use ime_plugin_api::*;
 use log::{error, info};
 
 use xous_ipc::{String, Buffer};
-use num_traits::FromPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
 use std::collections::HashMap;
-use xous::msg_scalar_unpack;
+use xous::{msg_scalar_unpack, Message};
+
+mod history;
+use history::History;
+mod phrases;
+use phrases::{PhraseCompletion, Phrases, DEFAULT_PHRASES};
+mod spell;
+use spell::corrections;
 
 fn main() -> ! {
     log_server::init_wait().unwrap();
@@ -20,9 +27,34 @@ fn main() -> ! {
     let ime_sh_sid = xns.register_name(ime_plugin_shell::SERVER_NAME_IME_PLUGIN_SHELL, None).expect("can't register server");
     log::trace!("registered with NS -- {:?}", ime_sh_sid);
 
-    let mut history_store: HashMap::<[u32;4], Vec<String<64>>> = HashMap::new();
-    let mut active_history: Option<([u32; 4], Vec<String<64>>)> = None;
+    let mut history_store: HashMap::<[u32;4], History> = HashMap::new();
+    let mut active_history: Option<([u32; 4], History)> = None;
     let history_max = 4;
+    // the latest `Input` and where it came from, and what's offered for it as of the last re-rank
+    let mut input = std::string::String::new();
+    let mut source = InputSource::default();
+    let mut offered = Offered::default();
+    let mut debounce = InputDebouncer::default();
+    let mut echo = false;
+    let mut phrases = Phrases::new(DEFAULT_PHRASES);
+    let mut phrases_enabled = false;
+    let mut spell_correct = false;
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    // sleeps for the requested ms, then sends `InputDebounceElapsed`
+    let (debounce_timer, timer_requests) = std::sync::mpsc::channel::<u64>();
+    let mut timer_armed = false;
+    std::thread::spawn({
+        let cid = xous::connect(ime_sh_sid).unwrap();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            while let Ok(ms) = timer_requests.recv() {
+                tt.sleep_ms(ms as usize).ok();
+                xous::send_message(cid,
+                    Message::new_scalar(Opcode::InputDebounceElapsed.to_usize().unwrap(), 0, 0, 0, 0)
+                ).ok();
+            }
+        }
+    });
 
     /*
         use core::fmt::Write as CoreWriter;
@@ -37,11 +69,8 @@ fn main() -> ! {
         history.push(test3);
     */
 
-    let mytriggers = PredictionTriggers {
-        newline: true,
-        punctuation: false,
-        whitespace: false,
-    };
+    let mytriggers = TRIGGERS;
+    let mut tokenizer = Tokenizer { mode: TokenizerMode::default(), triggers: mytriggers };
 
     loop {
         let mut msg = xous::receive_message(ime_sh_sid).unwrap();
@@ -63,17 +92,19 @@ fn main() -> ! {
                         }
                     } else {
                         let new_token = xous::create_server_id().unwrap().to_array();
-                        active_history = Some((new_token, Vec::new()));
+                        active_history = Some((new_token, History::new(history_max)));
                         ret.token = Some(new_token);
                     }
                 } else {
                     ret.token = None;
                     log::warn!("attempt to acquire lock on a predictor that was already locked");
                 }
+                offered.stale = true;
                 buffer.replace(ret).unwrap();
             }
             Some(Opcode::Release) => msg_scalar_unpack!(msg, t0, t1, t2, t3, {
                 let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                clear_input(&mut input, &mut source, &mut offered, &mut debounce, &mut phrases);
                 if let Some((t, h)) = active_history.take() {
                     if t == token {
                         history_store.insert(token, h);
@@ -84,24 +115,106 @@ fn main() -> ! {
                     log::warn!("Release called on a predictor that was in a released state");
                 }
             }),
-            Some(Opcode::Input) => {
-                // shell does nothing with the input, it only keeps track of
-                // the picked results
+            Some(Opcode::Input) | Some(Opcode::SourcedInput) => {
+                // predictions come from the picked results; the input only orders them,
+                // and is completed inline
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                input.clear();
+                if msg.body.id() == Opcode::SourcedInput.to_usize().unwrap() {
+                    let sourced = buffer.to_original::<SourcedInput, _>().unwrap();
+                    input.push_str(sourced.input.as_str());
+                    source = FromPrimitive::from_u8(sourced.source).unwrap_or_else(|| {
+                        log::warn!("unknown input source {}, taking it as typed", sourced.source);
+                        InputSource::default()
+                    });
+                } else {
+                    let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                    input.push_str(s.as_str());
+                    source = InputSource::Tap;
+                }
+                if debounce.input(tt.elapsed_ms()) {
+                    rerank(&tokenizer, &input, source, &active_history, Some(&mut phrases).filter(|_| phrases_enabled), spell_correct, &mut offered);
+                } else if !timer_armed {
+                    timer_armed = debounce_timer.send(debounce.debounce_ms() as u64).is_ok();
+                }
+            }
+            Some(Opcode::InputDebounceElapsed) => {
+                timer_armed = false;
+                let now = tt.elapsed_ms();
+                if debounce.expired(now) {
+                    rerank(&tokenizer, &input, source, &active_history, Some(&mut phrases).filter(|_| phrases_enabled), spell_correct, &mut offered);
+                } else if let Some(deadline) = debounce.deadline() {
+                    // more input arrived since the timer was armed
+                    timer_armed = debounce_timer.send(deadline.saturating_sub(now).max(1)).is_ok();
+                }
+            }
+            Some(Opcode::SetInputDebounce) => msg_scalar_unpack!(msg, ms, _, _, _, {
+                debounce.set_debounce_ms(ms as u32);
+            }),
+            Some(Opcode::SetEchoFallback) => msg_scalar_unpack!(msg, enabled, _, _, _, {
+                echo = enabled != 0;
+            }),
+            Some(Opcode::SetPhrases) => msg_scalar_unpack!(msg, enabled, _, _, _, {
+                phrases_enabled = enabled != 0;
+                if !phrases_enabled {
+                    phrases.clear_offered();
+                }
+                offered.stale = true;
+            }),
+            Some(Opcode::SetSpellCorrect) => msg_scalar_unpack!(msg, enabled, _, _, _, {
+                spell_correct = enabled != 0;
+                offered.stale = true;
+            }),
+            Some(Opcode::InlineCompletion) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut completion = buffer.to_original::<InlineCompletion, _>().unwrap();
+                completion.suffix.clear();
+                if debounce.flush() || offered.stale {
+                    rerank(&tokenizer, &input, source, &active_history, Some(&mut phrases).filter(|_| phrases_enabled), spell_correct, &mut offered);
+                }
+                if let Some((token, history)) = &active_history {
+                    if *token == completion.api_token {
+                        if let Some(top) = offered.top.and_then(|rank| history.get(rank)) {
+                            completion.suffix.append(completion_suffix(&offered.typed, top)).ok();
+                        }
+                    } else {
+                        log::warn!("api token mismatch, ignoring");
+                    }
+                } else {
+                    log::warn!("predictor not acquired, ignoring");
+                }
+                buffer.replace(completion).expect("couldn't return InlineCompletion");
             }
             Some(Opcode::Picked) => {
                 if let Some((_token, history)) = &mut active_history {
                     let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                     let s = buffer.as_flat::<String::<4000>, _>().unwrap();
-                    // the API allows for large picked feedback, but this implementation only keeps the first 64 characters
-                    let mut local_s: String<64> = String::new();
-                    use core::fmt::Write;
-                    write!(local_s, "{}", s.as_str()).expect("overflowed history variable");
-                    log::trace!("storing history value | {}", s.as_str());
-                    if history.len() == history_max {
-                        history.remove(0);
+                    if phrases.pick(s.as_str(), history) {
+                        // a phrase completion was picked, and its words recorded
+                    } else if tokenizer.mode == TokenizerMode::Whitespace {
+                        history.pick(s.as_str());
+                    } else {
+                        // the pick may be a run of several words in an unspaced script
+                        let tokens = tokenizer.tokenize(s.as_str(), |w| history.rank_of(w).is_some());
+                        for token in tokens {
+                            history.pick(token);
+                        }
+                    }
+                    offered.stale = true;
+                } else {
+                    log::warn!("predictor not acquired, ignoring");
+                }
+            }
+            Some(Opcode::FeedbackBatch) => {
+                if let Some((_token, history)) = &mut active_history {
+                    let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                    let s = buffer.as_flat::<String::<4096>, _>().unwrap();
+                    let tokens = tokenizer.tokenize(s.as_str(), |w| history.rank_of(w).is_some());
+                    for token in tokens {
+                        history.pick(token);
                     }
-                    history.push(local_s);
-                    log::trace!("history has length {}", history.len());
+                    log::trace!("history has length {} after batch", history.len());
+                    offered.stale = true;
                 } else {
                     log::warn!("predictor not acquired, ignoring");
                 }
@@ -109,36 +222,31 @@ fn main() -> ! {
             Some(Opcode::Prediction) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                if debounce.flush() || offered.stale {
+                    rerank(&tokenizer, &input, source, &active_history, Some(&mut phrases).filter(|_| phrases_enabled), spell_correct, &mut offered);
+                }
                 if let Some((token, history)) = &mut active_history {
                     if *token == prediction.api_token {
                         log::trace!("querying prediction index {}", prediction.index);
                         log::trace!("{:?}", prediction);
-                        if history.len() > 0 && ((prediction.index as usize) < history.len()) {
-                            let mut index = prediction.index;
-                            if index >= history.len() as u32 {
-                                index = history.len() as u32 - 1;
-                            }
-                            let mut i = 1;
-                            for &s in history.iter() {
-                                // iterator is from oldest to newest, so do some math to go from newest to oldest
-                                // TIL: there is a .rev() feature in iterators. Next time maybe use that instead.
-                                if (history.len() as u32 - i) == index {
-                                    // decompose the string into a character-by-character sequence
-                                    // and then stuff byte-by-byte, as fits, into the return array
-                                    prediction.string.clear();
-                                    for ch in s.as_str().unwrap().chars() {
-                                        if let Ok(_) = prediction.string.push(ch) {
-                                            // it's ok, carry on.
-                                        } else {
-                                            // we ran out of space, stop copying
-                                            break;
-                                        }
-                                    }
-                                    prediction.valid = true;
-                                    break;
-                                }
-                                i = i + 1;
+                        // a phrase completion goes ahead of the words
+                        let (phrase, ranks, fixes) = (&offered.phrase, &offered.ranks, &offered.fixes);
+                        let index = prediction.index as usize;
+                        if let (0, Some(completion)) = (index, phrase) {
+                            prediction.set(&completion.commit);
+                            prediction.set_display(&completion.phrase);
+                            prediction.set_source(PredictionSource::Phrase);
+                            prediction.valid = true;
+                        } else if let Some((rank, s)) = ranks.get(index - phrase.is_some() as usize).and_then(|&rank| history.get(rank).map(|s| (rank, s))) {
+                            prediction.set(s);
+                            if fixes.contains(&rank) {
+                                prediction.set_source(PredictionSource::Correction);
                             }
+                            prediction.valid = true;
+                        } else if let Some(s) = echo_fallback(echo, prediction.index, ranks.len() + phrase.is_some() as usize, &offered.typed) {
+                            prediction.set(s);
+                            prediction.set_source(PredictionSource::Echo);
+                            prediction.valid = true;
                         } else { // there is no history
                             prediction.valid = false;
                             log::trace!("no prediction found");
@@ -157,12 +265,8 @@ fn main() -> ! {
             }
             Some(Opcode::Unpick) => {
                 if let Some((_token, history)) = &mut active_history {
-                    if history.len() == 1 {
-                        let _ = history.remove(0);
-                    } else if history.len() > 1 {
-                        let _ = history.pop(); // discard the last entry
-                    }
-                    // in case of 0 length, do nothing
+                    history.unpick();
+                    offered.stale = true;
                 } else {
                     log::warn!("predictor not acquired, ignoring");
                 }
@@ -170,6 +274,48 @@ fn main() -> ! {
             Some(Opcode::GetPredictionTriggers) => {
                 xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
             }
+            Some(Opcode::WarmUp) => {
+                // the history is built on acquire, there is no model to load
+            }
+            Some(Opcode::SetTokenizer) => msg_scalar_unpack!(msg, mode, _, _, _, {
+                match FromPrimitive::from_usize(mode) {
+                    Some(mode) => {
+                        tokenizer.mode = mode;
+                        offered.stale = true;
+                    }
+                    None => log::error!("unknown tokenizer mode {}", mode),
+                }
+            }),
+            Some(Opcode::GetReadyState) => {
+                xous::return_scalar(msg.sender, ReadyState::Ready as usize).expect("couldn't return GetReadyState");
+            }
+            Some(Opcode::SelfCheck) => {
+                // the dictionary is the acquired history, so there's nothing to load; the
+                // history only counts as accessible once a client has acquired the predictor
+                if debounce.flush() || offered.stale {
+                    rerank(&tokenizer, &input, source, &active_history, Some(&mut phrases).filter(|_| phrases_enabled), spell_correct, &mut offered);
+                }
+                let history_ok = active_history.as_ref().map_or(false, |(_t, h)| h.is_well_formed());
+                let buffers_ok = input.len() <= 4000 && input.ends_with(offered.typed.as_str());
+                let check = SelfCheck::new(true, history_ok, buffers_ok);
+                if !check.is_healthy() {
+                    log::warn!("self-check failed: {:?}", check.failed_checks());
+                }
+                xous::return_scalar(msg.sender, check.0 as usize).expect("couldn't return SelfCheck");
+            }
+            Some(Opcode::NewField) => {
+                clear_input(&mut input, &mut source, &mut offered, &mut debounce, &mut phrases);
+                if let Some((_token, history)) = &mut active_history {
+                    history.new_field();
+                }
+                offered.stale = true;
+            }
+            Some(Opcode::GetDictionaryInfo) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                // the dictionary is the acquired history, which isn't tied to a language or a release
+                let word_count = active_history.as_ref().map(|(_t, h)| h.len() as u32).unwrap_or(0);
+                buffer.replace(DictionaryInfo::new("und", word_count, 0)).expect("couldn't return GetDictionaryInfo");
+            }
             Some(Opcode::Quit) => {
                 if active_history.is_some() {
                     error!("received quit, goodbye!"); break;
@@ -184,3 +330,103 @@ fn main() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+/// Predictions are only triggered by a newline: a shell command is taken as one token
+const TRIGGERS: PredictionTriggers = PredictionTriggers {
+    newline: true,
+    punctuation: false,
+    whitespace: false,
+};
+
+/// What's offered for the input, worked out as it's re-ranked rather than on every `Prediction`
+/// query
+#[derive(Default)]
+struct Offered {
+    /// the token being typed at the end of the input
+    typed: std::string::String,
+    /// the phrase completion, which goes ahead of the words
+    phrase: Option<PhraseCompletion>,
+    /// the history's ranks to offer, in order: corrections for a token nothing starts with go
+    /// ahead of the rest
+    ranks: Vec<usize>,
+    /// which of `ranks` are corrections
+    fixes: Vec<usize>,
+    /// the best match for the token, for the inline completion
+    top: Option<usize>,
+    /// the history or the settings have changed since, so the next query re-ranks
+    stale: bool,
+}
+
+/// Forgets the input and what was offered for it, as on `Release` and `NewField`
+fn clear_input(
+    input: &mut std::string::String,
+    source: &mut InputSource,
+    offered: &mut Offered,
+    debounce: &mut InputDebouncer,
+    phrases: &mut Phrases,
+) {
+    input.clear();
+    *source = InputSource::default();
+    *offered = Offered::default();
+    debounce.flush();
+    phrases.clear_offered();
+}
+
+/// Re-ranks against the latest input: finds the token being typed, using the acquired history
+/// as the dictionary, and works out what's offered for it. `phrases` is `None` while phrase
+/// completion is off.
+fn rerank(
+    tokenizer: &Tokenizer,
+    input: &str,
+    source: InputSource,
+    active_history: &Option<([u32; 4], History)>,
+    phrases: Option<&mut Phrases>,
+    spell_correct: bool,
+    offered: &mut Offered,
+) {
+    let history = active_history.as_ref().map(|(_token, history)| history);
+    offered.typed.clear();
+    offered.typed.push_str(tokenizer.current_token(input, |w| history.map_or(false, |h| h.rank_of(w).is_some())));
+    offered.phrase = phrases.and_then(|phrases| phrases.complete(input));
+    let (fixes, ranked) = match history {
+        Some(history) => (
+            corrections(spell_correct, history, &offered.typed, source),
+            history.ranked_for(&offered.typed, source),
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+    offered.top = ranked.first().copied();
+    offered.ranks = fixes.iter().copied().chain(ranked.into_iter().filter(|rank| !fixes.contains(rank))).collect();
+    offered.fixes = fixes;
+    offered.stale = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phrase_after_other_words() {
+        // with the shell's triggers, the token being typed is the whole line, but a phrase is
+        // still completed from where it starts
+        let tokenizer = Tokenizer { mode: TokenizerMode::default(), triggers: TRIGGERS };
+        let mut phrases = Phrases::new(DEFAULT_PHRASES);
+        let mut offered = Offered::default();
+        let active_history = Some(([0; 4], History::new(4)));
+        rerank(&tokenizer, "I'm on my", InputSource::Tap, &active_history, Some(&mut phrases), false, &mut offered);
+        assert_eq!(offered.typed, "I'm on my");
+        let completion = offered.phrase.clone().unwrap();
+        assert_eq!((completion.phrase.as_str(), completion.commit.as_str()), ("on my way", "my way"));
+        rerank(&tokenizer, "I'll see you ", InputSource::Tap, &active_history, Some(&mut phrases), false, &mut offered);
+        assert_eq!(offered.phrase.as_ref().map(|c| c.commit.as_str()), Some("soon"));
+
+        // and committing it is recognised as picking it
+        let mut history = History::new(4);
+        rerank(&tokenizer, "I'm on my", InputSource::Tap, &active_history, Some(&mut phrases), false, &mut offered);
+        assert!(phrases.pick("my way", &mut history));
+
+        // with phrases off, there's none
+        rerank(&tokenizer, "I'm on my", InputSource::Tap, &active_history, None, false, &mut offered);
+        assert!(offered.phrase.is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::history::History;
+
+/// phrases offered out of the box, in the order they're preferred before any are picked
+pub(crate) const DEFAULT_PHRASES: &[&str] = &[
+    "on my way",
+    "see you soon",
+    "see you later",
+    "talk to you later",
+    "let me know",
+    "thank you",
+    "thanks a lot",
+    "how are you",
+    "in a minute",
+    "as soon as possible",
+    "by the way",
+    "no problem",
+];
+
+struct Phrase {
+    text: String,
+    /// number of times this phrase has been picked
+    count: u32,
+}
+
+/// A phrase that completes the end of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PhraseCompletion {
+    /// the whole phrase, to show the user
+    pub(crate) phrase: String,
+    /// the phrase from the token being typed on, which is what's committed
+    pub(crate) commit: String,
+}
+
+/// Multi-word phrases, offered as a single prediction once the input ends with the start of one
+pub(crate) struct Phrases {
+    phrases: Vec<Phrase>,
+    /// the last completion offered, so its pick can be recognised
+    offered: Option<PhraseCompletion>,
+}
+
+impl Phrases {
+    pub(crate) fn new(dictionary: &[&str]) -> Phrases {
+        Phrases {
+            phrases: dictionary.iter().map(|p| Phrase { text: p.to_string(), count: 0 }).collect(),
+            offered: None,
+        }
+    }
+    /// Finds the phrase that completes `input`. The end of the input has to span at least two
+    /// words to start a phrase; when several phrases match, the one matching the most input wins,
+    /// then the most picked one.
+    pub(crate) fn complete(&mut self, input: &str) -> Option<PhraseCompletion> {
+        let starts = std::iter::once(0).chain(input.match_indices(' ').map(|(i, _)| i + 1));
+        let mut best: Option<(usize, u32, &Phrase)> = None;
+        for start in starts {
+            let tail = &input[start..];
+            if !tail.trim_end().contains(' ') {
+                break;
+            }
+            for phrase in self.phrases.iter() {
+                let matches = phrase.text.len() > tail.len()
+                    && phrase.text.get(..tail.len()).map_or(false, |p| p.eq_ignore_ascii_case(tail));
+                let better = match best {
+                    Some((len, count, _)) => tail.len() > len || (tail.len() == len && phrase.count > count),
+                    None => true,
+                };
+                if matches && better {
+                    best = Some((tail.len(), phrase.count, phrase));
+                }
+            }
+        }
+        // the words before the last one are already committed, whatever the tokenizer takes for
+        // the token being typed: the shell's triggers make it the whole line
+        let word = input.rsplit(' ').next().unwrap_or("");
+        self.offered = best.and_then(|(len, _, phrase)| {
+            let from = len.checked_sub(word.len())?;
+            Some(PhraseCompletion { phrase: phrase.text.clone(), commit: phrase.text[from..].to_string() })
+        });
+        self.offered.clone()
+    }
+    /// If `s` is the commit of the phrase last offered, learns the phrase, so it's preferred from
+    /// then on, records each of the words committed in `history`, and returns `true`.
+    pub(crate) fn pick(&mut self, s: &str, history: &mut History) -> bool {
+        match self.offered.take() {
+            Some(offered) if offered.commit == s => {
+                if let Some(phrase) = self.phrases.iter_mut().find(|p| p.text == offered.phrase) {
+                    phrase.count = phrase.count.saturating_add(1);
+                }
+                for word in s.split(' ').filter(|w| !w.is_empty()) {
+                    history.pick(word);
+                }
+                true
+            }
+            offered => {
+                self.offered = offered;
+                false
+            }
+        }
+    }
+    /// forgets the last completion offered, which carries a copy of the input
+    pub(crate) fn clear_offered(&mut self) {
+        self.offered = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phrase_completion() {
+        let mut phrases = Phrases::new(&["on my way", "on my own", "see you soon"]);
+        // one word isn't enough to go on
+        assert_eq!(phrases.complete("on"), None);
+        assert_eq!(phrases.complete("on "), None);
+        let completion = phrases.complete("I'm on my").unwrap();
+        assert_eq!(completion.phrase, "on my way");
+        assert_eq!(completion.commit, "my way");
+        // between the words, the rest of the phrase is committed
+        assert_eq!(phrases.complete("I'm on my ").unwrap().commit, "way");
+        assert_eq!(phrases.complete("see You s").unwrap().commit, "soon");
+        // a phrase that's been typed out in full has nothing left to complete
+        assert_eq!(phrases.complete("see you soon"), None);
+
+        // committing it records all its words, and learns the phrase
+        let mut history = History::new(8);
+        phrases.complete("I'm on my").unwrap();
+        assert!(!phrases.pick("myself", &mut history));
+        assert!(phrases.pick("my way", &mut history));
+        assert_eq!(history.len(), 2);
+        assert!(history.rank_of("my").is_some());
+        assert!(history.rank_of("way").is_some());
+
+        // once learned, a phrase is preferred over others starting the same way
+        let mut phrases = Phrases::new(&["on my own", "on my way"]);
+        assert_eq!(phrases.complete("on my").unwrap().phrase, "on my own");
+        assert_eq!(phrases.complete("on my w").unwrap().commit, "way");
+        assert!(phrases.pick("way", &mut history));
+        assert_eq!(phrases.complete("on my").unwrap().phrase, "on my way");
+    }
+}
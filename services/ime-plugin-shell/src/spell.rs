@@ -0,0 +1,90 @@
+use crate::history::History;
+use ime_plugin_api::InputSource;
+
+/// longest token that's corrected; past this a typo is more likely a word the dictionary lacks
+pub(crate) const MAX_CORRECTION_LEN: usize = 24;
+/// most dictionary entries compared against a token, most frequent first, so a large
+/// dictionary doesn't hold up the prediction on-device
+pub(crate) const MAX_CORRECTION_SCAN: usize = 1024;
+/// most corrections offered for a token
+pub(crate) const MAX_CORRECTIONS: usize = 4;
+
+/// Returns the ranks of the words to offer as corrections for the token `typed`, entered from
+/// `source`, when spell correction is `enabled`: the words one edit away from it, most
+/// frequent first. There are none if any word matches `typed`, as it's then taken to be
+/// the start of that word, or if it was pasted, as it's then taken to be what was meant.
+pub(crate) fn corrections(enabled: bool, history: &History, typed: &str, source: InputSource) -> Vec<usize> {
+    if !enabled || source == InputSource::Paste || typed.is_empty() || typed.chars().count() > MAX_CORRECTION_LEN {
+        return Vec::new();
+    }
+    let words = (0..history.len()).filter_map(|rank| history.get(rank).map(|w| (rank, w)));
+    if words.clone().any(|(_, w)| source.matches(typed, w)) {
+        return Vec::new();
+    }
+    words
+        .take(MAX_CORRECTION_SCAN)
+        .filter(|(_, w)| one_edit_apart(typed, w))
+        .map(|(rank, _)| rank)
+        .take(MAX_CORRECTIONS)
+        .collect()
+}
+
+/// `true` if `b` is `a` with one letter added, dropped or changed, or two adjacent letters
+/// swapped. Like `InputSource::matches()`, case is ignored.
+fn one_edit_apart(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().flat_map(char::to_lowercase).collect();
+    let b: Vec<char> = b.chars().flat_map(char::to_lowercase).collect();
+    if a == b {
+        return false;
+    }
+    // everything up to the first difference is common to both
+    let common = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[common..], &b[common..]);
+    if a.len() == b.len() {
+        a[1..] == b[1..] || (a.len() >= 2 && a[0] == b[1] && a[1] == b[0] && a[2..] == b[2..])
+    } else if a.len() == b.len() + 1 {
+        a[1..] == *b
+    } else if b.len() == a.len() + 1 {
+        *a == b[1..]
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrections() {
+        assert!(one_edit_apart("teh", "the"));
+        assert!(one_edit_apart("hte", "the"));
+        assert!(one_edit_apart("tha", "the"));
+        assert!(one_edit_apart("th", "the"));
+        assert!(one_edit_apart("thee", "the"));
+        assert!(one_edit_apart("Teh", "the"));
+        assert!(!one_edit_apart("the", "the"));
+        assert!(!one_edit_apart("The", "the"));
+        assert!(!one_edit_apart("eht", "the"));
+        assert!(!one_edit_apart("t", "the"));
+        assert!(one_edit_apart("café", "cafe"));
+
+        let mut history = History::new(8);
+        for w in ["the", "then", "they", "the", "cat", "cut", "cut", "the"].iter() {
+            history.pick(w);
+        }
+        let words = |ranks: Vec<usize>| -> Vec<&str> { ranks.iter().map(|&r| history.get(r).unwrap()).collect() };
+        // a common typo is only corrected when spell correction is on
+        assert!(corrections(false, &history, "teh", InputSource::Tap).is_empty());
+        assert_eq!(words(corrections(true, &history, "teh", InputSource::Tap)), vec!["the"]);
+        // the more frequent word goes first
+        assert_eq!(words(corrections(true, &history, "cst", InputSource::Tap)), vec!["cut", "cat"]);
+        // a token that starts a word isn't a typo
+        assert!(corrections(true, &history, "th", InputSource::Tap).is_empty());
+        assert!(corrections(true, &history, "", InputSource::Tap).is_empty());
+        assert!(corrections(true, &history, "xyz", InputSource::Tap).is_empty());
+        assert!(corrections(true, &history, "teh", InputSource::Paste).is_empty());
+        let long = "t".repeat(MAX_CORRECTION_LEN + 1);
+        assert!(corrections(true, &history, &long, InputSource::Tap).is_empty());
+    }
+}
@@ -61,13 +61,16 @@ fn main() -> ! {
                     log::warn!("Release called on a predictor that was in a released state");
                 }
             }),
-            Some(Opcode::Input) => {
+            Some(Opcode::Input) | Some(Opcode::SourcedInput) => {
             }
             Some(Opcode::Picked) => {
                 let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                 let s = buffer.as_flat::<String::<4000>, _>().unwrap();
                 tts.tts_simple(s.as_str()).unwrap();
             }
+            Some(Opcode::FeedbackBatch) => {
+                // pasted text is not read back
+            }
             Some(Opcode::Prediction) => {
                 // we don't check the API token, because we always return `false`
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
@@ -75,11 +78,51 @@ fn main() -> ! {
                 prediction.valid = false;
                 buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
             }
+            Some(Opcode::InlineCompletion) => {
+                // no predictions, so never a completion
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut completion = buffer.to_original::<InlineCompletion, _>().unwrap();
+                completion.suffix.clear();
+                buffer.replace(completion).expect("couldn't return InlineCompletion");
+            }
             Some(Opcode::Unpick) => {
             }
             Some(Opcode::GetPredictionTriggers) => {
                 xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
             }
+            Some(Opcode::WarmUp) => {
+                // the TTS connection is made at boot, there is nothing to warm up
+            }
+            Some(Opcode::SetTokenizer) => {
+                // input is spoken as-is, there are no tokens to learn
+            }
+            Some(Opcode::GetReadyState) => {
+                xous::return_scalar(msg.sender, ReadyState::Ready as usize).expect("couldn't return GetReadyState");
+            }
+            Some(Opcode::GetDictionaryInfo) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                // no dictionary, the input is passed through to the TTS engine
+                buffer.replace(DictionaryInfo::new("und", 0, 0)).expect("couldn't return GetDictionaryInfo");
+            }
+            Some(Opcode::SetInputDebounce) | Some(Opcode::InputDebounceElapsed) => {
+                // `Input` is ignored, there is nothing to re-rank
+            }
+            Some(Opcode::SetEchoFallback) => {
+                // predictions are never offered, so there is nothing to fall back from
+            }
+            Some(Opcode::SetPhrases) => {
+                // likewise, there are no predictions to complete a phrase with
+            }
+            Some(Opcode::SetSpellCorrect) => {
+                // nor any to correct with
+            }
+            Some(Opcode::SelfCheck) => {
+                // there's no dictionary or history to check, and the input isn't buffered
+                xous::return_scalar(msg.sender, SelfCheck::ALL as usize).expect("couldn't return SelfCheck");
+            }
+            Some(Opcode::NewField) => {
+                // input is spoken as it arrives, nothing carries over from the previous field
+            }
             Some(Opcode::Quit) => {
                 if api_token.is_some() {
                     log::error!("received quit, goodbye!"); break;
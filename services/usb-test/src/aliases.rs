@@ -0,0 +1,154 @@
+use crate::commands::is_command;
+
+/// most aliases the shell holds at once
+pub(crate) const MAX_ALIASES: usize = 16;
+/// most times a command line is expanded, so aliases that refer to each other can't loop
+pub(crate) const MAX_ALIAS_DEPTH: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum AliasError {
+    /// the name is empty, or has a space in it
+    InvalidName,
+    /// the name is taken by one of the shell's commands, which would always win
+    IsCommand,
+    /// an alias has to stand for something
+    EmptyCommand,
+    /// there's no room for another alias
+    Full,
+    /// no alias by that name
+    NotFound,
+    /// expansion went on past `MAX_ALIAS_DEPTH`, so the aliases probably refer to each other
+    TooDeep,
+}
+
+/// User-defined shorthands for command lines, set with `alias` and expanded before a command
+/// line is dispatched. The expansion replaces the command and keeps any arguments after it, so
+/// `alias ip pattern fill 0` makes `ip 16 inc` run `pattern fill 0 16 inc`.
+///
+/// They're held by the main loop, so they last until the process restarts. Over a suspend
+/// they're carried in a `ShellSnapshot`, along with the history.
+pub(crate) struct Aliases {
+    aliases: Vec<(String, String)>,
+}
+
+impl Aliases {
+    pub(crate) fn new() -> Aliases {
+        Aliases { aliases: Vec::new() }
+    }
+    /// Defines `name` to stand for `command`, replacing any alias of the same name
+    pub(crate) fn set(&mut self, name: &str, command: &str) -> Result<(), AliasError> {
+        if name.is_empty() || name.contains(' ') {
+            return Err(AliasError::InvalidName);
+        }
+        if is_command(name) {
+            return Err(AliasError::IsCommand);
+        }
+        let command = command.trim();
+        if command.is_empty() {
+            return Err(AliasError::EmptyCommand);
+        }
+        match self.aliases.iter().position(|(n, _)| n == name) {
+            Some(index) => self.aliases[index].1 = command.to_string(),
+            None if self.aliases.len() >= MAX_ALIASES => return Err(AliasError::Full),
+            None => self.aliases.push((name.to_string(), command.to_string())),
+        }
+        Ok(())
+    }
+    pub(crate) fn remove(&mut self, name: &str) -> Result<(), AliasError> {
+        let index = self.aliases.iter().position(|(n, _)| n == name).ok_or(AliasError::NotFound)?;
+        self.aliases.remove(index);
+        Ok(())
+    }
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.aliases.iter().find(|(n, _)| n == name).map(|(_, c)| c.as_str())
+    }
+    /// the aliases as (name, command), in the order they were defined
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(n, c)| (n.as_str(), c.as_str()))
+    }
+    /// Expands the aliases in `cmdline`. Returns `None` if it doesn't start with one. The shell's
+    /// own commands are never expanded, even if an alias was somehow given the same name.
+    pub(crate) fn expand(&self, cmdline: &str) -> Result<Option<String>, AliasError> {
+        let mut line = cmdline.to_string();
+        for depth in 0..=MAX_ALIAS_DEPTH {
+            let (cmd, args) = match line.split_once(' ') {
+                Some((cmd, args)) => (cmd, Some(args)),
+                None => (line.as_str(), None),
+            };
+            let command = match self.get(cmd) {
+                Some(command) if !is_command(cmd) => command,
+                _ => return Ok(if depth == 0 { None } else { Some(line) }),
+            };
+            if depth == MAX_ALIAS_DEPTH {
+                break;
+            }
+            line = match args {
+                Some(args) => format!("{} {}", command, args),
+                None => command.to_string(),
+            };
+        }
+        Err(AliasError::TooDeep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alias_expansion() {
+        let mut aliases = Aliases::new();
+        assert_eq!(aliases.set("on", "conn 1"), Ok(()));
+        assert_eq!(aliases.set("ip", "pattern fill 0"), Ok(()));
+        assert_eq!(aliases.expand("status"), Ok(None));
+        assert_eq!(aliases.expand("on"), Ok(Some("conn 1".to_string())));
+        // arguments are kept
+        assert_eq!(aliases.expand("ip 16 inc"), Ok(Some("pattern fill 0 16 inc".to_string())));
+        // aliases can build on each other
+        assert_eq!(aliases.set("ip16", "ip 16"), Ok(()));
+        assert_eq!(aliases.expand("ip16 prbs"), Ok(Some("pattern fill 0 16 prbs".to_string())));
+        // and can be redefined
+        assert_eq!(aliases.set("on", "conn 0"), Ok(()));
+        assert_eq!(aliases.expand("on"), Ok(Some("conn 0".to_string())));
+        assert_eq!(aliases.iter().count(), 3);
+
+        // real commands win
+        assert_eq!(aliases.set("status", "conn 1"), Err(AliasError::IsCommand));
+        aliases.aliases.push(("lint".to_string(), "conn 0".to_string()));
+        assert_eq!(aliases.expand("lint"), Ok(None));
+        aliases.aliases.pop();
+
+        assert_eq!(aliases.set("two words", "status"), Err(AliasError::InvalidName));
+        assert_eq!(aliases.set("s", "  "), Err(AliasError::EmptyCommand));
+        assert_eq!(aliases.remove("ip16"), Ok(()));
+        assert_eq!(aliases.remove("ip16"), Err(AliasError::NotFound));
+        assert_eq!(aliases.expand("ip16"), Ok(None));
+    }
+
+    #[test]
+    fn test_alias_recursion() {
+        let mut aliases = Aliases::new();
+        aliases.set("a", "b").unwrap();
+        aliases.set("b", "a x").unwrap();
+        assert_eq!(aliases.expand("a"), Err(AliasError::TooDeep));
+        aliases.set("self", "self").unwrap();
+        assert_eq!(aliases.expand("self"), Err(AliasError::TooDeep));
+
+        // a chain exactly as deep as the limit still expands
+        let mut aliases = Aliases::new();
+        for i in 0..MAX_ALIAS_DEPTH {
+            aliases.set(&format!("a{}", i), &format!("a{}", i + 1)).unwrap();
+        }
+        assert_eq!(aliases.expand("a0"), Ok(Some(format!("a{}", MAX_ALIAS_DEPTH))));
+        aliases.set(&format!("a{}", MAX_ALIAS_DEPTH), "status").unwrap();
+        assert_eq!(aliases.expand("a0"), Err(AliasError::TooDeep));
+
+        // there's only room for so many
+        let mut aliases = Aliases::new();
+        for i in 0..MAX_ALIASES {
+            aliases.set(&format!("a{}", i), "status").unwrap();
+        }
+        assert_eq!(aliases.set("more", "status"), Err(AliasError::Full));
+        assert_eq!(aliases.set("a0", "diag"), Ok(()));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+/// creator labels of the endpoint buffers, by endpoint index
+pub(crate) const EP_LABELS: [&str; 16] = [
+    "ep0", "ep1", "ep2", "ep3", "ep4", "ep5", "ep6", "ep7",
+    "ep8", "ep9", "ep10", "ep11", "ep12", "ep13", "ep14", "ep15",
+];
+
+/// An allocation that hasn't been freed yet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct LiveAlloc {
+    pub(crate) offset: u32,
+    pub(crate) len: u32,
+    /// ticktimer time of the allocation
+    pub(crate) timestamp: u64,
+    /// names the code that made the allocation
+    pub(crate) label: &'static str,
+}
+
+/// Usage of the descriptor memory allocator, for sizing the descriptor RAM: the high-watermarks
+/// of the bytes allocated, and when and by whom each live allocation was made. Lengths are as
+/// requested, before rounding up to the 16-byte alignment. The peaks are kept until
+/// `reset_peaks()`.
+pub(crate) struct AllocStats {
+    live: BTreeMap<u32, LiveAlloc>,
+    /// bytes currently allocated
+    total: u32,
+    /// most bytes allocated at once
+    peak_total: u32,
+    /// largest single allocation
+    peak_single: u32,
+}
+
+impl AllocStats {
+    pub(crate) fn new() -> AllocStats {
+        AllocStats {
+            live: BTreeMap::new(),
+            total: 0,
+            peak_total: 0,
+            peak_single: 0,
+        }
+    }
+    /// records a successful allocation of `len` bytes at `offset`
+    pub(crate) fn allocated(&mut self, offset: u32, len: u32, timestamp: u64, label: &'static str) {
+        if let Some(old) = self.live.insert(offset, LiveAlloc { offset, len, timestamp, label }) {
+            // the allocator never hands out a live offset, but don't count it twice if it does
+            self.total -= old.len;
+        }
+        self.total += len;
+        self.peak_total = self.peak_total.max(self.total);
+        self.peak_single = self.peak_single.max(len);
+    }
+    /// records a successful free of the allocation at `offset`
+    pub(crate) fn freed(&mut self, offset: u32) {
+        if let Some(old) = self.live.remove(&offset) {
+            self.total -= old.len;
+        }
+    }
+    pub(crate) fn total(&self) -> u32 {
+        self.total
+    }
+    pub(crate) fn peak_total(&self) -> u32 {
+        self.peak_total
+    }
+    pub(crate) fn peak_single(&self) -> u32 {
+        self.peak_single
+    }
+    /// Starts the peaks over from what's allocated now
+    pub(crate) fn reset_peaks(&mut self) {
+        self.peak_total = self.total;
+        self.peak_single = self.live.values().map(|a| a.len).max().unwrap_or(0);
+    }
+    /// the live allocations, oldest first
+    pub(crate) fn by_age(&self) -> Vec<LiveAlloc> {
+        let mut live: Vec<LiveAlloc> = self.live.values().copied().collect();
+        live.sort_by_key(|a| (a.timestamp, a.offset));
+        live
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_watermarks() {
+        let mut stats = AllocStats::new();
+        stats.allocated(0x60, 64, 10, "ep1");
+        stats.allocated(0xa0, 8, 20, "ep2");
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (72, 72, 64));
+        stats.freed(0x60);
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (8, 72, 64));
+        // the peak total only moves once usage goes past it
+        stats.allocated(0x60, 32, 30, "ep3");
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (40, 72, 64));
+        stats.allocated(0x100, 48, 40, "ep4");
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (88, 88, 64));
+        // a free of something that isn't allocated doesn't move the total
+        stats.freed(0x200);
+        assert_eq!(stats.total(), 88);
+
+        // resetting starts the peaks over from what's live
+        stats.freed(0x100);
+        stats.reset_peaks();
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (40, 40, 32));
+        stats.freed(0x60);
+        stats.freed(0xa0);
+        stats.reset_peaks();
+        assert_eq!((stats.total(), stats.peak_total(), stats.peak_single()), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_alloc_ages() {
+        let mut stats = AllocStats::new();
+        stats.allocated(0x100, 16, 30, "ep2");
+        stats.allocated(0x60, 64, 10, "ep1");
+        stats.allocated(0x200, 8, 20, "ep3");
+        stats.freed(0x200);
+        stats.allocated(0x300, 8, 30, "ep4");
+        let labels: Vec<&str> = stats.by_age().iter().map(|a| a.label).collect();
+        assert_eq!(labels, vec!["ep1", "ep2", "ep4"]);
+        assert_eq!(stats.by_age()[0], LiveAlloc { offset: 0x60, len: 64, timestamp: 10, label: "ep1" });
+    }
+}
@@ -0,0 +1,212 @@
+use crate::pattern::parse_number;
+use crate::{free_bytes, largest_free_block, try_alloc_inner, try_dealloc_inner, AllocatorConfig};
+use rand_chacha::ChaCha8Rng;
+use rand_chacha::rand_core::{SeedableRng, RngCore};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+/// iterations `alloctest` runs when not told otherwise, as many as `test_alloc` does
+pub(crate) const DEFAULT_ALLOCTEST_ITERATIONS: usize = 10240;
+/// iterations run per message to the main loop, between which other messages are handled
+pub(crate) const ALLOCTEST_CHUNK: usize = 256;
+/// number of operations kept for the dump of a failure
+pub(crate) const ALLOCTEST_TRACE_LEN: usize = 16;
+/// requests are drawn from `0..ALLOCTEST_MAX_REQUEST`, which includes the invalid 0
+const ALLOCTEST_MAX_REQUEST: u32 = 256;
+
+/// An operation `alloctest` did on its scratch allocation map
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum AllocOp {
+    /// a request for a number of bytes, and the offset it got
+    Alloc(u32, Option<u32>),
+    /// a free of the allocation at an offset
+    Free(u32),
+}
+impl fmt::Display for AllocOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocOp::Alloc(len, Some(offset)) => write!(f, "alloc {} -> 0x{:x}", len, offset),
+            AllocOp::Alloc(len, None) => write!(f, "alloc {} -> none", len),
+            AllocOp::Free(offset) => write!(f, "free 0x{:x}", offset),
+        }
+    }
+}
+
+/// A run of the randomized allocator test of `test_alloc`, on target. It works on a scratch
+/// allocation map with the live allocator's bounds, never on the live one, and checks the map
+/// after every operation. The operations come from ChaCha8 with the logged seed, so a failure
+/// can be replayed on the host by running an `AllocTest` with the same seed.
+pub(crate) struct AllocTest {
+    cfg: AllocatorConfig,
+    allocs: BTreeMap<u32, u32>,
+    /// offsets handed out and not freed yet
+    live: Vec<u32>,
+    rng: ChaCha8Rng,
+    pub(crate) seed: u64,
+    pub(crate) iterations: usize,
+    /// iterations run so far
+    pub(crate) done: usize,
+    /// the latest operations, with the iteration they were done in
+    trace: VecDeque<(usize, AllocOp)>,
+    /// the first inconsistency found; the run stops there
+    pub(crate) failure: Option<String>,
+    /// most fragmented the free space got: the share of it, in percent, outside the largest
+    /// free block
+    pub(crate) peak_fragmentation: u32,
+}
+
+impl AllocTest {
+    pub(crate) fn new(cfg: AllocatorConfig, iterations: usize, seed: u64) -> AllocTest {
+        AllocTest {
+            cfg,
+            allocs: BTreeMap::new(),
+            live: Vec::new(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            seed,
+            iterations,
+            done: 0,
+            trace: VecDeque::with_capacity(ALLOCTEST_TRACE_LEN),
+            failure: None,
+            peak_fragmentation: 0,
+        }
+    }
+    /// Parses the arguments of `alloctest`: `[iterations] [seed]`. A missing seed is `None`,
+    /// to be picked by the caller.
+    pub(crate) fn parse(args: &str) -> Option<(usize, Option<u64>)> {
+        let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+        let (iterations, seed) = match tokens[..] {
+            [] => (DEFAULT_ALLOCTEST_ITERATIONS, None),
+            [iterations] => (parse_number(iterations)?, None),
+            [iterations, seed] => (parse_number(iterations)?, Some(parse_number(seed)? as u64)),
+            _ => return None,
+        };
+        if iterations == 0 {
+            return None;
+        }
+        Some((iterations, seed))
+    }
+    pub(crate) fn is_done(&self) -> bool {
+        self.done >= self.iterations || self.failure.is_some()
+    }
+    fn record(&mut self, op: AllocOp) {
+        if self.trace.len() == ALLOCTEST_TRACE_LEN {
+            self.trace.pop_front();
+        }
+        self.trace.push_back((self.done, op));
+    }
+    /// One iteration: a coin flip between freeing a random live allocation and a random request
+    fn step(&mut self) -> Result<(), String> {
+        if self.rng.next_u32() % 2 == 0 {
+            if !self.live.is_empty() {
+                let offset = self.live.remove((self.rng.next_u32() % self.live.len() as u32) as usize);
+                self.record(AllocOp::Free(offset));
+                try_dealloc_inner(self.cfg, &mut self.allocs, offset)
+                    .map_err(|e| format!("free of live allocation 0x{:x} failed: {:?}", offset, e))?;
+            }
+        } else {
+            let requested = self.rng.next_u32() % ALLOCTEST_MAX_REQUEST;
+            let largest = largest_free_block(self.cfg, &self.allocs);
+            let result = try_alloc_inner(self.cfg, &mut self.allocs, requested).ok();
+            self.record(AllocOp::Alloc(requested, result));
+            match result {
+                Some(offset) => self.live.push(offset),
+                None if requested > 0 && requested <= largest => {
+                    return Err(format!("alloc {} failed with a {} byte block free", requested, largest));
+                }
+                None => {}
+            }
+        }
+        self.check()
+    }
+    /// checks the allocation map against the live allocations and the allocator's bounds
+    fn check(&mut self) -> Result<(), String> {
+        if self.allocs.len() != self.live.len() || self.live.iter().any(|o| !self.allocs.contains_key(o)) {
+            return Err(format!("{} allocations in the map, {} handed out", self.allocs.len(), self.live.len()));
+        }
+        let mut last_end = self.cfg.start;
+        for (&offset, &len) in self.allocs.iter() {
+            if offset & 0xF != 0 {
+                return Err(format!("misaligned allocation at 0x{:x}", offset));
+            }
+            if offset < last_end {
+                return Err(format!("allocation at 0x{:x} overlaps the one ending at 0x{:x}", offset, last_end));
+            }
+            if offset + len > self.cfg.end {
+                return Err(format!("allocation at 0x{:x}({}) runs past 0x{:x}", offset, len, self.cfg.end));
+            }
+            last_end = offset + ((len + 0xF) & !0xF);
+        }
+        let free = free_bytes(self.cfg, &self.allocs);
+        if free > 0 {
+            let fragmentation = (free - largest_free_block(self.cfg, &self.allocs)) * 100 / free;
+            self.peak_fragmentation = self.peak_fragmentation.max(fragmentation);
+        }
+        Ok(())
+    }
+    /// Runs up to `n` more iterations, stopping at the first failure
+    pub(crate) fn run(&mut self, n: usize) {
+        let end = self.iterations.min(self.done + n);
+        while self.done < end && self.failure.is_none() {
+            if let Err(e) = self.step() {
+                self.failure = Some(e);
+                break;
+            }
+            self.done += 1;
+        }
+    }
+    pub(crate) fn log_summary(&self) {
+        match &self.failure {
+            None => log::info!("alloctest: {} of {} iterations, seed {}: PASS, peak fragmentation {}%",
+                self.done, self.iterations, self.seed, self.peak_fragmentation),
+            Some(e) => {
+                log::info!("alloctest: FAIL at iteration {}, seed {}: {}", self.done, self.seed, e);
+                log::info!("alloctest: peak fragmentation {}%; last {} operations:", self.peak_fragmentation, self.trace.len());
+                for (iteration, op) in self.trace.iter() {
+                    log::info!("alloctest: #{} {}", iteration, op);
+                }
+                log::info!("alloctest: replay with `alloctest {} {}`, over 0x{:04x}-0x{:04x}",
+                    self.iterations, self.seed, self.cfg.start, self.cfg.end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloctest() {
+        assert_eq!(AllocTest::parse(""), Some((DEFAULT_ALLOCTEST_ITERATIONS, None)));
+        assert_eq!(AllocTest::parse("500"), Some((500, None)));
+        assert_eq!(AllocTest::parse("500 0x1234"), Some((500, Some(0x1234))));
+        assert_eq!(AllocTest::parse("0"), None);
+        assert_eq!(AllocTest::parse("500 1 2"), None);
+
+        let cfg = AllocatorConfig::default();
+        let mut test = AllocTest::new(cfg, 1000, 7);
+        // run in chunks, as on target
+        while !test.is_done() {
+            test.run(ALLOCTEST_CHUNK);
+        }
+        assert_eq!(test.failure, None);
+        assert_eq!(test.done, 1000);
+        assert_eq!(test.trace.len(), ALLOCTEST_TRACE_LEN);
+        assert!(test.peak_fragmentation > 0 && test.peak_fragmentation < 100);
+
+        // the same seed replays the same operations
+        let mut replay = AllocTest::new(cfg, 1000, 7);
+        replay.run(1000);
+        assert_eq!(replay.trace, test.trace);
+        assert_eq!(replay.peak_fragmentation, test.peak_fragmentation);
+
+        // a leaked allocation is caught
+        test.live.pop().unwrap();
+        test.iterations += 10;
+        test.run(10);
+        assert!(test.failure.is_some());
+        assert!(test.is_done());
+        assert_eq!(format!("{}", AllocOp::Alloc(64, Some(0x60))), "alloc 64 -> 0x60");
+        assert_eq!(format!("{}", AllocOp::Free(0x60)), "free 0x60");
+    }
+}
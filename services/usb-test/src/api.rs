@@ -12,6 +12,85 @@ pub(crate) enum Opcode {
     HandlerTrigger,
     /// Suspend/resume callback
     SuspendResume,
+    /// Register a server to receive decoded key events; see `KeyListenerRegistration`
+    RegisterKeyListener,
+    /// Query the result of the last `kbdtest` run. Returns (KbdTestState, number of faults)
+    KbdTestResult,
+    /// Periodic check for stuck transfers, and for the end of NAK windows
+    WatchdogTick,
+    /// Snapshot of the device's counters, for automated tests; see `UsbStatus`
+    GetStatus,
+    /// Counters of messages the main loop couldn't act on. Returns two scalars; see
+    /// `DiagCounters::pack()`
+    GetDiagCounters,
+    /// The shell's commands, for host-side harnesses; see `CommandList`
+    ListCommands,
+    /// Runs the next pass of a `memtest`; sent by the server to itself
+    MemTestPass,
+    /// Runs the next chunk of iterations of an `alloctest`; sent by the server to itself
+    AllocTestChunk,
+    /// Register a server to be told when VBUS comes and goes; see `VbusListenerRegistration`
+    RegisterVbusListener,
     /// Exits the server
     Quit,
+    /// Synthetic keyboard input for automated tests, handled exactly like a `KeyboardChar`.
+    /// Bypasses the real input path, so it's only in test and hosted builds, or with `inject-key`.
+    #[cfg(any(test, feature = "hosted", feature = "inject-key"))]
+    InjectKey,
+}
+
+/// Registration record for `Opcode::RegisterKeyListener`. Each decoded key event is forwarded to
+/// the named server as a scalar message of `(char, modifiers, pressed, 0)` on `listener_op_id`.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct KeyListenerRegistration {
+    pub server_name: xous_ipc::String::<64>,
+    pub listener_op_id: usize,
+}
+
+/// Registration record for `Opcode::RegisterVbusListener`. Each debounced VBUS transition is sent
+/// to the named server as a scalar message of `(present, 0, 0, 0)` on `listener_op_id`.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct VbusListenerRegistration {
+    pub server_name: xous_ipc::String::<64>,
+    pub listener_op_id: usize,
+}
+
+/// Snapshot of the device's counters, returned by `Opcode::GetStatus`
+#[derive(Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct UsbStatus {
+    pub ep_stats: [crate::EpStats; 16],
+    pub stuck_transfers: u32,
+    /// bus resets seen, and how many of them came after the host addressed the device but
+    /// before it configured it
+    pub bus_resets: u32,
+    pub resets_mid_config: u32,
+    /// debounced VBUS level, and the attaches and detaches seen since startup
+    pub vbus: bool,
+    pub vbus_attaches: u32,
+    pub vbus_detaches: u32,
+    pub dfu: crate::DfuStatus,
+    pub fido: crate::FidoStatus,
+}
+
+/// most commands a `CommandList` can carry
+pub(crate) const MAX_COMMANDS: usize = 48;
+
+/// A shell command, as listed by `Opcode::ListCommands`
+#[derive(Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct CommandEntry {
+    pub name: xous_ipc::String::<16>,
+    /// summary of the arguments, as the command's usage message gives them
+    pub args: xous_ipc::String::<96>,
+    /// `CMD_ARGS`, `CMD_BARE` and `CMD_ASYNC`
+    pub flags: u32,
+}
+
+/// The shell's commands, returned by `Opcode::ListCommands`. `version` changes whenever the
+/// command set changes incompatibly, so a harness can tell it's talking to a shell it doesn't
+/// know; see `SHELL_VERSION`.
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct CommandList {
+    pub version: u32,
+    pub count: u32,
+    pub commands: [CommandEntry; MAX_COMMANDS],
 }
@@ -0,0 +1,102 @@
+/// How far the host has got enumerating the device, as the bus driver sees it from the SETUP
+/// packets going past
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum EnumState {
+    /// reset, answering on address 0
+    Default,
+    /// given an address, not configured yet
+    Addressed,
+    Configured,
+}
+
+/// Tracks bus resets, and the enumeration state each one drops the device back from.
+///
+/// A reset leaves only EP0 live: the other endpoints are disarmed so stale transfers don't go
+/// out to a host that hasn't configured the device yet. The OUT endpoints among them are held
+/// here, and handed back to be armed again once the host sets a configuration.
+#[derive(Debug)]
+pub(crate) struct BusState {
+    state: EnumState,
+    /// resets seen
+    pub(crate) resets: u32,
+    /// resets that came after the host addressed the device but before it configured it
+    pub(crate) resets_mid_config: u32,
+    /// OUT endpoints, as a bitmask, waiting for a configuration to be armed again
+    parked_out: u16,
+    /// state the latest reset came in, until the main loop picks it up
+    unseen_reset: Option<EnumState>,
+}
+impl BusState {
+    pub(crate) fn new() -> BusState {
+        BusState { state: EnumState::Default, resets: 0, resets_mid_config: 0, parked_out: 0, unseen_reset: None }
+    }
+    pub(crate) fn state(&self) -> EnumState {
+        self.state
+    }
+    /// Records a bus reset, which disarmed the OUT endpoints in the bitmask `out_eps`
+    pub(crate) fn reset(&mut self, out_eps: u16) {
+        self.resets += 1;
+        if self.state == EnumState::Addressed {
+            self.resets_mid_config += 1;
+        }
+        self.unseen_reset = Some(self.state);
+        self.state = EnumState::Default;
+        self.parked_out |= out_eps;
+    }
+    /// Records a SET_ADDRESS
+    pub(crate) fn addressed(&mut self, address: u8) {
+        self.state = if address == 0 { EnumState::Default } else { EnumState::Addressed };
+    }
+    /// Records a SET_CONFIGURATION. Returns the OUT endpoints to arm again, as a bitmask; they're
+    /// only handed out once per reset.
+    pub(crate) fn configured(&mut self, config: u8) -> u16 {
+        if config == 0 {
+            // deconfigured: back to addressed, and the endpoints stay as they are
+            if self.state == EnumState::Configured {
+                self.state = EnumState::Addressed;
+            }
+            return 0;
+        }
+        self.state = EnumState::Configured;
+        core::mem::take(&mut self.parked_out)
+    }
+    /// The state the device was in when the latest reset came, if it hasn't been taken yet
+    pub(crate) fn take_reset(&mut self) -> Option<EnumState> {
+        self.unseen_reset.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_mid_config() {
+        let mut bus = BusState::new();
+        // the reset that follows enabling the core, before anything was set up
+        bus.reset(0b0100);
+        assert_eq!(bus.take_reset(), Some(EnumState::Default));
+        assert_eq!(bus.take_reset(), None);
+        bus.addressed(5);
+        assert_eq!(bus.state(), EnumState::Addressed);
+        // the host gives up on this enumeration and resets the device before configuring it
+        bus.reset(0b0100);
+        assert_eq!(bus.state(), EnumState::Default);
+        assert_eq!(bus.take_reset(), Some(EnumState::Addressed));
+        assert_eq!((bus.resets, bus.resets_mid_config), (2, 1));
+
+        // a second go goes all the way, and the OUT endpoint is armed once
+        bus.addressed(6);
+        assert_eq!(bus.configured(1), 0b0100);
+        assert_eq!(bus.state(), EnumState::Configured);
+        assert_eq!(bus.configured(1), 0);
+        // deconfiguring keeps the address; a reset from configured isn't mid-configuration
+        bus.configured(0);
+        assert_eq!(bus.state(), EnumState::Addressed);
+        bus.configured(1);
+        bus.reset(0b1000);
+        assert_eq!(bus.take_reset(), Some(EnumState::Configured));
+        assert_eq!((bus.resets, bus.resets_mid_config), (3, 1));
+        assert_eq!(bus.configured(1), 0b1000);
+    }
+}
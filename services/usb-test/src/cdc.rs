@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// max packet size of the bulk data endpoints
+pub(crate) const CDC_PACKET_LEN: usize = 64;
+/// max packet size of the notification endpoint; no notifications are sent, but ACM requires it
+const NOTIFY_PACKET_LEN: u16 = 8;
+/// bInterval of the notification endpoint, in frames
+const NOTIFY_INTERVAL: u8 = 255;
+/// bytes received and not echoed back yet past which further data is dropped
+pub(crate) const ECHO_QUEUE_LIMIT: usize = 1024;
+
+const CDC_COMM_CLASS: u8 = 0x02;
+const CDC_SUBCLASS_ACM: u8 = 0x02;
+const CDC_DATA_CLASS: u8 = 0x0A;
+const DESC_TYPE_CS_INTERFACE: u8 = 0x24;
+const CDC_FUNC_HEADER: u8 = 0x00;
+const CDC_FUNC_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_FUNC_ACM: u8 = 0x02;
+const CDC_FUNC_UNION: u8 = 0x06;
+/// bmCapabilities of the ACM functional descriptor: line coding and control line state
+const ACM_CAP_LINE: u8 = 1 << 1;
+
+const REQ_SET_LINE_CODING: u8 = 0x20;
+const REQ_GET_LINE_CODING: u8 = 0x21;
+const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
+const REQ_SEND_BREAK: u8 = 0x23;
+/// DTR bit of SET_CONTROL_LINE_STATE's wValue
+const CONTROL_LINE_DTR: u16 = 1 << 0;
+
+/// Line settings, as set by SET_LINE_CODING. They're only kept to be reported back: the port
+/// isn't connected to a UART.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct LineCoding {
+    pub(crate) baud: u32,
+    /// bCharFormat: 0 for 1 stop bit, 1 for 1.5, 2 for 2
+    pub(crate) stop_bits: u8,
+    /// bParityType: none, odd, even, mark or space
+    pub(crate) parity: u8,
+    pub(crate) data_bits: u8,
+}
+impl Default for LineCoding {
+    fn default() -> LineCoding {
+        LineCoding { baud: 115_200, stop_bits: 0, parity: 0, data_bits: 8 }
+    }
+}
+impl LineCoding {
+    pub(crate) fn from_bytes(data: &[u8]) -> Option<LineCoding> {
+        if data.len() < 7 {
+            return None;
+        }
+        Some(LineCoding {
+            baud: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            stop_bits: data[4],
+            parity: data[5],
+            data_bits: data[6],
+        })
+    }
+    pub(crate) fn to_bytes(&self) -> [u8; 7] {
+        let baud = self.baud.to_le_bytes();
+        [baud[0], baud[1], baud[2], baud[3], self.stop_bits, self.parity, self.data_bits]
+    }
+}
+
+/// Bytes received from the host, waiting to be sent back as packets of up to
+/// `CDC_PACKET_LEN` bytes. A transfer that ends on a full packet is followed by a zero-length
+/// packet, so the host doesn't wait for more.
+pub(crate) struct EchoQueue {
+    data: VecDeque<u8>,
+    /// the last packet sent was a full one, and nothing followed it
+    needs_zlp: bool,
+    dropped: u32,
+}
+impl EchoQueue {
+    pub(crate) fn new() -> EchoQueue {
+        EchoQueue { data: VecDeque::new(), needs_zlp: false, dropped: 0 }
+    }
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+        self.needs_zlp = false;
+    }
+    /// Queues `bytes`, dropping what doesn't fit under `ECHO_QUEUE_LIMIT`
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        let room = ECHO_QUEUE_LIMIT - self.data.len();
+        self.data.extend(bytes.iter().take(room));
+        self.dropped += bytes.len().saturating_sub(room) as u32;
+    }
+    /// number of bytes dropped because the queue was full
+    pub(crate) fn dropped(&self) -> u32 {
+        self.dropped
+    }
+    /// the next packet to send, if there's anything to send
+    pub(crate) fn next_packet(&self) -> Option<Vec<u8>> {
+        if self.data.is_empty() && !self.needs_zlp {
+            return None;
+        }
+        Some(self.data.iter().take(CDC_PACKET_LEN).copied().collect())
+    }
+    /// records that a packet of `len` bytes from `next_packet()` was sent
+    pub(crate) fn sent(&mut self, len: usize) {
+        self.data.drain(..len);
+        self.needs_zlp = len == CDC_PACKET_LEN && self.data.is_empty();
+    }
+}
+
+/// Counters of the CDC-ACM interface, logged by `class`
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct CdcStatus {
+    pub(crate) rx_bytes: u32,
+    pub(crate) tx_bytes: u32,
+    /// the host has asserted DTR, which terminal programs do when they open the port
+    pub(crate) dtr: bool,
+    /// bytes dropped because the echo queue was full
+    pub(crate) dropped: u32,
+}
+
+/// A CDC-ACM serial port, with a communications interface and its notification endpoint, and
+/// a data interface whose bulk endpoints echo back whatever the host sends.
+pub(crate) struct CdcAcm<'a, B: UsbBus> {
+    comm_iface: InterfaceNumber,
+    data_iface: InterfaceNumber,
+    ep_notify: EndpointIn<'a, B>,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    line_coding: LineCoding,
+    echo: EchoQueue,
+    status: CdcStatus,
+}
+impl<'a, B: UsbBus> CdcAcm<'a, B> {
+    pub(crate) fn new(alloc: &'a UsbBusAllocator<B>) -> CdcAcm<'a, B> {
+        CdcAcm {
+            comm_iface: alloc.interface(),
+            data_iface: alloc.interface(),
+            ep_notify: alloc.interrupt(NOTIFY_PACKET_LEN, NOTIFY_INTERVAL),
+            ep_in: alloc.bulk(CDC_PACKET_LEN as u16),
+            ep_out: alloc.bulk(CDC_PACKET_LEN as u16),
+            line_coding: LineCoding::default(),
+            echo: EchoQueue::new(),
+            status: CdcStatus::default(),
+        }
+    }
+    pub(crate) fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+    pub(crate) fn status(&self) -> CdcStatus {
+        CdcStatus { dropped: self.echo.dropped(), ..self.status }
+    }
+    /// sends the next packet of echoed data, if the IN endpoint is free
+    fn send_next(&mut self) {
+        if let Some(packet) = self.echo.next_packet() {
+            match self.ep_in.write(&packet) {
+                Ok(len) => {
+                    self.echo.sent(len);
+                    self.status.tx_bytes += len as u32;
+                }
+                Err(UsbError::WouldBlock) => {}
+                Err(e) => log::warn!("cdc: couldn't send echo: {:?}", e),
+            }
+        }
+    }
+    fn is_ours(&self, index: u16) -> bool {
+        index == u8::from(self.comm_iface) as u16
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcAcm<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.comm_iface, CDC_COMM_CLASS, CDC_SUBCLASS_ACM, 0)?;
+        writer.write(DESC_TYPE_CS_INTERFACE, &[
+            CDC_FUNC_HEADER,
+            0x10, 0x01, // bcdCDC 1.10
+        ])?;
+        writer.write(DESC_TYPE_CS_INTERFACE, &[
+            CDC_FUNC_CALL_MANAGEMENT,
+            0, // bmCapabilities: the device doesn't handle call management itself
+            u8::from(self.data_iface),
+        ])?;
+        writer.write(DESC_TYPE_CS_INTERFACE, &[CDC_FUNC_ACM, ACM_CAP_LINE])?;
+        writer.write(DESC_TYPE_CS_INTERFACE, &[
+            CDC_FUNC_UNION,
+            u8::from(self.comm_iface), // bControlInterface
+            u8::from(self.data_iface), // bSubordinateInterface0
+        ])?;
+        writer.endpoint(&self.ep_notify)?;
+        writer.interface(self.data_iface, CDC_DATA_CLASS, 0, 0)?;
+        writer.endpoint(&self.ep_out)?;
+        writer.endpoint(&self.ep_in)
+    }
+    fn reset(&mut self) {
+        self.echo.clear();
+        self.status.dtr = false;
+    }
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || !self.is_ours(req.index)
+        {
+            return;
+        }
+        match req.request {
+            REQ_GET_LINE_CODING => {
+                xfer.accept_with(&self.line_coding.to_bytes()).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || !self.is_ours(req.index)
+        {
+            return;
+        }
+        match req.request {
+            REQ_SET_LINE_CODING => match LineCoding::from_bytes(xfer.data()) {
+                Some(coding) => {
+                    log::info!("cdc: line coding {:?}", coding);
+                    self.line_coding = coding;
+                    xfer.accept().ok();
+                }
+                None => {
+                    xfer.reject().ok();
+                }
+            },
+            REQ_SET_CONTROL_LINE_STATE => {
+                let dtr = req.value & CONTROL_LINE_DTR != 0;
+                if dtr != self.status.dtr {
+                    log::info!("cdc: DTR {}", if dtr { "asserted" } else { "released" });
+                }
+                self.status.dtr = dtr;
+                xfer.accept().ok();
+            }
+            REQ_SEND_BREAK => {
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut packet = [0u8; CDC_PACKET_LEN];
+        match self.ep_out.read(&mut packet) {
+            Ok(len) => {
+                self.status.rx_bytes += len as u32;
+                self.echo.push(&packet[..len]);
+                self.send_next();
+            }
+            Err(e) => log::warn!("cdc: couldn't read data: {:?}", e),
+        }
+    }
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_in.address() {
+            self.send_next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cdc_echo() {
+        let coding = LineCoding { baud: 9600, stop_bits: 2, parity: 1, data_bits: 7 };
+        assert_eq!(coding.to_bytes(), [0x80, 0x25, 0, 0, 2, 1, 7]);
+        assert_eq!(LineCoding::from_bytes(&coding.to_bytes()), Some(coding));
+        assert_eq!(LineCoding::from_bytes(&[0x80, 0x25, 0, 0]), None);
+
+        let mut echo = EchoQueue::new();
+        assert_eq!(echo.next_packet(), None);
+        // a transfer longer than a packet goes back in full packets, then the rest
+        let data: Vec<u8> = (0..CDC_PACKET_LEN as u8 + 10).collect();
+        echo.push(&data);
+        let first = echo.next_packet().unwrap();
+        assert_eq!(first, data[..CDC_PACKET_LEN].to_vec());
+        echo.sent(first.len());
+        assert_eq!(echo.next_packet(), Some(data[CDC_PACKET_LEN..].to_vec()));
+        echo.sent(10);
+        assert_eq!(echo.next_packet(), None);
+
+        // one that ends on a full packet is followed by a zero-length packet
+        echo.push(&[0x55; CDC_PACKET_LEN]);
+        echo.sent(CDC_PACKET_LEN);
+        assert_eq!(echo.next_packet(), Some(Vec::new()));
+        echo.sent(0);
+        assert_eq!(echo.next_packet(), None);
+
+        // past the limit, data is dropped and counted
+        echo.push(&vec![0; ECHO_QUEUE_LIMIT - 4]);
+        echo.push(&[1; 10]);
+        assert_eq!(echo.dropped(), 6);
+        echo.clear();
+        assert_eq!(echo.next_packet(), None);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::api::{CommandEntry, CommandList, MAX_COMMANDS};
+
+/// Version of the command set, as reported by `Opcode::ListCommands` and `help --machine`.
+/// Bumped whenever a command is removed, or its arguments change incompatibly.
+pub(crate) const SHELL_VERSION: u32 = 1;
+
+/// the command takes arguments
+pub(crate) const CMD_ARGS: u32 = 1 << 0;
+/// the command can be run without arguments
+pub(crate) const CMD_BARE: u32 = 1 << 1;
+/// the command carries on in the background after the command line returns
+pub(crate) const CMD_ASYNC: u32 = 1 << 2;
+
+/// A shell command, as listed by `help`
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    /// summary of the arguments, in the form the usage messages give them
+    pub(crate) args: &'static str,
+    pub(crate) flags: u32,
+}
+
+/// Every command the shell dispatches. Keep in step with the `DoCmd` handler.
+pub(crate) const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { name: "help", args: "[--machine]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "test", args: "<arg>", flags: CMD_ARGS },
+    CommandSpec { name: "conn", args: "[1,0]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "status", args: "", flags: CMD_BARE },
+    CommandSpec { name: "dfu", args: "[on,off]", flags: CMD_ARGS },
+    CommandSpec { name: "fido", args: "[on,off]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "class", args: "[hid,cdc,msc]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "interval", args: "<ep 1-15> <frames>", flags: CMD_ARGS },
+    CommandSpec { name: "reconn", args: "", flags: CMD_BARE },
+    CommandSpec { name: "reenumerate", args: "[debounce ms]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "vbus", args: "[on,off,real]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "autoconn", args: "[on,off]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "nak", args: "<ep 1-15> <ms>", flags: CMD_ARGS },
+    CommandSpec { name: "susorder", args: "[suspend,resume] <kbd,usb or usb,kbd>", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "reset", args: "<count>", flags: CMD_ARGS | CMD_BARE | CMD_ASYNC },
+    CommandSpec { name: "regs", args: "[base,diff]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "trap", args: "[add <offset> [r,w,rw],list,clear]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "loglevel", args: "[<off,error,warn,info,debug,trace,default> [usb,kbd,setup]]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "setuptrace", args: "[on,off]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "poison", args: "[on,off]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "allochist", args: "[on,off]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "epstats", args: "[ep,clear]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "watchdog", args: "[<ms>,off] [--kill]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "pattern", args: "[fill,check] <offset> <len> [inc,prbs,const,poison] [seed/value]", flags: CMD_ARGS },
+    CommandSpec { name: "memtest", args: "<offset> <len> [passes] [-f]", flags: CMD_ARGS | CMD_ASYNC },
+    CommandSpec { name: "alloctest", args: "[iterations] [seed]", flags: CMD_ARGS | CMD_BARE | CMD_ASYNC },
+    CommandSpec { name: "memstat", args: "[-v,reset]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "timeline", args: "[n]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "lint", args: "", flags: CMD_BARE },
+    CommandSpec { name: "descdump", args: "", flags: CMD_BARE },
+    CommandSpec { name: "diag", args: "", flags: CMD_BARE },
+    CommandSpec { name: "listeners", args: "", flags: CMD_BARE },
+    CommandSpec { name: "kbdtest", args: "", flags: CMD_BARE | CMD_ASYNC },
+    CommandSpec { name: "repeat", args: "<count> [-k] [-q] <command...>", flags: CMD_ARGS | CMD_ASYNC },
+    CommandSpec { name: "history", args: "[clear]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "alias", args: "[<name> [<command...>]]", flags: CMD_ARGS | CMD_BARE },
+    CommandSpec { name: "unalias", args: "<name>", flags: CMD_ARGS },
+];
+
+/// `true` if `name` is one of the shell's own commands
+pub(crate) fn is_command(name: &str) -> bool {
+    command_spec(name).is_some()
+}
+pub(crate) fn command_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+impl CommandList {
+    /// Builds the list for `Opcode::ListCommands` from `commands`. Names and argument summaries
+    /// are truncated to fit, and commands past `MAX_COMMANDS` are left out.
+    pub(crate) fn from_registry(commands: &[CommandSpec]) -> CommandList {
+        let mut list = CommandList {
+            version: SHELL_VERSION,
+            count: 0,
+            commands: [CommandEntry::default(); MAX_COMMANDS],
+        };
+        for (entry, spec) in list.commands.iter_mut().zip(commands.iter()) {
+            entry.name.append(spec.name).ok();
+            entry.args.append(spec.args).ok();
+            entry.flags = spec.flags;
+            list.count += 1;
+        }
+        list
+    }
+    pub(crate) fn entries(&self) -> &[CommandEntry] {
+        &self.commands[..self.count as usize]
+    }
+}
+
+/// `help --machine`: a header line with the version and the number of commands, then one line
+/// per command of its name, flags in hex and argument summary, separated by tabs.
+pub(crate) fn machine_help(list: &CommandList) -> Vec<String> {
+    let mut lines = vec![format!("shell\t{}\t{}", list.version, list.count)];
+    for entry in list.entries() {
+        lines.push(format!("cmd\t{}\t{:x}\t{}", entry.name, entry.flags, entry.args));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_list_round_trip() {
+        use rkyv::{ser::{Serializer, serializers::BufferSerializer}, Deserialize};
+        assert!(COMMANDS.len() <= MAX_COMMANDS);
+        let list = CommandList::from_registry(COMMANDS);
+
+        #[repr(align(16))]
+        struct Aligned([u8; 16384]);
+        let mut storage = Aligned([0; 16384]);
+        let mut ser = BufferSerializer::new(&mut storage.0[..]);
+        let pos = ser.serialize_value(&list).unwrap();
+        let bytes = ser.into_inner();
+        let archived = unsafe { rkyv::archived_value::<CommandList>(bytes, pos) };
+        let received: CommandList = archived.deserialize(&mut xous_ipc::XousDeserializer {}).unwrap();
+
+        assert_eq!(received.version, SHELL_VERSION);
+        assert_eq!(received.entries().len(), COMMANDS.len());
+        for (entry, spec) in received.entries().iter().zip(COMMANDS.iter()) {
+            assert_eq!(entry.name.as_str().unwrap(), spec.name);
+            assert_eq!(entry.args.as_str().unwrap(), spec.args);
+            assert_eq!(entry.flags, spec.flags);
+        }
+        let lines = machine_help(&received);
+        assert_eq!(lines[0], format!("shell\t{}\t{}", SHELL_VERSION, COMMANDS.len()));
+        assert!(lines.contains(&"cmd\treset\t7\t<count>".to_string()));
+        assert!(lines.contains(&"cmd\tlint\t2\t".to_string()));
+    }
+}
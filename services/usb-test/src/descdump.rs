@@ -0,0 +1,208 @@
+/// Descriptor dump: prints the descriptors sent to the host in the indented layout of
+/// `lsusb -v`, so the device's view can be compared line by line with the host's.
+use crate::descriptors::*;
+
+/// Formats one `name value` line, with the value right-aligned like lsusb does
+fn field(lines: &mut Vec<String>, indent: usize, name: &str, value: String) {
+    lines.push(format!("{:indent$}{:<19} {:>5}", "", name, value, indent = indent));
+}
+
+/// Like `field()`, followed by `note` if there is one
+fn noted(lines: &mut Vec<String>, indent: usize, name: &str, value: String, note: &str) {
+    field(lines, indent, name, value);
+    if !note.is_empty() {
+        let line = lines.last_mut().unwrap();
+        line.push(' ');
+        line.push_str(note);
+    }
+}
+
+/// The text of string descriptor `index`, if the host has asked for it
+fn string_text(set: &DescriptorSet, index: u8) -> String {
+    match set.strings.get(&index) {
+        Some(s) if index != 0 && s.len() >= 2 => {
+            let units: Vec<u16> = s[2..].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::new(),
+    }
+}
+
+/// Formats a BCD version such as bcdUSB as e.g. "2.00"
+fn bcd(d: &[u8], offset: usize) -> String {
+    let v = u16::from_le_bytes([d[offset], d[offset + 1]]);
+    format!("{:x}.{:02x}", v >> 8, v & 0xFF)
+}
+
+fn class_name(class: u8) -> &'static str {
+    match class {
+        0x00 => "(Defined at Interface level)",
+        0x01 => "Audio",
+        0x02 => "Communications",
+        0x03 => "Human Interface Device",
+        0x08 => "Mass Storage",
+        0x09 => "Hub",
+        0x0A => "CDC Data",
+        0xEF => "Miscellaneous Device",
+        0xFE => "Application Specific Interface",
+        0xFF => "Vendor Specific Class",
+        _ => "",
+    }
+}
+
+/// Lists the descriptors in `set`, one line per field. The device descriptor comes first, with
+/// the configuration and everything in it nested underneath. A descriptor that's truncated or
+/// of a type that isn't decoded is dumped as raw bytes, and the walk stops at a zero bLength.
+pub(crate) fn dump_descriptors(set: &DescriptorSet) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut indent = 0;
+    if let Some(d) = &set.device {
+        if d.len() < 18 {
+            lines.push(format!("Device Descriptor: ** truncated: {:02x?}", d));
+        } else {
+            lines.push("Device Descriptor:".to_string());
+            field(&mut lines, 2, "bLength", d[0].to_string());
+            field(&mut lines, 2, "bDescriptorType", d[1].to_string());
+            field(&mut lines, 2, "bcdUSB", bcd(d, 2));
+            noted(&mut lines, 2, "bDeviceClass", d[4].to_string(), class_name(d[4]));
+            field(&mut lines, 2, "bDeviceSubClass", d[5].to_string());
+            field(&mut lines, 2, "bDeviceProtocol", d[6].to_string());
+            field(&mut lines, 2, "bMaxPacketSize0", d[7].to_string());
+            field(&mut lines, 2, "idVendor", format!("0x{:04x}", u16::from_le_bytes([d[8], d[9]])));
+            field(&mut lines, 2, "idProduct", format!("0x{:04x}", u16::from_le_bytes([d[10], d[11]])));
+            field(&mut lines, 2, "bcdDevice", bcd(d, 12));
+            noted(&mut lines, 2, "iManufacturer", d[14].to_string(), &string_text(set, d[14]));
+            noted(&mut lines, 2, "iProduct", d[15].to_string(), &string_text(set, d[15]));
+            noted(&mut lines, 2, "iSerial", d[16].to_string(), &string_text(set, d[16]));
+            field(&mut lines, 2, "bNumConfigurations", d[17].to_string());
+        }
+        indent = 2;
+    }
+    if let Some(c) = &set.configuration {
+        dump_configuration(set, c, indent, &mut lines);
+    }
+    lines
+}
+
+fn dump_configuration(set: &DescriptorSet, c: &[u8], base: usize, lines: &mut Vec<String>) {
+    let mut offset = 0;
+    // indent of the fields of the current interface's descriptors
+    let mut interface_indent = base + 4;
+    while offset + 2 <= c.len() {
+        let length = c[offset] as usize;
+        if length == 0 {
+            lines.push(format!("{:indent$}** zero bLength at offset {}", "", offset, indent = base));
+            break;
+        }
+        if offset + length > c.len() {
+            lines.push(format!("{:indent$}** truncated at offset {}: {:02x?}", "", offset, &c[offset..], indent = base));
+            break;
+        }
+        let d = &c[offset..offset + length];
+        match (d[1], length) {
+            (DESC_TYPE_CONFIGURATION, 9) => {
+                let i = base + 2;
+                lines.push(format!("{:indent$}Configuration Descriptor:", "", indent = base));
+                field(lines, i, "bLength", d[0].to_string());
+                field(lines, i, "bDescriptorType", d[1].to_string());
+                field(lines, i, "wTotalLength", format!("0x{:04x}", u16::from_le_bytes([d[2], d[3]])));
+                field(lines, i, "bNumInterfaces", d[4].to_string());
+                field(lines, i, "bConfigurationValue", d[5].to_string());
+                noted(lines, i, "iConfiguration", d[6].to_string(), &string_text(set, d[6]));
+                field(lines, i, "bmAttributes", format!("0x{:02x}", d[7]));
+                lines.push(format!("{:indent$}({})", "", if d[7] & 0x40 != 0 { "Self Powered" } else { "Bus Powered" }, indent = i + 2));
+                if d[7] & 0x20 != 0 {
+                    lines.push(format!("{:indent$}Remote Wakeup", "", indent = i + 2));
+                }
+                field(lines, i, "MaxPower", format!("{}mA", d[8] as u32 * 2));
+            }
+            (DESC_TYPE_INTERFACE, 9) => {
+                let i = base + 4;
+                interface_indent = i;
+                lines.push(format!("{:indent$}Interface Descriptor:", "", indent = base + 2));
+                field(lines, i, "bLength", d[0].to_string());
+                field(lines, i, "bDescriptorType", d[1].to_string());
+                field(lines, i, "bInterfaceNumber", d[2].to_string());
+                field(lines, i, "bAlternateSetting", d[3].to_string());
+                field(lines, i, "bNumEndpoints", d[4].to_string());
+                noted(lines, i, "bInterfaceClass", d[5].to_string(), class_name(d[5]));
+                field(lines, i, "bInterfaceSubClass", d[6].to_string());
+                field(lines, i, "bInterfaceProtocol", d[7].to_string());
+                noted(lines, i, "iInterface", d[8].to_string(), &string_text(set, d[8]));
+            }
+            (DESC_TYPE_HID, _) if length >= 6 => {
+                let i = interface_indent + 4;
+                lines.push(format!("{:indent$}HID Device Descriptor:", "", indent = interface_indent + 2));
+                field(lines, i, "bLength", d[0].to_string());
+                field(lines, i, "bDescriptorType", d[1].to_string());
+                field(lines, i, "bcdHID", bcd(d, 2));
+                field(lines, i, "bCountryCode", d[4].to_string());
+                field(lines, i, "bNumDescriptors", d[5].to_string());
+                for class_desc in d[6..].chunks(3) {
+                    if let [desc_type, lo, hi] = *class_desc {
+                        noted(lines, i, "bDescriptorType", desc_type.to_string(), if desc_type == 0x22 { "Report" } else { "" });
+                        field(lines, i, "wDescriptorLength", u16::from_le_bytes([lo, hi]).to_string());
+                    }
+                }
+            }
+            (DESC_TYPE_ENDPOINT, 7) => {
+                let i = interface_indent + 2;
+                lines.push(format!("{:indent$}Endpoint Descriptor:", "", indent = interface_indent));
+                field(lines, i, "bLength", d[0].to_string());
+                field(lines, i, "bDescriptorType", d[1].to_string());
+                noted(lines, i, "bEndpointAddress", format!("0x{:02x}", d[2]),
+                    &format!(" EP {} {}", d[2] & 0xF, if d[2] & 0x80 != 0 { "IN" } else { "OUT" }));
+                field(lines, i, "bmAttributes", d[3].to_string());
+                let transfer = ["Control", "Isochronous", "Bulk", "Interrupt"][(d[3] & 0b11) as usize];
+                lines.push(format!("{:indent$}Transfer Type            {}", "", transfer, indent = i + 2));
+                let size = u16::from_le_bytes([d[4], d[5]]);
+                noted(lines, i, "wMaxPacketSize", format!("0x{:04x}", size), &format!(" 1x {} bytes", size & 0x7FF));
+                field(lines, i, "bInterval", d[6].to_string());
+            }
+            _ => {
+                lines.push(format!("{:indent$}** UNRECOGNIZED: {:02x?}", "", d, indent = interface_indent));
+            }
+        }
+        offset += length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_keyboard() {
+        let mut set = DescriptorSet::default();
+        set.device = Some(vec![18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x09, 0x12, 0x13, 0x36, 0x10, 0x01, 1, 2, 0, 1]);
+        set.configuration = Some(vec![
+            9, 2, 34, 0, 1, 1, 0, 0xA0, 50,
+            9, 4, 0, 0, 1, 3, 1, 1, 0,
+            9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0,
+            7, 5, 0x81, 3, 8, 0, 10,
+        ]);
+        // "Xous", as UTF-16LE
+        set.strings.insert(2, vec![10, 3, b'X', 0, b'o', 0, b'u', 0, b's', 0]);
+        let lines = dump_descriptors(&set);
+        assert_eq!(lines[0], "Device Descriptor:");
+        assert!(lines.contains(&"  idVendor            0x1209".to_string()));
+        assert!(lines.contains(&"  bcdUSB               2.00".to_string()));
+        assert!(lines.contains(&"  bcdDevice            1.10".to_string()));
+        assert!(lines.contains(&"  iProduct                2 Xous".to_string()));
+        assert!(lines.contains(&"  iManufacturer           1".to_string()));
+        assert!(lines.contains(&"  Configuration Descriptor:".to_string()));
+        assert!(lines.contains(&"    MaxPower            100mA".to_string()));
+        assert!(lines.contains(&"      Remote Wakeup".to_string()));
+        assert!(lines.contains(&"      bInterfaceClass         3 Human Interface Device".to_string()));
+        assert!(lines.contains(&"        HID Device Descriptor:".to_string()));
+        assert!(lines.contains(&"          wDescriptorLength      63".to_string()));
+        assert!(lines.contains(&"        bEndpointAddress     0x81  EP 1 IN".to_string()));
+        assert!(lines.contains(&"        wMaxPacketSize      0x0008  1x 8 bytes".to_string()));
+        assert!(lines.contains(&"          Transfer Type            Interrupt".to_string()));
+
+        // what's resident in memory is dumped even when it's broken
+        set.configuration.as_mut().unwrap().truncate(30);
+        let lines = dump_descriptors(&set);
+        assert_eq!(lines.last().unwrap(), "  ** truncated at offset 27: [07, 05, 81]");
+    }
+}
@@ -0,0 +1,370 @@
+/// Descriptor lint: structural checks on USB descriptors, to turn cryptic host-side enumeration
+/// failures into a list of specific problems with byte offsets.
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub(crate) const DESC_TYPE_DEVICE: u8 = 1;
+pub(crate) const DESC_TYPE_CONFIGURATION: u8 = 2;
+pub(crate) const DESC_TYPE_STRING: u8 = 3;
+pub(crate) const DESC_TYPE_INTERFACE: u8 = 4;
+pub(crate) const DESC_TYPE_ENDPOINT: u8 = 5;
+pub(crate) const DESC_TYPE_HID: u8 = 0x21;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LintError {
+    /// bLength is zero, which would make a parser spin forever
+    ZeroLength,
+    /// bLength runs past the end of the data
+    Truncated { length: u8, available: usize },
+    /// bLength is not legal for the descriptor type
+    BadLength { desc_type: u8, length: u8 },
+    /// the descriptor is not of the type expected at this position
+    BadType { expected: u8, actual: u8 },
+    /// bMaxPacketSize0 must be 8, 16, 32 or 64 for a full speed device
+    BadMaxPacketSize0(u8),
+    /// wTotalLength does not match the bytes in the configuration
+    TotalLength { declared: u16, actual: usize },
+    /// bNumInterfaces does not match the number of interfaces in the configuration
+    NumInterfaces { declared: u8, actual: u8 },
+    /// interface numbers must start at 0 and increase by one
+    InterfaceNumber { expected: u8, actual: u8 },
+    /// bNumEndpoints does not match the endpoints that follow the interface
+    NumEndpoints { interface: u8, declared: u8, actual: u8 },
+    /// the endpoint address is already used by another interface
+    DuplicateEndpoint(u8),
+    /// endpoint descriptor found outside of an interface
+    OrphanEndpoint(u8),
+    /// HID descriptor bLength must be 6 + 3 * bNumDescriptors
+    HidLength { length: u8, num_descriptors: u8 },
+}
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintError::ZeroLength => write!(f, "bLength is 0"),
+            LintError::Truncated { length, available } =>
+                write!(f, "bLength {} runs past end of data ({} bytes left)", length, available),
+            LintError::BadLength { desc_type, length } =>
+                write!(f, "bLength {} is illegal for descriptor type 0x{:02x}", length, desc_type),
+            LintError::BadType { expected, actual } =>
+                write!(f, "expected descriptor type 0x{:02x}, got 0x{:02x}", expected, actual),
+            LintError::BadMaxPacketSize0(size) => write!(f, "bMaxPacketSize0 {} is not one of 8/16/32/64", size),
+            LintError::TotalLength { declared, actual } =>
+                write!(f, "wTotalLength {} does not match actual length {}", declared, actual),
+            LintError::NumInterfaces { declared, actual } =>
+                write!(f, "bNumInterfaces {} but {} interfaces found", declared, actual),
+            LintError::InterfaceNumber { expected, actual } =>
+                write!(f, "interface number {} out of sequence, expected {}", actual, expected),
+            LintError::NumEndpoints { interface, declared, actual } =>
+                write!(f, "interface {} declares {} endpoints but has {}", interface, declared, actual),
+            LintError::DuplicateEndpoint(addr) => write!(f, "endpoint address 0x{:02x} used more than once", addr),
+            LintError::OrphanEndpoint(addr) => write!(f, "endpoint 0x{:02x} is not inside an interface", addr),
+            LintError::HidLength { length, num_descriptors } =>
+                write!(f, "HID bLength {} inconsistent with bNumDescriptors {}", length, num_descriptors),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct LintViolation {
+    /// byte offset of the offending descriptor within its descriptor set
+    pub(crate) offset: usize,
+    pub(crate) error: LintError,
+}
+
+/// A copy of the descriptors handed to the host, as read back from descriptor memory after each
+/// packet was written.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DescriptorSet {
+    pub(crate) device: Option<Vec<u8>>,
+    /// the full configuration descriptor, including its interface/endpoint/class descriptors
+    pub(crate) configuration: Option<Vec<u8>>,
+    /// string descriptors, by index
+    pub(crate) strings: BTreeMap<u8, Vec<u8>>,
+}
+impl DescriptorSet {
+    /// returns the capture buffer for a given GET_DESCRIPTOR type/index, if it's one we lint
+    pub(crate) fn buffer_for(&mut self, desc_type: u8, index: u8) -> Option<&mut Vec<u8>> {
+        match desc_type {
+            DESC_TYPE_DEVICE => Some(self.device.get_or_insert_with(Vec::new)),
+            DESC_TYPE_CONFIGURATION => Some(self.configuration.get_or_insert_with(Vec::new)),
+            DESC_TYPE_STRING => Some(self.strings.entry(index).or_insert_with(Vec::new)),
+            _ => None,
+        }
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.device.is_none() && self.configuration.is_none() && self.strings.is_empty()
+    }
+}
+
+/// Checks the bLength/bDescriptorType header of the descriptor at `offset`. Returns the bLength
+/// if the descriptor can be walked past.
+fn check_header(data: &[u8], offset: usize, violations: &mut Vec<LintViolation>) -> Option<usize> {
+    let available = data.len() - offset;
+    if available < 2 {
+        violations.push(LintViolation { offset, error: LintError::Truncated { length: data[offset], available } });
+        return None;
+    }
+    let length = data[offset];
+    if length == 0 {
+        violations.push(LintViolation { offset, error: LintError::ZeroLength });
+        return None;
+    }
+    if length as usize > available {
+        violations.push(LintViolation { offset, error: LintError::Truncated { length, available } });
+        return None;
+    }
+    Some(length as usize)
+}
+
+pub(crate) fn validate_device(data: &[u8]) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+    if data.is_empty() {
+        return violations;
+    }
+    if let Some(length) = check_header(data, 0, &mut violations) {
+        if data[1] != DESC_TYPE_DEVICE {
+            violations.push(LintViolation { offset: 0, error: LintError::BadType { expected: DESC_TYPE_DEVICE, actual: data[1] } });
+        }
+        if length != 18 {
+            violations.push(LintViolation { offset: 0, error: LintError::BadLength { desc_type: DESC_TYPE_DEVICE, length: length as u8 } });
+        } else {
+            match data[7] {
+                8 | 16 | 32 | 64 => (),
+                size => violations.push(LintViolation { offset: 7, error: LintError::BadMaxPacketSize0(size) }),
+            }
+        }
+    }
+    violations
+}
+
+pub(crate) fn validate_configuration(data: &[u8]) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+    if data.is_empty() {
+        return violations;
+    }
+    let length = match check_header(data, 0, &mut violations) {
+        Some(l) => l,
+        None => return violations,
+    };
+    if data[1] != DESC_TYPE_CONFIGURATION {
+        violations.push(LintViolation { offset: 0, error: LintError::BadType { expected: DESC_TYPE_CONFIGURATION, actual: data[1] } });
+        return violations;
+    }
+    if length != 9 {
+        violations.push(LintViolation { offset: 0, error: LintError::BadLength { desc_type: DESC_TYPE_CONFIGURATION, length: length as u8 } });
+        return violations;
+    }
+    let total_length = u16::from_le_bytes([data[2], data[3]]);
+    if total_length as usize != data.len() {
+        violations.push(LintViolation { offset: 2, error: LintError::TotalLength { declared: total_length, actual: data.len() } });
+    }
+    let num_interfaces = data[4];
+
+    let mut interfaces: u8 = 0;
+    // (interface number, alternate setting, offset, declared endpoints, counted endpoints)
+    let mut current: Option<(u8, u8, usize, u8, u8)> = None;
+    // endpoint address -> (interface number, alternate setting)
+    let mut endpoints = BTreeMap::<u8, (u8, u8)>::new();
+    let mut offset = length;
+    let check_endpoint_count = |current: Option<(u8, u8, usize, u8, u8)>, violations: &mut Vec<LintViolation>| {
+        if let Some((interface, _alt, at, declared, actual)) = current {
+            if declared != actual {
+                violations.push(LintViolation { offset: at, error: LintError::NumEndpoints { interface, declared, actual } });
+            }
+        }
+    };
+    while offset < data.len() {
+        let length = match check_header(data, offset, &mut violations) {
+            Some(l) => l,
+            None => break,
+        };
+        let desc_type = data[offset + 1];
+        match desc_type {
+            DESC_TYPE_INTERFACE => {
+                if length != 9 {
+                    violations.push(LintViolation { offset, error: LintError::BadLength { desc_type, length: length as u8 } });
+                } else {
+                    check_endpoint_count(current, &mut violations);
+                    let number = data[offset + 2];
+                    let alt = data[offset + 3];
+                    if alt == 0 {
+                        if number != interfaces {
+                            violations.push(LintViolation { offset, error: LintError::InterfaceNumber { expected: interfaces, actual: number } });
+                        }
+                        interfaces = interfaces.saturating_add(1);
+                    }
+                    current = Some((number, alt, offset, data[offset + 4], 0));
+                }
+            }
+            DESC_TYPE_ENDPOINT => {
+                // 9 is legal for audio class endpoints
+                if length != 7 && length != 9 {
+                    violations.push(LintViolation { offset, error: LintError::BadLength { desc_type, length: length as u8 } });
+                } else {
+                    let addr = data[offset + 2];
+                    if let Some((number, alt, _, _, ref mut count)) = current {
+                        *count += 1;
+                        if let Some(&(other_number, other_alt)) = endpoints.get(&addr) {
+                            // alternate settings of the same interface may re-use an endpoint
+                            if other_number != number || other_alt == alt {
+                                violations.push(LintViolation { offset, error: LintError::DuplicateEndpoint(addr) });
+                            }
+                        }
+                        endpoints.insert(addr, (number, alt));
+                    } else {
+                        violations.push(LintViolation { offset, error: LintError::OrphanEndpoint(addr) });
+                    }
+                }
+            }
+            DESC_TYPE_HID => {
+                if length < 6 {
+                    violations.push(LintViolation { offset, error: LintError::BadLength { desc_type, length: length as u8 } });
+                } else {
+                    let num_descriptors = data[offset + 5];
+                    if length != 6 + 3 * num_descriptors as usize {
+                        violations.push(LintViolation { offset, error: LintError::HidLength { length: length as u8, num_descriptors } });
+                    }
+                }
+            }
+            _ => (), // class and vendor descriptors are only checked for a sane header
+        }
+        offset += length;
+    }
+    check_endpoint_count(current, &mut violations);
+    if interfaces != num_interfaces {
+        violations.push(LintViolation { offset: 4, error: LintError::NumInterfaces { declared: num_interfaces, actual: interfaces } });
+    }
+    violations
+}
+
+pub(crate) fn validate_string(index: u8, data: &[u8]) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+    if data.is_empty() {
+        return violations;
+    }
+    if let Some(length) = check_header(data, 0, &mut violations) {
+        if data[1] != DESC_TYPE_STRING {
+            violations.push(LintViolation { offset: 0, error: LintError::BadType { expected: DESC_TYPE_STRING, actual: data[1] } });
+        }
+        // UTF-16 payload must be an even number of bytes; string 0 holds at least one LANGID
+        if length % 2 != 0 || (index == 0 && length < 4) || length != data.len() {
+            violations.push(LintViolation { offset: 0, error: LintError::BadLength { desc_type: DESC_TYPE_STRING, length: length as u8 } });
+        }
+    }
+    violations
+}
+
+/// Runs every check over a descriptor set. Each violation is tagged with the name of the
+/// descriptor it was found in.
+pub(crate) fn validate_descriptors(set: &DescriptorSet) -> Vec<(std::string::String, LintViolation)> {
+    let mut results = Vec::new();
+    if let Some(device) = &set.device {
+        for v in validate_device(device) {
+            results.push(("device".to_string(), v));
+        }
+    }
+    if let Some(config) = &set.configuration {
+        for v in validate_configuration(config) {
+            results.push(("configuration".to_string(), v));
+        }
+    }
+    for (&index, string) in set.strings.iter() {
+        for v in validate_string(index, string) {
+            results.push((format!("string {}", index), v));
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: [u8; 18] = [
+        18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x09, 0x12, 0x13, 0x36, 0x10, 0x01, 1, 2, 3, 1,
+    ];
+    // a boot keyboard: one HID interface with an IN and an OUT endpoint
+    const CONFIG: [u8; 41] = [
+        9, 2, 41, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 2, 3, 1, 1, 0,
+        9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0,
+        7, 5, 0x81, 3, 8, 0, 10,
+        7, 5, 0x01, 3, 8, 0, 10,
+    ];
+
+    fn errors(v: Vec<LintViolation>) -> Vec<LintError> {
+        v.iter().map(|v| v.error).collect()
+    }
+
+    #[test]
+    fn test_valid_descriptors() {
+        assert!(validate_device(&DEVICE).is_empty());
+        assert!(validate_configuration(&CONFIG).is_empty());
+        assert!(validate_string(0, &[4, 3, 0x09, 0x04]).is_empty());
+        assert!(validate_string(1, &[6, 3, b'h', 0, b'i', 0]).is_empty());
+    }
+
+    #[test]
+    fn test_bad_device() {
+        let mut d = DEVICE;
+        d[7] = 12;
+        assert_eq!(validate_device(&d), vec![LintViolation { offset: 7, error: LintError::BadMaxPacketSize0(12) }]);
+        let mut d = DEVICE;
+        d[0] = 17;
+        assert_eq!(errors(validate_device(&d)), vec![LintError::BadLength { desc_type: 1, length: 17 }]);
+    }
+
+    #[test]
+    fn test_total_length() {
+        let mut c = CONFIG;
+        c[2] = 40;
+        assert_eq!(validate_configuration(&c), vec![
+            LintViolation { offset: 2, error: LintError::TotalLength { declared: 40, actual: 41 } }
+        ]);
+    }
+
+    #[test]
+    fn test_endpoint_collision() {
+        let mut c = CONFIG;
+        c[36] = 0x81; // second endpoint now collides with the first
+        assert_eq!(validate_configuration(&c), vec![
+            LintViolation { offset: 34, error: LintError::DuplicateEndpoint(0x81) }
+        ]);
+    }
+
+    #[test]
+    fn test_interface_numbering() {
+        let mut c = CONFIG;
+        c[11] = 1; // interface 1 without an interface 0
+        assert_eq!(errors(validate_configuration(&c)), vec![LintError::InterfaceNumber { expected: 0, actual: 1 }]);
+        let mut c = CONFIG;
+        c[4] = 2;
+        assert_eq!(errors(validate_configuration(&c)), vec![LintError::NumInterfaces { declared: 2, actual: 1 }]);
+        let mut c = CONFIG;
+        c[13] = 1;
+        assert_eq!(validate_configuration(&c), vec![
+            LintViolation { offset: 9, error: LintError::NumEndpoints { interface: 0, declared: 1, actual: 2 } }
+        ]);
+    }
+
+    #[test]
+    fn test_hid_length() {
+        let mut c = CONFIG;
+        c[23] = 2; // claims two class descriptors but only has room for one
+        assert_eq!(validate_configuration(&c), vec![
+            LintViolation { offset: 18, error: LintError::HidLength { length: 9, num_descriptors: 2 } }
+        ]);
+    }
+
+    #[test]
+    fn test_truncated_and_zero_length() {
+        let mut c = CONFIG;
+        c[34] = 0;
+        assert_eq!(errors(validate_configuration(&c)), vec![LintError::ZeroLength, LintError::NumEndpoints { interface: 0, declared: 2, actual: 1 }]);
+        let c = &CONFIG[..38];
+        let v = validate_configuration(c);
+        assert_eq!(v[0], LintViolation { offset: 2, error: LintError::TotalLength { declared: 41, actual: 38 } });
+        assert_eq!(v[1], LintViolation { offset: 34, error: LintError::Truncated { length: 7, available: 4 } });
+        assert_eq!(errors(validate_string(1, &[5, 3, b'h', 0, b'i'])), vec![LintError::BadLength { desc_type: 3, length: 5 }]);
+    }
+}
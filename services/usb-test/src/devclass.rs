@@ -0,0 +1,313 @@
+use usb_device::class_prelude::*;
+use usb_device::prelude::*;
+
+use crate::cdc::CdcAcm;
+use crate::dfu::{DfuRuntime, DfuStatus};
+use crate::fido::{FidoHid, FidoStatus, FIDO_POLL_INTERVAL_MS};
+use crate::msc::MassStorage;
+
+/// The function the device presents to the host, chosen with `class`. Only the active class is
+/// built, by `UsbFunctions`, so that its interfaces are numbered from 0 as the host expects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum DeviceClass {
+    /// the NKRO boot keyboard
+    Hid,
+    /// a CDC-ACM serial port that echoes back what it receives; see `CdcAcm`
+    Cdc,
+    /// a bulk-only mass storage RAM disk; see `MassStorage`
+    Msc,
+}
+impl Default for DeviceClass {
+    fn default() -> DeviceClass {
+        DeviceClass::Hid
+    }
+}
+
+impl DeviceClass {
+    pub(crate) const ALL: [DeviceClass; 3] = [DeviceClass::Hid, DeviceClass::Cdc, DeviceClass::Msc];
+
+    pub(crate) fn parse(name: &str) -> Option<DeviceClass> {
+        DeviceClass::ALL.iter().copied().find(|c| c.name() == name)
+    }
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DeviceClass::Hid => "hid",
+            DeviceClass::Cdc => "cdc",
+            DeviceClass::Msc => "msc",
+        }
+    }
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            DeviceClass::Hid => "keyboard",
+            DeviceClass::Cdc => "serial port, echoes what it receives",
+            DeviceClass::Msc => "mass storage, RAM disk",
+        }
+    }
+}
+
+/// The active device class, and the one selected with `class` that takes over on the next
+/// `reconn` or `reenumerate`. Switching classes under a configured host would leave it talking to interfaces
+/// the device no longer has, so the switch waits for the re-enumeration.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct ClassSelect {
+    active: DeviceClass,
+    selected: DeviceClass,
+}
+impl ClassSelect {
+    pub(crate) fn active(&self) -> DeviceClass {
+        self.active
+    }
+    pub(crate) fn selected(&self) -> DeviceClass {
+        self.selected
+    }
+    pub(crate) fn select(&mut self, class: DeviceClass) {
+        self.selected = class;
+    }
+    /// `true` if the selected class isn't the active one yet
+    pub(crate) fn is_pending(&self) -> bool {
+        self.selected != self.active
+    }
+    /// Makes the selected class the active one, ahead of a re-enumeration. Returns `true` if
+    /// that changed the active class.
+    pub(crate) fn apply(&mut self) -> bool {
+        let changed = self.is_pending();
+        self.active = self.selected;
+        changed
+    }
+}
+
+/// What `UsbFunctions` are built for: the class, and whether the DFU run-time and FIDO
+/// interfaces are enabled
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct UsbLayout {
+    pub(crate) class: DeviceClass,
+    pub(crate) dfu: bool,
+    pub(crate) fido: bool,
+}
+
+/// The functions built on one `UsbBusAllocator`: the active class, followed by the DFU run-time
+/// and FIDO interfaces if they're enabled. Only what's enabled is allocated, so the interfaces
+/// are numbered from 0 without gaps. usb-device can't allocate once the device is built, so a
+/// change of layout means building these again on a new allocator, carrying over the DFU and
+/// FIDO state with `carry_over()`.
+pub(crate) struct UsbFunctions<'a, B: UsbBus, K> {
+    layout: UsbLayout,
+    /// the keyboard, if the class is `Hid`. Its type is whatever the HID class builder makes of
+    /// the interfaces added to it, so it's left to the caller to build.
+    pub(crate) keyboard: Option<K>,
+    pub(crate) cdc: Option<CdcAcm<'a, B>>,
+    pub(crate) msc: Option<MassStorage<'a, B>>,
+    pub(crate) dfu: Option<DfuRuntime>,
+    pub(crate) fido: Option<FidoHid<'a, B>>,
+}
+impl<'a, B: UsbBus, K: UsbClass<B>> UsbFunctions<'a, B, K> {
+    /// Builds the functions for `layout` on `alloc`, in interface order. `keyboard` builds the
+    /// keyboard, and is only called if the class is `Hid`.
+    pub(crate) fn build(
+        alloc: &'a UsbBusAllocator<B>,
+        layout: UsbLayout,
+        keyboard: impl FnOnce(&'a UsbBusAllocator<B>) -> K,
+    ) -> UsbFunctions<'a, B, K> {
+        UsbFunctions {
+            layout,
+            keyboard: (layout.class == DeviceClass::Hid).then(|| keyboard(alloc)),
+            cdc: (layout.class == DeviceClass::Cdc).then(|| CdcAcm::new(alloc)),
+            msc: (layout.class == DeviceClass::Msc).then(|| MassStorage::new(alloc)),
+            dfu: layout.dfu.then(|| DfuRuntime::new(alloc, true)),
+            fido: layout.fido.then(|| FidoHid::new(alloc, FIDO_POLL_INTERVAL_MS)),
+        }
+    }
+    /// the layout these functions were built for
+    pub(crate) fn layout(&self) -> UsbLayout {
+        self.layout
+    }
+    /// Takes over the state that outlives a change of layout from `old`, the functions this
+    /// replaces. The class's own state, such as the RAM disk, starts afresh, as does that of
+    /// an interface that's just been enabled.
+    pub(crate) fn carry_over(&mut self, old: &mut UsbFunctions<'_, B, K>) {
+        if let (Some(dfu), Some(old_dfu)) = (self.dfu.as_mut(), old.dfu.as_mut()) {
+            dfu.state = core::mem::take(&mut old_dfu.state);
+        }
+        if let (Some(fido), Some(old_fido)) = (self.fido.as_mut(), old.fido.as_ref()) {
+            fido.restore(old_fido.status());
+        }
+    }
+    /// the DFU run-time interface's status, all zeroes if it isn't enabled
+    pub(crate) fn dfu_status(&self) -> DfuStatus {
+        self.dfu.as_ref().map(|dfu| dfu.state.status()).unwrap_or_default()
+    }
+    /// the FIDO interface's status, all zeroes if it isn't enabled
+    pub(crate) fn fido_status(&self) -> FidoStatus {
+        self.fido.as_ref().map(|fido| fido.status()).unwrap_or_default()
+    }
+    /// the functions to hand to `UsbDevice::poll()`
+    pub(crate) fn classes(&mut self) -> Vec<&mut dyn UsbClass<B>> {
+        let mut classes: Vec<&mut dyn UsbClass<B>> = Vec::new();
+        if let Some(keyboard) = self.keyboard.as_mut() {
+            classes.push(keyboard);
+        }
+        if let Some(cdc) = self.cdc.as_mut() {
+            classes.push(cdc);
+        }
+        if let Some(msc) = self.msc.as_mut() {
+            classes.push(msc);
+        }
+        if let Some(dfu) = self.dfu.as_mut() {
+            classes.push(dfu);
+        }
+        if let Some(fido) = self.fido.as_mut() {
+            classes.push(fido);
+        }
+        classes
+    }
+}
+
+/// Builds the device on `alloc`, once the functions have been
+pub(crate) fn build_device<B: UsbBus>(alloc: &UsbBusAllocator<B>) -> UsbDevice<'_, B> {
+    UsbDeviceBuilder::new(alloc, UsbVidPid(0x1209, 0x3613))
+        .manufacturer("usbd-human-interface-device")
+        .product("NKRO Keyboard")
+        .serial_number("PRECURSOR")
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptors::validate_configuration;
+    use crate::EmbeddedClock;
+    use std::sync::Mutex;
+    use usb_device::bus::PollResult;
+    use usb_device::UsbDirection;
+    use usbd_human_interface_device::device::keyboard::NKROBootKeyboardInterface;
+    use usbd_human_interface_device::prelude::*;
+
+    /// A bus with a host that asks for the configuration descriptor, and nothing else
+    #[derive(Default)]
+    struct DescriptorBus {
+        allocated: Vec<EndpointAddress>,
+        /// the SETUP packet of the request, until it's read
+        setup: Mutex<Option<[u8; 8]>>,
+        /// everything written to ep0
+        sent: Mutex<Vec<u8>>,
+        /// an ep0 IN packet is waiting for the host to take it
+        in_pending: Mutex<bool>,
+    }
+    impl UsbBus for DescriptorBus {
+        fn alloc_ep(
+            &mut self,
+            ep_dir: UsbDirection,
+            ep_addr: Option<EndpointAddress>,
+            _ep_type: EndpointType,
+            _max_packet_size: u16,
+            _interval: u8,
+        ) -> Result<EndpointAddress> {
+            let addr = match ep_addr {
+                Some(addr) => addr,
+                None => (1..16)
+                    .map(|index| EndpointAddress::from_parts(index, ep_dir))
+                    .find(|addr| !self.allocated.contains(addr))
+                    .ok_or(UsbError::EndpointOverflow)?,
+            };
+            self.allocated.push(addr);
+            Ok(addr)
+        }
+        fn enable(&mut self) {}
+        fn reset(&self) {}
+        fn set_device_address(&self, _addr: u8) {}
+        fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+            if ep_addr.index() == 0 {
+                self.sent.lock().unwrap().extend_from_slice(buf);
+                *self.in_pending.lock().unwrap() = true;
+            }
+            Ok(buf.len())
+        }
+        fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+            if ep_addr.index() != 0 {
+                return Err(UsbError::WouldBlock);
+            }
+            match self.setup.lock().unwrap().take() {
+                Some(setup) => {
+                    buf[..8].copy_from_slice(&setup);
+                    Ok(8)
+                }
+                None => Err(UsbError::WouldBlock),
+            }
+        }
+        fn set_ep0_out(&self) {}
+        fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) {}
+        fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+        fn suspend(&self) {}
+        fn resume(&self) {}
+        fn poll(&self) -> PollResult {
+            if self.setup.lock().unwrap().is_some() {
+                PollResult::Data { ep_out: 0, ep_in_complete: 0, ep_setup: 1 }
+            } else if core::mem::replace(&mut *self.in_pending.lock().unwrap(), false) {
+                PollResult::Data { ep_out: 0, ep_in_complete: 1, ep_setup: 0 }
+            } else {
+                PollResult::None
+            }
+        }
+    }
+
+    /// the configuration descriptor sent by a device with `layout`
+    fn configuration(layout: UsbLayout) -> Vec<u8> {
+        let clock = EmbeddedClock::new();
+        let bus = DescriptorBus::default();
+        // GET_DESCRIPTOR(CONFIGURATION), for up to 255 bytes
+        *bus.setup.lock().unwrap() = Some([0x80, 6, 0, 2, 0, 0, 0xFF, 0]);
+        let alloc = UsbBusAllocator::new(bus);
+        let mut usb = UsbFunctions::build(&alloc, layout, |alloc| {
+            UsbHidClassBuilder::new()
+                .add_interface(NKROBootKeyboardInterface::default_config(&clock))
+                .build(alloc)
+        });
+        let mut usb_dev = build_device(&alloc);
+        for _ in 0..64 {
+            usb_dev.poll(&mut usb.classes());
+        }
+        let config = usb_dev.bus().sent.lock().unwrap().clone();
+        config
+    }
+
+    #[test]
+    fn test_layout_interfaces() {
+        for &(class, interfaces) in [(DeviceClass::Hid, 1), (DeviceClass::Cdc, 2), (DeviceClass::Msc, 1)].iter() {
+            for &(dfu, fido) in [(false, false), (true, false), (false, true), (true, true)].iter() {
+                let layout = UsbLayout { class, dfu, fido };
+                let config = configuration(layout);
+                // in particular, the interfaces are numbered from 0, whatever's enabled
+                assert_eq!(validate_configuration(&config), vec![], "{:?}", layout);
+                assert_eq!(config[4], interfaces + dfu as u8 + fido as u8, "{:?} bNumInterfaces", layout);
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_class_select() {
+        assert_eq!(DeviceClass::parse("cdc"), Some(DeviceClass::Cdc));
+        assert_eq!(DeviceClass::parse("msc"), Some(DeviceClass::Msc));
+        assert_eq!(DeviceClass::parse("audio"), None);
+        for class in DeviceClass::ALL.iter() {
+            assert_eq!(DeviceClass::parse(class.name()), Some(*class));
+        }
+
+        let mut classes = ClassSelect::default();
+        assert_eq!(classes.active(), DeviceClass::Hid);
+        assert!(!classes.is_pending());
+        // selecting a class doesn't change what the host sees until it's applied
+        classes.select(DeviceClass::Msc);
+        assert!(classes.is_pending());
+        assert_eq!((classes.active(), classes.selected()), (DeviceClass::Hid, DeviceClass::Msc));
+        assert!(classes.apply());
+        assert_eq!(classes.active(), DeviceClass::Msc);
+        assert!(!classes.apply());
+        // going back to the active class leaves nothing pending
+        classes.select(DeviceClass::Cdc);
+        classes.select(DeviceClass::Msc);
+        assert!(!classes.is_pending());
+    }
+}
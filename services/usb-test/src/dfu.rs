@@ -0,0 +1,233 @@
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// interface class, subclass and run-time protocol of a DFU 1.1 run-time interface
+const DFU_CLASS: u8 = 0xFE;
+const DFU_SUBCLASS: u8 = 0x01;
+const DFU_PROTOCOL_RUNTIME: u8 = 0x01;
+/// descriptor type of the DFU functional descriptor
+const DESC_TYPE_DFU_FUNCTIONAL: u8 = 0x21;
+
+const DFU_DETACH: u8 = 0;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_GETSTATE: u8 = 5;
+
+/// bmAttributes: the device detaches and re-attaches by itself after a DFU_DETACH, instead of
+/// waiting for the host to reset the bus
+const ATTR_WILL_DETACH: u8 = 1 << 3;
+/// largest wDetachTimeOut the host may ask for
+pub(crate) const DFU_DETACH_TIMEOUT_MS: u16 = 1000;
+
+/// Run-time states, numbered as in the DFU 1.1 spec
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum DfuState {
+    AppIdle = 0,
+    /// a DFU_DETACH has been received, and the host is expected to reset the bus
+    AppDetach = 1,
+}
+impl Default for DfuState {
+    fn default() -> DfuState {
+        DfuState::AppIdle
+    }
+}
+
+/// Snapshot of the DFU run-time interface, reported in `UsbStatus`
+#[derive(Debug, Default, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct DfuStatus {
+    pub(crate) enabled: bool,
+    /// a `DfuState`, as the bState reported to the host
+    pub(crate) state: u8,
+    /// number of DFU_DETACH requests received
+    pub(crate) detaches: u32,
+    /// number of detaches that were followed by a bus reset within the timeout
+    pub(crate) resets: u32,
+}
+
+/// The DFU run-time state machine, kept apart from the class so that it can be driven by the
+/// main loop's ticks.
+#[derive(Debug, Default)]
+pub(crate) struct DfuRuntimeState {
+    state: DfuState,
+    status: DfuStatus,
+    /// ticktimer time of the last DFU_DETACH, and the timeout the host gave with it
+    detach: Option<(u64, u64)>,
+    /// re-enumeration has been asked for since the last DFU_DETACH
+    detach_requested: bool,
+    /// the device should drop off the bus and re-enumerate
+    reenumerate: bool,
+}
+impl DfuRuntimeState {
+    pub(crate) fn state(&self) -> DfuState {
+        self.state
+    }
+    pub(crate) fn status(&self) -> DfuStatus {
+        DfuStatus { state: self.state as u8, ..self.status }
+    }
+    /// handles a DFU_DETACH received at `now`, with the host's `timeout_ms`
+    pub(crate) fn detach(&mut self, now: u64, timeout_ms: u16) {
+        let timeout_ms = timeout_ms.min(DFU_DETACH_TIMEOUT_MS);
+        log::info!("dfu: DFU_DETACH, timeout {}ms", timeout_ms);
+        self.state = DfuState::AppDetach;
+        self.status.detaches += 1;
+        self.detach = Some((now, timeout_ms as u64));
+        self.detach_requested = false;
+    }
+    /// Handles a bus reset. A reset in appDETACH is where a DFU device would switch to its DFU
+    /// mode interface; flashing isn't implemented, so this goes back to appIDLE.
+    pub(crate) fn bus_reset(&mut self) {
+        if self.state == DfuState::AppDetach {
+            log::info!("dfu: bus reset after detach; DFU mode is not implemented, staying in run-time mode");
+            self.status.resets += 1;
+        }
+        self.state = DfuState::AppIdle;
+        self.detach = None;
+    }
+    /// Called periodically. If `will_detach`, a pending detach asks once for re-enumeration.
+    /// A detach that isn't followed by a bus reset within the timeout is abandoned.
+    pub(crate) fn tick(&mut self, now: u64, will_detach: bool) {
+        if let Some((at, timeout)) = self.detach {
+            if will_detach && !self.detach_requested {
+                self.reenumerate = true;
+                self.detach_requested = true;
+                // the timeout runs from the re-enumeration
+                self.detach = Some((now, timeout));
+            } else if now.saturating_sub(at) > timeout {
+                log::info!("dfu: no bus reset within {}ms of detach, back to appIDLE", timeout);
+                self.state = DfuState::AppIdle;
+                self.detach = None;
+            }
+        }
+    }
+    /// returns `true` once for each time the device should re-enumerate
+    pub(crate) fn take_reenumerate(&mut self) -> bool {
+        core::mem::replace(&mut self.reenumerate, false)
+    }
+}
+
+/// A DFU 1.1 run-time interface. It lets host tools such as `dfu-util -l` find the device, and
+/// answers DFU_DETACH, DFU_GETSTATUS and DFU_GETSTATE. There is no DFU mode to detach into.
+pub(crate) struct DfuRuntime {
+    iface: InterfaceNumber,
+    /// re-enumerate after a detach, rather than waiting for the host to reset the bus
+    will_detach: bool,
+    /// set by the main loop before each poll, for timestamping requests
+    now: u64,
+    pub(crate) state: DfuRuntimeState,
+}
+impl DfuRuntime {
+    pub(crate) fn new<B: UsbBus>(alloc: &UsbBusAllocator<B>, will_detach: bool) -> DfuRuntime {
+        DfuRuntime {
+            iface: alloc.interface(),
+            will_detach,
+            now: 0,
+            // it's only built once enabled
+            state: DfuRuntimeState {
+                status: DfuStatus { enabled: true, ..DfuStatus::default() },
+                ..DfuRuntimeState::default()
+            },
+        }
+    }
+    pub(crate) fn set_now(&mut self, now: u64) {
+        self.now = now;
+    }
+    pub(crate) fn tick(&mut self, now: u64) {
+        self.state.tick(now, self.will_detach);
+    }
+    fn is_ours(&self, req: &usb_device::control::Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.iface) as u16
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for DfuRuntime {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.iface, DFU_CLASS, DFU_SUBCLASS, DFU_PROTOCOL_RUNTIME)?;
+        let timeout = DFU_DETACH_TIMEOUT_MS.to_le_bytes();
+        writer.write(DESC_TYPE_DFU_FUNCTIONAL, &[
+            if self.will_detach { ATTR_WILL_DETACH } else { 0 },
+            timeout[0], timeout[1],
+            0, 0, // wTransferSize: no download or upload
+            0x10, 0x01, // bcdDFUVersion 1.1
+        ])
+    }
+    fn reset(&mut self) {
+        self.state.bus_reset();
+    }
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) {
+            return;
+        }
+        match req.request {
+            DFU_GETSTATUS => {
+                // bStatus OK, bwPollTimeout 0, bState, iString none
+                xfer.accept_with(&[0, 0, 0, 0, self.state.state() as u8, 0]).ok();
+            }
+            DFU_GETSTATE => {
+                xfer.accept_with(&[self.state.state() as u8]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) {
+            return;
+        }
+        match req.request {
+            DFU_DETACH => {
+                self.state.detach(self.now, req.value);
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dfu_detach() {
+        // host resets the bus in time
+        let mut dfu = DfuRuntimeState::default();
+        assert_eq!(dfu.state(), DfuState::AppIdle);
+        dfu.detach(100, 5000);
+        assert_eq!(dfu.state(), DfuState::AppDetach);
+        dfu.tick(100 + DFU_DETACH_TIMEOUT_MS as u64, false);
+        assert_eq!(dfu.state(), DfuState::AppDetach);
+        dfu.bus_reset();
+        assert_eq!(dfu.state(), DfuState::AppIdle);
+        assert_eq!((dfu.status().detaches, dfu.status().resets), (1, 1));
+        assert!(!dfu.take_reenumerate());
+
+        // host never resets the bus
+        dfu.detach(10_000, 200);
+        dfu.tick(10_200, false);
+        assert_eq!(dfu.state(), DfuState::AppDetach);
+        dfu.tick(10_201, false);
+        assert_eq!(dfu.state(), DfuState::AppIdle);
+        dfu.bus_reset();
+        assert_eq!((dfu.status().detaches, dfu.status().resets), (2, 1));
+
+        // device detaches itself: it asks to re-enumerate once, and the re-enumeration's reset
+        // completes the detach
+        dfu.detach(20_000, 200);
+        dfu.tick(20_001, true);
+        assert!(dfu.take_reenumerate());
+        dfu.tick(20_100, true);
+        assert!(!dfu.take_reenumerate());
+        assert_eq!(dfu.status().state, DfuState::AppDetach as u8);
+        dfu.bus_reset();
+        assert_eq!(dfu.state(), DfuState::AppIdle);
+        dfu.tick(30_000, true);
+        assert!(!dfu.take_reenumerate());
+        assert_eq!((dfu.status().detaches, dfu.status().resets), (3, 2));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::api::Opcode;
+use xous::Message;
+
+/// undecodable opcodes within `BAD_OPCODE_WINDOW_MS` that count as climbing fast
+const BAD_OPCODE_BURST: u32 = 16;
+const BAD_OPCODE_WINDOW_MS: u64 = 1000;
+/// minimum time between warnings about bad opcodes
+const BAD_OPCODE_WARN_INTERVAL_MS: u64 = 10_000;
+
+/// Counts messages the main loop couldn't act on, so a misbehaving client shows up without
+/// anyone watching the log. Reported by `Opcode::GetDiagCounters` and the `diag` command.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct DiagCounters {
+    /// messages whose opcode isn't an `Opcode`
+    pub(crate) bad_opcodes: u32,
+    /// scalar opcodes that arrived as some other kind of message, so couldn't be unpacked
+    pub(crate) bad_scalars: u32,
+    /// messages the main loop failed to send to itself
+    pub(crate) send_failures: u32,
+    /// start of the current burst window, and the bad opcodes seen in it
+    window_start: u64,
+    window_count: u32,
+    last_warning: Option<u64>,
+}
+impl DiagCounters {
+    pub(crate) fn new() -> DiagCounters {
+        DiagCounters::default()
+    }
+    /// Records an undecodable opcode at `now`. Returns `true` if a warning is due: the count
+    /// is climbing fast, and there hasn't been a warning in the last while.
+    pub(crate) fn bad_opcode(&mut self, now: u64) -> bool {
+        self.bad_opcodes = self.bad_opcodes.saturating_add(1);
+        if now.saturating_sub(self.window_start) >= BAD_OPCODE_WINDOW_MS {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+        self.window_count += 1;
+        if self.window_count >= BAD_OPCODE_BURST
+            && self.last_warning.map_or(true, |t| now.saturating_sub(t) >= BAD_OPCODE_WARN_INTERVAL_MS)
+        {
+            self.last_warning = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+    /// Records `body` against `op`, counting it if it's not the kind of message `op` is
+    /// unpacked from.
+    pub(crate) fn check_scalar(&mut self, op: &Opcode, body: &Message) {
+        let well_formed = match op {
+            Opcode::SuspendResume | Opcode::KeyboardChar => matches!(body, Message::Scalar(_)),
+            #[cfg(any(test, feature = "hosted", feature = "inject-key"))]
+            Opcode::InjectKey => matches!(body, Message::Scalar(_)),
+            Opcode::KbdTestResult | Opcode::GetDiagCounters => matches!(body, Message::BlockingScalar(_)),
+            _ => true,
+        };
+        if !well_formed {
+            self.bad_scalars = self.bad_scalars.saturating_add(1);
+        }
+    }
+    pub(crate) fn send_failed(&mut self) {
+        self.send_failures = self.send_failures.saturating_add(1);
+    }
+    /// Packs the counters into the two scalar results of `GetDiagCounters`: the bad opcode
+    /// count, then the bad scalar count in the low 16 bits and the send failures in the high
+    /// 16 bits, each saturating.
+    pub(crate) fn pack(&self) -> (usize, usize) {
+        let sat16 = |n: u32| n.min(0xFFFF) as usize;
+        (self.bad_opcodes as usize, sat16(self.bad_scalars) | sat16(self.send_failures) << 16)
+    }
+    /// Unpacks the results of `GetDiagCounters` into `(bad_opcodes, bad_scalars, send_failures)`
+    pub(crate) fn unpack(a: usize, b: usize) -> (u32, u32, u32) {
+        (a as u32, (b & 0xFFFF) as u32, ((b >> 16) & 0xFFFF) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diag_counters() {
+        let mut diag = DiagCounters::new();
+        // a trickle of bad opcodes is counted but doesn't warn
+        for t in 0..BAD_OPCODE_BURST as u64 - 1 {
+            assert!(!diag.bad_opcode(t * 100));
+        }
+        assert!(!diag.bad_opcode(2000));
+        assert_eq!(diag.bad_opcodes, BAD_OPCODE_BURST);
+
+        // a burst warns once, then not again until the warning interval has passed
+        let warnings = (0..100).filter(|&i| diag.bad_opcode(3000 + i)).count();
+        assert_eq!(warnings, 1);
+        let warnings = (0..100).filter(|&i| diag.bad_opcode(3000 + BAD_OPCODE_WARN_INTERVAL_MS + i)).count();
+        assert_eq!(warnings, 1);
+        assert_eq!(diag.bad_opcodes, BAD_OPCODE_BURST + 200);
+
+        // scalar opcodes must arrive as the scalar kind they're unpacked from
+        let scalar = Message::new_scalar(0, 1, 2, 3, 4);
+        let blocking = Message::new_blocking_scalar(0, 1, 2, 3, 4);
+        diag.check_scalar(&Opcode::KeyboardChar, &scalar);
+        diag.check_scalar(&Opcode::KbdTestResult, &blocking);
+        diag.check_scalar(&Opcode::DoCmd, &blocking);
+        assert_eq!(diag.bad_scalars, 0);
+        diag.check_scalar(&Opcode::KeyboardChar, &blocking);
+        diag.check_scalar(&Opcode::GetDiagCounters, &scalar);
+        assert_eq!(diag.bad_scalars, 2);
+
+        diag.send_failed();
+        let (a, b) = diag.pack();
+        assert_eq!(DiagCounters::unpack(a, b), (BAD_OPCODE_BURST + 200, 2, 1));
+        // the two packed counters saturate rather than spill into each other
+        diag.bad_scalars = 0x1_0000;
+        let (a, b) = diag.pack();
+        assert_eq!(DiagCounters::unpack(a, b), (BAD_OPCODE_BURST + 200, 0xFFFF, 1));
+    }
+}
@@ -0,0 +1,68 @@
+/// Transfer counters for a single endpoint, accumulated from descriptor completions.
+///
+/// The SpinalHDL core does not count NAKs, so there is no NAK counter. Errors are completions
+/// that ended with a non-zero descriptor code, which covers CRC and bit-stuffing failures.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct EpStats {
+    pub(crate) packets_in: u32,
+    pub(crate) bytes_in: u32,
+    pub(crate) packets_out: u32,
+    pub(crate) bytes_out: u32,
+    pub(crate) errors: u32,
+    /// largest packet seen in either direction
+    pub(crate) max_packet: u32,
+    /// ticktimer time of the latest completion, successful or not
+    pub(crate) last_ms: u64,
+}
+
+impl EpStats {
+    /// records a descriptor completed at ticktimer time `now`. `code` is the descriptor's
+    /// completion code, where 0 is success.
+    pub(crate) fn record(&mut self, is_in: bool, len: usize, code: u32, now: u64) {
+        self.last_ms = now;
+        if code != 0 {
+            self.errors = self.errors.saturating_add(1);
+            return;
+        }
+        let len = len as u32;
+        if is_in {
+            self.packets_in = self.packets_in.saturating_add(1);
+            self.bytes_in = self.bytes_in.saturating_add(len);
+        } else {
+            self.packets_out = self.packets_out.saturating_add(1);
+            self.bytes_out = self.bytes_out.saturating_add(len);
+        }
+        self.max_packet = self.max_packet.max(len);
+    }
+    pub(crate) fn is_idle(&self) -> bool {
+        *self == EpStats::default()
+    }
+    pub(crate) fn log(&self, index: usize) {
+        log::info!("ep{}: in {} pkts/{} bytes, out {} pkts/{} bytes, {} errors, max packet {}",
+            index, self.packets_in, self.bytes_in, self.packets_out, self.bytes_out, self.errors, self.max_packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epstats_record() {
+        let mut stats = EpStats::default();
+        assert!(stats.is_idle());
+        stats.record(true, 8, 0, 10);
+        stats.record(true, 64, 0, 11);
+        stats.record(false, 1, 0, 12);
+        stats.record(false, 32, 3, 13);
+        assert_eq!(stats, EpStats {
+            packets_in: 2,
+            bytes_in: 72,
+            packets_out: 1,
+            bytes_out: 1,
+            errors: 1,
+            max_packet: 64,
+            last_ms: 13,
+        });
+    }
+}
@@ -0,0 +1,34 @@
+/// Failures of usb-test's own device management: the endpoint allocator, raw access to the
+/// core's memory, and switching cores. Distinct from `usb_device::UsbError`, which is what the
+/// USB stack sees; see the `From` impl for how these are reported to the stack.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum UsbError {
+    /// no free region in the descriptor memory is large enough
+    OutOfMemory,
+    /// the request was zero-length, or larger than the descriptor memory
+    InvalidLength,
+    /// the offset is not on a 16-byte boundary
+    Misaligned,
+    /// the device core is not connected, or did not connect when asked
+    NotConnected,
+    /// the requested endpoint is already allocated
+    EndpointBusy,
+    /// the region lies outside of the descriptor memory
+    OutOfRange,
+    /// the offset does not start an allocated region
+    NotAllocated,
+}
+
+impl From<UsbError> for usb_device::UsbError {
+    fn from(e: UsbError) -> usb_device::UsbError {
+        match e {
+            UsbError::OutOfMemory => usb_device::UsbError::EndpointMemoryOverflow,
+            UsbError::EndpointBusy => usb_device::UsbError::InvalidEndpoint,
+            UsbError::NotConnected => usb_device::UsbError::InvalidState,
+            UsbError::InvalidLength
+            | UsbError::Misaligned
+            | UsbError::OutOfRange
+            | UsbError::NotAllocated => usb_device::UsbError::Unsupported,
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::VecDeque;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestType};
+
+/// size of every HID report on the FIDO interface
+pub(crate) const HID_REPORT_LEN: usize = 64;
+/// payload carried by an initialization packet: the report, less CID, CMD and BCNT
+const INIT_DATA_LEN: usize = HID_REPORT_LEN - 7;
+/// payload carried by a continuation packet: the report, less CID and SEQ
+const CONT_DATA_LEN: usize = HID_REPORT_LEN - 5;
+/// largest message that fits in an init packet and the 128 continuation packets allowed by the
+/// 7-bit sequence number
+pub(crate) const MAX_MESSAGE_LEN: usize = INIT_DATA_LEN + 128 * CONT_DATA_LEN;
+
+/// channel on which a client asks for a channel of its own
+pub(crate) const CID_BROADCAST: u32 = 0xFFFF_FFFF;
+/// bit set in the command byte of initialization packets
+const TYPE_INIT: u8 = 0x80;
+
+pub(crate) const U2FHID_PING: u8 = TYPE_INIT | 0x01;
+pub(crate) const U2FHID_INIT: u8 = TYPE_INIT | 0x06;
+pub(crate) const U2FHID_ERROR: u8 = TYPE_INIT | 0x3F;
+
+pub(crate) const ERR_INVALID_CMD: u8 = 0x01;
+pub(crate) const ERR_INVALID_LEN: u8 = 0x03;
+pub(crate) const ERR_INVALID_SEQ: u8 = 0x04;
+pub(crate) const ERR_CHANNEL_BUSY: u8 = 0x06;
+/// the command needs an allocated channel; called ERR_SYNC_REQUIRED in U2FHID 1.0
+pub(crate) const ERR_INVALID_CID: u8 = 0x0B;
+
+/// length of the nonce sent with U2FHID_INIT
+const INIT_NONCE_LEN: usize = 8;
+const U2FHID_IF_VERSION: u8 = 2;
+
+/// HID report descriptor: a FIDO Alliance usage page (0xF1D0) U2F authenticator with one
+/// 64-byte input report and one 64-byte output report
+const REPORT_DESCRIPTOR: [u8; 34] = [
+    0x06, 0xD0, 0xF1, // Usage Page (FIDO Alliance)
+    0x09, 0x01, // Usage (U2F Authenticator Device)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x20, //   Usage (Input Report Data)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x81, 0x02, //   Input (Data, Var, Abs)
+    0x09, 0x21, //   Usage (Output Report Data)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x91, 0x02, //   Output (Data, Var, Abs)
+    0xC0, // End Collection
+];
+const HID_CLASS: u8 = 0x03;
+const DESC_TYPE_HID: u8 = 0x21;
+const DESC_TYPE_REPORT: u8 = 0x22;
+const HID_SET_IDLE: u8 = 0x0A;
+/// default bInterval of the interrupt endpoints, in frames
+pub(crate) const FIDO_POLL_INTERVAL_MS: u8 = 5;
+
+/// A complete U2FHID message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct U2fMessage {
+    pub(crate) cid: u32,
+    pub(crate) cmd: u8,
+    pub(crate) payload: Vec<u8>,
+}
+impl U2fMessage {
+    pub(crate) fn error(cid: u32, code: u8) -> U2fMessage {
+        U2fMessage { cid, cmd: U2FHID_ERROR, payload: vec![code] }
+    }
+    /// Splits the message into an initialization packet and as many continuation packets as
+    /// its payload needs. Unused bytes in the last report are zero.
+    pub(crate) fn fragment(&self) -> Vec<[u8; HID_REPORT_LEN]> {
+        assert!(self.payload.len() <= MAX_MESSAGE_LEN, "U2FHID message too long to fragment");
+        let mut reports = Vec::new();
+        let mut report = [0u8; HID_REPORT_LEN];
+        report[..4].copy_from_slice(&self.cid.to_be_bytes());
+        report[4] = self.cmd;
+        report[5..7].copy_from_slice(&(self.payload.len() as u16).to_be_bytes());
+        let first = self.payload.len().min(INIT_DATA_LEN);
+        report[7..7 + first].copy_from_slice(&self.payload[..first]);
+        reports.push(report);
+        for (seq, chunk) in self.payload[first..].chunks(CONT_DATA_LEN).enumerate() {
+            let mut report = [0u8; HID_REPORT_LEN];
+            report[..4].copy_from_slice(&self.cid.to_be_bytes());
+            report[4] = seq as u8;
+            report[5..5 + chunk.len()].copy_from_slice(chunk);
+            reports.push(report);
+        }
+        reports
+    }
+}
+
+/// a message whose continuation packets are still arriving
+struct Partial {
+    cid: u32,
+    cmd: u8,
+    len: usize,
+    next_seq: u8,
+    payload: Vec<u8>,
+}
+
+/// Reassembles U2FHID messages from the reports received on the OUT endpoint. Only one
+/// message can be in flight at a time, as in the U2FHID spec.
+pub(crate) struct Reassembler {
+    pending: Option<Partial>,
+}
+impl Reassembler {
+    pub(crate) fn new() -> Reassembler {
+        Reassembler { pending: None }
+    }
+    pub(crate) fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+    pub(crate) fn abort(&mut self) {
+        self.pending = None;
+    }
+    /// Takes in one report. Returns the message it completes, if any, or the error to send
+    /// back to the channel that sent it.
+    pub(crate) fn push(&mut self, report: &[u8; HID_REPORT_LEN]) -> Result<Option<U2fMessage>, U2fMessage> {
+        let cid = u32::from_be_bytes([report[0], report[1], report[2], report[3]]);
+        if report[4] & TYPE_INIT != 0 {
+            let cmd = report[4];
+            if let Some(p) = &self.pending {
+                if p.cid != cid {
+                    return Err(U2fMessage::error(cid, ERR_CHANNEL_BUSY));
+                }
+                // an INIT on the busy channel resynchronizes it; anything else is out of order
+                self.pending = None;
+                if cmd != U2FHID_INIT {
+                    return Err(U2fMessage::error(cid, ERR_INVALID_SEQ));
+                }
+            }
+            let len = u16::from_be_bytes([report[5], report[6]]) as usize;
+            if len > MAX_MESSAGE_LEN {
+                return Err(U2fMessage::error(cid, ERR_INVALID_LEN));
+            }
+            let first = len.min(INIT_DATA_LEN);
+            let mut payload = Vec::with_capacity(len);
+            payload.extend_from_slice(&report[7..7 + first]);
+            if payload.len() == len {
+                return Ok(Some(U2fMessage { cid, cmd, payload }));
+            }
+            self.pending = Some(Partial { cid, cmd, len, next_seq: 0, payload });
+            Ok(None)
+        } else {
+            let seq = report[4];
+            let p = match &mut self.pending {
+                Some(p) if p.cid == cid => p,
+                // continuation packets that don't belong to the message in flight are ignored
+                _ => return Ok(None),
+            };
+            if seq != p.next_seq {
+                self.pending = None;
+                return Err(U2fMessage::error(cid, ERR_INVALID_SEQ));
+            }
+            p.next_seq += 1;
+            let take = (p.len - p.payload.len()).min(CONT_DATA_LEN);
+            p.payload.extend_from_slice(&report[5..5 + take]);
+            if p.payload.len() == p.len {
+                let p = self.pending.take().unwrap();
+                Ok(Some(U2fMessage { cid: p.cid, cmd: p.cmd, payload: p.payload }))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The U2FHID transport: reassembles requests, allocates channels, and answers INIT and PING.
+/// The U2F authenticator commands themselves are not implemented.
+pub(crate) struct U2fHid {
+    reassembler: Reassembler,
+    /// channels `1..next_cid` have been handed out
+    next_cid: u32,
+}
+impl U2fHid {
+    pub(crate) fn new() -> U2fHid {
+        U2fHid { reassembler: Reassembler::new(), next_cid: 1 }
+    }
+    pub(crate) fn reset(&mut self) {
+        self.reassembler.abort();
+        self.next_cid = 1;
+    }
+    fn is_allocated(&self, cid: u32) -> bool {
+        cid != 0 && cid < self.next_cid
+    }
+    /// Handles one report from the host, returning the reports to send back
+    pub(crate) fn handle_report(&mut self, report: &[u8; HID_REPORT_LEN]) -> Vec<[u8; HID_REPORT_LEN]> {
+        let response = match self.reassembler.push(report) {
+            Ok(Some(msg)) => self.handle_message(msg),
+            Ok(None) => return Vec::new(),
+            Err(e) => e,
+        };
+        response.fragment()
+    }
+    fn handle_message(&mut self, msg: U2fMessage) -> U2fMessage {
+        match msg.cmd {
+            U2FHID_INIT => {
+                if msg.payload.len() != INIT_NONCE_LEN {
+                    return U2fMessage::error(msg.cid, ERR_INVALID_LEN);
+                }
+                let cid = if msg.cid == CID_BROADCAST {
+                    let cid = self.next_cid;
+                    self.next_cid += 1;
+                    log::info!("fido: allocated channel 0x{:08x}", cid);
+                    cid
+                } else if self.is_allocated(msg.cid) {
+                    // resync of an existing channel
+                    msg.cid
+                } else {
+                    return U2fMessage::error(msg.cid, ERR_INVALID_CID);
+                };
+                let mut payload = msg.payload;
+                payload.extend_from_slice(&cid.to_be_bytes());
+                // interface version, device version 0.1.0, no capabilities
+                payload.extend_from_slice(&[U2FHID_IF_VERSION, 0, 1, 0, 0]);
+                U2fMessage { cid: msg.cid, cmd: U2FHID_INIT, payload }
+            }
+            _ if !self.is_allocated(msg.cid) => U2fMessage::error(msg.cid, ERR_INVALID_CID),
+            U2FHID_PING => msg,
+            cmd => {
+                log::info!("fido: unsupported command 0x{:02x} on channel 0x{:08x}", cmd, msg.cid);
+                U2fMessage::error(msg.cid, ERR_INVALID_CMD)
+            }
+        }
+    }
+}
+
+/// Snapshot of the FIDO interface, reported in `UsbStatus`
+#[derive(Debug, Default, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct FidoStatus {
+    pub(crate) enabled: bool,
+    /// HID reports received on the OUT endpoint
+    pub(crate) rx_frames: u32,
+    /// HID reports sent on the IN endpoint
+    pub(crate) tx_frames: u32,
+}
+
+/// A HID interface on the FIDO usage page, with a pair of 64-byte interrupt endpoints that
+/// speak U2FHID; see `U2fHid` for what it answers.
+pub(crate) struct FidoHid<'a, B: UsbBus> {
+    iface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    u2f: U2fHid,
+    /// reports waiting for the IN endpoint
+    tx_queue: VecDeque<[u8; HID_REPORT_LEN]>,
+    status: FidoStatus,
+}
+impl<'a, B: UsbBus> FidoHid<'a, B> {
+    /// `interval` is the bInterval of both endpoints
+    pub(crate) fn new(alloc: &'a UsbBusAllocator<B>, interval: u8) -> FidoHid<'a, B> {
+        FidoHid {
+            iface: alloc.interface(),
+            ep_in: alloc.interrupt(HID_REPORT_LEN as u16, interval),
+            ep_out: alloc.interrupt(HID_REPORT_LEN as u16, interval),
+            u2f: U2fHid::new(),
+            tx_queue: VecDeque::new(),
+            // it's only built once enabled
+            status: FidoStatus { enabled: true, ..FidoStatus::default() },
+        }
+    }
+    pub(crate) fn status(&self) -> FidoStatus {
+        self.status
+    }
+    /// takes over the status of the interface this one replaces, on a new allocator
+    pub(crate) fn restore(&mut self, status: FidoStatus) {
+        self.status = status;
+    }
+    /// sends the next queued report, if the IN endpoint is free
+    fn send_next(&mut self) {
+        if let Some(report) = self.tx_queue.front() {
+            match self.ep_in.write(report) {
+                Ok(_) => {
+                    self.tx_queue.pop_front();
+                    self.status.tx_frames += 1;
+                }
+                Err(UsbError::WouldBlock) => {}
+                Err(e) => log::warn!("fido: couldn't send report: {:?}", e),
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for FidoHid<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.iface, HID_CLASS, 0, 0)?;
+        let report_len = (REPORT_DESCRIPTOR.len() as u16).to_le_bytes();
+        writer.write(DESC_TYPE_HID, &[
+            0x11, 0x01, // bcdHID 1.11
+            0, // bCountryCode: not localized
+            1, // bNumDescriptors
+            DESC_TYPE_REPORT, report_len[0], report_len[1],
+        ])?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)
+    }
+    fn reset(&mut self) {
+        self.u2f.reset();
+        self.tx_queue.clear();
+    }
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if req.recipient != Recipient::Interface || req.index != u8::from(self.iface) as u16 {
+            return;
+        }
+        if req.request_type == RequestType::Standard
+            && req.request == Request::GET_DESCRIPTOR
+            && (req.value >> 8) as u8 == DESC_TYPE_REPORT
+        {
+            xfer.accept_with_static(&REPORT_DESCRIPTOR).ok();
+        } else if req.request_type == RequestType::Class {
+            xfer.reject().ok();
+        }
+    }
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return;
+        }
+        match req.request {
+            HID_SET_IDLE => {
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut report = [0u8; HID_REPORT_LEN];
+        match self.ep_out.read(&mut report) {
+            Ok(HID_REPORT_LEN) => {
+                self.status.rx_frames += 1;
+                let responses = self.u2f.handle_report(&report);
+                self.tx_queue.extend(responses);
+                self.send_next();
+            }
+            Ok(len) => log::warn!("fido: dropping short report of {} bytes", len),
+            Err(e) => log::warn!("fido: couldn't read report: {:?}", e),
+        }
+    }
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_in.address() {
+            self.send_next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_packet(cid: u32, cmd: u8, len: u16, data: &[u8]) -> [u8; HID_REPORT_LEN] {
+        let mut r = [0u8; HID_REPORT_LEN];
+        r[..4].copy_from_slice(&cid.to_be_bytes());
+        r[4] = cmd;
+        r[5..7].copy_from_slice(&len.to_be_bytes());
+        r[7..7 + data.len()].copy_from_slice(data);
+        r
+    }
+
+    #[test]
+    fn test_u2fhid_framing() {
+        // a message that needs two continuation packets, the last one partly filled
+        let payload: Vec<u8> = (0..INIT_DATA_LEN + CONT_DATA_LEN + 10).map(|i| i as u8).collect();
+        let msg = U2fMessage { cid: 0x1234_5678, cmd: U2FHID_PING, payload: payload.clone() };
+        let reports = msg.fragment();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(&reports[0][..7], &[0x12, 0x34, 0x56, 0x78, U2FHID_PING, 0, 126]);
+        assert_eq!(&reports[1][..5], &[0x12, 0x34, 0x56, 0x78, 0]);
+        assert_eq!(reports[2][4], 1);
+        assert!(reports[2][15..].iter().all(|&b| b == 0));
+
+        let mut r = Reassembler::new();
+        assert_eq!(r.push(&reports[0]), Ok(None));
+        assert!(r.is_busy());
+        assert_eq!(r.push(&reports[1]), Ok(None));
+        assert_eq!(r.push(&reports[2]), Ok(Some(msg.clone())));
+        assert!(!r.is_busy());
+
+        // a message that fits in the init packet, and an empty one
+        let short = U2fMessage { cid: 7, cmd: U2FHID_PING, payload: vec![1, 2, 3] };
+        assert_eq!(r.push(&short.fragment()[0]), Ok(Some(short)));
+        let empty = U2fMessage { cid: 7, cmd: U2FHID_PING, payload: vec![] };
+        assert_eq!(empty.fragment().len(), 1);
+        assert_eq!(r.push(&empty.fragment()[0]), Ok(Some(empty)));
+
+        // the largest message uses every sequence number
+        let max = U2fMessage { cid: 7, cmd: U2FHID_PING, payload: vec![0xA5; MAX_MESSAGE_LEN] };
+        let reports = max.fragment();
+        assert_eq!(reports.len(), 129);
+        assert_eq!(reports[128][4], 127);
+        for report in &reports[..128] {
+            assert_eq!(r.push(report), Ok(None));
+        }
+        assert_eq!(r.push(&reports[128]), Ok(Some(max)));
+        // and anything longer is refused
+        let too_long = init_packet(7, U2FHID_PING, MAX_MESSAGE_LEN as u16 + 1, &[]);
+        assert_eq!(r.push(&too_long), Err(U2fMessage::error(7, ERR_INVALID_LEN)));
+        assert!(!r.is_busy());
+
+        // out-of-order continuation aborts the message
+        let reports = msg.fragment();
+        r.push(&reports[0]).unwrap();
+        assert_eq!(r.push(&reports[2]), Err(U2fMessage::error(msg.cid, ERR_INVALID_SEQ)));
+        assert!(!r.is_busy());
+        // stray continuation packets are ignored
+        assert_eq!(r.push(&reports[1]), Ok(None));
+
+        // another channel can't start a message while one is in flight
+        r.push(&reports[0]).unwrap();
+        let other = init_packet(9, U2FHID_PING, 1, &[0]);
+        assert_eq!(r.push(&other), Err(U2fMessage::error(9, ERR_CHANNEL_BUSY)));
+        assert_eq!(r.push(&reports[1]), Ok(None));
+        assert_eq!(r.push(&reports[2]), Ok(Some(msg)));
+    }
+
+    #[test]
+    fn test_u2fhid_channels() {
+        let mut u2f = U2fHid::new();
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        // PING before a channel is allocated
+        let resp = u2f.handle_report(&init_packet(CID_BROADCAST, U2FHID_PING, 1, &[0]));
+        assert_eq!(&resp[0][4..8], &[U2FHID_ERROR, 0, 1, ERR_INVALID_CID]);
+
+        // INIT on the broadcast channel hands out a new channel each time
+        let resp = u2f.handle_report(&init_packet(CID_BROADCAST, U2FHID_INIT, 8, &nonce));
+        assert_eq!(resp.len(), 1);
+        assert_eq!(&resp[0][..7], &[0xFF, 0xFF, 0xFF, 0xFF, U2FHID_INIT, 0, 17]);
+        assert_eq!(&resp[0][7..15], &nonce);
+        assert_eq!(&resp[0][15..19], &1u32.to_be_bytes());
+        assert_eq!(resp[0][19], U2FHID_IF_VERSION);
+        let resp = u2f.handle_report(&init_packet(CID_BROADCAST, U2FHID_INIT, 8, &nonce));
+        assert_eq!(&resp[0][15..19], &2u32.to_be_bytes());
+        // INIT on an allocated channel keeps it
+        let resp = u2f.handle_report(&init_packet(2, U2FHID_INIT, 8, &nonce));
+        assert_eq!(&resp[0][..4], &2u32.to_be_bytes());
+        assert_eq!(&resp[0][15..19], &2u32.to_be_bytes());
+        // but not on one that was never handed out
+        let resp = u2f.handle_report(&init_packet(5, U2FHID_INIT, 8, &nonce));
+        assert_eq!(&resp[0][4..8], &[U2FHID_ERROR, 0, 1, ERR_INVALID_CID]);
+        let resp = u2f.handle_report(&init_packet(CID_BROADCAST, U2FHID_INIT, 4, &nonce[..4]));
+        assert_eq!(&resp[0][4..8], &[U2FHID_ERROR, 0, 1, ERR_INVALID_LEN]);
+
+        // a multi-packet PING is echoed back, fragmented the same way
+        let ping = U2fMessage { cid: 1, cmd: U2FHID_PING, payload: (0..200).map(|i| i as u8).collect() };
+        let mut resp = Vec::new();
+        for report in ping.fragment().iter() {
+            resp.extend(u2f.handle_report(report));
+        }
+        assert_eq!(resp, ping.fragment());
+
+        // unsupported commands are refused
+        let resp = u2f.handle_report(&init_packet(1, TYPE_INIT | 0x03, 0, &[]));
+        assert_eq!(&resp[0][4..8], &[U2FHID_ERROR, 0, 1, ERR_INVALID_CMD]);
+
+        // a reset forgets the channels
+        u2f.reset();
+        let resp = u2f.handle_report(&init_packet(1, U2FHID_PING, 0, &[]));
+        assert_eq!(&resp[0][4..8], &[U2FHID_ERROR, 0, 1, ERR_INVALID_CID]);
+    }
+}
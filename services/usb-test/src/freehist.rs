@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// number of recent frees remembered for diagnosing a bad free
+pub(crate) const FREE_HISTORY_LEN: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct FreeRecord {
+    pub(crate) offset: u32,
+    pub(crate) len: u32,
+    /// ticktimer time of the free
+    pub(crate) timestamp: u64,
+    /// names the code that did the free
+    pub(crate) tag: &'static str,
+}
+
+/// Why a free of an offset that isn't allocated failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum BadFree {
+    /// the offset was freed recently, and not re-allocated since
+    DoubleFree(FreeRecord),
+    /// the offset isn't in the free history either
+    WildFree,
+}
+
+/// Debug aid for the descriptor memory allocator: remembers the most recent successful frees,
+/// so that a free that misses can be told apart as a double free or a wild free.
+pub(crate) struct FreeHistory {
+    enabled: bool,
+    records: VecDeque<FreeRecord>,
+}
+
+impl FreeHistory {
+    pub(crate) fn new() -> FreeHistory {
+        FreeHistory {
+            enabled: false,
+            records: VecDeque::with_capacity(FREE_HISTORY_LEN),
+        }
+    }
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.records.clear();
+        }
+    }
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// records a successful free. Does nothing unless enabled.
+    pub(crate) fn record(&mut self, offset: u32, len: u32, timestamp: u64, tag: &'static str) {
+        if !self.enabled {
+            return;
+        }
+        if self.records.len() == FREE_HISTORY_LEN {
+            self.records.pop_front();
+        }
+        self.records.push_back(FreeRecord { offset, len, timestamp, tag });
+    }
+    /// Forgets earlier frees of `offset`, because it has been handed out again. Freeing it
+    /// twice after this is a double free of the new allocation, not of the old one.
+    pub(crate) fn reallocated(&mut self, offset: u32) {
+        self.records.retain(|r| r.offset != offset);
+    }
+    /// the recorded frees, oldest first
+    pub(crate) fn records(&self) -> impl Iterator<Item = &FreeRecord> {
+        self.records.iter()
+    }
+    /// Classifies a free of `offset` that missed the allocation map.
+    pub(crate) fn diagnose(&self, offset: u32) -> BadFree {
+        match self.records.iter().rev().find(|r| r.offset == offset) {
+            Some(r) => BadFree::DoubleFree(*r),
+            None => BadFree::WildFree,
+        }
+    }
+}
+
+/// Returns the `(offset, len)` of the allocation containing `offset`, or failing that, the
+/// one whose start is closest to it.
+pub(crate) fn nearest_region(allocs: &BTreeMap<u32, u32>, offset: u32) -> Option<(u32, u32)> {
+    let below = allocs.range(..=offset).next_back().map(|(&o, &l)| (o, l));
+    if let Some((o, l)) = below {
+        if offset < o + l {
+            return below;
+        }
+    }
+    let above = allocs.range(offset..).next().map(|(&o, &l)| (o, l));
+    match (below, above) {
+        (Some(b), Some(a)) => if offset - b.0 <= a.0 - offset { Some(b) } else { Some(a) },
+        (b, a) => b.or(a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_double_free() {
+        let mut hist = FreeHistory::new();
+        // nothing is remembered while disabled
+        hist.record(0x60, 64, 1, "test");
+        assert_eq!(hist.diagnose(0x60), BadFree::WildFree);
+
+        hist.set_enabled(true);
+        hist.record(0x60, 64, 10, "first");
+        hist.record(0xa0, 8, 20, "second");
+        hist.record(0x60, 64, 30, "third");
+        // the latest free of the offset is reported
+        match hist.diagnose(0x60) {
+            BadFree::DoubleFree(r) => {
+                assert_eq!(r.timestamp, 30);
+                assert_eq!(r.tag, "third");
+            }
+            BadFree::WildFree => panic!("double free not detected"),
+        }
+        // once handed out again, the old frees no longer count
+        hist.reallocated(0x60);
+        assert_eq!(hist.diagnose(0x60), BadFree::WildFree);
+        assert!(matches!(hist.diagnose(0xa0), BadFree::DoubleFree(_)));
+
+        // the history is bounded
+        for i in 0..FREE_HISTORY_LEN as u32 {
+            hist.record(0x100 + i * 16, 16, 100 + i as u64, "fill");
+        }
+        assert_eq!(hist.records().count(), FREE_HISTORY_LEN);
+        assert_eq!(hist.diagnose(0xa0), BadFree::WildFree);
+    }
+
+    #[test]
+    fn test_wild_free() {
+        let mut hist = FreeHistory::new();
+        hist.set_enabled(true);
+        hist.record(0x60, 64, 10, "ep1");
+        assert_eq!(hist.diagnose(0x70), BadFree::WildFree);
+
+        let mut allocs = BTreeMap::new();
+        assert_eq!(nearest_region(&allocs, 0x70), None);
+        allocs.insert(0x60, 64);
+        allocs.insert(0x200, 8);
+        // inside a region
+        assert_eq!(nearest_region(&allocs, 0x70), Some((0x60, 64)));
+        // between regions, closest start wins
+        assert_eq!(nearest_region(&allocs, 0x100), Some((0x60, 64)));
+        assert_eq!(nearest_region(&allocs, 0x1f0), Some((0x200, 8)));
+        // past the end, and before the start
+        assert_eq!(nearest_region(&allocs, 0x800), Some((0x200, 8)));
+        assert_eq!(nearest_region(&allocs, 0x10), Some((0x60, 64)));
+    }
+}
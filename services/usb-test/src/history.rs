@@ -0,0 +1,198 @@
+use crate::aliases::Aliases;
+use crate::pattern::parse_number;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+/// most command lines the history holds; older ones are dropped
+pub(crate) const MAX_HISTORY: usize = 32;
+/// most bytes a `ShellSnapshot` takes. Aliases go in first; history entries that don't fit
+/// after them are left out, oldest first.
+pub(crate) const MAX_SNAPSHOT_LEN: usize = 2048;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum HistoryError {
+    /// `!` wasn't followed by a number
+    InvalidNumber,
+    /// no entry by that number, or not any more
+    NotFound,
+}
+
+/// The command lines typed at the shell, numbered from 1 as they come in. Numbers aren't
+/// reused, so `!n` keeps meaning the same line while it's in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommandHistory {
+    entries: VecDeque<String>,
+    /// number of the oldest entry held
+    first: usize,
+}
+impl CommandHistory {
+    pub(crate) fn new() -> CommandHistory {
+        CommandHistory { entries: VecDeque::new(), first: 1 }
+    }
+    pub(crate) fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        if self.entries.len() >= MAX_HISTORY {
+            self.entries.pop_front();
+            self.first += 1;
+        }
+        self.entries.push_back(line.to_string());
+    }
+    pub(crate) fn get(&self, number: usize) -> Option<&str> {
+        self.entries.get(number.checked_sub(self.first)?).map(|e| e.as_str())
+    }
+    /// the entries as (number, line), oldest first
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.entries.iter().enumerate().map(move |(i, e)| (self.first + i, e.as_str()))
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Forgets every entry; numbering carries on from where it was
+    pub(crate) fn clear(&mut self) {
+        self.first += self.entries.len();
+        self.entries.clear();
+    }
+    /// Looks up a `!n` command line. Returns `None` if `cmdline` isn't one.
+    pub(crate) fn recall(&self, cmdline: &str) -> Result<Option<String>, HistoryError> {
+        let number = match cmdline.trim().strip_prefix('!') {
+            Some(number) => parse_number(number).ok_or(HistoryError::InvalidNumber)?,
+            None => return Ok(None),
+        };
+        self.get(number).map(|line| Some(line.to_string())).ok_or(HistoryError::NotFound)
+    }
+}
+
+/// The history and aliases, packed into a flat buffer of at most `MAX_SNAPSHOT_LEN` bytes so
+/// they can be carried over a suspend and put back on resume.
+pub(crate) struct ShellSnapshot {
+    bytes: Vec<u8>,
+    /// history entries that didn't fit
+    pub(crate) dropped: usize,
+}
+impl ShellSnapshot {
+    pub(crate) fn capture(history: &CommandHistory, aliases: &Aliases) -> ShellSnapshot {
+        let mut packed = vec![0u8];
+        for (name, command) in aliases.iter() {
+            if packed.len() + 4 + name.len() + command.len() > MAX_SNAPSHOT_LEN / 2 {
+                break;
+            }
+            push_str(&mut packed, name);
+            push_str(&mut packed, command);
+            packed[0] += 1;
+        }
+
+        // the newest entries that fit in what's left, after the history's own header
+        let mut room = MAX_SNAPSHOT_LEN - packed.len() - 5;
+        let kept = history.entries.iter().rev()
+            .take_while(|line| match room.checked_sub(2 + line.len()) {
+                Some(left) => {
+                    room = left;
+                    true
+                }
+                None => false,
+            })
+            .count();
+        let skip = history.len() - kept;
+        packed.extend_from_slice(&((history.first + skip) as u32).to_le_bytes());
+        packed.push(kept as u8);
+        for (_, line) in history.iter().skip(skip) {
+            push_str(&mut packed, line);
+        }
+        ShellSnapshot { bytes: packed, dropped: skip }
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len()
+    }
+    /// Unpacks the history and aliases. Returns `None` if the snapshot is malformed.
+    pub(crate) fn restore(&self) -> Option<(CommandHistory, Aliases)> {
+        let mut cursor = &self.bytes[..];
+        let mut aliases = Aliases::new();
+        for _ in 0..take_u8(&mut cursor)? {
+            let name = take_str(&mut cursor)?;
+            let command = take_str(&mut cursor)?;
+            aliases.set(&name, &command).ok()?;
+        }
+        let first = u32::from_le_bytes(take(&mut cursor, 4)?.try_into().ok()?) as usize;
+        let mut history = CommandHistory { entries: VecDeque::new(), first };
+        for _ in 0..take_u8(&mut cursor)? {
+            history.entries.push_back(take_str(&mut cursor)?);
+        }
+        if !cursor.is_empty() {
+            return None;
+        }
+        Some((history, aliases))
+    }
+}
+fn push_str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+fn take<'a>(cursor: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if cursor.len() < len {
+        return None;
+    }
+    let (head, tail) = cursor.split_at(len);
+    *cursor = tail;
+    Some(head)
+}
+fn take_u8(cursor: &mut &[u8]) -> Option<u8> {
+    take(cursor, 1).map(|b| b[0])
+}
+fn take_str(cursor: &mut &[u8]) -> Option<String> {
+    let len = u16::from_le_bytes(take(cursor, 2)?.try_into().ok()?) as usize;
+    String::from_utf8(take(cursor, len)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_snapshot() {
+        let mut history = CommandHistory::new();
+        history.push("status");
+        history.push("  ");
+        history.push("epstats 1");
+        assert_eq!(history.recall("!2"), Ok(Some("epstats 1".to_string())));
+        assert_eq!(history.recall("!3"), Err(HistoryError::NotFound));
+        assert_eq!(history.recall("!x"), Err(HistoryError::InvalidNumber));
+        assert_eq!(history.recall("status"), Ok(None));
+        for i in 0..MAX_HISTORY {
+            history.push(&format!("timeline {}", i));
+        }
+        // the oldest fell off, and the numbers stayed put
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history.get(2), None);
+        assert_eq!(history.get(3), Some("timeline 0"));
+
+        let mut aliases = Aliases::new();
+        aliases.set("on", "conn 1").unwrap();
+        aliases.set("ip", "pattern fill 0").unwrap();
+        let snapshot = ShellSnapshot::capture(&history, &aliases);
+        assert_eq!(snapshot.dropped, 0);
+        let (restored, restored_aliases) = snapshot.restore().unwrap();
+        assert_eq!(restored, history);
+        assert_eq!(restored_aliases.iter().collect::<Vec<_>>(), aliases.iter().collect::<Vec<_>>());
+
+        // long lines: the snapshot stays under the cap by leaving the oldest entries out
+        let long = "x".repeat(200);
+        for _ in 0..MAX_HISTORY {
+            history.push(&format!("repeat 2 {}", long));
+        }
+        let snapshot = ShellSnapshot::capture(&history, &aliases);
+        assert!(snapshot.len() <= MAX_SNAPSHOT_LEN);
+        assert!(snapshot.dropped > 0);
+        let (restored, _) = snapshot.restore().unwrap();
+        assert_eq!(restored.len(), MAX_HISTORY - snapshot.dropped);
+        let newest = history.iter().last().unwrap();
+        assert_eq!(restored.iter().last(), Some(newest));
+
+        // a damaged snapshot is refused rather than half restored
+        let mut damaged = ShellSnapshot::capture(&history, &aliases);
+        damaged.bytes.pop();
+        assert!(damaged.restore().is_none());
+    }
+}
@@ -12,6 +12,40 @@ impl UsbTest {
     }
 }
 
+/// Drives the shell from the terminal, since there is no UART in hosted mode: each line read
+/// from stdin is typed into `KeyboardChar` as if it came over the UART, and entered. At EOF
+/// the server is asked to `Quit`.
+pub(crate) fn spawn_stdin_reader(sid: xous::SID) {
+    use num_traits::ToPrimitive;
+    use std::io::BufRead;
+    std::thread::spawn(move || {
+        let cid = xous::connect(sid).unwrap();
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::error!("couldn't read stdin: {:?}", e);
+                    break;
+                }
+            };
+            for k in line_keys(&line) {
+                xous::send_message(cid, xous::Message::new_scalar(
+                    crate::api::Opcode::KeyboardChar.to_usize().unwrap(), k, 0, 0, 0
+                )).ok();
+            }
+        }
+        log::info!("stdin closed, quitting");
+        xous::send_message(cid, xous::Message::new_scalar(
+            crate::api::Opcode::Quit.to_usize().unwrap(), 0, 0, 0, 0
+        )).ok();
+    });
+}
+/// The UART codes that type out `line` and enter it. A trailing CR from a CRLF line ending is
+/// dropped, so it doesn't enter the line early.
+pub(crate) fn line_keys(line: &str) -> impl Iterator<Item = usize> + '_ {
+    line.trim_end_matches('\r').chars().map(|c| c as usize).chain(core::iter::once(0x0d))
+}
+
 use keyboard::{RowCol, KeyRawStates, ScanCode, KeyMap};
 #[allow(dead_code)]
 pub(crate) struct Keyboard {
@@ -56,6 +90,10 @@ impl Keyboard {
         Vec::new()
     }
 
+    pub fn layout(&self) -> Vec<(RowCol, ScanCode)> {
+        Vec::new()
+    }
+
     pub fn set_repeat(&mut self, rate: u32, delay: u32) {
         self.rate = rate;
         self.delay = delay;
@@ -77,24 +115,36 @@ impl Keyboard {
 
 
 pub struct SpinalUsbDevice {
+    // there's no cable in hosted mode: VBUS is whatever `vbus on/off` last set, present to start with
+    vbus: std::sync::Mutex<bool>,
 }
 
 impl SpinalUsbDevice {
-    pub fn new(_sid: xous::SID) -> SpinalUsbDevice {
-        SpinalUsbDevice {}
+    pub(crate) fn new(_sid: xous::SID, _alloc_cfg: crate::AllocatorConfig) -> SpinalUsbDevice {
+        SpinalUsbDevice { vbus: std::sync::Mutex::new(true) }
+    }
+    pub(crate) fn reallocate(&self) -> SpinalUsbDevice {
+        SpinalUsbDevice { vbus: std::sync::Mutex::new(self.vbus_present()) }
+    }
+    pub fn vbus_present(&self) -> bool {
+        *self.vbus.lock().unwrap()
+    }
+    /// Sets the simulated VBUS level; `None` puts it back to present
+    pub fn simulate_vbus(&self, present: Option<bool>) {
+        *self.vbus.lock().unwrap() = present.unwrap_or(true);
     }
     pub fn print_regs(&self) {
     }
     /// simple but easy to understand allocator for buffers inside the descriptor memory space
-    pub fn alloc_region(&mut self, requested: usize) -> Option<u32> {
-        None
+    pub(crate) fn alloc_region(&mut self, _requested: u32, _label: &'static str) -> Result<u32, crate::error::UsbError> {
+        Err(crate::error::UsbError::OutOfMemory)
     }
-    /// returns `true` if the region was available to be deallocated
-    pub fn dealloc_region(&mut self, offset: usize) -> bool {
-        false
+    pub(crate) fn dealloc_region(&mut self, _offset: u32, _tag: &'static str) -> Result<(), crate::error::UsbError> {
+        Err(crate::error::UsbError::NotAllocated)
     }
 
-    pub fn connect_device_core(&mut self, _state: bool) {
+    pub(crate) fn connect_device_core(&mut self, _state: bool) -> Result<(), crate::error::UsbError> {
+        Err(crate::error::UsbError::NotConnected)
     }
 
     pub fn suspend(&mut self) {
@@ -35,14 +35,284 @@ fn handle_usb(_irq_no: usize, arg: *mut usize) {
         xous::Message::new_scalar(Opcode::UsbIrqHandler.to_usize().unwrap(), 0, 0, 0, 0)).ok();
 }
 
+/// Writes `len` bytes of `kind` to the descriptor RAM at `base`, starting at byte `offset`.
+/// The memory only supports full-word accesses, so bytes are merged into their words.
+fn write_mem(base: *mut u32, offset: usize, len: usize, kind: PatternKind) {
+    for (i, b) in kind.generator().take(len).enumerate() {
+        let addr = offset + i;
+        let word = unsafe { base.add(addr / 4) };
+        let mut bytes = unsafe { word.read_volatile() }.to_le_bytes();
+        bytes[addr % 4] = b;
+        unsafe { word.write_volatile(u32::from_le_bytes(bytes)) };
+    }
+}
+
+/// Logs a warning if the descriptor endpoint `index` points at lies in a poisoned region.
+/// `context` says what was looking at the endpoint.
+fn check_poison(poison: &PoisonMap, base: *const u32, index: usize, ep_status: &UdcEpStatus, context: &str) {
+    if !poison.enabled() || ep_status.head_offset() == 0 {
+        return;
+    }
+    let addr = ep_status.head_offset() * 16;
+    let word = unsafe { base.add(addr as usize / 4).read_volatile() };
+    if let Some(hit) = poison.check_access(addr, word) {
+        log::warn!("{}: ep{} descriptor at 0x{:x} is in freed region 0x{:x}({}){}",
+            context, index, addr, hit.region.0, hit.region.1,
+            if hit.intact { "" } else { ", which was written after it was freed" });
+    }
+}
+
 pub struct SpinalUsbMgmt {
     csr: AtomicCsr<u32>, // consider using VolatileCell and/or refactory AtomicCsr so it is non-mutable
     usb: AtomicPtr<u8>,
     eps: AtomicPtr<UdcEpStatus>,
     srmem: ManagedMem<{ utralib::generated::HW_USBDEV_MEM_LEN / core::mem::size_of::<u32>() }>,
     regs: SpinalUdcRegs,
+    descriptors: Arc::<Mutex::<DescriptorSet>>,
+    watchdog: Arc::<Mutex::<Watchdog>>,
+    ep_stats: Arc::<Mutex::<[EpStats; 16]>>,
+    allocs: Arc::<Mutex::<BTreeMap<u32, u32>>>,
+    alloc_cfg: AllocatorConfig,
+    free_history: Arc::<Mutex::<FreeHistory>>,
+    alloc_stats: Arc::<Mutex::<AllocStats>>,
+    poison: Arc::<Mutex::<PoisonMap>>,
+    nak: Arc::<Mutex::<NakSim>>,
+    intervals: Arc::<Mutex::<EpIntervals>>,
+    setup_trace: Arc::<Mutex::<SetupTrace>>,
+    bus_state: Arc::<Mutex::<BusState>>,
+    /// transfers disarmed for a suspend, to be armed again on resume
+    quiesce: Quiesce,
 }
 impl SpinalUsbMgmt {
+    /// Turns on logging of each SETUP packet and its outcome as it happens. The trace is
+    /// recorded either way.
+    pub fn set_setup_trace(&self, live: bool) {
+        self.setup_trace.lock().unwrap().set_live(live);
+    }
+    /// whether live logging is on, and the recorded SETUP packets, oldest first
+    pub(crate) fn setup_trace(&self) -> (bool, Vec<SetupRecord>) {
+        let trace = self.setup_trace.lock().unwrap();
+        (trace.live(), trace.records().copied().collect())
+    }
+    /// Sets the polling interval of interrupt endpoint `index` as described to the host. Takes
+    /// effect on the next enumeration. Returns `false` if the endpoint isn't an interrupt endpoint.
+    pub fn set_interval(&self, index: usize, interval: u8) -> bool {
+        self.intervals.lock().unwrap().set(index, interval)
+    }
+    /// `(index, bInterval)` of each interrupt endpoint, as it will be described to the host on
+    /// the next enumeration
+    pub fn intervals(&self) -> Vec<(usize, u8)> {
+        self.intervals.lock().unwrap().all()
+    }
+    /// `(index, bInterval)` of each interrupt endpoint, as read back from the configuration
+    /// descriptor the host was last sent
+    pub fn described_intervals(&self) -> Vec<(usize, u8)> {
+        self.intervals.lock().unwrap().described()
+    }
+    /// Makes endpoint `index` NAK everything for `duration_ms` from `now`. A transfer that is
+    /// armed when the window opens is held back, and goes ahead when the window closes.
+    pub fn start_nak(&self, index: usize, now: u64, duration_ms: u64) {
+        let mut nak = self.nak.lock().unwrap();
+        let mut ep_status = self.status_from_index(index);
+        let armed = if ep_status.head_offset() != 0 { Some(ep_status.0) } else { None };
+        // an endpoint with no descriptor linked NAKs
+        ep_status.set_head_offset(0);
+        unsafe { self.eps.load(Ordering::SeqCst).add(index).write_volatile(ep_status) };
+        nak.start(index, now, duration_ms, armed);
+        log::info!("nak: ep{} NAKing for {}ms", index, duration_ms);
+    }
+    /// Called periodically to close NAK windows that have ended.
+    pub fn nak_check(&self, now: u64) {
+        let ended = self.nak.lock().unwrap().expire(now);
+        for (index, parked) in ended {
+            if let Some(status) = parked {
+                unsafe { self.eps.load(Ordering::SeqCst).add(index).write_volatile(UdcEpStatus(status)) };
+            }
+            log::info!("nak: ep{} NAK window ended{}", index, if parked.is_some() { ", transfer resumed" } else { "" });
+        }
+    }
+    /// Turns on filling freed regions with `PatternKind::Poison`, and warning about completions
+    /// and stuck transfers that reference them. Turning it off forgets the poisoned regions.
+    pub fn set_poisoning(&self, enabled: bool) {
+        self.poison.lock().unwrap().set_enabled(enabled);
+    }
+    /// whether poisoning is on, and the `(offset, len)` of each region poisoned so far
+    pub fn poisoned_regions(&self) -> (bool, Vec<(u32, u32)>) {
+        let poison = self.poison.lock().unwrap();
+        (poison.enabled(), poison.regions().map(|(&o, &l)| (o, l)).collect())
+    }
+    /// Turns on remembering recent frees, for diagnosing double and wild frees. Turning it
+    /// off discards the history.
+    pub fn set_free_tracking(&self, enabled: bool) {
+        self.free_history.lock().unwrap().set_enabled(enabled);
+    }
+    /// whether free tracking is on, and the recorded frees, oldest first
+    pub(crate) fn free_history(&self) -> (bool, Vec<FreeRecord>) {
+        let hist = self.free_history.lock().unwrap();
+        (hist.enabled(), hist.records().copied().collect())
+    }
+    /// `(peak total, peak single)` bytes allocated, since startup or the last
+    /// `reset_alloc_peaks()`
+    pub(crate) fn alloc_peaks(&self) -> (u32, u32) {
+        let stats = self.alloc_stats.lock().unwrap();
+        (stats.peak_total(), stats.peak_single())
+    }
+    /// Starts the allocation peaks over from what's allocated now
+    pub fn reset_alloc_peaks(&self) {
+        self.alloc_stats.lock().unwrap().reset_peaks();
+    }
+    /// the live allocations with their creators, oldest first
+    pub(crate) fn live_allocs(&self) -> Vec<LiveAlloc> {
+        self.alloc_stats.lock().unwrap().by_age()
+    }
+    /// bounds of the descriptor memory managed by the allocator
+    pub(crate) fn alloc_config(&self) -> AllocatorConfig {
+        self.alloc_cfg
+    }
+    /// total unallocated bytes in the descriptor memory
+    pub fn free_bytes(&self) -> u32 {
+        free_bytes(self.alloc_cfg, &self.allocs.lock().unwrap())
+    }
+    /// the largest buffer that can currently be allocated
+    pub fn largest_free_block(&self) -> u32 {
+        largest_free_block(self.alloc_cfg, &self.allocs.lock().unwrap())
+    }
+    /// a copy of the allocation map, as `offset => len`
+    pub fn allocs(&self) -> BTreeMap<u32, u32> {
+        self.allocs.lock().unwrap().clone()
+    }
+    pub(crate) fn ep_stats(&self) -> [EpStats; 16] {
+        *self.ep_stats.lock().unwrap()
+    }
+    pub fn clear_ep_stats(&self) {
+        *self.ep_stats.lock().unwrap() = [EpStats::default(); 16];
+    }
+    pub(crate) fn status(&self) -> UsbStatus {
+        let bus_state = self.bus_state.lock().unwrap();
+        UsbStatus {
+            ep_stats: self.ep_stats(),
+            stuck_transfers: self.watchdog.lock().unwrap().stuck_transfers(),
+            bus_resets: bus_state.resets,
+            resets_mid_config: bus_state.resets_mid_config,
+            // filled in by the main loop, which owns the DFU interface
+            dfu: DfuStatus::default(),
+        }
+    }
+    /// Sets the stuck-transfer threshold (`None` disables the watchdog), and whether endpoints
+    /// with stuck transfers are stalled.
+    pub fn set_watchdog(&self, threshold_ms: Option<u64>, kill: bool) {
+        let mut wd = self.watchdog.lock().unwrap();
+        wd.set_threshold(threshold_ms);
+        wd.set_kill(kill);
+    }
+    pub fn watchdog_state(&self) -> (Option<u64>, bool, u32) {
+        let wd = self.watchdog.lock().unwrap();
+        (wd.threshold(), wd.kill(), wd.stuck_transfers())
+    }
+    /// `(index, transfer)` of each transfer that's armed and still pending, oldest first
+    pub(crate) fn armed_transfers(&self) -> Vec<(usize, ArmedTransfer)> {
+        let wd = self.watchdog.lock().unwrap();
+        let mut armed: Vec<(usize, ArmedTransfer)> = (0..16).filter_map(|i| wd.armed(i).map(|t| (i, t))).collect();
+        armed.sort_by_key(|&(i, t)| (t.armed_at, i));
+        armed
+    }
+    /// Called periodically to report transfers that have been pending for too long.
+    pub fn watchdog_check(&self, now: u64) {
+        let mut wd = self.watchdog.lock().unwrap();
+        for stuck in wd.check(now) {
+            let ep_status = self.status_from_index(stuck.index);
+            log::warn!("watchdog: ep{} {:?} pending for {}ms", stuck.index, stuck.dir, stuck.pending_ms);
+            log::warn!("watchdog: ep{}_status: {:x?}", stuck.index, ep_status);
+            check_poison(&self.poison.lock().unwrap(), self.usb.load(Ordering::SeqCst) as *const u32,
+                stuck.index, &ep_status, "watchdog");
+            if ep_status.head_offset() != 0 {
+                let desc = self.descriptor_from_status(&ep_status);
+                log::warn!("watchdog: offset: {}, in_progress: {}, length: {}", desc.offset(), desc.in_progress(), desc.length());
+            }
+            if wd.kill() {
+                log::warn!("watchdog: stalling ep{}", stuck.index);
+                self.stall(stuck.index);
+                wd.complete(stuck.index);
+            }
+        }
+    }
+    /// forces a STALL on an endpoint, abandoning whatever transfer it had armed
+    fn stall(&self, index: usize) {
+        self.regs.set_halt(UdcHalt(index as u32 | 0x10));
+        let mut iters = 0;
+        while !self.regs.halt().enable_ack() {
+            xous::yield_slice();
+            iters += 1;
+            if iters == 1000 {
+                log::info!("halt for stall possibly timed out");
+            }
+        }
+        let mut ep_status = self.status_from_index(index);
+        ep_status.set_force_stall(true);
+        unsafe { self.eps.load(Ordering::SeqCst).add(index).write_volatile(ep_status) };
+        self.regs.set_halt(UdcHalt(index as u32));
+    }
+    /// Checks the descriptors most recently sent to the host. Returns an empty list if
+    /// everything checks out, and `NotConnected` if the host has yet to ask for any descriptors.
+    pub(crate) fn validate_descriptors(&self) -> core::result::Result<Vec<(String, LintViolation)>, crate::error::UsbError> {
+        let descriptors = self.descriptors.lock().unwrap();
+        if descriptors.is_empty() {
+            Err(crate::error::UsbError::NotConnected)
+        } else {
+            Ok(validate_descriptors(&descriptors))
+        }
+    }
+    /// A copy of the descriptors most recently sent to the host, as read back from descriptor
+    /// memory. Returns `NotConnected` if the host has yet to ask for any descriptors.
+    pub(crate) fn descriptors(&self) -> core::result::Result<DescriptorSet, crate::error::UsbError> {
+        let descriptors = self.descriptors.lock().unwrap();
+        if descriptors.is_empty() {
+            Err(crate::error::UsbError::NotConnected)
+        } else {
+            Ok(descriptors.clone())
+        }
+    }
+    /// Fills `len` bytes of the core's descriptor RAM at `offset` with a test pattern.
+    /// Note that this happily overwrites endpoint status and descriptors that are in use;
+    /// it's meant for testing with the device core disconnected.
+    pub(crate) fn fill_mem(&self, offset: usize, len: usize, kind: PatternKind) -> core::result::Result<(), crate::error::UsbError> {
+        check_region(offset, len, self.regs.ramsize() as usize)?;
+        write_mem(self.usb.load(Ordering::SeqCst) as *mut u32, offset, len, kind);
+        Ok(())
+    }
+    /// Checks `len` bytes of the core's descriptor RAM at `offset` against a test pattern.
+    pub(crate) fn verify_mem(&self, offset: usize, len: usize, kind: PatternKind) -> core::result::Result<VerifyResult, crate::error::UsbError> {
+        check_region(offset, len, self.regs.ramsize() as usize)?;
+        let base = self.usb.load(Ordering::SeqCst) as *mut u32;
+        let mut data = Vec::<u8>::with_capacity(len);
+        for addr in offset..offset + len {
+            let word = unsafe { base.add(addr / 4).read_volatile() };
+            data.push(word.to_le_bytes()[addr % 4]);
+        }
+        Ok(verify(&data, kind))
+    }
+    /// Writes `word(addr)` to each word of the `len` bytes of descriptor RAM at `offset`, which
+    /// have to be word aligned. Like `fill_mem()`, this overwrites whatever is in use.
+    pub(crate) fn write_words(&self, offset: usize, len: usize, word: impl Fn(usize) -> u32) -> core::result::Result<(), crate::error::UsbError> {
+        check_region(offset, len, self.regs.ramsize() as usize)?;
+        if offset % 4 != 0 || len % 4 != 0 {
+            return Err(crate::error::UsbError::Misaligned);
+        }
+        let base = self.usb.load(Ordering::SeqCst) as *mut u32;
+        for addr in (offset..offset + len).step_by(4) {
+            unsafe { base.add(addr / 4).write_volatile(word(addr)) };
+        }
+        Ok(())
+    }
+    /// Reads back the `len` bytes of descriptor RAM at `offset` as words
+    pub(crate) fn read_words(&self, offset: usize, len: usize) -> core::result::Result<Vec<u32>, crate::error::UsbError> {
+        check_region(offset, len, self.regs.ramsize() as usize)?;
+        if offset % 4 != 0 || len % 4 != 0 {
+            return Err(crate::error::UsbError::Misaligned);
+        }
+        let base = self.usb.load(Ordering::SeqCst) as *const u32;
+        Ok((offset..offset + len).step_by(4).map(|addr| unsafe { base.add(addr / 4).read_volatile() }).collect())
+    }
     pub fn print_regs(&self) {
         for i in 0..16 {
             let ep_status = self.status_from_index(i);
@@ -61,15 +331,68 @@ impl SpinalUsbMgmt {
                 }
             }
         }
-        log::trace!("frame id: {}", self.regs.frame_id());
-        log::debug!("usb addr: {}", self.regs.address());
-        log::debug!("ints: {:x?}", self.regs.interrupts());
-        log::trace!("halt: 0x{:x?}", self.regs.halt());
-        log::trace!("config: 0x{:x?}", self.regs.config());
-        log::trace!("ramsize: {}", self.regs.ramsize());
-        assert!(4096 == self.regs.ramsize(), "hardware ramsize parameter does not match our expectations");
+        log::info!("stuck transfers: {}", self.watchdog.lock().unwrap().stuck_transfers());
+        module_log!(LogModule::Usb, log::Level::Trace, "frame id: {}", self.regs.frame_id());
+        module_log!(LogModule::Usb, log::Level::Debug, "usb addr: {}", self.regs.address());
+        module_log!(LogModule::Usb, log::Level::Debug, "ints: {:x?}", self.regs.interrupts());
+        module_log!(LogModule::Usb, log::Level::Trace, "halt: 0x{:x?}", self.regs.halt());
+        module_log!(LogModule::Usb, log::Level::Trace, "config: 0x{:x?}", self.regs.config());
+        module_log!(LogModule::Usb, log::Level::Trace, "ramsize: {}", self.regs.ramsize());
+        if self.alloc_cfg.end > self.regs.ramsize() {
+            log::warn!("allocator end 0x{:x} is past the hardware ramsize 0x{:x}", self.alloc_cfg.end, self.regs.ramsize());
+        }
+    }
+    /// Captures every readable register: the core's registers, the USB select and event CSRs,
+    /// each endpoint's status word, and the setup area. Uses the same sources as `print_regs`.
+    pub(crate) fn snapshot_regs(&self) -> RegSnapshot {
+        let mut snap = RegSnapshot::new();
+        snap.push_free_running("frame_id", self.regs.frame_id());
+        snap.push("address", self.regs.address());
+        snap.push("interrupts", self.regs.interrupts().0);
+        snap.push("halt", self.regs.halt().0);
+        snap.push("config", self.regs.config().0);
+        snap.push("ramsize", self.regs.ramsize());
+        snap.push("usbdisable", self.csr.r(utra::usbdev::USBDISABLE));
+        snap.push("usbselect", self.csr.r(utra::usbdev::USBSELECT));
+        snap.push("ev_pending", self.csr.r(utra::usbdev::EV_PENDING));
+        snap.push("ev_enable", self.csr.r(utra::usbdev::EV_ENABLE));
+        for i in 0..16 {
+            snap.push(&format!("ep{}_status", i), self.status_from_index(i).0);
+        }
+        let setup_data_base = unsafe{self.usb.load(Ordering::SeqCst).add(0x40) as *mut u32};
+        snap.push("setup0", unsafe{setup_data_base.add(0).read_volatile()});
+        snap.push("setup1", unsafe{setup_data_base.add(1).read_volatile()});
+        snap
+    }
+    /// The traps on the core's registers, shared with the device; see `trap`
+    pub(crate) fn reg_traps(&self) -> &RegTraps {
+        self.regs.traps()
     }
-    pub fn connect_device_core(&mut self, state: bool) {
+    /// How far the host has got enumerating the device
+    pub(crate) fn enum_state(&self) -> EnumState {
+        self.bus_state.lock().unwrap().state()
+    }
+    /// The state the device was in when the latest bus reset came, if that reset hasn't been
+    /// taken already
+    pub(crate) fn take_bus_reset(&self) -> Option<EnumState> {
+        self.bus_state.lock().unwrap().take_reset()
+    }
+    /// Turns the pullup, and with it the interrupts, on or off. With the pullup off the device
+    /// is detached as far as the host can tell, though the core stays selected.
+    pub fn set_attached(&self, attached: bool) {
+        let mut cfg = UdcConfig(0);
+        if attached {
+            cfg.set_enable_ints(true);
+            cfg.set_pullup_on(true);
+        } else {
+            cfg.set_disable_ints(true);
+            cfg.set_pullup_off(true);
+        }
+        self.regs.set_config(cfg);
+    }
+    /// Selects the device core (`true`) or the debug core (`false`). Fails with `NotConnected`
+    /// if the selection doesn't read back as requested.
+    pub(crate) fn connect_device_core(&mut self, state: bool) -> core::result::Result<(), crate::error::UsbError> {
         log::info!("previous state: {}", self.csr.rf(utra::usbdev::USBSELECT_SELECT_DEVICE));
         if state {
             log::info!("connecting USB device core");
@@ -78,14 +401,33 @@ impl SpinalUsbMgmt {
             log::info!("connecting USB debug core");
             self.csr.wfo(utra::usbdev::USBSELECT_SELECT_DEVICE, 0);
         }
+        if (self.csr.rf(utra::usbdev::USBSELECT_SELECT_DEVICE) != 0) == state {
+            Ok(())
+        } else {
+            Err(crate::error::UsbError::NotConnected)
+        }
     }
     pub fn xous_suspend(&mut self) {
         self.csr.wo(utra::usbdev::EV_PENDING, 0xFFFF_FFFF);
         self.csr.wo(utra::usbdev::EV_ENABLE, 0x0);
+        // don't save the core's memory with a packet half-sent
+        let start = std::time::Instant::now();
+        let mut quiesce = std::mem::replace(&mut self.quiesce, Quiesce::new());
+        let (parked, timeouts) = quiesce.suspend(&*self, QUIESCE_TIMEOUT_MS, || {
+            xous::yield_slice();
+            start.elapsed().as_millis() as u64
+        });
+        self.quiesce = quiesce;
+        log::info!("suspend: quiesced {} endpoint(s) in {}ms{}", parked, start.elapsed().as_millis(),
+            if timeouts > 0 { format!(", {} didn't go idle in time", timeouts) } else { String::new() });
         self.srmem.suspend();
     }
     pub fn xous_resume(&mut self) {
         self.srmem.resume();
+        let mut quiesce = std::mem::replace(&mut self.quiesce, Quiesce::new());
+        let rearmed = quiesce.resume(&*self);
+        self.quiesce = quiesce;
+        log::info!("resume: re-armed {} endpoint(s)", rearmed);
         let p = self.csr.r(utra::usbdev::EV_PENDING); // this has to be expanded out because AtomicPtr is potentially mutable on read
         self.csr.wo(utra::usbdev::EV_PENDING, p); // clear in case it's pending for some reason
         self.csr.wfo(utra::usbdev::EV_ENABLE_USB, 1);
@@ -103,6 +445,29 @@ impl SpinalUsbMgmt {
         }
     }
 }
+impl EpControl for SpinalUsbMgmt {
+    fn armed(&self, index: usize) -> Option<u32> {
+        let ep_status = self.status_from_index(index);
+        if ep_status.head_offset() != 0 { Some(ep_status.0) } else { None }
+    }
+    fn request_halt(&self, index: usize) {
+        self.regs.set_halt(UdcHalt(index as u32 | 0x10));
+    }
+    fn halt_acked(&self) -> bool {
+        self.regs.halt().enable_ack()
+    }
+    fn release_halt(&self, index: usize) {
+        self.regs.set_halt(UdcHalt(index as u32));
+    }
+    fn disarm(&self, index: usize) {
+        let mut ep_status = self.status_from_index(index);
+        ep_status.set_head_offset(0);
+        unsafe { self.eps.load(Ordering::SeqCst).add(index).write_volatile(ep_status) };
+    }
+    fn rearm(&self, index: usize, status: u32) {
+        unsafe { self.eps.load(Ordering::SeqCst).add(index).write_volatile(UdcEpStatus(status)) };
+    }
+}
 pub struct SpinalUsbDevice {
     pub(crate) conn: CID,
     usb: xous::MemoryRange,
@@ -119,11 +484,41 @@ pub struct SpinalUsbDevice {
     ep0in_head: u32,
     // structure to track space allocations within the memory space
     allocs: Arc::<Mutex::<BTreeMap<u32, u32>>>, // key is offset, value is len
+    alloc_mode: AllocMode,
+    alloc_cfg: AllocatorConfig,
+    // recent frees, for diagnosing bad frees when enabled
+    free_history: Arc::<Mutex::<FreeHistory>>,
+    // usage high-watermarks, and the age and creator of each live allocation
+    alloc_stats: Arc::<Mutex::<AllocStats>>,
+    // freed regions that were filled with poison, for catching use-after-free when enabled
+    poison: Arc::<Mutex::<PoisonMap>>,
+    // endpoints that are being held in a NAK state
+    nak: Arc::<Mutex::<NakSim>>,
+    // polling intervals of the interrupt endpoints, patched into the configuration descriptor
+    intervals: Arc::<Mutex::<EpIntervals>>,
+    // the most recent SETUP packets and what was done with them
+    setup_trace: Arc::<Mutex::<SetupTrace>>,
+    // enumeration state, and the resets that knocked it back
+    bus_state: Arc::<Mutex::<BusState>>,
+    // copy of every descriptor sent in response to a GET_DESCRIPTOR, for the `lint` command
+    descriptors: Arc::<Mutex::<DescriptorSet>>,
+    // the (type, index) of the GET_DESCRIPTOR currently being answered on ep0, if any
+    desc_capture: Mutex::<Option<(u8, u8)>>,
+    // arm times of pending transfers, for catching transfers the host never completes
+    watchdog: Arc::<Mutex::<Watchdog>>,
+    // per-endpoint transfer counters
+    ep_stats: Arc::<Mutex::<[EpStats; 16]>>,
     tt: ticktimer_server::Ticktimer,
     address: AtomicUsize,
+    // VBUS is read off the XADC, which belongs to the LLIO server
+    llio: llio::Llio,
+    // VBUS level forced with `vbus on/off`, in place of the real one
+    vbus_override: Mutex::<Option<bool>>,
 }
 impl SpinalUsbDevice {
-    pub fn new(sid: xous::SID) -> SpinalUsbDevice {
+    /// `alloc_cfg` bounds the descriptor memory available to the allocator. Its end is clamped
+    /// to the RAM size reported by the core, if the core reports one.
+    pub(crate) fn new(sid: xous::SID, alloc_cfg: AllocatorConfig) -> SpinalUsbDevice {
         // this particular core does not use CSRs for control - it uses directly memory mapped registers
         let usb = xous::syscall::map_memory(
             xous::MemoryAddress::new(utralib::HW_USBDEV_MEM),
@@ -155,8 +550,23 @@ impl SpinalUsbDevice {
             ep0in_head: 0,
             ep_allocs: [None; 16],
             allocs: Arc::new(Mutex::new(BTreeMap::new())),
+            alloc_mode: AllocMode::FirstFit,
+            alloc_cfg,
+            free_history: Arc::new(Mutex::new(FreeHistory::new())),
+            alloc_stats: Arc::new(Mutex::new(AllocStats::new())),
+            poison: Arc::new(Mutex::new(PoisonMap::new())),
+            nak: Arc::new(Mutex::new(NakSim::new())),
+            intervals: Arc::new(Mutex::new(EpIntervals::new())),
+            setup_trace: Arc::new(Mutex::new(SetupTrace::new())),
+            bus_state: Arc::new(Mutex::new(BusState::new())),
+            descriptors: Arc::new(Mutex::new(DescriptorSet::default())),
+            desc_capture: Mutex::new(None),
+            watchdog: Arc::new(Mutex::new(Watchdog::new())),
+            ep_stats: Arc::new(Mutex::new([EpStats::default(); 16])),
             tt: ticktimer_server::Ticktimer::new().unwrap(),
             address: AtomicUsize::new(0),
+            llio: llio::Llio::new(&xous_names::XousNames::new().unwrap()),
+            vbus_override: Mutex::new(None),
         };
 
         xous::claim_interrupt(
@@ -169,10 +579,21 @@ impl SpinalUsbDevice {
         usbdev.csr.wo(utra::usbdev::EV_PENDING, p); // clear in case it's pending for some reason
         usbdev.csr.wfo(utra::usbdev::EV_ENABLE_USB, 1);
 
+        // there's no point in pulling up before a host can see it; `autoconn` attaches later
         let mut cfg = UdcConfig(0);
-        cfg.set_pullup_on(true); // required for proper operation
+        if usbdev.vbus_present() {
+            cfg.set_pullup_on(true); // required for proper operation
+        } else {
+            log::info!("no VBUS, holding the pullup off");
+            cfg.set_pullup_off(true);
+        }
         usbdev.regs.set_config(cfg);
 
+        usbdev.alloc_cfg = alloc_cfg.fit_to_ram(usbdev.regs.ramsize());
+        if usbdev.alloc_cfg != alloc_cfg {
+            log::info!("allocator limited to {:x?} by the core's ramsize", usbdev.alloc_cfg);
+        }
+
         usbdev
     }
     pub fn get_iface(&self) -> SpinalUsbMgmt {
@@ -184,8 +605,82 @@ impl SpinalUsbDevice {
             }),
             srmem: ManagedMem::new(self.usb),
             regs: self.regs.clone(),
+            descriptors: self.descriptors.clone(),
+            watchdog: self.watchdog.clone(),
+            ep_stats: self.ep_stats.clone(),
+            allocs: self.allocs.clone(),
+            alloc_cfg: self.alloc_cfg,
+            free_history: self.free_history.clone(),
+            alloc_stats: self.alloc_stats.clone(),
+            poison: self.poison.clone(),
+            nak: self.nak.clone(),
+            intervals: self.intervals.clone(),
+            setup_trace: self.setup_trace.clone(),
+            bus_state: self.bus_state.clone(),
+            quiesce: Quiesce::new(),
+        }
+    }
+    /// A fresh bus on the same core, for a new `UsbBusAllocator`. usb-device only allocates
+    /// endpoints before the device is built, and never hands the bus back, so changing the set
+    /// of classes means starting over on a new allocator. The endpoints allocated through this
+    /// bus are freed and disabled, so it mustn't be used once the new one is in service; the
+    /// allocator, statistics and diagnostics are shared with it, as with `get_iface()`. The
+    /// interrupt stays claimed from `new()`.
+    pub(crate) fn reallocate(&self) -> SpinalUsbDevice {
+        let mut usbdev = SpinalUsbDevice {
+            conn: self.conn,
+            usb: self.usb,
+            csr_addr: self.csr_addr,
+            csr: AtomicCsr::new(self.csr_addr as *mut u32),
+            regs: self.regs.clone(),
+            eps: AtomicPtr::new(self.eps.load(Ordering::SeqCst)),
+            ep0in_head: 0,
+            ep_allocs: [None; 16],
+            allocs: self.allocs.clone(),
+            alloc_mode: self.alloc_mode,
+            alloc_cfg: self.alloc_cfg,
+            free_history: self.free_history.clone(),
+            alloc_stats: self.alloc_stats.clone(),
+            poison: self.poison.clone(),
+            nak: self.nak.clone(),
+            intervals: self.intervals.clone(),
+            setup_trace: self.setup_trace.clone(),
+            bus_state: self.bus_state.clone(),
+            descriptors: self.descriptors.clone(),
+            desc_capture: Mutex::new(None),
+            watchdog: self.watchdog.clone(),
+            ep_stats: self.ep_stats.clone(),
+            tt: ticktimer_server::Ticktimer::new().unwrap(),
+            address: AtomicUsize::new(0),
+            llio: llio::Llio::new(&xous_names::XousNames::new().unwrap()),
+            vbus_override: Mutex::new(*self.vbus_override.lock().unwrap()),
+        };
+        for (index, ep) in self.ep_allocs.iter().enumerate() {
+            if let Some((head_offset, _)) = *ep {
+                usbdev.status_write_volatile(index, UdcEpStatus(0));
+                usbdev.dealloc_region(head_offset as u32 * 16, EP_LABELS[index]).ok();
+                usbdev.intervals.lock().unwrap().freed(index);
+            }
+        }
+        usbdev
+    }
+    /// `true` if a host is supplying VBUS, or as forced with `simulate_vbus()`. Not debounced.
+    pub fn vbus_present(&self) -> bool {
+        if let Some(present) = *self.vbus_override.lock().unwrap() {
+            return present;
+        }
+        match self.llio.adc_vbus() {
+            Ok(raw) => vbus_from_adc(raw),
+            Err(e) => {
+                log::error!("couldn't read VBUS: {:?}", e);
+                false
+            }
         }
     }
+    /// Forces the level `vbus_present()` reports, or goes back to the real one with `None`
+    pub fn simulate_vbus(&self, present: Option<bool>) {
+        *self.vbus_override.lock().unwrap() = present;
+    }
     fn print_poll_result(&self, poll_result: &PollResult) {
         let info = match poll_result {
             PollResult::None => "PollResult::None".to_string(),
@@ -195,7 +690,7 @@ impl SpinalUsbDevice {
             PollResult::Data {ep_out, ep_in_complete, ep_setup} =>
                 format!("PollResult::Data out:{:x} in:{:x} setup:{:x}", ep_out, ep_in_complete, ep_setup),
         };
-        log::debug!("<<<< {}", info);
+        module_log!(LogModule::Usb, log::Level::Debug, "<<<< {}", info);
     }
     #[allow(dead_code)]
     pub fn print_ep_stats(&self) {
@@ -221,14 +716,56 @@ impl SpinalUsbDevice {
     /// See notes inside src/main.rs `alloc_inner` for the functional description. Returns
     /// the full byte-addressed offset of the region, so it must be shifted to the right by
     /// 4 before being put into a SpinalHDL descriptor (it uses 16-byte alignment and thus
-    /// discards the lower 4 bits).
-    pub fn alloc_region(&mut self, requested: u32) -> Option<u32> {
-        alloc_inner(&mut self.allocs.lock().unwrap(), requested)
+    /// discards the lower 4 bits). `label` names the caller, and is reported with the allocation
+    /// by `memstat -v`.
+    pub(crate) fn alloc_region(&mut self, requested: u32, label: &'static str) -> core::result::Result<u32, crate::error::UsbError> {
+        let offset = alloc_with(self.alloc_mode, self.alloc_cfg, &mut self.allocs.lock().unwrap(), requested)?;
+        self.alloc_stats.lock().unwrap().allocated(offset, requested, self.tt.elapsed_ms(), label);
+        self.free_history.lock().unwrap().reallocated(offset);
+        self.poison.lock().unwrap().unpoison(offset, requested);
+        Ok(offset)
     }
+    /// Selects the placement policy for endpoint buffers. Only affects later allocations, so
+    /// this must be called before the device is handed to the bus allocator.
     #[allow(dead_code)]
-    /// returns `true` if the region was available to be deallocated
-    pub fn dealloc_region(&mut self, offset: u32) -> bool {
-        dealloc_inner(&mut self.allocs.lock().unwrap(), offset)
+    pub(crate) fn set_alloc_mode(&mut self, mode: AllocMode) {
+        self.alloc_mode = mode;
+    }
+    /// Frees the region at `offset`. `tag` names the caller, and is kept in the free history
+    /// when free tracking is on. A free of an offset that isn't allocated is logged, and with
+    /// free tracking on, identified as a double free or a wild free. With poisoning on, the
+    /// freed region is filled with `PatternKind::Poison`.
+    pub(crate) fn dealloc_region(&mut self, offset: u32, tag: &'static str) -> core::result::Result<(), crate::error::UsbError> {
+        let mut allocs = self.allocs.lock().unwrap();
+        let len = allocs.get(&offset).copied();
+        let result = try_dealloc_inner(self.alloc_cfg, &mut allocs, offset);
+        let mut hist = self.free_history.lock().unwrap();
+        match result {
+            Ok(()) => {
+                self.alloc_stats.lock().unwrap().freed(offset);
+                let len = len.unwrap_or(0);
+                hist.record(offset, len, self.tt.elapsed_ms(), tag);
+                // the whole 16-byte aligned span is freed, so the whole span is poisoned
+                let span = (len + 0xF) & !0xF;
+                if self.poison.lock().unwrap().poison(offset, span) {
+                    write_mem(self.usb.as_mut_ptr() as *mut u32, offset as usize, span as usize, PatternKind::Poison);
+                }
+            }
+            Err(e) => {
+                log::error!("{}: bad free of 0x{:x}: {:?}", tag, offset, e);
+                if hist.enabled() {
+                    match hist.diagnose(offset) {
+                        BadFree::DoubleFree(prev) => log::error!("double free: 0x{:x}({}) already freed by {} at {}ms",
+                            prev.offset, prev.len, prev.tag, prev.timestamp),
+                        BadFree::WildFree => log::error!("wild free: 0x{:x} was never allocated", offset),
+                    }
+                }
+                if let Some((o, l)) = nearest_region(&allocs, offset) {
+                    log::error!("nearest allocated region: 0x{:x}-0x{:x}({})", o, o + l, l);
+                }
+            }
+        }
+        result
     }
     pub(crate) fn descriptor_from_status(&self, ep_status: &UdcEpStatus) -> SpinalUdcDescriptor {
         SpinalUdcDescriptor::new(
@@ -267,6 +804,13 @@ impl SpinalUsbDevice {
             self.eps.load(Ordering::SeqCst).add(index).write_volatile(ep_status)
         }
     }
+    /// Commits the status that arms a transfer, unless the endpoint is in a NAK window, in
+    /// which case the transfer is held back until the window ends.
+    fn commit_transfer(&self, index: usize, ep_status: UdcEpStatus) {
+        if !self.nak.lock().unwrap().park(index, ep_status.0) {
+            self.status_write_volatile(index, ep_status);
+        }
+    }
     pub(crate) fn udc_hard_halt(&self, index: usize) {
         self.regs.set_halt(UdcHalt(index as u32 | 0x10));
         let mut iters = 0;
@@ -322,14 +866,14 @@ impl UsbBus for SpinalUsbDevice {
         ep_addr: Option<EndpointAddress>,
         ep_type: EndpointType,
         max_packet_size: u16,
-        _interval: u8,
+        interval: u8,
     ) -> Result<EndpointAddress> {
         // if ep_addr is specified, create a 1-unit range else a range through the entire space
         // note that ep_addr is a packed representation of index and direction,
         // so you must use `.index()` to get just the index part
-        log::debug!("alloc ep spec: {:?} of type {:?}", ep_addr, ep_type);
+        module_log!(LogModule::Usb, log::Level::Debug, "alloc ep spec: {:?} of type {:?}", ep_addr, ep_type);
         if ep_addr == Some(EndpointAddress::from_parts(0, UsbDirection::Out)) { // flag the control in as a special case
-            log::debug!("ep0 allocation fixed to 8 bytes, returning OK");
+            module_log!(LogModule::Usb, log::Level::Debug, "ep0 allocation fixed to 8 bytes, returning OK");
             // setup the default ep0 out descriptor but leave it unlinked
             self.ep0_out_reset();
             return Ok(EndpointAddress::from_parts(0, UsbDirection::Out))
@@ -337,14 +881,15 @@ impl UsbBus for SpinalUsbDevice {
         for index in ep_addr.map(|a| a.index()..a.index() + 1).unwrap_or(1..NUM_ENDPOINTS) {
             if self.ep_allocs[index].is_none() {
                 // only if there is memory that can accommodate the max_packet_size
-                if let Some(offset) = self.alloc_region(max_packet_size as _) {
+                let alloc = self.alloc_region(max_packet_size as _, EP_LABELS[index]);
+                if let Ok(offset) = alloc {
                     log::info!("allocated offset {:x}({})", offset, max_packet_size);
                     let mut ep_status = UdcEpStatus(0);
                     match ep_type {
                         EndpointType::Isochronous => ep_status.set_isochronous(true),
                         _ => ep_status.set_isochronous(false),
                     }
-                    log::debug!("alloc ep{}@{:x?}{} max_packet_size {}",
+                    module_log!(LogModule::Usb, log::Level::Debug, "alloc ep{}@{:x?}{} max_packet_size {}",
                         index,
                         offset,
                         match ep_dir {
@@ -353,6 +898,9 @@ impl UsbBus for SpinalUsbDevice {
                         },
                         max_packet_size
                     );
+                    if ep_type == EndpointType::Interrupt {
+                        self.intervals.lock().unwrap().allocated(index, interval);
+                    }
                     ep_status.set_head_offset(offset / 16);
                     ep_status.set_max_packet_size(max_packet_size as u32);
                     ep_status.set_enable(true);
@@ -382,14 +930,18 @@ impl UsbBus for SpinalUsbDevice {
 
                     core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
                     return Ok(EndpointAddress::from_parts(index as usize, ep_dir))
-                } else {
-                    return Err(UsbError::EndpointMemoryOverflow);
+                } else if let Err(e) = alloc {
+                    log::error!("couldn't allocate {} bytes for ep{}: {:?}", max_packet_size, index, e);
+                    return Err(e.into());
                 }
             }
         }
         // nothing matched, so there must be an error
         Err(match ep_addr {
-            Some(_) => UsbError::InvalidEndpoint,
+            Some(a) => {
+                log::error!("couldn't allocate ep{}: {:?}", a.index(), crate::error::UsbError::EndpointBusy);
+                crate::error::UsbError::EndpointBusy.into()
+            }
             None => UsbError::EndpointOverflow,
         })
     }
@@ -418,10 +970,12 @@ impl UsbBus for SpinalUsbDevice {
         self.regs.set_address(0x0); // this does *not* require the trigger
         self.address.store(0, Ordering::SeqCst);
         self.ep0_out_reset();
+        // OUT endpoints disarmed here, to be armed again when the host sets a configuration
+        let mut out_eps = 0u16;
         for (index, &ep) in self.ep_allocs.iter().enumerate() {
             if let Some((head_offset, max_len)) = ep {
                 if index == 0 {
-                    log::trace!("ep0 reset");
+                    module_log!(LogModule::Usb, log::Level::Trace, "ep0 reset");
                     // basically rewrite the whole EP0 setup from scratch.
                     let mut ep0_status = self.status_read_volatile(0);
                     ep0_status.set_head_offset(head_offset as u32);
@@ -436,12 +990,19 @@ impl UsbBus for SpinalUsbDevice {
                     descriptor.set_desc_flags(UsbDirection::In, true, true, true);
                 } else {
                     let mut ep_status = self.status_read_volatile(index);
-                    log::trace!("ep{} reset from {:?}", index, ep_status);
+                    module_log!(LogModule::Usb, log::Level::Trace, "ep{} reset from {:?}", index, ep_status);
                     ep_status.set_max_packet_size(max_len as u32);
                     ep_status.set_head_offset(head_offset as u32);
                     let descriptor = self.descriptor_from_status(&ep_status);
-                    self.status_write_volatile(index, ep_status);
+                    if descriptor.direction() == UsbDirection::Out {
+                        out_eps |= 1 << index;
+                    }
                     descriptor.set_offset(0); // reset the pointer to 0, and sets phase
+                    // only EP0 is live until the host configures the device again
+                    ep_status.set_head_offset(0);
+                    ep_status.set_data_phase(false); // reset to data0
+                    ep_status.set_force_stall(false);
+                    self.status_write_volatile(index, ep_status);
                 }
             }
         }
@@ -458,7 +1019,8 @@ impl UsbBus for SpinalUsbDevice {
                 }
             }
         }
-        log::debug!("{:?}", self.regs);
+        self.bus_state.lock().unwrap().reset(out_eps);
+        module_log!(LogModule::Usb, log::Level::Debug, "{:?}", self.regs);
         // clear other registers
         // self.regs.set_address(0); // i think this is automatic in the USB core...
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
@@ -469,7 +1031,7 @@ impl UsbBus for SpinalUsbDevice {
         // note: this core requires the address setting to be done right after the ep0 SETUP
         // packet that specifies setting up an address. Therefore, this call is a dummy.
         self.address.store(addr as usize, Ordering::SeqCst);
-        log::debug!("set_addr dummy {}", addr);
+        module_log!(LogModule::Usb, log::Level::Debug, "set_addr dummy {}", addr);
     }
 
     /// Writes a single packet of data to the specified endpoint and returns number of bytes
@@ -496,6 +1058,19 @@ impl UsbBus for SpinalUsbDevice {
             if buf.len() > max_len {
                 Err(UsbError::BufferOverflow)
             } else {
+                // changed polling intervals are patched into the configuration descriptor on its way out
+                let patched;
+                let buf = if ep_addr.index() == 0
+                    && matches!(*self.desc_capture.lock().unwrap(), Some((DESC_TYPE_CONFIGURATION, _)))
+                {
+                    let mut chunk = buf.to_vec();
+                    let descriptors = self.descriptors.lock().unwrap();
+                    self.intervals.lock().unwrap().patch(descriptors.configuration.as_deref().unwrap_or(&[]), &mut chunk);
+                    patched = chunk;
+                    &patched[..]
+                } else {
+                    buf
+                };
                 // this is reset to 0 after every transaction by the hardware, so we must reset it
                 ep_status.set_head_offset(head_offset as u32);
 
@@ -536,13 +1111,24 @@ impl UsbBus for SpinalUsbDevice {
                     descriptor.set_next_desc_and_len(0, buf.len());
                 }
                 // this is required to commit the ep_status record once all the setup is done
-                self.status_write_volatile(ep_addr.index(), ep_status);
+                self.commit_transfer(ep_addr.index(), ep_status);
                 let epcheck = self.status_read_volatile(ep_addr.index());
-                log::trace!("ep0 sanity check: {:?}", epcheck);
-                log::trace!("desc0 sanity check: {:?}", self.descriptor_from_status(&epcheck));
+                module_log!(LogModule::Usb, log::Level::Trace, "ep0 sanity check: {:?}", epcheck);
+                module_log!(LogModule::Usb, log::Level::Trace, "desc0 sanity check: {:?}", self.descriptor_from_status(&epcheck));
 
                 core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
-                log::debug!("ep{} write: {:x?}", ep_addr.index(), &buf);
+                module_log!(LogModule::Usb, log::Level::Debug, "ep{} write: {:x?}", ep_addr.index(), &buf);
+                self.watchdog.lock().unwrap().arm(ep_addr.index(), UsbDirection::In, self.tt.elapsed_ms());
+                if ep_addr.index() == 0 {
+                    self.setup_trace.lock().unwrap().ep0_write(buf.len());
+                    if let Some((desc_type, index)) = *self.desc_capture.lock().unwrap() {
+                        if let Some(capture) = self.descriptors.lock().unwrap().buffer_for(desc_type, index) {
+                            // read back what landed in descriptor memory, so faults in the write path show up
+                            let resident = (0..(buf.len() + 3) / 4).flat_map(|i| descriptor.read_data(i).to_le_bytes());
+                            capture.extend(resident.take(buf.len()));
+                        }
+                    }
+                }
                 Ok(buf.len())
             }
         } else {
@@ -569,7 +1155,7 @@ impl UsbBus for SpinalUsbDevice {
     ///
     /// Implementations may also return other errors if applicable.
     fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
-        log::trace!("read ep{} into buf of len {}", ep_addr.index(), buf.len());
+        module_log!(LogModule::Usb, log::Level::Trace, "read ep{} into buf of len {}", ep_addr.index(), buf.len());
         if let Some((head_offset, _max_len)) = self.ep_allocs[ep_addr.index()] {
             if ep_addr.index() == 0 {
                 if buf.len() == 0 {
@@ -583,8 +1169,10 @@ impl UsbBus for SpinalUsbDevice {
                     return Err(UsbError::BufferOverflow)
                 }
                 // setup data is in a special, fixed location
-                buf[..8].copy_from_slice(&self.get_setup());
-                log::debug!("ep0 read: {:x?}", &buf[..8]);
+                let setup = self.get_setup();
+                buf[..8].copy_from_slice(&setup);
+                self.setup_trace.lock().unwrap().setup(self.tt.elapsed_ms(), &setup);
+                module_log!(LogModule::Usb, log::Level::Debug, "ep0 read: {:x?}", &buf[..8]);
 
                 // this USB core automatically handles address set timing, so we intercept the
                 // address setup packet and jam it here with the "0x200" bit set which triggers
@@ -593,6 +1181,29 @@ impl UsbBus for SpinalUsbDevice {
                     log::info!("USB address set to {} + trigger", buf[2]);
                     self.regs.set_address(0x200 | buf[2] as u32);
                     self.address.store(buf[2] as usize, Ordering::SeqCst);
+                    self.bus_state.lock().unwrap().addressed(buf[2]);
+                }
+                // SET_CONFIGURATION: the endpoints disarmed by the last reset are live again
+                if buf[0] == 0 && buf[1] == 9 {
+                    let out_eps = self.bus_state.lock().unwrap().configured(buf[2]);
+                    for index in (1..NUM_ENDPOINTS).filter(|index| out_eps & (1 << index) != 0) {
+                        if let Some((head_offset, _)) = self.ep_allocs[index] {
+                            let mut ep_status = self.status_read_volatile(index);
+                            ep_status.set_head_offset(head_offset as u32);
+                            self.commit_transfer(index, ep_status);
+                        }
+                    }
+                }
+                // standard device-to-host GET_DESCRIPTOR: capture the response for linting. A
+                // descriptor is re-captured from scratch every time the host asks for it.
+                let mut capture = self.desc_capture.lock().unwrap();
+                if buf[0] == 0x80 && buf[1] == 6 {
+                    *capture = Some((buf[3], buf[2]));
+                    if let Some(desc) = self.descriptors.lock().unwrap().buffer_for(buf[3], buf[2]) {
+                        desc.clear();
+                    }
+                } else {
+                    *capture = None;
                 }
                 Ok(8)
             } else {
@@ -625,7 +1236,7 @@ impl UsbBus for SpinalUsbDevice {
                     // return before side-effecting any structures
                     return Err(UsbError::WouldBlock);
                 }
-                self.status_write_volatile(ep_addr.index(), ep_status);
+                self.commit_transfer(ep_addr.index(), ep_status);
                 let len = descriptor.offset();
                 if buf.len() < len {
                     log::error!("read ep{} would overflow: {} < {}", ep_addr.index(), buf.len(), len);
@@ -645,9 +1256,9 @@ impl UsbBus for SpinalUsbDevice {
                         buf[(len / 4) + i] = word[i]
                     }
                 }
-                log::debug!("read buf: {:x?}", &buf[..len]);
+                module_log!(LogModule::Usb, log::Level::Debug, "read buf: {:x?}", &buf[..len]);
                 descriptor.set_offset(0); // reset the read pointer to 0
-                log::debug!("ep{} read: {:x?} (len {} into buf of {})", ep_addr.index(), &buf[..len], len, buf.len());
+                module_log!(LogModule::Usb, log::Level::Debug, "ep{} read: {:x?} (len {} into buf of {})", ep_addr.index(), &buf[..len], len, buf.len());
                 Ok(len)
             }
         } else {
@@ -660,6 +1271,7 @@ impl UsbBus for SpinalUsbDevice {
         let mut ep0_status = self.status_read_volatile(0);
         ep0_status.set_head_offset(self.ep0_out_offset() as u32);
         self.status_write_volatile(0, ep0_status);
+        self.watchdog.lock().unwrap().arm(0, UsbDirection::Out, self.tt.elapsed_ms());
     }
     /// Sets or clears the STALL condition for an endpoint. If the endpoint is an OUT endpoint, it
     /// should be prepared to receive data again.
@@ -667,7 +1279,10 @@ impl UsbBus for SpinalUsbDevice {
         //if ep_addr.index() == 0 && ep_addr.direction() == UsbDirection::Out && stalled == false {
         //    return;
         //}
-        log::debug!("set_stalled ep{}->{} dir {:?}", ep_addr.index(), stalled, ep_addr.direction());
+        module_log!(LogModule::Usb, log::Level::Debug, "set_stalled ep{}->{} dir {:?}", ep_addr.index(), stalled, ep_addr.direction());
+        if ep_addr.index() == 0 && stalled {
+            self.setup_trace.lock().unwrap().ep0_stall();
+        }
         self.udc_hard_halt(ep_addr.index());
         let mut ep_status = self.status_read_volatile(ep_addr.index());
         match (stalled, ep_addr.direction()) {
@@ -713,11 +1328,12 @@ impl UsbBus for SpinalUsbDevice {
     fn poll(&self) -> PollResult {
         let interrupts = self.regs.interrupts();
         let mut ints_to_clear = UdcInterrupts(0);
-        log::debug!(">>>> frame {}: {:x?}", self.regs.frame_id(), interrupts);
+        module_log!(LogModule::Usb, log::Level::Debug, ">>>> frame {}: {:x?}", self.regs.frame_id(), interrupts);
         let poll_result =
         if interrupts.reset() {
             ints_to_clear.set_reset(true);
-            log::trace!("aft reset: {:x?}", interrupts.0);
+            self.watchdog.lock().unwrap().reset();
+            module_log!(LogModule::Usb, log::Level::Trace, "aft reset: {:x?}", interrupts.0);
             PollResult::Reset
         } else if interrupts.ep0_setup() {
             ints_to_clear.set_ep0_setup(true);
@@ -738,17 +1354,26 @@ impl UsbBus for SpinalUsbDevice {
                             // it will get written back on the next `write`)
                             ep_status.set_head_offset(self.ep0in_head);
                         }
+                        check_poison(&self.poison.lock().unwrap(), self.usb.as_ptr() as *const u32,
+                            bit, &ep_status, "completion");
                         let descriptor = self.descriptor_from_status(&ep_status);
+                        self.ep_stats.lock().unwrap()[bit].record(
+                            descriptor.direction() == UsbDirection::In,
+                            descriptor.offset(),
+                            descriptor.code(),
+                            self.tt.elapsed_ms(),
+                        );
                         if descriptor.direction() == UsbDirection::Out {
                             ep_out |= 1 << bit;
                         } else {
                             ep_in_complete |= 1 << bit;
                         }
                         ints_to_clear.set_endpoint(1 << bit);
+                        self.watchdog.lock().unwrap().complete(bit);
 
                         // full low-level readback
-                        log::debug!("status{}: {:?}", bit, self.status_read_volatile(bit));
-                        log::debug!("desc{}: {:?}", bit, self.descriptor_from_status(&self.status_read_volatile(bit)));
+                        module_log!(LogModule::Usb, log::Level::Debug, "status{}: {:?}", bit, self.status_read_volatile(bit));
+                        module_log!(LogModule::Usb, log::Level::Debug, "desc{}: {:?}", bit, self.descriptor_from_status(&self.status_read_volatile(bit)));
                         break;
                     }
                     bit += 1;
@@ -757,26 +1382,26 @@ impl UsbBus for SpinalUsbDevice {
             PollResult::Data { ep_out, ep_in_complete, ep_setup: 0 }
         } else if interrupts.resume() {
             ints_to_clear.set_reset(true);
-            log::trace!("aft resume: {:x?}", interrupts.0);
+            module_log!(LogModule::Usb, log::Level::Trace, "aft resume: {:x?}", interrupts.0);
             PollResult::Resume
         } else if interrupts.suspend() {
             ints_to_clear.set_suspend(true);
-            log::trace!("aft suspend: {:x?}", interrupts.0);
+            module_log!(LogModule::Usb, log::Level::Trace, "aft suspend: {:x?}", interrupts.0);
             PollResult::Suspend
         } else if interrupts.disconnect() {
             ints_to_clear.set_disconnect(true);
-            log::trace!("aft disconnect: {:x?}", interrupts.0);
+            module_log!(LogModule::Usb, log::Level::Trace, "aft disconnect: {:x?}", interrupts.0);
             PollResult::Reset
         } else {
             PollResult::None
         };
 
-        log::debug!("clearing ints: {:x?}", ints_to_clear);
+        module_log!(LogModule::Usb, log::Level::Debug, "clearing ints: {:x?}", ints_to_clear);
         self.regs.clear_some_interrupts(ints_to_clear);
         if self.regs.interrupts().0 == 0 {
-            log::debug!("all interrupts done");
+            module_log!(LogModule::Usb, log::Level::Debug, "all interrupts done");
         } else {
-            log::debug!("more interrupts to handle: {:x?}", self.regs.interrupts());
+            module_log!(LogModule::Usb, log::Level::Debug, "more interrupts to handle: {:x?}", self.regs.interrupts());
             // re-enter the interrupt handler to handle the next interrupt
             xous::try_send_message(self.conn,
                 xous::Message::new_scalar(Opcode::UsbIrqHandler.to_usize().unwrap(), 0, 0, 0, 0)).ok();
@@ -0,0 +1,207 @@
+use crate::descriptors::{DESC_TYPE_CONFIGURATION, DESC_TYPE_ENDPOINT};
+
+/// bmAttributes transfer type of an interrupt endpoint
+const EP_TYPE_INTERRUPT: u8 = 0b11;
+
+/// Bus speed, which sets how an interrupt endpoint's bInterval is read
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum UsbSpeed {
+    Low,
+    Full,
+    High,
+}
+
+/// the speed the device core runs at; it only does full speed, so that's what's negotiated
+pub(crate) const CORE_SPEED: UsbSpeed = UsbSpeed::Full;
+
+impl UsbSpeed {
+    /// Encodes a polling interval of `frames` as bInterval. At low and full speed bInterval is
+    /// the interval in frames of 1ms, and must be 10-255 and 1-255 respectively. At high speed
+    /// the interval is 2^(bInterval-1) microframes, so `frames` counts microframes of 125us and
+    /// must be a power of two up to 2^15.
+    pub(crate) fn encode_interval(self, frames: usize) -> Option<u8> {
+        match self {
+            UsbSpeed::Low if (10..=255).contains(&frames) => Some(frames as u8),
+            UsbSpeed::Full if (1..=255).contains(&frames) => Some(frames as u8),
+            UsbSpeed::High if frames.is_power_of_two() && frames <= 1 << 15 => {
+                Some(frames.trailing_zeros() as u8 + 1)
+            }
+            _ => None,
+        }
+    }
+    /// the polling interval bInterval stands for, in (micro)frames
+    pub(crate) fn decode_interval(self, interval: u8) -> usize {
+        match self {
+            UsbSpeed::Low | UsbSpeed::Full => interval as usize,
+            UsbSpeed::High => 1 << (interval.clamp(1, 16) - 1),
+        }
+    }
+    /// the intervals `encode_interval` accepts, for usage messages
+    pub(crate) fn interval_range(self) -> &'static str {
+        match self {
+            UsbSpeed::Low => "10-255 frames",
+            UsbSpeed::Full => "1-255 frames",
+            UsbSpeed::High => "a power of two from 1 to 32768 microframes",
+        }
+    }
+}
+
+/// Polling intervals of the interrupt endpoints. The classes fix their intervals when they
+/// allocate endpoints, so a changed interval is applied by rewriting bInterval in the
+/// configuration descriptor on its way to the host; the host only picks it up when it
+/// enumerates the device again.
+pub(crate) struct EpIntervals {
+    /// bInterval each interrupt endpoint was allocated with
+    allocated: [Option<u8>; 16],
+    /// bInterval set with `set()`, replacing the allocated one
+    overrides: [Option<u8>; 16],
+    /// bInterval of each interrupt endpoint in the configuration descriptor last sent
+    described: [Option<u8>; 16],
+}
+
+impl EpIntervals {
+    pub(crate) fn new() -> EpIntervals {
+        EpIntervals { allocated: [None; 16], overrides: [None; 16], described: [None; 16] }
+    }
+    /// records the interval an interrupt endpoint was allocated with
+    pub(crate) fn allocated(&mut self, index: usize, interval: u8) {
+        self.allocated[index] = Some(interval);
+        self.overrides[index] = None;
+    }
+    /// forgets an endpoint that's been freed, along with any override of its interval
+    pub(crate) fn freed(&mut self, index: usize) {
+        self.allocated[index] = None;
+        self.overrides[index] = None;
+    }
+    /// Overrides the interval of interrupt endpoint `index`. Returns `false`, and changes
+    /// nothing, if the endpoint isn't an interrupt endpoint.
+    pub(crate) fn set(&mut self, index: usize, interval: u8) -> bool {
+        if self.allocated[index].is_some() {
+            self.overrides[index] = Some(interval);
+            true
+        } else {
+            false
+        }
+    }
+    /// the interval of endpoint `index` as described to the host, if it's an interrupt endpoint
+    pub(crate) fn active(&self, index: usize) -> Option<u8> {
+        self.overrides[index].or(self.allocated[index])
+    }
+    /// `(index, interval)` of every interrupt endpoint
+    pub(crate) fn all(&self) -> Vec<(usize, u8)> {
+        (0..16).filter_map(|i| self.active(i).map(|interval| (i, interval))).collect()
+    }
+    /// `(index, interval)` of every interrupt endpoint, as read back from the configuration
+    /// descriptor the host was last sent
+    pub(crate) fn described(&self) -> Vec<(usize, u8)> {
+        (0..16).filter_map(|i| self.described[i].map(|interval| (i, interval))).collect()
+    }
+    /// Rewrites bInterval of the overridden endpoints in `chunk`, the next packet of a
+    /// configuration descriptor of which `sent` has already gone out, and records the
+    /// intervals that go out. Endpoint descriptors may straddle packets, so the descriptors are
+    /// walked from the start each time.
+    pub(crate) fn patch(&mut self, sent: &[u8], chunk: &mut [u8]) {
+        let base = sent.len();
+        let end = base + chunk.len();
+        let byte = |chunk: &[u8], i: usize| -> Option<u8> {
+            if i < base { sent.get(i).copied() } else { chunk.get(i - base).copied() }
+        };
+        if byte(chunk, 1) != Some(DESC_TYPE_CONFIGURATION) {
+            return;
+        }
+        if base == 0 {
+            self.described = [None; 16];
+        }
+        let mut offset = 0;
+        while offset < end {
+            let length = match byte(chunk, offset) {
+                Some(0) | None => break,
+                Some(l) => l as usize,
+            };
+            let interval_at = offset + 6;
+            if interval_at >= base && interval_at < end
+                && byte(chunk, offset + 1) == Some(DESC_TYPE_ENDPOINT)
+                && byte(chunk, offset + 3).map_or(false, |attr| attr & 0b11 == EP_TYPE_INTERRUPT)
+            {
+                let index = byte(chunk, offset + 2).unwrap_or(0) as usize & 0xF;
+                if let Some(interval) = self.overrides[index] {
+                    chunk[interval_at - base] = interval;
+                }
+                self.described[index] = Some(chunk[interval_at - base]);
+            }
+            offset += length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptors::validate_configuration;
+
+    /// configuration with one interface: an interrupt IN endpoint 1 at 10ms, and a bulk OUT
+    /// endpoint 2
+    fn configuration() -> Vec<u8> {
+        let mut c = vec![9, DESC_TYPE_CONFIGURATION, 32, 0, 1, 1, 0, 0x80, 50];
+        c.extend_from_slice(&[9, 4, 0, 0, 2, 0xFF, 0, 0, 0]);
+        c.extend_from_slice(&[7, DESC_TYPE_ENDPOINT, 0x81, 0x03, 8, 0, 10]);
+        c.extend_from_slice(&[7, DESC_TYPE_ENDPOINT, 0x02, 0x02, 64, 0, 0]);
+        c
+    }
+    /// sends the configuration through `patch` in packets of `packet` bytes
+    fn send(intervals: &mut EpIntervals, packet: usize) -> Vec<u8> {
+        let mut sent = Vec::new();
+        for chunk in configuration().chunks(packet) {
+            let mut chunk = chunk.to_vec();
+            intervals.patch(&sent, &mut chunk);
+            sent.extend_from_slice(&chunk);
+        }
+        sent
+    }
+
+    #[test]
+    fn test_interval_encoding() {
+        assert_eq!(UsbSpeed::Full.encode_interval(0), None);
+        assert_eq!(UsbSpeed::Full.encode_interval(1), Some(1));
+        assert_eq!(UsbSpeed::Full.encode_interval(255), Some(255));
+        assert_eq!(UsbSpeed::Full.encode_interval(256), None);
+        assert_eq!(UsbSpeed::Low.encode_interval(9), None);
+        assert_eq!(UsbSpeed::Low.encode_interval(10), Some(10));
+        assert_eq!(UsbSpeed::High.encode_interval(1), Some(1));
+        assert_eq!(UsbSpeed::High.encode_interval(8), Some(4));
+        assert_eq!(UsbSpeed::High.encode_interval(12), None);
+        assert_eq!(UsbSpeed::High.encode_interval(1 << 15), Some(16));
+        assert_eq!(UsbSpeed::High.encode_interval(1 << 16), None);
+        assert_eq!(UsbSpeed::High.decode_interval(4), 8);
+        assert_eq!(UsbSpeed::Full.decode_interval(4), 4);
+
+        let mut intervals = EpIntervals::new();
+        intervals.allocated(1, 10);
+        // only interrupt endpoints take an interval
+        assert!(!intervals.set(2, 4));
+        // with no overrides, the descriptor goes out as assembled
+        assert_eq!(send(&mut intervals, 64), configuration());
+        assert_eq!(intervals.described(), vec![(1, 10)]);
+
+        assert!(intervals.set(1, CORE_SPEED.encode_interval(4).unwrap()));
+        assert_eq!(intervals.all(), vec![(1, 4)]);
+        // bInterval lands at the same place however the descriptor is split into packets,
+        // including when the endpoint descriptor straddles two packets
+        for &packet in [64, 8, 16, 27, 1].iter() {
+            let patched = send(&mut intervals, packet);
+            assert_eq!(patched[24], 4, "packet size {}", packet);
+            let mut expected = configuration();
+            expected[24] = 4;
+            assert_eq!(patched, expected);
+            assert!(validate_configuration(&patched).is_empty());
+            assert_eq!(intervals.described(), vec![(1, 4)]);
+        }
+
+        // re-allocating the endpoint drops the override
+        intervals.allocated(1, 10);
+        assert_eq!(intervals.active(1), Some(10));
+        assert_eq!(send(&mut intervals, 8), configuration());
+        assert_eq!(intervals.described(), vec![(1, 10)]);
+    }
+}
@@ -1,6 +1,7 @@
 use utralib::generated::*;
 use keyboard::{RowCol, KeyRawStates, ScanCode};
 use crate::api::*;
+use crate::loglevel::LogModule;
 use ticktimer_server::Ticktimer;
 use xous::CID;
 use num_traits::ToPrimitive;
@@ -172,8 +173,8 @@ impl Keyboard {
         // EV_PENDING_KEYPRESSED effectively does an XOR of the previous keyboard state
         // to the current state, which is why update() does not repeatedly issue results
         // for keys that are pressed & held.
-        log::trace!("update new_state:  {:?}", self.new_state);
-        log::trace!("update last_state: {:?}", self.last_state);
+        module_log!(LogModule::Kbd, log::Level::Trace, "update new_state:  {:?}", self.new_state);
+        module_log!(LogModule::Kbd, log::Level::Trace, "update last_state: {:?}", self.last_state);
 
         let mut krs = KeyRawStates::new();
 
@@ -193,10 +194,25 @@ impl Keyboard {
             self.last_state.insert(rc);
         }
 
-        log::trace!("krs: {:?}", krs);
+        module_log!(LogModule::Kbd, log::Level::Trace, "krs: {:?}", krs);
         krs
     }
 
+    /// returns every (position, scancode) pair in the active key map, in matrix scan order
+    pub(crate) fn layout(&self) -> Vec<(RowCol, ScanCode)> {
+        let mut keys = Vec::new();
+        for r in 0..KBD_ROWS {
+            for c in 0..KBD_COLS {
+                let rc = RowCol{r: r as _, c: c as _};
+                let code = map_qwerty(rc);
+                if code.key.is_some() {
+                    keys.push((rc, code));
+                }
+            }
+        }
+        keys
+    }
+
     pub(crate) fn track_keys(&mut self, krs: &KeyRawStates) -> Vec<char> {
         /*
           "conventional" keyboard algorithm. The goals of this are to differentiate
@@ -0,0 +1,201 @@
+use keyboard::{RowCol, ScanCode};
+
+/// Result codes for the keyboard self-test, as returned by `Opcode::KbdTestResult`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub(crate) enum KbdTestState {
+    NotRun = 0,
+    Running = 1,
+    Pass = 2,
+    Fail = 3,
+}
+
+/// A key that was pressed when a different key was prompted for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Mismatch {
+    pub(crate) expected: RowCol,
+    pub(crate) observed: RowCol,
+}
+
+/// Guided keyboard matrix test. The test prompts for every key in the active layout, in order,
+/// and marks each key off as its scancode is observed. A key that shows up when a different key
+/// was prompted for is recorded as a mismatch, as is any scancode that isn't in the layout at all.
+pub(crate) struct KbdTest {
+    /// every key in the layout, in prompt order
+    expected: Vec<(RowCol, ScanCode)>,
+    /// keys that have yet to be observed, in prompt order
+    remaining: Vec<(RowCol, ScanCode)>,
+    mismatches: Vec<Mismatch>,
+    /// scancodes that were observed but do not map to any key in the layout
+    unmapped: Vec<RowCol>,
+    aborted: bool,
+}
+
+impl KbdTest {
+    /// `layout` is the list of every (position, scancode) pair in the active key map; see
+    /// `Keyboard::layout()`.
+    pub(crate) fn new(layout: Vec<(RowCol, ScanCode)>) -> KbdTest {
+        KbdTest {
+            remaining: layout.clone(),
+            expected: layout,
+            mismatches: Vec::new(),
+            unmapped: Vec::new(),
+            aborted: false,
+        }
+    }
+    /// labels for every key in the layout, in prompt order
+    pub(crate) fn expected_labels(&self) -> Vec<std::string::String> {
+        self.expected.iter().map(|(_, sc)| key_label(sc)).collect()
+    }
+    /// the key the operator should press next
+    pub(crate) fn prompt(&self) -> Option<(RowCol, ScanCode)> {
+        self.remaining.first().copied()
+    }
+    pub(crate) fn remaining(&self) -> usize {
+        self.remaining.len()
+    }
+    pub(crate) fn total(&self) -> usize {
+        self.expected.len()
+    }
+    pub(crate) fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches
+    }
+    pub(crate) fn unmapped(&self) -> &[RowCol] {
+        &self.unmapped
+    }
+    /// the number of faults found so far
+    pub(crate) fn faults(&self) -> usize {
+        self.mismatches.len() + self.unmapped.len()
+    }
+    /// Records a keydown at `rc`. Returns `true` if the observed key was the one prompted for.
+    pub(crate) fn observe(&mut self, rc: RowCol) -> bool {
+        if !self.expected.iter().any(|&(k, _)| k == rc) {
+            self.unmapped.push(rc);
+            return false;
+        }
+        let prompted = self.prompt();
+        if let Some(index) = self.remaining.iter().position(|&(k, _)| k == rc) {
+            self.remaining.remove(index);
+        }
+        match prompted {
+            Some((expected, _)) if expected == rc => true,
+            Some((expected, _)) => {
+                self.mismatches.push(Mismatch { expected, observed: rc });
+                false
+            }
+            // test is already done, extra presses are harmless
+            None => true,
+        }
+    }
+    /// stops the test early, e.g. on Ctrl-C
+    pub(crate) fn abort(&mut self) {
+        self.aborted = true;
+    }
+    pub(crate) fn is_done(&self) -> bool {
+        self.aborted || self.remaining.is_empty()
+    }
+    pub(crate) fn state(&self) -> KbdTestState {
+        if !self.is_done() {
+            KbdTestState::Running
+        } else if self.remaining.is_empty() && self.faults() == 0 {
+            KbdTestState::Pass
+        } else {
+            KbdTestState::Fail
+        }
+    }
+    pub(crate) fn log_prompt(&self) {
+        if let Some((rc, sc)) = self.prompt() {
+            log::info!("kbdtest: press '{}' ({},{})", key_label(&sc), rc.r, rc.c);
+        }
+    }
+    pub(crate) fn log_summary(&self) {
+        for m in self.mismatches().iter() {
+            let expected = self.scancode(m.expected).map(|sc| key_label(&sc)).unwrap_or_default();
+            let observed = self.scancode(m.observed).map(|sc| key_label(&sc)).unwrap_or_default();
+            log::info!("kbdtest: expected '{}' ({},{}) but got '{}' ({},{})",
+                expected, m.expected.r, m.expected.c, observed, m.observed.r, m.observed.c);
+        }
+        for rc in self.unmapped().iter() {
+            log::info!("kbdtest: scancode ({},{}) is not in the layout", rc.r, rc.c);
+        }
+        if self.remaining.len() > 0 {
+            let missing: Vec<std::string::String> = self.remaining.iter().map(|(_, sc)| key_label(sc)).collect();
+            log::info!("kbdtest: never saw: {}", missing.join(" "));
+        }
+        match self.state() {
+            KbdTestState::Pass => log::info!("kbdtest: PASS, all {} keys seen", self.total()),
+            _ => log::info!("kbdtest: FAIL, {} of {} keys seen, {} faults{}",
+                self.total() - self.remaining(), self.total(), self.faults(),
+                if self.aborted { " (aborted)" } else { "" }),
+        }
+    }
+    /// the scancode the layout assigns to `rc`, if any
+    pub(crate) fn scancode(&self, rc: RowCol) -> Option<ScanCode> {
+        self.expected.iter().find(|&&(k, _)| k == rc).map(|&(_, sc)| sc)
+    }
+}
+
+/// Human-readable name for a key, used when prompting the operator.
+pub(crate) fn key_label(sc: &ScanCode) -> std::string::String {
+    match sc.key {
+        Some('\u{0008}') => "bksp".to_string(),
+        Some('\u{000d}') => "enter".to_string(),
+        Some('\u{000e}') => "sym".to_string(),
+        Some('\u{000f}') => "shift".to_string(),
+        Some('\u{0011}') => "F1".to_string(),
+        Some('\u{0012}') => "F2".to_string(),
+        Some('\u{0013}') => "F3".to_string(),
+        Some('\u{0014}') => "F4".to_string(),
+        Some(' ') => "space".to_string(),
+        Some(c) => c.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> Vec<(RowCol, ScanCode)> {
+        vec![
+            (RowCol::new(0, 0), ScanCode { key: Some('1'), shift: None, hold: None, alt: None }),
+            (RowCol::new(1, 0), ScanCode { key: Some('q'), shift: None, hold: None, alt: None }),
+            (RowCol::new(1, 1), ScanCode { key: Some('w'), shift: None, hold: None, alt: None }),
+        ]
+    }
+
+    #[test]
+    fn test_kbdtest_pass() {
+        let mut t = KbdTest::new(layout());
+        assert_eq!(t.state(), KbdTestState::Running);
+        assert!(t.observe(RowCol::new(0, 0)));
+        assert_eq!(t.remaining(), 2);
+        assert!(t.observe(RowCol::new(1, 0)));
+        assert!(t.observe(RowCol::new(1, 1)));
+        assert!(t.is_done());
+        assert_eq!(t.state(), KbdTestState::Pass);
+    }
+
+    #[test]
+    fn test_kbdtest_faults() {
+        let mut t = KbdTest::new(layout());
+        // prompted for '1', but 'w' showed up
+        assert!(!t.observe(RowCol::new(1, 1)));
+        assert_eq!(t.mismatches(), &[Mismatch { expected: RowCol::new(0, 0), observed: RowCol::new(1, 1) }]);
+        assert_eq!(t.remaining(), 2);
+        // a position that isn't in the layout at all
+        assert!(!t.observe(RowCol::new(7, 7)));
+        assert_eq!(t.unmapped(), &[RowCol::new(7, 7)]);
+        assert!(t.observe(RowCol::new(0, 0)));
+        assert!(t.observe(RowCol::new(1, 0)));
+        assert_eq!(t.state(), KbdTestState::Fail);
+    }
+
+    #[test]
+    fn test_kbdtest_abort() {
+        let mut t = KbdTest::new(layout());
+        t.observe(RowCol::new(0, 0));
+        t.abort();
+        assert!(t.is_done());
+        assert_eq!(t.state(), KbdTestState::Fail);
+    }
+}
@@ -0,0 +1,112 @@
+use xous::{CID, Message};
+
+/// modifier bit set in the forwarded key event when shift was applied to the key
+pub(crate) const MOD_SHIFT: usize = 0x1;
+
+pub(crate) struct KeyListener {
+    pub(crate) name: String,
+    pub(crate) cid: CID,
+    pub(crate) opcode: usize,
+}
+
+/// Servers that have asked to receive the key events decoded by usb-test's keyboard.
+///
+/// Every event is forwarded as a non-blocking scalar message of `(char, modifiers, pressed, 0)`
+/// to each listener, in registration order. A listener whose send fails is dropped from the list.
+pub(crate) struct KeyListeners {
+    listeners: Vec<KeyListener>,
+}
+
+impl KeyListeners {
+    pub(crate) fn new() -> KeyListeners {
+        KeyListeners { listeners: Vec::new() }
+    }
+    pub(crate) fn register(&mut self, name: &str, cid: CID, opcode: usize) {
+        log::info!("registering key listener {} with opcode {}", name, opcode);
+        self.listeners.push(KeyListener { name: name.to_string(), cid, opcode });
+    }
+    pub(crate) fn iter(&self) -> core::slice::Iter<KeyListener> {
+        self.listeners.iter()
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.listeners.len()
+    }
+    /// forwards a key event to every registered listener
+    pub(crate) fn forward(&mut self, key: char, modifiers: usize, pressed: bool) {
+        self.forward_with(key, modifiers, pressed, |cid, msg| {
+            xous::send_message(cid, msg).map(|_| ())
+        });
+    }
+    /// the guts of `forward()`, with the message transport broken out so it can be substituted in tests
+    pub(crate) fn forward_with<F>(&mut self, key: char, modifiers: usize, pressed: bool, mut send: F)
+    where F: FnMut(CID, Message) -> Result<(), xous::Error>
+    {
+        self.listeners.retain(|l| {
+            let msg = Message::new_scalar(
+                l.opcode,
+                key as u32 as usize,
+                modifiers,
+                if pressed { 1 } else { 0 },
+                0,
+            );
+            match send(l.cid, msg) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::warn!("key listener {} failed ({:?}), deregistering", l.name, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_delivery_order() {
+        let mut listeners = KeyListeners::new();
+        listeners.register("first", 1, 10);
+        listeners.register("second", 2, 20);
+
+        // a dummy listener transport that records everything it is handed
+        let mut delivered = Vec::<(CID, usize, usize, usize, usize)>::new();
+        for &(key, pressed) in [('a', true), ('a', false), ('B', true)].iter() {
+            let mods = if key.is_uppercase() { MOD_SHIFT } else { 0 };
+            listeners.forward_with(key, mods, pressed, |cid, msg| {
+                if let Message::Scalar(s) = msg {
+                    delivered.push((cid, s.id, s.arg1, s.arg2, s.arg3));
+                }
+                Ok(())
+            });
+        }
+        assert_eq!(delivered, vec![
+            (1, 10, 'a' as usize, 0, 1),
+            (2, 20, 'a' as usize, 0, 1),
+            (1, 10, 'a' as usize, 0, 0),
+            (2, 20, 'a' as usize, 0, 0),
+            (1, 10, 'B' as usize, MOD_SHIFT, 1),
+            (2, 20, 'B' as usize, MOD_SHIFT, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_listener_deregistration() {
+        let mut listeners = KeyListeners::new();
+        listeners.register("gone", 1, 10);
+        listeners.register("alive", 2, 20);
+        let mut delivered = Vec::<CID>::new();
+        listeners.forward_with('x', 0, true, |cid, _msg| {
+            if cid == 1 {
+                Err(xous::Error::ServerNotFound)
+            } else {
+                delivered.push(cid);
+                Ok(())
+            }
+        });
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners.iter().next().unwrap().name, "alive");
+        assert_eq!(delivered, vec![2]);
+    }
+}
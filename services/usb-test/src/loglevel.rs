@@ -0,0 +1,131 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{Level, LevelFilter};
+
+/// `LevelFilter`s by their `as usize` value
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+/// a module level that isn't set, so the module follows the default level
+const UNSET: usize = usize::MAX;
+
+/// level of everything that isn't in a `LogModule`, and of the modules with no level of their own
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+static MODULE_LEVELS: [AtomicUsize; 3] = [AtomicUsize::new(UNSET), AtomicUsize::new(UNSET), AtomicUsize::new(UNSET)];
+
+/// A part of usb-test whose logging can be turned up or down on its own, with `loglevel`.
+///
+/// The log facade only has a single, global level. It's set to the most verbose of the default
+/// and the module levels, so each module checks its own level before logging; see `module_log!`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LogModule {
+    /// the device core driver, including the ep0 state machine
+    Usb,
+    /// the keyboard, and the key events it reports
+    Kbd,
+    /// the live SETUP packet trace turned on with `setuptrace on`
+    Setup,
+}
+
+impl LogModule {
+    pub(crate) const ALL: [LogModule; 3] = [LogModule::Usb, LogModule::Kbd, LogModule::Setup];
+
+    pub(crate) fn parse(name: &str) -> Option<LogModule> {
+        LogModule::ALL.iter().copied().find(|m| m.name() == name)
+    }
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LogModule::Usb => "usb",
+            LogModule::Kbd => "kbd",
+            LogModule::Setup => "setup",
+        }
+    }
+    /// the module's own level, if it has one
+    pub(crate) fn own_level(self) -> Option<LevelFilter> {
+        LEVELS.get(MODULE_LEVELS[self as usize].load(Ordering::Relaxed)).copied()
+    }
+    /// Sets the module's own level, or with `None`, has it follow the default level again
+    pub(crate) fn set_level(self, level: Option<LevelFilter>) {
+        MODULE_LEVELS[self as usize].store(level.map_or(UNSET, |l| l as usize), Ordering::Relaxed);
+        apply_log_levels();
+    }
+    pub(crate) fn level(self) -> LevelFilter {
+        self.own_level().unwrap_or_else(default_log_level)
+    }
+    /// `true` if the module logs messages of `level`
+    pub(crate) fn enabled(self, level: Level) -> bool {
+        level <= self.level()
+    }
+}
+
+/// Parses a level as given to `loglevel`: `off`, `error`, `warn`, `info`, `debug` or `trace`
+pub(crate) fn parse_log_level(s: &str) -> Option<LevelFilter> {
+    s.parse::<LevelFilter>().ok()
+}
+pub(crate) fn default_log_level() -> LevelFilter {
+    LEVELS[DEFAULT_LEVEL.load(Ordering::Relaxed)]
+}
+pub(crate) fn set_default_log_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as usize, Ordering::Relaxed);
+    apply_log_levels();
+}
+/// the level the log facade has to let through for every module to get its own
+pub(crate) fn facade_log_level() -> LevelFilter {
+    LogModule::ALL.iter().filter_map(|m| m.own_level()).fold(default_log_level(), |a, b| a.max(b))
+}
+fn apply_log_levels() {
+    log::set_max_level(facade_log_level());
+}
+
+/// Logs at `$level` if `$module` is enabled for it, e.g.
+/// `module_log!(LogModule::Usb, log::Level::Debug, "ep{} write", index)`
+macro_rules! module_log {
+    ($module:expr, $level:expr, $($arg:tt)+) => {
+        if $module.enabled($level) {
+            log::log!($level, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_levels() {
+        assert_eq!(parse_log_level("trace"), Some(LevelFilter::Trace));
+        assert_eq!(parse_log_level("off"), Some(LevelFilter::Off));
+        assert_eq!(parse_log_level("loud"), None);
+        assert_eq!(LogModule::parse("setup"), Some(LogModule::Setup));
+        assert_eq!(LogModule::parse("ep0"), None);
+
+        assert_eq!(default_log_level(), LevelFilter::Info);
+        assert!(LogModule::Usb.enabled(Level::Info));
+        assert!(!LogModule::Usb.enabled(Level::Debug));
+
+        // turning one module up lets its messages through the facade, but not the others'
+        LogModule::Usb.set_level(Some(LevelFilter::Trace));
+        assert_eq!(facade_log_level(), LevelFilter::Trace);
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        assert!(LogModule::Usb.enabled(Level::Trace));
+        assert!(!LogModule::Kbd.enabled(Level::Debug));
+
+        // a module can also be quieter than the default
+        LogModule::Setup.set_level(Some(LevelFilter::Warn));
+        assert!(!LogModule::Setup.enabled(Level::Info));
+        set_default_log_level(LevelFilter::Debug);
+        assert!(LogModule::Kbd.enabled(Level::Debug));
+        assert!(!LogModule::Setup.enabled(Level::Info));
+
+        for m in LogModule::ALL.iter() {
+            m.set_level(None);
+        }
+        set_default_log_level(LevelFilter::Info);
+        assert_eq!(facade_log_level(), LevelFilter::Info);
+        assert_eq!(log::max_level(), LevelFilter::Info);
+    }
+}
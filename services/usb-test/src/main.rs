@@ -4,6 +4,76 @@
 mod api;
 
 use api::*;
+#[macro_use]
+mod loglevel;
+use loglevel::*;
+mod kbdtest;
+use kbdtest::*;
+mod listeners;
+use listeners::*;
+mod descriptors;
+use descriptors::*;
+mod descdump;
+use descdump::*;
+mod pattern;
+use pattern::*;
+mod watchdog;
+use watchdog::*;
+mod epstats;
+use epstats::*;
+mod error;
+mod freehist;
+use freehist::*;
+mod allocstats;
+use allocstats::*;
+mod timeline;
+use timeline::*;
+mod poison;
+use poison::*;
+mod nak;
+use nak::*;
+mod dfu;
+use dfu::*;
+mod fido;
+use fido::*;
+mod cdc;
+use cdc::*;
+mod msc;
+use msc::*;
+mod devclass;
+use devclass::*;
+mod interval;
+use interval::*;
+mod setup;
+use setup::*;
+mod regsnap;
+use regsnap::*;
+mod diag;
+use diag::*;
+mod quiesce;
+use quiesce::*;
+mod resetbench;
+use resetbench::*;
+mod busstate;
+use busstate::*;
+mod vbus;
+use vbus::*;
+mod regtrap;
+use regtrap::*;
+mod history;
+use history::*;
+mod repeat;
+use repeat::*;
+mod progress;
+use progress::*;
+mod memtest;
+use memtest::*;
+mod alloctest;
+use alloctest::*;
+mod commands;
+use commands::*;
+mod aliases;
+use aliases::*;
 #[cfg(any(feature="precursor", feature="renode"))]
 mod kbd;
 #[cfg(any(feature="precursor", feature="renode"))]
@@ -22,7 +92,7 @@ use hosted::*;
 
 
 use num_traits::*;
-use xous::{CID, msg_scalar_unpack, Message, send_message};
+use xous::{CID, msg_scalar_unpack, msg_blocking_scalar_unpack, Message, send_message};
 use std::collections::BTreeMap;
 
 use usb_device::prelude::*;
@@ -54,23 +124,35 @@ impl Clock for EmbeddedClock {
 fn main() -> ! {
     let _gpio_base = crate::log_init();
     log_server::init_wait().unwrap();
-    log::set_max_level(log::LevelFilter::Info);
+    set_default_log_level(log::LevelFilter::Info);
     log::info!("my PID is {}", xous::process::id());
 
     let xns = xous_names::XousNames::new().unwrap();
     let usbdev_sid = xns.register_name(api::SERVER_NAME_USBTEST, None).expect("can't register server");
     log::trace!("registered with NS -- {:?}", usbdev_sid);
 
-    let usbdev = SpinalUsbDevice::new(usbdev_sid);
+    let usbdev = SpinalUsbDevice::new(usbdev_sid, AllocatorConfig::default());
+    #[cfg(feature = "alloc-sorted")]
+    let usbdev = {
+        let mut usbdev = usbdev;
+        // reproducible endpoint buffer layout, for comparing register dumps across builds
+        usbdev.set_alloc_mode(AllocMode::Sorted);
+        usbdev
+    };
     let mut usbmgmt = usbdev.get_iface();
     let mut kbd = kbd::Keyboard::new(usbdev_sid);
     let tt = ticktimer_server::Ticktimer::new().unwrap();
     log::info!("connecting device core");
-    usbmgmt.connect_device_core(true);
+    match usbmgmt.connect_device_core(true) {
+        Ok(_) => log::info!("devcore connected"),
+        Err(e) => log::error!("couldn't connect device core: {:?}", e),
+    }
     tt.sleep_ms(500).unwrap();
-    log::info!("devcore connected");
 
     log::trace!("ready to accept requests");
+    // there's no UART on the host, so take commands from the terminal instead
+    #[cfg(not(target_os = "xous"))]
+    spawn_stdin_reader(usbdev_sid);
 
     std::thread::spawn({
         move || {
@@ -82,6 +164,18 @@ fn main() -> ! {
         }
     });
 
+    std::thread::spawn({
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            let cid = xous::connect(usbdev_sid).unwrap();
+            loop {
+                tt.sleep_ms(WATCHDOG_TICK_MS).unwrap();
+                xous::try_send_message(cid,
+                    Message::new_scalar(Opcode::WatchdogTick.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+            }
+        }
+    });
+
     // register a suspend/resume listener
     let cid = xous::connect(usbdev_sid).expect("couldn't create suspend callback connection");
     let mut susres = susres::Susres::new(
@@ -91,32 +185,165 @@ fn main() -> ! {
         cid
     ).expect("couldn't create suspend/resume object");
 
-    let usb_alloc = UsbBusAllocator::new(usbdev);
-    let clock = EmbeddedClock::new();
-    let mut keyboard = UsbHidClassBuilder::new()
-        .add_interface(
-            NKROBootKeyboardInterface::default_config(&clock),
-        )
-        .build(&usb_alloc);
-    let mut usb_dev = UsbDeviceBuilder::new(&usb_alloc, UsbVidPid(0x1209, 0x3613))
-        .manufacturer("usbd-human-interface-device")
-        .product("NKRO Keyboard")
-        .serial_number("PRECURSOR")
-        .build();
+    // the functions are built again on a new allocator when the layout changes; see `relayout`
+    let clock: &'static EmbeddedClock = Box::leak(Box::new(EmbeddedClock::new()));
+    let keyboard = |alloc: &'static UsbBusAllocator<SpinalUsbDevice>| {
+        UsbHidClassBuilder::new()
+            .add_interface(
+                NKROBootKeyboardInterface::default_config(clock),
+            )
+            .build(alloc)
+    };
+    let usb_alloc: &'static UsbBusAllocator<SpinalUsbDevice> = Box::leak(Box::new(UsbBusAllocator::new(usbdev)));
+    let mut device_class = ClassSelect::default();
+    // DFU and FIDO are only included once enabled with the `dfu` and `fido` commands
+    let mut usb = UsbFunctions::build(usb_alloc, UsbLayout::default(), keyboard);
+    let mut usb_dev = build_device(usb_alloc);
+    let mut vbus = VbusDebounce::new(usb_dev.bus().vbus_present(), VBUS_DEBOUNCE_MS);
+    let mut vbus_listeners = VbusListeners::new();
+    // whether the pullup follows VBUS, set with `autoconn`
+    let mut autoconn = false;
 
     let mut cmdline = String::new();
+    // the most recent keyboard self-test; retained after it finishes so the result can be queried
+    let mut kbdtest: Option<KbdTest> = None;
+    let mut key_listeners = KeyListeners::new();
+    // baseline for `regs diff`
+    let mut reg_baseline: Option<RegSnapshot> = None;
+    let mut diag = DiagCounters::new();
+    let mut power_order = PowerOrder::default();
+    // the run of forced bus resets started by the `reset` command, if one is going
+    let mut reset_bench: Option<ResetBench> = None;
+    let mut reset_progress = Progress::new("reset", false);
+    let mut kbdtest_progress = Progress::new("kbdtest", false);
+    // the command being run by `repeat`, if one is going
+    let mut repeat: Option<RepeatRun> = None;
+    let mut aliases = Aliases::new();
+    // command lines typed at the shell, for `history` and `!n`
+    let mut history = CommandHistory::new();
+    // the descriptor RAM test started by `memtest`, if one is going
+    let mut memtest: Option<MemTest> = None;
+    let mut memtest_progress = Progress::new("memtest", false);
+    // the allocator test started by `alloctest`, if one is going
+    let mut alloctest: Option<AllocTest> = None;
+    let mut alloctest_progress = Progress::new("alloctest", false);
+    // whether the host had configured the device as of the last poll
+    let mut was_configured = false;
+    // start and end of each shell command, and the keys sent to the host, for `timeline`
+    let mut shell_events = EventRing::new();
+    let mut key_events = EventRing::new();
     loop {
-        let msg = xous::receive_message(usbdev_sid).unwrap();
-        match FromPrimitive::from_usize(msg.body.id()) {
+        let mut msg = xous::receive_message(usbdev_sid).unwrap();
+        let opcode: Option<Opcode> = FromPrimitive::from_usize(msg.body.id());
+        if let Some(op) = &opcode {
+            diag.check_scalar(op, &msg.body);
+        }
+        match opcode {
             Some(Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
-                kbd.suspend();
-                usbmgmt.xous_suspend();
+                let start = tt.elapsed_ms();
+                for subsystem in power_order.suspend.iter() {
+                    match subsystem {
+                        Subsystem::Keyboard => kbd.suspend(),
+                        Subsystem::Usb => usbmgmt.xous_suspend(),
+                    }
+                }
+                // the shell's state rides out the suspend in a snapshot, restored below
+                let snapshot = ShellSnapshot::capture(&history, &aliases);
+                if snapshot.dropped > 0 {
+                    log::warn!("suspend: {} history entries didn't fit in the snapshot", snapshot.dropped);
+                }
+                let suspended = tt.elapsed_ms();
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
-                kbd.resume();
-                usbmgmt.xous_resume();
+                let woke = tt.elapsed_ms();
+                match snapshot.restore() {
+                    Some((restored_history, restored_aliases)) => {
+                        history = restored_history;
+                        aliases = restored_aliases;
+                    }
+                    None => log::error!("suspend: shell snapshot of {} bytes was damaged, history and aliases kept as they were", snapshot.len()),
+                }
+                for subsystem in power_order.resume.iter() {
+                    match subsystem {
+                        Subsystem::Keyboard => kbd.resume(),
+                        Subsystem::Usb => usbmgmt.xous_resume(),
+                    }
+                }
+                log::info!("suspend: {}ms to suspend, {}ms suspended, {}ms to resume",
+                    suspended - start, woke - suspended, tt.elapsed_ms() - woke);
             }),
+            Some(Opcode::GetStatus) => {
+                let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut status = usbmgmt.status();
+                status.dfu = usb.dfu_status();
+                status.fido = usb.fido_status();
+                status.vbus = vbus.present();
+                status.vbus_attaches = vbus.attaches;
+                status.vbus_detaches = vbus.detaches;
+                buffer.replace(status).expect("couldn't return status");
+            }
+            Some(Opcode::WatchdogTick) => {
+                if let Some(present) = vbus.sample(usb_dev.bus().vbus_present(), tt.elapsed_ms()) {
+                    log::info!("vbus: {}", if present { "attached" } else { "detached" });
+                    vbus_listeners.notify(present);
+                    if autoconn {
+                        usbmgmt.set_attached(present);
+                        log::info!("autoconn: pullup {}", if present { "on" } else { "off" });
+                    }
+                }
+                usbmgmt.watchdog_check(tt.elapsed_ms());
+                usbmgmt.nak_check(tt.elapsed_ms());
+                if let Some(dfu) = usb.dfu.as_mut() {
+                    dfu.tick(tt.elapsed_ms());
+                    if dfu.state.take_reenumerate() {
+                        log::info!("dfu: detaching");
+                        reenumerate(&mut usbmgmt, &tt);
+                    }
+                }
+                if let Some(bench) = reset_bench.as_mut() {
+                    if bench.check_timeout(tt.elapsed_ms()) {
+                        log::info!("reset: the host didn't configure the device within {}ms", RESET_TIMEOUT_MS);
+                        let (done, total) = bench.progress();
+                        reset_progress.update(tt.elapsed_ms(), done, total);
+                    }
+                    if bench.wants_reset() {
+                        reset_cycle(bench, &mut usbmgmt, &tt);
+                    } else if bench.is_done() {
+                        log_reset_stats(bench);
+                        reset_bench = None;
+                    }
+                }
+            }
             Some(Opcode::UsbIrqHandler) => {
-                if usb_dev.poll(&mut [&mut keyboard]) {
+                if let Some(dfu) = usb.dfu.as_mut() {
+                    dfu.set_now(tt.elapsed_ms());
+                }
+                let polled = usb_dev.poll(&mut usb.classes());
+                let configured = usb_dev.state() == UsbDeviceState::Configured;
+                if configured && !was_configured {
+                    log::info!("class: configured as {} ({})", device_class.active().name(), device_class.active().description());
+                    // read back what the host was actually told
+                    for (ep, interval) in usbmgmt.described_intervals() {
+                        log::info!("interval: ep{} enumerated with bInterval {}, polled every {} frames",
+                            ep, interval, CORE_SPEED.decode_interval(interval));
+                    }
+                }
+                was_configured = configured;
+                // the poll has already reset the active class; this just reports it
+                if let Some(from) = usbmgmt.take_bus_reset() {
+                    match from {
+                        EnumState::Addressed => log::info!("reset: host reset the device mid-configuration, {} rebuilt",
+                            device_class.active().name()),
+                        _ => log::debug!("reset: bus reset from {:?}, {} rebuilt", from, device_class.active().name()),
+                    }
+                }
+                if let Some(bench) = reset_bench.as_mut() {
+                    if let Some(ms) = bench.observe(configured, tt.elapsed_ms()) {
+                        log::trace!("reset: configured again after {}ms", ms);
+                        let (done, total) = bench.progress();
+                        reset_progress.update(tt.elapsed_ms(), done, total);
+                    }
+                }
+                if let Some(keyboard) = usb.keyboard.as_mut().filter(|_| polled) {
                     match keyboard.interface().read_report() {
                         Ok(l) => {
                             log::info!("got led state {:?}", l);
@@ -126,102 +353,903 @@ fn main() -> ! {
                 }
             }
             Some(Opcode::DoCmd) => {
+                let mut expanded = if let Some(run) = repeat.as_ref() {
+                    // the next iteration
+                    cmdline.clear();
+                    cmdline.push_str(&run.command);
+                    expand_aliases(&aliases, &mut cmdline)
+                } else {
+                    enter_cmdline(&mut history, &aliases, &mut cmdline)
+                };
+                if expanded && repeat.is_none() {
+                    if let ("repeat", Some(args)) = split_cmdline(&cmdline) {
+                        match RepeatRun::parse(args) {
+                            Some(run) => {
+                                log::info!("repeat: running '{}', Ctrl-C to abort", run.command);
+                                cmdline = run.command.clone();
+                                repeat = Some(run);
+                                expanded = expand_aliases(&aliases, &mut cmdline);
+                            }
+                            None => {
+                                log::info!("usage: repeat <count> [-k] [-q] <command...>; -k keeps going after a failure, -q hides progress");
+                                cmdline.clear();
+                                continue;
+                            }
+                        }
+                    }
+                }
+                if !expanded {
+                    cmdline.clear();
+                    if let Some(run) = repeat.take() {
+                        run.log_summary();
+                    }
+                    continue;
+                }
+                if let Some(run) = repeat.as_mut() {
+                    run.begin(tt.elapsed_ms());
+                }
+                // set by commands that ran but failed, so `repeat` can stop on them
+                let mut cmd_failed = false;
+                // long-running commands don't report progress when run by `repeat -q`
+                let quiet = repeat.as_ref().map_or(false, |run| run.quiet);
                 log::info!("got command line: {}", cmdline);
-                if let Some((cmd, args)) = cmdline.split_once(' ') {
+                let cmd_name = command_spec(split_cmdline(&cmdline).0).map_or("unknown", |spec| spec.name);
+                shell_events.push(tt.elapsed_ms(), EventKind::CmdStart(cmd_name));
+                usbmgmt.reg_traps().with_table(|t| t.set_command(Some(cmd_name)));
+                if let (cmd, Some(args)) = split_cmdline(&cmdline) {
                     // command and args
                     match cmd {
                         "test" => {
                             log::info!("got test command with arg {}", args);
                         }
+                        "alias" => {
+                            match args.trim_start().split_once(' ') {
+                                Some((name, command)) => match aliases.set(name, command) {
+                                    Ok(()) => log::info!("alias: {} = {}", name, command.trim()),
+                                    Err(e) => {
+                                        cmd_failed = true;
+                                        log::info!("alias: couldn't define {}: {:?}", name, e);
+                                    }
+                                },
+                                None => match aliases.get(args.trim()) {
+                                    Some(command) => log::info!("alias: {} = {}", args.trim(), command),
+                                    None => log::info!("usage: alias [<name> [<command...>]]"),
+                                },
+                            }
+                        }
+                        "unalias" => {
+                            match aliases.remove(args.trim()) {
+                                Ok(()) => log::info!("unalias: removed {}", args.trim()),
+                                Err(e) => {
+                                    cmd_failed = true;
+                                    log::info!("unalias: couldn't remove {}: {:?}", args.trim(), e);
+                                }
+                            }
+                        }
+                        "help" => {
+                            match args {
+                                "--machine" => {
+                                    for line in machine_help(&CommandList::from_registry(COMMANDS)) {
+                                        log::info!("{}", line);
+                                    }
+                                }
+                                _ => log::info!("usage: help [--machine]"),
+                            }
+                        }
                         "conn" => {
                             match args {
-                                "1" => {
-                                    usbmgmt.connect_device_core(true);
-                                    log::info!("device core connected");
+                                "1" => match usbmgmt.connect_device_core(true) {
+                                    Ok(_) => log::info!("device core connected"),
+                                    Err(e) => {
+                                        cmd_failed = true;
+                                        log::info!("device core connect failed: {:?}", e);
+                                    }
                                 },
-                                "0" => {
-                                    usbmgmt.connect_device_core(false);
-                                    log::info!("debug core connected");
+                                "0" => match usbmgmt.connect_device_core(false) {
+                                    Ok(_) => log::info!("debug core connected"),
+                                    Err(e) => {
+                                        cmd_failed = true;
+                                        log::info!("debug core connect failed: {:?}", e);
+                                    }
                                 },
                                 _ => log::info!("usage: conn [1,0]; got: 'conn {}'", args),
                             }
                             usbmgmt.print_regs();
                         }
+                        "history" => {
+                            match args {
+                                "clear" => {
+                                    history.clear();
+                                    log::info!("history: cleared");
+                                }
+                                _ => log::info!("usage: history [clear]"),
+                            }
+                        }
+                        "trap" => {
+                            let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+                            match tokens[..] {
+                                ["add", offset] | ["add", offset, _] => {
+                                    let offset = parse_number(offset).filter(|&o| o % 4 == 0 && o <= MAX_TRAP_OFFSET);
+                                    let access = tokens.get(2).map_or(Some(TrapAccess::ReadWrite), |a| TrapAccess::parse(a));
+                                    match (offset, access) {
+                                        (Some(offset), Some(access)) => {
+                                            if usbmgmt.reg_traps().with_table(|t| t.add(offset, access)) {
+                                                log::info!("trap: watching 0x{:02x} for {}", offset, access.name());
+                                            } else {
+                                                cmd_failed = true;
+                                                log::info!("trap: all {} traps are in use, `trap clear` to free them", MAX_TRAPS);
+                                            }
+                                        }
+                                        _ => log::info!("usage: trap add <offset> [r,w,rw]; offset a word offset up to 0x{:02x}", MAX_TRAP_OFFSET),
+                                    }
+                                }
+                                ["list"] => log_traps(&usbmgmt),
+                                ["clear"] => {
+                                    usbmgmt.reg_traps().with_table(|t| t.clear());
+                                    log::info!("trap: cleared");
+                                }
+                                _ => log::info!("usage: trap [add <offset> [r,w,rw],list,clear]"),
+                            }
+                        }
+                        "vbus" => {
+                            match args {
+                                "on" | "off" => {
+                                    usb_dev.bus().simulate_vbus(Some(args == "on"));
+                                    log::info!("vbus: simulating {}, `vbus real` to stop", args);
+                                }
+                                "real" => {
+                                    usb_dev.bus().simulate_vbus(None);
+                                    log::info!("vbus: following the real level");
+                                }
+                                _ => log::info!("usage: vbus [on,off,real]"),
+                            }
+                        }
+                        "autoconn" => {
+                            match args {
+                                "on" | "off" => {
+                                    autoconn = args == "on";
+                                    if autoconn && vbus.present() {
+                                        usbmgmt.set_attached(true);
+                                    }
+                                    log::info!("autoconn: {}", args);
+                                }
+                                _ => log::info!("usage: autoconn [on,off]"),
+                            }
+                        }
+                        "dfu" => {
+                            match args {
+                                "on" | "off" => {
+                                    relayout(&mut usb_dev, &mut usb, |layout| layout.dfu = args == "on", keyboard);
+                                    // the host only sees the interface come or go on enumeration
+                                    reenumerate(&mut usbmgmt, &tt);
+                                    log::info!("dfu run-time interface {}", if usb.dfu.is_some() { "enabled" } else { "disabled" });
+                                }
+                                _ => log::info!("usage: dfu [on,off]"),
+                            }
+                        }
+                        "fido" => {
+                            match args {
+                                "on" | "off" => {
+                                    relayout(&mut usb_dev, &mut usb, |layout| layout.fido = args == "on", keyboard);
+                                    reenumerate(&mut usbmgmt, &tt);
+                                    log::info!("fido hid interface {}", if usb.fido.is_some() { "enabled" } else { "disabled" });
+                                }
+                                _ => log::info!("usage: fido [on,off]"),
+                            }
+                        }
+                        "class" => {
+                            match DeviceClass::parse(args) {
+                                Some(class) => {
+                                    device_class.select(class);
+                                    if device_class.is_pending() {
+                                        log::info!("class: {} selected, run reconn to apply", class.name());
+                                    } else {
+                                        log::info!("class: {} is already active", class.name());
+                                    }
+                                }
+                                None => log::info!("usage: class [hid,cdc,msc]"),
+                            }
+                        }
+                        "interval" => {
+                            let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+                            match (tokens.get(0).and_then(|t| parse_number(t)), tokens.get(1).and_then(|t| parse_number(t))) {
+                                (Some(ep), Some(frames)) if tokens.len() == 2 && ep > 0 && ep < 16 => {
+                                    match CORE_SPEED.encode_interval(frames) {
+                                        Some(interval) => {
+                                            if usbmgmt.set_interval(ep, interval) {
+                                                // the host only reads bInterval on enumeration
+                                                log::info!("interval: ep{} bInterval set to {}, run reconn to apply", ep, interval);
+                                            } else {
+                                                cmd_failed = true;
+                                                log::info!("interval: ep{} is not an interrupt endpoint", ep);
+                                            }
+                                        }
+                                        None => {
+                                            cmd_failed = true;
+                                            log::info!("interval: {} is out of range, {:?} speed allows {}",
+                                                frames, CORE_SPEED, CORE_SPEED.interval_range());
+                                        }
+                                    }
+                                }
+                                _ => log::info!("usage: interval <ep 1-15> <frames>"),
+                            }
+                        }
+                        "nak" => {
+                            let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+                            match (tokens.get(0).and_then(|t| parse_number(t)), tokens.get(1).and_then(|t| parse_number(t))) {
+                                // ep0 carries enumeration and control, so it can't be held off
+                                (Some(ep), Some(ms)) if tokens.len() == 2 && ep > 0 && ep < 16 => {
+                                    usbmgmt.start_nak(ep, tt.elapsed_ms(), ms as u64);
+                                }
+                                _ => log::info!("usage: nak <ep 1-15> <ms>"),
+                            }
+                        }
+                        "susorder" => {
+                            let (phase, order) = args.split_once(' ').unwrap_or((args, ""));
+                            match (phase, parse_order(order)) {
+                                ("suspend", Some(order)) => power_order.suspend = order,
+                                ("resume", Some(order)) => power_order.resume = order,
+                                _ => log::info!("usage: susorder [suspend,resume] <kbd,usb or usb,kbd>"),
+                            }
+                            log::info!("susorder: suspend {:?}, resume {:?}", power_order.suspend, power_order.resume);
+                        }
+                        "reset" => {
+                            match parse_number(args) {
+                                Some(count) if count > 0 => {
+                                    reset_progress = Progress::new("reset", quiet);
+                                    let mut bench = ResetBench::new(count as u32);
+                                    reset_cycle(&mut bench, &mut usbmgmt, &tt);
+                                    reset_bench = Some(bench);
+                                }
+                                _ => log::info!("usage: reset <count>"),
+                            }
+                        }
+                        "reenumerate" => {
+                            match parse_number(args) {
+                                Some(ms) if ms > 0 && ms <= MAX_DEBOUNCE_MS => {
+                                    soft_reenumerate(&usbmgmt, &mut device_class, &tt, ms,
+                                        |class| relayout(&mut usb_dev, &mut usb, |layout| layout.class = class, keyboard))
+                                }
+                                _ => log::info!("usage: reenumerate [debounce ms, 1-{}]", MAX_DEBOUNCE_MS),
+                            }
+                        }
+                        "regs" => {
+                            match args {
+                                "base" => {
+                                    reg_baseline = Some(usbmgmt.snapshot_regs());
+                                    log::info!("regs: baseline captured");
+                                }
+                                "diff" => match &reg_baseline {
+                                    Some(base) => {
+                                        let changes = base.diff(&usbmgmt.snapshot_regs());
+                                        for c in changes.iter() {
+                                            log::info!("regs: {}: 0x{:08x} -> 0x{:08x}", c.name, c.old, c.new);
+                                        }
+                                        log::info!("regs: {} register(s) changed since the baseline", changes.len());
+                                    }
+                                    None => {
+                                        reg_baseline = Some(usbmgmt.snapshot_regs());
+                                        log::info!("regs: no baseline yet, captured one; run `regs diff` again to compare");
+                                    }
+                                },
+                                _ => log::info!("usage: regs [base,diff]"),
+                            }
+                        }
+                        "memtest" => {
+                            match MemTest::parse(args) {
+                                Some(_) if memtest.is_some() => {
+                                    cmd_failed = true;
+                                    log::info!("memtest: already running, Ctrl-C to abort it");
+                                }
+                                Some(test) => match overlapping_alloc(&usbmgmt.allocs(), test.offset, test.len) {
+                                    Some((o, l)) if !test.force => {
+                                        cmd_failed = true;
+                                        log::info!("memtest: 0x{:x}+{} overlaps the allocation at 0x{:x}({}); -f to test it anyway",
+                                            test.offset, test.len, o, l);
+                                    }
+                                    _ => {
+                                        log::info!("memtest: {} passes over 0x{:x}+{}, Ctrl-C to abort", test.passes, test.offset, test.len);
+                                        memtest_progress = Progress::new("memtest", quiet);
+                                        if let Err(e) = send_message(cid, Message::new_scalar(
+                                            Opcode::MemTestPass.to_usize().unwrap(), 0, 0, 0, 0
+                                        )) {
+                                            cmd_failed = true;
+                                            log::error!("couldn't send MemTestPass: {:?}", e);
+                                            diag.send_failed();
+                                        } else {
+                                            memtest = Some(test);
+                                        }
+                                    }
+                                },
+                                None => log::info!("usage: memtest <offset> <len> [passes] [-f]; offset and len word aligned, -f allows live allocations"),
+                            }
+                        }
+                        "alloctest" => {
+                            match AllocTest::parse(args) {
+                                Some(_) if alloctest.is_some() => {
+                                    cmd_failed = true;
+                                    log::info!("alloctest: already running, Ctrl-C to abort it");
+                                }
+                                Some((iterations, seed)) => {
+                                    alloctest_progress = Progress::new("alloctest", quiet);
+                                    let seed = seed.unwrap_or_else(|| tt.elapsed_ms());
+                                    alloctest = start_alloctest(AllocTest::new(usbmgmt.alloc_config(), iterations, seed), cid, &mut diag);
+                                    cmd_failed = alloctest.is_none();
+                                }
+                                None => log::info!("usage: alloctest [iterations] [seed]"),
+                            }
+                        }
+                        "loglevel" => {
+                            let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+                            match (tokens.get(0).copied(), tokens.get(1).map(|m| LogModule::parse(m))) {
+                                (Some(level), None) if tokens.len() == 1 => match parse_log_level(level) {
+                                    Some(level) => {
+                                        set_default_log_level(level);
+                                        log::info!("loglevel: default {}", level);
+                                    }
+                                    None => {
+                                        cmd_failed = true;
+                                        log::info!("loglevel: unknown level {}", level);
+                                    }
+                                },
+                                (Some("default"), Some(Some(module))) if tokens.len() == 2 => {
+                                    module.set_level(None);
+                                    log::info!("loglevel: {} follows the default, {}", module.name(), default_log_level());
+                                }
+                                (Some(level), Some(Some(module))) if tokens.len() == 2 => match parse_log_level(level) {
+                                    Some(level) => {
+                                        module.set_level(Some(level));
+                                        log::info!("loglevel: {} {}", module.name(), level);
+                                    }
+                                    None => {
+                                        cmd_failed = true;
+                                        log::info!("loglevel: unknown level {}", level);
+                                    }
+                                },
+                                _ => log::info!("usage: loglevel [<off,error,warn,info,debug,trace,default> [usb,kbd,setup]]"),
+                            }
+                        }
+                        "setuptrace" => {
+                            match args {
+                                "on" => usbmgmt.set_setup_trace(true),
+                                "off" => usbmgmt.set_setup_trace(false),
+                                _ => log::info!("usage: setuptrace [on,off]"),
+                            }
+                        }
+                        "poison" => {
+                            match args {
+                                "on" => usbmgmt.set_poisoning(true),
+                                "off" => usbmgmt.set_poisoning(false),
+                                _ => log::info!("usage: poison [on,off]"),
+                            }
+                        }
+                        "allochist" => {
+                            match args {
+                                "on" => usbmgmt.set_free_tracking(true),
+                                "off" => usbmgmt.set_free_tracking(false),
+                                _ => log::info!("usage: allochist [on,off]"),
+                            }
+                        }
+                        "memstat" => {
+                            match args {
+                                "-v" => {
+                                    let now = tt.elapsed_ms();
+                                    for a in usbmgmt.live_allocs().iter() {
+                                        log::info!("memstat: 0x{:04x}-0x{:04x} ({}) {}, age {}ms",
+                                            a.offset, a.offset + a.len, a.len, a.label, now.saturating_sub(a.timestamp));
+                                    }
+                                    log_memstat(&usbmgmt);
+                                }
+                                "reset" => {
+                                    usbmgmt.reset_alloc_peaks();
+                                    log::info!("memstat: peaks reset");
+                                }
+                                _ => log::info!("usage: memstat [-v,reset]"),
+                            }
+                        }
+                        "timeline" => {
+                            match parse_number(args) {
+                                Some(n) => log_timeline(&usbmgmt, &shell_events, &key_events, n),
+                                None => log::info!("usage: timeline [n]"),
+                            }
+                        }
+                        "epstats" => {
+                            if args == "clear" {
+                                usbmgmt.clear_ep_stats();
+                                log::info!("endpoint statistics cleared");
+                            } else if let Some(ep) = parse_number(args).filter(|&ep| ep < 16) {
+                                usbmgmt.ep_stats()[ep].log(ep);
+                            } else {
+                                log::info!("usage: epstats [ep,clear]");
+                            }
+                        }
+                        "watchdog" => {
+                            let (mut threshold, mut kill, _) = usbmgmt.watchdog_state();
+                            let mut ok = true;
+                            for token in args.split(' ').filter(|t| !t.is_empty()) {
+                                match token {
+                                    "off" => {
+                                        threshold = None;
+                                        kill = false;
+                                    }
+                                    "--kill" => kill = true,
+                                    _ => match parse_number(token) {
+                                        Some(ms) => threshold = Some(ms as u64),
+                                        None => ok = false,
+                                    },
+                                }
+                            }
+                            if ok {
+                                usbmgmt.set_watchdog(threshold, kill);
+                                log::info!("watchdog: threshold {:?}ms, kill {}", threshold, kill);
+                            } else {
+                                log::info!("usage: watchdog [<ms>,off] [--kill]");
+                            }
+                        }
+                        "pattern" => {
+                            let usage = "usage: pattern [fill,check] <offset> <len> [inc,prbs,const,poison] [seed/value]";
+                            let tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+                            if tokens.len() < 4 || tokens.len() > 5 {
+                                log::info!("{}", usage);
+                            } else if let (Some(offset), Some(len), Some(kind)) = (
+                                parse_number(tokens[1]),
+                                parse_number(tokens[2]),
+                                PatternKind::parse(tokens[3], tokens.get(4).copied()),
+                            ) {
+                                match tokens[0] {
+                                    "fill" => match usbmgmt.fill_mem(offset, len, kind) {
+                                        Ok(_) => log::info!("filled {} bytes at 0x{:x} with {:?}", len, offset, kind),
+                                        Err(e) => {
+                                            cmd_failed = true;
+                                            log::info!("fill of 0x{:x}+{} failed: {:?}", offset, len, e);
+                                        }
+                                    },
+                                    "check" => match usbmgmt.verify_mem(offset, len, kind) {
+                                        Ok(VerifyResult { first_mismatch: Some(first), errors }) => {
+                                            cmd_failed = true;
+                                            log::info!("FAIL: {} errors in {} bytes, first mismatch at 0x{:x}",
+                                                errors, len, offset + first);
+                                        }
+                                        Ok(_) => log::info!("PASS: {} bytes at 0x{:x} match {:?}", len, offset, kind),
+                                        Err(e) => {
+                                            cmd_failed = true;
+                                            log::info!("check of 0x{:x}+{} failed: {:?}", offset, len, e);
+                                        }
+                                    },
+                                    _ => log::info!("{}", usage),
+                                }
+                            } else {
+                                log::info!("{}", usage);
+                            }
+                        }
                         _ => {
+                            cmd_failed = true;
                             log::info!("unrecognized command {}", cmd);
                         }
                     }
                 } else {
                     // just the command
                     match cmdline.as_str() {
+                        "loglevel" => {
+                            log::info!("loglevel: default {}", default_log_level());
+                            for module in LogModule::ALL.iter() {
+                                match module.own_level() {
+                                    Some(level) => log::info!("loglevel: {} {}", module.name(), level),
+                                    None => log::info!("loglevel: {} follows the default", module.name()),
+                                }
+                            }
+                        }
+                        "reconn" => {
+                            if device_class.apply() {
+                                log::info!("reconn: presenting as {}", device_class.active().name());
+                            }
+                            let class = device_class.active();
+                            relayout(&mut usb_dev, &mut usb, |layout| layout.class = class, keyboard);
+                            reenumerate(&mut usbmgmt, &tt);
+                            log::info!("reconn: device core reconnected");
+                        }
                         "help" => {
-                            log::info!("wouldn't that be nice...");
+                            for spec in COMMANDS.iter() {
+                                log::info!("help: {} {}", spec.name, spec.args);
+                            }
+                        }
+                        "alias" => {
+                            if aliases.iter().next().is_none() {
+                                log::info!("alias: none defined");
+                            }
+                            for (name, command) in aliases.iter() {
+                                log::info!("alias: {} = {}", name, command);
+                            }
                         }
                         "conn" => {
-                            usbmgmt.connect_device_core(true);
-                            log::info!("device core connected");
+                            match usbmgmt.connect_device_core(true) {
+                                Ok(_) => log::info!("device core connected"),
+                                Err(e) => {
+                                    cmd_failed = true;
+                                    log::info!("device core connect failed: {:?}", e);
+                                }
+                            }
                             usbmgmt.print_regs();
                         }
                         "regs" => {
                             usbmgmt.print_regs();
                         }
+                        "vbus" => {
+                            log::info!("vbus: {}, {} attaches, {} detaches, {} listeners, autoconn {}",
+                                if vbus.present() { "present" } else { "absent" },
+                                vbus.attaches, vbus.detaches, vbus_listeners.len(), if autoconn { "on" } else { "off" });
+                        }
+                        "autoconn" => log::info!("autoconn: {}", if autoconn { "on" } else { "off" }),
+                        "trap" => log_traps(&usbmgmt),
+                        "history" => {
+                            for (number, line) in history.iter() {
+                                log::info!("history: {} {}", number, line);
+                            }
+                        }
+                        "status" => {
+                            let status = usbmgmt.status();
+                            let dfu_status = usb.dfu_status();
+                            log::info!("status: {} stuck transfers", status.stuck_transfers);
+                            log::info!("status: {:?}, {} bus resets, {} of them mid-configuration",
+                                usbmgmt.enum_state(), status.bus_resets, status.resets_mid_config);
+                            log::info!("status: vbus {}, autoconn {}",
+                                if vbus.present() { "present" } else { "absent" }, if autoconn { "on" } else { "off" });
+                            let described = usbmgmt.described_intervals();
+                            for (ep, interval) in usbmgmt.intervals() {
+                                let pending = if described.contains(&(ep, interval)) { "" } else { ", reconn to apply" };
+                                log::info!("status: ep{} interrupt, bInterval {}{}", ep, interval, pending);
+                            }
+                            log::info!("status: dfu {}, {:?}, {} detaches, {} resets after detach",
+                                if dfu_status.enabled { "enabled" } else { "disabled" },
+                                usb.dfu.as_ref().map(|dfu| dfu.state.state()).unwrap_or_default(),
+                                dfu_status.detaches, dfu_status.resets);
+                            let fido_status = usb.fido_status();
+                            log::info!("status: fido {}, {} reports received, {} sent",
+                                if fido_status.enabled { "enabled" } else { "disabled" },
+                                fido_status.rx_frames, fido_status.tx_frames);
+                            log::info!("status: class {}{}", device_class.active().name(),
+                                if device_class.is_pending() { ", reconn to apply the selected class" } else { "" });
+                        }
+                        "fido" => {
+                            let fido_status = usb.fido_status();
+                            log::info!("fido: {}, {} reports received, {} sent",
+                                if fido_status.enabled { "enabled" } else { "disabled; turn it on with `fido on`" },
+                                fido_status.rx_frames, fido_status.tx_frames);
+                        }
+                        "class" => {
+                            for class in DeviceClass::ALL.iter().copied() {
+                                let mark = if class == device_class.active() {
+                                    ", active"
+                                } else if class == device_class.selected() {
+                                    ", selected, reconn to apply"
+                                } else {
+                                    ""
+                                };
+                                log::info!("class: {} - {}{}", class.name(), class.description(), mark);
+                            }
+                            if let Some(cdc) = usb.cdc.as_ref() {
+                                let cdc_status = cdc.status();
+                                log::info!("class: cdc {:?}, DTR {}, {} bytes received, {} echoed, {} dropped",
+                                    cdc.line_coding(), if cdc_status.dtr { "on" } else { "off" },
+                                    cdc_status.rx_bytes, cdc_status.tx_bytes, cdc_status.dropped);
+                            }
+                            if let Some(msc) = usb.msc.as_ref() {
+                                let msc_status = msc.status();
+                                log::info!("class: msc {} blocks of {} bytes, {} commands, {} failed, {} invalid CBWs",
+                                    MSC_BLOCKS, MSC_BLOCK_LEN, msc_status.commands, msc_status.failed, msc_status.invalid_cbws);
+                            }
+                        }
+                        "memstat" => {
+                            for (offset, len) in usbmgmt.allocs().iter() {
+                                log::info!("memstat: 0x{:04x}-0x{:04x} ({})", offset, offset + len, len);
+                            }
+                            log_memstat(&usbmgmt);
+                        }
+                        "timeline" => log_timeline(&usbmgmt, &shell_events, &key_events, DEFAULT_TIMELINE_LEN),
+                        "alloctest" => {
+                            if alloctest.is_some() {
+                                cmd_failed = true;
+                                log::info!("alloctest: already running, Ctrl-C to abort it");
+                            } else {
+                                alloctest_progress = Progress::new("alloctest", quiet);
+                                let test = AllocTest::new(usbmgmt.alloc_config(), DEFAULT_ALLOCTEST_ITERATIONS, tt.elapsed_ms());
+                                alloctest = start_alloctest(test, cid, &mut diag);
+                                cmd_failed = alloctest.is_none();
+                            }
+                        }
+                        "lint" => {
+                            match usbmgmt.validate_descriptors() {
+                                Ok(violations) => {
+                                    for (desc, v) in violations.iter() {
+                                        log::info!("lint: {} +{}: {}", desc, v.offset, v.error);
+                                    }
+                                    log::info!("lint: {} violation(s)", violations.len());
+                                    cmd_failed = !violations.is_empty();
+                                }
+                                Err(e) => {
+                                    cmd_failed = true;
+                                    log::info!("lint failed: {:?}; connect to a host first", e);
+                                }
+                            }
+                        }
+                        "diag" => {
+                            log::info!("diag: {} undecodable opcodes, {} malformed scalars, {} send failures",
+                                diag.bad_opcodes, diag.bad_scalars, diag.send_failures);
+                        }
+                        "susorder" => {
+                            log::info!("susorder: suspend {:?}, resume {:?}", power_order.suspend, power_order.resume);
+                        }
+                        "descdump" => {
+                            match usbmgmt.descriptors() {
+                                Ok(set) => {
+                                    for line in dump_descriptors(&set) {
+                                        log::info!("descdump: {}", line);
+                                    }
+                                }
+                                Err(e) => {
+                                    cmd_failed = true;
+                                    log::info!("descdump failed: {:?}; connect to a host first", e);
+                                }
+                            }
+                        }
+                        "setuptrace" => {
+                            let (live, records) = usbmgmt.setup_trace();
+                            for r in records.iter() {
+                                log::info!("setuptrace: {}ms {} -> {:?}", r.timestamp, r.packet, r.outcome);
+                            }
+                            if !live {
+                                log::info!("live tracing is off; turn it on with `setuptrace on`");
+                            }
+                        }
+                        "poison" => {
+                            let (enabled, regions) = usbmgmt.poisoned_regions();
+                            if !enabled {
+                                log::info!("poisoning is off; turn it on with `poison on`");
+                            }
+                            for (offset, len) in regions.iter() {
+                                log::info!("poison: 0x{:04x}-0x{:04x} ({})", offset, offset + len, len);
+                            }
+                        }
+                        "allochist" => {
+                            let (enabled, records) = usbmgmt.free_history();
+                            if !enabled {
+                                log::info!("free tracking is off; turn it on with `allochist on`");
+                            }
+                            for r in records.iter() {
+                                log::info!("allochist: {}ms {} freed 0x{:04x}({})", r.timestamp, r.tag, r.offset, r.len);
+                            }
+                        }
+                        "epstats" => {
+                            for (ep, stats) in usbmgmt.ep_stats().iter().enumerate() {
+                                if !stats.is_idle() {
+                                    stats.log(ep);
+                                }
+                            }
+                        }
+                        "watchdog" => {
+                            let (threshold, kill, stuck) = usbmgmt.watchdog_state();
+                            log::info!("watchdog: threshold {:?}ms, kill {}, {} stuck transfers", threshold, kill, stuck);
+                        }
+                        "listeners" => {
+                            log::info!("{} key listener(s)", key_listeners.len());
+                            for l in key_listeners.iter() {
+                                log::info!("  {} (cid {}, opcode {})", l.name, l.cid, l.opcode);
+                            }
+                        }
+                        "reenumerate" => soft_reenumerate(&usbmgmt, &mut device_class, &tt, DEFAULT_DEBOUNCE_MS,
+                            |class| relayout(&mut usb_dev, &mut usb, |layout| layout.class = class, keyboard)),
+                        "reset" => {
+                            reset_progress = Progress::new("reset", quiet);
+                            let mut bench = ResetBench::new(1);
+                            reset_cycle(&mut bench, &mut usbmgmt, &tt);
+                            reset_bench = Some(bench);
+                        }
+                        "kbdtest" => {
+                            let test = KbdTest::new(kbd.layout());
+                            let keys: Vec<String> = test.expected_labels();
+                            log::info!("kbdtest: press each key when prompted, Ctrl-C to abort. {} keys: {}",
+                                test.total(), keys.join(" "));
+                            test.log_prompt();
+                            kbdtest = Some(test);
+                            kbdtest_progress = Progress::new("kbdtest", quiet);
+                        }
                         _ => {
+                            cmd_failed = true;
                             log::info!("unrecognized command");
                         }
                     }
                 }
+                usbmgmt.reg_traps().with_table(|t| t.set_command(None));
+                shell_events.push(tt.elapsed_ms(), EventKind::CmdEnd(cmd_name, cmd_failed));
                 cmdline.clear();
+                if let Some(run) = repeat.as_mut() {
+                    if run.end(tt.elapsed_ms(), cmd_failed) {
+                        // queue the next iteration behind anything already waiting, such as an abort
+                        if let Err(e) = send_message(cid, Message::new_scalar(
+                            Opcode::DoCmd.to_usize().unwrap(), 0, 0, 0, 0
+                        )) {
+                            log::error!("couldn't send DoCmd: {:?}", e);
+                            diag.send_failed();
+                            run.log_summary();
+                            repeat = None;
+                        }
+                    } else {
+                        run.log_summary();
+                        repeat = None;
+                    }
+                }
+            }
+            Some(Opcode::RegisterKeyListener) => {
+                let buffer = unsafe{xous_ipc::Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let kr = buffer.as_flat::<KeyListenerRegistration, _>().unwrap();
+                match xns.request_connection_blocking(kr.server_name.as_str()) {
+                    Ok(cid) => key_listeners.register(kr.server_name.as_str(), cid, kr.listener_op_id as usize),
+                    Err(e) => log::error!("couldn't connect to key listener: {:?}", e),
+                }
+            }
+            Some(Opcode::RegisterVbusListener) => {
+                let buffer = unsafe{xous_ipc::Buffer::from_memory_message(msg.body.memory_message().unwrap())};
+                let vr = buffer.as_flat::<VbusListenerRegistration, _>().unwrap();
+                match xns.request_connection_blocking(vr.server_name.as_str()) {
+                    Ok(cid) => vbus_listeners.register(vr.server_name.as_str(), cid, vr.listener_op_id as usize),
+                    Err(e) => log::error!("couldn't connect to vbus listener: {:?}", e),
+                }
             }
+            Some(Opcode::KbdTestResult) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                let (state, faults) = match &kbdtest {
+                    Some(test) => (test.state(), test.faults()),
+                    None => (KbdTestState::NotRun, 0),
+                };
+                xous::return_scalar2(msg.sender, state.to_usize().unwrap(), faults).unwrap();
+            }),
             // this is via UART
             Some(Opcode::KeyboardChar) => msg_scalar_unpack!(msg, k, _, _, _, {
-                let key = {
-                    let bs_del_fix = if k == 0x7f {
-                        0x08
-                    } else {
-                        k
-                    };
-                    core::char::from_u32(bs_del_fix as u32).unwrap_or('\u{0000}')
-                };
-                if key != '\u{0000}' {
-                    if key != '\u{000d}' {
-                        cmdline.push(key);
-                    } else {
-                        send_message(cid, Message::new_scalar(
-                            Opcode::DoCmd.to_usize().unwrap(), 0, 0, 0, 0
-                        )).unwrap();
+                if k == 0x03 { // Ctrl-C
+                    if let Some(test) = kbdtest.as_mut().filter(|t| !t.is_done()) {
+                        test.abort();
+                        test.log_summary();
+                        continue;
+                    }
+                    if let Some(test) = memtest.take() {
+                        log::info!("memtest: aborted");
+                        test.log_summary();
+                        continue;
+                    }
+                    if let Some(test) = alloctest.take() {
+                        log::info!("alloctest: aborted");
+                        test.log_summary();
+                        continue;
+                    }
+                    if let Some(run) = repeat.take() {
+                        log::info!("repeat: aborted");
+                        run.log_summary();
+                        continue;
+                    }
+                }
+                if repeat.is_some() {
+                    // the command line is in use until the run ends
+                    continue;
+                }
+                if let Some((opcode, arg)) = route_key(Opcode::KeyboardChar, k, &mut cmdline) {
+                    if let Err(e) = send_message(cid, Message::new_scalar(opcode.to_usize().unwrap(), arg, 0, 0, 0)) {
+                        log::error!("couldn't send {:?}: {:?}", opcode, e);
+                        diag.send_failed();
+                    }
+                }
+            }),
+            #[cfg(any(test, feature = "hosted", feature = "inject-key"))]
+            Some(Opcode::InjectKey) => msg_scalar_unpack!(msg, k, _, _, _, {
+                if let Some((opcode, arg)) = route_key(Opcode::InjectKey, k, &mut cmdline) {
+                    if let Err(e) = send_message(cid, Message::new_scalar(opcode.to_usize().unwrap(), arg, 0, 0, 0)) {
+                        log::error!("couldn't send {:?}: {:?}", opcode, e);
+                        diag.send_failed();
                     }
                 }
             }),
             // this is via physical keyboard
             Some(Opcode::HandlerTrigger) => {
                 let rawstates = kbd.update();
+                if let Some(test) = kbdtest.as_mut().filter(|t| !t.is_done()) {
+                    // keys are consumed by the self-test and not sent on to the host
+                    for &rc in rawstates.keydowns.iter() {
+                        if !test.observe(rc) {
+                            log::info!("kbdtest: unexpected key at ({},{})", rc.r, rc.c);
+                        }
+                        kbdtest_progress.update(tt.elapsed_ms(), test.total() - test.remaining(), test.total());
+                        if test.is_done() {
+                            test.log_summary();
+                            break;
+                        }
+                        test.log_prompt();
+                    }
+                    continue;
+                }
                 // interpret scancodes
                 let kc: Vec<char> = kbd.track_keys(&rawstates);
                 // handle keys, if any
                 for &key in kc.iter() {
                     // send it to the USB interface
                     let code = hid_convert(key);
-                    keyboard.interface().write_report(&code).ok();
-                    keyboard.interface().tick().unwrap();
+                    let modifiers = if code.contains(&Keyboard::LeftShift) { MOD_SHIFT } else { 0 };
+                    // with another class active, the keys only go to the shell and the listeners
+                    if let Some(keyboard) = usb.keyboard.as_mut() {
+                        keyboard.interface().write_report(&code).ok();
+                        keyboard.interface().tick().unwrap();
+                        key_events.push(tt.elapsed_ms(), EventKind::Key(key));
+                    }
+                    key_listeners.forward(key, modifiers, true);
                     tt.sleep_ms(20).unwrap();
-                    keyboard.interface().write_report(&[]).ok(); // this is the key-up
-                    keyboard.interface().tick().unwrap();
+                    if let Some(keyboard) = usb.keyboard.as_mut() {
+                        keyboard.interface().write_report(&[]).ok(); // this is the key-up
+                        keyboard.interface().tick().unwrap();
+                    }
+                    key_listeners.forward(key, modifiers, false);
 
-                    if key != '\u{000d}' {
-                        cmdline.push(key);
-                    } else {
-                        send_message(cid, Message::new_scalar(
+                    if repeat.is_none() && cmdline_key(&mut cmdline, key) {
+                        if let Err(e) = send_message(cid, Message::new_scalar(
                             Opcode::DoCmd.to_usize().unwrap(), 0, 0, 0, 0
-                        )).unwrap();
+                        )) {
+                            log::error!("couldn't send DoCmd: {:?}", e);
+                            diag.send_failed();
+                        }
                     }
                 }
             },
+            Some(Opcode::GetDiagCounters) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                let (a, b) = diag.pack();
+                xous::return_scalar2(msg.sender, a, b).unwrap();
+            }),
+            Some(Opcode::MemTestPass) => {
+                if let Some(test) = memtest.as_mut() {
+                    let result = memtest_pass(test, &usbmgmt);
+                    memtest_progress.update(tt.elapsed_ms(), test.pass, test.passes);
+                    if let Err(e) = result {
+                        log::info!("memtest: stopped at pass {}: {:?}", test.pass, e);
+                        test.log_summary();
+                        memtest = None;
+                    } else if test.is_done() {
+                        test.log_summary();
+                        memtest = None;
+                    } else if let Err(e) = send_message(cid, Message::new_scalar(
+                        Opcode::MemTestPass.to_usize().unwrap(), 0, 0, 0, 0
+                    )) {
+                        log::error!("couldn't send MemTestPass: {:?}", e);
+                        diag.send_failed();
+                        test.log_summary();
+                        memtest = None;
+                    }
+                }
+            }
+            Some(Opcode::AllocTestChunk) => {
+                if let Some(test) = alloctest.as_mut() {
+                    test.run(ALLOCTEST_CHUNK);
+                    alloctest_progress.update(tt.elapsed_ms(), test.done, test.iterations);
+                    if test.is_done() {
+                        test.log_summary();
+                        alloctest = None;
+                    } else if let Err(e) = send_message(cid, Message::new_scalar(
+                        Opcode::AllocTestChunk.to_usize().unwrap(), 0, 0, 0, 0
+                    )) {
+                        log::error!("couldn't send AllocTestChunk: {:?}", e);
+                        diag.send_failed();
+                        test.log_summary();
+                        alloctest = None;
+                    }
+                }
+            }
+            Some(Opcode::ListCommands) => {
+                let mut buffer = unsafe { xous_ipc::Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                buffer.replace(CommandList::from_registry(COMMANDS)).expect("couldn't return ListCommands");
+            }
             Some(Opcode::Quit) => {
                 log::warn!("Quit received, goodbye world!");
                 break;
             },
             None => {
                 log::error!("couldn't convert opcode: {:?}", msg);
+                if diag.bad_opcode(tt.elapsed_ms()) {
+                    log::warn!("undecodable opcodes are arriving fast, {} so far", diag.bad_opcodes);
+                }
             }
         }
     }
@@ -233,8 +1261,255 @@ fn main() -> ! {
     xous::terminate_process(0)
 }
 
+/// Converts a character received on the UART to a key, mapping DEL to backspace. Returns `None`
+/// for NUL and for codes that aren't valid characters.
+fn uart_key(k: usize) -> Option<char> {
+    let bs_del_fix = if k == 0x7f {
+        0x08
+    } else {
+        k
+    };
+    match core::char::from_u32(bs_del_fix as u32) {
+        Some('\u{0000}') | None => None,
+        Some(key) => Some(key),
+    }
+}
+/// Adds a key to the command line. Returns `true` if the key completes the line, in which case
+/// the line should be dispatched with a `DoCmd`.
+fn cmdline_key(cmdline: &mut String, key: char) -> bool {
+    if key != '\u{000d}' {
+        cmdline.push(key);
+        false
+    } else {
+        true
+    }
+}
+/// Takes a key the shell's been sent on to wherever it goes next, returning the scalar message
+/// to send on for it, if any. An injected key is requeued as UART input, so it takes exactly the
+/// same path as a typed one; a UART key is added to the command line, and the key that
+/// completes the line sends a `DoCmd` for it.
+fn route_key(opcode: Opcode, k: usize, cmdline: &mut String) -> Option<(Opcode, usize)> {
+    match opcode {
+        #[cfg(any(test, feature = "hosted", feature = "inject-key"))]
+        Opcode::InjectKey => Some((Opcode::KeyboardChar, k)),
+        Opcode::KeyboardChar => match uart_key(k) {
+            Some(key) if cmdline_key(cmdline, key) => Some((Opcode::DoCmd, 0)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+/// Takes the line typed at the shell as it's entered with a `DoCmd`: recalls any history it
+/// refers to, records it in the history, and expands aliases. Returns `false` if there's
+/// nothing to run.
+fn enter_cmdline(history: &mut CommandHistory, aliases: &Aliases, cmdline: &mut String) -> bool {
+    if !recall_history(history, cmdline) {
+        return false;
+    }
+    history.push(cmdline);
+    expand_aliases(aliases, cmdline)
+}
+/// Splits a command line into the command and its arguments, if there are any.
+fn split_cmdline(cmdline: &str) -> (&str, Option<&str>) {
+    match cmdline.split_once(' ') {
+        Some((cmd, args)) => (cmd, Some(args)),
+        None => (cmdline, None),
+    }
+}
+
+/// Expands any alias at the start of the command line. Returns `false` if it couldn't be, in
+/// which case the line shouldn't be run.
+fn expand_aliases(aliases: &Aliases, cmdline: &mut String) -> bool {
+    match aliases.expand(cmdline) {
+        Ok(Some(expanded)) => {
+            log::info!("alias: {} -> {}", cmdline, expanded);
+            *cmdline = expanded;
+            true
+        }
+        Ok(None) => true,
+        Err(e) => {
+            log::info!("alias: couldn't expand {}: {:?}", cmdline, e);
+            false
+        }
+    }
+}
+
+/// Replaces a `!n` command line with entry `n` of the history. Returns `false` if there's no
+/// such entry, in which case the line shouldn't be run.
+fn recall_history(history: &CommandHistory, cmdline: &mut String) -> bool {
+    match history.recall(cmdline) {
+        Ok(Some(line)) => {
+            log::info!("history: {} -> {}", cmdline, line);
+            *cmdline = line;
+            true
+        }
+        Ok(None) => true,
+        Err(e) => {
+            log::info!("history: couldn't recall {}: {:?}", cmdline, e);
+            false
+        }
+    }
+}
+
+/// time the device core is held off the bus to force the host to re-enumerate
+const REENUMERATE_DELAY_MS: usize = 200;
+/// Drops the device core off the bus for a moment, so the host enumerates it again
+fn reenumerate(usbmgmt: &mut SpinalUsbMgmt, tt: &ticktimer_server::Ticktimer) {
+    if let Err(e) = usbmgmt.connect_device_core(false) {
+        log::error!("couldn't disconnect device core: {:?}", e);
+    }
+    tt.sleep_ms(REENUMERATE_DELAY_MS).ok();
+    if let Err(e) = usbmgmt.connect_device_core(true) {
+        log::error!("couldn't reconnect device core: {:?}", e);
+    }
+}
+
+/// Applies `change` to the layout of `usb`, and if that changes it, builds the functions and the
+/// device on a new allocator, so that the new set of interfaces is numbered from 0. Everything
+/// built on the old allocator borrows it for good, so it's leaked, along with its bus; that's a
+/// few hundred bytes each time the layout changes.
+fn relayout<K: UsbClass<SpinalUsbDevice>>(
+    usb_dev: &mut UsbDevice<'static, SpinalUsbDevice>,
+    usb: &mut UsbFunctions<'static, SpinalUsbDevice, K>,
+    change: impl FnOnce(&mut UsbLayout),
+    keyboard: impl FnOnce(&'static UsbBusAllocator<SpinalUsbDevice>) -> K,
+) {
+    let mut layout = usb.layout();
+    change(&mut layout);
+    if layout == usb.layout() {
+        return;
+    }
+    let alloc: &'static UsbBusAllocator<SpinalUsbDevice> = Box::leak(Box::new(UsbBusAllocator::new(usb_dev.bus().reallocate())));
+    let mut functions = UsbFunctions::build(alloc, layout, keyboard);
+    functions.carry_over(usb);
+    *usb = functions;
+    *usb_dev = build_device(alloc);
+    log::info!("layout: {:?} rebuilt on a new allocator", layout);
+}
+
+/// time `reenumerate` keeps the device detached for when not told otherwise
+const DEFAULT_DEBOUNCE_MS: usize = 100;
+/// longest `reenumerate` will keep the device detached for
+const MAX_DEBOUNCE_MS: usize = 5000;
+/// Detaches the device by dropping the pullup, waits `debounce_ms` for the host to notice, and
+/// attaches it again, applying the selected class on the way: `relayout` is called with it while
+/// the device is detached. Unlike `reconn`, the device core stays selected throughout.
+fn soft_reenumerate(usbmgmt: &SpinalUsbMgmt, device_class: &mut ClassSelect, tt: &ticktimer_server::Ticktimer, debounce_ms: usize,
+    relayout: impl FnOnce(DeviceClass)) {
+    usbmgmt.set_attached(false);
+    if device_class.apply() {
+        log::info!("reenumerate: presenting as {}", device_class.active().name());
+    }
+    relayout(device_class.active());
+    tt.sleep_ms(debounce_ms).ok();
+    usbmgmt.set_attached(true);
+    log::info!("reenumerate: detached for {}ms, attached again", debounce_ms);
+}
+
+/// Forces a bus reset for the next cycle of `bench`. Enumeration is timed from when the device
+/// core is back on the bus.
+fn reset_cycle(bench: &mut ResetBench, usbmgmt: &mut SpinalUsbMgmt, tt: &ticktimer_server::Ticktimer) {
+    reenumerate(usbmgmt, tt);
+    bench.start(tt.elapsed_ms());
+}
+/// Runs the next pass of `test`: each pattern is written over the whole region, then read back
+fn memtest_pass(test: &mut MemTest, usbmgmt: &SpinalUsbMgmt) -> Result<(), error::UsbError> {
+    let (offset, len, pass) = (test.offset, test.len, test.pass);
+    for (pattern, result) in MemTestPattern::ALL.iter().zip(test.results.iter_mut()) {
+        usbmgmt.write_words(offset, len, |addr| pattern.word(pass, addr))?;
+        let words = usbmgmt.read_words(offset, len)?;
+        result.check(*pattern, pass, offset, &words);
+    }
+    test.pass += 1;
+    Ok(())
+}
+/// Lists the register traps and their hit counts, for `trap list`
+fn log_traps(usbmgmt: &SpinalUsbMgmt) {
+    usbmgmt.reg_traps().with_table(|table| {
+        if table.traps().is_empty() {
+            log::info!("trap: none set");
+        }
+        for trap in table.traps().iter() {
+            log::info!("trap: 0x{:02x} {}, {} hits", trap.offset, trap.access.name(), trap.hits);
+        }
+    });
+}
+/// Logs the start of `test` and queues its first chunk. Returns the test if it's going.
+fn start_alloctest(test: AllocTest, cid: CID, diag: &mut DiagCounters) -> Option<AllocTest> {
+    log::info!("alloctest: {} iterations, seed {}, Ctrl-C to abort", test.iterations, test.seed);
+    match send_message(cid, Message::new_scalar(Opcode::AllocTestChunk.to_usize().unwrap(), 0, 0, 0, 0)) {
+        Ok(_) => Some(test),
+        Err(e) => {
+            log::error!("couldn't send AllocTestChunk: {:?}", e);
+            diag.send_failed();
+            None
+        }
+    }
+}
+/// Logs the latest `n` events of every source, merged in the order they happened, for `timeline`
+fn log_timeline(usbmgmt: &SpinalUsbMgmt, shell_events: &EventRing, key_events: &EventRing, n: usize) {
+    let (_, setups) = usbmgmt.setup_trace();
+    let mut eps: Vec<(usize, u64)> = usbmgmt.ep_stats().iter().enumerate()
+        .filter(|(_, s)| !s.is_idle())
+        .map(|(index, s)| (index, s.last_ms))
+        .collect();
+    eps.sort_by_key(|&(index, last_ms)| (last_ms, index));
+    let armed = usbmgmt.armed_transfers();
+    let events = merge_latest(&mut [
+        &mut setups.iter().map(|r| TimelineEvent { timestamp: r.timestamp, kind: EventKind::Setup(*r) }),
+        &mut eps.iter().map(|&(index, last_ms)| TimelineEvent { timestamp: last_ms, kind: EventKind::EpActivity(index) }),
+        &mut armed.iter().map(|&(index, t)| TimelineEvent { timestamp: t.armed_at, kind: EventKind::Armed(index, t.dir) }),
+        &mut shell_events.iter(),
+        &mut key_events.iter(),
+    ], n);
+    for e in events.iter() {
+        log::info!("timeline: {}", e);
+    }
+}
+/// Logs the usage of the descriptor memory, for `memstat`
+fn log_memstat(usbmgmt: &SpinalUsbMgmt) {
+    let cfg = usbmgmt.alloc_config();
+    let (peak_total, peak_single) = usbmgmt.alloc_peaks();
+    log::info!("memstat: managing 0x{:04x}-0x{:04x}, {} bytes free, largest free block {}",
+        cfg.start, cfg.end, usbmgmt.free_bytes(), usbmgmt.largest_free_block());
+    log::info!("memstat: peak {} bytes allocated, largest allocation {} bytes", peak_total, peak_single);
+}
+fn log_reset_stats(bench: &ResetBench) {
+    match bench.stats() {
+        Some(stats) => log::info!("reset: {} cycles, re-enumeration min {}ms max {}ms avg {}ms, {} timed out",
+            stats.cycles, stats.min, stats.max, stats.avg, bench.timeouts),
+        None => log::info!("reset: no cycles completed, {} timed out", bench.timeouts),
+    }
+}
+
 pub(crate) const START_OFFSET: u32 = 0x0048 + 8 + 16; // align spinal free space to 16-byte boundary + 16 bytes for EP0 read
-pub(crate) const END_OFFSET: u32 = 0x1000; // derived from RAMSIZE parameter: this could be a dynamically read out constant, but, in practice, it's part of the hardware
+pub(crate) const END_OFFSET: u32 = 0x1000; // derived from RAMSIZE parameter: the default for the Precursor core; other configurations are probed, see `AllocatorConfig`
+/// Bounds of the descriptor memory managed by the allocator. Allocations fall in `start..end`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct AllocatorConfig {
+    pub(crate) start: u32,
+    pub(crate) end: u32,
+}
+impl Default for AllocatorConfig {
+    fn default() -> AllocatorConfig {
+        AllocatorConfig { start: START_OFFSET, end: END_OFFSET }
+    }
+}
+impl AllocatorConfig {
+    /// Clamps the end of the managed space to the RAM size reported by the core. A report that
+    /// leaves no room past `start` is taken to mean the core doesn't report its size, and the
+    /// configuration is returned as-is.
+    pub(crate) fn fit_to_ram(self, ramsize: u32) -> AllocatorConfig {
+        if ramsize > self.start && ramsize < self.end {
+            AllocatorConfig { start: self.start, end: ramsize }
+        } else {
+            self
+        }
+    }
+    pub(crate) fn size(&self) -> u32 {
+        self.end - self.start
+    }
+}
 /// USB endpoint allocator. The SpinalHDL USB controller appears as a block of
 /// unstructured memory to the host. You can specify pointers into the memory with
 /// an offset and length to define where various USB descriptors should be placed.
@@ -245,11 +1520,16 @@ pub(crate) const END_OFFSET: u32 = 0x1000; // derived from RAMSIZE parameter: th
 ///
 /// Returns a full memory address as the pointer. Must be shifted left by 4 to get the
 /// aligned representation used by the SpinalHDL block.
-pub(crate) fn alloc_inner(allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Option<u32> {
-    if requested == 0 {
-        return None;
+#[allow(dead_code)]
+pub(crate) fn alloc_inner(cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Option<u32> {
+    try_alloc_inner(cfg, allocs, requested).ok()
+}
+/// `alloc_inner`, but reports why an allocation failed
+pub(crate) fn try_alloc_inner(cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Result<u32, error::UsbError> {
+    if requested == 0 || requested > cfg.size() {
+        return Err(error::UsbError::InvalidLength);
     }
-    let mut alloc_offset = START_OFFSET;
+    let mut alloc_offset = cfg.start;
     for (&offset, &length) in allocs.iter() {
         // round length up to the nearest 16-byte increment
         let length = if length & 0xF == 0 { length } else { (length + 16) & !0xF };
@@ -263,40 +1543,292 @@ pub(crate) fn alloc_inner(allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Op
         }
         alloc_offset = offset + length;
     }
-    if alloc_offset + requested <= END_OFFSET {
+    if alloc_offset + requested <= cfg.end {
+        allocs.insert(alloc_offset, requested);
+        Ok(alloc_offset)
+    } else {
+        Err(error::UsbError::OutOfMemory)
+    }
+}
+/// Placement policy for endpoint buffers
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum AllocMode {
+    /// lowest address that fits; see `alloc_inner`
+    FirstFit,
+    /// Small buffers are packed up from the bottom of the memory space and large buffers down
+    /// from the top, so the offsets within each size class depend only on the order of the
+    /// requests in that class. Makes layouts comparable across configurations that add or
+    /// reorder endpoints of the other class.
+    Sorted,
+}
+/// requests of this size or larger are placed from the top of memory by `AllocMode::Sorted`
+pub(crate) const SIZE_CLASS_LARGE: u32 = 64;
+
+pub(crate) fn alloc_with(mode: AllocMode, cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Result<u32, error::UsbError> {
+    match mode {
+        AllocMode::FirstFit => try_alloc_inner(cfg, allocs, requested),
+        AllocMode::Sorted => alloc_sorted(cfg, allocs, requested),
+    }
+}
+/// Places small requests first-fit, and large requests in the highest hole that fits.
+pub(crate) fn alloc_sorted(cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, requested: u32) -> Result<u32, error::UsbError> {
+    if requested < SIZE_CLASS_LARGE || requested > cfg.size() {
+        return try_alloc_inner(cfg, allocs, requested);
+    }
+    let requested_aligned = if requested & 0xF == 0 { requested } else { (requested + 16) & !0xF };
+    let mut hole_end = cfg.end;
+    for (&offset, &length) in allocs.iter().rev() {
+        let length = if length & 0xF == 0 { length } else { (length + 16) & !0xF };
+        let hole_start = offset + length;
+        assert!(hole_start <= hole_end, "allocated regions overlap");
+        if hole_end - hole_start >= requested_aligned {
+            let alloc_offset = hole_end - requested_aligned;
+            allocs.insert(alloc_offset, requested);
+            return Ok(alloc_offset);
+        }
+        hole_end = offset;
+    }
+    if hole_end >= cfg.start + requested_aligned {
+        let alloc_offset = hole_end - requested_aligned;
         allocs.insert(alloc_offset, requested);
-        Some(alloc_offset)
+        Ok(alloc_offset)
     } else {
-        None
+        Err(error::UsbError::OutOfMemory)
     }
 }
 #[allow(dead_code)]
-pub(crate) fn dealloc_inner(allocs: &mut BTreeMap<u32, u32>, offset: u32) -> bool {
-    allocs.remove(&offset).is_some()
+pub(crate) fn dealloc_inner(cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, offset: u32) -> bool {
+    try_dealloc_inner(cfg, allocs, offset).is_ok()
+}
+/// `dealloc_inner`, but reports why a region couldn't be freed
+pub(crate) fn try_dealloc_inner(cfg: AllocatorConfig, allocs: &mut BTreeMap<u32, u32>, offset: u32) -> Result<(), error::UsbError> {
+    if offset & 0xF != 0 {
+        Err(error::UsbError::Misaligned)
+    } else if offset < cfg.start || offset >= cfg.end {
+        Err(error::UsbError::OutOfRange)
+    } else {
+        allocs.remove(&offset).map(|_| ()).ok_or(error::UsbError::NotAllocated)
+    }
+}
+/// Returns the `(offset, len)` of each unallocated hole in the managed space, in address order.
+/// Allocated lengths are rounded up to the 16-byte alignment, so the holes are exactly the
+/// space available to later allocations.
+fn free_blocks(cfg: AllocatorConfig, allocs: &BTreeMap<u32, u32>) -> Vec<(u32, u32)> {
+    let mut blocks = Vec::new();
+    let mut hole_start = cfg.start;
+    for (&offset, &length) in allocs.iter() {
+        let length = if length & 0xF == 0 { length } else { (length + 16) & !0xF };
+        if offset > hole_start {
+            blocks.push((hole_start, offset - hole_start));
+        }
+        hole_start = offset + length;
+    }
+    if cfg.end > hole_start {
+        blocks.push((hole_start, cfg.end - hole_start));
+    }
+    blocks
+}
+/// total bytes not covered by an allocation
+pub(crate) fn free_bytes(cfg: AllocatorConfig, allocs: &BTreeMap<u32, u32>) -> u32 {
+    free_blocks(cfg, allocs).iter().map(|&(_, len)| len).sum()
+}
+/// the largest request that can currently succeed
+pub(crate) fn largest_free_block(cfg: AllocatorConfig, allocs: &BTreeMap<u32, u32>) -> u32 {
+    free_blocks(cfg, allocs).iter().map(|&(_, len)| len).max().unwrap_or(0)
+}
+/// Checks that `len` bytes at `offset` are within a memory of `size` bytes
+pub(crate) fn check_region(offset: usize, len: usize, size: usize) -> Result<(), error::UsbError> {
+    if len == 0 {
+        Err(error::UsbError::InvalidLength)
+    } else if offset.checked_add(len).map(|end| end > size).unwrap_or(true) {
+        Err(error::UsbError::OutOfRange)
+    } else {
+        Ok(())
+    }
 }
 
 // run with `cargo test -- --nocapture --test-threads=1`:
 #[cfg(test)]
 mod tests {
     use super::*;
+    /// the allocator tests are run over each of these
+    const CONFIGS: [AllocatorConfig; 3] = [
+        AllocatorConfig { start: START_OFFSET, end: END_OFFSET },
+        AllocatorConfig { start: START_OFFSET, end: 0x800 },
+        AllocatorConfig { start: 0x200, end: 0x2000 },
+    ];
+    #[test]
+    fn test_alloc_errors() {
+        use error::UsbError;
+        for &cfg in CONFIGS.iter() {
+            let mut allocs = BTreeMap::<u32, u32>::new();
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, 0), Err(UsbError::InvalidLength));
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, cfg.end), Err(UsbError::InvalidLength));
+            assert_eq!(alloc_sorted(cfg, &mut allocs, cfg.end), Err(UsbError::InvalidLength));
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, cfg.size()), Ok(cfg.start));
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, 8), Err(UsbError::OutOfMemory));
+
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.start + 4), Err(UsbError::Misaligned));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, 0), Err(UsbError::OutOfRange));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.end), Err(UsbError::OutOfRange));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.start + 16), Err(UsbError::NotAllocated));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.start), Ok(()));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.start), Err(UsbError::NotAllocated));
+        }
+
+        assert_eq!(check_region(0, 0, 4096), Err(UsbError::InvalidLength));
+        assert_eq!(check_region(4000, 97, 4096), Err(UsbError::OutOfRange));
+        assert_eq!(check_region(usize::MAX, 2, 4096), Err(UsbError::OutOfRange));
+        assert_eq!(check_region(4000, 96, 4096), Ok(()));
+
+        // how each error is reported to the USB stack
+        assert_eq!(usb_device::UsbError::from(UsbError::OutOfMemory), usb_device::UsbError::EndpointMemoryOverflow);
+        assert_eq!(usb_device::UsbError::from(UsbError::EndpointBusy), usb_device::UsbError::InvalidEndpoint);
+        assert_eq!(usb_device::UsbError::from(UsbError::NotConnected), usb_device::UsbError::InvalidState);
+        assert_eq!(usb_device::UsbError::from(UsbError::Misaligned), usb_device::UsbError::Unsupported);
+    }
+
+    #[test]
+    fn test_alloc_deterministic() {
+        fn layout(mode: AllocMode, cfg: AllocatorConfig, requests: &[u32]) -> BTreeMap<u32, u32> {
+            let mut allocs = BTreeMap::<u32, u32>::new();
+            for &r in requests.iter() {
+                assert!(alloc_with(mode, cfg, &mut allocs, r).is_ok());
+            }
+            allocs
+        }
+        for &cfg in CONFIGS.iter() {
+            let requests = [8, 64, 8, 256, 16, 64, 512, 1];
+            for &mode in [AllocMode::FirstFit, AllocMode::Sorted].iter() {
+                assert_eq!(layout(mode, cfg, &requests), layout(mode, cfg, &requests));
+            }
+            // in sorted mode, interleaving small and large requests doesn't move anything
+            let grouped = [8, 8, 16, 64, 256];
+            let interleaved = [64, 8, 256, 8, 16];
+            assert_eq!(layout(AllocMode::Sorted, cfg, &grouped), layout(AllocMode::Sorted, cfg, &interleaved));
+            assert_ne!(layout(AllocMode::FirstFit, cfg, &grouped), layout(AllocMode::FirstFit, cfg, &interleaved));
+            let sorted = layout(AllocMode::Sorted, cfg, &grouped);
+            assert_eq!(sorted.get(&cfg.start), Some(&8));
+            assert_eq!(sorted.get(&(cfg.end - 64)), Some(&64));
+            assert_eq!(sorted.get(&(cfg.end - 64 - 256)), Some(&256));
+
+            // freed holes at the top are re-used by large requests, and exhaustion is reported
+            let mut allocs = layout(AllocMode::Sorted, cfg, &grouped);
+            assert!(dealloc_inner(cfg, &mut allocs, cfg.end - 64));
+            assert_eq!(alloc_sorted(cfg, &mut allocs, 64), Ok(cfg.end - 64));
+            let largest = largest_free_block(cfg, &allocs);
+            assert_eq!(alloc_sorted(cfg, &mut allocs, largest + 16), Err(error::UsbError::OutOfMemory));
+            assert!(alloc_sorted(cfg, &mut allocs, largest).is_ok());
+        }
+    }
+    #[test]
+    fn test_free_space() {
+        for &cfg in CONFIGS.iter() {
+            let mut allocs = BTreeMap::<u32, u32>::new();
+            assert_eq!(free_bytes(cfg, &allocs), cfg.size());
+            assert_eq!(largest_free_block(cfg, &allocs), cfg.size());
+
+            // lengths count at their 16-byte aligned size
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, 8), Ok(cfg.start));
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, 256), Ok(cfg.start + 16));
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, 64), Ok(cfg.start + 16 + 256));
+            assert_eq!(free_bytes(cfg, &allocs), cfg.size() - 16 - 256 - 64);
+            assert_eq!(largest_free_block(cfg, &allocs), cfg.size() - 16 - 256 - 64);
+
+            // a hole that is larger than the tail becomes the largest block
+            assert_eq!(try_alloc_inner(cfg, &mut allocs, cfg.size() - 16 - 256 - 64 - 32), Ok(cfg.start + 16 + 256 + 64));
+            assert_eq!(try_dealloc_inner(cfg, &mut allocs, cfg.start + 16), Ok(()));
+            assert_eq!(free_bytes(cfg, &allocs), 256 + 32);
+            assert_eq!(largest_free_block(cfg, &allocs), 256);
+
+            try_alloc_inner(cfg, &mut allocs, 256).unwrap();
+            try_alloc_inner(cfg, &mut allocs, 32).unwrap();
+            assert_eq!(free_bytes(cfg, &allocs), 0);
+            assert_eq!(largest_free_block(cfg, &allocs), 0);
+        }
+
+        let cfg = AllocatorConfig::default();
+        assert_eq!(cfg.fit_to_ram(0x800), AllocatorConfig { start: START_OFFSET, end: 0x800 });
+        assert_eq!(cfg.fit_to_ram(0x2000), cfg);
+        // no usable size reported
+        assert_eq!(cfg.fit_to_ram(1), cfg);
+    }
+    #[test]
+    fn test_inject_cmdline() {
+        // keys go through the shell's own routing, a message at a time: injected keys are
+        // requeued as UART input, and a completed line is entered and dispatched with DoCmd
+        fn inject(cmdline: &mut String, history: &mut CommandHistory, s: &str) -> Vec<String> {
+            let mut queue: std::collections::VecDeque<(Opcode, usize)> = s.chars().map(|k| (Opcode::InjectKey, k as usize)).collect();
+            let mut dispatched = Vec::new();
+            while let Some((opcode, k)) = queue.pop_front() {
+                if let Opcode::DoCmd = opcode {
+                    if enter_cmdline(history, &Aliases::new(), cmdline) {
+                        dispatched.push(cmdline.clone());
+                    }
+                    cmdline.clear();
+                } else {
+                    queue.extend(route_key(opcode, k, cmdline));
+                }
+            }
+            dispatched
+        }
+        let mut cmdline = String::new();
+        let mut history = CommandHistory::new();
+        assert!(inject(&mut cmdline, &mut history, "reg").is_empty());
+        assert_eq!(cmdline, "reg");
+        let dispatched = inject(&mut cmdline, &mut history, "s\r");
+        assert_eq!(dispatched, vec!["regs".to_string()]);
+        assert_eq!(command_spec(split_cmdline(&dispatched[0]).0).map(|spec| spec.name), Some("regs"));
+        assert_eq!(cmdline, "");
+        // in order, and each entered into the history
+        assert_eq!(inject(&mut cmdline, &mut history, "conn 0\rlint\r"), vec!["conn 0".to_string(), "lint".to_string()]);
+        assert_eq!(history.iter().map(|(_, line)| line).collect::<Vec<_>>(), vec!["regs", "conn 0", "lint"]);
+        assert_eq!(inject(&mut cmdline, &mut history, "!1\r"), vec!["regs".to_string()]);
+        assert!(route_key(Opcode::DoCmd, 0, &mut cmdline).is_none());
+        assert_eq!(uart_key(0x7f), Some('\u{0008}'));
+        assert_eq!(uart_key(0), None);
+    }
+    #[test]
+    fn test_stdin_line() {
+        let typed = |line: &str| -> (Vec<bool>, String) {
+            let mut cmdline = String::new();
+            let entered = line_keys(line).filter_map(uart_key).map(|k| cmdline_key(&mut cmdline, k)).collect();
+            (entered, cmdline)
+        };
+        // a line is typed out and entered once, at the end
+        let mut entered = vec![false; 8];
+        entered.push(true);
+        assert_eq!(typed("nak 1 50"), (entered, "nak 1 50".to_string()));
+        // a CRLF line ending doesn't enter the line twice
+        assert_eq!(typed("regs\r"), (vec![false, false, false, false, true], "regs".to_string()));
+        // an empty line is just entered
+        assert_eq!(typed(""), (vec![true], String::new()));
+    }
     #[test]
     fn test_alloc() {
+        for &cfg in CONFIGS.iter() {
+            alloc_suite(cfg);
+        }
+    }
+    fn alloc_suite(cfg: AllocatorConfig) {
         use rand_chacha::ChaCha8Rng;
         use rand_chacha::rand_core::SeedableRng;
         use rand_chacha::rand_core::RngCore;
         let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let start = cfg.start;
 
         let mut allocs = BTreeMap::<u32, u32>::new();
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET));
-        assert_eq!(alloc_inner(&mut allocs, 64), Some(START_OFFSET + 128));
-        assert_eq!(alloc_inner(&mut allocs, 256), Some(START_OFFSET + 128 + 64));
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64 + 256));
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64 + 256 + 128));
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64 + 256 + 128 + 128));
-        assert_eq!(alloc_inner(&mut allocs, 0xFF00), None);
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 64), Some(start + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 256), Some(start + 128 + 64));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64 + 256));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64 + 256 + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64 + 256 + 128 + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 0xFF00), None);
 
         // create two holes and fill first hole, interleaved
-        assert_eq!(dealloc_inner(&mut allocs, START_OFFSET + 128 + 64), true);
+        assert_eq!(dealloc_inner(cfg, &mut allocs, start + 128 + 64), true);
         let mut last_alloc = 0;
         // consistency check and print out
         for (&offset, &len) in allocs.iter() {
@@ -305,18 +1837,18 @@ mod tests {
             last_alloc = offset + len;
         }
 
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64));
-        assert_eq!(dealloc_inner(&mut allocs, START_OFFSET + 128 + 64 + 256 + 128), true);
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64 + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64));
+        assert_eq!(dealloc_inner(cfg, &mut allocs, start + 128 + 64 + 256 + 128), true);
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64 + 128));
 
         // alloc something that doesn't fit at all
-        assert_eq!(alloc_inner(&mut allocs, 256), Some(START_OFFSET + 128 + 64 + 256 + 128 + 128 + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 256), Some(start + 128 + 64 + 256 + 128 + 128 + 128));
 
         // fill second hole
-        assert_eq!(alloc_inner(&mut allocs, 128), Some(START_OFFSET + 128 + 64 + 256 + 128));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 128), Some(start + 128 + 64 + 256 + 128));
 
         // final tail alloc
-        assert_eq!(alloc_inner(&mut allocs, 64), Some(START_OFFSET + 128 + 64 + 256 + 128 + 128 + 128 + 256));
+        assert_eq!(alloc_inner(cfg, &mut allocs, 64), Some(start + 128 + 64 + 256 + 128 + 128 + 128 + 256));
 
         println!("after structured test:");
         let mut last_alloc = 0;
@@ -335,11 +1867,11 @@ mod tests {
                     //println!("tracker: {:?}", tracker);
                     let index = tracker.remove((rng.next_u32() % tracker.len() as u32) as usize);
                     //println!("removing: {} of {}", index, tracker.len());
-                    assert_eq!(dealloc_inner(&mut allocs, index), true);
+                    assert_eq!(dealloc_inner(cfg, &mut allocs, index), true);
                 }
             } else {
                 let req = rng.next_u32() % 256;
-                if let Some(offset) = alloc_inner(&mut allocs, req) {
+                if let Some(offset) = alloc_inner(cfg, &mut allocs, req) {
                     //println!("tracker: {:?}", tracker);
                     //println!("alloc: {}+{}", offset, req);
                     tracker.push(offset);
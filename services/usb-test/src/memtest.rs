@@ -0,0 +1,184 @@
+use crate::pattern::parse_number;
+use std::collections::BTreeMap;
+
+/// Word patterns `memtest` writes over descriptor RAM. Each word is computed from its address
+/// and the pass, so a fault can be reported with the value that was expected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum MemTestPattern {
+    /// a single set bit, moving one place per word and per pass, so every bit of every word is
+    /// exercised over 32 passes
+    WalkingOnes,
+    /// the complement of `WalkingOnes`: a single clear bit
+    WalkingZeros,
+    /// each word holds its own byte address, which catches aliased or swapped address lines;
+    /// complemented on odd passes, so each address bit is seen both ways
+    AddressInAddress,
+}
+
+impl MemTestPattern {
+    pub(crate) const ALL: [MemTestPattern; 3] =
+        [MemTestPattern::WalkingOnes, MemTestPattern::WalkingZeros, MemTestPattern::AddressInAddress];
+
+    /// the word written at byte address `addr` in pass `pass`
+    pub(crate) fn word(self, pass: usize, addr: usize) -> u32 {
+        let walking = 1u32 << ((addr / 4 + pass) % 32);
+        match self {
+            MemTestPattern::WalkingOnes => walking,
+            MemTestPattern::WalkingZeros => !walking,
+            MemTestPattern::AddressInAddress if pass % 2 == 0 => addr as u32,
+            MemTestPattern::AddressInAddress => !(addr as u32),
+        }
+    }
+}
+
+/// A word that didn't read back as written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct MemFault {
+    pub(crate) addr: usize,
+    pub(crate) expected: u32,
+    pub(crate) actual: u32,
+}
+
+/// Faults found with one pattern, over any number of passes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct PatternResult {
+    /// number of words that didn't match
+    pub(crate) errors: usize,
+    /// the first word that didn't match
+    pub(crate) first: Option<MemFault>,
+}
+
+impl PatternResult {
+    /// Checks `words`, read back from byte address `offset` on, against `pattern` in `pass`, and
+    /// adds up the faults
+    pub(crate) fn check(&mut self, pattern: MemTestPattern, pass: usize, offset: usize, words: &[u32]) {
+        for (i, &actual) in words.iter().enumerate() {
+            let addr = offset + i * 4;
+            let expected = pattern.word(pass, addr);
+            if actual != expected {
+                self.errors += 1;
+                if self.first.is_none() {
+                    self.first = Some(MemFault { addr, expected, actual });
+                }
+            }
+        }
+    }
+}
+
+/// Returns the first allocation in `allocs`, as `offset => len`, that overlaps the `len` bytes
+/// at `offset`
+pub(crate) fn overlapping_alloc(allocs: &BTreeMap<u32, u32>, offset: usize, len: usize) -> Option<(u32, u32)> {
+    let end = offset.saturating_add(len);
+    allocs
+        .iter()
+        .map(|(&o, &l)| (o, l))
+        .find(|&(o, l)| (o as usize) < end && offset < o as usize + l as usize)
+}
+
+/// A run of `memtest` over a region of descriptor RAM. Each pass is its own message to the
+/// main loop, so the abort key is handled between passes.
+pub(crate) struct MemTest {
+    pub(crate) offset: usize,
+    pub(crate) len: usize,
+    pub(crate) passes: usize,
+    /// the pass to run next
+    pub(crate) pass: usize,
+    /// allows the region to overlap live allocations
+    pub(crate) force: bool,
+    pub(crate) results: [PatternResult; 3],
+}
+
+impl MemTest {
+    /// Parses the arguments of `memtest`: `<offset> <len> [passes] [-f]`. The region has to be
+    /// word aligned, as the memory only takes whole-word accesses.
+    pub(crate) fn parse(args: &str) -> Option<MemTest> {
+        let mut tokens: Vec<&str> = args.split(' ').filter(|t| !t.is_empty()).collect();
+        let force = tokens.last() == Some(&"-f");
+        if force {
+            tokens.pop();
+        }
+        let (offset, len, passes) = match tokens[..] {
+            [offset, len] => (parse_number(offset)?, parse_number(len)?, 1),
+            [offset, len, passes] => (parse_number(offset)?, parse_number(len)?, parse_number(passes)?),
+            _ => return None,
+        };
+        if len == 0 || passes == 0 || offset % 4 != 0 || len % 4 != 0 {
+            return None;
+        }
+        Some(MemTest { offset, len, passes, pass: 0, force, results: [PatternResult::default(); 3] })
+    }
+    pub(crate) fn is_done(&self) -> bool {
+        self.pass >= self.passes
+    }
+    pub(crate) fn errors(&self) -> usize {
+        self.results.iter().map(|r| r.errors).sum()
+    }
+    pub(crate) fn log_summary(&self) {
+        log::info!("memtest: {} of {} passes over 0x{:x}+{}, {} errors",
+            self.pass, self.passes, self.offset, self.len, self.errors());
+        for (pattern, result) in MemTestPattern::ALL.iter().zip(self.results.iter()) {
+            match result.first {
+                Some(f) => log::info!("memtest: FAIL {:?}: {} errors, first at 0x{:x}: expected 0x{:08x}, read 0x{:08x}",
+                    pattern, result.errors, f.addr, f.expected, f.actual),
+                None => log::info!("memtest: PASS {:?}", pattern),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memtest_patterns() {
+        assert_eq!(MemTestPattern::WalkingOnes.word(0, 0), 1);
+        assert_eq!(MemTestPattern::WalkingOnes.word(0, 4), 2);
+        assert_eq!(MemTestPattern::WalkingOnes.word(1, 4), 4);
+        assert_eq!(MemTestPattern::WalkingOnes.word(0, 31 * 4), 0x8000_0000);
+        assert_eq!(MemTestPattern::WalkingOnes.word(1, 31 * 4), 1);
+        assert_eq!(MemTestPattern::WalkingZeros.word(0, 4), 0xFFFF_FFFD);
+        assert_eq!(MemTestPattern::AddressInAddress.word(0, 0x120), 0x120);
+        assert_eq!(MemTestPattern::AddressInAddress.word(1, 0x120), !0x120);
+        // over 32 passes, every bit of a word is walked through
+        let bits = (0..32).fold(0, |acc, pass| acc | MemTestPattern::WalkingOnes.word(pass, 0x100));
+        assert_eq!(bits, 0xFFFF_FFFF);
+
+        let mut result = PatternResult::default();
+        let mut words: Vec<u32> = (0..8).map(|i| MemTestPattern::WalkingZeros.word(3, 0x40 + i * 4)).collect();
+        result.check(MemTestPattern::WalkingZeros, 3, 0x40, &words);
+        assert_eq!(result, PatternResult::default());
+        words[2] ^= 0x10;
+        words[5] = 0;
+        result.check(MemTestPattern::WalkingZeros, 3, 0x40, &words);
+        assert_eq!(result.errors, 2);
+        assert_eq!(result.first, Some(MemFault {
+            addr: 0x48,
+            expected: MemTestPattern::WalkingZeros.word(3, 0x48),
+            actual: MemTestPattern::WalkingZeros.word(3, 0x48) ^ 0x10,
+        }));
+    }
+
+    #[test]
+    fn test_memtest_parse() {
+        let test = MemTest::parse("0x100 64").unwrap();
+        assert_eq!((test.offset, test.len, test.passes, test.force), (0x100, 64, 1, false));
+        let test = MemTest::parse("0x100 0x40 32 -f").unwrap();
+        assert_eq!((test.offset, test.len, test.passes, test.force), (0x100, 64, 32, true));
+        assert!(MemTest::parse("0x102 64").is_none());
+        assert!(MemTest::parse("0x100 63").is_none());
+        assert!(MemTest::parse("0x100 0").is_none());
+        assert!(MemTest::parse("0x100 64 0").is_none());
+        assert!(MemTest::parse("0x100").is_none());
+        assert!(MemTest::parse("0x100 64 1 2").is_none());
+
+        let mut allocs = BTreeMap::new();
+        allocs.insert(0x100, 0x40);
+        allocs.insert(0x200, 0x10);
+        assert_eq!(overlapping_alloc(&allocs, 0x0c0, 0x40), None);
+        assert_eq!(overlapping_alloc(&allocs, 0x0c0, 0x44), Some((0x100, 0x40)));
+        assert_eq!(overlapping_alloc(&allocs, 0x13c, 0x10), Some((0x100, 0x40)));
+        assert_eq!(overlapping_alloc(&allocs, 0x140, 0xc0), None);
+        assert_eq!(overlapping_alloc(&allocs, 0x140, 0xc4), Some((0x200, 0x10)));
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::VecDeque;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// max packet size of the bulk endpoints
+pub(crate) const MSC_PACKET_LEN: usize = 64;
+pub(crate) const MSC_BLOCK_LEN: usize = 512;
+/// size of the RAM disk, in blocks
+pub(crate) const MSC_BLOCKS: usize = 128;
+
+const MSC_CLASS: u8 = 0x08;
+/// SCSI transparent command set
+const MSC_SUBCLASS_SCSI: u8 = 0x06;
+/// bulk-only transport
+const MSC_PROTOCOL_BBB: u8 = 0x50;
+const REQ_GET_MAX_LUN: u8 = 0xFE;
+const REQ_BULK_ONLY_RESET: u8 = 0xFF;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LEN: usize = 31;
+/// bit of bmCBWFlags set for transfers from the device to the host
+const CBW_DIR_IN: u8 = 1 << 7;
+
+pub(crate) const CSW_PASSED: u8 = 0;
+pub(crate) const CSW_FAILED: u8 = 1;
+pub(crate) const CSW_PHASE_ERROR: u8 = 2;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_MODE_SENSE_6: u8 = 0x1A;
+const SCSI_PREVENT_ALLOW_REMOVAL: u8 = 0x1E;
+const SCSI_READ_FORMAT_CAPACITIES: u8 = 0x23;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2A;
+
+/// sense key and additional sense code of a command that failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Sense {
+    pub(crate) key: u8,
+    pub(crate) asc: u8,
+}
+const SENSE_NONE: Sense = Sense { key: 0x00, asc: 0x00 };
+pub(crate) const SENSE_INVALID_OPCODE: Sense = Sense { key: 0x05, asc: 0x20 };
+pub(crate) const SENSE_LBA_OUT_OF_RANGE: Sense = Sense { key: 0x05, asc: 0x21 };
+
+/// What a SCSI command needs from the data stage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ScsiData {
+    /// no data; the command is done
+    None,
+    /// data for the host
+    In(Vec<u8>),
+    /// the number of bytes the command takes from the host, to be handed to `RamDisk::write()`
+    Out(usize),
+    /// the command failed, and its sense is kept for REQUEST SENSE
+    Failed,
+}
+
+/// The SCSI block device behind the transport: a RAM disk, lost on every restart
+pub(crate) struct RamDisk {
+    blocks: Vec<u8>,
+    sense: Sense,
+}
+impl RamDisk {
+    pub(crate) fn new() -> RamDisk {
+        RamDisk { blocks: vec![0; MSC_BLOCKS * MSC_BLOCK_LEN], sense: SENSE_NONE }
+    }
+    fn fail(&mut self, sense: Sense) -> ScsiData {
+        self.sense = sense;
+        ScsiData::Failed
+    }
+    /// the byte range of the READ(10) or WRITE(10) in `cb`, if it's on the disk
+    fn range(cb: &[u8]) -> Option<std::ops::Range<usize>> {
+        if cb.len() < 10 {
+            return None;
+        }
+        let lba = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]) as usize;
+        let count = u16::from_be_bytes([cb[7], cb[8]]) as usize;
+        // `usize` is 32 bits on the device, so an LBA near the top of its range would wrap
+        if lba >= MSC_BLOCKS || count > MSC_BLOCKS - lba {
+            return None;
+        }
+        Some(lba * MSC_BLOCK_LEN..(lba + count) * MSC_BLOCK_LEN)
+    }
+    /// Runs the command block `cb`, up to its data stage
+    pub(crate) fn command(&mut self, cb: &[u8]) -> ScsiData {
+        let opcode = match cb.first() {
+            Some(&op) => op,
+            None => return self.fail(SENSE_INVALID_OPCODE),
+        };
+        // allocation length of the commands that have a one-byte one
+        let alloc_len = cb.get(4).copied().unwrap_or(0) as usize;
+        let data = match opcode {
+            SCSI_TEST_UNIT_READY | SCSI_PREVENT_ALLOW_REMOVAL => ScsiData::None,
+            SCSI_REQUEST_SENSE => {
+                let mut sense = vec![0u8; 18];
+                sense[0] = 0x70; // current error, fixed format
+                sense[2] = self.sense.key;
+                sense[7] = 10; // additional sense length
+                sense[12] = self.sense.asc;
+                sense.truncate(alloc_len);
+                ScsiData::In(sense)
+            }
+            SCSI_INQUIRY => {
+                let mut inquiry = vec![
+                    0x00, // direct access block device
+                    0x80, // removable
+                    0x04, // SPC-2
+                    0x02, // response data format
+                    31, // additional length
+                    0, 0, 0,
+                ];
+                inquiry.extend_from_slice(b"Precursr");
+                inquiry.extend_from_slice(b"usb-test ramdisk");
+                inquiry.extend_from_slice(b"0001");
+                inquiry.truncate(alloc_len);
+                ScsiData::In(inquiry)
+            }
+            SCSI_MODE_SENSE_6 => {
+                // mode data length, medium type, no write protect, no block descriptors
+                let mut mode = vec![3, 0, 0, 0];
+                mode.truncate(alloc_len);
+                ScsiData::In(mode)
+            }
+            SCSI_READ_FORMAT_CAPACITIES => {
+                let mut capacities = vec![0, 0, 0, 8];
+                capacities.extend_from_slice(&(MSC_BLOCKS as u32).to_be_bytes());
+                capacities.push(0x02); // formatted media
+                capacities.extend_from_slice(&(MSC_BLOCK_LEN as u32).to_be_bytes()[1..]);
+                ScsiData::In(capacities)
+            }
+            SCSI_READ_CAPACITY_10 => {
+                let mut capacity = Vec::with_capacity(8);
+                capacity.extend_from_slice(&(MSC_BLOCKS as u32 - 1).to_be_bytes());
+                capacity.extend_from_slice(&(MSC_BLOCK_LEN as u32).to_be_bytes());
+                ScsiData::In(capacity)
+            }
+            SCSI_READ_10 => match RamDisk::range(cb) {
+                Some(range) => ScsiData::In(self.blocks[range].to_vec()),
+                None => return self.fail(SENSE_LBA_OUT_OF_RANGE),
+            },
+            // the range is checked once the data is in, as the host sends it either way
+            SCSI_WRITE_10 if cb.len() >= 10 => ScsiData::Out(u16::from_be_bytes([cb[7], cb[8]]) as usize * MSC_BLOCK_LEN),
+            _ => {
+                log::info!("msc: unsupported SCSI command 0x{:02x}", opcode);
+                return self.fail(SENSE_INVALID_OPCODE);
+            }
+        };
+        self.sense = SENSE_NONE;
+        data
+    }
+    /// Completes the command `cb`, that asked for `data` with `ScsiData::Out`. Returns `false`
+    /// if it failed.
+    pub(crate) fn write(&mut self, cb: &[u8], data: &[u8]) -> bool {
+        match RamDisk::range(cb) {
+            Some(range) if cb[0] == SCSI_WRITE_10 && range.len() == data.len() => {
+                self.blocks[range].copy_from_slice(data);
+                true
+            }
+            _ => {
+                self.sense = SENSE_LBA_OUT_OF_RANGE;
+                false
+            }
+        }
+    }
+}
+
+/// A command whose data is still coming in from the host
+struct DataOut {
+    tag: u32,
+    cb: Vec<u8>,
+    /// dCBWDataTransferLength
+    expected: usize,
+    /// bytes the command takes, out of `expected`; the rest is received and dropped
+    used: usize,
+    status: u8,
+    data: Vec<u8>,
+}
+
+/// The bulk-only transport: takes command blocks wrapped in CBWs from the OUT endpoint, and
+/// queues the data and CSW for the IN endpoint.
+///
+/// The data stage is always as long as the host asked for: data the command doesn't have is
+/// padded with zeros, or received and dropped, and counted in the CSW residue. CBWs that aren't
+/// valid are dropped, instead of stalling the endpoints until a reset recovery as the spec has
+/// it.
+pub(crate) struct BulkOnly {
+    disk: RamDisk,
+    data_out: Option<DataOut>,
+    /// packets for the IN endpoint
+    tx_queue: VecDeque<Vec<u8>>,
+    pub(crate) commands: u32,
+    pub(crate) failed: u32,
+    pub(crate) invalid_cbws: u32,
+}
+impl BulkOnly {
+    pub(crate) fn new() -> BulkOnly {
+        BulkOnly {
+            disk: RamDisk::new(),
+            data_out: None,
+            tx_queue: VecDeque::new(),
+            commands: 0,
+            failed: 0,
+            invalid_cbws: 0,
+        }
+    }
+    /// Drops the command in progress, as on a Bulk-Only Mass Storage Reset. The disk is kept.
+    pub(crate) fn reset(&mut self) {
+        self.data_out = None;
+        self.tx_queue.clear();
+    }
+    pub(crate) fn next_packet(&self) -> Option<&Vec<u8>> {
+        self.tx_queue.front()
+    }
+    pub(crate) fn sent(&mut self) {
+        self.tx_queue.pop_front();
+    }
+    fn queue_csw(&mut self, tag: u32, residue: usize, status: u8) {
+        self.commands += 1;
+        if status != CSW_PASSED {
+            self.failed += 1;
+        }
+        let mut csw = Vec::with_capacity(13);
+        csw.extend_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw.extend_from_slice(&tag.to_le_bytes());
+        csw.extend_from_slice(&(residue as u32).to_le_bytes());
+        csw.push(status);
+        self.tx_queue.push_back(csw);
+    }
+    /// queues `data` as the data stage of a command that expects `expected` bytes, then its CSW
+    fn queue_data_in(&mut self, tag: u32, expected: usize, mut data: Vec<u8>, status: u8) {
+        let residue = expected.saturating_sub(data.len());
+        data.resize(expected, 0);
+        for packet in data.chunks(MSC_PACKET_LEN) {
+            self.tx_queue.push_back(packet.to_vec());
+        }
+        self.queue_csw(tag, residue, status);
+    }
+    fn finish_data_out(&mut self) {
+        if let Some(out) = self.data_out.take() {
+            let mut status = out.status;
+            if status == CSW_PASSED && out.used > 0 && !self.disk.write(&out.cb, &out.data[..out.used]) {
+                status = CSW_FAILED;
+            }
+            self.queue_csw(out.tag, out.expected - out.used, status);
+        }
+    }
+    fn start_data_out(&mut self, tag: u32, cb: &[u8], expected: usize, used: usize, status: u8) {
+        self.data_out = Some(DataOut { tag, cb: cb.to_vec(), expected, used, status, data: Vec::with_capacity(expected) });
+        if expected == 0 {
+            self.finish_data_out();
+        }
+    }
+    /// Takes in one packet from the OUT endpoint
+    pub(crate) fn receive(&mut self, packet: &[u8]) {
+        if let Some(out) = self.data_out.as_mut() {
+            let take = packet.len().min(out.expected - out.data.len());
+            out.data.extend_from_slice(&packet[..take]);
+            if out.data.len() == out.expected {
+                self.finish_data_out();
+            }
+            return;
+        }
+        let valid = packet.len() == CBW_LEN
+            && u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]) == CBW_SIGNATURE;
+        let expected = if valid { u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]) as usize } else { 0 };
+        // no command moves more than the whole disk
+        if !valid || expected > MSC_BLOCKS * MSC_BLOCK_LEN {
+            log::warn!("msc: dropping invalid CBW of {} bytes", packet.len());
+            self.invalid_cbws += 1;
+            return;
+        }
+        let tag = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let dir_in = packet[12] & CBW_DIR_IN != 0;
+        let cb_len = (packet[14] as usize).min(16);
+        let cb = &packet[15..15 + cb_len];
+        match self.disk.command(cb) {
+            ScsiData::In(data) if expected > 0 && dir_in => self.queue_data_in(tag, expected, data, CSW_PASSED),
+            ScsiData::Out(len) if len <= expected && (expected == 0 || !dir_in) => {
+                self.start_data_out(tag, cb, expected, len, CSW_PASSED)
+            }
+            // the host and the command disagree on the direction or length of the data
+            ScsiData::In(_) | ScsiData::Out(_) if expected == 0 => self.queue_csw(tag, 0, CSW_PHASE_ERROR),
+            ScsiData::In(_) | ScsiData::Out(_) if dir_in => self.queue_data_in(tag, expected, Vec::new(), CSW_PHASE_ERROR),
+            ScsiData::In(_) | ScsiData::Out(_) => self.start_data_out(tag, cb, expected, 0, CSW_PHASE_ERROR),
+            data @ (ScsiData::None | ScsiData::Failed) => {
+                let status = if data == ScsiData::Failed { CSW_FAILED } else { CSW_PASSED };
+                if dir_in && expected > 0 {
+                    self.queue_data_in(tag, expected, Vec::new(), status);
+                } else {
+                    self.start_data_out(tag, cb, expected, 0, status);
+                }
+            }
+        }
+    }
+}
+
+/// Counters of the mass storage interface, logged by `class`
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct MscStatus {
+    pub(crate) commands: u32,
+    pub(crate) failed: u32,
+    pub(crate) invalid_cbws: u32,
+}
+
+/// A mass storage interface on the bulk-only transport, presenting a `RamDisk` of
+/// `MSC_BLOCKS` blocks
+pub(crate) struct MassStorage<'a, B: UsbBus> {
+    iface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    transport: BulkOnly,
+}
+impl<'a, B: UsbBus> MassStorage<'a, B> {
+    pub(crate) fn new(alloc: &'a UsbBusAllocator<B>) -> MassStorage<'a, B> {
+        MassStorage {
+            iface: alloc.interface(),
+            ep_in: alloc.bulk(MSC_PACKET_LEN as u16),
+            ep_out: alloc.bulk(MSC_PACKET_LEN as u16),
+            transport: BulkOnly::new(),
+        }
+    }
+    pub(crate) fn status(&self) -> MscStatus {
+        MscStatus {
+            commands: self.transport.commands,
+            failed: self.transport.failed,
+            invalid_cbws: self.transport.invalid_cbws,
+        }
+    }
+    /// sends the next queued packet, if the IN endpoint is free
+    fn send_next(&mut self) {
+        if let Some(packet) = self.transport.next_packet() {
+            match self.ep_in.write(packet) {
+                Ok(_) => self.transport.sent(),
+                Err(UsbError::WouldBlock) => {}
+                Err(e) => log::warn!("msc: couldn't send packet: {:?}", e),
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MassStorage<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.iface, MSC_CLASS, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BBB)?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)
+    }
+    fn reset(&mut self) {
+        self.transport.reset();
+    }
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return;
+        }
+        match req.request {
+            REQ_GET_MAX_LUN => {
+                xfer.accept_with(&[0]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.iface) as u16
+        {
+            return;
+        }
+        match req.request {
+            REQ_BULK_ONLY_RESET => {
+                log::info!("msc: bulk-only reset");
+                self.transport.reset();
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut packet = [0u8; MSC_PACKET_LEN];
+        match self.ep_out.read(&mut packet) {
+            Ok(len) => {
+                self.transport.receive(&packet[..len]);
+                self.send_next();
+            }
+            Err(e) => log::warn!("msc: couldn't read packet: {:?}", e),
+        }
+    }
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_in.address() {
+            self.send_next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cbw(tag: u32, expected: u32, dir_in: bool, cb: &[u8]) -> Vec<u8> {
+        let mut cbw = Vec::new();
+        cbw.extend_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw.extend_from_slice(&tag.to_le_bytes());
+        cbw.extend_from_slice(&expected.to_le_bytes());
+        cbw.push(if dir_in { CBW_DIR_IN } else { 0 });
+        cbw.push(0); // LUN
+        cbw.push(cb.len() as u8);
+        cbw.extend_from_slice(cb);
+        cbw.resize(CBW_LEN, 0);
+        cbw
+    }
+    fn rw10(opcode: u8, lba: u32, count: u16) -> Vec<u8> {
+        let mut cb = vec![opcode, 0];
+        cb.extend_from_slice(&lba.to_be_bytes());
+        cb.push(0);
+        cb.extend_from_slice(&count.to_be_bytes());
+        cb.push(0);
+        cb
+    }
+    /// everything the transport has queued for the host, and the CSW that ends it as
+    /// (tag, residue, status)
+    fn drain(bot: &mut BulkOnly) -> (Vec<u8>, (u32, u32, u8)) {
+        let mut packets = Vec::new();
+        while let Some(p) = bot.next_packet() {
+            assert!(p.len() <= MSC_PACKET_LEN);
+            packets.push(p.clone());
+            bot.sent();
+        }
+        let csw = packets.pop().expect("no CSW");
+        assert_eq!(csw.len(), 13);
+        assert_eq!(&csw[..4], &CSW_SIGNATURE.to_le_bytes());
+        let tag = u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]);
+        let residue = u32::from_le_bytes([csw[8], csw[9], csw[10], csw[11]]);
+        (packets.concat(), (tag, residue, csw[12]))
+    }
+
+    #[test]
+    fn test_msc_commands() {
+        let mut bot = BulkOnly::new();
+        bot.receive(&cbw(1, 0, false, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]));
+        assert_eq!(drain(&mut bot), (vec![], (1, 0, CSW_PASSED)));
+
+        bot.receive(&cbw(2, 36, true, &[SCSI_INQUIRY, 0, 0, 0, 36, 0]));
+        let (data, csw) = drain(&mut bot);
+        assert_eq!(csw, (2, 0, CSW_PASSED));
+        assert_eq!(data.len(), 36);
+        assert_eq!(&data[8..16], b"Precursr");
+
+        bot.receive(&cbw(3, 8, true, &[SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        let (data, _) = drain(&mut bot);
+        assert_eq!(data, vec![0, 0, 0, MSC_BLOCKS as u8 - 1, 0, 0, 2, 0]);
+
+        // a block written across several packets reads back the same
+        let block: Vec<u8> = (0..MSC_BLOCK_LEN).map(|i| (i * 7) as u8).collect();
+        bot.receive(&cbw(4, MSC_BLOCK_LEN as u32, false, &rw10(SCSI_WRITE_10, 5, 1)));
+        for packet in block.chunks(MSC_PACKET_LEN) {
+            assert!(bot.next_packet().is_none());
+            bot.receive(packet);
+        }
+        assert_eq!(drain(&mut bot), (vec![], (4, 0, CSW_PASSED)));
+        bot.receive(&cbw(5, MSC_BLOCK_LEN as u32, true, &rw10(SCSI_READ_10, 5, 1)));
+        assert_eq!(drain(&mut bot), (block, (5, 0, CSW_PASSED)));
+
+        // a read past the end fails, and REQUEST SENSE says why
+        bot.receive(&cbw(6, MSC_BLOCK_LEN as u32, true, &rw10(SCSI_READ_10, MSC_BLOCKS as u32, 1)));
+        let (data, csw) = drain(&mut bot);
+        assert_eq!(csw, (6, MSC_BLOCK_LEN as u32, CSW_FAILED));
+        assert!(data.iter().all(|&b| b == 0));
+        bot.receive(&cbw(7, 18, true, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]));
+        let (data, _) = drain(&mut bot);
+        assert_eq!((data[2], data[12]), (SENSE_LBA_OUT_OF_RANGE.key, SENSE_LBA_OUT_OF_RANGE.asc));
+        // and the sense is cleared by the next command that passes
+        bot.receive(&cbw(8, 18, true, &[SCSI_REQUEST_SENSE, 0, 0, 0, 18, 0]));
+        let (data, _) = drain(&mut bot);
+        assert_eq!((data[2], data[12]), (0, 0));
+
+        bot.receive(&cbw(9, 0, false, &[0xC7, 0, 0, 0, 0, 0]));
+        assert_eq!(drain(&mut bot).1, (9, 0, CSW_FAILED));
+        assert_eq!((bot.commands, bot.failed), (9, 2));
+    }
+
+    #[test]
+    fn test_ram_disk_range() {
+        let mut disk = RamDisk::new();
+        let last = MSC_BLOCKS as u32 - 1;
+        assert_eq!(disk.command(&rw10(SCSI_READ_10, last, 1)), ScsiData::In(vec![0; MSC_BLOCK_LEN]));
+        assert_eq!(disk.command(&rw10(SCSI_READ_10, last, 2)), ScsiData::Failed);
+        // an LBA whose end would wrap around is still off the end of the disk
+        assert_eq!(disk.command(&rw10(SCSI_READ_10, 0xFFFF_FFFF, 1)), ScsiData::Failed);
+        assert_eq!(disk.command(&rw10(SCSI_READ_10, 0xFFFF_FFFF, 0xFFFF)), ScsiData::Failed);
+        assert!(!disk.write(&rw10(SCSI_WRITE_10, 0xFFFF_FFFF, 1), &[0; MSC_BLOCK_LEN]));
+        assert_eq!(disk.sense, SENSE_LBA_OUT_OF_RANGE);
+        assert!(disk.write(&rw10(SCSI_WRITE_10, last, 1), &[0; MSC_BLOCK_LEN]));
+    }
+
+    #[test]
+    fn test_msc_transport() {
+        let mut bot = BulkOnly::new();
+        // a host asking for more than there is gets padding, counted in the residue
+        bot.receive(&cbw(1, 64, true, &[SCSI_INQUIRY, 0, 0, 0, 36, 0]));
+        let (data, csw) = drain(&mut bot);
+        assert_eq!((data.len(), csw), (64, (1, 28, CSW_PASSED)));
+        // the data going the other way than the host expects is a phase error
+        bot.receive(&cbw(2, 0, false, &[SCSI_INQUIRY, 0, 0, 0, 36, 0]));
+        assert_eq!(drain(&mut bot).1, (2, 0, CSW_PHASE_ERROR));
+        bot.receive(&cbw(3, MSC_BLOCK_LEN as u32, true, &rw10(SCSI_WRITE_10, 0, 1)));
+        assert_eq!(drain(&mut bot).1, (3, MSC_BLOCK_LEN as u32, CSW_PHASE_ERROR));
+        // data the host sends for a command that takes none is received and dropped
+        bot.receive(&cbw(4, 8, false, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]));
+        assert!(bot.next_packet().is_none());
+        bot.receive(&[0; 8]);
+        assert_eq!(drain(&mut bot), (vec![], (4, 8, CSW_PASSED)));
+
+        // CBWs that aren't valid are dropped
+        let mut bad = cbw(5, 0, false, &[SCSI_TEST_UNIT_READY]);
+        bad[0] = 0;
+        bot.receive(&bad);
+        bot.receive(&bad[..13]);
+        bot.receive(&cbw(6, u32::MAX, true, &rw10(SCSI_READ_10, 0, 1)));
+        assert!(bot.next_packet().is_none());
+        assert_eq!(bot.invalid_cbws, 3);
+
+        // a reset drops the command in progress
+        bot.receive(&cbw(7, MSC_BLOCK_LEN as u32, false, &rw10(SCSI_WRITE_10, 0, 1)));
+        bot.receive(&[0xAA; MSC_PACKET_LEN]);
+        bot.reset();
+        bot.receive(&cbw(8, 0, false, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0]));
+        assert_eq!(drain(&mut bot), (vec![], (8, 0, CSW_PASSED)));
+    }
+}
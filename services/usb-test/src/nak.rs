@@ -0,0 +1,93 @@
+#[derive(Debug, Copy, Clone)]
+struct NakWindow {
+    /// ticktimer time at which the window ends
+    until: u64,
+    /// raw status of the last transfer armed on the endpoint, to be committed when the window ends
+    parked: Option<u32>,
+}
+
+/// Holds endpoints in a NAK state for a while, to exercise host retry and timeout handling.
+///
+/// The core NAKs any transfer on an endpoint that has no descriptor linked to it. A window
+/// unlinks the endpoint's descriptor, and parks the status of any transfer armed while it is
+/// open instead of committing it; when the window ends, the parked status is committed and
+/// the transfer goes ahead.
+pub(crate) struct NakSim {
+    windows: [Option<NakWindow>; 16],
+}
+
+impl NakSim {
+    pub(crate) fn new() -> NakSim {
+        NakSim { windows: [None; 16] }
+    }
+    /// Opens a window on endpoint `index` lasting `duration_ms` from `now`. `armed` is the raw
+    /// status of a transfer that was armed when the window opened, if any. Restarting an open
+    /// window extends it, keeping what it has parked.
+    pub(crate) fn start(&mut self, index: usize, now: u64, duration_ms: u64, armed: Option<u32>) {
+        let parked = match self.windows[index] {
+            Some(w) => w.parked.or(armed),
+            None => armed,
+        };
+        self.windows[index] = Some(NakWindow { until: now + duration_ms, parked });
+    }
+    pub(crate) fn is_active(&self, index: usize) -> bool {
+        self.windows[index].is_some()
+    }
+    /// If a window is open on endpoint `index`, parks `status` to be committed when it closes
+    /// and returns `true`. Returns `false` if the status should be committed now.
+    pub(crate) fn park(&mut self, index: usize, status: u32) -> bool {
+        match &mut self.windows[index] {
+            Some(w) => {
+                w.parked = Some(status);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Closes the windows that have ended as of `now`. Returns the index of each, along with
+    /// the status it parked, if any.
+    pub(crate) fn expire(&mut self, now: u64) -> Vec<(usize, Option<u32>)> {
+        let mut ended = Vec::new();
+        for (index, slot) in self.windows.iter_mut().enumerate() {
+            if let Some(w) = slot {
+                if now >= w.until {
+                    ended.push((index, w.parked));
+                    *slot = None;
+                }
+            }
+        }
+        ended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nak_window() {
+        let mut nak = NakSim::new();
+        // outside a window, transfers are committed directly
+        assert!(!nak.park(1, 0x11));
+
+        nak.start(1, 1000, 500, None);
+        assert!(nak.is_active(1));
+        assert!(!nak.is_active(2));
+        // transfers armed during the window are held back, latest wins
+        assert!(nak.park(1, 0x11));
+        assert!(nak.park(1, 0x12));
+        assert!(nak.expire(1499).is_empty());
+        assert_eq!(nak.expire(1500), vec![(1, Some(0x12))]);
+        assert!(!nak.is_active(1));
+        // and afterwards, they go straight through again
+        assert!(!nak.park(1, 0x13));
+
+        // a transfer armed when the window opened is resumed even if nothing else is armed
+        nak.start(2, 0, 100, Some(0x21));
+        // restarting extends the window and keeps the parked transfer
+        nak.start(2, 50, 100, None);
+        assert!(nak.expire(100).is_empty());
+        nak.start(3, 0, 10, None);
+        assert_eq!(nak.expire(150), vec![(2, Some(0x21)), (3, None)]);
+    }
+}
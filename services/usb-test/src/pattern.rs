@@ -0,0 +1,160 @@
+use rand_chacha::ChaCha8Rng;
+use rand_chacha::rand_core::{SeedableRng, RngCore};
+
+/// Known data patterns for checking the integrity of the USB core's memory and of data sent
+/// over endpoints. Patterns are generated relative to the start of the region being checked,
+/// so a region filled with a pattern can be verified from any other copy of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PatternKind {
+    /// 0, 1, 2, ... wrapping at 255
+    Incrementing,
+    /// pseudo-random bytes from ChaCha8, seeded with the given value
+    Prbs(u64),
+    /// every byte has the given value
+    Constant(u8),
+    /// every word is `POISON_WORD`; freed descriptor memory is filled with this
+    Poison,
+}
+/// fill for freed descriptor memory, when poisoning is on
+pub(crate) const POISON_WORD: u32 = 0xDEAD_DEAD;
+impl PatternKind {
+    /// Parses a pattern from the command line. `arg` is the seed for `prbs`, the value for
+    /// `const`, and is ignored for `inc` and `poison`. A missing `arg` defaults to 0.
+    pub(crate) fn parse(kind: &str, arg: Option<&str>) -> Option<PatternKind> {
+        let arg = match arg {
+            Some(a) => Some(parse_number(a)?),
+            None => None,
+        };
+        match kind {
+            "inc" => Some(PatternKind::Incrementing),
+            "prbs" => Some(PatternKind::Prbs(arg.unwrap_or(0) as u64)),
+            "const" => Some(PatternKind::Constant(arg.unwrap_or(0) as u8)),
+            "poison" => Some(PatternKind::Poison),
+            _ => None,
+        }
+    }
+    pub(crate) fn generator(&self) -> PatternGen {
+        PatternGen {
+            kind: *self,
+            index: 0,
+            rng: match self {
+                PatternKind::Prbs(seed) => Some(ChaCha8Rng::seed_from_u64(*seed)),
+                _ => None,
+            },
+            word: [0; 4],
+        }
+    }
+}
+
+/// An endless stream of bytes in a given pattern
+pub(crate) struct PatternGen {
+    kind: PatternKind,
+    index: usize,
+    rng: Option<ChaCha8Rng>,
+    /// PRBS bytes are drawn from the generator a word at a time
+    word: [u8; 4],
+}
+impl Iterator for PatternGen {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        let b = match self.kind {
+            PatternKind::Incrementing => self.index as u8,
+            PatternKind::Constant(c) => c,
+            PatternKind::Poison => POISON_WORD.to_le_bytes()[self.index % 4],
+            PatternKind::Prbs(_) => {
+                if self.index % 4 == 0 {
+                    self.word = self.rng.as_mut().unwrap().next_u32().to_le_bytes();
+                }
+                self.word[self.index % 4]
+            }
+        };
+        self.index += 1;
+        Some(b)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct VerifyResult {
+    /// offset of the first byte that did not match, relative to the start of the region
+    pub(crate) first_mismatch: Option<usize>,
+    /// total number of bytes that did not match
+    pub(crate) errors: usize,
+}
+
+pub(crate) fn fill(buf: &mut [u8], kind: PatternKind) {
+    for (dst, src) in buf.iter_mut().zip(kind.generator()) {
+        *dst = src;
+    }
+}
+
+pub(crate) fn verify(buf: &[u8], kind: PatternKind) -> VerifyResult {
+    let mut result = VerifyResult { first_mismatch: None, errors: 0 };
+    for (offset, (&actual, expected)) in buf.iter().zip(kind.generator()).enumerate() {
+        if actual != expected {
+            if result.first_mismatch.is_none() {
+                result.first_mismatch = Some(offset);
+            }
+            result.errors += 1;
+        }
+    }
+    result
+}
+
+/// accepts decimal, or hex with a `0x` prefix
+pub(crate) fn parse_number(s: &str) -> Option<usize> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse::<usize>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prbs_reproducible() {
+        let a: Vec<u8> = PatternKind::Prbs(1234).generator().take(1000).collect();
+        let b: Vec<u8> = PatternKind::Prbs(1234).generator().take(1000).collect();
+        assert_eq!(a, b);
+        // a different seed gives a different stream
+        let c: Vec<u8> = PatternKind::Prbs(1235).generator().take(1000).collect();
+        assert_ne!(a, c);
+        // a prefix of the stream doesn't depend on how much is generated
+        let d: Vec<u8> = PatternKind::Prbs(1234).generator().take(13).collect();
+        assert_eq!(&a[..13], &d[..]);
+        // and the stream matches the generator it's derived from
+        let mut rng = ChaCha8Rng::seed_from_u64(1234);
+        assert_eq!(&a[..4], &rng.next_u32().to_le_bytes());
+        assert_eq!(&a[4..8], &rng.next_u32().to_le_bytes());
+    }
+
+    #[test]
+    fn test_fill_verify() {
+        let mut buf = [0u8; 300];
+        for &kind in [PatternKind::Incrementing, PatternKind::Prbs(7), PatternKind::Constant(0xa5), PatternKind::Poison].iter() {
+            fill(&mut buf, kind);
+            assert_eq!(verify(&buf, kind), VerifyResult { first_mismatch: None, errors: 0 });
+        }
+        fill(&mut buf, PatternKind::Incrementing);
+        assert_eq!(buf[255], 255);
+        assert_eq!(buf[256], 0);
+
+        fill(&mut buf, PatternKind::Prbs(7));
+        buf[17] ^= 0x10;
+        buf[200] ^= 0xff;
+        assert_eq!(verify(&buf, PatternKind::Prbs(7)), VerifyResult { first_mismatch: Some(17), errors: 2 });
+        assert_eq!(verify(&buf, PatternKind::Prbs(8)).first_mismatch, Some(0));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(PatternKind::parse("inc", None), Some(PatternKind::Incrementing));
+        assert_eq!(PatternKind::parse("prbs", Some("0x10")), Some(PatternKind::Prbs(16)));
+        assert_eq!(PatternKind::parse("const", Some("85")), Some(PatternKind::Constant(85)));
+        assert_eq!(PatternKind::parse("const", Some("zz")), None);
+        assert_eq!(PatternKind::parse("poison", None), Some(PatternKind::Poison));
+        assert_eq!(PatternKind::parse("walking", None), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use crate::pattern::POISON_WORD;
+
+/// A descriptor access that landed in a poisoned region
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct PoisonHit {
+    /// `(offset, len)` of the poisoned region
+    pub(crate) region: (u32, u32),
+    /// `false` if the word accessed no longer holds the poison, i.e. something wrote to the
+    /// region after it was freed
+    pub(crate) intact: bool,
+}
+
+/// Debug aid for use-after-free of descriptor RAM. When enabled, freed regions are filled with
+/// `PatternKind::Poison` and remembered until they are allocated again, so a completion or a
+/// stuck transfer whose descriptor sits in one can be flagged.
+pub(crate) struct PoisonMap {
+    enabled: bool,
+    /// key is offset, value is len
+    regions: BTreeMap<u32, u32>,
+}
+
+impl PoisonMap {
+    pub(crate) fn new() -> PoisonMap {
+        PoisonMap {
+            enabled: false,
+            regions: BTreeMap::new(),
+        }
+    }
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.regions.clear();
+        }
+    }
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// Records a freed region as poisoned. Returns `true` if the caller should fill it with the
+    /// poison pattern, i.e. if poisoning is on.
+    pub(crate) fn poison(&mut self, offset: u32, len: u32) -> bool {
+        if self.enabled {
+            self.regions.insert(offset, len);
+        }
+        self.enabled
+    }
+    /// Forgets any poisoned regions overlapping `offset..offset + len`, because it has been
+    /// allocated again.
+    pub(crate) fn unpoison(&mut self, offset: u32, len: u32) {
+        self.regions.retain(|&o, &mut l| o + l <= offset || o >= offset + len);
+    }
+    pub(crate) fn regions(&self) -> impl Iterator<Item = (&u32, &u32)> {
+        self.regions.iter()
+    }
+    /// Checks an access to the descriptor at `addr`, where `word` is the first word of the
+    /// descriptor as read from RAM.
+    pub(crate) fn check_access(&self, addr: u32, word: u32) -> Option<PoisonHit> {
+        let (&offset, &len) = self.regions.range(..=addr).next_back()?;
+        if addr < offset + len {
+            Some(PoisonHit { region: (offset, len), intact: word == POISON_WORD })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{fill, verify, PatternKind};
+
+    #[test]
+    fn test_poisoned_descriptor_access() {
+        // a stand-in for the descriptor RAM, and an endpoint buffer with a descriptor at its head
+        let mut ram = [0u8; 0x200];
+        let (offset, len) = (0x60u32, 0x40u32);
+        let desc_word = |ram: &[u8], addr: u32| {
+            let a = addr as usize;
+            u32::from_le_bytes([ram[a], ram[a + 1], ram[a + 2], ram[a + 3]])
+        };
+        let mut map = PoisonMap::new();
+
+        // with poisoning off, a free leaves nothing behind
+        assert!(!map.poison(offset, len));
+        assert_eq!(map.check_access(offset, desc_word(&ram, offset)), None);
+
+        // free the endpoint buffer with poisoning on
+        map.set_enabled(true);
+        if map.poison(offset, len) {
+            fill(&mut ram[offset as usize..(offset + len) as usize], PatternKind::Poison);
+        }
+        assert!(verify(&ram[offset as usize..(offset + len) as usize], PatternKind::Poison).first_mismatch.is_none());
+
+        // a stale completion that reads the freed descriptor is caught
+        assert_eq!(map.check_access(offset, desc_word(&ram, offset)),
+            Some(PoisonHit { region: (offset, len), intact: true }));
+        // as is one that was written after the free
+        ram[offset as usize + 0x10] = 0x12;
+        assert_eq!(map.check_access(offset + 0x10, desc_word(&ram, offset + 0x10)),
+            Some(PoisonHit { region: (offset, len), intact: false }));
+        // accesses outside the region are fine
+        assert_eq!(map.check_access(offset + len, 0), None);
+        assert_eq!(map.check_access(offset - 16, 0), None);
+
+        // re-allocating any part of the region lifts the poison
+        map.unpoison(offset + 0x20, 0x10);
+        assert_eq!(map.check_access(offset, desc_word(&ram, offset)), None);
+        assert_eq!(map.regions().count(), 0);
+    }
+}
@@ -0,0 +1,60 @@
+/// minimum time between progress lines, so a slow UART isn't flooded
+pub(crate) const PROGRESS_INTERVAL_MS: u64 = 1000;
+
+/// Progress of a long-running command, so it's clear over the UART that the device is still
+/// working. Commands call `update()` as they go, and a line is printed at most once per
+/// `PROGRESS_INTERVAL_MS`, plus once on completion.
+pub(crate) struct Progress {
+    label: &'static str,
+    /// suppresses all output, e.g. when run by `repeat -q`
+    quiet: bool,
+    last_line: Option<u64>,
+}
+
+impl Progress {
+    pub(crate) fn new(label: &'static str, quiet: bool) -> Progress {
+        Progress { label, quiet, last_line: None }
+    }
+    /// Returns the line for `done` of `total` as of `now`, if one is due: on the first update,
+    /// on completion, and otherwise once the interval has passed since the last line.
+    pub(crate) fn line(&mut self, now: u64, done: usize, total: usize) -> Option<String> {
+        if self.quiet {
+            return None;
+        }
+        let due = done >= total
+            || self.last_line.map_or(true, |last| now.saturating_sub(last) >= PROGRESS_INTERVAL_MS);
+        if !due {
+            return None;
+        }
+        self.last_line = Some(now);
+        let percent = if total == 0 { 100 } else { done.min(total) * 100 / total };
+        Some(format!("{}: {}/{} ({}%)", self.label, done, total, percent))
+    }
+    pub(crate) fn update(&mut self, now: u64, done: usize, total: usize) {
+        if let Some(line) = self.line(now, done, total) {
+            log::info!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_throttle() {
+        let mut progress = Progress::new("bench", false);
+        assert_eq!(progress.line(0, 0, 200), Some("bench: 0/200 (0%)".to_string()));
+        // frequent updates are held back until the interval passes
+        assert_eq!(progress.line(10, 1, 200), None);
+        assert_eq!(progress.line(PROGRESS_INTERVAL_MS - 1, 50, 200), None);
+        assert_eq!(progress.line(PROGRESS_INTERVAL_MS, 51, 200), Some("bench: 51/200 (25%)".to_string()));
+        assert_eq!(progress.line(PROGRESS_INTERVAL_MS + 1, 52, 200), None);
+        // but completion is always reported
+        assert_eq!(progress.line(PROGRESS_INTERVAL_MS + 2, 200, 200), Some("bench: 200/200 (100%)".to_string()));
+
+        let mut quiet = Progress::new("bench", true);
+        assert_eq!(quiet.line(0, 0, 200), None);
+        assert_eq!(quiet.line(5000, 200, 200), None);
+    }
+}
@@ -0,0 +1,189 @@
+/// longest a suspend waits for an endpoint to finish the packet it has in flight
+pub(crate) const QUIESCE_TIMEOUT_MS: u64 = 20;
+
+/// The controls of the core that quiescing needs, so the sequence can be run against a
+/// simulated core
+pub(crate) trait EpControl {
+    /// the status word of endpoint `index`, if it has a transfer armed
+    fn armed(&self, index: usize) -> Option<u32>;
+    /// asks the core to hold endpoint `index` once the packet it has in flight, if any, is done
+    fn request_halt(&self, index: usize);
+    /// `true` once the core has acknowledged the halt request
+    fn halt_acked(&self) -> bool;
+    fn release_halt(&self, index: usize);
+    /// unlinks the transfer armed on endpoint `index`, so it NAKs
+    fn disarm(&self, index: usize);
+    /// restores a status word returned by `armed()`
+    fn rearm(&self, index: usize, status: u32);
+}
+
+/// Transfers that were armed when a suspend came in. They're disarmed before the core's
+/// memory is saved, so the core doesn't come back mid-packet, and armed again on resume.
+pub(crate) struct Quiesce {
+    parked: [Option<u32>; 16],
+}
+impl Quiesce {
+    pub(crate) fn new() -> Quiesce {
+        Quiesce { parked: [None; 16] }
+    }
+    /// Disarms every endpoint with a transfer armed, waiting up to `timeout_ms` on each for the
+    /// core to go idle. `poll` is called while waiting; it should yield, and returns the time
+    /// in ms. Returns the number of endpoints parked, and how many of them didn't go idle in
+    /// time and were disarmed anyway.
+    pub(crate) fn suspend<C: EpControl, P: FnMut() -> u64>(&mut self, ctl: &C, timeout_ms: u64, mut poll: P) -> (usize, usize) {
+        let (mut parked, mut timeouts) = (0, 0);
+        for index in 0..16 {
+            if ctl.armed(index).is_none() {
+                continue;
+            }
+            ctl.request_halt(index);
+            let start = poll();
+            while !ctl.halt_acked() {
+                if poll().saturating_sub(start) >= timeout_ms {
+                    timeouts += 1;
+                    break;
+                }
+            }
+            // read again, as the status is updated as the packet completes
+            self.parked[index] = ctl.armed(index);
+            ctl.disarm(index);
+            ctl.release_halt(index);
+            parked += 1;
+        }
+        (parked, timeouts)
+    }
+    /// Arms the transfers parked by `suspend()` again. Returns the number re-armed.
+    pub(crate) fn resume<C: EpControl>(&mut self, ctl: &C) -> usize {
+        let mut rearmed = 0;
+        for (index, parked) in self.parked.iter_mut().enumerate() {
+            if let Some(status) = parked.take() {
+                ctl.rearm(index, status);
+                rearmed += 1;
+            }
+        }
+        rearmed
+    }
+}
+
+/// The parts of usb-test that are suspended and resumed in turn
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Subsystem {
+    Keyboard,
+    Usb,
+}
+impl Subsystem {
+    fn parse(s: &str) -> Option<Subsystem> {
+        match s {
+            "kbd" => Some(Subsystem::Keyboard),
+            "usb" => Some(Subsystem::Usb),
+            _ => None,
+        }
+    }
+}
+
+/// The order subsystems are suspended and resumed in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct PowerOrder {
+    pub(crate) suspend: [Subsystem; 2],
+    pub(crate) resume: [Subsystem; 2],
+}
+impl Default for PowerOrder {
+    fn default() -> PowerOrder {
+        PowerOrder {
+            suspend: [Subsystem::Keyboard, Subsystem::Usb],
+            resume: [Subsystem::Keyboard, Subsystem::Usb],
+        }
+    }
+}
+/// Parses an order such as "usb,kbd", which must name each subsystem once
+pub(crate) fn parse_order(s: &str) -> Option<[Subsystem; 2]> {
+    let mut order = s.split(',').map(|t| Subsystem::parse(t.trim()));
+    match (order.next(), order.next(), order.next()) {
+        (Some(Some(a)), Some(Some(b)), None) if a != b => Some([a, b]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    /// a core with a transfer in flight on ep2, which takes a few polls to finish its packet
+    struct SimCore {
+        status: RefCell<[u32; 16]>,
+        /// polls until the halt is acknowledged
+        busy_polls: Cell<u32>,
+        halted: Cell<Option<usize>>,
+    }
+    impl EpControl for SimCore {
+        fn armed(&self, index: usize) -> Option<u32> {
+            let status = self.status.borrow()[index];
+            if status & 0xFFF0 != 0 { Some(status) } else { None }
+        }
+        fn request_halt(&self, index: usize) {
+            self.halted.set(Some(index));
+        }
+        fn halt_acked(&self) -> bool {
+            match self.busy_polls.get() {
+                0 => true,
+                n => {
+                    self.busy_polls.set(n - 1);
+                    if n == 1 {
+                        // the packet completes: the data phase toggles
+                        self.status.borrow_mut()[2] ^= 0b1000;
+                    }
+                    false
+                }
+            }
+        }
+        fn release_halt(&self, _index: usize) {
+            self.halted.set(None);
+        }
+        fn disarm(&self, index: usize) {
+            self.status.borrow_mut()[index] &= !0xFFF0;
+        }
+        fn rearm(&self, index: usize, status: u32) {
+            self.status.borrow_mut()[index] = status;
+        }
+    }
+
+    #[test]
+    fn test_quiesce_mid_transfer() {
+        let mut status = [0u32; 16];
+        status[2] = 0x0400_0001 | (0x30 << 4); // enabled, descriptor at 0x300
+        let core = SimCore { status: RefCell::new(status), busy_polls: Cell::new(3), halted: Cell::new(None) };
+        let mut quiesce = Quiesce::new();
+        let mut clock = 0;
+        assert_eq!(quiesce.suspend(&core, QUIESCE_TIMEOUT_MS, || { clock += 1; clock }), (1, 0));
+        // disarmed, and no longer held
+        assert_eq!(core.armed(2), None);
+        assert_eq!(core.halted.get(), None);
+
+        // the suspend saves and restores the core's memory, after which the transfer is armed
+        // again as it stood once the packet in flight had completed
+        assert_eq!(quiesce.resume(&core), 1);
+        assert_eq!(core.armed(2), Some(0x0400_0001 | (0x30 << 4) | 0b1000));
+        // resuming again doesn't arm anything twice
+        assert_eq!(quiesce.resume(&core), 0);
+
+        // a core that never goes idle holds the suspend up only for the timeout
+        let core = SimCore { status: RefCell::new(status), busy_polls: Cell::new(u32::MAX), halted: Cell::new(None) };
+        let mut clock = 0;
+        assert_eq!(quiesce.suspend(&core, QUIESCE_TIMEOUT_MS, || { clock += 1; clock }), (1, 1));
+        assert!(clock <= QUIESCE_TIMEOUT_MS + 2);
+        assert_eq!(core.armed(2), None);
+    }
+
+    #[test]
+    fn test_power_order() {
+        let order = PowerOrder::default();
+        assert_eq!(order.resume, [Subsystem::Keyboard, Subsystem::Usb]);
+        assert_eq!(parse_order("usb,kbd"), Some([Subsystem::Usb, Subsystem::Keyboard]));
+        assert_eq!(parse_order(" kbd , usb"), Some([Subsystem::Keyboard, Subsystem::Usb]));
+        assert_eq!(parse_order("usb,usb"), None);
+        assert_eq!(parse_order("usb"), None);
+        assert_eq!(parse_order("usb,kbd,usb"), None);
+        assert_eq!(parse_order("usb,mouse"), None);
+    }
+}
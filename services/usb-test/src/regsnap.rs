@@ -0,0 +1,72 @@
+/// One register as captured by a `RegSnapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RegValue {
+    pub(crate) name: String,
+    pub(crate) value: u32,
+    /// the register changes on its own, e.g. the frame counter, so it's left out of diffs
+    pub(crate) free_running: bool,
+}
+
+/// A register whose value differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RegChange {
+    pub(crate) name: String,
+    pub(crate) old: u32,
+    pub(crate) new: u32,
+}
+
+/// The value of every readable register of the core at one point in time, in a fixed order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RegSnapshot {
+    pub(crate) regs: Vec<RegValue>,
+}
+impl RegSnapshot {
+    pub(crate) fn new() -> RegSnapshot {
+        RegSnapshot { regs: Vec::new() }
+    }
+    pub(crate) fn push(&mut self, name: &str, value: u32) {
+        self.regs.push(RegValue { name: name.to_string(), value, free_running: false });
+    }
+    pub(crate) fn push_free_running(&mut self, name: &str, value: u32) {
+        self.regs.push(RegValue { name: name.to_string(), value, free_running: true });
+    }
+    /// Lists the registers that changed from `self` to `later`, leaving out free-running ones.
+    /// Registers are matched by name, so snapshots need not list them in the same order.
+    pub(crate) fn diff(&self, later: &RegSnapshot) -> Vec<RegChange> {
+        later.regs.iter().filter(|r| !r.free_running).filter_map(|r| {
+            match self.regs.iter().find(|old| old.name == r.name) {
+                Some(old) if old.value == r.value => None,
+                Some(old) => Some(RegChange { name: r.name.clone(), old: old.value, new: r.value }),
+                None => None,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reg_diff() {
+        let mut before = RegSnapshot::new();
+        before.push_free_running("frame_id", 100);
+        before.push("address", 0);
+        before.push("config", 0x10);
+        before.push("ep0_status", 0x8000_0041);
+        let mut after = RegSnapshot::new();
+        after.push_free_running("frame_id", 340);
+        after.push("address", 5);
+        after.push("config", 0x10);
+        after.push("ep0_status", 0x8000_0001);
+
+        assert!(before.diff(&before).is_empty());
+        assert_eq!(before.diff(&after), vec![
+            RegChange { name: "address".to_string(), old: 0, new: 5 },
+            RegChange { name: "ep0_status".to_string(), old: 0x8000_0041, new: 0x8000_0001 },
+        ]);
+        // registers missing from the baseline can't be compared
+        after.push("usbselect", 1);
+        assert_eq!(before.diff(&after).len(), 2);
+    }
+}
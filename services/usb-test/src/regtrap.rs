@@ -0,0 +1,163 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// most registers that can be watched at once
+pub(crate) const MAX_TRAPS: usize = 4;
+/// offset of the last register in the UDC register bank, RAMSIZE
+pub(crate) const MAX_TRAP_OFFSET: usize = 0x20;
+
+/// The accesses a trap fires on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TrapAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+impl TrapAccess {
+    pub(crate) fn parse(s: &str) -> Option<TrapAccess> {
+        match s {
+            "r" => Some(TrapAccess::Read),
+            "w" => Some(TrapAccess::Write),
+            "rw" => Some(TrapAccess::ReadWrite),
+            _ => None,
+        }
+    }
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            TrapAccess::Read => "r",
+            TrapAccess::Write => "w",
+            TrapAccess::ReadWrite => "rw",
+        }
+    }
+    fn matches(self, write: bool) -> bool {
+        match self {
+            TrapAccess::Read => !write,
+            TrapAccess::Write => write,
+            TrapAccess::ReadWrite => true,
+        }
+    }
+}
+
+/// A watched register, by its offset in the UDC register bank
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct RegTrap {
+    pub(crate) offset: usize,
+    pub(crate) access: TrapAccess,
+    pub(crate) hits: u32,
+}
+
+/// An access that set off a trap
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct TrapHit {
+    /// counts every hit on every trap, so hits can be put in order across registers
+    pub(crate) seq: u32,
+    pub(crate) offset: usize,
+    pub(crate) write: bool,
+    pub(crate) value: u32,
+    /// the shell command running when it happened, if any
+    pub(crate) command: Option<&'static str>,
+}
+
+/// The traps set with `trap add`, and the shell command that's running, to pin accesses on
+#[derive(Debug, Default)]
+pub(crate) struct TrapTable {
+    traps: Vec<RegTrap>,
+    seq: u32,
+    command: Option<&'static str>,
+}
+impl TrapTable {
+    /// Watches `offset` for `access`, replacing any trap already on it. Returns `false` if
+    /// there's no room for another trap.
+    pub(crate) fn add(&mut self, offset: usize, access: TrapAccess) -> bool {
+        if let Some(trap) = self.traps.iter_mut().find(|t| t.offset == offset) {
+            trap.access = access;
+            return true;
+        }
+        if self.traps.len() >= MAX_TRAPS {
+            return false;
+        }
+        self.traps.push(RegTrap { offset, access, hits: 0 });
+        true
+    }
+    pub(crate) fn clear(&mut self) {
+        self.traps.clear();
+    }
+    pub(crate) fn traps(&self) -> &[RegTrap] {
+        &self.traps
+    }
+    pub(crate) fn set_command(&mut self, command: Option<&'static str>) {
+        self.command = command;
+    }
+    /// Checks an access of `value` to `offset` against the traps. Returns the hit if one fired.
+    pub(crate) fn access(&mut self, offset: usize, write: bool, value: u32) -> Option<TrapHit> {
+        let trap = self.traps.iter_mut().find(|t| t.offset == offset && t.access.matches(write))?;
+        trap.hits += 1;
+        self.seq += 1;
+        Some(TrapHit { seq: self.seq, offset, write, value, command: self.command })
+    }
+}
+
+/// The trap table as the register accessors see it. The count of traps is kept outside the
+/// lock, so an accessor only pays for an atomic load while no trap is set.
+#[derive(Debug, Default)]
+pub(crate) struct RegTraps {
+    armed: AtomicUsize,
+    table: Mutex<TrapTable>,
+}
+impl RegTraps {
+    /// Runs `f` on the table, and keeps the count of traps in step with it
+    pub(crate) fn with_table<R>(&self, f: impl FnOnce(&mut TrapTable) -> R) -> R {
+        let mut table = self.table.lock().unwrap();
+        let result = f(&mut table);
+        self.armed.store(table.traps().len(), Ordering::SeqCst);
+        result
+    }
+    /// Logs the access of `value` to `offset` if it sets off a trap
+    pub(crate) fn check(&self, offset: usize, write: bool, value: u32) {
+        if self.armed.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        if let Some(hit) = self.table.lock().unwrap().access(offset, write, value) {
+            log::warn!("trap #{}: {} 0x{:02x} = 0x{:08x} during {}", hit.seq,
+                if hit.write { "write" } else { "read" }, hit.offset, hit.value, hit.command.unwrap_or("no command"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reg_traps() {
+        assert_eq!(TrapAccess::parse("rw"), Some(TrapAccess::ReadWrite));
+        assert_eq!(TrapAccess::parse("x"), None);
+
+        let mut table = TrapTable::default();
+        assert!(table.add(0x10, TrapAccess::Write));
+        assert!(table.add(0x8, TrapAccess::Read));
+        // reads of a write trap pass unnoticed
+        assert_eq!(table.access(0x10, false, 1), None);
+        table.set_command(Some("reenumerate"));
+        assert_eq!(table.access(0x10, true, 0x5),
+            Some(TrapHit { seq: 1, offset: 0x10, write: true, value: 0x5, command: Some("reenumerate") }));
+        table.set_command(None);
+        assert_eq!(table.access(0x8, false, 0x1_0000).map(|h| (h.seq, h.command)), Some((2, None)));
+        assert_eq!(table.access(0x4, true, 0), None);
+        // re-adding an offset changes its access, and keeps its count
+        assert!(table.add(0x10, TrapAccess::ReadWrite));
+        assert!(table.access(0x10, false, 0).is_some());
+        assert_eq!(table.traps()[0], RegTrap { offset: 0x10, access: TrapAccess::ReadWrite, hits: 2 });
+        assert!(table.add(0x0, TrapAccess::Read));
+        assert!(table.add(0xC, TrapAccess::Read));
+        assert!(!table.add(0x20, TrapAccess::Read));
+
+        let traps = RegTraps::default();
+        traps.with_table(|t| t.add(0x10, TrapAccess::Write));
+        traps.check(0x10, true, 3);
+        traps.check(0x10, true, 4);
+        assert_eq!(traps.with_table(|t| t.traps()[0].hits), 2);
+        traps.with_table(|t| t.clear());
+        assert_eq!(traps.armed.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::pattern::parse_number;
+
+/// A command being run over and over by `repeat`. Each iteration is its own `DoCmd`, so other
+/// messages, such as the abort key, are handled between iterations.
+pub(crate) struct RepeatRun {
+    pub(crate) command: String,
+    count: u32,
+    /// carry on past failures, rather than stopping at the first
+    keep_going: bool,
+    /// suppresses progress output from the command
+    pub(crate) quiet: bool,
+    pub(crate) iterations: u32,
+    pub(crate) failures: u32,
+    started: u64,
+    min_ms: u64,
+    max_ms: u64,
+    total_ms: u64,
+}
+
+/// Summary of a `RepeatRun`, with durations in ms
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct RepeatSummary {
+    pub(crate) iterations: u32,
+    pub(crate) failures: u32,
+    pub(crate) min: u64,
+    pub(crate) avg: u64,
+    pub(crate) max: u64,
+}
+
+impl RepeatRun {
+    /// Parses the arguments of `repeat`: `<count> [-k] [-q] <command...>`. The command can't be
+    /// another `repeat`.
+    pub(crate) fn parse(args: &str) -> Option<RepeatRun> {
+        let (count, rest) = args.trim_start().split_once(' ')?;
+        let count = parse_number(count).filter(|&c| c > 0 && c <= u32::MAX as usize)? as u32;
+        let (mut keep_going, mut quiet) = (false, false);
+        let mut command = rest.trim_start();
+        loop {
+            if let Some(rest) = command.strip_prefix("-k ") {
+                keep_going = true;
+                command = rest.trim_start();
+            } else if let Some(rest) = command.strip_prefix("-q ") {
+                quiet = true;
+                command = rest.trim_start();
+            } else {
+                break;
+            }
+        }
+        if command.is_empty() || command == "repeat" || command.starts_with("repeat ") {
+            return None;
+        }
+        Some(RepeatRun {
+            command: command.to_string(),
+            count,
+            keep_going,
+            quiet,
+            iterations: 0,
+            failures: 0,
+            started: 0,
+            min_ms: u64::MAX,
+            max_ms: 0,
+            total_ms: 0,
+        })
+    }
+    /// Records that an iteration started at `now`
+    pub(crate) fn begin(&mut self, now: u64) {
+        self.started = now;
+    }
+    /// Records that the iteration in progress ended at `now`. Returns `true` if there's another
+    /// to run.
+    pub(crate) fn end(&mut self, now: u64, failed: bool) -> bool {
+        let elapsed = now.saturating_sub(self.started);
+        self.iterations += 1;
+        self.min_ms = self.min_ms.min(elapsed);
+        self.max_ms = self.max_ms.max(elapsed);
+        self.total_ms = self.total_ms.saturating_add(elapsed);
+        if failed {
+            self.failures += 1;
+        }
+        self.iterations < self.count && (self.keep_going || !failed)
+    }
+    pub(crate) fn summary(&self) -> RepeatSummary {
+        match self.iterations {
+            0 => RepeatSummary { iterations: 0, failures: 0, min: 0, avg: 0, max: 0 },
+            n => RepeatSummary {
+                iterations: n,
+                failures: self.failures,
+                min: self.min_ms,
+                avg: self.total_ms / n as u64,
+                max: self.max_ms,
+            },
+        }
+    }
+    pub(crate) fn log_summary(&self) {
+        let s = self.summary();
+        log::info!("repeat: '{}' ran {} of {} times, {} failed, min {}ms avg {}ms max {}ms",
+            self.command, s.iterations, self.count, s.failures, s.min, s.avg, s.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeat_run() {
+        assert!(RepeatRun::parse("0 status").is_none());
+        assert!(RepeatRun::parse("10").is_none());
+        assert!(RepeatRun::parse("10 -k ").is_none());
+        assert!(RepeatRun::parse("x status").is_none());
+        assert!(RepeatRun::parse("10 repeat 10 status").is_none());
+        let run = RepeatRun::parse("0x10 -k pattern fill 0x100 16 ramp").unwrap();
+        assert_eq!(run.command, "pattern fill 0x100 16 ramp");
+        assert_eq!(run.count, 16);
+        assert!(run.keep_going);
+        assert!(!run.quiet);
+        let run = RepeatRun::parse("3 -q -k reset 2").unwrap();
+        assert_eq!(run.command, "reset 2");
+        assert!(run.keep_going && run.quiet);
+
+        // stops at the first failure
+        let mut run = RepeatRun::parse("5 lint").unwrap();
+        assert!(!run.keep_going);
+        run.begin(100);
+        assert!(run.end(110, false));
+        run.begin(110);
+        assert!(!run.end(140, true));
+        assert_eq!(run.summary(), RepeatSummary { iterations: 2, failures: 1, min: 10, avg: 20, max: 30 });
+
+        // or runs to the end with -k
+        let mut run = RepeatRun::parse("3 -k lint").unwrap();
+        for (i, failed) in [true, false, true].iter().enumerate() {
+            run.begin(i as u64 * 10);
+            assert_eq!(run.end(i as u64 * 10 + 5, *failed), i < 2);
+        }
+        assert_eq!(run.summary(), RepeatSummary { iterations: 3, failures: 2, min: 5, avg: 5, max: 5 });
+    }
+}
@@ -0,0 +1,119 @@
+/// longest a cycle waits for the host to configure the device again before it's given up on
+pub(crate) const RESET_TIMEOUT_MS: u64 = 5000;
+
+/// Re-enumeration times gathered by a `ResetBench`, in ms
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ResetStats {
+    pub(crate) cycles: usize,
+    pub(crate) min: u64,
+    pub(crate) max: u64,
+    pub(crate) avg: u64,
+}
+
+/// A run of forced bus resets, timing how long the host takes to configure the device again
+/// after each one.
+///
+/// The device state only changes as bus events are polled, so right after a reset it still
+/// reads as configured. A cycle only completes once the state has been seen to leave the
+/// configured state and come back to it.
+pub(crate) struct ResetBench {
+    count: u32,
+    /// cycles still to be started
+    remaining: u32,
+    /// start of the cycle in progress, and whether the reset has been seen yet
+    started: Option<u64>,
+    left_configured: bool,
+    samples: Vec<u64>,
+    pub(crate) timeouts: u32,
+}
+impl ResetBench {
+    pub(crate) fn new(count: u32) -> ResetBench {
+        ResetBench { count, remaining: count, started: None, left_configured: false, samples: Vec::new(), timeouts: 0 }
+    }
+    /// `true` if another cycle should be started: none is in progress, and there are some left
+    pub(crate) fn wants_reset(&self) -> bool {
+        self.started.is_none() && self.remaining > 0
+    }
+    pub(crate) fn is_done(&self) -> bool {
+        self.started.is_none() && self.remaining == 0
+    }
+    /// the number of cycles run, and the number there are to run in all
+    pub(crate) fn progress(&self) -> (usize, usize) {
+        (self.samples.len() + self.timeouts as usize, self.count as usize)
+    }
+    /// Records that a cycle's reset was released at `now`
+    pub(crate) fn start(&mut self, now: u64) {
+        self.remaining = self.remaining.saturating_sub(1);
+        self.started = Some(now);
+        self.left_configured = false;
+    }
+    /// Records the device state as of `now`. Returns the time the cycle in progress took if
+    /// this completes it.
+    pub(crate) fn observe(&mut self, configured: bool, now: u64) -> Option<u64> {
+        let start = self.started?;
+        if !configured {
+            self.left_configured = true;
+            None
+        } else if self.left_configured {
+            self.started = None;
+            let elapsed = now.saturating_sub(start);
+            self.samples.push(elapsed);
+            Some(elapsed)
+        } else {
+            None
+        }
+    }
+    /// Gives up on the cycle in progress if it has gone on past `RESET_TIMEOUT_MS` as of
+    /// `now`. Returns `true` if it did.
+    pub(crate) fn check_timeout(&mut self, now: u64) -> bool {
+        match self.started {
+            Some(start) if now.saturating_sub(start) >= RESET_TIMEOUT_MS => {
+                self.started = None;
+                self.timeouts += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Statistics over the cycles completed so far, if any were
+    pub(crate) fn stats(&self) -> Option<ResetStats> {
+        let min = *self.samples.iter().min()?;
+        let max = *self.samples.iter().max()?;
+        let cycles = self.samples.len();
+        let avg = self.samples.iter().sum::<u64>() / cycles as u64;
+        Some(ResetStats { cycles, min, max, avg })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_bench() {
+        let mut bench = ResetBench::new(3);
+        assert!(bench.wants_reset());
+        bench.start(1000);
+        assert!(!bench.wants_reset());
+        // stale state from before the reset doesn't count
+        assert_eq!(bench.observe(true, 1001), None);
+        assert_eq!(bench.observe(false, 1010), None);
+        assert_eq!(bench.observe(true, 1120), Some(120));
+
+        bench.start(2000);
+        assert_eq!(bench.observe(false, 2005), None);
+        assert_eq!(bench.observe(true, 2080), Some(80));
+
+        // the host never comes back
+        bench.start(3000);
+        assert_eq!(bench.observe(false, 3005), None);
+        assert!(!bench.check_timeout(3000 + RESET_TIMEOUT_MS - 1));
+        assert!(bench.check_timeout(3000 + RESET_TIMEOUT_MS));
+        assert!(bench.is_done());
+        assert_eq!(bench.progress(), (3, 3));
+        assert_eq!(bench.timeouts, 1);
+        assert_eq!(bench.stats(), Some(ResetStats { cycles: 2, min: 80, max: 120, avg: 100 }));
+
+        assert_eq!(ResetBench::new(1).stats(), None);
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use crate::loglevel::LogModule;
+use std::fmt;
+
+/// number of SETUP packets kept by the trace
+pub(crate) const SETUP_TRACE_LEN: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RequestKind {
+    Standard,
+    Class,
+    Vendor,
+    Reserved,
+}
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RequestRecipient {
+    Device,
+    Interface,
+    Endpoint,
+    Other,
+    Reserved,
+}
+
+/// A SETUP packet, as received on ep0
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SetupPacket {
+    pub(crate) request_type: u8,
+    pub(crate) request: u8,
+    pub(crate) value: u16,
+    pub(crate) index: u16,
+    pub(crate) length: u16,
+}
+impl SetupPacket {
+    pub(crate) fn from_bytes(b: &[u8; 8]) -> SetupPacket {
+        SetupPacket {
+            request_type: b[0],
+            request: b[1],
+            value: u16::from_le_bytes([b[2], b[3]]),
+            index: u16::from_le_bytes([b[4], b[5]]),
+            length: u16::from_le_bytes([b[6], b[7]]),
+        }
+    }
+    /// `true` for device-to-host requests
+    pub(crate) fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+    pub(crate) fn kind(&self) -> RequestKind {
+        match (self.request_type >> 5) & 0b11 {
+            0 => RequestKind::Standard,
+            1 => RequestKind::Class,
+            2 => RequestKind::Vendor,
+            _ => RequestKind::Reserved,
+        }
+    }
+    pub(crate) fn recipient(&self) -> RequestRecipient {
+        match self.request_type & 0x1F {
+            0 => RequestRecipient::Device,
+            1 => RequestRecipient::Interface,
+            2 => RequestRecipient::Endpoint,
+            3 => RequestRecipient::Other,
+            _ => RequestRecipient::Reserved,
+        }
+    }
+    /// name of the request, for standard requests
+    pub(crate) fn request_name(&self) -> Option<&'static str> {
+        if self.kind() != RequestKind::Standard {
+            return None;
+        }
+        Some(match self.request {
+            0 => "GET_STATUS",
+            1 => "CLEAR_FEATURE",
+            3 => "SET_FEATURE",
+            5 => "SET_ADDRESS",
+            6 => "GET_DESCRIPTOR",
+            7 => "SET_DESCRIPTOR",
+            8 => "GET_CONFIGURATION",
+            9 => "SET_CONFIGURATION",
+            10 => "GET_INTERFACE",
+            11 => "SET_INTERFACE",
+            12 => "SYNCH_FRAME",
+            _ => return None,
+        })
+    }
+    /// name of the descriptor type asked for, for standard GET_DESCRIPTOR and SET_DESCRIPTOR
+    fn descriptor_name(&self) -> Option<&'static str> {
+        if self.kind() != RequestKind::Standard || (self.request != 6 && self.request != 7) {
+            return None;
+        }
+        Some(match (self.value >> 8) as u8 {
+            1 => "DEVICE",
+            2 => "CONFIGURATION",
+            3 => "STRING",
+            4 => "INTERFACE",
+            5 => "ENDPOINT",
+            6 => "DEVICE_QUALIFIER",
+            7 => "OTHER_SPEED_CONFIGURATION",
+            0x0F => "BOS",
+            0x21 => "HID",
+            0x22 => "REPORT",
+            _ => return None,
+        })
+    }
+}
+impl fmt::Display for SetupPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?} {:?} ", if self.is_in() { "IN" } else { "OUT" }, self.kind(), self.recipient())?;
+        match (self.request_name(), self.descriptor_name()) {
+            (Some(name), Some(desc)) => write!(f, "{}({})", name, desc)?,
+            (Some(name), None) => write!(f, "{}", name)?,
+            _ => write!(f, "bRequest=0x{:02x}", self.request)?,
+        }
+        write!(f, " wValue=0x{:04x} wIndex=0x{:04x} wLength={}", self.value, self.index, self.length)
+    }
+}
+
+/// What the device did with a SETUP packet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum SetupOutcome {
+    /// nothing has been sent back yet
+    Pending,
+    /// data stage sent to the host, with the total bytes so far
+    Data(usize),
+    /// completed with a zero-length status stage and no data stage
+    Accepted,
+    /// ep0 was stalled
+    Stalled,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SetupRecord {
+    /// ticktimer time the SETUP was received
+    pub(crate) timestamp: u64,
+    pub(crate) packet: SetupPacket,
+    pub(crate) outcome: SetupOutcome,
+}
+
+/// Ring of the most recent SETUP packets and what became of them. Always recording, so it can
+/// be dumped after a failed enumeration; `live` additionally logs each event as it happens,
+/// subject to the level of `LogModule::Setup`.
+pub(crate) struct SetupTrace {
+    live: bool,
+    records: VecDeque<SetupRecord>,
+}
+impl SetupTrace {
+    pub(crate) fn new() -> SetupTrace {
+        SetupTrace {
+            live: false,
+            records: VecDeque::with_capacity(SETUP_TRACE_LEN),
+        }
+    }
+    pub(crate) fn set_live(&mut self, live: bool) {
+        self.live = live;
+    }
+    pub(crate) fn live(&self) -> bool {
+        self.live
+    }
+    pub(crate) fn records(&self) -> impl Iterator<Item = &SetupRecord> {
+        self.records.iter()
+    }
+    pub(crate) fn setup(&mut self, timestamp: u64, bytes: &[u8; 8]) {
+        let packet = SetupPacket::from_bytes(bytes);
+        if self.live {
+            module_log!(LogModule::Setup, log::Level::Info, "setup: {}", packet);
+        }
+        if self.records.len() == SETUP_TRACE_LEN {
+            self.records.pop_front();
+        }
+        self.records.push_back(SetupRecord { timestamp, packet, outcome: SetupOutcome::Pending });
+    }
+    /// records a packet of `len` bytes written to ep0 in answer to the latest SETUP
+    pub(crate) fn ep0_write(&mut self, len: usize) {
+        let live = self.live;
+        if let Some(r) = self.records.back_mut() {
+            r.outcome = match (r.outcome, len) {
+                (SetupOutcome::Pending, 0) => SetupOutcome::Accepted,
+                (SetupOutcome::Data(sent), _) => SetupOutcome::Data(sent + len),
+                (SetupOutcome::Pending, _) => SetupOutcome::Data(len),
+                // the status stage of a data OUT request, or anything after a stall
+                (outcome, _) => outcome,
+            };
+            if live {
+                module_log!(LogModule::Setup, log::Level::Info, "setup:   -> {:?}", r.outcome);
+            }
+        }
+    }
+    pub(crate) fn ep0_stall(&mut self) {
+        if let Some(r) = self.records.back_mut() {
+            r.outcome = SetupOutcome::Stalled;
+            if self.live {
+                module_log!(LogModule::Setup, log::Level::Info, "setup:   -> Stalled");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_decode() {
+        // standard GET_DESCRIPTOR(CONFIGURATION), device-to-host
+        let p = SetupPacket::from_bytes(&[0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xFF, 0x00]);
+        assert!(p.is_in());
+        assert_eq!((p.kind(), p.recipient()), (RequestKind::Standard, RequestRecipient::Device));
+        assert_eq!(p.request_name(), Some("GET_DESCRIPTOR"));
+        assert_eq!(format!("{}", p), "IN Standard Device GET_DESCRIPTOR(CONFIGURATION) wValue=0x0200 wIndex=0x0000 wLength=255");
+
+        // standard SET_ADDRESS, host-to-device
+        let p = SetupPacket::from_bytes(&[0x00, 0x05, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(format!("{}", p), "OUT Standard Device SET_ADDRESS wValue=0x0007 wIndex=0x0000 wLength=0");
+
+        // standard CLEAR_FEATURE(ENDPOINT_HALT) on ep1 IN
+        let p = SetupPacket::from_bytes(&[0x02, 0x01, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00]);
+        assert_eq!(p.recipient(), RequestRecipient::Endpoint);
+        assert_eq!(p.request_name(), Some("CLEAR_FEATURE"));
+        assert_eq!(p.index, 0x81);
+
+        // class: HID SET_IDLE to interface 1; bRequest numbers aren't named outside standard
+        let p = SetupPacket::from_bytes(&[0x21, 0x0A, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!((p.kind(), p.recipient()), (RequestKind::Class, RequestRecipient::Interface));
+        assert_eq!(p.request_name(), None);
+        assert_eq!(format!("{}", p), "OUT Class Interface bRequest=0x0a wValue=0x0000 wIndex=0x0001 wLength=0");
+
+        // vendor, device-to-host, with a large wLength
+        let p = SetupPacket::from_bytes(&[0xC0, 0x01, 0x34, 0x12, 0x78, 0x56, 0x00, 0x10]);
+        assert_eq!((p.kind(), p.recipient()), (RequestKind::Vendor, RequestRecipient::Device));
+        assert_eq!(p.request_name(), None);
+        assert_eq!((p.value, p.index, p.length), (0x1234, 0x5678, 0x1000));
+
+        // reserved type and recipient
+        let p = SetupPacket::from_bytes(&[0x7F, 0x06, 0, 0, 0, 0, 0, 0]);
+        assert_eq!((p.kind(), p.recipient()), (RequestKind::Reserved, RequestRecipient::Reserved));
+    }
+
+    #[test]
+    fn test_setup_trace() {
+        let mut trace = SetupTrace::new();
+        // outcomes before any SETUP are ignored
+        trace.ep0_write(8);
+        assert_eq!(trace.records().count(), 0);
+
+        trace.setup(1, &[0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xFF, 0x00]);
+        trace.ep0_write(64);
+        trace.ep0_write(20);
+        trace.setup(2, &[0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        trace.ep0_write(0);
+        trace.setup(3, &[0xC0, 0x01, 0, 0, 0, 0, 4, 0]);
+        trace.ep0_stall();
+        let outcomes: Vec<SetupOutcome> = trace.records().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, vec![SetupOutcome::Data(84), SetupOutcome::Accepted, SetupOutcome::Stalled]);
+
+        // the ring keeps the latest SETUP_TRACE_LEN packets
+        for t in 0..SETUP_TRACE_LEN as u64 {
+            trace.setup(100 + t, &[0; 8]);
+        }
+        assert_eq!(trace.records().count(), SETUP_TRACE_LEN);
+        assert_eq!(trace.records().next().unwrap().timestamp, 100);
+    }
+}
@@ -7,6 +7,8 @@ use usb_device::UsbDirection;
 use std::fmt;
 use core::sync::atomic::{AtomicPtr, Ordering};
 use core::mem::size_of;
+use std::sync::Arc;
+use crate::RegTraps;
 
 pub(crate) const NUM_ENDPOINTS: usize = 16;
 
@@ -77,6 +79,8 @@ bitfield! {
 /// This is located at 0xFF00 offset from the base of the memory region open for the UDC
 pub struct SpinalUdcRegs {
     regs: AtomicPtr<u32>,
+    /// watched registers, shared by every clone; see `trap`
+    traps: Arc<RegTraps>,
 }
 // constants in UsbDeviceCtrl.scala (L111-116) are in hex
 const FRAME_OFFSET: usize = 0x0;
@@ -89,75 +93,73 @@ const RAMSIZE_OFFSET: usize = 0x20;
 impl SpinalUdcRegs {
     pub fn new(ptr: *mut u32) -> SpinalUdcRegs {
         SpinalUdcRegs {
-            regs: AtomicPtr::new(ptr)
+            regs: AtomicPtr::new(ptr),
+            traps: Arc::new(RegTraps::default()),
         }
     }
     pub fn clone(&self) -> SpinalUdcRegs {
         SpinalUdcRegs {
-            regs: AtomicPtr::new(self.regs.load(Ordering::SeqCst))
+            regs: AtomicPtr::new(self.regs.load(Ordering::SeqCst)),
+            traps: self.traps.clone(),
         }
     }
-    /// current USB frame ID
-    pub fn frame_id(&self) -> u32 {
+    pub(crate) fn traps(&self) -> &RegTraps {
+        &self.traps
+    }
+    /// every register read goes through here, so it can be trapped
+    fn read_reg(&self, offset: usize) -> u32 {
+        let value = unsafe {
+            self.regs.load(Ordering::SeqCst).add(offset / size_of::<u32>()).read_volatile()
+        };
+        self.traps.check(offset, false, value);
+        value
+    }
+    /// every register write goes through here, so it can be trapped
+    fn write_reg(&self, offset: usize, value: u32) {
+        self.traps.check(offset, true, value);
         unsafe {
-            self.regs.load(Ordering::SeqCst).add(FRAME_OFFSET / size_of::<u32>()).read_volatile()
+            self.regs.load(Ordering::SeqCst).add(offset / size_of::<u32>()).write_volatile(value);
         }
     }
+    /// current USB frame ID
+    pub fn frame_id(&self) -> u32 {
+        self.read_reg(FRAME_OFFSET)
+    }
     /// currently active address for tokens. cleared by USB reset
     pub fn address(&self) -> u32 {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(ADDRESS_OFFSET / size_of::<u32>()).read_volatile()
-        }
+        self.read_reg(ADDRESS_OFFSET)
     }
     pub fn set_address(&self, addr: u32) {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(ADDRESS_OFFSET / size_of::<u32>()).write_volatile(addr);
-        }
+        self.write_reg(ADDRESS_OFFSET, addr);
     }
     /// interrupt flags
     pub fn interrupts(&self) -> UdcInterrupts {
-        unsafe {
-            UdcInterrupts(self.regs.load(Ordering::SeqCst).add(INT_OFFSET / size_of::<u32>()).read_volatile())
-        }
+        UdcInterrupts(self.read_reg(INT_OFFSET))
     }
     pub fn clear_some_interrupts(&self, ints: UdcInterrupts) {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(INT_OFFSET / size_of::<u32>()).write_volatile(ints.0);
-        }
+        self.write_reg(INT_OFFSET, ints.0);
     }
     pub fn clear_all_interrupts(&self) {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(INT_OFFSET / size_of::<u32>()).write_volatile(0xffff_ffff);
-        }
+        self.write_reg(INT_OFFSET, 0xffff_ffff);
     }
     /// halt - use this to pause an endpoint to give the CPU a mutex on r/w access to its registers
     pub fn halt(&self) -> UdcHalt {
-        unsafe {
-            UdcHalt(self.regs.load(Ordering::SeqCst).add(HALT_OFFSET / size_of::<u32>()).read_volatile())
-        }
+        UdcHalt(self.read_reg(HALT_OFFSET))
     }
     pub fn set_halt(&self, halt: UdcHalt) {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(HALT_OFFSET / size_of::<u32>()).write_volatile(halt.0)
-        }
+        self.write_reg(HALT_OFFSET, halt.0)
     }
     /// config
     pub fn config(&self) -> UdcConfig {
-        unsafe {
-            UdcConfig(self.regs.load(Ordering::SeqCst).add(CONFIG_OFFSET / size_of::<u32>()).read_volatile())
-        }
+        UdcConfig(self.read_reg(CONFIG_OFFSET))
     }
     pub fn set_config(&self, cfg: UdcConfig) {
-        unsafe {
-            self.regs.load(Ordering::SeqCst).add(CONFIG_OFFSET / size_of::<u32>()).write_volatile(cfg.0)
-        }
+        self.write_reg(CONFIG_OFFSET, cfg.0)
     }
     /// the ram starting at 0 has a size of 1 << ramsize. Only the lower 4 bits are valid, but the field takes up a u32
     /// the returned value is the properly computed bytes as read out by the hardware field (no further maths needed)
     pub fn ramsize(&self) -> u32 {
-        1 << (unsafe {
-            self.regs.load(Ordering::SeqCst).add(RAMSIZE_OFFSET / size_of::<u32>()).read_volatile()
-        } & 0xF)
+        1 << (self.read_reg(RAMSIZE_OFFSET) & 0xF)
     }
 }
 impl fmt::Debug for SpinalUdcRegs {
@@ -251,6 +253,10 @@ impl SpinalUdcDescriptor {
     pub fn in_progress(&self) -> bool {
         UdcDesc0(self.read(0)).code() == 0xF
     }
+    /// completion code: 0xF while in progress, 0 on success, anything else is an error
+    pub fn code(&self) -> u32 {
+        UdcDesc0(self.read(0)).code()
+    }
     pub fn next_descriptor_addr(&self) -> usize {
         UdcDesc1(self.read(1)).next_descriptor_addr() as usize
     }
@@ -0,0 +1,133 @@
+use crate::setup::SetupRecord;
+use std::collections::VecDeque;
+use std::fmt;
+use usb_device::UsbDirection;
+
+/// number of events kept by an `EventRing`
+pub(crate) const EVENT_RING_LEN: usize = 32;
+/// number of events `timeline` shows when not told otherwise
+pub(crate) const DEFAULT_TIMELINE_LEN: usize = 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum EventKind {
+    /// a SETUP packet received on ep0, from the setup trace
+    Setup(SetupRecord),
+    /// the latest completion on an endpoint, from its statistics
+    EpActivity(usize),
+    /// a transfer that was armed and is still pending, from the watchdog
+    Armed(usize, UsbDirection),
+    /// a shell command was started
+    CmdStart(&'static str),
+    /// a shell command finished, and whether it failed
+    CmdEnd(&'static str, bool),
+    /// a key was typed on the keyboard and sent to the host
+    Key(char),
+}
+
+/// An event, timestamped with the ticktimer time it happened at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct TimelineEvent {
+    pub(crate) timestamp: u64,
+    pub(crate) kind: EventKind,
+}
+impl fmt::Display for TimelineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms ", self.timestamp)?;
+        match self.kind {
+            EventKind::Setup(r) => write!(f, "setup: {} -> {:?}", r.packet, r.outcome),
+            EventKind::EpActivity(index) => write!(f, "ep{}: last completion", index),
+            EventKind::Armed(index, dir) => write!(f, "watchdog: ep{} {:?} armed", index, dir),
+            EventKind::CmdStart(name) => write!(f, "shell: {} started", name),
+            EventKind::CmdEnd(name, failed) => write!(f, "shell: {} {}", name, if failed { "failed" } else { "done" }),
+            EventKind::Key(key) => write!(f, "kbd: {:?}", key),
+        }
+    }
+}
+
+/// Ring of the most recent events of a source that doesn't keep a record of its own
+pub(crate) struct EventRing {
+    events: VecDeque<TimelineEvent>,
+}
+impl EventRing {
+    pub(crate) fn new() -> EventRing {
+        EventRing { events: VecDeque::with_capacity(EVENT_RING_LEN) }
+    }
+    pub(crate) fn push(&mut self, timestamp: u64, kind: EventKind) {
+        if self.events.len() == EVENT_RING_LEN {
+            self.events.pop_front();
+        }
+        self.events.push_back(TimelineEvent { timestamp, kind });
+    }
+    /// the events, oldest first
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = TimelineEvent> + '_ {
+        self.events.iter().copied()
+    }
+}
+
+/// Merges `sources`, each oldest first, into their `n` most recent events, oldest first. The
+/// sources are walked back from their newest event, so only the events kept are copied. Events
+/// with the same timestamp are kept in the order of their sources.
+pub(crate) fn merge_latest(sources: &mut [&mut dyn DoubleEndedIterator<Item = TimelineEvent>], n: usize) -> Vec<TimelineEvent> {
+    let mut heads: Vec<Option<TimelineEvent>> = sources.iter_mut().map(|s| s.next_back()).collect();
+    let mut merged = Vec::with_capacity(n);
+    while merged.len() < n {
+        // on a tie the later source goes first, as the result is reversed
+        let newest = heads.iter().enumerate().filter_map(|(i, h)| h.map(|e| (e.timestamp, i))).max();
+        match newest {
+            Some((_, i)) => {
+                merged.extend(heads[i].take());
+                heads[i] = sources[i].next_back();
+            }
+            None => break,
+        }
+    }
+    merged.reverse();
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u64, kind: EventKind) -> TimelineEvent {
+        TimelineEvent { timestamp, kind }
+    }
+
+    #[test]
+    fn test_timeline_merge() {
+        let mut shell = EventRing::new();
+        shell.push(10, EventKind::CmdStart("conn"));
+        shell.push(15, EventKind::CmdEnd("conn", false));
+        shell.push(40, EventKind::CmdStart("reset"));
+        let keys = vec![event(12, EventKind::Key('a')), event(40, EventKind::Key('b'))];
+        let eps = vec![event(5, EventKind::EpActivity(1)), event(30, EventKind::EpActivity(2))];
+        let merge = |n: usize| -> Vec<TimelineEvent> {
+            merge_latest(&mut [&mut shell.iter(), &mut keys.iter().copied(), &mut eps.iter().copied()], n)
+        };
+
+        let all = merge(100);
+        let times: Vec<u64> = all.iter().map(|e| e.timestamp).collect();
+        assert_eq!(times, vec![5, 10, 12, 15, 30, 40, 40]);
+        // a tie keeps the order of the sources
+        assert_eq!(all[5].kind, EventKind::CmdStart("reset"));
+        assert_eq!(all[6].kind, EventKind::Key('b'));
+        // only the most recent are kept, still oldest first
+        assert_eq!(merge(3), all[4..].to_vec());
+        assert!(merge(0).is_empty());
+        assert!(merge_latest(&mut [], 10).is_empty());
+
+        assert_eq!(format!("{}", all[3]), "15ms shell: conn done");
+        assert_eq!(format!("{}", event(7, EventKind::Armed(3, UsbDirection::In))), "7ms watchdog: ep3 In armed");
+    }
+
+    #[test]
+    fn test_event_ring() {
+        let mut ring = EventRing::new();
+        for t in 0..EVENT_RING_LEN as u64 + 4 {
+            ring.push(t, EventKind::Key('x'));
+        }
+        assert_eq!(ring.iter().count(), EVENT_RING_LEN);
+        assert_eq!(ring.iter().next().unwrap().timestamp, 4);
+        assert_eq!(ring.iter().next_back().unwrap().timestamp, EVENT_RING_LEN as u64 + 3);
+    }
+}
@@ -0,0 +1,144 @@
+use xous::{CID, Message};
+
+/// VBUS is taken as present above 1.5V: the XADC reads 0.005033V per count, compared here
+/// scaled up by 100_000, as the other VBUS checks in the tree do
+const VBUS_PRESENT_THRESHOLD: u32 = 150_000;
+/// how long VBUS has to hold a new level before it counts as an attach or a detach
+pub(crate) const VBUS_DEBOUNCE_MS: u64 = 500;
+
+/// `true` if the raw XADC VBUS reading `raw` means a host is supplying power
+pub(crate) fn vbus_from_adc(raw: u16) -> bool {
+    (raw as u32) * 503 > VBUS_PRESENT_THRESHOLD
+}
+
+/// Debounces VBUS samples into attach and detach transitions. A cable going in bounces for a
+/// while, so a new level only counts once it has been seen continuously for `debounce_ms`.
+pub(crate) struct VbusDebounce {
+    debounce_ms: u64,
+    /// the level last reported
+    present: bool,
+    /// a level different from `present`, and when it was first seen
+    candidate: Option<(bool, u64)>,
+    pub(crate) attaches: u32,
+    pub(crate) detaches: u32,
+}
+impl VbusDebounce {
+    /// `present` is the level at startup, which isn't reported as a transition
+    pub(crate) fn new(present: bool, debounce_ms: u64) -> VbusDebounce {
+        VbusDebounce { debounce_ms, present, candidate: None, attaches: 0, detaches: 0 }
+    }
+    pub(crate) fn present(&self) -> bool {
+        self.present
+    }
+    /// Takes a sample of VBUS at `now`. Returns the new level if this completes a transition.
+    pub(crate) fn sample(&mut self, present: bool, now: u64) -> Option<bool> {
+        if present == self.present {
+            self.candidate = None;
+            return None;
+        }
+        match self.candidate {
+            Some((level, since)) if level == present => {
+                if now.saturating_sub(since) < self.debounce_ms {
+                    return None;
+                }
+            }
+            _ => {
+                self.candidate = Some((present, now));
+                return None;
+            }
+        }
+        self.candidate = None;
+        self.present = present;
+        if present {
+            self.attaches += 1;
+        } else {
+            self.detaches += 1;
+        }
+        Some(present)
+    }
+}
+
+struct VbusListener {
+    name: String,
+    cid: CID,
+    opcode: usize,
+}
+
+/// Servers that have asked to hear about VBUS attach and detach. Each transition is sent as a
+/// non-blocking scalar message of `(present, 0, 0, 0)`; a listener whose send fails is dropped.
+pub(crate) struct VbusListeners {
+    listeners: Vec<VbusListener>,
+}
+impl VbusListeners {
+    pub(crate) fn new() -> VbusListeners {
+        VbusListeners { listeners: Vec::new() }
+    }
+    pub(crate) fn register(&mut self, name: &str, cid: CID, opcode: usize) {
+        log::info!("registering vbus listener {} with opcode {}", name, opcode);
+        self.listeners.push(VbusListener { name: name.to_string(), cid, opcode });
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.listeners.len()
+    }
+    pub(crate) fn notify(&mut self, present: bool) {
+        self.notify_with(present, |cid, msg| xous::send_message(cid, msg).map(|_| ()));
+    }
+    /// the guts of `notify()`, with the message transport broken out so it can be substituted in tests
+    pub(crate) fn notify_with<F>(&mut self, present: bool, mut send: F)
+    where F: FnMut(CID, Message) -> Result<(), xous::Error>
+    {
+        self.listeners.retain(|l| {
+            let msg = Message::new_scalar(l.opcode, if present { 1 } else { 0 }, 0, 0, 0);
+            match send(l.cid, msg) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::warn!("vbus listener {} failed ({:?}), deregistering", l.name, e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vbus_debounce() {
+        assert!(!vbus_from_adc(2));
+        assert!(vbus_from_adc(1000)); // about 5V
+
+        let mut vbus = VbusDebounce::new(false, VBUS_DEBOUNCE_MS);
+        // a cable going in: bounces, then settles
+        let samples = [(0, true), (250, false), (500, true), (750, true), (1000, true), (1250, true)];
+        let transitions: Vec<(u64, bool)> = samples.iter()
+            .filter_map(|&(now, present)| vbus.sample(present, now).map(|level| (now, level)))
+            .collect();
+        // held from 500, so it's in at 1000 and not before
+        assert_eq!(transitions, vec![(1000, true)]);
+        assert!(vbus.present());
+        // a dropout shorter than the debounce goes unreported
+        assert_eq!(vbus.sample(false, 1500), None);
+        assert_eq!(vbus.sample(true, 1750), None);
+        assert_eq!(vbus.sample(false, 2000), None);
+        assert_eq!(vbus.sample(false, 2500), Some(false));
+        assert_eq!((vbus.attaches, vbus.detaches), (1, 1));
+
+        let mut listeners = VbusListeners::new();
+        listeners.register("status", 1, 10);
+        listeners.register("gone", 2, 20);
+        let mut delivered = Vec::new();
+        listeners.notify_with(true, |cid, msg| {
+            if cid == 2 {
+                return Err(xous::Error::ServerNotFound);
+            }
+            if let Message::Scalar(s) = msg {
+                delivered.push((cid, s.id, s.arg1));
+            }
+            Ok(())
+        });
+        assert_eq!(delivered, vec![(1, 10, 1)]);
+        assert_eq!(listeners.len(), 1);
+    }
+}
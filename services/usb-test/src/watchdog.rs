@@ -0,0 +1,127 @@
+use usb_device::UsbDirection;
+
+/// interval at which the main loop is asked to check for stuck transfers
+pub(crate) const WATCHDOG_TICK_MS: usize = 250;
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ArmedTransfer {
+    pub(crate) dir: UsbDirection,
+    /// ticktimer time at which the endpoint was armed
+    pub(crate) armed_at: u64,
+    /// set once the transfer has been reported, so it's only counted once
+    flagged: bool,
+}
+
+/// A transfer that has been pending for longer than the watchdog threshold
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct StuckTransfer {
+    pub(crate) index: usize,
+    pub(crate) dir: UsbDirection,
+    pub(crate) pending_ms: u64,
+}
+
+/// Tracks when each endpoint was armed, so that transfers the host never completes can be
+/// reported instead of hanging silently.
+pub(crate) struct Watchdog {
+    /// transfers pending longer than this are flagged; `None` disables the watchdog
+    threshold_ms: Option<u64>,
+    /// stall endpoints with stuck transfers
+    kill: bool,
+    armed: [Option<ArmedTransfer>; 16],
+    stuck_transfers: u32,
+}
+
+impl Watchdog {
+    pub(crate) fn new() -> Watchdog {
+        Watchdog {
+            threshold_ms: None,
+            kill: false,
+            armed: [None; 16],
+            stuck_transfers: 0,
+        }
+    }
+    pub(crate) fn set_threshold(&mut self, threshold_ms: Option<u64>) {
+        self.threshold_ms = threshold_ms;
+    }
+    pub(crate) fn threshold(&self) -> Option<u64> {
+        self.threshold_ms
+    }
+    pub(crate) fn set_kill(&mut self, kill: bool) {
+        self.kill = kill;
+    }
+    pub(crate) fn kill(&self) -> bool {
+        self.kill
+    }
+    /// total number of transfers flagged as stuck
+    pub(crate) fn stuck_transfers(&self) -> u32 {
+        self.stuck_transfers
+    }
+    pub(crate) fn armed(&self, index: usize) -> Option<ArmedTransfer> {
+        self.armed[index]
+    }
+    /// records that endpoint `index` was armed at time `now`. Re-arming restarts the clock.
+    pub(crate) fn arm(&mut self, index: usize, dir: UsbDirection, now: u64) {
+        self.armed[index] = Some(ArmedTransfer { dir, armed_at: now, flagged: false });
+    }
+    /// records that the transfer on endpoint `index` completed, or was abandoned
+    pub(crate) fn complete(&mut self, index: usize) {
+        self.armed[index] = None;
+    }
+    pub(crate) fn reset(&mut self) {
+        self.armed = [None; 16];
+    }
+    /// Returns the transfers that have newly gone over the threshold as of `now`. Each
+    /// transfer is reported once per arm.
+    pub(crate) fn check(&mut self, now: u64) -> Vec<StuckTransfer> {
+        let mut stuck = Vec::new();
+        if let Some(threshold) = self.threshold_ms {
+            for (index, slot) in self.armed.iter_mut().enumerate() {
+                if let Some(t) = slot {
+                    let pending_ms = now.saturating_sub(t.armed_at);
+                    if !t.flagged && pending_ms > threshold {
+                        t.flagged = true;
+                        self.stuck_transfers += 1;
+                        stuck.push(StuckTransfer { index, dir: t.dir, pending_ms });
+                    }
+                }
+            }
+        }
+        stuck
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_flags_once() {
+        let mut wd = Watchdog::new();
+        wd.arm(1, UsbDirection::In, 0);
+        // disabled by default
+        assert!(wd.check(10_000).is_empty());
+
+        wd.set_threshold(Some(100));
+        wd.arm(2, UsbDirection::In, 50);
+        wd.complete(2);
+        let stuck = wd.check(10_000);
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].index, 1);
+        assert_eq!(stuck[0].dir, UsbDirection::In);
+        assert_eq!(stuck[0].pending_ms, 10_000);
+        // still pending, but already reported
+        assert!(wd.check(20_000).is_empty());
+        assert_eq!(wd.stuck_transfers(), 1);
+
+        // re-arming restarts the clock and allows another report
+        wd.arm(1, UsbDirection::In, 20_000);
+        assert!(wd.check(20_100).is_empty());
+        assert_eq!(wd.check(20_101).len(), 1);
+        assert_eq!(wd.stuck_transfers(), 2);
+
+        wd.reset();
+        wd.arm(0, UsbDirection::Out, 0);
+        wd.complete(0);
+        assert!(wd.check(1_000_000).is_empty());
+    }
+}
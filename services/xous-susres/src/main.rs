@@ -451,7 +451,12 @@ mod implementation {
         }
         pub fn reboot(&self, _reboot_soc: bool) {}
         pub fn set_reboot_vector(&self, _vector: u32) {}
-        pub fn force_power_off(&mut self) {}
+        /// There's no power to pull in hosted mode, so this shuts down the emulated system
+        /// instead: the kernel destroys every server and process, and exits.
+        pub fn force_power_off(&mut self) {
+            log::info!("power off requested, shutting down the hosted system");
+            xous::rsyscall(xous::SysCall::Shutdown).expect("couldn't shut down the hosted system");
+        }
         pub fn do_suspend(&mut self, _forced: bool) {
         }
         pub fn do_resume(&mut self) -> bool {